@@ -0,0 +1,25 @@
+use tonic_build::manual::{Builder, Method, Service};
+
+// The gRPC service is declared here rather than in a .proto file, so building needs no
+// protoc; the messages are prost structs in src/grpc.rs
+fn main() {
+    let method = |name: &str, route: &str, input: &str, output: &str| {
+        Method::builder()
+            .name(name)
+            .route_name(route)
+            .input_type(format!("super::{}", input))
+            .output_type(format!("super::{}", output))
+            .codec_path("tonic::codec::ProstCodec")
+    };
+
+    let service = Service::builder()
+        .name("Redactor")
+        .package("redactor")
+        .method(method("handshake", "Handshake", "HandshakeRequest", "HandshakeReply").build())
+        .method(method("upload", "Upload", "UploadChunk", "UploadReply").client_streaming().build())
+        .method(method("download", "Download", "DownloadRequest", "DownloadChunk").server_streaming().build())
+        .method(method("analyze", "Analyze", "AnalyzeRequest", "AnalyzeReply").build())
+        .build();
+
+    Builder::new().build_client(false).compile(&[service]);
+}
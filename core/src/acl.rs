@@ -0,0 +1,178 @@
+use serde::{Deserialize, Serialize};
+
+use crate::caller::{Caller, Scope};
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum AclOperation {
+    Download,
+    Review,
+    Delete,
+    Unredact,
+}
+
+// Principals allowed to act on a file. Entries are principal ids or `tenant:<id>` to
+// grant a whole tenant; the uploader is always allowed.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct FileAcl {
+    #[serde(default)]
+    pub download: Vec<String>,
+    #[serde(default)]
+    pub review: Vec<String>,
+    #[serde(default)]
+    pub delete: Vec<String>,
+    #[serde(default)]
+    pub unredact: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AclPatch {
+    pub download: Option<Vec<String>>,
+    pub review: Option<Vec<String>>,
+    pub delete: Option<Vec<String>>,
+    pub unredact: Option<Vec<String>>,
+}
+
+impl FileAcl {
+    pub fn apply(&mut self, patch: AclPatch) {
+        if let Some(download) = patch.download {
+            self.download = download;
+        }
+        if let Some(review) = patch.review {
+            self.review = review;
+        }
+        if let Some(delete) = patch.delete {
+            self.delete = delete;
+        }
+        if let Some(unredact) = patch.unredact {
+            self.unredact = unredact;
+        }
+    }
+
+    fn entries(&self, operation: AclOperation) -> &[String] {
+        match operation {
+            AclOperation::Download => &self.download,
+            AclOperation::Review => &self.review,
+            AclOperation::Delete => &self.delete,
+            AclOperation::Unredact => &self.unredact,
+        }
+    }
+}
+
+// Files uploaded without an ACL stay open to every caller whose credential may download,
+// except for unredacting, which only the uploader may do unless the ACL grants it
+pub fn is_allowed(acl: Option<&FileAcl>, owner: Option<&str>, caller: &Caller, operation: AclOperation) -> bool {
+    let scope = match operation {
+        AclOperation::Download => Some(Scope::Download),
+        AclOperation::Unredact => Some(Scope::Unredact),
+        AclOperation::Delete => Some(Scope::Delete),
+        AclOperation::Review => None,
+    };
+    if scope.is_some_and(|scope| !caller.allows(scope)) {
+        return false;
+    }
+
+    let principal = caller.principal.as_deref();
+    let is_owner = owner.is_some() && principal == owner;
+    let Some(acl) = acl else {
+        return is_owner || !matches!(operation, AclOperation::Unredact);
+    };
+    if is_owner {
+        return true;
+    }
+
+    acl.entries(operation).iter().any(|entry| match entry.strip_prefix("tenant:") {
+        Some(tenant) => caller.tenant.as_deref() == Some(tenant),
+        None => principal == Some(entry.as_str()),
+    })
+}
+
+// Stored files are keyed by tenant and id: a file of another tenant is not found, unless
+// its ACL names the caller or the caller's tenant for some operation
+pub fn is_visible(tenant: Option<&str>, acl: Option<&FileAcl>, caller: &Caller) -> bool {
+    if caller.tenant.as_deref() == tenant {
+        return true;
+    }
+    let Some(acl) = acl else {
+        return false;
+    };
+    [&acl.download, &acl.review, &acl.delete, &acl.unredact].into_iter().flatten().any(|entry| match entry.strip_prefix("tenant:") {
+        Some(tenant) => caller.tenant.as_deref() == Some(tenant),
+        None => caller.principal.as_deref() == Some(entry.as_str()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caller(principal: Option<&str>, tenant: Option<&str>) -> Caller {
+        Caller {
+            principal: principal.map(str::to_string),
+            tenant: tenant.map(str::to_string),
+            scopes: None,
+        }
+    }
+
+    #[test]
+    fn test_acl_enforcement() {
+        let acl = FileAcl {
+            download: vec!["bob".to_string(), "tenant:legal".to_string()],
+            review: vec!["carol".to_string()],
+            delete: Vec::new(),
+            unredact: vec!["tenant:legal".to_string()],
+        };
+        let owner = Some("alice");
+
+        assert!(is_allowed(Some(&acl), owner, &caller(Some("alice"), None), AclOperation::Delete));
+        assert!(is_allowed(Some(&acl), owner, &caller(Some("bob"), None), AclOperation::Download));
+        assert!(is_allowed(Some(&acl), owner, &caller(Some("dave"), Some("legal")), AclOperation::Download));
+        assert!(!is_allowed(Some(&acl), owner, &caller(Some("bob"), None), AclOperation::Review));
+        assert!(!is_allowed(Some(&acl), owner, &caller(None, None), AclOperation::Download));
+        assert!(is_allowed(None, owner, &caller(None, None), AclOperation::Delete));
+
+        // Unredacting is granted separately from downloading
+        assert!(!is_allowed(Some(&acl), owner, &caller(Some("bob"), None), AclOperation::Unredact));
+        assert!(is_allowed(Some(&acl), owner, &caller(Some("dave"), Some("legal")), AclOperation::Unredact));
+        assert!(!is_allowed(None, owner, &caller(Some("bob"), None), AclOperation::Unredact));
+        assert!(is_allowed(None, owner, &caller(Some("alice"), None), AclOperation::Unredact));
+
+        // Scoped credentials delete only with the `delete` scope, even their own files
+        let scoped = |scope: Scope| Caller { scopes: Some(vec![scope]), ..caller(Some("alice"), None) };
+        assert!(!is_allowed(Some(&acl), owner, &scoped(Scope::Download), AclOperation::Delete));
+        assert!(is_allowed(Some(&acl), owner, &scoped(Scope::Delete), AclOperation::Delete));
+    }
+
+    #[test]
+    fn test_other_tenants_files_are_hidden_unless_shared() {
+        let acl = FileAcl { review: vec!["tenant:legal".to_string(), "erin".to_string()], ..Default::default() };
+        assert!(is_visible(Some("acme"), None, &caller(Some("bob"), Some("acme"))));
+        assert!(is_visible(None, None, &caller(Some("bob"), None)));
+        assert!(!is_visible(Some("acme"), None, &caller(Some("bob"), Some("globex"))));
+        assert!(!is_visible(Some("acme"), None, &caller(Some("bob"), None)));
+        assert!(!is_visible(None, None, &caller(Some("bob"), Some("acme"))));
+
+        // Sharing by ACL reaches across tenants
+        assert!(is_visible(Some("acme"), Some(&acl), &caller(Some("dave"), Some("legal"))));
+        assert!(is_visible(Some("acme"), Some(&acl), &caller(Some("erin"), Some("globex"))));
+        assert!(!is_visible(Some("acme"), Some(&acl), &caller(Some("dave"), Some("globex"))));
+    }
+
+    #[test]
+    fn test_patch_replaces_only_given_lists() {
+        let mut acl = FileAcl {
+            download: vec!["bob".to_string()],
+            ..Default::default()
+        };
+        acl.apply(AclPatch {
+            download: None,
+            review: Some(vec!["carol".to_string()]),
+            delete: None,
+            unredact: None,
+        });
+        assert_eq!(acl.download, vec!["bob"]);
+        assert_eq!(acl.review, vec!["carol"]);
+    }
+}
@@ -0,0 +1,143 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::envelope;
+use crate::upstream;
+
+const MAX_NONCE_LEN: usize = 128;
+
+// Evidence that the handshake key lives in a genuine enclave. `report_data` is what
+// the platform report binds (see `report_data`); clients recompute it and check it
+// against the document before trusting the key.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AttestationEvidence {
+    // `mock`, or whatever the agent reports: e.g. `nitro`, `sev-snp`, `sgx-dcap`
+    pub format: String,
+    pub report_data: String,
+    // Platform attestation document, base64
+    pub document: String,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+struct AgentResponse {
+    format: String,
+    document: String,
+}
+
+// Source of attestation evidence, chosen by `ATTESTATION_MODE`:
+// - `none` (default): handshakes carry no evidence
+// - `mock`: an unsigned document for development, refused by release builds
+// - `agent`: POSTs `{ "report_data": "<hex>" }` to `ATTESTATION_AGENT_URL`, a sidecar
+//   that asks the platform (Nitro NSM, SEV-SNP firmware, SGX quoting enclave) for a
+//   report over it and answers `{ "format", "document" }`
+pub enum Attester {
+    Disabled,
+    Mock,
+    Agent { client: Client, url: String },
+}
+
+impl Attester {
+    pub fn from_env() -> Result<Self> {
+        let mode = std::env::var("ATTESTATION_MODE").unwrap_or_else(|_| "none".to_string());
+        match mode.as_str() {
+            "none" => Ok(Self::Disabled),
+            "mock" => {
+                if !cfg!(debug_assertions) {
+                    return Err(anyhow!("ATTESTATION_MODE=mock is only available in debug builds"));
+                }
+                warn!("Attestation is mocked: handshake evidence is not signed by any platform");
+                Ok(Self::Mock)
+            }
+            "agent" => {
+                let url = std::env::var("ATTESTATION_AGENT_URL")
+                    .map_err(|_| anyhow!("ATTESTATION_MODE=agent requires ATTESTATION_AGENT_URL"))?;
+                let client = upstream::build_client("ATTESTATION", Duration::from_secs(10))?;
+                info!("Attestation evidence from agent at {}", url);
+                Ok(Self::Agent { client, url })
+            }
+            other => Err(anyhow!("Unknown ATTESTATION_MODE: {}", other)),
+        }
+    }
+
+    pub fn format(&self) -> Option<&'static str> {
+        match self {
+            Self::Disabled => None,
+            Self::Mock => Some("mock"),
+            Self::Agent { .. } => Some("agent"),
+        }
+    }
+
+    // Evidence binding the public key and the client's nonce, if attestation is enabled
+    pub async fn attest(&self, public_key_pem: &str, nonce: Option<&str>) -> Result<Option<AttestationEvidence>> {
+        if nonce.is_some_and(|nonce| nonce.len() > MAX_NONCE_LEN) {
+            return Err(anyhow!("Attestation nonce is limited to {} bytes", MAX_NONCE_LEN));
+        }
+        let report_data = envelope::hex(&report_data(public_key_pem, nonce));
+
+        match self {
+            Self::Disabled => Ok(None),
+            Self::Mock => {
+                let issued_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                let document = serde_json::json!({
+                    "format": "mock",
+                    "report_data": report_data,
+                    "pcrs": { "0": "0".repeat(96), "1": "0".repeat(96), "2": "0".repeat(96) },
+                    "issued_at": issued_at,
+                });
+                Ok(Some(AttestationEvidence {
+                    format: "mock".to_string(),
+                    report_data,
+                    document: BASE64.encode(document.to_string()),
+                }))
+            }
+            Self::Agent { client, url } => {
+                let response = client.post(url)
+                    .json(&serde_json::json!({ "report_data": report_data }))
+                    .send()
+                    .await
+                    .map_err(|e| anyhow!("Attestation agent request failed: {}", e))?;
+                if !response.status().is_success() {
+                    return Err(anyhow!("Attestation agent error ({})", response.status()));
+                }
+                let response: AgentResponse = response.json().await
+                    .map_err(|e| anyhow!("Invalid attestation agent response: {}", e))?;
+                Ok(Some(AttestationEvidence { format: response.format, report_data, document: response.document }))
+            }
+        }
+    }
+}
+
+// SHA-256 of the public key PEM followed by the client nonce, if any. Platform reports
+// carry it as user/report data, tying the key (and the freshness of the report) to
+// the measured enclave.
+pub fn report_data(public_key_pem: &str, nonce: Option<&str>) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(public_key_pem.as_bytes());
+    hasher.update(nonce.unwrap_or_default().as_bytes());
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mock_evidence_binds_key_and_nonce() {
+        let evidence = Attester::Mock.attest("-----BEGIN PUBLIC KEY-----", Some("c0ffee")).await.unwrap().unwrap();
+        assert_eq!(evidence.report_data, envelope::hex(&report_data("-----BEGIN PUBLIC KEY-----", Some("c0ffee"))));
+        assert_ne!(evidence.report_data, envelope::hex(&report_data("-----BEGIN PUBLIC KEY-----", None)));
+
+        let document: serde_json::Value = serde_json::from_slice(&BASE64.decode(&evidence.document).unwrap()).unwrap();
+        assert_eq!(document["report_data"], evidence.report_data);
+
+        assert!(Attester::Disabled.attest("key", None).await.unwrap().is_none());
+        assert!(Attester::Mock.attest("key", Some(&"n".repeat(129))).await.is_err());
+    }
+}
@@ -0,0 +1,551 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{HeaderMap, Method, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use futures_util::{stream, StreamExt};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::caller::{Caller, Scope};
+use crate::crypto;
+use crate::envelope;
+
+// Largest signed body that is buffered to be hashed; larger ones must send their
+// digest in `BODY_SHA256`
+const MAX_BUFFERED_SIGNED_BODY_BYTES: usize = 1024 * 1024;
+const DEFAULT_MAX_CLOCK_SKEW_SECONDS: u64 = 300;
+// Hex SHA-256 of the body as sent, which signed requests may send instead of having
+// the body buffered; the body is checked against it as it is read
+pub const BODY_SHA256: &str = "X-Content-SHA256";
+
+// The parts of a request an auth provider sees. The body digest is only taken when the
+// request's credentials sign the body.
+pub struct AuthRequest<'a> {
+    pub method: &'a Method,
+    pub uri: &'a Uri,
+    pub headers: &'a HeaderMap,
+    pub body_sha256: Option<[u8; 32]>,
+}
+
+pub enum AuthOutcome {
+    Authenticated(Caller),
+    // The request carries no credentials of this provider's scheme
+    NotApplicable,
+    // Credentials of this scheme were presented and are invalid
+    Rejected(String),
+}
+
+// A scheme for establishing who is calling, e.g. HMAC request signing or an SSO token
+// introspection endpoint
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    fn name(&self) -> &str;
+    async fn authenticate(&self, request: &AuthRequest<'_>) -> AuthOutcome;
+    // Whether the credentials in `headers` sign the request body, so `authenticate`
+    // needs its digest
+    fn signs_body(&self, _headers: &HeaderMap) -> bool {
+        false
+    }
+    // Whether callers prove who they are to this provider, rather than it trusting what
+    // a gateway asserts
+    fn checks_credentials(&self) -> bool {
+        true
+    }
+}
+
+// Kept beside the `Caller` by `middleware` when a provider that checks credentials
+// authenticated the request
+#[derive(Clone, Copy, Debug)]
+pub struct Credentialed;
+
+// Identity asserted by a trusted gateway in `X-Principal-Id` and `X-Tenant-Id`
+pub struct HeaderProvider;
+
+#[async_trait]
+impl AuthProvider for HeaderProvider {
+    fn name(&self) -> &str {
+        "headers"
+    }
+
+    async fn authenticate(&self, request: &AuthRequest<'_>) -> AuthOutcome {
+        let caller = Caller::from_headers(request.headers);
+        if caller.principal.is_none() && caller.tenant.is_none() {
+            return AuthOutcome::NotApplicable;
+        }
+        AuthOutcome::Authenticated(caller)
+    }
+
+    fn checks_credentials(&self) -> bool {
+        false
+    }
+}
+
+// Providers consulted in order: the first to authenticate the request decides the
+// caller, and the first to reject it fails the request. When none applies the request
+// proceeds anonymously, unless authentication is required.
+pub struct AuthChain {
+    providers: Vec<Box<dyn AuthProvider>>,
+    required: bool,
+}
+
+impl AuthChain {
+    pub fn new(providers: Vec<Box<dyn AuthProvider>>) -> Self {
+        Self { providers, required: false }
+    }
+
+    pub fn require_authentication(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+
+    // `AUTH_PROVIDERS` lists the built-in providers in order: `api_keys`, `hmac`,
+    // `headers`. Defaults to `headers`.
+    pub fn from_env() -> Result<Self> {
+        let names = std::env::var("AUTH_PROVIDERS").unwrap_or_else(|_| "headers".to_string());
+
+        let mut providers: Vec<Box<dyn AuthProvider>> = Vec::new();
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match name {
+                "headers" => providers.push(Box::new(HeaderProvider)),
+                "api_keys" => {
+                    let provider = ApiKeyProvider::from_env()?
+                        .ok_or_else(|| anyhow!("AUTH_PROVIDERS includes api_keys but AUTH_API_KEYS_PATH is unset"))?;
+                    providers.push(Box::new(provider));
+                }
+                "hmac" => {
+                    let provider = HmacProvider::from_env()?
+                        .ok_or_else(|| anyhow!("AUTH_PROVIDERS includes hmac but AUTH_HMAC_KEYS_PATH is unset"))?;
+                    providers.push(Box::new(provider));
+                }
+                other => return Err(anyhow!("Unknown auth provider: {}", other)),
+            }
+        }
+
+        let required = std::env::var("AUTH_REQUIRED")
+            .is_ok_and(|value| value == "1" || value.eq_ignore_ascii_case("true"));
+
+        info!("Auth providers: {}", providers.iter().map(|provider| provider.name()).collect::<Vec<_>>().join(","));
+        Ok(Self::new(providers).require_authentication(required))
+    }
+
+    fn signs_body(&self, headers: &HeaderMap) -> bool {
+        self.providers.iter().any(|provider| provider.signs_body(headers))
+    }
+
+    pub async fn authenticate(&self, request: &AuthRequest<'_>) -> Result<Caller, String> {
+        self.resolve(request).await.map(|(caller, _)| caller)
+    }
+
+    // `authenticate`, and whether the provider that authenticated the caller checked
+    // credentials
+    pub async fn resolve(&self, request: &AuthRequest<'_>) -> Result<(Caller, bool), String> {
+        for provider in &self.providers {
+            match provider.authenticate(request).await {
+                AuthOutcome::Authenticated(caller) => return Ok((caller, provider.checks_credentials())),
+                AuthOutcome::NotApplicable => continue,
+                AuthOutcome::Rejected(reason) => {
+                    warn!("Request rejected by auth provider {}: {}", provider.name(), reason);
+                    return Err(reason);
+                }
+            }
+        }
+        if self.required {
+            return Err("Authentication required".to_string());
+        }
+        Ok((Caller::default(), false))
+    }
+}
+
+impl Default for AuthChain {
+    fn default() -> Self {
+        Self::new(vec![Box::new(HeaderProvider)])
+    }
+}
+
+// Axum middleware resolving the caller through the chain. The `Caller` extractor and
+// `RedactionService` use the resolved caller instead of reading the identity headers.
+//
+//     router.layer(axum::middleware::from_fn_with_state(Arc::new(chain), auth::middleware))
+//
+// Bodies are left streaming unless the request signs one without sending its digest.
+pub async fn middleware(State(chain): State<Arc<AuthChain>>, request: Request, next: Next) -> Response {
+    let error = |status: StatusCode, code: &str, error: String| {
+        (status, Json(serde_json::json!({ "error": error, "code": code }))).into_response()
+    };
+
+    let (mut parts, body) = request.into_parts();
+    let (body, body_sha256) = if !chain.signs_body(&parts.headers) {
+        (body, None)
+    } else if let Some(claimed) = parts.headers.get(BODY_SHA256) {
+        let Some(digest) = claimed.to_str().ok().and_then(|value| crypto::parse_sha256_hex(value).ok()) else {
+            return error(StatusCode::UNAUTHORIZED, "unauthorized", format!("{} must be 64 hex characters", BODY_SHA256));
+        };
+        (checked_body(body, digest), Some(digest))
+    } else {
+        match axum::body::to_bytes(body, MAX_BUFFERED_SIGNED_BODY_BYTES).await {
+            Ok(bytes) => {
+                let digest = crypto::sha256(&bytes);
+                (Body::from(bytes), Some(digest))
+            }
+            Err(e) => return error(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "payload_too_large",
+                format!("Signed bodies over {} bytes must send {}: {}", MAX_BUFFERED_SIGNED_BODY_BYTES, BODY_SHA256, e),
+            ),
+        }
+    };
+
+    let auth_request = AuthRequest {
+        method: &parts.method,
+        uri: &parts.uri,
+        headers: &parts.headers,
+        body_sha256,
+    };
+    let (caller, credentialed) = match chain.resolve(&auth_request).await {
+        Ok(resolved) => resolved,
+        Err(reason) => return error(StatusCode::UNAUTHORIZED, "unauthorized", reason),
+    };
+
+    parts.extensions.insert(caller);
+    if credentialed {
+        parts.extensions.insert(Credentialed);
+    }
+    next.run(Request::from_parts(parts, body)).await
+}
+
+// `body` as it streams in, failing at its end unless it hashes to `expected`, so a
+// handler reading it never sees a body other than the one signed complete
+fn checked_body(body: Body, expected: [u8; 32]) -> Body {
+    let chunks = body.into_data_stream();
+    Body::from_stream(stream::unfold((chunks, Some(Sha256::new())), move |(mut chunks, hasher)| async move {
+        let mut hasher = hasher?;
+        match chunks.next().await {
+            Some(Ok(chunk)) => {
+                hasher.update(&chunk);
+                Some((Ok(chunk), (chunks, Some(hasher))))
+            }
+            Some(Err(e)) => Some((Err(axum::BoxError::from(e)), (chunks, None))),
+            None if <[u8; 32]>::from(hasher.finalize()) == expected => None,
+            None => Some((Err(format!("Body does not match {}", BODY_SHA256).into()), (chunks, None))),
+        }
+    }))
+}
+
+// Static keys sent in `X-API-Key`. Each key acts as its own principal (the key id,
+// unless the file names one) and carries the scopes it was issued with.
+pub struct ApiKeyProvider {
+    // By the SHA-256 of the key, so the service never holds the keys themselves
+    keys: HashMap<[u8; 32], ApiKey>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ApiKey {
+    #[serde(skip)]
+    pub key_id: String,
+    pub principal: Option<String>,
+    pub tenant: Option<String>,
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Deserialize)]
+struct ApiKeyFile {
+    key_sha256: String,
+    #[serde(flatten)]
+    key: ApiKey,
+}
+
+impl ApiKeyProvider {
+    pub fn new(keys: HashMap<[u8; 32], ApiKey>) -> Self {
+        Self { keys }
+    }
+
+    // Keys are provisioned as a JSON file of { "<key_id>": { "key_sha256": "<hex>",
+    // "scopes": ["upload", "download"], "principal": "...", "tenant": "..." } }
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(path) = std::env::var("AUTH_API_KEYS_PATH") else {
+            return Ok(None);
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read API keys from {}: {}", path, e))?;
+        let encoded: HashMap<String, ApiKeyFile> = serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("Invalid API keys file: {}", e))?;
+
+        let keys = encoded.into_iter()
+            .map(|(key_id, entry)| {
+                let digest = crypto::parse_sha256_hex(&entry.key_sha256)
+                    .map_err(|_| anyhow!("key_sha256 of API key {} must be 64 hex characters", key_id))?;
+                Ok((digest, ApiKey { key_id, ..entry.key }))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        info!("Loaded {} API key(s)", keys.len());
+        Ok(Some(Self::new(keys)))
+    }
+}
+
+#[async_trait]
+impl AuthProvider for ApiKeyProvider {
+    fn name(&self) -> &str {
+        "api_keys"
+    }
+
+    async fn authenticate(&self, request: &AuthRequest<'_>) -> AuthOutcome {
+        let Some(presented) = request.headers.get("X-API-Key") else {
+            return AuthOutcome::NotApplicable;
+        };
+        let Some(key) = self.keys.get(&crypto::sha256(presented.as_bytes())) else {
+            return AuthOutcome::Rejected("Invalid API key".to_string());
+        };
+
+        AuthOutcome::Authenticated(Caller {
+            principal: Some(key.principal.clone().unwrap_or_else(|| key.key_id.clone())),
+            tenant: key.tenant.clone(),
+            scopes: Some(key.scopes.clone()),
+        })
+    }
+}
+
+// Signed requests carry `X-Auth-Key-Id`, `X-Auth-Timestamp` (unix seconds) and
+// `X-Auth-Signature`: the hex HMAC-SHA256, under the key's shared secret, of
+// `string_to_sign`. Timestamps further than the allowed skew from the service clock
+// are rejected, which bounds how long a captured request can be replayed.
+pub struct HmacProvider {
+    keys: HashMap<String, SigningKey>,
+    max_clock_skew: u64,
+}
+
+pub struct SigningKey {
+    pub secret: Vec<u8>,
+    pub principal: String,
+    pub tenant: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SigningKeyFile {
+    secret: String,
+    principal: String,
+    tenant: Option<String>,
+}
+
+impl HmacProvider {
+    pub fn new(keys: HashMap<String, SigningKey>, max_clock_skew: u64) -> Self {
+        Self { keys, max_clock_skew }
+    }
+
+    // Keys are provisioned as a JSON file of
+    // { "<key_id>": { "secret": "<base64>", "principal": "...", "tenant": "..." } }
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(path) = std::env::var("AUTH_HMAC_KEYS_PATH") else {
+            return Ok(None);
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read signing keys from {}: {}", path, e))?;
+        let encoded: HashMap<String, SigningKeyFile> = serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("Invalid signing keys file: {}", e))?;
+
+        let keys = encoded.into_iter()
+            .map(|(key_id, key)| {
+                let secret = BASE64.decode(&key.secret)
+                    .map_err(|e| anyhow!("Invalid base64 secret for signing key {}: {}", key_id, e))?;
+                if secret.len() < 32 {
+                    return Err(anyhow!("Signing key {} must be at least 32 bytes", key_id));
+                }
+                Ok((key_id, SigningKey { secret, principal: key.principal, tenant: key.tenant }))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        let max_clock_skew = std::env::var("AUTH_HMAC_MAX_SKEW_SECONDS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_MAX_CLOCK_SKEW_SECONDS);
+
+        info!("Loaded {} request signing key(s)", keys.len());
+        Ok(Some(Self::new(keys, max_clock_skew)))
+    }
+}
+
+#[async_trait]
+impl AuthProvider for HmacProvider {
+    fn name(&self) -> &str {
+        "hmac"
+    }
+
+    async fn authenticate(&self, request: &AuthRequest<'_>) -> AuthOutcome {
+        let header = |name: &str| request.headers.get(name).and_then(|value| value.to_str().ok());
+        let Some(signature) = header("X-Auth-Signature") else {
+            return AuthOutcome::NotApplicable;
+        };
+        let reject = |reason: &str| AuthOutcome::Rejected(reason.to_string());
+
+        let Some(key) = header("X-Auth-Key-Id").and_then(|key_id| self.keys.get(key_id)) else {
+            return reject("Unknown signing key");
+        };
+        let Some(timestamp) = header("X-Auth-Timestamp").and_then(|value| value.parse::<u64>().ok()) else {
+            return reject("Missing or invalid X-Auth-Timestamp");
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        if now.abs_diff(timestamp) > self.max_clock_skew {
+            return reject("Request timestamp is outside the allowed clock skew");
+        }
+        let Ok(signature) = crypto::parse_sha256_hex(signature) else {
+            return reject("X-Auth-Signature must be 64 hex characters");
+        };
+
+        let body_sha256 = request.body_sha256.unwrap_or_else(|| crypto::sha256(b""));
+        let string_to_sign = string_to_sign(request.method, request.uri, timestamp, &body_sha256);
+        let mut mac = <Hmac<Sha256>>::new_from_slice(&key.secret).expect("HMAC accepts any key length");
+        mac.update(string_to_sign.as_bytes());
+        if mac.verify_slice(&signature).is_err() {
+            return reject("Invalid request signature");
+        }
+
+        AuthOutcome::Authenticated(Caller {
+            principal: Some(key.principal.clone()),
+            tenant: key.tenant.clone(),
+            scopes: None,
+        })
+    }
+
+    fn signs_body(&self, headers: &HeaderMap) -> bool {
+        headers.contains_key("X-Auth-Signature")
+    }
+}
+
+// Algorithm, timestamp, method, path, raw query (empty when absent) and the hex
+// SHA-256 of the body as sent (before any decompression), one per line
+pub fn string_to_sign(method: &Method, uri: &Uri, timestamp: u64, body_sha256: &[u8; 32]) -> String {
+    format!(
+        "HMAC-SHA256\n{}\n{}\n{}\n{}\n{}",
+        timestamp,
+        method.as_str(),
+        uri.path(),
+        uri.query().unwrap_or_default(),
+        envelope::hex(body_sha256),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct SignedRequests;
+
+    #[async_trait]
+    impl AuthProvider for SignedRequests {
+        fn name(&self) -> &str {
+            "signed"
+        }
+
+        async fn authenticate(&self, request: &AuthRequest<'_>) -> AuthOutcome {
+            match request.headers.get("X-Signature").map(|value| value.as_bytes()) {
+                None => AuthOutcome::NotApplicable,
+                Some(b"valid") => AuthOutcome::Authenticated(Caller { principal: Some("svc".to_string()), tenant: None, scopes: None }),
+                Some(_) => AuthOutcome::Rejected("Invalid signature".to_string()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chain_consults_providers_in_order() {
+        let chain = AuthChain::new(vec![Box::new(SignedRequests), Box::new(HeaderProvider)]);
+        let (method, uri) = (Method::GET, Uri::from_static("/download/f1"));
+        let authenticate = |headers: &[(&'static str, &'static str)]| {
+            let headers: HeaderMap = headers.iter()
+                .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+                .collect();
+            let chain = &chain;
+            let (method, uri) = (&method, &uri);
+            async move { chain.resolve(&AuthRequest { method, uri, headers: &headers, body_sha256: None }).await }
+        };
+
+        let (caller, credentialed) = authenticate(&[("X-Signature", "valid"), ("X-Principal-Id", "alice")]).await.unwrap();
+        assert_eq!((caller.principal.as_deref(), credentialed), (Some("svc"), true));
+
+        // Headers only assert an identity
+        let (caller, credentialed) = authenticate(&[("X-Principal-Id", "alice")]).await.unwrap();
+        assert_eq!((caller.principal.as_deref(), credentialed), (Some("alice"), false));
+
+        assert!(authenticate(&[("X-Signature", "forged"), ("X-Principal-Id", "alice")]).await.is_err());
+        assert!(authenticate(&[]).await.unwrap().0.principal.is_none());
+
+        let chain = AuthChain::new(vec![Box::new(SignedRequests)]).require_authentication(true);
+        let request = AuthRequest { method: &method, uri: &uri, headers: &HeaderMap::new(), body_sha256: None };
+        assert!(chain.authenticate(&request).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_api_keys_resolve_to_scoped_callers() {
+        let key = ApiKey { key_id: "ingest".to_string(), principal: None, tenant: None, scopes: vec![Scope::Upload] };
+        let provider = ApiKeyProvider::new(HashMap::from([(crypto::sha256(b"sk-test"), key)]));
+
+        let (method, uri) = (Method::POST, Uri::from_static("/upload"));
+        let authenticate = |key: Option<&'static str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(key) = key {
+                headers.insert("X-API-Key", key.parse().unwrap());
+            }
+            let provider = &provider;
+            let (method, uri) = (&method, &uri);
+            async move { provider.authenticate(&AuthRequest { method, uri, headers: &headers, body_sha256: None }).await }
+        };
+
+        let AuthOutcome::Authenticated(caller) = authenticate(Some("sk-test")).await else {
+            panic!("valid key was not accepted");
+        };
+        assert_eq!(caller.principal.as_deref(), Some("ingest"));
+        assert!(caller.allows(Scope::Upload) && !caller.allows(Scope::Download));
+
+        assert!(matches!(authenticate(Some("sk-other")).await, AuthOutcome::Rejected(_)));
+        assert!(matches!(authenticate(None).await, AuthOutcome::NotApplicable));
+    }
+
+    #[tokio::test]
+    async fn test_hmac_signatures_cover_body_and_timestamp() {
+        let secret = vec![7u8; 32];
+        let key = SigningKey { secret: secret.clone(), principal: "partner".to_string(), tenant: Some("acme".to_string()) };
+        let provider = HmacProvider::new(HashMap::from([("k1".to_string(), key)]), 300);
+
+        let (method, uri) = (Method::POST, Uri::from_static("/upload?async=true"));
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let signed = |timestamp: u64, body: &[u8]| {
+            let mut mac = <Hmac<Sha256>>::new_from_slice(&secret).unwrap();
+            mac.update(string_to_sign(&method, &uri, timestamp, &crypto::sha256(body)).as_bytes());
+            let mut headers = HeaderMap::new();
+            headers.insert("X-Auth-Key-Id", "k1".parse().unwrap());
+            headers.insert("X-Auth-Timestamp", timestamp.to_string().parse().unwrap());
+            headers.insert("X-Auth-Signature", envelope::hex(&mac.finalize().into_bytes()).parse().unwrap());
+            headers
+        };
+
+        let headers = signed(now, b"{}");
+        let request = AuthRequest { method: &method, uri: &uri, headers: &headers, body_sha256: Some(crypto::sha256(b"{}")) };
+        assert!(matches!(
+            provider.authenticate(&request).await,
+            AuthOutcome::Authenticated(caller) if caller.principal.as_deref() == Some("partner") && caller.tenant.as_deref() == Some("acme")
+        ));
+
+        let tampered = AuthRequest { body_sha256: Some(crypto::sha256(b"{\"x\":1}")), ..request };
+        assert!(matches!(provider.authenticate(&tampered).await, AuthOutcome::Rejected(_)));
+
+        let headers = signed(now - 600, b"{}");
+        let stale = AuthRequest { method: &method, uri: &uri, headers: &headers, body_sha256: Some(crypto::sha256(b"{}")) };
+        assert!(matches!(provider.authenticate(&stale).await, AuthOutcome::Rejected(_)));
+
+        let unsigned = AuthRequest { method: &method, uri: &uri, headers: &HeaderMap::new(), body_sha256: None };
+        assert!(matches!(provider.authenticate(&unsigned).await, AuthOutcome::NotApplicable));
+    }
+}
@@ -0,0 +1,230 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use tracing::warn;
+
+use crate::report::Detection;
+use crate::resilience::CircuitStatus;
+
+// What a backend found in one piece of text
+pub struct Analysis {
+    pub redacted: String,
+    // Byte offsets into the analyzed text, in any order
+    pub detections: Vec<Detection>,
+    // Answered by a backend of a fallback chain after an earlier one failed
+    pub fallback: bool,
+}
+
+// Entity types the Presidio analyzer detects out of the box, which `entities` filters
+// may name
+pub const SUPPORTED_ENTITIES: &[&str] = &[
+    "CREDIT_CARD", "CRYPTO", "DATE_TIME", "EMAIL_ADDRESS", "IBAN_CODE", "IP_ADDRESS", "LOCATION",
+    "MEDICAL_LICENSE", "NRP", "PERSON", "PHONE_NUMBER", "URL", "US_BANK_NUMBER", "US_DRIVER_LICENSE",
+    "US_ITIN", "US_PASSPORT", "US_SSN", "UK_NHS", "UK_NINO", "ES_NIF", "ES_NIE", "IT_FISCAL_CODE",
+    "IT_DRIVER_LICENSE", "IT_VAT_CODE", "IT_PASSPORT", "IT_IDENTITY_CARD", "PL_PESEL", "SG_NRIC_FIN",
+    "SG_UEN", "AU_ABN", "AU_ACN", "AU_TFN", "AU_MEDICARE", "IN_PAN", "IN_AADHAAR", "IN_VEHICLE_REGISTRATION",
+    "IN_VOTER", "IN_PASSPORT", "FI_PERSONAL_IDENTITY_CODE",
+];
+
+// Which detections a caller wants redacted. Unset fields keep the backend's defaults:
+// every entity type, at the analyzer's own threshold, in its default language.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EntityFilter<'a> {
+    pub entities: Option<&'a [String]>,
+    // Minimum analyzer score, from 0 to 1
+    pub score_threshold: Option<f32>,
+    // ISO 639-1 code of the text's language, picking the analyzer's model for it
+    pub language: Option<&'a str>,
+}
+
+impl EntityFilter<'_> {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(entities) = self.entities {
+            if entities.is_empty() {
+                return Err(anyhow!("entities must name at least one entity type"));
+            }
+            let unknown: Vec<&str> = entities.iter()
+                .map(String::as_str)
+                .filter(|entity| !SUPPORTED_ENTITIES.contains(entity))
+                .collect();
+            if !unknown.is_empty() {
+                return Err(anyhow!(
+                    "Unknown entity types: {}. Supported types: {}",
+                    unknown.join(", "),
+                    SUPPORTED_ENTITIES.join(", ")
+                ));
+            }
+        }
+        if self.score_threshold.is_some_and(|threshold| !(0.0..=1.0).contains(&threshold)) {
+            return Err(anyhow!("score_threshold must be between 0 and 1"));
+        }
+        Ok(())
+    }
+
+    pub fn allows(&self, entity_type: &str, score: f64) -> bool {
+        self.entities.is_none_or(|entities| entities.iter().any(|entity| entity == entity_type))
+            && self.score_threshold.is_none_or(|threshold| score as f32 >= threshold)
+    }
+}
+
+// Detects and redacts PII in text with one of the redaction strategies (`replace`,
+// `mask`, `fake`, `custom`), leaving what `filter` excludes in place
+#[async_trait]
+pub trait RedactionBackend: Send + Sync {
+    fn name(&self) -> &str;
+    async fn analyze(&self, text: &str, strategy: &str, filter: EntityFilter<'_>) -> Result<Analysis>;
+    // Only the detection phase, for dry runs; backends that can skip anonymizing override this
+    async fn detect(&self, text: &str, filter: EntityFilter<'_>) -> Result<Vec<Detection>> {
+        Ok(self.analyze(text, "replace", filter).await?.detections)
+    }
+    // Entity types the backend can detect
+    fn entities(&self) -> Vec<&str> {
+        SUPPORTED_ENTITIES.to_vec()
+    }
+    // Circuit breakers guarding remote backends, for the health endpoint
+    fn circuits(&self) -> Vec<CircuitStatus> {
+        Vec::new()
+    }
+    // Check at startup that a remote backend answers in a shape this service understands
+    async fn check_contract(&self) -> Result<()> {
+        Ok(())
+    }
+    // Whether the backend can take requests now, for the readiness probe; local ones always can
+    async fn ping(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Tries each backend in order, falling through to the next when one fails, e.g. the
+// regex engine when Presidio is unreachable
+pub struct FallbackChain {
+    backends: Vec<Box<dyn RedactionBackend>>,
+    name: String,
+}
+
+impl FallbackChain {
+    pub fn new(backends: Vec<Box<dyn RedactionBackend>>) -> Result<Self> {
+        if backends.is_empty() {
+            return Err(anyhow!("A fallback chain needs at least one backend"));
+        }
+        let name = backends.iter().map(|backend| backend.name()).collect::<Vec<_>>().join(",");
+        Ok(Self { backends, name })
+    }
+}
+
+#[async_trait]
+impl RedactionBackend for FallbackChain {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn analyze(&self, text: &str, strategy: &str, filter: EntityFilter<'_>) -> Result<Analysis> {
+        let mut last_error = None;
+        for backend in &self.backends {
+            match backend.analyze(text, strategy, filter).await {
+                Ok(analysis) => return Ok(Analysis { fallback: last_error.is_some(), ..analysis }),
+                Err(e) => {
+                    warn!("Redaction backend {} failed, trying the next one: {}", backend.name(), e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow!("No redaction backend available")))
+    }
+
+    async fn detect(&self, text: &str, filter: EntityFilter<'_>) -> Result<Vec<Detection>> {
+        let mut last_error = None;
+        for backend in &self.backends {
+            match backend.detect(text, filter).await {
+                Ok(detections) => return Ok(detections),
+                Err(e) => {
+                    warn!("Redaction backend {} failed, trying the next one: {}", backend.name(), e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow!("No redaction backend available")))
+    }
+
+    // Any backend in the chain may be the one that answers
+    fn entities(&self) -> Vec<&str> {
+        let mut entities: Vec<&str> = self.backends.iter().flat_map(|backend| backend.entities()).collect();
+        entities.sort_unstable();
+        entities.dedup();
+        entities
+    }
+
+    fn circuits(&self) -> Vec<CircuitStatus> {
+        self.backends.iter().flat_map(|backend| backend.circuits()).collect()
+    }
+
+    async fn check_contract(&self) -> Result<()> {
+        for backend in &self.backends {
+            backend.check_contract().await?;
+        }
+        Ok(())
+    }
+
+    // Ready while any backend in the chain answers, as uploads fall through to it
+    async fn ping(&self) -> Result<()> {
+        let mut failures = Vec::new();
+        for backend in &self.backends {
+            match backend.ping().await {
+                Ok(()) => return Ok(()),
+                Err(e) => failures.push(format!("{}: {}", backend.name(), e)),
+            }
+        }
+        Err(anyhow!("No redaction backend answers ({})", failures.join("; ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::RegexEngine;
+
+    struct Unreachable;
+
+    #[async_trait]
+    impl RedactionBackend for Unreachable {
+        fn name(&self) -> &str {
+            "presidio"
+        }
+
+        async fn analyze(&self, _text: &str, _strategy: &str, _filter: EntityFilter<'_>) -> Result<Analysis> {
+            Err(anyhow!("connection refused"))
+        }
+
+        async fn ping(&self) -> Result<()> {
+            Err(anyhow!("connection refused"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chain_falls_back_when_a_backend_fails() {
+        let chain = FallbackChain::new(vec![Box::new(Unreachable), Box::new(RegexEngine::new())]).unwrap();
+        assert_eq!(chain.name(), "presidio,regex");
+
+        let analysis = chain.analyze("Mail jane@example.com", "replace", EntityFilter::default()).await.unwrap();
+        assert_eq!((analysis.redacted.as_str(), analysis.fallback), ("Mail <EMAIL_ADDRESS>", true));
+
+        let only_failing = FallbackChain::new(vec![Box::new(Unreachable)]).unwrap();
+        assert!(only_failing.analyze("text", "replace", EntityFilter::default()).await.is_err());
+
+        assert!(chain.ping().await.is_ok());
+        let down = only_failing.ping().await.unwrap_err();
+        assert_eq!(down.to_string(), "No redaction backend answers (presidio: connection refused)");
+    }
+
+    #[test]
+    fn test_entity_filters_are_validated() {
+        let entities = vec!["US_SSN".to_string(), "SHOE_SIZE".to_string()];
+        let unknown = EntityFilter { entities: Some(&entities), score_threshold: None, language: None }.validate().unwrap_err();
+        assert!(unknown.to_string().starts_with("Unknown entity types: SHOE_SIZE. Supported types: CREDIT_CARD"), "{}", unknown);
+        assert!(EntityFilter { entities: Some(&[]), score_threshold: None, language: None }.validate().is_err());
+        assert!(EntityFilter { entities: None, score_threshold: Some(1.5), language: None }.validate().is_err());
+
+        let filter = EntityFilter { entities: Some(&entities[..1]), score_threshold: Some(0.6), language: None };
+        assert!(filter.validate().is_ok());
+        assert!(filter.allows("US_SSN", 0.6) && !filter.allows("US_SSN", 0.5) && !filter.allows("PERSON", 1.0));
+    }
+}
@@ -0,0 +1,198 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+// Explicit directional formatting characters, all three bytes in UTF-8
+const LRE: char = '\u{202a}';
+const RLE: char = '\u{202b}';
+const PDF: char = '\u{202c}';
+const LRO: char = '\u{202d}';
+const RLO: char = '\u{202e}';
+const LRI: char = '\u{2066}';
+const RLI: char = '\u{2067}';
+const FSI: char = '\u{2068}';
+const PDI: char = '\u{2069}';
+const CONTROL_LEN: usize = 3;
+
+// What happens to bidi control characters in uploaded text. Overrides can reorder how
+// text renders, e.g. to make an unredacted value look like something else, and controls
+// placed inside an entity keep it from being detected.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BidiMode {
+    // Remove embeddings, overrides and isolates before analysis
+    #[default]
+    Strip,
+    // Turn overrides into embeddings, which set direction without reordering letters,
+    // and close embeddings and isolates left open at the end of each line
+    Neutralize,
+    // Leave the text as it is
+    Keep,
+}
+
+// Text with its bidi controls handled, mapping offsets back to the original
+pub struct BidiText<'a> {
+    pub text: Cow<'a, str>,
+    // Offsets into `text` where a control was removed, in order
+    removed: Vec<usize>,
+}
+
+impl BidiText<'_> {
+    // Offset into the original text of a range starting at `offset`, after any control
+    // removed there
+    pub fn original_start(&self, offset: usize) -> usize {
+        offset + CONTROL_LEN * self.removed.partition_point(|removed| *removed <= offset)
+    }
+
+    // Offset into the original text of a range ending at `offset`, before any control
+    // removed there
+    pub fn original_end(&self, offset: usize) -> usize {
+        offset + CONTROL_LEN * self.removed.partition_point(|removed| *removed < offset)
+    }
+}
+
+pub fn sanitize(text: &str, mode: BidiMode) -> BidiText<'_> {
+    if mode == BidiMode::Keep || !text.chars().any(is_control) {
+        return BidiText { text: Cow::Borrowed(text), removed: Vec::new() };
+    }
+
+    let mut output = String::with_capacity(text.len());
+    let mut removed = Vec::new();
+    for c in text.chars() {
+        match (mode, c) {
+            (BidiMode::Strip, c) if is_control(c) => removed.push(output.len()),
+            (BidiMode::Neutralize, LRO) => output.push(LRE),
+            (BidiMode::Neutralize, RLO) => output.push(RLE),
+            (_, c) => output.push(c),
+        }
+    }
+    BidiText { text: Cow::Owned(output), removed }
+}
+
+// Close the embeddings and isolates still open at each line break and at the end, so
+// they cannot carry over into the next line
+pub fn close_open_runs(text: &str) -> Cow<'_, str> {
+    if !text.chars().any(is_control) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut output = String::with_capacity(text.len());
+    // Open runs, innermost last; true for isolates
+    let mut open: Vec<bool> = Vec::new();
+    let close = |output: &mut String, open: &mut Vec<bool>| {
+        while let Some(isolate) = open.pop() {
+            output.push(if isolate { PDI } else { PDF });
+        }
+    };
+    for c in text.chars() {
+        match c {
+            LRE | RLE | LRO | RLO => open.push(false),
+            LRI | RLI | FSI => open.push(true),
+            // Closes the innermost embedding, unless an isolate is open inside it
+            PDF if open.last() == Some(&false) => {
+                open.pop();
+            }
+            // Closes the innermost isolate and any embeddings opened inside it
+            PDI => {
+                if let Some(isolate) = open.iter().rposition(|isolate| *isolate) {
+                    open.truncate(isolate);
+                }
+            }
+            '\n' | '\r' | '\u{2029}' => close(&mut output, &mut open),
+            _ => {}
+        }
+        output.push(c);
+    }
+    close(&mut output, &mut open);
+    Cow::Owned(output)
+}
+
+// Whether the text has right-to-left letters (Hebrew, Arabic, Syriac, Thaana, ...)
+pub fn has_rtl(text: &str) -> bool {
+    text.chars().any(|c| matches!(c,
+        '\u{0590}'..='\u{08ff}' | '\u{fb1d}'..='\u{fdff}' | '\u{fe70}'..='\u{feff}'
+        | '\u{10800}'..='\u{10fff}' | '\u{1e800}'..='\u{1efff}'))
+}
+
+// Wrap redaction markers (`<PERSON>`, `<EMAIL_ADDRESS_1>`, `[REDACTED]`) in first strong
+// isolates, so in right-to-left text they render as a unit and keep the surrounding
+// text in place
+pub fn isolate_markers(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(['<', '[']) {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let len = marker_len(rest);
+        if len > 0 {
+            output.push(FSI);
+            output.push_str(&rest[..len]);
+            output.push(PDI);
+        } else {
+            output.push_str(&rest[..1]);
+        }
+        rest = &rest[len.max(1)..];
+    }
+    output.push_str(rest);
+    output
+}
+
+// Length of the marker `text` starts with, or 0
+pub(crate) fn marker_len(text: &str) -> usize {
+    if text.starts_with("[REDACTED]") {
+        return "[REDACTED]".len();
+    }
+    let Some(tag) = text.strip_prefix('<').and_then(|rest| rest.split_once('>')).map(|(tag, _)| tag) else {
+        return 0;
+    };
+    let is_tag = !tag.is_empty() && tag.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+    if is_tag { tag.len() + 2 } else { 0 }
+}
+
+// Remove the isolates `isolate_markers` put around a marker that starts at `rest` and
+// is `len` bytes long, given the output written before it
+pub(crate) fn unwrap_isolated(output: &mut String, rest: &str, len: usize) -> usize {
+    if output.ends_with(FSI) && rest[len..].starts_with(PDI) {
+        output.truncate(output.len() - CONTROL_LEN);
+        return len + CONTROL_LEN;
+    }
+    len
+}
+
+fn is_control(c: char) -> bool {
+    matches!(c, LRE | RLE | PDF | LRO | RLO | LRI | RLI | FSI | PDI)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides_are_stripped_or_neutralized() {
+        // An override makes the Hebrew-labelled email render as "moc.elpmaxe@enaj"
+        let text = "דוא״ל: \u{202e}jane@exa\u{2066}mple.com\u{202c}\nשלום";
+        let stripped = sanitize(text, BidiMode::Strip);
+        assert_eq!(stripped.text, "דוא״ל: jane@example.com\nשלום");
+        let email = stripped.text.find("jane").unwrap();
+        let end = email + "jane@example.com".len();
+        assert_eq!(&text[stripped.original_start(email)..stripped.original_end(end)], "jane@exa\u{2066}mple.com");
+
+        let neutralized = sanitize(text, BidiMode::Neutralize);
+        assert_eq!(neutralized.text, "דוא״ל: \u{202b}jane@exa\u{2066}mple.com\u{202c}\nשלום");
+        // The PDF cannot close the embedding while the isolate inside it is open, so
+        // both are closed before the line break
+        assert_eq!(
+            close_open_runs(&neutralized.text),
+            "דוא״ל: \u{202b}jane@exa\u{2066}mple.com\u{202c}\u{2069}\u{202c}\nשלום"
+        );
+        assert!(matches!(sanitize(text, BidiMode::Keep).text, Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_markers_are_isolated_in_rtl_text() {
+        assert!(has_rtl("اتصل بـ jane") && has_rtl("שלום") && !has_rtl("hello <PERSON>"));
+        assert_eq!(
+            isolate_markers("اتصل بـ <EMAIL_ADDRESS_1> أو [REDACTED] <a> [x"),
+            "اتصل بـ \u{2068}<EMAIL_ADDRESS_1>\u{2069} أو \u{2068}[REDACTED]\u{2069} <a> [x"
+        );
+    }
+}
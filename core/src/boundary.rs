@@ -0,0 +1,209 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::report::Detection;
+
+// Moves detections that start or end inside a word, such as a name found within a URL
+// or a snake_case identifier, so redacting them does not leave part of the word behind.
+// `BoundaryRules` come from the policy; embedding services can plug in their own.
+pub trait BoundaryAdjuster: Send + Sync {
+    // Adjust the detections of `text` in place, returning whether any moved or was dropped
+    fn adjust(&self, text: &str, detections: &mut Vec<Detection>) -> bool;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BoundaryMode {
+    // Leave the detection as the analyzer found it
+    #[default]
+    Keep,
+    // Grow it to cover the whole tokens it touches
+    Expand,
+    // Shrink it to the whole tokens inside it, dropping it when none are
+    Contract,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoundaryRule {
+    #[serde(default)]
+    pub mode: BoundaryMode,
+    // Characters besides letters, digits and `_` that continue a token, e.g. `./:-` so
+    // that a URL is one token
+    #[serde(default)]
+    pub joiners: String,
+}
+
+impl BoundaryRule {
+    fn is_token_char(&self, c: char) -> bool {
+        c.is_alphanumeric() || c == '_' || self.joiners.contains(c)
+    }
+
+    fn token_before(&self, text: &str, at: usize) -> Option<char> {
+        text[..at].chars().next_back().filter(|c| self.is_token_char(*c))
+    }
+
+    fn token_after(&self, text: &str, at: usize) -> Option<char> {
+        text[at..].chars().next().filter(|c| self.is_token_char(*c))
+    }
+
+    // The span moved to token boundaries, or None when contracting leaves nothing
+    fn apply(&self, text: &str, start: usize, end: usize) -> Option<(usize, usize)> {
+        let (mut start, mut end) = (start, end);
+        match self.mode {
+            BoundaryMode::Keep => {}
+            BoundaryMode::Expand => {
+                while let Some(c) = self.token_before(text, start) {
+                    start -= c.len_utf8();
+                }
+                while let Some(c) = self.token_after(text, end) {
+                    end += c.len_utf8();
+                }
+            }
+            BoundaryMode::Contract => {
+                let not_token = |c: char| !self.is_token_char(c);
+                if self.token_before(text, start).is_some() && self.token_after(text, start).is_some() {
+                    while let Some(c) = self.token_after(&text[..end], start) {
+                        start += c.len_utf8();
+                    }
+                    start = end - text[start..end].trim_start_matches(not_token).len();
+                }
+                if self.token_before(text, end).is_some() && self.token_after(text, end).is_some() {
+                    while let Some(c) = self.token_before(&text[start..], end - start) {
+                        end -= c.len_utf8();
+                    }
+                    end = start + text[start..end].trim_end_matches(not_token).len();
+                }
+                if start >= end {
+                    return None;
+                }
+            }
+        }
+        Some((start, end))
+    }
+}
+
+// Rules keyed by entity type, with `*` for types without their own. Types with neither
+// are kept as found.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(transparent)]
+pub struct BoundaryRules {
+    rules: HashMap<String, BoundaryRule>,
+}
+
+impl BoundaryRules {
+    pub fn is_empty(&self) -> bool {
+        self.rules.values().all(|rule| rule.mode == BoundaryMode::Keep)
+    }
+
+    fn rule(&self, entity_type: &str) -> Option<&BoundaryRule> {
+        self.rules.get(entity_type).or_else(|| self.rules.get("*"))
+    }
+}
+
+impl BoundaryAdjuster for BoundaryRules {
+    fn adjust(&self, text: &str, detections: &mut Vec<Detection>) -> bool {
+        let mut changed = false;
+        detections.retain_mut(|detection| {
+            let Some(rule) = self.rule(&detection.entity_type) else { return true };
+            // Offsets off the text's character boundaries are left for the caller to reject
+            let (start, end) = (detection.start, detection.end);
+            if start >= end || !text.is_char_boundary(start) || !text.is_char_boundary(end) {
+                return true;
+            }
+            match rule.apply(text, start, end) {
+                Some(adjusted) => {
+                    changed |= adjusted != (start, end);
+                    (detection.start, detection.end) = adjusted;
+                    true
+                }
+                None => {
+                    changed = true;
+                    false
+                }
+            }
+        });
+        if changed {
+            // Detections expanded to the same token are reported once, at the best score
+            detections.sort_by(|a, b| {
+                (a.start, a.end, &a.entity_type).cmp(&(b.start, b.end, &b.entity_type)).then(b.score.total_cmp(&a.score))
+            });
+            detections.dedup_by(|b, a| (a.start, a.end, &a.entity_type) == (b.start, b.end, &b.entity_type));
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detection(text: &str, entity_type: &str, value: &str) -> Detection {
+        let start = text.find(value).unwrap();
+        Detection { entity_type: entity_type.to_string(), start, end: start + value.len(), score: 0.8 }
+    }
+
+    fn adjusted<'a>(rules: &BoundaryRules, text: &'a str, found: Vec<Detection>) -> Vec<(&'a str, String)> {
+        let mut detections = found;
+        rules.adjust(text, &mut detections);
+        detections.into_iter().map(|detection| (&text[detection.start..detection.end], detection.entity_type)).collect()
+    }
+
+    fn rules(value: serde_json::Value) -> BoundaryRules {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_urls_expand_to_the_whole_address() {
+        let rules = rules(serde_json::json!({ "URL": { "mode": "expand", "joiners": ":/.?=&%-~#" } }));
+        let text = "See https://intranet.example.com/people/jane-doe?tab=cv, then reply.";
+        let found = vec![detection(text, "URL", "intranet.example.com/people")];
+        assert_eq!(adjusted(&rules, text, found), [("https://intranet.example.com/people/jane-doe?tab=cv", "URL".to_string())]);
+
+        // Two pieces of one address become a single detection
+        let found = vec![detection(text, "URL", "https"), detection(text, "URL", "jane-doe")];
+        assert_eq!(adjusted(&rules, text, found).len(), 1);
+    }
+
+    #[test]
+    fn test_emails_inside_tokens_follow_the_rule_of_their_type() {
+        let text = "Logs from ops_jane@example.com_2024.txt and bob@example.org";
+        let found = || vec![detection(text, "EMAIL_ADDRESS", "jane@example.com"), detection(text, "EMAIL_ADDRESS", "bob@example.org")];
+
+        let expand = rules(serde_json::json!({ "EMAIL_ADDRESS": { "mode": "expand", "joiners": ".@-" } }));
+        assert_eq!(
+            adjusted(&expand, text, found()).iter().map(|(value, _)| *value).collect::<Vec<_>>(),
+            ["ops_jane@example.com_2024.txt", "bob@example.org"]
+        );
+
+        // Without `@` and `.` as joiners, contracting keeps only the tokens wholly inside
+        let contract = rules(serde_json::json!({ "*": { "mode": "contract" } }));
+        assert_eq!(
+            adjusted(&contract, text, found()).iter().map(|(value, _)| *value).collect::<Vec<_>>(),
+            ["example", "bob@example.org"]
+        );
+    }
+
+    #[test]
+    fn test_snake_case_identifiers_are_not_split() {
+        let text = "Set user_john_smith_id = 42 for John Smithson";
+        let found = || vec![detection(text, "PERSON", "john_smith"), detection(text, "PERSON", "John Smith")];
+
+        let expand = rules(serde_json::json!({ "PERSON": { "mode": "expand" } }));
+        assert_eq!(
+            adjusted(&expand, text, found()).iter().map(|(value, _)| *value).collect::<Vec<_>>(),
+            ["user_john_smith_id", "John Smithson"]
+        );
+
+        // Contracting drops the detection inside the identifier and the partial surname
+        let contract = rules(serde_json::json!({ "PERSON": { "mode": "contract" } }));
+        assert_eq!(adjusted(&contract, text, found()), [("John", "PERSON".to_string())]);
+
+        // Types without a rule, and `keep`, stay as found
+        let mut detections = found();
+        assert!(!rules(serde_json::json!({ "URL": { "mode": "expand" }, "*": {} })).adjust(text, &mut detections));
+        assert_eq!(detections, found());
+        assert!(rules(serde_json::json!({ "PERSON": { "mode": "keep" } })).is_empty());
+        assert!(serde_json::from_value::<BoundaryRules>(serde_json::json!({ "PERSON": { "mode": "grow" } })).is_err());
+    }
+}
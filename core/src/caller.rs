@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+
+// Identity of the party making a request, as asserted by the `X-Principal-Id`
+// and `X-Tenant-Id` headers
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Caller {
+    pub principal: Option<String>,
+    pub tenant: Option<String>,
+    // What the credential may do; `None` for identities that are not scoped, such as
+    // ones asserted by a gateway
+    pub scopes: Option<Vec<Scope>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Upload,
+    Download,
+    // Reverse pseudonymized files; never implied by `download`
+    Unredact,
+    // Remove stored files
+    Delete,
+    // Operator reads such as the audit trail; only granted explicitly
+    Admin,
+    // Read the retained originals of uploads; only granted explicitly
+    Original,
+}
+
+impl Caller {
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.as_ref().is_none_or(|scopes| scopes.contains(&scope))
+    }
+
+    // Whether the credential names `scope`; unscoped identities are granted nothing
+    pub fn granted(&self, scope: Scope) -> bool {
+        self.scopes.as_ref().is_some_and(|scopes| scopes.contains(&scope))
+    }
+}
+
+#[cfg(feature = "axum")]
+mod extract {
+    use axum::{async_trait, extract::FromRequestParts, http::{request::Parts, HeaderMap}};
+    use std::convert::Infallible;
+
+    use super::Caller;
+
+    impl Caller {
+        pub fn from_headers(headers: &HeaderMap) -> Self {
+            let header = |name: &str| {
+                headers.get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::trim)
+                    .filter(|value| !value.is_empty())
+                    .map(str::to_string)
+            };
+
+            Self {
+                principal: header("X-Principal-Id"),
+                tenant: header("X-Tenant-Id"),
+                scopes: None,
+            }
+        }
+    }
+
+    // The caller resolved by `auth::middleware` when it runs, otherwise the headers
+    #[async_trait]
+    impl<S: Send + Sync> FromRequestParts<S> for Caller {
+        type Rejection = Infallible;
+
+        async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+            if let Some(caller) = parts.extensions.get::<Caller>() {
+                return Ok(caller.clone());
+            }
+            Ok(Self::from_headers(&parts.headers))
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures_util::stream::{self, StreamExt};
+use std::future::Future;
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::backend::{Analysis, EntityFilter, RedactionBackend};
+use crate::report::{rewrite_detections, Detection};
+use crate::resilience::CircuitStatus;
+use crate::rules;
+
+// Where a chunk may end, best first: after a paragraph, a sentence, then any space
+const BREAKS: [&[&str]; 3] = [&["\n\n"], &[". ", "! ", "? ", ".\n", "!\n", "?\n", "\n"], &[" ", "\t"]];
+
+// How far the analysis of one text has got: `chunk` of its `chunks` is done, and
+// `entities` were found in that chunk. Detections in the overlap between chunks are
+// counted in both.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkProgress {
+    pub chunk: usize,
+    pub chunks: usize,
+    pub entities: usize,
+}
+
+// How far an upload has got: a stage of its pipeline finished after `ms`, as recorded
+// in its `UploadProfile`, or a chunk of its text was analyzed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UploadProgress {
+    Stage { stage: &'static str, ms: f64 },
+    Chunk(ChunkProgress),
+}
+
+pub type ProgressReporter = Arc<dyn Fn(UploadProgress) + Send + Sync>;
+
+tokio::task_local! {
+    static PROGRESS: ProgressReporter;
+}
+
+// Run `future`, telling `report` of every stage finished and chunk analyzed within it,
+// e.g. for an upload job whose client follows its progress
+pub async fn report_progress<F: Future>(report: ProgressReporter, future: F) -> F::Output {
+    PROGRESS.scope(report, future).await
+}
+
+fn progress(chunk: usize, chunks: usize, entities: usize) {
+    let _ = PROGRESS.try_with(|report| report(UploadProgress::Chunk(ChunkProgress { chunk, chunks, entities })));
+}
+
+// Tell the reporter of the running upload, if any, that `stage` took `ms`
+pub fn stage_done(stage: &'static str, ms: f64) {
+    let _ = PROGRESS.try_with(|report| report(UploadProgress::Stage { stage, ms }));
+}
+
+// Sends texts longer than `max_bytes` to the backend in chunks, as Presidio rejects or
+// times out on very large ones. Chunks overlap by about `overlap_bytes`, so an entity
+// cut at one chunk's edge is found whole in the next, and up to `concurrency` of them
+// are analyzed at once. Shorter texts go to the backend as they are.
+pub struct ChunkedBackend {
+    backend: Box<dyn RedactionBackend>,
+    max_bytes: usize,
+    overlap_bytes: usize,
+    concurrency: usize,
+}
+
+impl ChunkedBackend {
+    pub fn new(backend: Box<dyn RedactionBackend>, max_bytes: usize, overlap_bytes: usize, concurrency: usize) -> Result<Self> {
+        if overlap_bytes >= max_bytes / 2 {
+            return Err(anyhow!("The chunk overlap must be less than half the chunk size"));
+        }
+        Ok(Self { backend, max_bytes, overlap_bytes, concurrency: concurrency.max(1) })
+    }
+}
+
+#[async_trait]
+impl RedactionBackend for ChunkedBackend {
+    fn name(&self) -> &str {
+        self.backend.name()
+    }
+
+    // The backend's redactions cannot be stitched where chunks overlap, so the text is
+    // redacted again from the detections of all chunks
+    async fn analyze(&self, text: &str, strategy: &str, filter: EntityFilter<'_>) -> Result<Analysis> {
+        let ranges = chunk_ranges(text, self.max_bytes, self.overlap_bytes);
+        if ranges.len() == 1 {
+            let analysis = self.backend.analyze(text, strategy, filter).await?;
+            progress(1, 1, analysis.detections.len());
+            return Ok(analysis);
+        }
+
+        // Collected first; a lazy iterator held across the await would not be `Send`
+        let calls: Vec<_> = ranges.iter().map(|range| self.backend.analyze(&text[range.clone()], strategy, filter)).collect();
+        let mut results = stream::iter(calls).buffered(self.concurrency);
+        let mut analyses: Vec<Result<Analysis>> = Vec::with_capacity(ranges.len());
+        while let Some(analysis) = results.next().await {
+            progress(analyses.len() + 1, ranges.len(), analysis.as_ref().map_or(0, |analysis| analysis.detections.len()));
+            analyses.push(analysis);
+        }
+        let mut fallback = false;
+        let mut found = Vec::new();
+        for (chunk, (range, analysis)) in ranges.iter().zip(analyses).enumerate() {
+            let analysis = analysis?;
+            fallback |= analysis.fallback;
+            found.extend(analysis.detections.into_iter().map(|detection| (chunk, shifted(detection, range.start))));
+        }
+        let detections = stitched(found);
+        let redacted = rewrite_detections(text, &detections, |detection, _| rules::replacement(&detection.entity_type, strategy))
+            .ok_or_else(|| anyhow!("Detections do not fall on character boundaries"))?;
+        Ok(Analysis { redacted, detections, fallback })
+    }
+
+    async fn detect(&self, text: &str, filter: EntityFilter<'_>) -> Result<Vec<Detection>> {
+        let ranges = chunk_ranges(text, self.max_bytes, self.overlap_bytes);
+        if ranges.len() == 1 {
+            let detections = self.backend.detect(text, filter).await?;
+            progress(1, 1, detections.len());
+            return Ok(detections);
+        }
+
+        let calls: Vec<_> = ranges.iter().map(|range| self.backend.detect(&text[range.clone()], filter)).collect();
+        let mut pending = stream::iter(calls).buffered(self.concurrency);
+        let mut results: Vec<Result<Vec<Detection>>> = Vec::with_capacity(ranges.len());
+        while let Some(detections) = pending.next().await {
+            progress(results.len() + 1, ranges.len(), detections.as_ref().map_or(0, Vec::len));
+            results.push(detections);
+        }
+        let mut found = Vec::new();
+        for (chunk, (range, detections)) in ranges.iter().zip(results).enumerate() {
+            found.extend(detections?.into_iter().map(|detection| (chunk, shifted(detection, range.start))));
+        }
+        Ok(stitched(found))
+    }
+
+    fn entities(&self) -> Vec<&str> {
+        self.backend.entities()
+    }
+
+    fn circuits(&self) -> Vec<CircuitStatus> {
+        self.backend.circuits()
+    }
+
+    async fn check_contract(&self) -> Result<()> {
+        self.backend.check_contract().await
+    }
+
+    async fn ping(&self) -> Result<()> {
+        self.backend.ping().await
+    }
+}
+
+// Byte ranges covering `text`, each at most `max_bytes` long and starting up to
+// `overlap_bytes` before the previous one ends. A chunk ends at the best break in the
+// back half of its window, and is cut between characters only when there is none.
+pub fn chunk_ranges(text: &str, max_bytes: usize, overlap_bytes: usize) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    while text.len() - start > max_bytes {
+        let limit = match text.floor_char_boundary(start + max_bytes) {
+            limit if limit > start => limit,
+            _ => text.ceil_char_boundary(start + 1),
+        };
+        let window = &text[start..limit];
+        let end = BREAKS.iter()
+            .find_map(|breaks| {
+                breaks.iter()
+                    .filter_map(|separator| window.rfind(separator).map(|at| at + separator.len()))
+                    .max()
+                    .filter(|&at| at > window.len() / 2)
+            })
+            .map_or(limit, |at| start + at);
+        ranges.push(start..end);
+
+        // The overlap starts after a space, so the next chunk does not open mid-word
+        let from = text.ceil_char_boundary(end.saturating_sub(overlap_bytes).max(start + 1));
+        start = match text[from..end].char_indices().find(|(_, c)| c.is_whitespace()) {
+            Some((at, c)) => from + at + c.len_utf8(),
+            None => from,
+        };
+    }
+    ranges.push(start..text.len());
+    ranges
+}
+
+fn shifted(detection: Detection, offset: usize) -> Detection {
+    Detection { start: detection.start + offset, end: detection.end + offset, ..detection }
+}
+
+// Detections of all chunks as one list. Where a detection overlaps one from another
+// chunk they are the same entity, seen twice in the overlap or cut at a chunk's edge:
+// it is kept once, over both extents, as the type scored higher.
+fn stitched(mut found: Vec<(usize, Detection)>) -> Vec<Detection> {
+    found.sort_by_key(|(_, detection)| (detection.start, std::cmp::Reverse(detection.end)));
+    let mut merged: Vec<(usize, Detection)> = Vec::with_capacity(found.len());
+    for (chunk, detection) in found {
+        match merged.last_mut() {
+            Some((seen_in, seen)) if *seen_in != chunk && detection.start < seen.end => {
+                seen.end = seen.end.max(detection.end);
+                if detection.score > seen.score {
+                    seen.entity_type = detection.entity_type;
+                    seen.score = detection.score;
+                }
+            }
+            _ => merged.push((chunk, detection)),
+        }
+    }
+    merged.into_iter().map(|(_, detection)| detection).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::RegexEngine;
+
+    #[test]
+    fn test_chunks_end_on_sentences_and_overlap() {
+        let text = "First sentence here. Second one follows.\n\nA new paragraph starts. And ends.";
+        let ranges = chunk_ranges(text, 48, 12);
+        assert_eq!(&text[ranges[0].clone()], "First sentence here. Second one follows.\n\n");
+        assert!(ranges.windows(2).all(|pair| pair[1].start < pair[0].end && pair[1].start > pair[0].start));
+        assert_eq!(ranges.last().unwrap().end, text.len());
+        assert!(ranges.iter().all(|range| range.len() <= 48));
+
+        // Without breaks, chunks are cut between characters
+        let text = "é".repeat(40);
+        let ranges = chunk_ranges(&text, 25, 4);
+        assert!(ranges.iter().all(|range| text.is_char_boundary(range.start) && text.is_char_boundary(range.end)));
+        assert_eq!(chunk_ranges("short", 48, 12), vec![0..5]);
+    }
+
+    #[tokio::test]
+    async fn test_entities_at_chunk_edges_are_redacted_once() {
+        let filler = "Nothing to see in this line at all. ".repeat(3);
+        let text = format!("{}Mail jane@example.com now. {}Call 555-010-0199 today.", filler, filler);
+        let chunked = ChunkedBackend::new(Box::new(RegexEngine::new()), 64, 24, 2).unwrap();
+        assert!(chunk_ranges(&text, 64, 24).len() > 3);
+
+        let whole = RegexEngine::new().analyze(&text, "replace", EntityFilter::default()).await.unwrap();
+        let analysis = chunked.analyze(&text, "replace", EntityFilter::default()).await.unwrap();
+        assert_eq!(analysis.redacted, whole.redacted);
+        assert_eq!(analysis.detections.len(), 2);
+        let email = &analysis.detections[0];
+        assert_eq!(&text[email.start..email.end], "jane@example.com");
+
+        // Each chunk is reported as it is done, in order
+        let reported = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = reported.clone();
+        let detect = chunked.detect(&text, EntityFilter::default());
+        let report = move |progress| {
+            if let UploadProgress::Chunk(progress) = progress {
+                sink.lock().unwrap().push(progress);
+            }
+        };
+        let detections = report_progress(Arc::new(report), detect).await.unwrap();
+        assert_eq!(detections, analysis.detections);
+        let reported = reported.lock().unwrap();
+        let chunks = chunk_ranges(&text, 64, 24).len();
+        assert_eq!(reported.iter().map(|progress| (progress.chunk, progress.chunks)).collect::<Vec<_>>(), (1..=chunks).map(|chunk| (chunk, chunks)).collect::<Vec<_>>());
+        assert!(reported.iter().map(|progress| progress.entities).sum::<usize>() >= 2);
+    }
+
+    #[test]
+    fn test_detections_cut_at_an_edge_are_merged() {
+        let detection = |entity_type: &str, start, end, score| Detection { entity_type: entity_type.to_string(), start, end, score };
+        let found = vec![
+            (0, detection("PERSON", 40, 44, 0.6)),
+            (1, detection("PERSON", 40, 49, 0.85)),
+            (1, detection("EMAIL_ADDRESS", 60, 76, 1.0)),
+            (2, detection("EMAIL_ADDRESS", 60, 76, 1.0)),
+        ];
+        assert_eq!(stitched(found), vec![detection("PERSON", 40, 49, 0.85), detection("EMAIL_ADDRESS", 60, 76, 1.0)]);
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Read;
+
+use crate::secret::SecretBytes;
+
+// Bound on what a compressed plaintext may inflate to, unless `max_decompressed_bytes` is set
+pub const DEFAULT_MAX_DECOMPRESSED_BYTES: usize = 16 * 1024 * 1024;
+
+// How the client compressed an upload's plaintext before encrypting it. It is
+// decompressed once decrypted, before any check or redaction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+// A plaintext inflating past the limit, told apart from a corrupt one so it can be
+// answered with 413
+#[derive(Debug)]
+pub struct DecompressedTooLarge {
+    pub max_bytes: usize,
+}
+
+impl fmt::Display for DecompressedTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The plaintext decompresses to more than {} bytes", self.max_bytes)
+    }
+}
+
+impl std::error::Error for DecompressedTooLarge {}
+
+impl Compression {
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    // Read no more than `max_bytes` of output, so a small upload cannot inflate without bound
+    pub fn decompress(self, data: SecretBytes, max_bytes: usize) -> Result<SecretBytes> {
+        let decoder: Box<dyn Read + '_> = match self {
+            Compression::None => return Ok(data),
+            Compression::Gzip => Box::new(flate2::read::GzDecoder::new(&*data)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(&*data)
+                .map_err(|e| anyhow!("Invalid zstd stream: {}", e))?),
+        };
+        let plaintext = SecretBytes::read_from(decoder.take(max_bytes as u64 + 1))
+            .map_err(|e| anyhow!("Invalid {} stream: {}", self.name(), e))?;
+        if plaintext.len() > max_bytes {
+            return Err(DecompressedTooLarge { max_bytes }.into());
+        }
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_plaintexts_decompress_within_the_limit() {
+        let text = "Mail jane@example.com about the claim. ".repeat(100);
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(text.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = zstd::stream::encode_all(text.as_bytes(), 0).unwrap();
+
+        let secret = |bytes: &[u8]| SecretBytes::new(bytes.to_vec());
+        assert_eq!(&*Compression::Gzip.decompress(secret(&gzip), text.len()).unwrap(), text.as_bytes());
+        assert_eq!(&*Compression::Zstd.decompress(secret(&zstd), text.len()).unwrap(), text.as_bytes());
+        assert_eq!(&*Compression::None.decompress(secret(b"as is"), 1).unwrap(), b"as is");
+
+        let too_large = Compression::Gzip.decompress(secret(&gzip), text.len() - 1).unwrap_err();
+        assert!(too_large.downcast_ref::<DecompressedTooLarge>().is_some());
+        let corrupt = Compression::Zstd.decompress(secret(text.as_bytes()), DEFAULT_MAX_DECOMPRESSED_BYTES).unwrap_err();
+        assert!(corrupt.downcast_ref::<DecompressedTooLarge>().is_none());
+    }
+}
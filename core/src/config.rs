@@ -0,0 +1,634 @@
+use anyhow::{anyhow, Result};
+use figment::{
+    providers::{Env, Format, Serialized, Toml},
+    Figment,
+};
+use rustls::{server::AllowAnyAuthenticatedClient, RootCertStore, ServerConfig};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+use crate::compression::DEFAULT_MAX_DECOMPRESSED_BYTES;
+use crate::deprecation::ProtocolDeprecations;
+use crate::language::Languages;
+use crate::settings::RuntimeSettings;
+use crate::storage::{EvictionPolicy, StorageLimits};
+use crate::upstream::{self, UpstreamTlsConfig};
+
+// Settings of the service, layered from the defaults below, the TOML file at
+// `CONFIG_PATH`, then environment variables under the names the README lists. Keys in
+// the file are the variable names in lowercase, e.g. `presidio_url`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AppConfig {
+    pub bind_addr: IpAddr,
+    pub port: u16,
+    // Port of the gRPC interface; it is not served when unset
+    pub grpc_port: Option<u16>,
+    // PEM certificate chain and key; both interfaces serve TLS when they are set
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    // CA bundle of client certificates; clients without one it signed are refused
+    pub tls_client_ca_path: Option<String>,
+    // Body limit of `POST /upload`, base64 ciphertext included
+    pub max_upload_bytes: usize,
+    // Body limit of every route without its own
+    pub max_request_bytes: usize,
+    // What an upload's plaintext may inflate to when it names a `compression`
+    pub max_decompressed_bytes: usize,
+    // Body limits of `POST /upload/multipart` and `POST /upload/stream`
+    pub multipart_max_bytes: usize,
+    pub stream_upload_max_bytes: usize,
+    // `memory`, `disk` or `s3`; `disk` when `storage_dir` is set, otherwise `memory`
+    pub storage_backend: Option<String>,
+    pub storage_dir: Option<String>,
+    pub s3_bucket: Option<String>,
+    // Prepended to every object key
+    pub s3_prefix: String,
+    // S3-compatible service to use instead of AWS, e.g. MinIO
+    pub s3_endpoint: Option<String>,
+    pub s3_region: String,
+    #[serde(deserialize_with = "flag")]
+    pub s3_force_path_style: bool,
+    // Directory or bucket stored files are copied to in the background, such as one in
+    // another region. The replica bucket shares `s3_prefix` and, unless these are set,
+    // the primary's endpoint and region.
+    pub replica_storage_dir: Option<String>,
+    pub replica_s3_bucket: Option<String>,
+    pub replica_s3_endpoint: Option<String>,
+    pub replica_s3_region: Option<String>,
+    // Serve from the replica instead of the primary storage, which is not replicated to
+    #[serde(deserialize_with = "flag")]
+    pub storage_failover: bool,
+    // Directory or bucket files move to once `cold_tier_after_days` old, checked every
+    // `cold_tier_sweep_seconds`. The cold bucket shares `s3_prefix` and, unless these are
+    // set, the primary's endpoint and region.
+    pub cold_storage_dir: Option<String>,
+    pub cold_s3_bucket: Option<String>,
+    pub cold_s3_endpoint: Option<String>,
+    pub cold_s3_region: Option<String>,
+    pub cold_tier_after_days: u64,
+    pub cold_tier_sweep_seconds: u64,
+    // Serve reads only, from disk or S3 storage an instance taking uploads writes to,
+    // picking up its changes every `read_only_refresh_seconds`
+    #[serde(deserialize_with = "flag")]
+    pub read_only: bool,
+    pub read_only_refresh_seconds: u64,
+    // Bounds on stored files, which are all held in memory; storing past
+    // `storage_max_bytes` or `storage_max_files` evicts `oldest` or `lru` files first
+    pub storage_max_bytes: Option<u64>,
+    pub storage_max_files: Option<usize>,
+    pub storage_max_file_bytes: Option<usize>,
+    pub storage_eviction: String,
+    // How often the backend's usage totals are written through, so at most that much
+    // is lost on a restart
+    pub usage_snapshot_seconds: u64,
+    // Backends to try in order, comma-separated
+    pub redaction_backend: String,
+    pub presidio_url: String,
+    pub presidio_timeout_seconds: u64,
+    pub presidio_ca_bundle: Option<String>,
+    pub presidio_client_cert: Option<String>,
+    pub presidio_client_key: Option<String>,
+    // Startup check that Presidio answers in a known shape: `warn`, `strict` or `off`
+    pub presidio_contract_check: String,
+    // Directory of the ONNX NER model the `onnx` backend loads
+    pub ner_model_dir: Option<String>,
+    // Texts longer than this are analyzed in chunks overlapping by about
+    // `redaction_chunk_overlap_bytes`, up to `redaction_chunk_concurrency` at once
+    pub redaction_chunk_bytes: usize,
+    pub redaction_chunk_overlap_bytes: usize,
+    pub redaction_chunk_concurrency: usize,
+    // ISO 639-1 codes of the languages uploads may be redacted in, comma-separated; the
+    // first is used for uploads naming none unless `language_detection` is set
+    pub supported_languages: String,
+    #[serde(deserialize_with = "flag")]
+    pub language_detection: bool,
+    // Accept uploads without a nonce, decrypting them under the all-zero nonce
+    #[serde(deserialize_with = "flag")]
+    pub allow_legacy_zero_nonce: bool,
+    // Modes being retired, as `mode[=YYYY-MM-DD],...`; only `zero-nonce` when unset
+    pub protocol_deprecations: Option<String>,
+    pub session_ttl_seconds: u64,
+    pub session_max_uploads: usize,
+    // TTL of uploads that set no `ttl_seconds`; kept until deleted when unset
+    pub default_file_ttl_seconds: Option<u64>,
+    // How often expired files are purged, and how long originals are kept; as long as
+    // their file when unset
+    pub file_expiry_sweep_seconds: u64,
+    pub original_retention_seconds: Option<u64>,
+    // Strategy, entity types (comma-separated) and score threshold of uploads that set
+    // none of their own
+    pub default_strategy: String,
+    pub default_entities: Option<String>,
+    pub default_score_threshold: Option<f32>,
+    // Requests per client per minute and the burst allowed; unlimited when unset
+    pub rate_limit_per_minute: Option<u32>,
+    pub rate_limit_burst: Option<u32>,
+    // Operator alert channels: `smtp://` (STARTTLS) or `smtps://` with the credentials
+    // in the URL, and comma-separated recipients
+    pub alert_smtp_url: Option<String>,
+    pub alert_email_from: Option<String>,
+    pub alert_email_to: Option<String>,
+    pub alert_slack_webhook_url: Option<String>,
+    pub alert_pagerduty_routing_key: Option<String>,
+    // Channels each severity goes to, comma-separated; unconfigured ones are skipped
+    pub alert_channels_critical: String,
+    pub alert_channels_warning: String,
+    pub alert_channels_info: String,
+    // Failed upload jobs within the window that raise an alert
+    pub alert_job_failures: u32,
+    pub alert_job_failure_window_seconds: u64,
+    // Delivery targets told when a file is erased: a webhook, signed with the secret,
+    // and a bucket holding delivered copies under `<prefix><file_id>`, reached like
+    // the storage bucket
+    pub deletion_webhook_url: Option<String>,
+    pub deletion_webhook_secret: Option<String>,
+    pub deletion_s3_bucket: Option<String>,
+    pub deletion_s3_prefix: String,
+    // Receives an anonymized report of each request whose handler panicked
+    pub crash_report_url: Option<String>,
+    // JSONL file reviewer feedback is kept in across restarts; in memory only when unset
+    pub feedback_path: Option<String>,
+    // False-positive reports of a value before it is suggested for the allowlist
+    pub feedback_allowlist_min_occurrences: usize,
+    // JSONL file upload jobs' changes of state are appended to, so pending jobs survive a
+    // restart; in memory only when unset
+    pub job_journal_path: Option<String>,
+    // JSONL file upload callbacks are kept in until delivered, so pending ones are retried
+    // after a restart; in memory only when unset
+    pub callback_outbox_path: Option<String>,
+    // Backend, named as in `redaction_backend`, that sampled originals are re-run through
+    // to score the live one's detections; sampling is off when unset
+    pub quality_reference_backend: Option<String>,
+    // Presidio the reference backend calls, when it is a pinned Presidio apart from the
+    // live one; `presidio_url` when unset
+    pub quality_reference_presidio_url: Option<String>,
+    // Retained originals of the most recent uploads scored per run
+    pub quality_sample_size: usize,
+    pub quality_sample_seconds: u64,
+}
+
+// Names of the operator alert channels
+pub const ALERT_CHANNELS: [&str; 3] = ["email", "slack", "pagerduty"];
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            bind_addr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 10003,
+            grpc_port: None,
+            tls_cert_path: None,
+            tls_key_path: None,
+            tls_client_ca_path: None,
+            max_upload_bytes: 2 * 1024 * 1024,
+            max_request_bytes: 1024 * 1024,
+            max_decompressed_bytes: DEFAULT_MAX_DECOMPRESSED_BYTES,
+            multipart_max_bytes: 64 * 1024 * 1024,
+            stream_upload_max_bytes: 1024 * 1024 * 1024,
+            storage_backend: None,
+            storage_dir: None,
+            s3_bucket: None,
+            s3_prefix: String::new(),
+            s3_endpoint: None,
+            s3_region: "us-east-1".to_string(),
+            s3_force_path_style: false,
+            replica_storage_dir: None,
+            replica_s3_bucket: None,
+            replica_s3_endpoint: None,
+            replica_s3_region: None,
+            storage_failover: false,
+            cold_storage_dir: None,
+            cold_s3_bucket: None,
+            cold_s3_endpoint: None,
+            cold_s3_region: None,
+            cold_tier_after_days: 30,
+            cold_tier_sweep_seconds: 3600,
+            read_only: false,
+            read_only_refresh_seconds: 30,
+            storage_max_bytes: None,
+            storage_max_files: None,
+            storage_max_file_bytes: None,
+            storage_eviction: "oldest".to_string(),
+            usage_snapshot_seconds: 60,
+            redaction_backend: "presidio".to_string(),
+            presidio_url: "http://localhost:8001".to_string(),
+            presidio_timeout_seconds: 30,
+            presidio_ca_bundle: None,
+            presidio_client_cert: None,
+            presidio_client_key: None,
+            presidio_contract_check: "warn".to_string(),
+            ner_model_dir: None,
+            redaction_chunk_bytes: 100_000,
+            redaction_chunk_overlap_bytes: 200,
+            redaction_chunk_concurrency: 4,
+            supported_languages: "en".to_string(),
+            language_detection: false,
+            allow_legacy_zero_nonce: false,
+            protocol_deprecations: None,
+            session_ttl_seconds: 3600,
+            session_max_uploads: 1000,
+            default_file_ttl_seconds: None,
+            file_expiry_sweep_seconds: 60,
+            original_retention_seconds: None,
+            default_strategy: "replace".to_string(),
+            default_entities: None,
+            default_score_threshold: None,
+            rate_limit_per_minute: None,
+            rate_limit_burst: None,
+            alert_smtp_url: None,
+            alert_email_from: None,
+            alert_email_to: None,
+            alert_slack_webhook_url: None,
+            alert_pagerduty_routing_key: None,
+            alert_channels_critical: "pagerduty,slack,email".to_string(),
+            alert_channels_warning: "slack,email".to_string(),
+            alert_channels_info: String::new(),
+            alert_job_failures: 5,
+            alert_job_failure_window_seconds: 300,
+            deletion_webhook_url: None,
+            deletion_webhook_secret: None,
+            deletion_s3_bucket: None,
+            deletion_s3_prefix: String::new(),
+            crash_report_url: None,
+            feedback_path: None,
+            feedback_allowlist_min_occurrences: 3,
+            job_journal_path: None,
+            callback_outbox_path: None,
+            quality_reference_backend: None,
+            quality_reference_presidio_url: None,
+            quality_sample_size: 20,
+            quality_sample_seconds: 3600,
+        }
+    }
+}
+
+const ENV_KEYS: [&str; 84] = [
+    "BIND_ADDR",
+    "PORT",
+    "GRPC_PORT",
+    "TLS_CERT_PATH",
+    "TLS_KEY_PATH",
+    "TLS_CLIENT_CA_PATH",
+    "MAX_UPLOAD_BYTES",
+    "MAX_REQUEST_BYTES",
+    "MAX_DECOMPRESSED_BYTES",
+    "MULTIPART_MAX_BYTES",
+    "STREAM_UPLOAD_MAX_BYTES",
+    "STORAGE_BACKEND",
+    "STORAGE_DIR",
+    "S3_BUCKET",
+    "S3_PREFIX",
+    "S3_ENDPOINT",
+    "S3_REGION",
+    "S3_FORCE_PATH_STYLE",
+    "REPLICA_STORAGE_DIR",
+    "REPLICA_S3_BUCKET",
+    "REPLICA_S3_ENDPOINT",
+    "REPLICA_S3_REGION",
+    "STORAGE_FAILOVER",
+    "COLD_STORAGE_DIR",
+    "COLD_S3_BUCKET",
+    "COLD_S3_ENDPOINT",
+    "COLD_S3_REGION",
+    "COLD_TIER_AFTER_DAYS",
+    "COLD_TIER_SWEEP_SECONDS",
+    "READ_ONLY",
+    "READ_ONLY_REFRESH_SECONDS",
+    "STORAGE_MAX_BYTES",
+    "STORAGE_MAX_FILES",
+    "STORAGE_MAX_FILE_BYTES",
+    "STORAGE_EVICTION",
+    "USAGE_SNAPSHOT_SECONDS",
+    "REDACTION_BACKEND",
+    "PRESIDIO_URL",
+    "PRESIDIO_TIMEOUT_SECONDS",
+    "PRESIDIO_CA_BUNDLE",
+    "PRESIDIO_CLIENT_CERT",
+    "PRESIDIO_CLIENT_KEY",
+    "PRESIDIO_CONTRACT_CHECK",
+    "NER_MODEL_DIR",
+    "REDACTION_CHUNK_BYTES",
+    "REDACTION_CHUNK_OVERLAP_BYTES",
+    "REDACTION_CHUNK_CONCURRENCY",
+    "SUPPORTED_LANGUAGES",
+    "LANGUAGE_DETECTION",
+    "ALLOW_LEGACY_ZERO_NONCE",
+    "PROTOCOL_DEPRECATIONS",
+    "SESSION_TTL_SECONDS",
+    "SESSION_MAX_UPLOADS",
+    "DEFAULT_FILE_TTL_SECONDS",
+    "FILE_EXPIRY_SWEEP_SECONDS",
+    "ORIGINAL_RETENTION_SECONDS",
+    "DEFAULT_STRATEGY",
+    "DEFAULT_ENTITIES",
+    "DEFAULT_SCORE_THRESHOLD",
+    "RATE_LIMIT_PER_MINUTE",
+    "RATE_LIMIT_BURST",
+    "ALERT_SMTP_URL",
+    "ALERT_EMAIL_FROM",
+    "ALERT_EMAIL_TO",
+    "ALERT_SLACK_WEBHOOK_URL",
+    "ALERT_PAGERDUTY_ROUTING_KEY",
+    "ALERT_CHANNELS_CRITICAL",
+    "ALERT_CHANNELS_WARNING",
+    "ALERT_CHANNELS_INFO",
+    "ALERT_JOB_FAILURES",
+    "ALERT_JOB_FAILURE_WINDOW_SECONDS",
+    "DELETION_WEBHOOK_URL",
+    "DELETION_WEBHOOK_SECRET",
+    "DELETION_S3_BUCKET",
+    "DELETION_S3_PREFIX",
+    "CRASH_REPORT_URL",
+    "FEEDBACK_PATH",
+    "FEEDBACK_ALLOWLIST_MIN_OCCURRENCES",
+    "JOB_JOURNAL_PATH",
+    "CALLBACK_OUTBOX_PATH",
+    "QUALITY_REFERENCE_BACKEND",
+    "QUALITY_REFERENCE_PRESIDIO_URL",
+    "QUALITY_SAMPLE_SIZE",
+    "QUALITY_SAMPLE_SECONDS",
+];
+
+impl AppConfig {
+    // Defaults, then the file, then the environment; callers can merge more on top,
+    // such as command-line flags
+    pub fn figment(path: Option<&str>) -> Figment {
+        let figment = Figment::from(Serialized::defaults(Self::default()));
+        let figment = match path {
+            Some(path) => figment.merge(Toml::file_exact(path)),
+            None => figment,
+        };
+        figment.merge(Env::raw().only(&ENV_KEYS))
+    }
+
+    pub fn from_env() -> Result<Self> {
+        Self::extract(Self::figment(std::env::var("CONFIG_PATH").ok().as_deref()))
+    }
+
+    pub fn extract(figment: Figment) -> Result<Self> {
+        let config: Self = figment.extract().map_err(|e| anyhow!("Invalid configuration: {}", e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn socket_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_addr, self.port)
+    }
+
+    pub fn grpc_socket_addr(&self) -> Option<SocketAddr> {
+        self.grpc_port.map(|port| SocketAddr::new(self.bind_addr, port))
+    }
+
+    // TLS of both listeners, None when serving plain HTTP. With a client CA every
+    // connection must present a certificate it signed.
+    pub fn server_tls(&self) -> Result<Option<Arc<ServerConfig>>> {
+        let (Some(cert_path), Some(key_path)) = (&self.tls_cert_path, &self.tls_key_path) else {
+            return Ok(None);
+        };
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match &self.tls_client_ca_path {
+            Some(path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in upstream::read_certs(path)? {
+                    roots.add(&cert).map_err(|e| anyhow!("Invalid client CA certificate in {}: {}", path, e))?;
+                }
+                builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder
+            .with_single_cert(upstream::read_certs(cert_path)?, upstream::read_private_key(key_path)?)
+            .map_err(|e| anyhow!("Invalid TLS certificate: {}", e))?;
+        // gRPC needs HTTP/2; HTTP clients may use either
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(Some(Arc::new(config)))
+    }
+
+    // TLS of the Presidio client: the paths here, with pins still read from the environment
+    pub fn presidio_tls(&self) -> Result<UpstreamTlsConfig> {
+        Ok(UpstreamTlsConfig {
+            ca_bundle: self.presidio_ca_bundle.clone(),
+            client_cert: self.presidio_client_cert.clone(),
+            client_key: self.presidio_client_key.clone(),
+            ..UpstreamTlsConfig::from_env("PRESIDIO")?
+        })
+    }
+
+    // Where redacted files are kept: `memory`, `disk` or `s3`
+    pub fn storage_backend(&self) -> &str {
+        match (&self.storage_backend, &self.storage_dir) {
+            (Some(backend), _) => backend,
+            (None, Some(_)) => "disk",
+            (None, None) => "memory",
+        }
+    }
+
+    pub fn protocol_deprecations(&self) -> Result<ProtocolDeprecations> {
+        match &self.protocol_deprecations {
+            Some(spec) => ProtocolDeprecations::parse(spec).map_err(|e| anyhow!("Invalid configuration: {}", e)),
+            None => Ok(ProtocolDeprecations::default()),
+        }
+    }
+
+    pub fn storage_limits(&self) -> Result<StorageLimits> {
+        Ok(StorageLimits {
+            max_bytes: self.storage_max_bytes,
+            max_files: self.storage_max_files,
+            max_file_bytes: self.storage_max_file_bytes,
+            eviction: EvictionPolicy::parse(&self.storage_eviction).map_err(|e| anyhow!("Invalid configuration: {}", e))?,
+        })
+    }
+
+    pub fn languages(&self) -> Result<Languages> {
+        Languages::new(&self.supported_languages, self.language_detection).map_err(|e| anyhow!("Invalid configuration: {}", e))
+    }
+
+    // The settings the admin API can change while the service runs, as configured
+    pub fn runtime_settings(&self) -> Result<RuntimeSettings> {
+        let settings = RuntimeSettings {
+            presidio_url: self.presidio_url.clone(),
+            default_strategy: self.default_strategy.clone(),
+            default_entities: self.default_entities.as_deref().map(|entities| {
+                entities.split(',').map(str::trim).filter(|entity| !entity.is_empty()).map(str::to_string).collect()
+            }),
+            default_score_threshold: self.default_score_threshold,
+            default_file_ttl_seconds: self.default_file_ttl_seconds,
+            rate_limit_per_minute: self.rate_limit_per_minute,
+            rate_limit_burst: self.rate_limit_burst,
+        };
+        settings.validate().map_err(|e| anyhow!("Invalid configuration: {}", e))?;
+        Ok(settings)
+    }
+
+    // Channel names of an `alert_channels_*` setting
+    pub fn alert_channels(value: &str) -> impl Iterator<Item = &str> {
+        value.split(',').map(str::trim).filter(|channel| !channel.is_empty())
+    }
+
+    fn validate(&self) -> Result<()> {
+        self.protocol_deprecations()?;
+        self.storage_limits()?;
+        self.languages()?;
+        self.runtime_settings()?;
+        if let (Some(max_file_bytes), Some(max_bytes)) = (self.storage_max_file_bytes, self.storage_max_bytes) {
+            if max_file_bytes as u64 > max_bytes {
+                return Err(anyhow!("Invalid configuration: storage_max_file_bytes must not exceed storage_max_bytes"));
+            }
+        }
+        for channels in [&self.alert_channels_critical, &self.alert_channels_warning, &self.alert_channels_info] {
+            if let Some(unknown) = Self::alert_channels(channels).find(|channel| !ALERT_CHANNELS.contains(channel)) {
+                return Err(anyhow!("Invalid configuration: unknown alert channel {}", unknown));
+            }
+        }
+        if self.alert_smtp_url.is_some() && (self.alert_email_from.is_none() || self.alert_email_to.is_none()) {
+            return Err(anyhow!("Invalid configuration: alert_smtp_url needs alert_email_from and alert_email_to"));
+        }
+
+        if self.deletion_webhook_url.is_some() && self.deletion_webhook_secret.as_deref().is_none_or(str::is_empty) {
+            return Err(anyhow!("Invalid configuration: deletion_webhook_url needs deletion_webhook_secret"));
+        }
+
+        if !["warn", "strict", "off"].contains(&self.presidio_contract_check.as_str()) {
+            return Err(anyhow!("Invalid configuration: presidio_contract_check must be warn, strict or off"));
+        }
+
+        match self.storage_backend() {
+            "memory" => {}
+            "disk" if self.storage_dir.is_none() => return Err(anyhow!("Invalid configuration: disk storage needs storage_dir")),
+            "s3" if self.s3_bucket.is_none() => return Err(anyhow!("Invalid configuration: s3 storage needs s3_bucket")),
+            "disk" | "s3" => {}
+            other => return Err(anyhow!("Invalid configuration: unknown storage_backend {}", other)),
+        }
+        if self.replica_storage_dir.is_some() && self.replica_s3_bucket.is_some() {
+            return Err(anyhow!("Invalid configuration: set replica_storage_dir or replica_s3_bucket, not both"));
+        }
+        if self.storage_failover && self.replica_storage_dir.is_none() && self.replica_s3_bucket.is_none() {
+            return Err(anyhow!("Invalid configuration: storage_failover needs replica_storage_dir or replica_s3_bucket"));
+        }
+        let cold_tier = self.cold_storage_dir.is_some() || self.cold_s3_bucket.is_some();
+        if self.cold_storage_dir.is_some() && self.cold_s3_bucket.is_some() {
+            return Err(anyhow!("Invalid configuration: set cold_storage_dir or cold_s3_bucket, not both"));
+        }
+        if cold_tier && (self.read_only || self.replica_storage_dir.is_some() || self.replica_s3_bucket.is_some()) {
+            return Err(anyhow!("Invalid configuration: cold storage cannot be combined with read_only or a replica"));
+        }
+        if self.read_only && self.storage_backend() == "memory" {
+            return Err(anyhow!("Invalid configuration: read_only needs disk or s3 storage shared with an instance taking uploads"));
+        }
+
+        if self.tls_cert_path.is_some() != self.tls_key_path.is_some() {
+            return Err(anyhow!("Invalid configuration: tls_cert_path and tls_key_path must be set together"));
+        }
+        if self.tls_client_ca_path.is_some() && self.tls_cert_path.is_none() {
+            return Err(anyhow!("Invalid configuration: tls_client_ca_path needs tls_cert_path and tls_key_path"));
+        }
+
+        if self.redaction_chunk_bytes < 1024 {
+            return Err(anyhow!("Invalid configuration: redaction_chunk_bytes must be at least 1024"));
+        }
+        if self.redaction_chunk_overlap_bytes >= self.redaction_chunk_bytes / 2 {
+            return Err(anyhow!("Invalid configuration: redaction_chunk_overlap_bytes must be less than half of redaction_chunk_bytes"));
+        }
+
+        if self.grpc_port == Some(self.port) {
+            return Err(anyhow!("Invalid configuration: grpc_port must differ from port"));
+        }
+
+        let positive = [
+            ("max_upload_bytes", self.max_upload_bytes as u64),
+            ("max_request_bytes", self.max_request_bytes as u64),
+            ("max_decompressed_bytes", self.max_decompressed_bytes as u64),
+            ("multipart_max_bytes", self.multipart_max_bytes as u64),
+            ("stream_upload_max_bytes", self.stream_upload_max_bytes as u64),
+            ("usage_snapshot_seconds", self.usage_snapshot_seconds),
+            ("file_expiry_sweep_seconds", self.file_expiry_sweep_seconds),
+            ("presidio_timeout_seconds", self.presidio_timeout_seconds),
+            ("redaction_chunk_concurrency", self.redaction_chunk_concurrency as u64),
+            ("read_only_refresh_seconds", self.read_only_refresh_seconds),
+            ("session_ttl_seconds", self.session_ttl_seconds),
+            ("session_max_uploads", self.session_max_uploads as u64),
+            ("storage_max_bytes", self.storage_max_bytes.unwrap_or(1)),
+            ("storage_max_files", self.storage_max_files.unwrap_or(1) as u64),
+            ("storage_max_file_bytes", self.storage_max_file_bytes.unwrap_or(1) as u64),
+            ("alert_job_failures", self.alert_job_failures as u64),
+            ("alert_job_failure_window_seconds", self.alert_job_failure_window_seconds),
+            ("feedback_allowlist_min_occurrences", self.feedback_allowlist_min_occurrences as u64),
+            ("quality_sample_size", self.quality_sample_size as u64),
+            ("quality_sample_seconds", self.quality_sample_seconds),
+            ("cold_tier_after_days", self.cold_tier_after_days),
+            ("cold_tier_sweep_seconds", self.cold_tier_sweep_seconds),
+        ];
+        match positive.iter().find(|(_, value)| *value == 0) {
+            Some((name, _)) => Err(anyhow!("Invalid configuration: {} must be positive", name)),
+            None => Ok(()),
+        }
+    }
+}
+
+// `true` or `1`, as flags have always been read from the environment
+fn flag<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Flag {
+        Bool(bool),
+        Number(u64),
+        Text(String),
+    }
+    Ok(match Flag::deserialize(deserializer)? {
+        Flag::Bool(value) => value,
+        Flag::Number(value) => value == 1,
+        Flag::Text(value) => value == "1" || value.eq_ignore_ascii_case("true"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_is_overridden_by_later_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("redactor.toml");
+        std::fs::write(&path, "port = 8080\npresidio_url = \"https://presidio.internal\"\nstorage_dir = \"/data\"").unwrap();
+        let path = path.to_str();
+
+        // `ALLOW_LEGACY_ZERO_NONCE=1` reaches the flag as a number
+        let figment = AppConfig::figment(path).merge(("port", 9090)).merge(("allow_legacy_zero_nonce", 1));
+        let config = AppConfig::extract(figment).unwrap();
+        assert_eq!(config.socket_addr().to_string(), "0.0.0.0:9090");
+        assert_eq!((config.presidio_url.as_str(), config.storage_dir.as_deref()), ("https://presidio.internal", Some("/data")));
+        assert!(config.allow_legacy_zero_nonce);
+        assert_eq!(config.session_ttl_seconds, 3600);
+
+        assert_eq!(config.storage_backend(), "disk");
+        assert!(AppConfig::extract(AppConfig::figment(path).merge(("session_ttl_seconds", 0))).is_err());
+        assert!(AppConfig::extract(AppConfig::figment(path).merge(("file_expiry_sweep_seconds", 0))).is_err());
+        let retention = AppConfig::extract(AppConfig::figment(path).merge(("original_retention_seconds", 86400))).unwrap();
+        assert_eq!((retention.original_retention_seconds, retention.stream_upload_max_bytes), (Some(86400), 1024 * 1024 * 1024));
+        assert!(AppConfig::extract(AppConfig::figment(path).merge(("storage_backend", "s3"))).is_err());
+        assert!(AppConfig::extract(AppConfig::figment(path).merge(("read_only", "true"))).unwrap().read_only);
+        assert!(AppConfig::extract(AppConfig::figment(path).merge(("read_only", "true")).merge(("storage_backend", "memory"))).is_err());
+        let limited = AppConfig::extract(AppConfig::figment(path).merge(("storage_max_files", 100)).merge(("storage_eviction", "lru"))).unwrap();
+        assert_eq!(limited.storage_limits().unwrap().eviction, EvictionPolicy::Lru);
+        assert!(AppConfig::extract(AppConfig::figment(path).merge(("storage_eviction", "random"))).is_err());
+        assert!(AppConfig::extract(AppConfig::figment(path).merge(("storage_max_bytes", 10)).merge(("storage_max_file_bytes", 20))).is_err());
+        let multilingual = AppConfig::extract(AppConfig::figment(path).merge(("supported_languages", "en,es")).merge(("language_detection", "true"))).unwrap();
+        assert_eq!(multilingual.languages().unwrap().supported(), ["en", "es"]);
+        assert!(AppConfig::extract(AppConfig::figment(path).merge(("supported_languages", "english"))).is_err());
+        assert!(AppConfig::extract(AppConfig::figment(Some("missing.toml"))).is_err());
+        assert!(AppConfig::extract(AppConfig::figment(path).merge(("presidio_contract_check", "fail"))).is_err());
+        let defaults = AppConfig::extract(AppConfig::figment(path).merge(("default_entities", "EMAIL_ADDRESS, PHONE_NUMBER"))).unwrap();
+        assert_eq!(defaults.runtime_settings().unwrap().default_entities.unwrap(), ["EMAIL_ADDRESS", "PHONE_NUMBER"]);
+        assert!(AppConfig::extract(AppConfig::figment(path).merge(("default_strategy", "pseudonymize"))).is_err());
+
+        assert!(AppConfig::extract(AppConfig::figment(path).merge(("alert_channels_warning", "slack,sms"))).is_err());
+        assert!(AppConfig::extract(AppConfig::figment(path).merge(("alert_smtp_url", "smtps://mail.example.com"))).is_err());
+
+        // TLS needs both halves of the key pair, and mTLS needs TLS
+        assert!(config.server_tls().unwrap().is_none());
+        assert!(AppConfig::extract(AppConfig::figment(path).merge(("tls_cert_path", "/tls/cert.pem"))).is_err());
+        assert!(AppConfig::extract(AppConfig::figment(path).merge(("tls_client_ca_path", "/tls/ca.pem"))).is_err());
+        let figment = AppConfig::figment(path).merge(("tls_cert_path", "/missing/cert.pem")).merge(("tls_key_path", "/missing/key.pem"));
+        assert!(AppConfig::extract(figment).unwrap().server_tls().is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::operations::{ErrorKind, OperationError, UploadRequest};
+
+// The v1 envelope: no nonce, or the all-zero one
+pub const ZERO_NONCE: &str = "zero-nonce";
+
+// Upload modes that can be retired. Uploads under a `POST /handshake` session are not
+// listed: their key exchange is settled by the handshake.
+const MODES: [&str; 3] = [ZERO_NONCE, "rsa-oaep-sha256", "psk-hkdf-sha256"];
+
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Deprecation {
+    pub mode: String,
+    // `YYYY-MM-DD`; uploads in this mode are rejected from that day on (UTC)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sunset: Option<String>,
+    #[serde(skip)]
+    sunset_at: Option<u64>,
+}
+
+impl Deprecation {
+    // Whether the sunset date has passed
+    pub fn is_retired(&self) -> bool {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        self.sunset_at.is_some_and(|sunset_at| now >= sunset_at)
+    }
+}
+
+// Modes clients should move off, parsed from `mode[=YYYY-MM-DD],...`. A mode without a
+// date is deprecated but still accepted.
+#[derive(Clone, Debug)]
+pub struct ProtocolDeprecations {
+    deprecations: Vec<Deprecation>,
+}
+
+impl Default for ProtocolDeprecations {
+    fn default() -> Self {
+        Self {
+            deprecations: vec![Deprecation { mode: ZERO_NONCE.to_string(), sunset: None, sunset_at: None }],
+        }
+    }
+}
+
+impl ProtocolDeprecations {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let deprecations = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (mode, sunset) = match entry.split_once('=') {
+                    Some((mode, sunset)) => (mode.trim(), Some(sunset.trim())),
+                    None => (entry, None),
+                };
+                if !MODES.contains(&mode) {
+                    return Err(anyhow!("Unknown protocol mode {}; expected one of {}", mode, MODES.join(", ")));
+                }
+                let sunset_at = sunset.map(parse_date).transpose()?;
+                Ok(Deprecation { mode: mode.to_string(), sunset: sunset.map(str::to_string), sunset_at })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { deprecations })
+    }
+
+    pub fn deprecations(&self) -> &[Deprecation] {
+        &self.deprecations
+    }
+
+    // The deprecated modes `request` uses
+    pub fn used_by(&self, request: &UploadRequest) -> Vec<&Deprecation> {
+        let modes = upload_modes(request);
+        self.deprecations.iter().filter(|deprecation| modes.contains(&deprecation.mode.as_str())).collect()
+    }
+
+    // Reject uploads in a mode past its sunset; the rest go through
+    pub fn check(&self, request: &UploadRequest) -> Result<(), OperationError> {
+        match self.used_by(request).into_iter().find(|deprecation| deprecation.is_retired()) {
+            Some(retired) => Err(OperationError::new(
+                ErrorKind::UpgradeRequired,
+                format!(
+                    "The {} mode was retired on {}; see /capabilities for the supported ones",
+                    retired.mode,
+                    retired.sunset.as_deref().unwrap_or_default()
+                ),
+            )
+            .with_code("upgrade_required")
+            .with_link("/capabilities")),
+            None => Ok(()),
+        }
+    }
+}
+
+fn upload_modes(request: &UploadRequest) -> Vec<&'static str> {
+    let zero_nonce = match &request.nonce {
+        None => true,
+        Some(nonce) => BASE64.decode(nonce).is_ok_and(|nonce| nonce == [0u8; 12]),
+    };
+    let key_exchange = match (&request.encrypted_session_key, &request.psk_id) {
+        (Some(_), _) => Some("rsa-oaep-sha256"),
+        (None, Some(_)) => Some("psk-hkdf-sha256"),
+        (None, None) => None,
+    };
+    zero_nonce.then_some(ZERO_NONCE).into_iter().chain(key_exchange).collect()
+}
+
+// Unix time of midnight UTC on a `YYYY-MM-DD` date
+fn parse_date(date: &str) -> Result<u64> {
+    let invalid = || anyhow!("Invalid sunset date {}; expected YYYY-MM-DD", date);
+    let parts: Vec<&str> = date.split('-').collect();
+    let [year, month, day] = parts.as_slice() else {
+        return Err(invalid());
+    };
+    let (year, month, day): (i64, i64, i64) = (
+        year.parse().map_err(|_| invalid())?,
+        month.parse().map_err(|_| invalid())?,
+        day.parse().map_err(|_| invalid())?,
+    );
+    if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+    // Days since the epoch in the proleptic Gregorian calendar, counting from March
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    Ok(days as u64 * 86400)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_modes_are_rejected_from_their_sunset() {
+        assert_eq!(parse_date("1970-01-01").unwrap(), 0);
+        assert_eq!(parse_date("2026-12-31").unwrap(), 1798675200);
+        assert!(parse_date("2026-13-01").is_err());
+
+        let deprecations = ProtocolDeprecations::parse("zero-nonce=2020-01-01, psk-hkdf-sha256").unwrap();
+        let legacy = UploadRequest { encrypted_session_key: Some("key".to_string()), ..UploadRequest::default() };
+        let error = deprecations.check(&legacy).unwrap_err();
+        assert_eq!((error.kind.status(), error.code, error.link), (426, Some("upgrade_required"), Some("/capabilities")));
+
+        // Deprecated without a date: accepted, and still reported
+        let psk = UploadRequest { psk_id: Some("partner".to_string()), nonce: Some(BASE64.encode([1u8; 12])), ..UploadRequest::default() };
+        assert!(deprecations.check(&psk).is_ok());
+        assert_eq!(deprecations.used_by(&psk)[0].mode, "psk-hkdf-sha256");
+
+        assert!(ProtocolDeprecations::parse("aes-cbc").is_err());
+    }
+}
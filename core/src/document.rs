@@ -0,0 +1,413 @@
+use serde::{Deserialize, Serialize};
+
+pub const DOCX_CONTENT_TYPE: &str = "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
+
+// Binary document formats whose text is extracted for redaction. Uploads are detected
+// by their magic bytes, or by the type the client declares.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentFormat {
+    Pdf,
+    Docx,
+}
+
+impl DocumentFormat {
+    // A declared `document_type`: the short name or the MIME type
+    pub fn from_declared(declared: &str) -> Option<Self> {
+        match declared.trim().to_ascii_lowercase().as_str() {
+            "pdf" | "application/pdf" => Some(DocumentFormat::Pdf),
+            "docx" | DOCX_CONTENT_TYPE => Some(DocumentFormat::Docx),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            DocumentFormat::Pdf => "application/pdf",
+            DocumentFormat::Docx => DOCX_CONTENT_TYPE,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            DocumentFormat::Pdf => "pdf",
+            DocumentFormat::Docx => "docx",
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+pub use codec::{detect, extract_text, rebuild, scrub_metadata};
+
+#[cfg(feature = "server")]
+mod codec {
+    use anyhow::{anyhow, Result};
+    use pdf_extract::Object;
+    use quick_xml::{events::{BytesStart, Event}, Reader};
+    use std::io::{Cursor, Read, Write};
+    use zip::{write::SimpleFileOptions, ZipArchive, ZipWriter};
+
+    use super::DocumentFormat;
+    use crate::report::ScrubbedMetadata;
+
+    // Largest DOCX part inflated, so a small archive cannot expand without bound
+    const MAX_PART_BYTES: u64 = 64 * 1024 * 1024;
+    // Extended properties that name people or places; the rest are counts like `Pages`
+    const APP_PROPERTIES: [&[u8]; 4] = [b"Company", b"Manager", b"Template", b"HyperlinkBase"];
+    // Rebuilt PDFs: US Letter, 10 pt Helvetica
+    const PAGE_WIDTH: u32 = 612;
+    const PAGE_HEIGHT: u32 = 792;
+    const MARGIN: u32 = 50;
+    const LEADING: u32 = 12;
+    const LINES_PER_PAGE: usize = ((PAGE_HEIGHT - 2 * MARGIN) / LEADING) as usize;
+    const CHARS_PER_LINE: usize = 95;
+
+    // The upload's document format: the declared one, or sniffed from the plaintext.
+    // None for anything else, which is handled as text.
+    pub fn detect(bytes: &[u8], declared: Option<&str>) -> Result<Option<DocumentFormat>> {
+        if let Some(declared) = declared {
+            return DocumentFormat::from_declared(declared)
+                .map(Some)
+                .ok_or_else(|| anyhow!("Unsupported document type: {}", declared));
+        }
+        if bytes.starts_with(b"%PDF-") {
+            return Ok(Some(DocumentFormat::Pdf));
+        }
+        // DOCX files are zip archives with the body at `word/document.xml`
+        let is_docx = bytes.starts_with(b"PK\x03\x04")
+            && ZipArchive::new(Cursor::new(bytes)).is_ok_and(|mut archive| archive.by_name("word/document.xml").is_ok());
+        Ok(is_docx.then_some(DocumentFormat::Docx))
+    }
+
+    pub fn extract_text(format: DocumentFormat, bytes: &[u8]) -> Result<String> {
+        match format {
+            // The parser panics on some malformed files instead of returning an error
+            DocumentFormat::Pdf => std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem(bytes))
+                .map_err(|_| anyhow!("Malformed PDF"))?
+                .map_err(|e| anyhow!("Invalid PDF: {}", e)),
+            DocumentFormat::Docx => docx_text(bytes),
+        }
+    }
+
+    // The embedded metadata the extracted text leaves behind: document properties, XMP,
+    // comments and revision history. The output never carries any of it, as it is
+    // rebuilt from the text alone.
+    pub fn scrub_metadata(format: DocumentFormat, bytes: &[u8]) -> Result<ScrubbedMetadata> {
+        match format {
+            DocumentFormat::Pdf => pdf_metadata(bytes),
+            DocumentFormat::Docx => docx_metadata(bytes),
+        }
+    }
+
+    // A simple document in `format` holding `text`, one line per paragraph
+    pub fn rebuild(format: DocumentFormat, text: &str) -> Result<Vec<u8>> {
+        match format {
+            DocumentFormat::Pdf => Ok(pdf(text)),
+            DocumentFormat::Docx => docx(text),
+        }
+    }
+
+    // Text runs of the body, with a line per paragraph. Deleted text of tracked changes
+    // is in `w:delText` runs, so it is left out.
+    fn docx_text(bytes: &[u8]) -> Result<String> {
+        let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(|e| anyhow!("Invalid DOCX: {}", e))?;
+        let xml = docx_part(&mut archive, "word/document.xml")?.ok_or_else(|| anyhow!("Invalid DOCX: no word/document.xml"))?;
+
+        let mut reader = Reader::from_str(&xml);
+        let (mut text, mut in_run) = (String::new(), false);
+        loop {
+            match reader.read_event().map_err(|e| anyhow!("Invalid DOCX body: {}", e))? {
+                Event::Start(e) if e.local_name().as_ref() == b"t" => in_run = true,
+                Event::End(e) => match e.local_name().as_ref() {
+                    b"t" => in_run = false,
+                    b"p" => text.push('\n'),
+                    _ => {}
+                },
+                Event::Empty(e) => match e.local_name().as_ref() {
+                    b"tab" => text.push('\t'),
+                    b"br" | b"cr" => text.push('\n'),
+                    _ => {}
+                },
+                Event::Text(e) if in_run => text.push_str(&e.unescape().map_err(|e| anyhow!("Invalid DOCX body: {}", e))?),
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        Ok(text)
+    }
+
+    fn docx_metadata(bytes: &[u8]) -> Result<ScrubbedMetadata> {
+        let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(|e| anyhow!("Invalid DOCX: {}", e))?;
+        let mut scrubbed = ScrubbedMetadata::default();
+
+        // Core properties are all personal or descriptive: author, title, dates, ...
+        let mut properties = Vec::new();
+        if let Some(xml) = docx_part(&mut archive, "docProps/core.xml")? {
+            properties.extend(filled_elements(&xml, |_| true)?);
+        }
+        if let Some(xml) = docx_part(&mut archive, "docProps/app.xml")? {
+            properties.extend(filled_elements(&xml, |name| APP_PROPERTIES.contains(&name))?);
+        }
+        if let Some(xml) = docx_part(&mut archive, "docProps/custom.xml")? {
+            for_each_start(&xml, |name, element| {
+                if name == b"property" {
+                    if let Some(property) = element.try_get_attribute("name")? {
+                        properties.push(format!("custom:{}", property.unescape_value()?));
+                    }
+                }
+                Ok(())
+            })?;
+        }
+        scrubbed.properties = properties;
+
+        if let Some(xml) = docx_part(&mut archive, "word/comments.xml")? {
+            for_each_start(&xml, |name, _| {
+                scrubbed.comments += usize::from(name == b"comment");
+                Ok(())
+            })?;
+        }
+        if let Some(xml) = docx_part(&mut archive, "word/document.xml")? {
+            for_each_start(&xml, |name, _| {
+                match name {
+                    b"ins" => scrubbed.tracked_insertions += 1,
+                    b"del" => scrubbed.tracked_deletions += 1,
+                    _ => {}
+                }
+                Ok(())
+            })?;
+        }
+        Ok(scrubbed)
+    }
+
+    // A part of a DOCX archive, None when it has none
+    fn docx_part(archive: &mut ZipArchive<Cursor<&[u8]>>, name: &str) -> Result<Option<String>> {
+        let entry = match archive.by_name(name) {
+            Ok(entry) => entry,
+            Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+            Err(e) => return Err(anyhow!("Invalid DOCX part {}: {}", name, e)),
+        };
+        let mut xml = String::new();
+        entry.take(MAX_PART_BYTES + 1).read_to_string(&mut xml)?;
+        if xml.len() as u64 > MAX_PART_BYTES {
+            return Err(anyhow!("DOCX part {} is larger than {} bytes", name, MAX_PART_BYTES));
+        }
+        Ok(Some(xml))
+    }
+
+    // Local names of the elements, among those `wanted`, that hold some text
+    fn filled_elements(xml: &str, wanted: impl Fn(&[u8]) -> bool) -> Result<Vec<String>> {
+        let mut reader = Reader::from_str(xml);
+        let (mut names, mut current) = (Vec::new(), None);
+        loop {
+            match reader.read_event().map_err(|e| anyhow!("Invalid DOCX properties: {}", e))? {
+                Event::Start(e) => current = Some(String::from_utf8_lossy(e.local_name().as_ref()).into_owned()),
+                Event::Text(e) if !e.iter().all(u8::is_ascii_whitespace) => {
+                    if let Some(name) = current.take().filter(|name| wanted(name.as_bytes()) && !names.contains(name)) {
+                        names.push(name);
+                    }
+                }
+                Event::End(_) => current = None,
+                Event::Eof => return Ok(names),
+                _ => {}
+            }
+        }
+    }
+
+    // Call `visit` with the local name of every opening or empty element
+    fn for_each_start(xml: &str, mut visit: impl FnMut(&[u8], &BytesStart<'_>) -> Result<()>) -> Result<()> {
+        let mut reader = Reader::from_str(xml);
+        loop {
+            match reader.read_event().map_err(|e| anyhow!("Invalid DOCX part: {}", e))? {
+                Event::Start(e) | Event::Empty(e) => visit(e.local_name().as_ref(), &e)?,
+                Event::Eof => return Ok(()),
+                _ => {}
+            }
+        }
+    }
+
+    // Info dictionary entries, the XMP packet and annotations with comment text
+    fn pdf_metadata(bytes: &[u8]) -> Result<ScrubbedMetadata> {
+        let document = std::panic::catch_unwind(|| pdf_extract::Document::load_mem(bytes))
+            .map_err(|_| anyhow!("Malformed PDF"))?
+            .map_err(|e| anyhow!("Invalid PDF: {}", e))?;
+        let mut scrubbed = ScrubbedMetadata::default();
+
+        let info = document.trailer.get(b"Info").and_then(|info| document.dereference(info)).and_then(|(_, info)| info.as_dict());
+        if let Ok(info) = info {
+            scrubbed.properties = info.iter()
+                .filter(|(_, value)| !matches!(value, Object::String(value, _) if value.is_empty()))
+                .map(|(key, _)| String::from_utf8_lossy(key).into_owned())
+                .collect();
+        }
+        scrubbed.xmp = document.catalog().is_ok_and(|catalog| catalog.has(b"Metadata"));
+        scrubbed.comments = document.get_pages()
+            .into_values()
+            .filter_map(|page| document.get_page_annotations(page).ok())
+            .map(|annotations| annotations.iter().filter(|annotation| annotation.has(b"Contents")).count())
+            .sum();
+        Ok(scrubbed)
+    }
+
+    fn docx(text: &str) -> Result<Vec<u8>> {
+        let mut body = String::new();
+        for line in text.lines() {
+            body.push_str("<w:p><w:r>");
+            for (i, part) in line.split('\t').enumerate() {
+                if i > 0 {
+                    body.push_str("<w:tab/>");
+                }
+                body.push_str(&format!("<w:t xml:space=\"preserve\">{}</w:t>", quick_xml::escape::escape(part)));
+            }
+            body.push_str("</w:r></w:p>");
+        }
+
+        let files = [
+            ("[Content_Types].xml", concat!(
+                r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+                r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">"#,
+                r#"<Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>"#,
+                r#"<Default Extension="xml" ContentType="application/xml"/>"#,
+                r#"<Override PartName="/word/document.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml"/>"#,
+                r#"</Types>"#,
+            ).to_string()),
+            ("_rels/.rels", concat!(
+                r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+                r#"<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="word/document.xml"/>"#,
+                r#"</Relationships>"#,
+            ).to_string()),
+            ("word/document.xml", format!(
+                r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body>{}</w:body></w:document>"#,
+                body
+            )),
+        ];
+
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            zip.start_file(name, SimpleFileOptions::default())?;
+            zip.write_all(content.as_bytes())?;
+        }
+        Ok(zip.finish()?.into_inner())
+    }
+
+    // Helvetica in WinAnsiEncoding, so characters outside Latin-1 come out as `?`
+    fn pdf(text: &str) -> Vec<u8> {
+        let mut lines = Vec::new();
+        for line in text.replace('\t', "    ").lines() {
+            let chars: Vec<char> = line.chars().collect();
+            if chars.is_empty() {
+                lines.push(String::new());
+            }
+            lines.extend(chars.chunks(CHARS_PER_LINE).map(|chunk| chunk.iter().collect::<String>()));
+        }
+        if lines.is_empty() {
+            lines.push(String::new());
+        }
+        let pages: Vec<&[String]> = lines.chunks(LINES_PER_PAGE).collect();
+
+        // Objects 1-3 are the catalog, page tree and font; each page then takes two
+        let mut objects = vec![
+            b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                (0..pages.len()).map(|i| format!("{} 0 R", 4 + 2 * i)).collect::<Vec<_>>().join(" "),
+                pages.len()
+            ).into_bytes(),
+            b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_vec(),
+        ];
+        for (i, page) in pages.iter().enumerate() {
+            let mut stream = format!("BT /F1 10 Tf {} TL {} {} Td\n", LEADING, MARGIN, PAGE_HEIGHT - MARGIN).into_bytes();
+            for line in page.iter() {
+                stream.push(b'(');
+                for c in line.chars() {
+                    match c {
+                        '(' | ')' | '\\' => stream.extend([b'\\', c as u8]),
+                        ' '..='~' | '\u{a0}'..='\u{ff}' => stream.push(c as u32 as u8),
+                        _ => stream.push(b'?'),
+                    }
+                }
+                stream.extend(b") Tj T*\n");
+            }
+            stream.extend(b"ET");
+
+            objects.push(format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+                PAGE_WIDTH, PAGE_HEIGHT, 5 + 2 * i
+            ).into_bytes());
+            let mut contents = format!("<< /Length {} >>\nstream\n", stream.len()).into_bytes();
+            contents.extend(stream);
+            contents.extend(b"\nendstream");
+            objects.push(contents);
+        }
+
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend(format!("{} 0 obj\n", i + 1).into_bytes());
+            pdf.extend(object);
+            pdf.extend(b"\nendobj\n");
+        }
+        let xref = pdf.len();
+        pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).into_bytes());
+        for offset in offsets {
+            pdf.extend(format!("{:010} 00000 n \n", offset).into_bytes());
+        }
+        pdf.extend(format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref).into_bytes());
+        pdf
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_documents_are_detected_and_round_trip() {
+        let text = "Call Jane Doe\tat 555-0100\nShe & (her) team";
+        let docx = rebuild(DocumentFormat::Docx, text).unwrap();
+        assert_eq!(detect(&docx, None).unwrap(), Some(DocumentFormat::Docx));
+        assert_eq!(extract_text(DocumentFormat::Docx, &docx).unwrap(), format!("{}\n", text));
+
+        let pdf = rebuild(DocumentFormat::Pdf, text).unwrap();
+        assert_eq!(detect(&pdf, None).unwrap(), Some(DocumentFormat::Pdf));
+        let extracted = extract_text(DocumentFormat::Pdf, &pdf).unwrap();
+        assert!(extracted.contains("Call Jane Doe") && extracted.contains("She & (her) team"), "{:?}", extracted);
+
+        assert_eq!(detect(b"plain text", None).unwrap(), None);
+        assert_eq!(detect(b"plain text", Some("application/pdf")).unwrap(), Some(DocumentFormat::Pdf));
+        assert!(detect(b"plain text", Some("image/png")).is_err());
+        assert!(extract_text(DocumentFormat::Pdf, b"%PDF-1.4 truncated").is_err());
+    }
+
+    #[test]
+    fn test_metadata_is_reported_without_values() {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let parts = [
+            ("docProps/core.xml", r#"<cp:coreProperties xmlns:cp="c" xmlns:dc="d"><dc:creator>Jane Doe</dc:creator><dc:title> </dc:title><cp:lastModifiedBy>John Roe</cp:lastModifiedBy></cp:coreProperties>"#),
+            ("docProps/app.xml", r#"<Properties><Pages>2</Pages><Company>Acme</Company></Properties>"#),
+            ("docProps/custom.xml", r#"<Properties><property name="Client"><vt:lpwstr xmlns:vt="v">Globex</vt:lpwstr></property></Properties>"#),
+            ("word/comments.xml", r#"<w:comments xmlns:w="w"><w:comment w:author="Jane Doe"/><w:comment w:author="John Roe"/></w:comments>"#),
+            ("word/document.xml", r#"<w:document xmlns:w="w"><w:body><w:p><w:r><w:t>Kept </w:t></w:r><w:del w:author="John Roe"><w:r><w:delText>secret </w:delText></w:r></w:del><w:ins w:author="Jane Doe"><w:r><w:t>added</w:t></w:r></w:ins></w:p></w:body></w:document>"#),
+        ];
+        for (name, content) in parts {
+            zip.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+            std::io::Write::write_all(&mut zip, content.as_bytes()).unwrap();
+        }
+        let docx = zip.finish().unwrap().into_inner();
+
+        assert_eq!(extract_text(DocumentFormat::Docx, &docx).unwrap(), "Kept added\n");
+        let scrubbed = scrub_metadata(DocumentFormat::Docx, &docx).unwrap();
+        assert_eq!(scrubbed.properties, ["creator", "lastModifiedBy", "Company", "custom:Client"]);
+        assert_eq!((scrubbed.comments, scrubbed.tracked_insertions, scrubbed.tracked_deletions), (2, 1, 1));
+        assert!(!serde_json::to_string(&scrubbed).unwrap().contains("Jane"));
+
+        // Rebuilt documents carry none of it
+        let pdf = String::from_utf8(rebuild(DocumentFormat::Pdf, "text").unwrap()).unwrap();
+        assert!(scrub_metadata(DocumentFormat::Pdf, pdf.as_bytes()).unwrap().is_empty());
+        let pdf = pdf.replace("/Root 1 0 R >>", "/Root 1 0 R /Info << /Author (Jane Doe) /Subject () >> >>");
+        assert_eq!(scrub_metadata(DocumentFormat::Pdf, pdf.as_bytes()).unwrap().properties, ["Author"]);
+        assert!(scrub_metadata(DocumentFormat::Docx, &rebuild(DocumentFormat::Docx, "text").unwrap()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,80 @@
+use anyhow::{anyhow, Result};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rand::{rngs::OsRng, RngCore};
+use rsa::{pkcs8::DecodePublicKey, Oaep, RsaPublicKey};
+use serde::Serialize;
+use sha2::Sha256;
+
+use crate::crypto;
+
+// Client side of the upload envelope. Field names match the upload request, so the
+// envelope can be merged straight into its JSON body.
+#[derive(Clone, Debug, Serialize)]
+pub struct SealedEnvelope {
+    pub encrypted_data: String,
+    pub encrypted_session_key: String,
+    pub nonce: String,
+    pub ciphertext_sha256: String,
+    pub plaintext_sha256: String,
+}
+
+// Encrypt under a fresh 32-byte session key and random nonce with ChaCha20-Poly1305,
+// binding the plaintext digest as AAD, and wrap the session key to the service's
+// public key with RSA-OAEP-SHA256
+pub fn seal(public_key_pem: &str, plaintext: &[u8]) -> Result<SealedEnvelope> {
+    let mut session_key = [0u8; 32];
+    OsRng.fill_bytes(&mut session_key);
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut nonce);
+
+    let plaintext_digest = crypto::sha256(plaintext);
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&session_key))
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: &plaintext_digest })
+        .map_err(|e| anyhow!("Encryption failed: {}", e))?;
+
+    Ok(SealedEnvelope {
+        encrypted_data: BASE64.encode(&ciphertext),
+        encrypted_session_key: wrap_session_key(public_key_pem, &session_key)?,
+        nonce: BASE64.encode(nonce),
+        ciphertext_sha256: hex(&crypto::sha256(&ciphertext)),
+        plaintext_sha256: hex(&plaintext_digest),
+    })
+}
+
+// RSA-OAEP-SHA256 wrap of a session key for `encrypted_session_key`, base64 encoded
+pub fn wrap_session_key(public_key_pem: &str, session_key: &[u8]) -> Result<String> {
+    let public_key = RsaPublicKey::from_public_key_pem(public_key_pem)
+        .map_err(|e| anyhow!("Invalid public key: {}", e))?;
+    let wrapped_key = public_key.encrypt(&mut OsRng, Oaep::new::<Sha256>(), session_key)
+        .map_err(|e| anyhow!("Failed to wrap session key: {}", e))?;
+    Ok(BASE64.encode(wrapped_key))
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CryptoService;
+
+    #[test]
+    fn test_sealed_envelope_opens_on_the_service() {
+        let crypto = CryptoService::new().unwrap();
+        let envelope = seal(&crypto.get_public_key().unwrap(), "Patient: Jane Roe".as_bytes()).unwrap();
+
+        let session_key = crypto.decrypt_session_key(&envelope.encrypted_session_key).unwrap();
+        let aad = crypto::parse_sha256_hex(&envelope.plaintext_sha256).unwrap();
+        let plaintext = crypto.decrypt_file_with_session_key(&envelope.encrypted_data, &session_key, Some(&envelope.nonce), &aad).unwrap();
+        assert_eq!(plaintext, "Patient: Jane Roe");
+
+        let ciphertext = BASE64.decode(&envelope.encrypted_data).unwrap();
+        assert_eq!(envelope.ciphertext_sha256, hex(&crypto::sha256(&ciphertext)));
+        assert!(seal("not a key", b"x").is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::crypto::CryptoService;
+use crate::storage::FileMetadata;
+
+pub const RECEIPT_ALGORITHM: &str = "RSASSA-PKCS1-v1_5-SHA256";
+
+// What was destroyed. Only digests are kept, so a receipt never reveals file content.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ErasureStatement {
+    pub file_id: String,
+    pub file_name_sha256: String,
+    pub content_sha256: String,
+    pub size: usize,
+    pub reason: String,
+    pub deleted_at: u64,
+    pub backends_purged: Vec<String>,
+    // Delivery targets the deletion is being propagated to; `GET /audit/receipts`
+    // reports how far each has got
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub propagated_to: Vec<String>,
+}
+
+// A statement plus the service's signature over its exact JSON encoding (`payload`)
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ErasureReceipt {
+    pub receipt: ErasureStatement,
+    pub payload: String,
+    pub algorithm: String,
+    pub signature: String,
+    pub public_key: String,
+}
+
+impl ErasureReceipt {
+    pub fn issue(
+        crypto: &CryptoService,
+        file_id: &str,
+        metadata: &FileMetadata,
+        reason: &str,
+        backends_purged: &[&str],
+        propagated_to: &[&str],
+    ) -> anyhow::Result<Self> {
+        let receipt = ErasureStatement {
+            file_id: file_id.to_string(),
+            file_name_sha256: hex_digest(metadata.file_name.as_bytes()),
+            content_sha256: hex_digest(metadata.content.as_bytes()),
+            size: metadata.content.len(),
+            reason: reason.to_string(),
+            deleted_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            backends_purged: backends_purged.iter().map(|backend| backend.to_string()).collect(),
+            propagated_to: propagated_to.iter().map(|target| target.to_string()).collect(),
+        };
+
+        let payload = serde_json::to_vec(&receipt)?;
+        Ok(Self {
+            receipt,
+            signature: crypto.sign(&payload),
+            payload: BASE64.encode(&payload),
+            algorithm: RECEIPT_ALGORITHM.to_string(),
+            public_key: crypto.get_public_key()?,
+        })
+    }
+}
+
+fn hex_digest(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{FileStorage, Storage};
+    use rsa::{pkcs1v15::{Signature, VerifyingKey}, pkcs8::DecodePublicKey, signature::Verifier, RsaPublicKey};
+
+    #[test]
+    fn test_receipt_signature_verifies() {
+        let crypto = CryptoService::new().unwrap();
+        let mut storage = FileStorage::new();
+        let metadata = storage.store_file("f1", "report.txt", "<PERSON> called").clone();
+
+        let receipt = ErasureReceipt::issue(&crypto, "f1", &metadata, "deleted", &["memory"], &[]).unwrap();
+        assert_eq!(receipt.receipt.content_sha256, hex_digest(b"<PERSON> called"));
+        assert_eq!(receipt.receipt.backends_purged, vec!["memory"]);
+
+        let public_key = RsaPublicKey::from_public_key_pem(&receipt.public_key).unwrap();
+        let verifying_key = VerifyingKey::<Sha256>::new(public_key);
+        let payload = BASE64.decode(&receipt.payload).unwrap();
+        let signature = Signature::try_from(BASE64.decode(&receipt.signature).unwrap().as_slice()).unwrap();
+        assert!(verifying_key.verify(&payload, &signature).is_ok());
+        assert!(verifying_key.verify(b"tampered", &signature).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rand::{rngs::OsRng, RngCore};
+use rsa::{
+    pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding},
+    Oaep, RsaPrivateKey, RsaPublicKey,
+};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sharks::{Share, Sharks};
+use std::collections::{BTreeMap, HashMap};
+use tracing::info;
+
+// Operators who each receive one share of the escrow key, loaded from a JSON file shaped as
+// { "threshold": 2, "operators": { "<name>": "<PEM RSA public key>" } }
+pub struct EscrowConfig {
+    threshold: u8,
+    operators: Vec<(String, RsaPublicKey)>,
+}
+
+#[derive(Deserialize)]
+struct EscrowConfigFile {
+    threshold: u8,
+    operators: HashMap<String, String>,
+}
+
+// Everything needed to rebuild the service key from `threshold` operator shares. The key is
+// sealed under a random escrow key, which is split with Shamir's scheme over GF(256); each
+// share is then wrapped with RSA-OAEP-SHA256 to one operator's public key.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct EscrowBundle {
+    pub threshold: u8,
+    pub public_key: String,
+    pub encrypted_key: String,
+    pub nonce: String,
+    pub shares: BTreeMap<String, String>,
+}
+
+impl EscrowConfig {
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(path) = std::env::var("KEY_ESCROW_CONFIG_PATH") else {
+            return Ok(None);
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read key escrow config from {}: {}", path, e))?;
+        let file: EscrowConfigFile = serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("Invalid key escrow config: {}", e))?;
+
+        let operators = file.operators.into_iter()
+            .map(|(name, pem)| {
+                let key = RsaPublicKey::from_public_key_pem(&pem)
+                    .map_err(|e| anyhow!("Invalid public key for escrow operator {}: {}", name, e))?;
+                Ok((name, key))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if file.threshold < 2 || usize::from(file.threshold) > operators.len() {
+            return Err(anyhow!(
+                "Key escrow threshold must be between 2 and the number of operators ({})",
+                operators.len()
+            ));
+        }
+
+        info!("Key escrow enabled: {} of {} operators", file.threshold, operators.len());
+        Ok(Some(Self {
+            threshold: file.threshold,
+            operators,
+        }))
+    }
+
+    pub fn seal(&self, private_key: &RsaPrivateKey) -> Result<EscrowBundle> {
+        let mut escrow_key = [0u8; 32];
+        OsRng.fill_bytes(&mut escrow_key);
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce);
+
+        let der = private_key.to_pkcs8_der()
+            .map_err(|e| anyhow!("Failed to encode private key: {}", e))?;
+        let encrypted_key = ChaCha20Poly1305::new(Key::from_slice(&escrow_key))
+            .encrypt(Nonce::from_slice(&nonce), der.as_bytes())
+            .map_err(|e| anyhow!("Failed to seal private key: {}", e))?;
+
+        let dealer = Sharks(self.threshold).dealer_rng(&escrow_key, &mut OsRng);
+        let shares = self.operators.iter()
+            .zip(dealer)
+            .map(|((name, operator_key), share)| {
+                let wrapped = operator_key.encrypt(&mut OsRng, Oaep::new::<Sha256>(), &Vec::from(&share))
+                    .map_err(|e| anyhow!("Failed to wrap share for {}: {}", name, e))?;
+                Ok((name.clone(), BASE64.encode(wrapped)))
+            })
+            .collect::<Result<BTreeMap<_, _>>>()?;
+
+        let public_key = RsaPublicKey::from(private_key).to_public_key_pem(LineEnding::LF)
+            .map_err(|e| anyhow!("Failed to export public key: {}", e))?;
+
+        Ok(EscrowBundle {
+            threshold: self.threshold,
+            public_key,
+            encrypted_key: BASE64.encode(encrypted_key),
+            nonce: BASE64.encode(nonce),
+            shares,
+        })
+    }
+}
+
+// Rebuild the private key from operator shares that have already been unwrapped
+// with the operators' private keys
+pub fn recover(bundle: &EscrowBundle, shares: &[Vec<u8>]) -> Result<RsaPrivateKey> {
+    let shares = shares.iter()
+        .map(|share| Share::try_from(share.as_slice()).map_err(|e| anyhow!("Invalid escrow share: {}", e)))
+        .collect::<Result<Vec<_>>>()?;
+    let escrow_key = Sharks(bundle.threshold).recover(&shares)
+        .map_err(|e| anyhow!("Failed to combine escrow shares: {}", e))?;
+    if escrow_key.len() != 32 {
+        return Err(anyhow!("Escrow shares do not belong to this bundle"));
+    }
+
+    let encrypted_key = BASE64.decode(&bundle.encrypted_key)
+        .map_err(|e| anyhow!("Invalid base64 encrypted key: {}", e))?;
+    let nonce = BASE64.decode(&bundle.nonce)
+        .ok()
+        .filter(|nonce| nonce.len() == 12)
+        .ok_or_else(|| anyhow!("Invalid escrow nonce"))?;
+    let der = ChaCha20Poly1305::new(Key::from_slice(&escrow_key))
+        .decrypt(Nonce::from_slice(&nonce), encrypted_key.as_ref())
+        .map_err(|_| anyhow!("Escrow shares do not unlock this bundle"))?;
+
+    RsaPrivateKey::from_pkcs8_der(&der)
+        .map_err(|e| anyhow!("Invalid escrowed private key: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_recover_with_threshold_shares() {
+        let service_key = RsaPrivateKey::new(&mut OsRng, 1024).unwrap();
+        let operator_keys: Vec<RsaPrivateKey> = (0..3).map(|_| RsaPrivateKey::new(&mut OsRng, 1024).unwrap()).collect();
+        let config = EscrowConfig {
+            threshold: 2,
+            operators: ["alice", "bob", "carol"].iter()
+                .zip(&operator_keys)
+                .map(|(name, key)| (name.to_string(), RsaPublicKey::from(key)))
+                .collect(),
+        };
+
+        let bundle = config.seal(&service_key).unwrap();
+        assert_eq!(bundle.shares.len(), 3);
+
+        // Each operator unwraps their own share; any two are enough
+        let unwrap = |name: &str, key: &RsaPrivateKey| {
+            key.decrypt(Oaep::new::<Sha256>(), &BASE64.decode(&bundle.shares[name]).unwrap()).unwrap()
+        };
+        let shares = vec![unwrap("alice", &operator_keys[0]), unwrap("carol", &operator_keys[2])];
+        assert_eq!(recover(&bundle, &shares).unwrap(), service_key);
+
+        assert!(recover(&bundle, &shares[..1]).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+// One field to keep. `field` matches "Label: value" / "Label = value" lines; `pattern` is
+// a regex whose `value` named group (or first group, or whole match) is kept.
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct KeepRule {
+    pub name: String,
+    pub field: Option<String>,
+    pub pattern: Option<String>,
+}
+
+pub struct TemplateExtractor {
+    rules: Vec<(String, Regex)>,
+}
+
+impl TemplateExtractor {
+    pub fn new(rules: &[KeepRule]) -> Result<Self> {
+        if rules.is_empty() {
+            return Err(anyhow!("The extract strategy requires at least one keep rule"));
+        }
+
+        let rules = rules.iter()
+            .map(|rule| {
+                let pattern = match (&rule.field, &rule.pattern) {
+                    (Some(field), None) => {
+                        format!(r"(?mi)^[ \t]*{}[ \t]*[:=][ \t]*(?P<value>.*?)[ \t]*$", regex::escape(field))
+                    }
+                    (None, Some(pattern)) => pattern.clone(),
+                    _ => return Err(anyhow!("Keep rule {} must set exactly one of field or pattern", rule.name)),
+                };
+                let regex = Regex::new(&pattern)
+                    .map_err(|e| anyhow!("Invalid pattern for keep rule {}: {}", rule.name, e))?;
+                Ok((rule.name.clone(), regex))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { rules })
+    }
+
+    // Produce a minimal document of `name: value` lines in document order,
+    // dropping everything not selected by a keep rule
+    pub fn extract(&self, text: &str) -> String {
+        let mut kept: Vec<(usize, &str, &str)> = Vec::new();
+
+        for (name, regex) in &self.rules {
+            for captures in regex.captures_iter(text) {
+                let value = captures.name("value")
+                    .or_else(|| captures.get(1))
+                    .or_else(|| captures.get(0));
+                if let Some(value) = value.filter(|value| !value.as_str().is_empty()) {
+                    kept.push((value.start(), name, value.as_str()));
+                }
+            }
+        }
+
+        kept.sort_by_key(|(start, _, _)| *start);
+        kept.iter()
+            .map(|(_, name, value)| format!("{}: {}\n", name, value))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(name: &str, field: Option<&str>, pattern: Option<&str>) -> KeepRule {
+        KeepRule {
+            name: name.to_string(),
+            field: field.map(str::to_string),
+            pattern: pattern.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_extract_keeps_only_selected_fields() {
+        let text = "Customer: John Doe\nInvoice Number: INV-2024-001\nTotal = $1,250.00\nNotes: call 555-0101\n";
+        let extractor = TemplateExtractor::new(&[
+            rule("total", None, Some(r"Total = (\$[\d,.]+)")),
+            rule("invoice_number", Some("invoice number"), None),
+        ]).unwrap();
+
+        assert_eq!(extractor.extract(text), "invoice_number: INV-2024-001\ntotal: $1,250.00\n");
+    }
+
+    #[test]
+    fn test_invalid_rules_rejected() {
+        assert!(TemplateExtractor::new(&[]).is_err());
+        assert!(TemplateExtractor::new(&[rule("x", Some("a"), Some("b"))]).is_err());
+        assert!(TemplateExtractor::new(&[rule("x", None, Some("("))]).is_err());
+    }
+}
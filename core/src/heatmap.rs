@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::report::Detection;
+
+const DEFAULT_LINES_PER_BUCKET: usize = 50;
+// Buckets listed in `densest`
+const DENSEST_BUCKETS: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum HeatmapUnit {
+    // Pages separated by form feeds
+    Page,
+    // Runs of `lines_per_bucket` lines, for text without page breaks
+    Lines,
+}
+
+// Where in a file the redactions are, so reviewers can go straight to the densest parts
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Heatmap {
+    pub unit: HeatmapUnit,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lines_per_bucket: Option<usize>,
+    pub buckets: Vec<HeatmapBucket>,
+    // `index` of the buckets with the highest density, densest first
+    pub densest: Vec<usize>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct HeatmapBucket {
+    // From 1; the page number in page mode
+    pub index: usize,
+    // First and last line, from 1
+    pub start_line: usize,
+    pub end_line: usize,
+    // Half-open byte range into the plaintext
+    pub start: usize,
+    pub end: usize,
+    pub detections: usize,
+    pub entities: BTreeMap<String, usize>,
+    // Share of the bucket's bytes that were redacted, from 0 to 1
+    pub density: f64,
+}
+
+impl Heatmap {
+    // Bucket size for text without page breaks, from `HEATMAP_LINES_PER_BUCKET`
+    pub fn lines_per_bucket_from_env() -> usize {
+        std::env::var("HEATMAP_LINES_PER_BUCKET")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(DEFAULT_LINES_PER_BUCKET)
+    }
+
+    // Bucket `detections` (byte offsets into `text`) by page when the text has form
+    // feeds, and by `lines_per_bucket` lines otherwise
+    pub fn build(text: &str, detections: &[Detection], lines_per_bucket: usize) -> Self {
+        let paged = text.contains('\u{c}');
+        let lines_per_bucket = lines_per_bucket.max(1);
+
+        let mut buckets = Vec::new();
+        let mut bucket = |start: usize, end: usize, start_line: usize, end_line: usize| {
+            buckets.push(HeatmapBucket {
+                index: buckets.len() + 1,
+                start_line,
+                end_line,
+                start,
+                end,
+                detections: 0,
+                entities: BTreeMap::new(),
+                density: 0.0,
+            });
+        };
+        let (mut start, mut start_line, mut line, mut lines) = (0, 1, 1, 0);
+        for (i, b) in text.bytes().enumerate() {
+            let ends_bucket = match b {
+                b'\n' => {
+                    line += 1;
+                    lines += 1;
+                    !paged && lines == lines_per_bucket
+                }
+                b'\x0c' => paged,
+                _ => false,
+            };
+            if ends_bucket {
+                // A bucket ending in, or at the start of, a line break ends on the line before
+                let at_line_start = i > 0 && text.as_bytes()[i - 1] == b'\n';
+                let end_line = if b == b'\n' || at_line_start { (line - 1).max(start_line) } else { line };
+                bucket(start, i + 1, start_line, end_line);
+                (start, start_line, lines) = (i + 1, line, 0);
+            }
+        }
+        if start < text.len() || text.is_empty() {
+            let end_line = if text.ends_with('\n') { (line - 1).max(start_line) } else { line };
+            bucket(start, text.len(), start_line, end_line);
+        }
+
+        // Each detection counts toward the bucket it starts in; its bytes toward every
+        // bucket it covers
+        let mut redacted = vec![0usize; buckets.len()];
+        for detection in detections {
+            let first = buckets.partition_point(|bucket| bucket.start <= detection.start).saturating_sub(1);
+            if let Some(bucket) = buckets.get_mut(first) {
+                bucket.detections += 1;
+                *bucket.entities.entry(detection.entity_type.clone()).or_default() += 1;
+            }
+            for (i, bucket) in buckets.iter().enumerate().skip(first) {
+                if bucket.start >= detection.end {
+                    break;
+                }
+                redacted[i] += detection.end.min(bucket.end).saturating_sub(detection.start.max(bucket.start));
+            }
+        }
+        for (bucket, redacted) in buckets.iter_mut().zip(redacted) {
+            let len = bucket.end - bucket.start;
+            if len > 0 {
+                bucket.density = (redacted as f64 / len as f64).min(1.0);
+            }
+        }
+
+        let mut densest: Vec<&HeatmapBucket> = buckets.iter().filter(|bucket| bucket.detections > 0).collect();
+        densest.sort_by(|a, b| b.density.total_cmp(&a.density).then(a.index.cmp(&b.index)));
+        let densest = densest.iter().take(DENSEST_BUCKETS).map(|bucket| bucket.index).collect();
+
+        Self {
+            unit: if paged { HeatmapUnit::Page } else { HeatmapUnit::Lines },
+            lines_per_bucket: (!paged).then_some(lines_per_bucket),
+            buckets,
+            densest,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detection(entity_type: &str, start: usize, end: usize) -> Detection {
+        Detection { entity_type: entity_type.to_string(), start, end, score: 1.0 }
+    }
+
+    #[test]
+    fn test_heatmap_buckets_lines_and_pages() {
+        // Lines 1-2, 3-4 and 5
+        let text = "a\nb\nc jane@example.com\nd\ne";
+        let heatmap = Heatmap::build(text, &[detection("EMAIL_ADDRESS", 6, 22)], 2);
+        assert_eq!(heatmap.unit, HeatmapUnit::Lines);
+        let ranges: Vec<_> = heatmap.buckets.iter().map(|bucket| (bucket.start_line, bucket.end_line, bucket.start, bucket.end)).collect();
+        assert_eq!(ranges, vec![(1, 2, 0, 4), (3, 4, 4, 25), (5, 5, 25, 26)]);
+        assert_eq!(heatmap.buckets[1].entities["EMAIL_ADDRESS"], 1);
+        assert!((heatmap.buckets[1].density - 16.0 / 21.0).abs() < 1e-9);
+        assert_eq!(heatmap.densest, vec![2]);
+        assert_eq!(Heatmap::build("a\nb\nc\n", &[], 2).buckets[1].end_line, 3);
+
+        let pages = "Jane Roe\n\u{c}nothing here\n\u{c}Bob and Jane";
+        let heatmap = Heatmap::build(pages, &[detection("PERSON", 0, 8), detection("PERSON", 24, 27), detection("PERSON", 32, 36)], 50);
+        assert_eq!((heatmap.unit, heatmap.lines_per_bucket), (HeatmapUnit::Page, None));
+        assert_eq!(heatmap.buckets.iter().map(|bucket| bucket.detections).collect::<Vec<_>>(), vec![1, 0, 2]);
+        assert_eq!((heatmap.buckets[0].end_line, heatmap.buckets[1].start_line), (1, 2));
+        assert_eq!(heatmap.densest, vec![1, 3]);
+    }
+}
@@ -0,0 +1,109 @@
+use anyhow::{anyhow, Result};
+use rand::rngs::OsRng;
+use rsa::{
+    pkcs8::{DecodePrivateKey, EncodePrivateKey, EncodePublicKey, LineEnding},
+    RsaPrivateKey, RsaPublicKey,
+};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+// Identifier of a service key: the first 16 hex characters of the SHA-256 of its DER
+// public key, so the same key has the same `kid` wherever it is loaded
+pub fn key_id(public_key: &RsaPublicKey) -> Result<String> {
+    let der = public_key.to_public_key_der()
+        .map_err(|e| anyhow!("Failed to encode public key: {}", e))?;
+    Ok(Sha256::digest(der.as_bytes()).iter().take(8).map(|byte| format!("{:02x}", byte)).collect())
+}
+
+// Where service keys are kept across restarts. A PKCS#11 token or a KMS can stand in for
+// `PemKeyStore` by implementing this.
+pub trait KeyStore: Send + Sync {
+    // Stored keys, oldest first
+    fn load(&self) -> Result<Vec<RsaPrivateKey>>;
+    fn save(&self, private_key: &RsaPrivateKey) -> Result<()>;
+    fn remove(&self, kid: &str) -> Result<()>;
+}
+
+// Keys as passphrase-encrypted PKCS#8 PEM files (scrypt, AES-256-CBC) named
+// `<creation time in ms>-<kid>.pem`, so they sort oldest first
+pub struct PemKeyStore {
+    dir: PathBuf,
+    passphrase: String,
+}
+
+impl PemKeyStore {
+    pub fn new(dir: impl Into<PathBuf>, passphrase: impl Into<String>) -> Self {
+        Self { dir: dir.into(), passphrase: passphrase.into() }
+    }
+
+    // `SERVICE_KEY_DIR`, unlocked with `SERVICE_KEY_PASSPHRASE`; None when no directory is set
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(dir) = std::env::var("SERVICE_KEY_DIR") else {
+            return Ok(None);
+        };
+        let passphrase = std::env::var("SERVICE_KEY_PASSPHRASE")
+            .ok()
+            .filter(|passphrase| !passphrase.is_empty())
+            .ok_or_else(|| anyhow!("SERVICE_KEY_DIR needs SERVICE_KEY_PASSPHRASE"))?;
+        Ok(Some(Self::new(dir, passphrase)))
+    }
+
+    // Key files, oldest first
+    fn files(&self) -> Result<Vec<PathBuf>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(anyhow!("Failed to list service keys in {}: {}", self.dir.display(), e)),
+        };
+        let mut files: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "pem"))
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+}
+
+impl KeyStore for PemKeyStore {
+    fn load(&self) -> Result<Vec<RsaPrivateKey>> {
+        let keys = self.files()?
+            .iter()
+            .map(|path| {
+                let pem = std::fs::read_to_string(path)
+                    .map_err(|e| anyhow!("Failed to read service key {}: {}", path.display(), e))?;
+                RsaPrivateKey::from_pkcs8_encrypted_pem(&pem, self.passphrase.as_bytes())
+                    .map_err(|e| anyhow!("Failed to decrypt service key {}: {}", path.display(), e))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if !keys.is_empty() {
+            info!("Loaded {} service key(s) from {}", keys.len(), self.dir.display());
+        }
+        Ok(keys)
+    }
+
+    fn save(&self, private_key: &RsaPrivateKey) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| anyhow!("Failed to create service key directory {}: {}", self.dir.display(), e))?;
+        let pem = private_key.to_pkcs8_encrypted_pem(OsRng, self.passphrase.as_bytes(), LineEnding::LF)
+            .map_err(|e| anyhow!("Failed to encrypt service key: {}", e))?;
+        let created = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let kid = key_id(&RsaPublicKey::from(private_key))?;
+        let path = self.dir.join(format!("{:013}-{}.pem", created, kid));
+        std::fs::write(&path, pem.as_bytes())
+            .map_err(|e| anyhow!("Failed to write service key {}: {}", path.display(), e))
+    }
+
+    fn remove(&self, kid: &str) -> Result<()> {
+        let suffix = format!("-{}.pem", kid);
+        for path in self.files()? {
+            if path.file_name().is_some_and(|name| name.to_string_lossy().ends_with(&suffix)) {
+                std::fs::remove_file(&path)
+                    .map_err(|e| anyhow!("Failed to remove service key {}: {}", path.display(), e))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use tracing::info;
+
+// Tenant used for labels that apply to every tenant without its own override
+const ALL_TENANTS: &str = "*";
+
+// entity type -> replacement label
+type LocaleLabels = HashMap<String, String>;
+
+// Replacement labels per tenant and locale, loaded from a JSON file shaped as
+// { "<tenant or *>": { "<locale>": { "<ENTITY_TYPE>": "<label>" } } }
+#[derive(Default)]
+pub struct LabelCatalog {
+    tenants: HashMap<String, HashMap<String, LocaleLabels>>,
+}
+
+impl LabelCatalog {
+    pub fn from_env() -> Result<Self> {
+        let Ok(path) = std::env::var("ENTITY_LABELS_PATH") else {
+            return Ok(Self::default());
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read entity labels from {}: {}", path, e))?;
+        let catalog = Self::from_json(&contents)?;
+
+        info!("Loaded entity labels for {} tenant(s) from {}", catalog.tenants.len(), path);
+        Ok(catalog)
+    }
+
+    pub fn from_json(contents: &str) -> Result<Self> {
+        let tenants = serde_json::from_str(contents)
+            .map_err(|e| anyhow!("Invalid entity labels: {}", e))?;
+        Ok(Self { tenants })
+    }
+
+    // Resolve the label for an entity, preferring the tenant's own labels over the shared
+    // ones and an exact locale (`fr-CA`) over its base language (`fr`)
+    fn label_for(&self, tenant: Option<&str>, language: &str, entity_type: &str) -> Option<&str> {
+        let base_language = language.split(['-', '_']).next().unwrap_or(language);
+
+        tenant.into_iter()
+            .chain([ALL_TENANTS])
+            .filter_map(|tenant| self.tenants.get(tenant))
+            .flat_map(|locales| [language, base_language].into_iter().filter_map(|l| locales.get(l)))
+            .find_map(|labels| labels.get(entity_type))
+            .map(String::as_str)
+    }
+
+    // Rewrite `<ENTITY_TYPE>` tags produced by the replace strategy into localized labels
+    pub fn localize(&self, text: &str, tenant: Option<&str>, language: &str) -> String {
+        if self.tenants.is_empty() {
+            return text.to_string();
+        }
+
+        let mut output = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(start) = rest.find('<') {
+            output.push_str(&rest[..start]);
+            let candidate = &rest[start..];
+
+            let tag = candidate[1..].find('>').map(|end| &candidate[1..end + 1]);
+            match tag.filter(|tag| is_entity_tag(tag)) {
+                Some(entity_type) => {
+                    match self.label_for(tenant, language, entity_type) {
+                        Some(label) => output.push_str(label),
+                        None => output.push_str(&candidate[..entity_type.len() + 2]),
+                    }
+                    rest = &candidate[entity_type.len() + 2..];
+                }
+                None => {
+                    output.push('<');
+                    rest = &candidate[1..];
+                }
+            }
+        }
+
+        output.push_str(rest);
+        output
+    }
+}
+
+fn is_entity_tag(tag: &str) -> bool {
+    !tag.is_empty() && tag.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalog() -> LabelCatalog {
+        LabelCatalog::from_json(r#"{
+            "*": { "fr": { "PERSON": "<PERSONNE>" } },
+            "acme": { "en": { "PERSON": "[name removed]" } }
+        }"#).unwrap()
+    }
+
+    #[test]
+    fn test_localize_by_tenant_and_language() {
+        let catalog = catalog();
+        let text = "Contact <PERSON> at <EMAIL_ADDRESS> if a < b";
+
+        assert_eq!(
+            catalog.localize(text, None, "fr-CA"),
+            "Contact <PERSONNE> at <EMAIL_ADDRESS> if a < b"
+        );
+        assert_eq!(
+            catalog.localize(text, Some("acme"), "en"),
+            "Contact [name removed] at <EMAIL_ADDRESS> if a < b"
+        );
+        assert_eq!(catalog.localize(text, Some("acme"), "de"), text);
+    }
+
+    #[test]
+    fn test_tenant_falls_back_to_shared_labels() {
+        let catalog = catalog();
+        assert_eq!(catalog.localize("<PERSON>", Some("acme"), "fr"), "<PERSONNE>");
+    }
+}
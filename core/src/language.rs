@@ -0,0 +1,135 @@
+use anyhow::{anyhow, Result};
+use tracing::info;
+use whatlang::Lang;
+
+// Language an upload names to have it detected from its text
+pub const AUTO: &str = "auto";
+
+// ISO 639-1 codes of the languages whatlang detects
+const CODES: [(Lang, &str); 69] = [
+    (Lang::Afr, "af"), (Lang::Aka, "ak"), (Lang::Amh, "am"), (Lang::Ara, "ar"), (Lang::Aze, "az"),
+    (Lang::Bel, "be"), (Lang::Ben, "bn"), (Lang::Bul, "bg"), (Lang::Cat, "ca"), (Lang::Ces, "cs"),
+    (Lang::Cmn, "zh"), (Lang::Dan, "da"), (Lang::Deu, "de"), (Lang::Ell, "el"), (Lang::Eng, "en"),
+    (Lang::Epo, "eo"), (Lang::Est, "et"), (Lang::Fin, "fi"), (Lang::Fra, "fr"), (Lang::Guj, "gu"),
+    (Lang::Heb, "he"), (Lang::Hin, "hi"), (Lang::Hrv, "hr"), (Lang::Hun, "hu"), (Lang::Hye, "hy"),
+    (Lang::Ind, "id"), (Lang::Ita, "it"), (Lang::Jav, "jv"), (Lang::Jpn, "ja"), (Lang::Kan, "kn"),
+    (Lang::Kat, "ka"), (Lang::Khm, "km"), (Lang::Kor, "ko"), (Lang::Lat, "la"), (Lang::Lav, "lv"),
+    (Lang::Lit, "lt"), (Lang::Mal, "ml"), (Lang::Mar, "mr"), (Lang::Mkd, "mk"), (Lang::Mya, "my"),
+    (Lang::Nep, "ne"), (Lang::Nld, "nl"), (Lang::Nob, "nb"), (Lang::Ori, "or"), (Lang::Pan, "pa"),
+    (Lang::Pes, "fa"), (Lang::Pol, "pl"), (Lang::Por, "pt"), (Lang::Ron, "ro"), (Lang::Rus, "ru"),
+    (Lang::Sin, "si"), (Lang::Slk, "sk"), (Lang::Slv, "sl"), (Lang::Sna, "sn"), (Lang::Spa, "es"),
+    (Lang::Srp, "sr"), (Lang::Swe, "sv"), (Lang::Tam, "ta"), (Lang::Tel, "te"), (Lang::Tgl, "tl"),
+    (Lang::Tha, "th"), (Lang::Tuk, "tk"), (Lang::Tur, "tr"), (Lang::Ukr, "uk"), (Lang::Urd, "ur"),
+    (Lang::Uzb, "uz"), (Lang::Vie, "vi"), (Lang::Yid, "yi"), (Lang::Zul, "zu"),
+];
+
+// Languages the analyzer is set up for, as ISO 639-1 codes. Uploads naming none are
+// redacted in the first, or in the one detected from their text when `detect` is set.
+#[derive(Clone, Debug)]
+pub struct Languages {
+    supported: Vec<String>,
+    detect: bool,
+}
+
+impl Default for Languages {
+    fn default() -> Self {
+        Self { supported: vec!["en".to_string()], detect: false }
+    }
+}
+
+impl Languages {
+    // `supported` is comma-separated
+    pub fn new(supported: &str, detect: bool) -> Result<Self> {
+        let supported: Vec<String> = supported.split(',')
+            .map(str::trim)
+            .filter(|code| !code.is_empty())
+            .map(str::to_string)
+            .collect();
+        if supported.is_empty() {
+            return Err(anyhow!("supported_languages must name at least one language"));
+        }
+        if let Some(code) = supported.iter().find(|code| !CODES.iter().any(|(_, known)| known == code)) {
+            return Err(anyhow!("Unknown language {}; expected ISO 639-1 codes such as en or es", code));
+        }
+        Ok(Self { supported, detect })
+    }
+
+    pub fn supported(&self) -> &[String] {
+        &self.supported
+    }
+
+    pub fn default_language(&self) -> &str {
+        &self.supported[0]
+    }
+
+    // The language an upload names, before its text is decrypted
+    pub fn check(&self, requested: Option<&str>) -> Result<()> {
+        match requested {
+            None | Some(AUTO) => Ok(()),
+            Some(code) if self.supported.iter().any(|supported| supported == code) => Ok(()),
+            Some(code) => Err(anyhow!("Language {} is not supported; supported languages: {}", code, self.supported.join(", "))),
+        }
+    }
+
+    // The language of text redacted as it arrives, before any of it could be detected:
+    // the one named, or the default
+    pub fn fixed(&self, requested: Option<&str>) -> Result<String> {
+        match requested {
+            Some(AUTO) => Err(anyhow!("Streamed text cannot have its language detected; name one of {}", self.supported.join(", "))),
+            requested => self.resolve(requested, ""),
+        }
+    }
+
+    // The language `text` is redacted in. Detection that is not sure of the language
+    // falls back to the default one; a language it is sure of must be supported.
+    pub fn resolve(&self, requested: Option<&str>, text: &str) -> Result<String> {
+        self.check(requested)?;
+        match requested {
+            Some(AUTO) => {}
+            Some(code) => return Ok(code.to_string()),
+            None if !self.detect => return Ok(self.default_language().to_string()),
+            None => {}
+        }
+        let Some(detected) = whatlang::detect(text).filter(|info| info.is_reliable()) else {
+            return Ok(self.default_language().to_string());
+        };
+        let code = CODES.iter().find(|(lang, _)| *lang == detected.lang()).map_or("unknown", |(_, code)| *code);
+        if !self.supported.iter().any(|supported| supported == code) {
+            return Err(anyhow!(
+                "The text was detected as {} ({}), which is not supported; supported languages: {}",
+                detected.lang().eng_name(),
+                code,
+                self.supported.join(", ")
+            ));
+        }
+        info!("Detected language {} with confidence {:.2}", code, detected.confidence());
+        Ok(code.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_languages_are_checked_and_detected() {
+        assert!(Languages::new("", false).is_err());
+        assert!(Languages::new("en,xx", false).is_err());
+
+        let languages = Languages::new("en, es", false).unwrap();
+        assert_eq!(languages.resolve(None, "").unwrap(), "en");
+        assert_eq!(languages.resolve(Some("es"), "").unwrap(), "es");
+        assert!(languages.resolve(Some("de"), "").is_err());
+
+        let spanish = "El paciente fue trasladado al hospital general después de la consulta con su médico de cabecera.";
+        let german = "Der Patient wurde nach der Untersuchung durch seinen Hausarzt in das Krankenhaus gebracht.";
+        assert_eq!(languages.resolve(Some(AUTO), spanish).unwrap(), "es");
+        assert!(languages.resolve(Some(AUTO), german).is_err());
+        // Too short to be sure of, so the default
+        assert_eq!(languages.resolve(Some(AUTO), "ok").unwrap(), "en");
+        assert!(languages.fixed(Some(AUTO)).is_err());
+
+        // With detection on, uploads naming no language have it detected
+        assert_eq!(Languages::new("en,es", true).unwrap().resolve(None, spanish).unwrap(), "es");
+    }
+}
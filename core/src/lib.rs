@@ -0,0 +1,88 @@
+// Core of the Sentient redactor: envelope crypto, the redaction pipeline and artifact
+// storage, usable in-process without running the HTTP service.
+//
+// A minimal embedding decrypts a client envelope and redacts it:
+//
+//     let crypto = CryptoService::new()?;
+//     let session_key = crypto.decrypt_session_key(&encrypted_session_key)?;
+//     let text = crypto.decrypt_file_with_session_key(&encrypted_data, &session_key, Some(&nonce), &[])?;
+//     let segments = spans::resolve_segments(&text, &[], &[])?;
+//     let options = RedactionOptions { strategy: "replace", tenant: None, language: "en", pipelines: None, filter: EntityFilter::default(), bidi: BidiMode::default(), custom: None, boundaries: None, hashing: None };
+//     let redacted = RedactorService::new()?.redact_segments(&segments, &options).await?;
+
+pub mod acl;
+#[cfg(feature = "server")]
+pub mod attestation;
+#[cfg(feature = "axum")]
+pub mod auth;
+pub mod backend;
+pub mod bidi;
+pub mod boundary;
+pub mod caller;
+#[cfg(feature = "server")]
+pub mod chunking;
+#[cfg(feature = "server")]
+pub mod compression;
+#[cfg(feature = "server")]
+pub mod config;
+pub mod crypto;
+#[cfg(feature = "server")]
+pub mod deprecation;
+pub mod document;
+pub mod envelope;
+pub mod erasure;
+pub mod escrow;
+pub mod extract;
+pub mod heatmap;
+pub mod keystore;
+pub mod labels;
+#[cfg(feature = "server")]
+pub mod language;
+pub mod manifest;
+pub mod masking;
+#[cfg(feature = "onnx")]
+pub mod ner;
+#[cfg(feature = "server")]
+pub mod operations;
+pub mod original;
+pub mod pipeline;
+pub mod policy;
+#[cfg(feature = "server")]
+pub mod presidio;
+pub mod pseudonym;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "server")]
+pub mod redactor;
+pub mod relay;
+pub mod report;
+pub mod resilience;
+pub mod rules;
+pub mod secret;
+#[cfg(feature = "s3")]
+pub mod s3;
+#[cfg(feature = "server")]
+pub mod selftest;
+#[cfg(feature = "server")]
+pub mod session;
+#[cfg(feature = "tower")]
+pub mod service;
+#[cfg(feature = "server")]
+pub mod settings;
+pub mod spans;
+pub mod storage;
+pub mod structured;
+#[cfg(feature = "server")]
+pub mod upstream;
+pub mod usage;
+pub mod views;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "server")]
+pub mod workers;
+
+pub use backend::EntityFilter;
+pub use crypto::CryptoService;
+#[cfg(feature = "server")]
+pub use redactor::{RedactionOptions, RedactorService};
+pub use storage::{DiskStorage, FileMetadata, FileStorage, Storage};
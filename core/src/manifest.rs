@@ -0,0 +1,252 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::crypto::CryptoService;
+use crate::erasure::RECEIPT_ALGORITHM;
+use crate::report::RedactionReport;
+use crate::views::DownloadFormat;
+
+// Delimits the manifest trailer of a text download
+pub const TRAILER_BEGIN: &str = "-----BEGIN REDACTION MANIFEST-----";
+pub const TRAILER_END: &str = "-----END REDACTION MANIFEST-----";
+// Name of the manifest beside the document in a bundled download
+pub const BUNDLE_MANIFEST: &str = "redaction-manifest.json";
+pub const OUTPUT_SIGNATURE_ALGORITHM: &str = "Ed25519";
+
+// How a file was processed, fixed when it is stored. Only digests of the output are
+// kept, so a manifest reveals nothing the file does not.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ProcessingStatement {
+    pub file_id: String,
+    pub processed_at: u64,
+    pub service_version: String,
+    // Configured redaction backends, in the order they are tried
+    pub backend: String,
+    pub policy_version: String,
+    pub strategy: String,
+    // The redacted text as stored, which a `txt` download returns before any trailer
+    pub content_sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report_sha256: Option<String>,
+}
+
+// A statement plus the service's signature over its exact JSON encoding (`payload`),
+// as for erasure receipts
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ProcessingManifest {
+    pub manifest: ProcessingStatement,
+    pub payload: String,
+    pub algorithm: String,
+    pub key_id: String,
+    pub signature: String,
+    pub public_key: String,
+}
+
+impl ProcessingManifest {
+    pub fn issue(crypto: &CryptoService, manifest: ProcessingStatement) -> Result<Self> {
+        let payload = serde_json::to_vec(&manifest)?;
+        Ok(Self {
+            manifest,
+            signature: crypto.sign(&payload),
+            payload: BASE64.encode(&payload),
+            algorithm: RECEIPT_ALGORITHM.to_string(),
+            key_id: crypto.key_id(),
+            public_key: crypto.get_public_key()?,
+        })
+    }
+}
+
+// Detached signature of a redacted output, over its file id, when it was signed and the
+// digest of the exact bytes served. Downloads carry it as `X-Signature`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct OutputSignature {
+    pub file_id: String,
+    // Unix seconds
+    pub timestamp: u64,
+    pub content_sha256: String,
+    pub algorithm: String,
+    // `kid` of the service key the signing key belongs to
+    pub key_id: String,
+    // Base64 Ed25519 public key, as `signing_key` at `/handshake`
+    pub public_key: String,
+    pub signature: String,
+}
+
+impl OutputSignature {
+    pub fn issue(crypto: &CryptoService, file_id: &str, content: &[u8], timestamp: u64) -> Self {
+        let content_sha256 = content_digest(content);
+        let (key_id, signature) = crypto.sign_output(&signed_output(file_id, timestamp, &content_sha256));
+        Self {
+            file_id: file_id.to_string(),
+            timestamp,
+            content_sha256,
+            algorithm: OUTPUT_SIGNATURE_ALGORITHM.to_string(),
+            key_id,
+            public_key: crypto.signing_public_key(),
+            signature,
+        }
+    }
+
+    // `X-Signature: t=<timestamp>,kid=<key id>,ed25519=<signature>`
+    pub fn header(&self) -> String {
+        format!("t={},kid={},ed25519={}", self.timestamp, self.key_id, self.signature)
+    }
+
+    // Check the signature against `content` under a base64 Ed25519 public key
+    pub fn verify(&self, public_key: &str, content: &[u8]) -> Result<()> {
+        use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+        let public_key: [u8; 32] = BASE64.decode(public_key).ok()
+            .and_then(|key| key.try_into().ok())
+            .ok_or_else(|| anyhow!("Invalid Ed25519 public key"))?;
+        let signature: [u8; 64] = BASE64.decode(&self.signature).ok()
+            .and_then(|signature| signature.try_into().ok())
+            .ok_or_else(|| anyhow!("Invalid Ed25519 signature"))?;
+        let content_sha256 = content_digest(content);
+        if content_sha256 != self.content_sha256 {
+            return Err(anyhow!("The content does not match the signed digest"));
+        }
+        VerifyingKey::from_bytes(&public_key)
+            .map_err(|e| anyhow!("Invalid Ed25519 public key: {}", e))?
+            .verify(&signed_output(&self.file_id, self.timestamp, &content_sha256), &Signature::from_bytes(&signature))
+            .map_err(|_| anyhow!("The signature does not match"))
+    }
+}
+
+// The signed message: file id, timestamp and content digest, one per line
+fn signed_output(file_id: &str, timestamp: u64, content_sha256: &str) -> Vec<u8> {
+    format!("{}\n{}\n{}", file_id, timestamp, content_sha256).into_bytes()
+}
+
+pub fn content_digest(content: &[u8]) -> String {
+    Sha256::digest(content).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn report_digest(report: &RedactionReport) -> Option<String> {
+    serde_json::to_vec(report).ok().map(|json| content_digest(&json))
+}
+
+// A download in `format` carrying the manifest: a trailer after the text, a `manifest`
+// field of the JSON view, or a JSON script block in the HTML view
+pub fn embed(format: DownloadFormat, content: String, manifest: &ProcessingManifest) -> String {
+    let json = serde_json::to_string(manifest).unwrap_or_default();
+    match format {
+        // Always on a line of its own, so the text is everything before the newline ahead of it
+        DownloadFormat::Txt => format!("{}\n{}\n{}\n{}\n", content, TRAILER_BEGIN, json, TRAILER_END),
+        DownloadFormat::Json => match serde_json::from_str::<serde_json::Value>(&content) {
+            Ok(serde_json::Value::Object(mut view)) => {
+                view.insert("manifest".to_string(), serde_json::to_value(manifest).unwrap_or_default());
+                serde_json::Value::Object(view).to_string()
+            }
+            _ => content,
+        },
+        DownloadFormat::Html => {
+            // `<` is escaped so the JSON cannot close the script element
+            let block = format!(
+                "<script type=\"application/json\" id=\"redaction-manifest\">{}</script>\n",
+                json.replace('<', "\\u003c")
+            );
+            match content.rfind("</body>") {
+                Some(end) => format!("{}{}{}", &content[..end], block, &content[end..]),
+                None => content + &block,
+            }
+        }
+    }
+}
+
+// A binary download as a zip of the file and the manifest beside it
+#[cfg(feature = "server")]
+pub fn bundle(file_name: &str, content: &[u8], manifest: &ProcessingManifest) -> Result<Vec<u8>> {
+    use std::io::{Cursor, Write};
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    zip.start_file(file_name, SimpleFileOptions::default())?;
+    zip.write_all(content)?;
+    zip.start_file(BUNDLE_MANIFEST, SimpleFileOptions::default())?;
+    zip.write_all(&serde_json::to_vec_pretty(manifest)?)?;
+    Ok(zip.finish()?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::{pkcs1v15::{Signature, VerifyingKey}, pkcs8::DecodePublicKey, signature::Verifier, RsaPublicKey};
+    use sha2::Sha256;
+
+    fn manifest() -> ProcessingManifest {
+        let crypto = CryptoService::new().unwrap();
+        let statement = ProcessingStatement {
+            file_id: "f1".to_string(),
+            processed_at: 1_700_000_000,
+            service_version: "0.1.0".to_string(),
+            backend: "regex".to_string(),
+            policy_version: "default".to_string(),
+            strategy: "replace".to_string(),
+            content_sha256: content_digest(b"Call <PHONE_NUMBER>"),
+            report_sha256: None,
+        };
+        ProcessingManifest::issue(&crypto, statement).unwrap()
+    }
+
+    #[test]
+    fn test_manifests_travel_with_the_file() {
+        let manifest = manifest();
+        let public_key = RsaPublicKey::from_public_key_pem(&manifest.public_key).unwrap();
+        let signature = Signature::try_from(BASE64.decode(&manifest.signature).unwrap().as_slice()).unwrap();
+        let payload = BASE64.decode(&manifest.payload).unwrap();
+        assert!(VerifyingKey::<Sha256>::new(public_key).verify(&payload, &signature).is_ok());
+
+        // The text before the trailer is what the digest covers
+        let text = embed(DownloadFormat::Txt, "Call <PHONE_NUMBER>".to_string(), &manifest);
+        let (content, trailer) = text.rsplit_once(&format!("\n{}\n", TRAILER_BEGIN)).unwrap();
+        assert_eq!(content_digest(content.as_bytes()), manifest.manifest.content_sha256);
+        let embedded: ProcessingManifest = serde_json::from_str(trailer.strip_suffix(&format!("\n{}\n", TRAILER_END)).unwrap()).unwrap();
+        assert_eq!(embedded.signature, manifest.signature);
+
+        let json = embed(DownloadFormat::Json, r#"{"file_id":"f1","content":"x"}"#.to_string(), &manifest);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&json).unwrap()["manifest"]["manifest"]["file_id"], "f1");
+        let html = embed(DownloadFormat::Html, "<html><body><pre>x</pre></body></html>".to_string(), &manifest);
+        assert!(html.contains("id=\"redaction-manifest\">{") && html.ends_with("</script>\n</body></html>"));
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn test_manifests_are_bundled_with_documents() {
+        use std::io::{Cursor, Read};
+
+        let manifest = manifest();
+        let archive = bundle("memo.docx", b"PK...", &manifest).unwrap();
+        let mut archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+        let mut bundled = String::new();
+        archive.by_name(BUNDLE_MANIFEST).unwrap().read_to_string(&mut bundled).unwrap();
+        assert_eq!(serde_json::from_str::<ProcessingManifest>(&bundled).unwrap().payload, manifest.payload);
+        assert!(archive.by_name("memo.docx").is_ok());
+    }
+
+    #[test]
+    fn test_outputs_are_signed_for_downstream_checks() {
+        let crypto = CryptoService::new().unwrap();
+        let signature = OutputSignature::issue(&crypto, "f1", b"Call <PHONE_NUMBER>", 1_700_000_000);
+        assert_eq!(signature.public_key, crypto.signing_public_key());
+        assert!(signature.header().starts_with(&format!("t=1700000000,kid={},ed25519=", crypto.key_id())));
+        signature.verify(&signature.public_key, b"Call <PHONE_NUMBER>").unwrap();
+
+        // Altered content, another file id or timestamp, or another key do not verify
+        assert!(signature.verify(&signature.public_key, b"Call 555-0100").is_err());
+        let moved = OutputSignature { file_id: "f2".to_string(), ..signature.clone() };
+        assert!(moved.verify(&signature.public_key, b"Call <PHONE_NUMBER>").is_err());
+        let backdated = OutputSignature { timestamp: 1, ..signature.clone() };
+        assert!(backdated.verify(&signature.public_key, b"Call <PHONE_NUMBER>").is_err());
+        let other = CryptoService::new().unwrap().signing_public_key();
+        assert!(signature.verify(&other, b"Call <PHONE_NUMBER>").is_err());
+
+        // The signing key follows the service key it is derived from
+        let old = crypto.signing_public_key();
+        crypto.rotate().unwrap();
+        assert_ne!(crypto.signing_public_key(), old);
+    }
+}
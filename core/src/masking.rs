@@ -0,0 +1,76 @@
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::envelope;
+use crate::report::{self, Detection};
+
+// Strategy replacing each value with a keyed hash of it
+pub const HASH: &str = "hash";
+// Hex digits a surrogate keeps: all of the digest by default, or as few as `MIN_LENGTH`
+pub const MAX_LENGTH: usize = 64;
+pub const MIN_LENGTH: usize = 6;
+
+// The `hash` strategy. Each value becomes its entity type and the hex HMAC-SHA256 of
+// the value under the tenant's masking key, e.g. `EMAIL_ADDRESS_a1b2c3`, so equal values
+// get equal surrogates across files and analytics can join on them. Unlike pseudonyms,
+// nothing is kept that reverses them.
+pub struct HashMasking {
+    key: Zeroizing<[u8; 32]>,
+    length: usize,
+}
+
+impl HashMasking {
+    // `key` comes from `CryptoService::masking_key`; `length` truncates the digest
+    pub fn new(key: [u8; 32], length: Option<usize>) -> Result<Self> {
+        let length = Self::length(length)?;
+        Ok(Self { key: Zeroizing::new(key), length })
+    }
+
+    pub fn length(length: Option<usize>) -> Result<usize> {
+        match length.unwrap_or(MAX_LENGTH) {
+            length if (MIN_LENGTH..=MAX_LENGTH).contains(&length) => Ok(length),
+            _ => Err(anyhow!("hash_length must be between {} and {}", MIN_LENGTH, MAX_LENGTH)),
+        }
+    }
+
+    pub fn surrogate(&self, entity_type: &str, value: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.key.as_slice()).expect("HMAC takes keys of any length");
+        mac.update(value.as_bytes());
+        let digest = envelope::hex(&mac.finalize().into_bytes());
+        format!("{}_{}", entity_type, &digest[..self.length])
+    }
+
+    // Replace every detection in `text` with its surrogate. None when a detection does
+    // not fall on character boundaries of `text`.
+    pub fn apply_to_text(&self, text: &str, detections: &[Detection]) -> Option<String> {
+        report::rewrite_detections(text, detections, |detection, value| self.surrogate(&detection.entity_type, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_equal_values_get_equal_surrogates() {
+        let masking = HashMasking::new([1; 32], None).unwrap();
+        let text = "jane@example.com wrote to bob@example.com, cc jane@example.com";
+        let detection = |start, end| Detection { entity_type: "EMAIL_ADDRESS".to_string(), start, end, score: 1.0 };
+        let masked = masking.apply_to_text(text, &[detection(0, 16), detection(26, 41), detection(46, 62)]).unwrap();
+        let surrogates: Vec<&str> = masked.split([' ', ',']).filter(|word| word.starts_with("EMAIL_ADDRESS_")).collect();
+        assert_eq!(surrogates.len(), 3);
+        assert_eq!(surrogates[0].len(), "EMAIL_ADDRESS_".len() + MAX_LENGTH);
+        assert_eq!(surrogates[0], surrogates[2]);
+        assert_ne!(surrogates[0], surrogates[1]);
+
+        // Truncated, the surrogate is a prefix of the full one; another key gives another
+        let short = HashMasking::new([1; 32], Some(6)).unwrap().surrogate("EMAIL_ADDRESS", "jane@example.com");
+        assert!(surrogates[0].starts_with(&short) && short.len() == "EMAIL_ADDRESS_".len() + 6);
+        assert_ne!(HashMasking::new([2; 32], Some(6)).unwrap().surrogate("EMAIL_ADDRESS", "jane@example.com"), short);
+
+        assert!(HashMasking::new([1; 32], Some(5)).is_err());
+        assert!(HashMasking::new([1; 32], Some(65)).is_err());
+    }
+}
@@ -0,0 +1,366 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use ort::session::Session;
+use ort::value::Tensor;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+use crate::backend::{Analysis, EntityFilter, RedactionBackend};
+use crate::report::{self, Detection};
+use crate::rules::replacement;
+
+// Tokens one inference pass takes, [CLS] and [SEP] included; longer texts are tagged in
+// consecutive windows
+const MAX_TOKENS: usize = 512;
+// Longer words become [UNK], as in BERT's own tokenizer
+const MAX_WORD_CHARS: usize = 100;
+
+// Model labels and the entity types they are reported as, the mapping Presidio applies
+// to its own NER models. Other labels, such as ORG and MISC, are not redacted.
+const LABELS: &[(&str, &str)] = &[
+    ("PER", "PERSON"),
+    ("PERSON", "PERSON"),
+    ("LOC", "LOCATION"),
+    ("LOCATION", "LOCATION"),
+    ("GPE", "LOCATION"),
+    ("NORP", "NRP"),
+    ("NRP", "NRP"),
+    ("DATE", "DATE_TIME"),
+    ("TIME", "DATE_TIME"),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Tag {
+    // Starts an entity
+    Begin,
+    // Continues the entity of the same type before it, or starts one
+    Inside,
+}
+
+type Label = (Tag, &'static str);
+
+// A label in the IOB, BIOES or BILOU schemes, e.g. `B-PER`; None for `O` and for types
+// that are not redacted
+fn label(name: &str) -> Option<Label> {
+    let (tag, kind) = match name.split_once('-') {
+        Some(("B" | "S" | "U", kind)) => (Tag::Begin, kind),
+        Some(("I" | "E" | "L", kind)) => (Tag::Inside, kind),
+        _ => (Tag::Inside, name),
+    };
+    LABELS.iter().find(|(known, _)| known.eq_ignore_ascii_case(kind)).map(|(_, entity_type)| (tag, *entity_type))
+}
+
+// The Hugging Face `config.json` of the model, naming the label of each output class
+#[derive(Deserialize)]
+struct ModelConfig {
+    id2label: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct TokenizerConfig {
+    #[serde(default)]
+    do_lower_case: bool,
+}
+
+// One word piece, with its byte offsets into the text
+#[derive(Clone, Debug, PartialEq)]
+struct Token {
+    id: i64,
+    start: usize,
+    end: usize,
+    // A `##` piece continuing the word before it
+    continues: bool,
+}
+
+// BERT's WordPiece tokenizer: text is split on whitespace and punctuation, then each
+// word into the longest pieces the vocabulary has
+struct WordPiece {
+    vocab: HashMap<String, i64>,
+    lowercase: bool,
+    unknown: i64,
+    cls: i64,
+    sep: i64,
+}
+
+impl WordPiece {
+    fn new(vocab: HashMap<String, i64>, lowercase: bool) -> Result<Self> {
+        let special = |token: &str| vocab.get(token).copied().ok_or_else(|| anyhow!("The vocabulary has no {} token", token));
+        Ok(Self { unknown: special("[UNK]")?, cls: special("[CLS]")?, sep: special("[SEP]")?, vocab, lowercase })
+    }
+
+    // `vocab.txt`, one token per line, its id being its line number
+    fn load(path: &Path, lowercase: bool) -> Result<Self> {
+        let vocab = std::fs::read_to_string(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        Self::new(vocab.lines().enumerate().map(|(id, token)| (token.to_string(), id as i64)).collect(), lowercase)
+    }
+
+    fn tokenize(&self, text: &str) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        for (start, word) in words(text) {
+            self.split_word(word, start, &mut tokens);
+        }
+        tokens
+    }
+
+    // Greedy longest-match-first; a word with any part outside the vocabulary is [UNK]
+    fn split_word(&self, word: &str, offset: usize, tokens: &mut Vec<Token>) {
+        let unknown = Token { id: self.unknown, start: offset, end: offset + word.len(), continues: false };
+        let bounds: Vec<usize> = word.char_indices().map(|(i, _)| i).chain([word.len()]).collect();
+        if bounds.len() - 1 > MAX_WORD_CHARS {
+            tokens.push(unknown);
+            return;
+        }
+        let mut pieces = Vec::new();
+        let mut from = 0;
+        while from < bounds.len() - 1 {
+            let found = (from + 1..bounds.len()).rev().find_map(|to| {
+                let piece = &word[bounds[from]..bounds[to]];
+                let piece = if self.lowercase { piece.to_lowercase() } else { piece.to_string() };
+                let piece = if from > 0 { format!("##{}", piece) } else { piece };
+                self.vocab.get(&piece).map(|id| (to, *id))
+            });
+            let Some((to, id)) = found else {
+                tokens.push(unknown);
+                return;
+            };
+            pieces.push(Token { id, start: offset + bounds[from], end: offset + bounds[to], continues: from > 0 });
+            from = to;
+        }
+        tokens.extend(pieces);
+    }
+}
+
+// Words of `text` with their byte offsets; each punctuation mark is a word of its own
+fn words(text: &str) -> Vec<(usize, &str)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        let separate = c.is_whitespace() || c.is_control();
+        let punctuation = !separate && !c.is_alphanumeric();
+        if separate || punctuation {
+            if let Some(from) = start.take() {
+                words.push((from, &text[from..i]));
+            }
+            if punctuation {
+                words.push((i, &text[i..i + c.len_utf8()]));
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(from) = start {
+        words.push((from, &text[from..]));
+    }
+    words
+}
+
+// Merge tagged tokens into detections. A word takes the label of its first piece, and
+// continues the entity before it when inside-tagged with the same type.
+fn decode(tokens: &[Token], predictions: &[(Option<Label>, f32)]) -> Vec<Detection> {
+    // Each entity with the scores of its words summed
+    let mut entities: Vec<(Detection, f64, usize)> = Vec::new();
+    // Whether the current word is in the last entity
+    let mut open = false;
+    for (token, (label, score)) in tokens.iter().zip(predictions) {
+        if token.continues {
+            if let Some((entity, _, _)) = entities.last_mut().filter(|_| open) {
+                entity.end = token.end;
+            }
+            continue;
+        }
+        let Some((tag, entity_type)) = label else {
+            open = false;
+            continue;
+        };
+        match entities.last_mut() {
+            Some((entity, sum, words)) if open && *tag == Tag::Inside && entity.entity_type == *entity_type => {
+                entity.end = token.end;
+                *sum += *score as f64;
+                *words += 1;
+            }
+            _ => {
+                let entity = Detection { entity_type: entity_type.to_string(), start: token.start, end: token.end, score: 0.0 };
+                entities.push((entity, *score as f64, 1));
+            }
+        }
+        open = true;
+    }
+    entities.into_iter().map(|(entity, sum, words)| Detection { score: sum / words as f64, ..entity }).collect()
+}
+
+struct Model {
+    session: Mutex<Session>,
+    // Whether the model takes `token_type_ids`, as BERT does and DistilBERT does not
+    token_types: bool,
+    tokenizer: WordPiece,
+    // Indexed by output class
+    labels: Vec<Option<Label>>,
+}
+
+impl Model {
+    fn recognize(&self, text: &str) -> Result<Vec<Detection>> {
+        let tokens = self.tokenizer.tokenize(text);
+        let mut predictions = Vec::with_capacity(tokens.len());
+        let mut from = 0;
+        while from < tokens.len() {
+            // Windows end between words, unless one word fills a whole window
+            let mut to = (from + MAX_TOKENS - 2).min(tokens.len());
+            while to < tokens.len() && to > from + 1 && tokens[to].continues {
+                to -= 1;
+            }
+            predictions.extend(self.tag(&tokens[from..to])?);
+            from = to;
+        }
+        Ok(decode(&tokens, &predictions))
+    }
+
+    // The most likely label of each token, and its probability
+    fn tag(&self, tokens: &[Token]) -> Result<Vec<(Option<Label>, f32)>> {
+        let ids: Vec<i64> = [self.tokenizer.cls].into_iter()
+            .chain(tokens.iter().map(|token| token.id))
+            .chain([self.tokenizer.sep])
+            .collect();
+        let shape = [1, ids.len()];
+        let mut inputs = vec![
+            ("input_ids", Tensor::from_array((shape, ids.clone()))?),
+            ("attention_mask", Tensor::from_array((shape, vec![1i64; ids.len()]))?),
+        ];
+        if self.token_types {
+            inputs.push(("token_type_ids", Tensor::from_array((shape, vec![0i64; ids.len()]))?));
+        }
+
+        let mut session = self.session.lock().unwrap();
+        let outputs = session.run(inputs)?;
+        let (shape, logits) = outputs[0].try_extract_tensor::<f32>()?;
+        let classes = shape.last().copied().unwrap_or_default() as usize;
+        if classes != self.labels.len() || logits.len() != ids.len() * classes {
+            return Err(anyhow!("The model returned logits of shape {:?} for {} tokens and {} labels", shape, ids.len(), self.labels.len()));
+        }
+        // Skipping [CLS] and [SEP]
+        Ok(logits.chunks(classes)
+            .skip(1)
+            .take(tokens.len())
+            .map(|row| {
+                let (best, max) = row.iter().enumerate().fold((0, f32::MIN), |best, (i, logit)| if *logit > best.1 { (i, *logit) } else { best });
+                let total: f32 = row.iter().map(|logit| (logit - max).exp()).sum();
+                (self.labels[best], 1.0 / total)
+            })
+            .collect())
+    }
+}
+
+// A token-classification transformer (BERT, DistilBERT and the like) exported to ONNX,
+// run in-process so the service needs no Presidio sidecar. It detects PERSON, LOCATION,
+// NRP and DATE_TIME, as far as the model's labels cover them, and redacts them with the
+// same strategies and replacements as the regex engine.
+pub struct NerBackend {
+    model: Arc<Model>,
+}
+
+impl NerBackend {
+    // A directory holding `model.onnx`, `vocab.txt` and `config.json`, and optionally
+    // `tokenizer_config.json`, as Hugging Face exports them
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let read = |name: &str| {
+            let path = dir.join(name);
+            std::fs::read_to_string(&path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))
+        };
+        let config: ModelConfig = serde_json::from_str(&read("config.json")?)
+            .map_err(|e| anyhow!("Invalid config.json: {}", e))?;
+        let mut labels = vec![None; config.id2label.len()];
+        for (id, name) in &config.id2label {
+            let id: usize = id.parse().ok().filter(|id| *id < labels.len())
+                .ok_or_else(|| anyhow!("Invalid config.json: label id {} is out of range", id))?;
+            labels[id] = label(name);
+        }
+        let lowercase = match dir.join("tokenizer_config.json").exists() {
+            true => serde_json::from_str::<TokenizerConfig>(&read("tokenizer_config.json")?)
+                .map_err(|e| anyhow!("Invalid tokenizer_config.json: {}", e))?
+                .do_lower_case,
+            false => false,
+        };
+        let tokenizer = WordPiece::load(&dir.join("vocab.txt"), lowercase)?;
+
+        let environment = ort::init().with_name("sentient-redactor").build()?;
+        let session = Session::builder(&environment)
+            .and_then(|mut builder| builder.commit_from_file(dir.join("model.onnx")))
+            .map_err(|e| anyhow!("Failed to load {}: {}", dir.join("model.onnx").display(), e))?;
+        let token_types = session.inputs().iter().any(|input| input.name() == "token_type_ids");
+
+        let backend = Self { model: Arc::new(Model { session: Mutex::new(session), token_types, tokenizer, labels }) };
+        info!("NER backend loaded {} with entity types {}", dir.display(), backend.entities().join(", "));
+        Ok(backend)
+    }
+
+    async fn recognize(&self, text: &str, filter: EntityFilter<'_>) -> Result<Vec<Detection>> {
+        let model = self.model.clone();
+        let text = text.to_string();
+        let detections = tokio::task::spawn_blocking(move || model.recognize(&text))
+            .await
+            .map_err(|e| anyhow!("NER inference failed: {}", e))??;
+        Ok(detections.into_iter().filter(|detection| filter.allows(&detection.entity_type, detection.score)).collect())
+    }
+}
+
+#[async_trait]
+impl RedactionBackend for NerBackend {
+    fn name(&self) -> &str {
+        "onnx"
+    }
+
+    fn entities(&self) -> Vec<&str> {
+        let mut entities: Vec<&str> = self.model.labels.iter().flatten().map(|(_, entity_type)| *entity_type).collect();
+        entities.sort_unstable();
+        entities.dedup();
+        entities
+    }
+
+    async fn analyze(&self, text: &str, strategy: &str, filter: EntityFilter<'_>) -> Result<Analysis> {
+        let detections = self.recognize(text, filter).await?;
+        let redacted = report::rewrite_detections(text, &detections, |detection, _| replacement(&detection.entity_type, strategy))
+            .ok_or_else(|| anyhow!("NER detections do not fall on character boundaries"))?;
+        Ok(Analysis { redacted, detections, fallback: false })
+    }
+
+    async fn detect(&self, text: &str, filter: EntityFilter<'_>) -> Result<Vec<Detection>> {
+        self.recognize(text, filter).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_pieces_are_tagged_into_entities() {
+        let vocab = ["[UNK]", "[CLS]", "[SEP]", "jane", "do", "##e", "moved", "to", "new", "york", "in", "may", "."];
+        let tokenizer = WordPiece::new(vocab.iter().enumerate().map(|(id, token)| (token.to_string(), id as i64)).collect(), true).unwrap();
+        let text = "Jane Doe moved to New York in May. Zoë";
+        let tokens = tokenizer.tokenize(text);
+        let pieces: Vec<&str> = tokens.iter().map(|token| &text[token.start..token.end]).collect();
+        assert_eq!(pieces, ["Jane", "Do", "e", "moved", "to", "New", "York", "in", "May", ".", "Zoë"]);
+        assert!(tokens[2].continues && !tokens[1].continues);
+        assert_eq!(tokens[10].id, tokenizer.unknown);
+
+        // A stray inside tag on a continuation piece and an ORG label change nothing
+        let predictions: Vec<(Option<Label>, f32)> = [
+            ("B-PER", 0.9), ("I-PER", 0.8), ("I-LOC", 0.1), ("O", 1.0), ("O", 1.0),
+            ("B-GPE", 0.9), ("I-GPE", 0.7), ("O", 1.0), ("B-DATE", 0.6), ("O", 1.0), ("B-ORG", 0.9),
+        ]
+        .iter()
+        .map(|(name, score)| (label(name), *score))
+        .collect();
+        let detections = decode(&tokens, &predictions);
+        let found: Vec<(&str, &str)> = detections.iter().map(|d| (d.entity_type.as_str(), &text[d.start..d.end])).collect();
+        assert_eq!(found, [("PERSON", "Jane Doe"), ("LOCATION", "New York"), ("DATE_TIME", "May")]);
+        assert!((detections[1].score - 0.8).abs() < 1e-6);
+
+        assert_eq!(label("S-PERSON"), Some((Tag::Begin, "PERSON")));
+        assert_eq!(label("NORP"), Some((Tag::Inside, "NRP")));
+        assert_eq!(label("O"), None);
+    }
+}
@@ -0,0 +1,2797 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use crate::acl::{self, AclOperation, FileAcl};
+use crate::attestation::AttestationEvidence;
+use crate::backend::EntityFilter;
+use crate::bidi;
+use crate::chunking;
+use crate::caller::{Caller, Scope};
+use crate::compression::{Compression, DecompressedTooLarge};
+use crate::crypto::{self, CryptoService, PayloadCipher};
+use crate::deprecation::{Deprecation, ProtocolDeprecations};
+use crate::document::{self, DocumentFormat};
+use crate::extract::{KeepRule, TemplateExtractor};
+use crate::heatmap::Heatmap;
+use crate::manifest::{self, ProcessingManifest, ProcessingStatement};
+use crate::original::RetainedOriginal;
+use crate::policy::{Deduplication, ProcessingWindow, RedactionPolicy, ReviewHold, StrictAction};
+use crate::presidio::ContractMismatch;
+use crate::pseudonym::{PseudonymMap, SealedPseudonyms};
+use crate::masking::{HashMasking, HASH};
+use crate::redactor::{RedactionOptions, RedactorService, PSEUDONYMIZE};
+use crate::relay::{RelayEnvelope, RelayIdentities, RelayRegistry};
+use crate::report::{RedactionReport, ReportQuery, ReportSummary, ScrubbedMetadata};
+use crate::rules::{CustomPattern, CustomRules};
+use crate::secret::{SecretBytes, SecretText};
+use crate::resilience::{BackendUnavailable, DeadlineExceeded, Stage};
+use crate::workers::CryptoSaturated;
+use crate::session::{SessionError, SessionGrant, SessionManager};
+use crate::settings::RuntimeSettings;
+use crate::spans::{self, ByteSpan};
+use crate::storage::{ColdTier, FileMetadata, Storage};
+use crate::structured::{self, ContentType};
+use crate::views::{self, DownloadFormat};
+
+// Upper bound of an upload's `backend_timeout_ms`
+pub const MAX_BACKEND_TIMEOUT_MS: u64 = 10 * 60 * 1000;
+
+// Framework-agnostic operations behind the HTTP endpoints. They take plain structs and
+// return plain results, so any web framework can wrap them in a thin adapter.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    Conflict,
+    Gone,
+    // The body is over the route's size limit
+    PayloadTooLarge,
+    // The instance does not serve the route's method, e.g. a write on a read-only replica
+    MethodNotAllowed,
+    UnsupportedMediaType,
+    Unprocessable,
+    Internal,
+    // An upstream the request depends on failed, e.g. the attestation service
+    BadGateway,
+    // A backend is down, e.g. its circuit breaker is open
+    Unavailable,
+    Timeout,
+    // The client uses a retired protocol mode
+    UpgradeRequired,
+    // The client is over its rate limit, or the service is at capacity
+    TooManyRequests,
+}
+
+const ERROR_KINDS: [ErrorKind; 16] = [
+    ErrorKind::BadRequest,
+    ErrorKind::Unauthorized,
+    ErrorKind::Forbidden,
+    ErrorKind::NotFound,
+    ErrorKind::Conflict,
+    ErrorKind::Gone,
+    ErrorKind::PayloadTooLarge,
+    ErrorKind::MethodNotAllowed,
+    ErrorKind::UnsupportedMediaType,
+    ErrorKind::Unprocessable,
+    ErrorKind::Internal,
+    ErrorKind::BadGateway,
+    ErrorKind::Unavailable,
+    ErrorKind::Timeout,
+    ErrorKind::UpgradeRequired,
+    ErrorKind::TooManyRequests,
+];
+
+impl ErrorKind {
+    // HTTP status code for adapters
+    pub fn status(self) -> u16 {
+        match self {
+            ErrorKind::BadRequest => 400,
+            ErrorKind::Unauthorized => 401,
+            ErrorKind::Forbidden => 403,
+            ErrorKind::NotFound => 404,
+            ErrorKind::MethodNotAllowed => 405,
+            ErrorKind::Conflict => 409,
+            ErrorKind::Gone => 410,
+            ErrorKind::PayloadTooLarge => 413,
+            ErrorKind::UnsupportedMediaType => 415,
+            ErrorKind::Unprocessable => 422,
+            ErrorKind::UpgradeRequired => 426,
+            ErrorKind::TooManyRequests => 429,
+            ErrorKind::Internal => 500,
+            ErrorKind::BadGateway => 502,
+            ErrorKind::Unavailable => 503,
+            ErrorKind::Timeout => 504,
+        }
+    }
+
+    // Code of errors that carry no more specific one, so every error body has a code
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorKind::BadRequest => "bad_request",
+            ErrorKind::Unauthorized => "unauthorized",
+            ErrorKind::Forbidden => "forbidden",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::MethodNotAllowed => "method_not_allowed",
+            ErrorKind::Conflict => "conflict",
+            ErrorKind::Gone => "gone",
+            ErrorKind::PayloadTooLarge => "payload_too_large",
+            ErrorKind::UnsupportedMediaType => "unsupported_media_type",
+            ErrorKind::Unprocessable => "unprocessable",
+            ErrorKind::UpgradeRequired => "upgrade_required",
+            ErrorKind::TooManyRequests => "too_many_requests",
+            ErrorKind::Internal => "internal",
+            ErrorKind::BadGateway => "bad_gateway",
+            ErrorKind::Unavailable => "unavailable",
+            ErrorKind::Timeout => "timeout",
+        }
+    }
+
+    // The kind of an error status, for responses made outside the operations
+    pub fn from_status(status: u16) -> Option<Self> {
+        ERROR_KINDS.into_iter().find(|kind| kind.status() == status)
+    }
+}
+
+#[derive(Debug)]
+pub struct OperationError {
+    pub kind: ErrorKind,
+    // Stable machine-readable code, for failures clients must tell apart
+    pub code: Option<&'static str>,
+    pub message: String,
+    // Endpoint that tells the client how to recover, e.g. `/capabilities`
+    pub link: Option<&'static str>,
+}
+
+impl OperationError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            code: None,
+            message: message.into(),
+            link: None,
+        }
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_link(mut self, link: &'static str) -> Self {
+        self.link = Some(link);
+        self
+    }
+}
+
+impl fmt::Display for OperationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct HandshakeResponse {
+    pub public_key: String,
+    // Id of `public_key`, for uploads to send as `key_id`
+    pub kid: String,
+    pub algorithm: &'static str,
+    // Payload ciphers uploads may name in `cipher`
+    pub ciphers: Vec<&'static str>,
+    // Base64 Ed25519 key redacted outputs are signed with, and the RSA signature of
+    // `public_key` over its raw bytes, which ties it to the attested key
+    pub signing_key: String,
+    pub signing_key_signature: String,
+    // Filled in by adapters when attestation is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<AttestationEvidence>,
+    // Present when the handshake negotiated a session (`POST /handshake`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<SessionGrant>,
+}
+
+// Whether an upload's redacted output is kept for download
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Retention {
+    #[default]
+    Stored,
+    // Returned in the upload response, encrypted under the session key, and never stored
+    None,
+}
+
+// How an upload response carries the redacted content
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseMode {
+    // Only the file id, for `GET /download/:file_id`
+    #[default]
+    Reference,
+    // The content in the response body, saving the download round trip
+    Inline,
+    // The same, encrypted under the upload's session key
+    InlineEncrypted,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UploadRequest {
+    // Filled in by the service for uploads from a URL
+    #[serde(default)]
+    pub encrypted_data: String,
+    // Raw ciphertext of a binary (multipart) upload, used instead of `encrypted_data`
+    #[serde(skip)]
+    pub ciphertext: Option<Vec<u8>>,
+    pub encrypted_session_key: Option<String>,
+    // `kid` of the service key `encrypted_session_key` is wrapped to; every key is tried when unset
+    pub key_id: Option<String>,
+    // Base64 96-bit nonce, unique per session key
+    pub nonce: Option<String>,
+    // Cipher `encrypted_data` is sealed with, one of those `/handshake` lists;
+    // `chacha20-poly1305` when unset
+    pub cipher: Option<String>,
+    pub psk_id: Option<String>,
+    pub psk_salt: Option<String>,
+    // Session negotiated by `POST /handshake`, whose key the upload is encrypted under
+    pub session_id: Option<String>,
+    pub file_name: Option<String>,
+    pub redaction_strategy: Option<String>,
+    // Hex digits kept of each `hash` surrogate, 6 to 64 (all of them when unset)
+    pub hash_length: Option<usize>,
+    pub language: Option<String>,
+    // Redact only these entity types (all supported ones when unset)
+    pub entities: Option<Vec<String>>,
+    // Leave detections scoring below this in place
+    pub score_threshold: Option<f32>,
+    pub protected_spans: Option<Vec<ByteSpan>>,
+    pub force_redact_spans: Option<Vec<ByteSpan>>,
+    pub keep_rules: Option<Vec<KeepRule>>,
+    // Recognizers and literal terms for identifiers the backend does not know
+    pub custom_patterns: Option<Vec<CustomPattern>>,
+    pub deny_list: Option<Vec<String>>,
+    pub relay: Option<RelayEnvelope>,
+    pub acl: Option<FileAcl>,
+    pub ciphertext_sha256: Option<String>,
+    pub plaintext_sha256: Option<String>,
+    // Client's own identifier for the artifact, unique within the caller's tenant
+    pub external_id: Option<String>,
+    // Purge the redacted file this many seconds after upload
+    pub ttl_seconds: Option<u64>,
+    // Plaintext bytes per sealed chunk of a streamed upload
+    pub chunk_size: Option<usize>,
+    // JSON and CSV uploads have only their string values redacted
+    #[serde(default)]
+    pub content_type: ContentType,
+    // `pdf`, `docx` or their MIME types; the plaintext's magic bytes are checked when unset
+    pub document_type: Option<String>,
+    // How the plaintext was compressed before it was encrypted
+    #[serde(default)]
+    pub compression: Compression,
+    // JSON paths or CSV columns to redact, instead of every string value
+    pub structured_fields: Option<Vec<String>>,
+    // Time allowed for the redaction backend, retries included, instead of the
+    // policy's `analyze` budget
+    pub backend_timeout_ms: Option<u64>,
+    // Fail, or hold for review, output the analyzer was unsure of; see `StrictPolicy`
+    #[serde(default)]
+    pub strict: bool,
+    // Keep the plaintext, sealed to the service key, so `reprocess_file` can redact it
+    // again with other settings
+    #[serde(default)]
+    pub retain_original: bool,
+    #[serde(default)]
+    pub retention: Retention,
+    #[serde(default)]
+    pub response_mode: ResponseMode,
+    // Where to POST a signed notice once the upload is redacted or has failed
+    pub callback_url: Option<String>,
+    // Token of an upload registered ahead of time, whose constraints the upload must meet
+    pub expectation: Option<String>,
+    // Set by the service when it queues the upload, so the file id is known up front
+    #[serde(skip)]
+    pub file_id: Option<String>,
+}
+
+// Settings a retained original is redacted with again. Unset ones take their defaults,
+// not the source file's.
+#[derive(Default, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ReprocessRequest {
+    pub redaction_strategy: Option<String>,
+    pub hash_length: Option<usize>,
+    pub language: Option<String>,
+    pub entities: Option<Vec<String>>,
+    pub score_threshold: Option<f32>,
+    pub keep_rules: Option<Vec<KeepRule>>,
+    pub custom_patterns: Option<Vec<CustomPattern>>,
+    pub deny_list: Option<Vec<String>>,
+    pub structured_fields: Option<Vec<String>>,
+    pub file_name: Option<String>,
+    pub external_id: Option<String>,
+    pub ttl_seconds: Option<u64>,
+    #[serde(default)]
+    pub strict: bool,
+    #[serde(default)]
+    pub response_mode: ResponseMode,
+}
+
+// What `analyze_upload` found, without anything being stored
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AnalysisResponse {
+    pub backend: String,
+    pub entities: BTreeMap<String, usize>,
+    pub detections: Vec<AnalyzedEntity>,
+    // Whether the snippets are masked
+    pub masked: bool,
+}
+
+// One detection with the text it covers; byte offsets into the plaintext
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AnalyzedEntity {
+    pub entity_type: String,
+    pub start: usize,
+    pub end: usize,
+    pub score: f64,
+    pub snippet: String,
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UploadResponse {
+    pub file_id: String,
+    pub filename: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relay: Option<RelayIdentities>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    // Entity counts; the full report is at `GET /files/:file_id/report`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report_summary: Option<ReportSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub review_hold: Option<ReviewHold>,
+    // Format of a PDF or DOCX upload, whose extracted text was redacted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document_format: Option<DocumentFormat>,
+    // Language the upload was redacted in, as the request named it or as detected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    // Unix time the file expires, for uploads with a `ttl_seconds`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    // Deprecated modes the upload used, with the dates they stop being accepted
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deprecations: Vec<Deprecation>,
+    // Set when the upload matched a stored one and got its file instead of being redacted again
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub deduplicated: bool,
+    // Hex SHA-256 of the redacted text, as txt downloads serve it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_sha256: Option<String>,
+    // The redacted content, for `inline` uploads
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    // The redacted content encrypted, for `inline_encrypted` uploads and those with `retention: "none"`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<EncryptedDownload>,
+    // For adapters (metrics, logging, profiling); not part of the response body
+    #[serde(skip)]
+    pub report: Option<RedactionReport>,
+    #[serde(skip)]
+    pub profile: UploadProfile,
+}
+
+// Where an upload spent its time, plus the sizes involved
+#[derive(Clone, Debug, Default, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UploadProfile {
+    pub total_ms: f64,
+    // In pipeline order
+    pub stages: Vec<StageTiming>,
+    pub backend: String,
+    pub ciphertext_bytes: usize,
+    pub plaintext_bytes: usize,
+    pub redacted_bytes: usize,
+    // Segments sent to the analyzer after span resolution
+    pub analyzed_chunks: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct StageTiming {
+    pub stage: &'static str,
+    pub ms: f64,
+}
+
+impl UploadProfile {
+    // Record the stage that ran since `since`, returning the start of the next one. Upload
+    // jobs also report it as it finishes.
+    pub fn record(&mut self, stage: &'static str, since: Instant) -> Instant {
+        let now = Instant::now();
+        let ms = now.duration_since(since).as_secs_f64() * 1000.0;
+        chunking::stage_done(stage, ms);
+        self.stages.push(StageTiming { stage, ms });
+        now
+    }
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ReportMatch {
+    pub file_id: String,
+    pub filename: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    pub created_at: u64,
+    pub report: RedactionReport,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub review_hold: Option<ReviewHold>,
+}
+
+// Filters of `list_files`
+#[derive(Debug, Default)]
+pub struct FileFilter {
+    pub name_prefix: Option<String>,
+    // Unix seconds; only files uploaded after it
+    pub created_after: Option<u64>,
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct FileListing {
+    pub file_id: String,
+    pub filename: String,
+    pub size: usize,
+    // Unset for files stored before strategies were recorded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    pub created_at: u64,
+    // Set for files moved to the cold tier, which take longer to reach
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cold_tier: Option<ColdTier>,
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct FileList {
+    pub files: Vec<FileListing>,
+    // Matching files across all pages
+    pub total: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_offset: Option<usize>,
+}
+
+pub struct FilePreview {
+    pub content: String,
+    pub total_size: usize,
+    pub truncated: bool,
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ExternalIdMatch {
+    pub file_id: String,
+    pub external_id: String,
+    pub filename: String,
+    pub size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub review_hold: Option<ReviewHold>,
+}
+
+pub struct DownloadedFile {
+    pub file_name: String,
+    pub content: String,
+    pub relay: Option<RelayIdentities>,
+    pub session_key: Option<SecretBytes>,
+    pub document_format: Option<DocumentFormat>,
+    pub manifest: Option<ProcessingManifest>,
+}
+
+// Redacted content encrypted for the client, so it never leaves the service in clear
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct EncryptedDownload {
+    pub file_id: String,
+    pub filename: String,
+    pub algorithm: &'static str,
+    pub encrypted_data: String,
+    pub nonce: String,
+    // Hex SHA-256 of the content before encryption, to check once it is decrypted
+    pub content_sha256: String,
+}
+
+// Services an upload runs through
+pub struct UploadContext<'a> {
+    pub crypto: &'a CryptoService,
+    pub redactor: &'a RedactorService,
+    pub relays: &'a RelayRegistry,
+    pub storage: &'a RwLock<Box<dyn Storage>>,
+    pub policy: &'a RedactionPolicy,
+    pub sessions: &'a SessionManager,
+    pub deprecations: &'a ProtocolDeprecations,
+    // What a compressed plaintext may inflate to
+    pub max_decompressed_bytes: usize,
+    // The admin-configurable defaults as the request started
+    pub settings: Arc<RuntimeSettings>,
+}
+
+// An upload that is decrypted and redacted, for `store_upload`
+pub struct RedactedUpload {
+    pub file_id: String,
+    pub strategy: String,
+    pub content: String,
+    pub report: Option<RedactionReport>,
+    pub pseudonyms: Option<SealedPseudonyms>,
+    pub original: Option<RetainedOriginal>,
+    pub session_key: SecretBytes,
+    pub relay: Option<RelayIdentities>,
+    pub document_format: Option<DocumentFormat>,
+    pub profile: UploadProfile,
+    // When processing began, for the profile's total
+    pub started: Instant,
+    // Kept with the file so identical uploads can reuse it; see `content_key`
+    pub content_key: Option<String>,
+}
+
+pub fn handshake(crypto: &CryptoService) -> Result<HandshakeResponse, OperationError> {
+    let public_key = crypto.get_public_key().map_err(|e| {
+        OperationError::new(ErrorKind::Internal, format!("Failed to get public key: {}", e))
+    })?;
+
+    Ok(HandshakeResponse {
+        public_key,
+        kid: crypto.key_id(),
+        algorithm: "RSA-2048",
+        ciphers: PayloadCipher::ALL.iter().map(PayloadCipher::name).collect(),
+        signing_key: crypto.signing_public_key(),
+        signing_key_signature: crypto.endorse_signing_key(),
+        attestation: None,
+        session: None,
+    })
+}
+
+// Verify, decrypt, redact and store one upload
+pub async fn process_upload(
+    context: &UploadContext<'_>,
+    caller: &Caller,
+    mut request: UploadRequest,
+) -> Result<UploadResponse, OperationError> {
+    let started = Instant::now();
+    apply_defaults(&context.settings, &mut request);
+    validate_upload(context, caller, &request).await?;
+
+    // Async uploads are told their file id up front, so they cannot take another's
+    let assigned = request.file_id.is_some();
+    let file_id = request.file_id.take().unwrap_or_else(new_file_id);
+
+    info!("Processing upload for file_id: {}", file_id);
+
+    // JSON uploads send base64 `encrypted_data` and binary ones the raw bytes. Relay
+    // signatures cover the ciphertext as sent; everything else works on the bytes.
+    let raw = request.ciphertext.take();
+    let sent = raw.as_deref().unwrap_or(request.encrypted_data.as_bytes());
+    let ciphertext = match &raw {
+        Some(raw) => Ok(Cow::Borrowed(raw.as_slice())),
+        None => BASE64.decode(&request.encrypted_data)
+            .map(Cow::Owned)
+            .map_err(|e| anyhow::anyhow!("Invalid base64: {}", e)),
+    };
+
+    let mut profile = UploadProfile {
+        ciphertext_bytes: sent.len(),
+        ..UploadProfile::default()
+    };
+    let mark = profile.record("validation", started);
+
+    // Verify the relay layer and the client's inner integrity tag before unwrapping
+    let relay_identities = verify_relay(context, &request, sent, &file_id)?;
+    let mark = profile.record("relay_verification", mark);
+
+    let timeouts = &context.policy.stage_timeouts;
+    let session_key = recover_session_key(context, caller, &request, &file_id).await?;
+    let decryption = mark;
+    let mark = profile.record("session_key", mark);
+
+    let Plaintext { text: decrypted_content, document_format, metadata } = decrypt_payload(context, &request, ciphertext, &session_key, &file_id).await?;
+    overran(Stage::Decrypt, timeouts.budget(Stage::Decrypt), decryption.elapsed())?;
+    let mark = profile.record("decryption", mark);
+    profile.plaintext_bytes = decrypted_content.len();
+    request.language = Some(resolve_language(context, &request, &decrypted_content)?);
+
+    let strategy = request.redaction_strategy.clone().unwrap_or_else(|| "replace".to_string());
+    let content_key = match !assigned && reuses_output(context.policy, caller, &request) {
+        true => Some(content_key(context, caller, &request, &strategy, &decrypted_content)),
+        false => None,
+    };
+    if let Some(content_key) = &content_key {
+        if let Some(response) = reuse_stored(context, caller, &request, content_key, &session_key, profile.clone(), started).await? {
+            return Ok(response);
+        }
+    }
+    let original = match request.retain_original {
+        true => Some(retain(context, &file_id, &decrypted_content, request.content_type)?),
+        false => None,
+    };
+    let (redacted_content, report, pseudonyms, analysis) = redact_plaintext(context, caller, &request, &file_id, &decrypted_content, metadata, &mut profile).await?;
+    overran(Stage::Anonymize, timeouts.budget(Stage::Anonymize), mark.elapsed().saturating_sub(analysis))?;
+    profile.record("redaction", mark);
+    profile.redacted_bytes = redacted_content.len();
+
+    store_upload(context, caller, request, RedactedUpload {
+        file_id,
+        strategy,
+        content: redacted_content,
+        report,
+        pseudonyms,
+        original,
+        session_key,
+        relay: relay_identities,
+        document_format,
+        profile,
+        started,
+        content_key,
+    })
+    .await
+}
+
+// Uploads that only describe their content may take the output of an identical one the
+// same principal uploaded. Anonymous uploads, and those asking for a file of their own,
+// with an external id, ACL, TTL, relay or retained original, or for nothing stored, are
+// always redacted.
+fn reuses_output(policy: &RedactionPolicy, caller: &Caller, request: &UploadRequest) -> bool {
+    policy.deduplication == Deduplication::Reuse
+        && caller.principal.is_some()
+        && request.retention == Retention::Stored
+        && request.external_id.is_none()
+        && request.acl.is_none()
+        && request.ttl_seconds.is_none()
+        && request.relay.is_none()
+        && !request.retain_original
+}
+
+// Digest of a plaintext together with who uploaded it and everything that decides how
+// it is redacted: the policy version, the backend and the request's redaction settings.
+// A stored file keeps its uploader's session key and ACL, so it is never handed to
+// anyone else.
+fn content_key(context: &UploadContext<'_>, caller: &Caller, request: &UploadRequest, strategy: &str, plaintext: &str) -> String {
+    let settings = serde_json::json!({
+        "principal": caller.principal,
+        "tenant": caller.tenant,
+        "policy": context.policy.version,
+        "backend": context.redactor.backend_name(),
+        "strategy": strategy,
+        "hash_length": request.hash_length,
+        "language": request.language,
+        "entities": request.entities,
+        "score_threshold": request.score_threshold,
+        "protected_spans": request.protected_spans,
+        "force_redact_spans": request.force_redact_spans,
+        "keep_rules": request.keep_rules,
+        "custom_patterns": request.custom_patterns,
+        "deny_list": request.deny_list,
+        "content_type": request.content_type,
+        "document_type": request.document_type,
+        "structured_fields": request.structured_fields,
+        "strict": request.strict,
+    });
+    // Holds a copy of the plaintext, so it is zeroized too
+    let mut material = Zeroizing::new(settings.to_string().into_bytes());
+    material.push(0);
+    material.extend_from_slice(plaintext.as_bytes());
+    manifest::content_digest(&material)
+}
+
+// The response for an upload identical to a stored file the caller uploaded, if any.
+// Nothing new is stored or counted; the caller gets the file that is already there.
+async fn reuse_stored(
+    context: &UploadContext<'_>,
+    caller: &Caller,
+    request: &UploadRequest,
+    content_key: &str,
+    session_key: &[u8],
+    mut profile: UploadProfile,
+    started: Instant,
+) -> Result<Option<UploadResponse>, OperationError> {
+    let mark = Instant::now();
+    let storage = context.storage.read().await;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let stored = storage.find_by_content(caller.tenant.as_deref(), content_key)
+        .into_iter()
+        .filter_map(|file_id| storage.get_metadata(file_id).map(|metadata| (file_id, metadata)))
+        .find(|(_, metadata)| {
+            !metadata.is_expired(now)
+                && metadata.owner.is_some()
+                && metadata.owner == caller.principal
+                && acl::is_allowed(metadata.acl.as_ref(), metadata.owner.as_deref(), caller, AclOperation::Download)
+        });
+    let Some((file_id, metadata)) = stored else {
+        return Ok(None);
+    };
+
+    // Held output is only handed out once it is released
+    let (content, output) = match metadata.review_hold {
+        Some(_) => (None, None),
+        None => inline_output(context, request.response_mode, file_id, &metadata.file_name, &metadata.content, session_key)?,
+    };
+    profile.record("deduplication", mark);
+    profile.total_ms = started.elapsed().as_secs_f64() * 1000.0;
+    info!("Upload is identical to file_id {}; returning it instead of redacting again", file_id);
+
+    Ok(Some(UploadResponse {
+        file_id: file_id.to_string(),
+        filename: metadata.file_name.clone(),
+        message: "An identical upload was already redacted; its file is returned".to_string(),
+        relay: None,
+        external_id: metadata.external_id.clone(),
+        report_summary: metadata.report.as_ref().map(|report| ReportSummary {
+            max_severity: context.policy.max_severity(report),
+            ..report.summary()
+        }),
+        review_hold: metadata.review_hold.clone(),
+        document_format: metadata.document_format,
+        language: metadata.language.clone(),
+        expires_at: metadata.expires_at(),
+        deprecations: context.deprecations.used_by(request).into_iter().cloned().collect(),
+        deduplicated: true,
+        content_sha256: Some(manifest::content_digest(metadata.content.as_bytes())),
+        content,
+        output,
+        report: None,
+        profile,
+    }))
+}
+
+// Redact a plaintext as the request asks: extract, redact structured values or redact
+// the text. Also returns the time spent in the backend.
+async fn redact_plaintext(
+    context: &UploadContext<'_>,
+    caller: &Caller,
+    request: &UploadRequest,
+    file_id: &str,
+    decrypted_content: &str,
+    metadata: Option<ScrubbedMetadata>,
+    profile: &mut UploadProfile,
+) -> Result<(String, Option<RedactionReport>, Option<SealedPseudonyms>, Duration), OperationError> {
+    let strategy = request.redaction_strategy.as_deref().unwrap_or("replace");
+    let language = request.language.as_deref().unwrap_or(context.redactor.languages().default_language());
+    let filter = EntityFilter { entities: request.entities.as_deref(), score_threshold: request.score_threshold, language: Some(language) };
+    let custom = custom_rules(request)?;
+    let hashing = hash_masking(context.crypto, caller, request)?;
+    let options = RedactionOptions {
+        strategy,
+        tenant: caller.tenant.as_deref(),
+        language,
+        pipelines: context.policy.pipelines_for(caller.tenant.as_deref()),
+        filter,
+        bidi: context.policy.bidi,
+        custom: custom.as_ref(),
+        boundaries: context.policy.boundaries(),
+        hashing: hashing.as_ref(),
+    };
+    let redaction_failed = |e: anyhow::Error| redaction_error(file_id, e);
+    let budget = request.backend_timeout_ms.map_or(context.policy.stage_timeouts.budget(Stage::Analyze), Duration::from_millis);
+    // Time in the backend, so the rest of the redaction counts against `Anonymize`
+    let mut analysis = Duration::ZERO;
+    // The tokens' originals are stored sealed to the service key, for `unredact_file`
+    let seal = |pseudonyms: PseudonymMap| {
+        context.crypto.get_public_key()
+            .and_then(|public_key| pseudonyms.seal(&public_key, file_id))
+            .map_err(|e| {
+                error!("Failed to seal pseudonyms of file_id {}: {}", file_id, e);
+                OperationError::new(ErrorKind::Internal, "Failed to store the pseudonyms")
+            })
+    };
+
+    // Template extraction keeps only the selected fields and skips analysis entirely
+    let (redacted_content, report, pseudonyms) = if strategy == "extract" {
+        let extractor = TemplateExtractor::new(request.keep_rules.as_deref().unwrap_or_default())
+            .map_err(|e| {
+                warn!("Invalid keep rules for file_id {}: {}", file_id, e);
+                OperationError::new(ErrorKind::BadRequest, format!("Invalid keep rules: {}", e))
+            })?;
+        profile.backend = "template".to_string();
+        (extractor.extract(&bidi::sanitize(decrypted_content, context.policy.bidi).text), None, None)
+    } else if request.content_type != ContentType::Text {
+        // Each string value is redacted on its own and written back in place
+        let selectors = request.structured_fields.as_deref().unwrap_or_default();
+        let fields = structured::fields(decrypted_content, request.content_type, selectors).map_err(|e| {
+            warn!("Invalid structured content for file_id {}: {}", file_id, e);
+            OperationError::new(ErrorKind::BadRequest, e.to_string()).with_code("invalid_structured_content")
+        })?;
+        profile.backend = context.redactor.backend_name().to_string();
+        profile.analyzed_chunks = fields.iter().filter(|field| !field.value.trim().is_empty()).count();
+
+        let values: Vec<&str> = fields.iter().map(|field| field.value.as_str()).collect();
+        let analyzing = Instant::now();
+        let (outputs, mut report, pseudonyms) = within(Stage::Analyze, budget, context.redactor.redact_fields(&values, &options)).await
+            .map_err(redaction_failed)?;
+        analysis = analyzing.elapsed();
+        structured::map_detections(&fields, &mut report.detections);
+        let sealed = match strategy == PSEUDONYMIZE {
+            true => Some(seal(pseudonyms)?),
+            false => None,
+        };
+        (structured::splice(decrypted_content, &fields, &outputs, request.content_type), Some(report), sealed)
+    } else {
+        // Resolve client-provided spans against the plaintext
+        let segments = spans::resolve_segments(
+            decrypted_content,
+            request.protected_spans.as_deref().unwrap_or_default(),
+            request.force_redact_spans.as_deref().unwrap_or_default(),
+        )
+        .map_err(|e| {
+            warn!("Invalid spans for file_id {}: {}", file_id, e);
+            OperationError::new(ErrorKind::BadRequest, format!("Invalid spans: {}", e))
+        })?;
+
+        profile.backend = context.redactor.backend_name().to_string();
+        profile.analyzed_chunks = segments.iter()
+            .filter(|segment| matches!(segment, spans::Segment::Analyze(text) if !text.trim().is_empty()))
+            .count();
+
+        let analyzing = Instant::now();
+        if strategy == PSEUDONYMIZE {
+            let (redacted, report, pseudonyms) = within(Stage::Analyze, budget, context.redactor.pseudonymize_segments(&segments, &options)).await
+                .map_err(redaction_failed)?;
+            analysis = analyzing.elapsed();
+            (redacted, Some(report), Some(seal(pseudonyms)?))
+        } else {
+            let (redacted, report) = within(Stage::Analyze, budget, context.redactor.redact_segments_with_report(&segments, &options)).await
+                .map_err(redaction_failed)?;
+            analysis = analyzing.elapsed();
+            (redacted, Some(report), None)
+        }
+    };
+
+    // Positions are into the plaintext, so the heatmap is built before it is dropped
+    let report = report.map(|report| RedactionReport {
+        heatmap: Some(Heatmap::build(decrypted_content, &report.detections, Heatmap::lines_per_bucket_from_env())),
+        metadata,
+        ..report
+    });
+    Ok((redacted_content, report, pseudonyms, analysis))
+}
+
+// Seal an upload's plaintext for `reprocess_file`
+fn retain(context: &UploadContext<'_>, file_id: &str, plaintext: &str, content_type: ContentType) -> Result<RetainedOriginal, OperationError> {
+    RetainedOriginal::seal(context.crypto, file_id, plaintext, content_type)
+        .map_err(|e| {
+            error!("Failed to seal the original of file_id {}: {}", file_id, e);
+            OperationError::new(ErrorKind::Internal, "Failed to retain the original")
+        })
+}
+
+// Redact a file's retained original again with other settings, stored as a new file
+// under the source's session key, with its original retained in turn. Other settings
+// can reveal what the source left redacted, so besides `upload` the caller needs the
+// `unredact` scope and the source's `unredact` permission.
+pub async fn reprocess_file(
+    context: &UploadContext<'_>,
+    caller: &Caller,
+    source_id: &str,
+    request: ReprocessRequest,
+) -> Result<UploadResponse, OperationError> {
+    let started = Instant::now();
+    if !caller.allows(Scope::Unredact) {
+        return Err(OperationError::new(ErrorKind::Forbidden, "This credential may not reprocess files").with_code("scope_denied"));
+    }
+    let (original, session_key, document_format) = {
+        let storage = context.storage.read().await;
+        let Some(metadata) = find_file(storage.as_ref(), caller, source_id) else {
+            if storage.was_expired(source_id) {
+                return Err(file_expired());
+            }
+            return Err(OperationError::new(ErrorKind::NotFound, "File not found"));
+        };
+        if !acl::is_allowed(metadata.acl.as_ref(), metadata.owner.as_deref(), caller, AclOperation::Unredact) {
+            warn!("Reprocessing of file_id {} denied by ACL", source_id);
+            return Err(OperationError::new(ErrorKind::Forbidden, "Access denied"));
+        }
+        if metadata.is_expired(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()) {
+            return Err(file_expired());
+        }
+        let original = retained_original(metadata)?.clone();
+        let session_key = metadata.session_key.clone()
+            .ok_or_else(|| OperationError::new(ErrorKind::Conflict, "File has no session key to store its reprocessed output under"))?;
+        (original, session_key, metadata.document_format)
+    };
+
+    let mut upload = UploadRequest {
+        file_name: request.file_name,
+        redaction_strategy: request.redaction_strategy,
+        hash_length: request.hash_length,
+        language: request.language,
+        entities: request.entities,
+        score_threshold: request.score_threshold,
+        keep_rules: request.keep_rules,
+        custom_patterns: request.custom_patterns,
+        deny_list: request.deny_list,
+        external_id: request.external_id,
+        ttl_seconds: request.ttl_seconds,
+        content_type: original.content_type,
+        structured_fields: request.structured_fields,
+        strict: request.strict,
+        retain_original: true,
+        response_mode: request.response_mode,
+        ..UploadRequest::default()
+    };
+    apply_defaults(&context.settings, &mut upload);
+    // The upload checks, less those of the envelope, which reprocessing has none of
+    if !caller.allows(Scope::Upload) {
+        return Err(OperationError::new(ErrorKind::Forbidden, "This credential may not upload files").with_code("scope_denied"));
+    }
+    validate_options(&upload)?;
+    validate_language(context, &upload)?;
+    validate_tenant_limits(context.policy, caller, upload.redaction_strategy.as_deref(), upload.ttl_seconds)?;
+    if upload.response_mode != ResponseMode::Reference && !context.policy.downloads_allowed(caller.tenant.as_deref()) {
+        return Err(downloads_disabled());
+    }
+    if upload.ttl_seconds == Some(0) {
+        return Err(OperationError::new(ErrorKind::BadRequest, "ttl_seconds must be positive"));
+    }
+    if let Some(external_id) = &upload.external_id {
+        validate_external_id(external_id)?;
+    }
+
+    let file_id = new_file_id();
+    info!("Reprocessing file_id {} as file_id {}", source_id, file_id);
+    let plaintext = original.open(context.crypto, source_id).map_err(|e| {
+        error!("Failed to open the original of file_id {}: {}", source_id, e);
+        OperationError::new(ErrorKind::Internal, "Failed to open the retained original")
+    })?;
+    upload.language = Some(resolve_language(context, &upload, &plaintext)?);
+    let mut profile = UploadProfile { plaintext_bytes: plaintext.len(), ..UploadProfile::default() };
+    let mark = profile.record("validation", started);
+    let original = retain(context, &file_id, &plaintext, original.content_type)?;
+
+    let strategy = upload.redaction_strategy.clone().unwrap_or_else(|| "replace".to_string());
+    let (content, report, pseudonyms, analysis) = redact_plaintext(context, caller, &upload, &file_id, &plaintext, None, &mut profile).await?;
+    overran(Stage::Anonymize, context.policy.stage_timeouts.budget(Stage::Anonymize), mark.elapsed().saturating_sub(analysis))?;
+    profile.record("redaction", mark);
+    profile.redacted_bytes = content.len();
+
+    let mut response = store_upload(context, caller, upload, RedactedUpload {
+        file_id,
+        strategy,
+        content,
+        report,
+        pseudonyms,
+        original: Some(original),
+        session_key,
+        relay: None,
+        document_format,
+        profile,
+        started,
+        content_key: None,
+    })
+    .await?;
+    // No envelope was sent, so no protocol mode was used
+    response.deprecations.clear();
+    Ok(response)
+}
+
+// Decrypt an upload and run only the backend's detection phase. Nothing is stored; the
+// plaintext is dropped once the snippets are cut from it.
+pub async fn analyze_upload(
+    context: &UploadContext<'_>,
+    caller: &Caller,
+    mut request: UploadRequest,
+    mask_snippets: bool,
+) -> Result<AnalysisResponse, OperationError> {
+    apply_defaults(&context.settings, &mut request);
+    validate_request(context, caller, &request)?;
+    let custom = custom_rules(&request)?;
+    // Never stored, so the id only ties together this dry run's log lines
+    let analysis_id = new_file_id();
+    info!("Analyzing upload {} without storing it", analysis_id);
+
+    let raw = request.ciphertext.take();
+    let sent = raw.as_deref().unwrap_or(request.encrypted_data.as_bytes());
+    let ciphertext = match &raw {
+        Some(raw) => Ok(Cow::Borrowed(raw.as_slice())),
+        None => BASE64.decode(&request.encrypted_data)
+            .map(Cow::Owned)
+            .map_err(|e| anyhow::anyhow!("Invalid base64: {}", e)),
+    };
+    verify_relay(context, &request, sent, &analysis_id)?;
+    let session_key = recover_session_key(context, caller, &request, &analysis_id).await?;
+    let plaintext = decrypt_payload(context, &request, ciphertext, &session_key, &analysis_id).await?.text;
+    let language = resolve_language(context, &request, &plaintext)?;
+
+    let filter = EntityFilter { entities: request.entities.as_deref(), score_threshold: request.score_threshold, language: Some(&language) };
+    let budget = request.backend_timeout_ms
+        .map_or(context.policy.stage_timeouts.budget(Stage::Analyze), Duration::from_millis);
+    // Structured content is analyzed field by field, as an upload would be
+    let fields = match request.content_type {
+        ContentType::Text => None,
+        content_type => {
+            let selectors = request.structured_fields.as_deref().unwrap_or_default();
+            Some(structured::fields(&plaintext, content_type, selectors).map_err(|e| {
+                OperationError::new(ErrorKind::BadRequest, e.to_string()).with_code("invalid_structured_content")
+            })?)
+        }
+    };
+    let segments = match &fields {
+        Some(fields) => fields.iter().map(|field| spans::Segment::Analyze(&field.value)).collect(),
+        None => spans::resolve_segments(
+            &plaintext,
+            request.protected_spans.as_deref().unwrap_or_default(),
+            request.force_redact_spans.as_deref().unwrap_or_default(),
+        )
+        .map_err(|e| OperationError::new(ErrorKind::BadRequest, format!("Invalid spans: {}", e)))?,
+    };
+    let mut detections = within(Stage::Analyze, budget, context.redactor.detect_segments(&segments, filter, context.policy.bidi, custom.as_ref(), context.policy.boundaries())).await
+        .map_err(|e| redaction_error(&analysis_id, e))?;
+    if let Some(fields) = &fields {
+        structured::map_detections(fields, &mut detections);
+    }
+
+    let mut entities = BTreeMap::new();
+    let detections = detections
+        .into_iter()
+        .map(|detection| {
+            *entities.entry(detection.entity_type.clone()).or_default() += 1;
+            let value = plaintext.get(detection.start..detection.end).unwrap_or_default();
+            let snippet = match mask_snippets {
+                true => "*".repeat(value.chars().count()),
+                false => value.to_string(),
+            };
+            AnalyzedEntity {
+                entity_type: detection.entity_type,
+                start: detection.start,
+                end: detection.end,
+                score: detection.score,
+                snippet,
+            }
+        })
+        .collect();
+
+    Ok(AnalysisResponse {
+        backend: context.redactor.backend_name().to_string(),
+        entities,
+        detections,
+        masked: mask_snippets,
+    })
+}
+
+// Redaction failures, telling a backend that is down or out of time from one that failed
+pub fn redaction_error(file_id: &str, e: anyhow::Error) -> OperationError {
+    warn!("Redaction failed for file_id {}: {}", file_id, e);
+    if e.downcast_ref::<BackendUnavailable>().is_some() {
+        return OperationError::new(ErrorKind::Unavailable, e.to_string()).with_code("backend_unavailable");
+    }
+    if let Some(deadline) = e.downcast_ref::<DeadlineExceeded>() {
+        return stage_timeout(deadline);
+    }
+    if e.downcast_ref::<ContractMismatch>().is_some() {
+        return OperationError::new(ErrorKind::BadGateway, e.to_string()).with_code("upstream_contract_mismatch");
+    }
+    OperationError::new(ErrorKind::Internal, format!("Redaction failed: {}", e)).with_code("redaction_failed")
+}
+
+pub fn stage_timeout(deadline: &DeadlineExceeded) -> OperationError {
+    OperationError::new(ErrorKind::Timeout, deadline.to_string()).with_code(deadline.stage.timeout_code())
+}
+
+// `work` cut short once it runs past its stage's budget
+pub async fn within<T, E: From<DeadlineExceeded>>(stage: Stage, budget: Duration, work: impl Future<Output = Result<T, E>>) -> Result<T, E> {
+    tokio::time::timeout(budget, work)
+        .await
+        .unwrap_or_else(|_| Err(DeadlineExceeded { stage, budget }.into()))
+}
+
+// Stages that never yield cannot be cut short, so they are failed once they are done
+fn overran(stage: Stage, budget: Duration, elapsed: Duration) -> Result<(), OperationError> {
+    match elapsed > budget {
+        true => {
+            warn!("The {} stage took {} ms, over its budget of {} ms", stage.name(), elapsed.as_millis(), budget.as_millis());
+            Err(stage_timeout(&DeadlineExceeded { stage, budget }))
+        }
+        false => Ok(()),
+    }
+}
+
+pub fn new_file_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+// Checks made before any work on an upload: the caller's scope, the options, and
+// that a client `external_id` is free
+// The configured strategy, entity types and score threshold, where the upload sets none
+pub fn apply_defaults(settings: &RuntimeSettings, request: &mut UploadRequest) {
+    request.redaction_strategy.get_or_insert_with(|| settings.default_strategy.clone());
+    if request.entities.is_none() {
+        request.entities = settings.default_entities.clone();
+    }
+    request.score_threshold = request.score_threshold.or(settings.default_score_threshold);
+}
+
+pub async fn validate_upload(context: &UploadContext<'_>, caller: &Caller, request: &UploadRequest) -> Result<(), OperationError> {
+    validate_request(context, caller, request)?;
+    // Inline output is a download by another route
+    let inline = request.response_mode != ResponseMode::Reference || request.retention == Retention::None;
+    if inline && !context.policy.downloads_allowed(caller.tenant.as_deref()) {
+        return Err(downloads_disabled());
+    }
+    if request.retention == Retention::None {
+        // These only mean something for a stored file
+        let unsupported = [
+            ("ttl_seconds", request.ttl_seconds.is_some()),
+            ("external_id", request.external_id.is_some()),
+            ("acl", request.acl.is_some()),
+            ("the pseudonymize strategy", request.redaction_strategy.as_deref() == Some(PSEUDONYMIZE)),
+            ("retain_original", request.retain_original),
+        ];
+        if let Some((option, _)) = unsupported.iter().find(|(_, set)| *set) {
+            return Err(OperationError::new(ErrorKind::BadRequest, format!("Uploads with retention none do not support {}", option)));
+        }
+    }
+    if request.ttl_seconds == Some(0) {
+        return Err(OperationError::new(ErrorKind::BadRequest, "ttl_seconds must be positive"));
+    }
+    let storage = context.storage.read().await;
+    if let Some(external_id) = &request.external_id {
+        validate_external_id(external_id)?;
+        if storage.find_by_external_id(caller.tenant.as_deref(), external_id).is_some() {
+            return Err(external_id_conflict(external_id));
+        }
+    }
+    // Checked again once the output's size is known
+    if request.retention == Retention::Stored {
+        check_quota(storage.as_ref(), context.policy, caller.tenant.as_deref(), 0)?;
+    }
+    Ok(())
+}
+
+// The checks an upload and its dry run share: nothing here depends on what is stored
+fn validate_request(context: &UploadContext<'_>, caller: &Caller, request: &UploadRequest) -> Result<(), OperationError> {
+    if !caller.allows(Scope::Upload) {
+        return Err(OperationError::new(ErrorKind::Forbidden, "This credential may not upload files").with_code("scope_denied"));
+    }
+    context.deprecations.check(request)?;
+    validate_payload(context, request)?;
+    validate_options(request)?;
+    validate_language(context, request)?;
+    validate_tenant_limits(context.policy, caller, request.redaction_strategy.as_deref(), request.ttl_seconds)
+}
+
+// The language an upload names, one of the supported ones or `auto`
+fn validate_language(context: &UploadContext<'_>, request: &UploadRequest) -> Result<(), OperationError> {
+    context.redactor.languages().check(request.language.as_deref()).map_err(unsupported_language)
+}
+
+// The language the upload's plaintext is redacted in, detected when it asks for that
+pub fn resolve_language(context: &UploadContext<'_>, request: &UploadRequest, plaintext: &str) -> Result<String, OperationError> {
+    context.redactor.languages().resolve(request.language.as_deref(), plaintext).map_err(unsupported_language)
+}
+
+pub fn unsupported_language(e: anyhow::Error) -> OperationError {
+    OperationError::new(ErrorKind::BadRequest, e.to_string()).with_code("unsupported_language")
+}
+
+// The strategy and TTL against the caller's tenant limits
+fn validate_tenant_limits(policy: &RedactionPolicy, caller: &Caller, strategy: Option<&str>, ttl_seconds: Option<u64>) -> Result<(), OperationError> {
+    let Some(limits) = policy.limits_for(caller.tenant.as_deref()) else {
+        return Ok(());
+    };
+    let strategy = strategy.unwrap_or("replace");
+    if !limits.allows_strategy(strategy) {
+        return Err(OperationError::new(
+            ErrorKind::Forbidden,
+            format!("This tenant may not use the {} strategy; it may use {}", strategy, limits.strategies.join(", ")),
+        )
+        .with_code("strategy_not_allowed"));
+    }
+    if let Some(max) = limits.max_ttl_seconds.filter(|max| ttl_seconds.is_some_and(|ttl| ttl > *max)) {
+        return Err(OperationError::new(ErrorKind::BadRequest, format!("ttl_seconds may be at most {} for this tenant", max))
+            .with_code("ttl_too_long"));
+    }
+    Ok(())
+}
+
+// Refuse to store `adding` more bytes, in one more file, for a tenant at its quota
+fn check_quota(storage: &dyn Storage, policy: &RedactionPolicy, tenant: Option<&str>, adding: usize) -> Result<(), OperationError> {
+    let Some(limits) = policy.limits_for(tenant).filter(|limits| limits.max_files.is_some() || limits.max_stored_bytes.is_some()) else {
+        return Ok(());
+    };
+    let (files, bytes) = storage.file_ids()
+        .iter()
+        .filter_map(|file_id| storage.get_metadata(file_id))
+        .filter(|metadata| metadata.tenant.as_deref() == tenant)
+        .fold((0, 0), |(files, bytes), metadata| (files + 1, bytes + metadata.size));
+    let over = match (limits.max_files, limits.max_stored_bytes) {
+        (Some(max), _) if files >= max => Some(("files", max)),
+        (_, Some(max)) if bytes + adding.max(1) > max => Some(("bytes", max)),
+        _ => None,
+    };
+    let Some((quota, limit)) = over else {
+        return Ok(());
+    };
+    warn!("Tenant {:?} is at its quota of {} stored {}", tenant, limit, quota);
+    Err(OperationError::new(
+        ErrorKind::Forbidden,
+        format!("This tenant has reached its quota of {} stored {} ({} files, {} bytes stored); delete files to make room", limit, quota, files, bytes),
+    )
+    .with_code("quota_exceeded"))
+}
+
+// Checks on the redaction settings alone, which reprocessing shares
+fn validate_options(request: &UploadRequest) -> Result<(), OperationError> {
+    if request.backend_timeout_ms.is_some_and(|ms| !(1..=MAX_BACKEND_TIMEOUT_MS).contains(&ms)) {
+        return Err(OperationError::new(
+            ErrorKind::BadRequest,
+            format!("backend_timeout_ms must be between 1 and {}", MAX_BACKEND_TIMEOUT_MS),
+        ));
+    }
+    hash_masking_length(request)?;
+    let filter = EntityFilter { entities: request.entities.as_deref(), score_threshold: request.score_threshold, language: None };
+    filter.validate().map_err(|e| {
+        OperationError::new(ErrorKind::BadRequest, e.to_string()).with_code("invalid_entity_filter")
+    })?;
+
+    if request.content_type != ContentType::Text {
+        let unsupported = [
+            ("the extract strategy", request.redaction_strategy.as_deref() == Some("extract")),
+            ("protected_spans", request.protected_spans.is_some()),
+            ("force_redact_spans", request.force_redact_spans.is_some()),
+        ];
+        if let Some((option, _)) = unsupported.iter().find(|(_, set)| *set) {
+            return Err(OperationError::new(ErrorKind::BadRequest, format!("Structured uploads do not support {}", option)));
+        }
+    } else if request.structured_fields.is_some() {
+        return Err(OperationError::new(ErrorKind::BadRequest, "structured_fields needs a json or csv content_type"));
+    }
+    if let Some(declared) = request.document_type.as_deref().filter(|declared| DocumentFormat::from_declared(declared).is_none()) {
+        return Err(OperationError::new(ErrorKind::BadRequest, format!("Unsupported document type: {}", declared))
+            .with_code("unsupported_document_type"));
+    }
+    Ok(())
+}
+
+// Hex digits the upload's `hash` surrogates keep; `hash_length` only goes with that strategy
+fn hash_masking_length(request: &UploadRequest) -> Result<usize, OperationError> {
+    let invalid = |message: String| OperationError::new(ErrorKind::BadRequest, message).with_code("invalid_hash_length");
+    if request.hash_length.is_some() && request.redaction_strategy.as_deref() != Some(HASH) {
+        return Err(invalid("hash_length needs the hash strategy".to_string()));
+    }
+    HashMasking::length(request.hash_length).map_err(|e| invalid(e.to_string()))
+}
+
+// Surrogates of a `hash` upload, under the caller's tenant's masking key
+pub fn hash_masking(crypto: &CryptoService, caller: &Caller, request: &UploadRequest) -> Result<Option<HashMasking>, OperationError> {
+    if request.redaction_strategy.as_deref() != Some(HASH) {
+        return Ok(None);
+    }
+    let length = hash_masking_length(request)?;
+    HashMasking::new(crypto.masking_key(caller.tenant.as_deref()), Some(length))
+        .map(Some)
+        .map_err(|e| OperationError::new(ErrorKind::BadRequest, e.to_string()).with_code("invalid_hash_length"))
+}
+
+// The request's own patterns and deny-list terms, compiled; None when it sends neither
+fn custom_rules(request: &UploadRequest) -> Result<Option<CustomRules>, OperationError> {
+    if request.custom_patterns.is_none() && request.deny_list.is_none() {
+        return Ok(None);
+    }
+    CustomRules::new(request.custom_patterns.as_deref().unwrap_or_default(), request.deny_list.as_deref().unwrap_or_default())
+        .map(|rules| Some(rules).filter(|rules| !rules.is_empty()))
+        .map_err(|e| OperationError::new(ErrorKind::BadRequest, e.to_string()).with_code("invalid_custom_pattern"))
+}
+
+// The relay identities of a relayed upload, once its envelope is verified
+fn verify_relay(
+    context: &UploadContext<'_>,
+    request: &UploadRequest,
+    sent: &[u8],
+    file_id: &str,
+) -> Result<Option<RelayIdentities>, OperationError> {
+    match &request.relay {
+        Some(_) if !context.relays.is_enabled() => Err(OperationError::new(
+            ErrorKind::BadRequest,
+            "Relay envelopes are not accepted by this service",
+        )),
+        Some(envelope) => match context.relays.verify(envelope, sent) {
+            Ok(identities) => {
+                info!(
+                    "Upload {} relayed by {} on behalf of client {}",
+                    file_id, identities.relay_id, identities.client_id
+                );
+                Ok(Some(identities))
+            }
+            Err(e) => {
+                warn!("Relay verification failed for file_id {}: {}", file_id, e);
+                Err(OperationError::new(
+                    ErrorKind::Unauthorized,
+                    format!("Relay verification failed: {}", e),
+                ))
+            }
+        },
+        None => Ok(None),
+    }
+}
+
+// The text of an upload, and for PDF and DOCX uploads their format and the embedded
+// metadata left out of the text
+struct Plaintext {
+    text: SecretText,
+    document_format: Option<DocumentFormat>,
+    metadata: Option<ScrubbedMetadata>,
+}
+
+// Decrypt an upload's ciphertext with its session key, checking the client's checksums.
+// The text of PDF and DOCX uploads is extracted and their metadata scrubbed.
+async fn decrypt_payload(
+    context: &UploadContext<'_>,
+    request: &UploadRequest,
+    ciphertext: anyhow::Result<Cow<'_, [u8]>>,
+    session_key: &[u8],
+    file_id: &str,
+) -> Result<Plaintext, OperationError> {
+    // Optional client checksums: SHA-256 of the ciphertext catches transport corruption,
+    // and SHA-256 of the plaintext is bound into decryption as AAD
+    let parse_digest = |digest: &Option<String>| {
+        digest.as_deref()
+            .map(crypto::parse_sha256_hex)
+            .transpose()
+            .map_err(|e| OperationError::new(ErrorKind::BadRequest, format!("Invalid checksum: {}", e)))
+    };
+    let ciphertext_sha256 = parse_digest(&request.ciphertext_sha256)?;
+    let plaintext_sha256 = parse_digest(&request.plaintext_sha256)?;
+    let cipher = PayloadCipher::parse(request.cipher.as_deref())
+        .map_err(|e| OperationError::new(ErrorKind::BadRequest, e.to_string()).with_code("unsupported_cipher"))?;
+
+    if let Some(expected) = ciphertext_sha256 {
+        let matches = ciphertext.as_ref()
+            .is_ok_and(|ciphertext| crypto::sha256(ciphertext) == expected);
+        if !matches {
+            warn!("Ciphertext checksum mismatch for file_id {}", file_id);
+            return Err(OperationError::new(
+                ErrorKind::Unprocessable,
+                "Ciphertext does not match ciphertext_sha256; it was corrupted in transit",
+            )
+            .with_code("ciphertext_checksum_mismatch"));
+        }
+    }
+
+    // Decrypt the file using the session key
+    let aad = plaintext_sha256.as_ref().map(|digest| digest.as_slice()).unwrap_or_default();
+    let decryption_failed = |e: anyhow::Error| {
+        warn!("File decryption failed for file_id {}: {}", file_id, e);
+        OperationError::new(ErrorKind::BadRequest, format!("File decryption failed: {}", e))
+            .with_code("decryption_failed")
+    };
+    let plaintext = match ciphertext {
+        Ok(ciphertext) => context.crypto.decrypt_payload_pooled(cipher, &ciphertext, session_key, request.nonce.as_deref(), aad).await,
+        Err(e) => Err(e),
+    };
+    let plaintext = plaintext.map_err(|e| crypto_saturated(&e).unwrap_or_else(|| decryption_failed(e)))?;
+    let plaintext = request.compression.decompress(plaintext, context.max_decompressed_bytes).map_err(|e| {
+        warn!("Decompression failed for file_id {}: {}", file_id, e);
+        match e.downcast_ref::<DecompressedTooLarge>() {
+            Some(_) => OperationError::new(ErrorKind::PayloadTooLarge, e.to_string()).with_code("decompressed_too_large"),
+            None => OperationError::new(ErrorKind::BadRequest, e.to_string()).with_code("decompression_failed"),
+        }
+    })?;
+
+    if plaintext_sha256.is_some_and(|expected| crypto::sha256(&plaintext) != expected) {
+        warn!("Plaintext checksum mismatch for file_id {}", file_id);
+        return Err(OperationError::new(
+            ErrorKind::Unprocessable,
+            "Decrypted content does not match plaintext_sha256",
+        )
+        .with_code("plaintext_checksum_mismatch"));
+    }
+
+    let format = document::detect(&plaintext, request.document_type.as_deref())
+        .map_err(|e| OperationError::new(ErrorKind::BadRequest, e.to_string()).with_code("unsupported_document_type"))?;
+    let Some(format) = format else {
+        let text = SecretText::from_utf8(plaintext).map_err(|e| decryption_failed(anyhow::anyhow!("Invalid UTF-8: {}", e)))?;
+        return Ok(Plaintext { text, document_format: None, metadata: None });
+    };
+    if request.content_type != ContentType::Text {
+        return Err(OperationError::new(ErrorKind::BadRequest, "Documents are redacted as text; leave content_type unset"));
+    }
+    let text = document::extract_text(format, &plaintext).map(SecretText::new).map_err(|e| {
+        warn!("Text extraction failed for file_id {}: {}", file_id, e);
+        OperationError::new(ErrorKind::Unprocessable, format!("Failed to extract the document's text: {}", e))
+            .with_code("document_extraction_failed")
+    })?;
+    info!("Extracted {} bytes of text from the {:?} upload {}", text.len(), format, file_id);
+    // The metadata is dropped with the rest of the file either way; this only reports it
+    let metadata = document::scrub_metadata(format, &plaintext)
+        .inspect_err(|e| warn!("Metadata scrub of file_id {} could not read the document: {}", file_id, e))
+        .ok();
+    Ok(Plaintext { text, document_format: Some(format), metadata })
+}
+
+// Uploads refused because the crypto pool's queue is full, to be retried shortly
+fn crypto_saturated(e: &anyhow::Error) -> Option<OperationError> {
+    let saturated = e.downcast_ref::<CryptoSaturated>()?;
+    warn!("{}", saturated);
+    Some(OperationError::new(ErrorKind::Unavailable, saturated.to_string()).with_code("crypto_saturated"))
+}
+
+// Shape checks on the envelope, so a malformed one fails before any key is unwrapped
+fn validate_payload(context: &UploadContext<'_>, request: &UploadRequest) -> Result<(), OperationError> {
+    let invalid = |message: String| OperationError::new(ErrorKind::BadRequest, message).with_code("invalid_payload");
+    // Streamed uploads send no `encrypted_data`, and binary ones send raw bytes instead
+    let data = &request.encrypted_data;
+    if request.ciphertext.is_none() && !data.is_empty() {
+        if !data.len().is_multiple_of(4) {
+            return Err(invalid("encrypted_data must be padded base64".to_string()));
+        }
+        if base64_len(data) < crypto::TAG_LEN {
+            return Err(invalid(format!("encrypted_data is shorter than the {}-byte authentication tag", crypto::TAG_LEN)));
+        }
+    }
+    // 12 bytes are 16 base64 characters, without padding
+    if request.nonce.as_ref().is_some_and(|nonce| nonce.len() != 16) {
+        return Err(invalid("nonce must be 12 bytes of base64".to_string()));
+    }
+    if let Some(wrapped) = &request.encrypted_session_key {
+        if !wrapped.len().is_multiple_of(4) || !context.crypto.fits_wrapped_key(base64_len(wrapped)) {
+            return Err(invalid("encrypted_session_key is not the size of a key wrapped to the service key".to_string()));
+        }
+    }
+    Ok(())
+}
+
+// Bytes that padded base64 of this length decodes to, without decoding it
+fn base64_len(encoded: &str) -> usize {
+    let padding = encoded.bytes().rev().take(2).filter(|byte| *byte == b'=').count();
+    (encoded.len() / 4 * 3).saturating_sub(padding)
+}
+
+// Recover an upload's session key: RSA-wrapped by the client, derived from a pre-shared
+// key, or negotiated for a session
+pub async fn recover_session_key(
+    context: &UploadContext<'_>,
+    caller: &Caller,
+    request: &UploadRequest,
+    file_id: &str,
+) -> Result<SecretBytes, OperationError> {
+    let session_key = match (&request.encrypted_session_key, &request.psk_id, &request.session_id) {
+        (Some(encrypted_session_key), None, None) => {
+            if let Some(key_id) = request.key_id.as_deref().filter(|key_id| !context.crypto.has_key(key_id)) {
+                return Err(OperationError::new(
+                    ErrorKind::BadRequest,
+                    format!("Unknown key_id {}; fetch the current key from /handshake", key_id),
+                )
+                .with_code("unknown_key_id"));
+            }
+            context.crypto.unwrap_session_key_pooled(encrypted_session_key, request.key_id.as_deref()).await
+        }
+        (None, Some(psk_id), None) => {
+            let salt = request.psk_salt.as_deref().unwrap_or_default();
+            context.crypto.derive_psk_session_key(psk_id, salt)
+        }
+        (None, None, Some(session_id)) => {
+            let key = context.sessions.upload_key(caller, session_id, request.nonce.as_deref()).map_err(|e| {
+                warn!("Session {} refused for file_id {}: {}", session_id, file_id, e);
+                session_error(e)
+            })?;
+            Ok(key)
+        }
+        _ => Err(anyhow::anyhow!("Provide exactly one of encrypted_session_key, psk_id or session_id")),
+    };
+    session_key.map_err(|e| {
+        if let Some(saturated) = crypto_saturated(&e) {
+            return saturated;
+        }
+        warn!("Session key decryption failed for file_id {}: {}", file_id, e);
+        OperationError::new(ErrorKind::BadRequest, format!("Session key decryption failed: {}", e))
+            .with_code("session_key_failed")
+    })
+}
+
+// Hold, store and account for a redacted upload
+pub async fn store_upload(
+    context: &UploadContext<'_>,
+    caller: &Caller,
+    request: UploadRequest,
+    upload: RedactedUpload,
+) -> Result<UploadResponse, OperationError> {
+    let RedactedUpload { file_id, strategy, content: redacted_content, report, pseudonyms, original, session_key, relay: relay_identities, document_format, mut profile, started, content_key } = upload;
+    let mark = Instant::now();
+
+    // Blocking rules withhold the artifact until a reviewer releases it
+    let mut review_hold = report.as_ref().and_then(|report| context.policy.evaluate(report));
+    // Strict uploads the analyzer was unsure of fail, or are held like those
+    let strict = &context.policy.strict;
+    let violations = match (&report, strict.applies(request.strict, caller.tenant.as_deref())) {
+        (Some(report), true) => strict.violations(report),
+        _ => Vec::new(),
+    };
+    if !violations.is_empty() {
+        warn!("file_id {} did not pass strict mode: {}", file_id, violations.join("; "));
+        match strict.action {
+            StrictAction::Reject => return Err(strict_mode_violation(&violations)),
+            StrictAction::Review => review_hold
+                .get_or_insert_with(|| ReviewHold { rules: Vec::new(), max_severity: report.as_ref().and_then(|report| context.policy.max_severity(report)) })
+                .rules
+                .push(STRICT_MODE_RULE.to_string()),
+        }
+    }
+    if let Some(hold) = &review_hold {
+        info!("file_id {} held for review by rules {:?}", file_id, hold.rules);
+    }
+
+    let deprecations = context.deprecations.used_by(&request).into_iter().cloned().collect();
+    let name = request.file_name.as_deref().unwrap_or("file");
+    let final_file_name = format!("{}_{}_redacted_{}.txt", name, strategy, file_id);
+
+    if request.retention == Retention::None {
+        // Returning held output inline would get around the review
+        if review_hold.is_some() {
+            return Err(OperationError::new(
+                ErrorKind::Conflict,
+                "The output is held for review, so it cannot be returned inline; upload it with stored retention",
+            )
+            .with_code("review_required"));
+        }
+        // Unstored output can only come back in the response, encrypted unless asked otherwise
+        let mode = match request.response_mode {
+            ResponseMode::Reference => ResponseMode::InlineEncrypted,
+            mode => mode,
+        };
+        let (content, output) = inline_output(context, mode, &file_id, &final_file_name, &redacted_content, &session_key)?;
+        // Only the usage counts are kept
+        let mut storage = within(Stage::Store, context.policy.stage_timeouts.budget(Stage::Store), async { Ok(context.storage.write().await) })
+            .await
+            .map_err(|deadline| stage_timeout(&deadline))?;
+        storage.usage_mut().record(caller.tenant.as_deref(), profile.plaintext_bytes, report.as_ref());
+        drop(storage);
+
+        profile.record("output", mark);
+        profile.total_ms = started.elapsed().as_secs_f64() * 1000.0;
+        info!("Processed file_id {} without storing its output", file_id);
+
+        return Ok(UploadResponse {
+            file_id,
+            filename: final_file_name,
+            message: "File redacted; the output is returned inline and was not stored".to_string(),
+            relay: relay_identities,
+            external_id: None,
+            report_summary: report.as_ref().map(|report| ReportSummary {
+                max_severity: context.policy.max_severity(report),
+                ..report.summary()
+            }),
+            review_hold: None,
+            document_format,
+            language: request.language.clone(),
+            expires_at: None,
+            deprecations,
+            deduplicated: false,
+            content_sha256: Some(manifest::content_digest(redacted_content.as_bytes())),
+            content,
+            output,
+            report,
+            profile,
+        });
+    }
+
+    let content_sha256 = manifest::content_digest(redacted_content.as_bytes());
+    let statement = ProcessingStatement {
+        file_id: file_id.clone(),
+        processed_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        service_version: env!("CARGO_PKG_VERSION").to_string(),
+        backend: context.redactor.backend_name().to_string(),
+        policy_version: context.policy.version.clone(),
+        strategy: strategy.clone(),
+        content_sha256: content_sha256.clone(),
+        report_sha256: report.as_ref().and_then(manifest::report_digest),
+    };
+    let manifest = ProcessingManifest::issue(context.crypto, statement)
+        .map_err(|e| OperationError::new(ErrorKind::Internal, format!("Failed to sign the processing manifest: {}", e)))?;
+
+    // Held output is only handed out once it is released
+    let (content, output) = match review_hold {
+        Some(_) => (None, None),
+        None => inline_output(context, request.response_mode, &file_id, &final_file_name, &redacted_content, &session_key)?,
+    };
+
+    // Store the redacted file
+    let expires_at = {
+        let mut storage = within(Stage::Store, context.policy.stage_timeouts.budget(Stage::Store), async { Ok(context.storage.write().await) })
+            .await
+            .map_err(|deadline| stage_timeout(&deadline))?;
+        // Checked again under the write lock, as a concurrent upload may have claimed it
+        if let Some(external_id) = &request.external_id {
+            if storage.find_by_external_id(caller.tenant.as_deref(), external_id).is_some() {
+                return Err(external_id_conflict(external_id));
+            }
+        }
+        check_quota(storage.as_ref(), context.policy, caller.tenant.as_deref(), redacted_content.len())?;
+        if let Some(max_file_bytes) = storage.limits().max_file_bytes.filter(|max| redacted_content.len() > *max) {
+            return Err(OperationError::new(
+                ErrorKind::PayloadTooLarge,
+                format!("The redacted file is {} bytes; storage takes files of at most {} bytes", redacted_content.len(), max_file_bytes),
+            )
+            .with_code("file_too_large"));
+        }
+        let metadata = storage.store_file(&file_id, &final_file_name, &redacted_content);
+        metadata.relay = relay_identities.clone();
+        metadata.owner = caller.principal.clone();
+        // Files uploaded with a scoped credential are private to it unless given an ACL
+        metadata.acl = request.acl.or_else(|| caller.scopes.is_some().then(FileAcl::default));
+        metadata.tenant = caller.tenant.clone();
+        metadata.external_id = request.external_id.clone();
+        metadata.strategy = Some(strategy.clone());
+        metadata.language = request.language.clone();
+        metadata.session_key = Some(session_key.clone());
+        metadata.review_hold = review_hold.clone();
+        metadata.ttl_seconds = match context.policy.limits_for(caller.tenant.as_deref()) {
+            Some(limits) => limits.ttl_seconds(request.ttl_seconds, context.settings.default_file_ttl_seconds),
+            None => request.ttl_seconds.or(context.settings.default_file_ttl_seconds),
+        };
+        metadata.pseudonyms = pseudonyms;
+        metadata.original = original;
+        metadata.document_format = document_format;
+        metadata.manifest = Some(manifest);
+        metadata.content_key = content_key;
+        let expires_at = metadata.expires_at();
+        if let Some(report) = &report {
+            storage.set_report(&file_id, report.clone());
+        }
+        if let Err(e) = storage.persist(&file_id) {
+            error!("Failed to persist file_id {}: {}", file_id, e);
+            storage.delete_file(&file_id);
+            return Err(OperationError::new(ErrorKind::Internal, "Failed to store the redacted file"));
+        }
+        storage.usage_mut().record(caller.tenant.as_deref(), profile.plaintext_bytes, report.as_ref());
+        expires_at
+    };
+
+    profile.record("storage", mark);
+    profile.total_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    info!("Successfully processed file_id: {}", file_id);
+
+    let message = match review_hold {
+        Some(_) => "File uploaded and redacted; it is held for review",
+        None => "File uploaded and redacted successfully",
+    };
+    Ok(UploadResponse {
+        file_id,
+        filename: final_file_name,
+        message: message.to_string(),
+        relay: relay_identities,
+        external_id: request.external_id,
+        report_summary: report.as_ref().map(|report| ReportSummary {
+            max_severity: context.policy.max_severity(report),
+            ..report.summary()
+        }),
+        review_hold,
+        document_format,
+        language: request.language,
+        expires_at,
+        deprecations,
+        deduplicated: false,
+        content_sha256: Some(content_sha256),
+        content,
+        output,
+        report,
+        profile,
+    })
+}
+
+// The redacted content for the response body, as `mode` asks for it
+fn inline_output(
+    context: &UploadContext<'_>,
+    mode: ResponseMode,
+    file_id: &str,
+    file_name: &str,
+    content: &str,
+    session_key: &[u8],
+) -> Result<(Option<String>, Option<EncryptedDownload>), OperationError> {
+    match mode {
+        ResponseMode::Reference => Ok((None, None)),
+        ResponseMode::Inline => Ok((Some(content.to_string()), None)),
+        ResponseMode::InlineEncrypted => {
+            let output = encrypt_bytes(context.crypto, file_id, file_name.to_string(), content.as_bytes(), Some(SecretBytes::new(session_key.to_vec())), None)?;
+            Ok((None, Some(output)))
+        }
+    }
+}
+
+// For work that cannot wait for the tenant's processing window to open
+pub fn outside_processing_window(window: &ProcessingWindow, now: u64) -> OperationError {
+    OperationError::new(
+        ErrorKind::Unavailable,
+        format!("This tenant's data is only processed {}; the window opens in {} s", window, window.opens_in(now)),
+    )
+    .with_code("outside_processing_window")
+}
+
+// The rule a strict upload is held under
+pub const STRICT_MODE_RULE: &str = "strict_mode";
+
+fn strict_mode_violation(violations: &[String]) -> OperationError {
+    OperationError::new(
+        ErrorKind::Unprocessable,
+        format!("Strict mode: the output may be under-redacted: {}", violations.join("; ")),
+    )
+    .with_code("strict_mode_violation")
+}
+
+pub fn review_required() -> OperationError {
+    OperationError::new(ErrorKind::Conflict, "File is held for review and has not been released")
+        .with_code("review_required")
+}
+
+// For tenants whose outputs only leave through their delivery target
+pub fn downloads_disabled() -> OperationError {
+    OperationError::new(ErrorKind::Forbidden, "Downloads are disabled for this tenant; outputs are only delivered to its configured target")
+        .with_code("downloads_disabled")
+}
+
+pub fn file_expired() -> OperationError {
+    OperationError::new(ErrorKind::Gone, "File has expired").with_code("expired")
+}
+
+// Lift a file's review hold. Reviewers need the file's `review` permission, and cannot
+// release what they uploaded themselves.
+pub fn release_hold(storage: &mut dyn Storage, caller: &Caller, file_id: &str) -> Result<ReviewHold, OperationError> {
+    if find_file(storage, caller, file_id).is_none() {
+        return Err(OperationError::new(ErrorKind::NotFound, "File not found"));
+    }
+    let metadata = storage.get_metadata_mut(file_id)
+        .ok_or_else(|| OperationError::new(ErrorKind::NotFound, "File not found"))?;
+
+    if !acl::is_allowed(metadata.acl.as_ref(), metadata.owner.as_deref(), caller, AclOperation::Review) {
+        return Err(OperationError::new(ErrorKind::Forbidden, "Access denied"));
+    }
+    if caller.principal.is_none() || caller.principal == metadata.owner {
+        return Err(OperationError::new(ErrorKind::Forbidden, "Files must be released by a reviewer other than the uploader")
+            .with_code("self_review"));
+    }
+    let hold = metadata.review_hold.take()
+        .ok_or_else(|| OperationError::new(ErrorKind::Conflict, "File is not held for review").with_code("not_held"))?;
+
+    if let Err(e) = storage.persist(file_id) {
+        error!("Failed to persist release of file_id {}: {}", file_id, e);
+        if let Some(metadata) = storage.get_metadata_mut(file_id) {
+            metadata.review_hold = Some(hold);
+        }
+        return Err(OperationError::new(ErrorKind::Internal, "Failed to store the release"));
+    }
+    info!("file_id {} released from review", file_id);
+    Ok(hold)
+}
+
+// External ids are 1-128 characters of letters, digits, '.', '_', ':' and '-'
+fn validate_external_id(external_id: &str) -> Result<(), OperationError> {
+    let valid = (1..=128).contains(&external_id.len())
+        && external_id.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b':' | b'-'));
+    if valid {
+        Ok(())
+    } else {
+        Err(OperationError::new(
+            ErrorKind::BadRequest,
+            "external_id must be 1-128 characters of letters, digits, '.', '_', ':' or '-'",
+        ))
+    }
+}
+
+// Clients re-handshake on `session_expired`
+pub fn session_error(e: SessionError) -> OperationError {
+    let (kind, code) = match e {
+        SessionError::Unknown => (ErrorKind::Unauthorized, "unknown_session"),
+        SessionError::Expired | SessionError::Exhausted => (ErrorKind::Unauthorized, "session_expired"),
+        SessionError::NonceRequired => (ErrorKind::BadRequest, "nonce_required"),
+        SessionError::NonceReused => (ErrorKind::BadRequest, "nonce_reused"),
+    };
+    OperationError::new(kind, e.to_string()).with_code(code)
+}
+
+fn external_id_conflict(external_id: &str) -> OperationError {
+    OperationError::new(
+        ErrorKind::Conflict,
+        format!("external_id {} is already in use", external_id),
+    )
+    .with_code("external_id_conflict")
+}
+
+// First `max_bytes` of a stored file, cut back to a UTF-8 character boundary
+pub fn fetch_preview(storage: &dyn Storage, caller: &Caller, file_id: &str, max_bytes: usize) -> Result<FilePreview, OperationError> {
+    let file = fetch_download(storage, caller, file_id)?;
+
+    let mut end = max_bytes.min(file.content.len());
+    while !file.content.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    Ok(FilePreview {
+        total_size: file.content.len(),
+        truncated: end < file.content.len(),
+        content: file.content[..end].to_string(),
+    })
+}
+
+// A stored file, if it is in the caller's tenant or shared with the caller. Files of
+// other tenants are answered as not found, so ids tell nothing about them.
+pub fn find_file<'a>(storage: &'a dyn Storage, caller: &Caller, file_id: &str) -> Option<&'a FileMetadata> {
+    storage.get_metadata(file_id).filter(|metadata| acl::is_visible(metadata.tenant.as_deref(), metadata.acl.as_ref(), caller))
+}
+
+// Redaction report of a stored file, for callers allowed to download it
+pub fn fetch_report(storage: &dyn Storage, caller: &Caller, file_id: &str) -> Result<ReportMatch, OperationError> {
+    let metadata = find_file(storage, caller, file_id)
+        .ok_or_else(|| OperationError::new(ErrorKind::NotFound, "File not found"))?;
+    // Reports stay readable while the file is held, since reviewers work from them
+    if !acl::is_allowed(metadata.acl.as_ref(), metadata.owner.as_deref(), caller, AclOperation::Download) {
+        return Err(OperationError::new(ErrorKind::Forbidden, "Access denied"));
+    }
+    let report = metadata.report.clone().ok_or_else(|| {
+        OperationError::new(ErrorKind::NotFound, "No redaction report is stored for this file")
+            .with_code("report_unavailable")
+    })?;
+    // Served on its own by `fetch_heatmap`
+    let report = RedactionReport { heatmap: None, ..report };
+
+    Ok(ReportMatch {
+        file_id: file_id.to_string(),
+        filename: metadata.file_name.clone(),
+        external_id: metadata.external_id.clone(),
+        created_at: metadata.created_at,
+        report,
+        review_hold: metadata.review_hold.clone(),
+    })
+}
+
+// Redaction density by page or run of lines, for callers allowed to download the file
+pub fn fetch_heatmap(storage: &dyn Storage, caller: &Caller, file_id: &str) -> Result<Heatmap, OperationError> {
+    let metadata = find_file(storage, caller, file_id)
+        .ok_or_else(|| OperationError::new(ErrorKind::NotFound, "File not found"))?;
+    if !acl::is_allowed(metadata.acl.as_ref(), metadata.owner.as_deref(), caller, AclOperation::Download) {
+        return Err(OperationError::new(ErrorKind::Forbidden, "Access denied"));
+    }
+    // Extract uploads, and files stored before heatmaps, have none
+    metadata.report.as_ref().and_then(|report| report.heatmap.clone()).ok_or_else(|| {
+        OperationError::new(ErrorKind::NotFound, "No heatmap is stored for this file").with_code("heatmap_unavailable")
+    })
+}
+
+// Files in the caller's tenant whose reports match the query and that the caller may
+// download, newest first
+pub fn search_reports(storage: &dyn Storage, caller: &Caller, mut query: ReportQuery, limit: usize) -> Vec<ReportMatch> {
+    query.tenant = caller.tenant.clone();
+
+    let mut matches: Vec<ReportMatch> = storage.search_reports(&query)
+        .into_iter()
+        .filter_map(|file_id| {
+            let metadata = storage.get_metadata(&file_id)?;
+            if !acl::is_allowed(metadata.acl.as_ref(), metadata.owner.as_deref(), caller, AclOperation::Download) {
+                return None;
+            }
+            // Counts only; offsets are served per file by `fetch_report`
+            let report = RedactionReport { detections: Vec::new(), heatmap: None, ..metadata.report.clone()? };
+            Some(ReportMatch {
+                filename: metadata.file_name.clone(),
+                external_id: metadata.external_id.clone(),
+                created_at: metadata.created_at,
+                report,
+                review_hold: metadata.review_hold.clone(),
+                file_id,
+            })
+        })
+        .collect();
+
+    matches.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| a.file_id.cmp(&b.file_id)));
+    matches.truncate(limit);
+    matches
+}
+
+// One page of the files in the caller's tenant that it may download, newest first
+pub fn list_files(storage: &dyn Storage, caller: &Caller, filter: &FileFilter, offset: usize, limit: usize) -> Result<FileList, OperationError> {
+    if !caller.allows(Scope::Download) {
+        return Err(OperationError::new(ErrorKind::Forbidden, "Listing files needs the download scope").with_code("scope_denied"));
+    }
+
+    let mut files: Vec<FileListing> = storage.file_ids()
+        .into_iter()
+        .filter_map(|file_id| {
+            let metadata = storage.get_metadata(&file_id)?;
+            let listed = metadata.tenant == caller.tenant
+                && filter.name_prefix.as_deref().is_none_or(|prefix| metadata.file_name.starts_with(prefix))
+                && filter.created_after.is_none_or(|after| metadata.created_at > after)
+                && acl::is_allowed(metadata.acl.as_ref(), metadata.owner.as_deref(), caller, AclOperation::Download);
+            listed.then(|| FileListing {
+                filename: metadata.file_name.clone(),
+                size: metadata.size,
+                strategy: metadata.strategy.clone(),
+                language: metadata.language.clone(),
+                created_at: metadata.created_at,
+                cold_tier: metadata.cold_tier,
+                file_id,
+            })
+        })
+        .collect();
+
+    files.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| a.file_id.cmp(&b.file_id)));
+    let total = files.len();
+    let files: Vec<FileListing> = files.into_iter().skip(offset).take(limit).collect();
+    let next_offset = Some(offset + files.len()).filter(|next| *next < total);
+    Ok(FileList { files, total, next_offset })
+}
+
+// Resolve a client's external id within its tenant, enforcing the file's ACL
+pub fn find_by_external_id(storage: &dyn Storage, caller: &Caller, external_id: &str) -> Result<ExternalIdMatch, OperationError> {
+    let not_found = || OperationError::new(ErrorKind::NotFound, "No file with this external_id");
+    let file_id = storage.find_by_external_id(caller.tenant.as_deref(), external_id)
+        .ok_or_else(not_found)?;
+    let metadata = storage.get_metadata(file_id).ok_or_else(not_found)?;
+
+    if !acl::is_allowed(metadata.acl.as_ref(), metadata.owner.as_deref(), caller, AclOperation::Download) {
+        warn!("Lookup of file_id {} by external id denied by ACL", file_id);
+        return Err(OperationError::new(ErrorKind::Forbidden, "Access denied"));
+    }
+
+    Ok(ExternalIdMatch {
+        file_id: file_id.to_string(),
+        external_id: external_id.to_string(),
+        filename: metadata.file_name.clone(),
+        size: metadata.size,
+        review_hold: metadata.review_hold.clone(),
+    })
+}
+
+// Look up a stored file for download, enforcing its access-control list
+pub fn fetch_download(storage: &dyn Storage, caller: &Caller, file_id: &str) -> Result<DownloadedFile, OperationError> {
+    let Some(metadata) = find_file(storage, caller, file_id) else {
+        if storage.was_expired(file_id) {
+            return Err(file_expired());
+        }
+        return Err(OperationError::new(ErrorKind::NotFound, "File not found"));
+    };
+
+    if !acl::is_allowed(metadata.acl.as_ref(), metadata.owner.as_deref(), caller, AclOperation::Download) {
+        warn!("Download of file_id {} denied by ACL", file_id);
+        return Err(OperationError::new(ErrorKind::Forbidden, "Access denied"));
+    }
+    // Expired but not yet purged by the sweep
+    if metadata.is_expired(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()) {
+        return Err(file_expired());
+    }
+    if metadata.review_hold.is_some() {
+        return Err(review_required());
+    }
+    storage.touch(file_id);
+
+    Ok(DownloadedFile {
+        file_name: metadata.file_name.clone(),
+        content: metadata.content.clone(),
+        relay: metadata.relay.clone(),
+        session_key: metadata.session_key.clone(),
+        document_format: metadata.document_format,
+        manifest: metadata.manifest.clone(),
+    })
+}
+
+// A download in `format`. Views other than `txt` are rendered from the stored text on
+// first request; the second value is a newly rendered one to hand to `cache_view`.
+pub fn fetch_download_as(
+    storage: &dyn Storage,
+    caller: &Caller,
+    file_id: &str,
+    format: DownloadFormat,
+) -> Result<(DownloadedFile, Option<String>), OperationError> {
+    let mut file = fetch_download(storage, caller, file_id)?;
+    if format == DownloadFormat::Txt {
+        return Ok((file, None));
+    }
+    let metadata = storage.get_metadata(file_id)
+        .ok_or_else(|| OperationError::new(ErrorKind::NotFound, "File not found"))?;
+
+    file.file_name = std::path::Path::new(&file.file_name)
+        .with_extension(format.extension())
+        .to_string_lossy()
+        .into_owned();
+    if let Some(view) = metadata.views.get(&format) {
+        file.content = view.clone();
+        return Ok((file, None));
+    }
+    file.content = views::render(format, file_id, &file.content, metadata.report.as_ref());
+    let rendered = file.content.clone();
+    Ok((file, Some(rendered)))
+}
+
+pub fn cache_view(storage: &mut dyn Storage, file_id: &str, format: DownloadFormat, view: String) {
+    if let Some(metadata) = storage.get_metadata_mut(file_id) {
+        metadata.views.insert(format, view);
+    }
+}
+
+// Original text of a pseudonymized file. Besides the download checks, the caller needs
+// the `unredact` scope and the file's `unredact` permission, which downloading does not
+// imply.
+pub fn unredact_file(
+    crypto: &CryptoService,
+    storage: &dyn Storage,
+    caller: &Caller,
+    file_id: &str,
+) -> Result<DownloadedFile, OperationError> {
+    let mut file = fetch_download(storage, caller, file_id)?;
+    let metadata = storage.get_metadata(file_id)
+        .ok_or_else(|| OperationError::new(ErrorKind::NotFound, "File not found"))?;
+
+    if !acl::is_allowed(metadata.acl.as_ref(), metadata.owner.as_deref(), caller, AclOperation::Unredact) {
+        warn!("Unredaction of file_id {} denied", file_id);
+        return Err(OperationError::new(ErrorKind::Forbidden, "Access denied"));
+    }
+    let sealed = metadata.pseudonyms.as_ref().ok_or_else(|| {
+        OperationError::new(ErrorKind::Conflict, "File was not redacted with the pseudonymize strategy")
+            .with_code("not_pseudonymized")
+    })?;
+    let pseudonyms = sealed.open(crypto, file_id).map_err(|e| {
+        error!("Failed to open the pseudonyms of file_id {}: {}", file_id, e);
+        OperationError::new(ErrorKind::Internal, "Failed to open the pseudonyms")
+    })?;
+
+    file.content = pseudonyms.unredact(&file.content);
+    file.manifest = None;
+    Ok(file)
+}
+
+// The original of a file uploaded with `retain_original`, for legal holds and the like.
+// The caller needs the `original` scope, which only credentials naming it have, and the
+// file's `unredact` permission. Review holds do not apply: they hold back redacted output.
+pub fn original_file(
+    crypto: &CryptoService,
+    storage: &dyn Storage,
+    caller: &Caller,
+    file_id: &str,
+) -> Result<DownloadedFile, OperationError> {
+    if !caller.granted(Scope::Original) {
+        return Err(OperationError::new(ErrorKind::Forbidden, "This credential may not read retained originals").with_code("scope_denied"));
+    }
+    let Some(metadata) = find_file(storage, caller, file_id) else {
+        if storage.was_expired(file_id) {
+            return Err(file_expired());
+        }
+        return Err(OperationError::new(ErrorKind::NotFound, "File not found"));
+    };
+    if !acl::is_allowed(metadata.acl.as_ref(), metadata.owner.as_deref(), caller, AclOperation::Unredact) {
+        warn!("Access to the original of file_id {} denied", file_id);
+        return Err(OperationError::new(ErrorKind::Forbidden, "Access denied"));
+    }
+    if metadata.is_expired(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()) {
+        return Err(file_expired());
+    }
+    let content = retained_original(metadata)?.open(crypto, file_id).map_err(|e| {
+        error!("Failed to open the original of file_id {}: {}", file_id, e);
+        OperationError::new(ErrorKind::Internal, "Failed to open the retained original")
+    })?;
+
+    Ok(DownloadedFile {
+        file_name: format!("original_{}.txt", file_id),
+        content,
+        relay: None,
+        session_key: metadata.session_key.clone(),
+        document_format: None,
+        manifest: None,
+    })
+}
+
+fn retained_original(metadata: &FileMetadata) -> Result<&RetainedOriginal, OperationError> {
+    match (&metadata.original, metadata.original_purged_at) {
+        (Some(original), _) => Ok(original),
+        (None, Some(_)) => Err(OperationError::new(ErrorKind::Gone, "The file's original was purged at the end of its retention")
+            .with_code("original_purged")),
+        (None, None) => Err(OperationError::new(ErrorKind::Conflict, "File was uploaded without retain_original").with_code("original_not_retained")),
+    }
+}
+
+// Drop the originals retained for more than `retention_seconds`, keeping the files.
+// Returns the ids of the files whose original was purged.
+pub fn purge_originals(storage: &mut dyn Storage, retention_seconds: u64, now: u64) -> Vec<String> {
+    let due: Vec<String> = storage.file_ids()
+        .into_iter()
+        .filter(|file_id| storage.get_metadata(file_id).is_some_and(|metadata| {
+            metadata.original.is_some() && metadata.created_at.saturating_add(retention_seconds) <= now
+        }))
+        .collect();
+    let mut purged = Vec::new();
+    for file_id in due {
+        let Some(metadata) = storage.get_metadata_mut(&file_id) else { continue };
+        let original = metadata.original.take();
+        metadata.original_purged_at = Some(now);
+        if let Err(e) = storage.persist(&file_id) {
+            error!("Failed to persist the purged original of file_id {}: {}", file_id, e);
+            if let Some(metadata) = storage.get_metadata_mut(&file_id) {
+                metadata.original = original;
+                metadata.original_purged_at = None;
+            }
+            continue;
+        }
+        info!("Purged the retained original of file_id {}", file_id);
+        purged.push(file_id);
+    }
+    purged
+}
+
+// A PDF or DOCX upload's redacted text rebuilt as a simple document in its original
+// format, with the file name to serve it under
+pub fn rebuild_document(file_id: &str, file: &DownloadedFile) -> Result<(String, Vec<u8>), OperationError> {
+    let format = file.document_format.ok_or_else(|| {
+        OperationError::new(ErrorKind::Conflict, "File was not uploaded as a PDF or DOCX document").with_code("not_a_document")
+    })?;
+    let content = document::rebuild(format, &file.content).map_err(|e| {
+        error!("Failed to rebuild the document of file_id {}: {}", file_id, e);
+        OperationError::new(ErrorKind::Internal, "Failed to rebuild the document")
+    })?;
+    let file_name = std::path::Path::new(&file.file_name)
+        .with_extension(format.extension())
+        .to_string_lossy()
+        .into_owned();
+    Ok((file_name, content))
+}
+
+// The download with the file's processing manifest embedded in `format`
+pub fn embed_manifest(mut file: DownloadedFile, format: DownloadFormat) -> Result<DownloadedFile, OperationError> {
+    let manifest = file.manifest.as_ref().ok_or_else(manifest_unavailable)?;
+    file.content = manifest::embed(format, std::mem::take(&mut file.content), manifest);
+    Ok(file)
+}
+
+// A rebuilt document zipped with the file's processing manifest, under the name to serve it as
+pub fn bundle_manifest(file_id: &str, file: &DownloadedFile, file_name: &str, content: &[u8]) -> Result<(String, Vec<u8>), OperationError> {
+    let manifest = file.manifest.as_ref().ok_or_else(manifest_unavailable)?;
+    let bundle = manifest::bundle(file_name, content, manifest).map_err(|e| {
+        error!("Failed to bundle the manifest of file_id {}: {}", file_id, e);
+        OperationError::new(ErrorKind::Internal, "Failed to bundle the processing manifest")
+    })?;
+    let bundle_name = std::path::Path::new(file_name).with_extension("zip").to_string_lossy().into_owned();
+    Ok((bundle_name, bundle))
+}
+
+fn manifest_unavailable() -> OperationError {
+    OperationError::new(ErrorKind::NotFound, "No processing manifest is available for this download")
+        .with_code("manifest_unavailable")
+}
+
+// Encrypt a download under the upload's session key, or under a key the client wraps
+// to the service key, with the file id bound as AAD
+pub fn encrypt_download(
+    crypto: &CryptoService,
+    file_id: &str,
+    file: DownloadedFile,
+    encrypted_session_key: Option<&str>,
+) -> Result<EncryptedDownload, OperationError> {
+    encrypt_bytes(crypto, file_id, file.file_name, file.content.as_bytes(), file.session_key, encrypted_session_key)
+}
+
+// Same as `encrypt_download`, for content that is not the stored text
+pub fn encrypt_bytes(
+    crypto: &CryptoService,
+    file_id: &str,
+    file_name: String,
+    content: &[u8],
+    stored_session_key: Option<SecretBytes>,
+    encrypted_session_key: Option<&str>,
+) -> Result<EncryptedDownload, OperationError> {
+    let session_key = match encrypted_session_key {
+        Some(encrypted_session_key) => crypto.decrypt_session_key(encrypted_session_key).map_err(|e| {
+            OperationError::new(ErrorKind::BadRequest, format!("Session key decryption failed: {}", e))
+        })?,
+        None => stored_session_key.ok_or_else(|| {
+            OperationError::new(ErrorKind::Conflict, "No session key is stored for this file; provide one")
+                .with_code("session_key_unavailable")
+        })?,
+    };
+
+    let (encrypted_data, nonce) = crypto::encrypt_with_session_key(content, &session_key, file_id.as_bytes())
+        .map_err(|e| OperationError::new(ErrorKind::BadRequest, format!("Encryption failed: {}", e)))?;
+
+    Ok(EncryptedDownload {
+        file_id: file_id.to_string(),
+        filename: file_name,
+        algorithm: "chacha20-poly1305",
+        encrypted_data,
+        nonce,
+        content_sha256: manifest::content_digest(content),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::DEFAULT_MAX_DECOMPRESSED_BYTES;
+    use crate::config::AppConfig;
+    use crate::storage::FileStorage;
+
+    #[test]
+    fn test_fetch_download_enforces_acl() {
+        let mut storage = FileStorage::new();
+        let metadata = storage.store_file("f1", "report.txt", "<PERSON> called");
+        metadata.owner = Some("alice".to_string());
+        metadata.acl = Some(FileAcl::default());
+
+        let caller = |principal: &str| Caller {
+            principal: Some(principal.to_string()),
+            tenant: None,
+            scopes: None,
+        };
+
+        let file = fetch_download(&storage, &caller("alice"), "f1").unwrap();
+        assert_eq!(file.content, "<PERSON> called");
+
+        let denied = fetch_download(&storage, &caller("mallory"), "f1").err().unwrap();
+        assert_eq!(denied.kind.status(), 403);
+
+        let missing = fetch_download(&storage, &caller("alice"), "f2").err().unwrap();
+        assert_eq!(missing.kind, ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_expired_files_are_gone() {
+        let mut storage = FileStorage::new();
+        storage.store_file("f1", "notes.txt", "<PERSON> called").ttl_seconds = Some(3600);
+        storage.store_file("f2", "old.txt", "<PERSON> called").ttl_seconds = Some(1);
+        storage.get_metadata_mut("f2").unwrap().created_at -= 10;
+        storage.store_file("f3", "kept.txt", "no ttl");
+
+        let caller = Caller::default();
+        assert!(fetch_download(&storage, &caller, "f1").is_ok());
+        let expired = fetch_download(&storage, &caller, "f2").err().unwrap();
+        assert_eq!((expired.kind.status(), expired.code), (410, Some("expired")));
+
+        let now = storage.get_metadata("f1").unwrap().created_at;
+        assert_eq!(storage.expired_file_ids(now), vec!["f2"]);
+        assert!(storage.expire_file("f2"));
+        assert_eq!(fetch_download(&storage, &caller, "f2").err().unwrap().kind, ErrorKind::Gone);
+        assert_eq!(fetch_download(&storage, &caller, "f4").err().unwrap().kind, ErrorKind::NotFound);
+        assert!(storage.expired_file_ids(now + 3600).contains(&"f1".to_string()));
+    }
+
+    #[test]
+    fn test_unredact_needs_its_own_permission() {
+        use crate::pseudonym::PseudonymMap;
+
+        let crypto = CryptoService::new().unwrap();
+        let mut pseudonyms = PseudonymMap::default();
+        let content = format!("{} called", pseudonyms.token_for("PERSON", "Jane Roe"));
+        let sealed = pseudonyms.seal(&crypto.get_public_key().unwrap(), "f1").unwrap();
+
+        let mut storage = FileStorage::new();
+        let metadata = storage.store_file("f1", "notes.txt", &content);
+        metadata.owner = Some("alice".to_string());
+        metadata.acl = Some(FileAcl {
+            download: vec!["bob".to_string(), "carol".to_string()],
+            unredact: vec!["carol".to_string()],
+            ..FileAcl::default()
+        });
+        metadata.pseudonyms = Some(sealed);
+        storage.store_file("f2", "plain.txt", "<PERSON> called").owner = Some("alice".to_string());
+
+        let caller = |principal: &str| Caller { principal: Some(principal.to_string()), tenant: None, scopes: None };
+        assert_eq!(unredact_file(&crypto, &storage, &caller("alice"), "f1").unwrap().content, "Jane Roe called");
+        assert_eq!(unredact_file(&crypto, &storage, &caller("carol"), "f1").unwrap().content, "Jane Roe called");
+        assert_eq!(unredact_file(&crypto, &storage, &caller("bob"), "f1").err().unwrap().kind, ErrorKind::Forbidden);
+        assert_eq!(fetch_download(&storage, &caller("bob"), "f1").unwrap().content, "<PERSON_1> called");
+
+        let scoped = Caller { scopes: Some(vec![Scope::Download]), ..caller("alice") };
+        assert_eq!(unredact_file(&crypto, &storage, &scoped, "f1").err().unwrap().kind, ErrorKind::Forbidden);
+        assert_eq!(unredact_file(&crypto, &storage, &Caller::default(), "f2").err().unwrap().kind, ErrorKind::Forbidden);
+        assert_eq!(unredact_file(&crypto, &storage, &caller("alice"), "f2").err().unwrap().code, Some("not_pseudonymized"));
+    }
+
+    #[test]
+    fn test_download_views_are_rendered_once() {
+        let mut storage = FileStorage::new();
+        let metadata = storage.store_file("f1", "notes.txt", "<PERSON> called");
+        metadata.owner = Some("alice".to_string());
+        metadata.acl = Some(FileAcl::default());
+
+        let alice = Caller { principal: Some("alice".to_string()), tenant: None, scopes: None };
+        let (file, rendered) = fetch_download_as(&storage, &alice, "f1", DownloadFormat::Html).unwrap();
+        assert_eq!(file.file_name, "notes.html");
+        assert_eq!(rendered.as_deref(), Some(file.content.as_str()));
+        cache_view(&mut storage, "f1", DownloadFormat::Html, rendered.unwrap());
+        assert!(fetch_download_as(&storage, &alice, "f1", DownloadFormat::Html).unwrap().1.is_none());
+        assert_eq!(fetch_download_as(&storage, &alice, "f1", DownloadFormat::Txt).unwrap().0.content, "<PERSON> called");
+
+        // A cached view is still behind the file's checks
+        let mallory = Caller { principal: Some("mallory".to_string()), ..alice };
+        assert_eq!(fetch_download_as(&storage, &mallory, "f1", DownloadFormat::Html).err().unwrap().kind, ErrorKind::Forbidden);
+    }
+
+    #[test]
+    fn test_held_files_need_another_reviewer() {
+        let mut storage = FileStorage::new();
+        let metadata = storage.store_file("f1", "passport.txt", "<US_PASSPORT>");
+        metadata.owner = Some("alice".to_string());
+        metadata.acl = Some(FileAcl { review: vec!["carol".to_string()], ..FileAcl::default() });
+        metadata.review_hold = Some(ReviewHold { rules: vec!["passport_review".to_string()], max_severity: None });
+        storage.set_report("f1", RedactionReport::default());
+
+        let caller = |principal: &str| Caller { principal: Some(principal.to_string()), tenant: None, scopes: None };
+        let held = fetch_download(&storage, &caller("alice"), "f1").err().unwrap();
+        assert_eq!((held.kind, held.code), (ErrorKind::Conflict, Some("review_required")));
+        assert!(fetch_report(&storage, &caller("alice"), "f1").unwrap().review_hold.is_some());
+
+        assert_eq!(release_hold(&mut storage, &caller("alice"), "f1").err().unwrap().code, Some("self_review"));
+        assert_eq!(release_hold(&mut storage, &caller("mallory"), "f1").err().unwrap().kind, ErrorKind::Forbidden);
+        assert_eq!(release_hold(&mut storage, &caller("carol"), "f1").unwrap().rules, vec!["passport_review"]);
+        assert!(fetch_download(&storage, &caller("alice"), "f1").is_ok());
+    }
+
+    #[test]
+    fn test_download_is_encrypted_under_the_session_key() {
+        use chacha20poly1305::{aead::{Aead, KeyInit, Payload}, ChaCha20Poly1305, Key, Nonce};
+
+        let crypto = CryptoService::new().unwrap();
+        let mut storage = FileStorage::new();
+        storage.store_file("f1", "notes.txt", "<PERSON> called").session_key = Some(SecretBytes::new(vec![7u8; 32]));
+        storage.store_file("f2", "other.txt", "no key");
+
+        let file = fetch_download(&storage, &Caller::default(), "f1").unwrap();
+        let encrypted = encrypt_download(&crypto, "f1", file, None).unwrap();
+        let nonce = BASE64.decode(&encrypted.nonce).unwrap();
+        assert_ne!(nonce, [0u8; 12]);
+
+        let plaintext = ChaCha20Poly1305::new(Key::from_slice(&[7u8; 32]))
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload { msg: &BASE64.decode(&encrypted.encrypted_data).unwrap(), aad: b"f1" },
+            )
+            .unwrap();
+        assert_eq!(plaintext, b"<PERSON> called");
+
+        let file = fetch_download(&storage, &Caller::default(), "f2").unwrap();
+        let missing = encrypt_download(&crypto, "f2", file, None).err().unwrap();
+        assert_eq!(missing.code, Some("session_key_unavailable"));
+    }
+
+    #[tokio::test]
+    async fn test_backend_outages_are_told_apart() {
+        let unavailable = redaction_error("f1", BackendUnavailable { backend: "presidio".to_string() }.into());
+        assert_eq!((unavailable.kind.status(), unavailable.code), (503, Some("backend_unavailable")));
+
+        let slow = within(Stage::Analyze, Duration::from_millis(10), async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            anyhow::Ok(())
+        });
+        let timeout = redaction_error("f1", slow.await.unwrap_err());
+        assert_eq!((timeout.kind.status(), timeout.code), (504, Some("backend_timeout")));
+
+        let mismatch = ContractMismatch { route: "/redact", reason: "expected redacted_text".to_string() };
+        let mismatch = redaction_error("f1", mismatch.into());
+        assert_eq!((mismatch.kind.status(), mismatch.code), (502, Some("upstream_contract_mismatch")));
+        assert!(timeout.message.contains("analyze stage"));
+
+        // Stages that cannot be interrupted fail once they are done
+        assert!(overran(Stage::Decrypt, Duration::from_millis(10), Duration::from_millis(5)).is_ok());
+        let overrun = overran(Stage::Anonymize, Duration::from_millis(10), Duration::from_millis(11)).unwrap_err();
+        assert_eq!(overrun.code, Some("anonymize_timeout"));
+        assert_eq!(redaction_error("f1", anyhow::anyhow!("bad")).code, Some("redaction_failed"));
+    }
+
+    #[test]
+    fn test_preview_truncates_on_char_boundaries() {
+        let mut storage = FileStorage::new();
+        storage.store_file("f1", "notes.txt", "café <PERSON>");
+        let caller = Caller::default();
+
+        let preview = fetch_preview(&storage, &caller, "f1", 4).unwrap();
+        assert_eq!(preview.content, "caf");
+        assert!(preview.truncated);
+        assert_eq!(preview.total_size, 14);
+
+        let preview = fetch_preview(&storage, &caller, "f1", 4096).unwrap();
+        assert_eq!(preview.content, "café <PERSON>");
+        assert!(!preview.truncated);
+    }
+
+    #[test]
+    fn test_external_ids_are_scoped_to_the_tenant() {
+        let mut storage = FileStorage::new();
+        let metadata = storage.store_file("f1", "claim.txt", "<PERSON> called");
+        metadata.tenant = Some("acme".to_string());
+        metadata.external_id = Some("claim-42".to_string());
+
+        let caller = |tenant: &str| Caller {
+            principal: None,
+            tenant: Some(tenant.to_string()),
+            scopes: None,
+        };
+
+        let found = find_by_external_id(&storage, &caller("acme"), "claim-42").unwrap();
+        assert_eq!(found.file_id, "f1");
+        assert_eq!(found.size, 15);
+
+        let other_tenant = find_by_external_id(&storage, &caller("globex"), "claim-42").err().unwrap();
+        assert_eq!(other_tenant.kind, ErrorKind::NotFound);
+
+        assert!(validate_external_id("crm:ticket_7.v2").is_ok());
+        assert!(validate_external_id("").is_err());
+        assert!(validate_external_id("a/b").is_err());
+    }
+
+    #[test]
+    fn test_file_listing_pages_through_the_tenant() {
+        let mut storage = FileStorage::new();
+        for (file_id, name, tenant, created_at) in [("f1", "claim_a.txt", "acme", 100), ("f2", "claim_b.txt", "acme", 200), ("f3", "memo.txt", "acme", 300), ("f4", "claim_c.txt", "globex", 400)] {
+            let metadata = storage.store_file(file_id, name, "<PERSON> called");
+            metadata.tenant = Some(tenant.to_string());
+            metadata.created_at = created_at;
+            metadata.strategy = Some("replace".to_string());
+        }
+        let caller = Caller { tenant: Some("acme".to_string()), ..Caller::default() };
+        let ids = |list: &FileList| list.files.iter().map(|file| file.file_id.as_str()).collect::<Vec<_>>().join(",");
+
+        let first = list_files(&storage, &caller, &FileFilter::default(), 0, 2).unwrap();
+        assert_eq!((ids(&first).as_str(), first.total, first.next_offset), ("f3,f2", 3, Some(2)));
+        let last = list_files(&storage, &caller, &FileFilter::default(), 2, 2).unwrap();
+        assert_eq!((ids(&last).as_str(), last.next_offset), ("f1", None));
+
+        let filter = FileFilter { name_prefix: Some("claim".to_string()), created_after: Some(100) };
+        assert_eq!(ids(&list_files(&storage, &caller, &filter, 0, 10).unwrap()), "f2");
+
+        let uploader = Caller { scopes: Some(vec![Scope::Upload]), ..caller };
+        assert_eq!(list_files(&storage, &uploader, &filter, 0, 10).err().unwrap().code, Some("scope_denied"));
+    }
+
+    // What an upload runs through, with the regex backend and in-memory storage
+    struct Services {
+        crypto: CryptoService,
+        redactor: RedactorService,
+        relays: RelayRegistry,
+        storage: RwLock<Box<dyn Storage>>,
+        policy: RedactionPolicy,
+        sessions: SessionManager,
+        deprecations: ProtocolDeprecations,
+    }
+
+    impl Services {
+        fn new() -> Self {
+            use crate::labels::LabelCatalog;
+            use crate::rules::RegexEngine;
+
+            Self {
+                crypto: CryptoService::generate(false).unwrap(),
+                redactor: RedactorService::with_backend(Box::new(RegexEngine::new()), LabelCatalog::default()),
+                relays: RelayRegistry::from_env().unwrap(),
+                storage: RwLock::new(Box::new(FileStorage::new())),
+                policy: RedactionPolicy::default(),
+                sessions: SessionManager::new(60, 10),
+                deprecations: ProtocolDeprecations::default(),
+            }
+        }
+
+        fn context(&self) -> UploadContext<'_> {
+            UploadContext {
+                crypto: &self.crypto,
+                redactor: &self.redactor,
+                relays: &self.relays,
+                storage: &self.storage,
+                policy: &self.policy,
+                sessions: &self.sessions,
+                deprecations: &self.deprecations,
+                max_decompressed_bytes: DEFAULT_MAX_DECOMPRESSED_BYTES,
+                settings: Arc::new(AppConfig::default().runtime_settings().unwrap()),
+            }
+        }
+
+        // `document` encrypted under `session_key`, wrapped to the service key
+        fn upload(&self, document: &str, session_key: &[u8; 32]) -> UploadRequest {
+            let (encrypted_data, nonce) = crypto::encrypt_with_session_key(document.as_bytes(), session_key, &[]).unwrap();
+            UploadRequest {
+                encrypted_data,
+                encrypted_session_key: Some(self.crypto.wrap_session_key(session_key).unwrap()),
+                nonce: Some(nonce),
+                ..UploadRequest::default()
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_analysis_reports_entities_and_stores_nothing() {
+        let services = Services::new();
+        let context = services.context();
+        let document = "Mail jane@example.com, then jane@example.com again";
+
+        let analysis = analyze_upload(&context, &Caller::default(), services.upload(document, &[7; 32]), false).await.unwrap();
+        assert_eq!(analysis.entities.get("EMAIL_ADDRESS"), Some(&2));
+        assert_eq!(analysis.detections[0].snippet, "jane@example.com");
+        assert_eq!(&document[analysis.detections[1].start..analysis.detections[1].end], "jane@example.com");
+
+        let masked = analyze_upload(&context, &Caller::default(), services.upload(document, &[7; 32]), true).await.unwrap();
+        assert_eq!(masked.detections[0].snippet, "*".repeat(16));
+        assert!(services.storage.read().await.file_ids().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_uploads_take_the_runtime_defaults() {
+        let services = Services::new();
+        let settings = RuntimeSettings {
+            default_strategy: "mask".to_string(),
+            default_entities: Some(vec!["EMAIL_ADDRESS".to_string()]),
+            default_file_ttl_seconds: Some(600),
+            ..AppConfig::default().runtime_settings().unwrap()
+        };
+        let context = UploadContext { settings: Arc::new(settings), ..services.context() };
+        let document = "Mail jane@example.com or call 212-555-0199";
+
+        let response = process_upload(&context, &Caller::default(), services.upload(document, &[7; 32])).await.unwrap();
+        let storage = services.storage.read().await;
+        let metadata = storage.get_metadata(&response.file_id).unwrap();
+        assert_eq!((metadata.strategy.as_deref(), metadata.ttl_seconds), (Some("mask"), Some(600)));
+        let content = fetch_download(storage.as_ref(), &Caller::default(), &response.file_id).unwrap().content;
+        assert!(!content.contains("jane@example.com") && content.contains("212-555-0199"));
+        drop(storage);
+
+        // What the upload sets is kept
+        let request = UploadRequest { redaction_strategy: Some("replace".to_string()), ttl_seconds: Some(60), ..services.upload(document, &[7; 32]) };
+        let response = process_upload(&context, &Caller::default(), request).await.unwrap();
+        let metadata = services.storage.read().await.get_metadata(&response.file_id).cloned().unwrap();
+        assert_eq!((metadata.strategy.as_deref(), metadata.ttl_seconds), (Some("replace"), Some(60)));
+    }
+
+    #[tokio::test]
+    async fn test_unretained_output_is_returned_inline() {
+        let services = Services::new();
+        let context = services.context();
+        let session_key = [9u8; 32];
+        let request = UploadRequest { retention: Retention::None, ..services.upload("Mail jane@example.com", &session_key) };
+
+        let response = process_upload(&context, &Caller::default(), request).await.unwrap();
+        let output = response.output.unwrap();
+        let plaintext = services.crypto
+            .decrypt_file_with_session_key(&output.encrypted_data, &session_key, Some(&output.nonce), response.file_id.as_bytes())
+            .unwrap();
+        assert_eq!(plaintext, "Mail <EMAIL_ADDRESS>");
+        assert_eq!(output.content_sha256, manifest::content_digest(plaintext.as_bytes()));
+        assert_eq!(response.content_sha256.as_ref(), Some(&output.content_sha256));
+        assert!(services.storage.read().await.file_ids().is_empty());
+
+        let request = UploadRequest { retention: Retention::None, ttl_seconds: Some(60), ..services.upload("text", &session_key) };
+        assert_eq!(process_upload(&context, &Caller::default(), request).await.err().unwrap().kind, ErrorKind::BadRequest);
+    }
+
+    #[tokio::test]
+    async fn test_inline_responses_carry_the_stored_content() {
+        let services = Services::new();
+        let context = services.context();
+        let request = UploadRequest { response_mode: ResponseMode::Inline, ..services.upload("Mail jane@example.com", &[3; 32]) };
+
+        let response = process_upload(&context, &Caller::default(), request).await.unwrap();
+        assert_eq!(response.content.as_deref(), Some("Mail <EMAIL_ADDRESS>"));
+        assert!(response.output.is_none());
+        let file = fetch_download(services.storage.read().await.as_ref(), &Caller::default(), &response.file_id).unwrap();
+        assert_eq!(file.content, "Mail <EMAIL_ADDRESS>");
+        assert_eq!(response.content_sha256, Some(manifest::content_digest(file.content.as_bytes())));
+    }
+
+    #[tokio::test]
+    async fn test_compressed_plaintexts_are_redacted_once_decompressed() {
+        use std::io::Write;
+
+        let services = Services::new();
+        let document = "Mail jane@example.com";
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(document.as_bytes()).unwrap();
+        // Checksums cover the text as the client wrote it, and are bound in as AAD
+        let digest = crypto::sha256(document.as_bytes());
+        let (encrypted_data, nonce) = crypto::encrypt_with_session_key(&gzip.finish().unwrap(), &[4; 32], &digest).unwrap();
+        let compressed = || UploadRequest {
+            encrypted_data: encrypted_data.clone(),
+            nonce: Some(nonce.clone()),
+            compression: Compression::Gzip,
+            plaintext_sha256: Some(manifest::content_digest(document.as_bytes())),
+            ..services.upload("", &[4; 32])
+        };
+
+        let context = services.context();
+        let response = process_upload(&context, &Caller::default(), compressed()).await.unwrap();
+        let file = fetch_download(services.storage.read().await.as_ref(), &Caller::default(), &response.file_id).unwrap();
+        assert_eq!(file.content, "Mail <EMAIL_ADDRESS>");
+
+        // Undeclared, the compressed bytes do not match the checksum
+        let undeclared = UploadRequest { compression: Compression::None, ..compressed() };
+        assert_eq!(process_upload(&context, &Caller::default(), undeclared).await.err().unwrap().code, Some("plaintext_checksum_mismatch"));
+        let corrupt = UploadRequest { compression: Compression::Zstd, ..compressed() };
+        assert_eq!(process_upload(&context, &Caller::default(), corrupt).await.err().unwrap().code, Some("decompression_failed"));
+        let request = compressed();
+        let context = UploadContext { max_decompressed_bytes: document.len() - 1, ..services.context() };
+        let error = process_upload(&context, &Caller::default(), request).await.err().unwrap();
+        assert_eq!((error.kind, error.code), (ErrorKind::PayloadTooLarge, Some("decompressed_too_large")));
+    }
+
+    #[tokio::test]
+    async fn test_delivery_only_tenants_get_no_inline_output() {
+        let mut services = Services::new();
+        services.policy.delivery_only.insert("acme".to_string());
+        let acme = Caller { tenant: Some("acme".to_string()), ..Caller::default() };
+        let inline = |services: &Services| UploadRequest { response_mode: ResponseMode::Inline, ..services.upload("Mail jane@example.com", &[4; 32]) };
+
+        let error = process_upload(&services.context(), &acme, inline(&services)).await.err().unwrap();
+        assert_eq!(error.code, Some("downloads_disabled"));
+        let unretained = UploadRequest { retention: Retention::None, ..services.upload("text", &[4; 32]) };
+        assert_eq!(process_upload(&services.context(), &acme, unretained).await.err().unwrap().code, Some("downloads_disabled"));
+
+        // Stored by reference it is fine, as are other tenants' inline uploads
+        assert!(process_upload(&services.context(), &acme, services.upload("text", &[4; 32])).await.is_ok());
+        assert!(process_upload(&services.context(), &Caller::default(), inline(&services)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_tenants_are_isolated_and_limited() {
+        let mut services = Services::new();
+        services.policy.tenants.insert("acme".to_string(), serde_json::from_value(serde_json::json!({
+            "max_files": 2, "max_ttl_seconds": 3600, "strategies": ["replace", "mask"]
+        })).unwrap());
+        let acme = Caller { principal: Some("alice".to_string()), tenant: Some("acme".to_string()), scopes: None };
+        let globex = Caller { principal: Some("bob".to_string()), tenant: Some("globex".to_string()), scopes: None };
+
+        let response = process_upload(&services.context(), &acme, services.upload("Mail jane@example.com", &[7; 32])).await.unwrap();
+        assert!(response.expires_at.is_some());
+        {
+            let storage = services.storage.read().await;
+            assert_eq!(storage.get_metadata(&response.file_id).unwrap().ttl_seconds, Some(3600));
+            assert!(fetch_download(storage.as_ref(), &acme, &response.file_id).is_ok());
+            // Another tenant's files are not found
+            assert_eq!(fetch_download(storage.as_ref(), &globex, &response.file_id).err().unwrap().kind, ErrorKind::NotFound);
+            assert_eq!(fetch_report(storage.as_ref(), &globex, &response.file_id).err().unwrap().kind, ErrorKind::NotFound);
+        }
+
+        let faked = UploadRequest { redaction_strategy: Some("fake".to_string()), ..services.upload("text", &[7; 32]) };
+        assert_eq!(process_upload(&services.context(), &acme, faked).await.err().unwrap().code, Some("strategy_not_allowed"));
+        let long_lived = UploadRequest { ttl_seconds: Some(7200), ..services.upload("text", &[7; 32]) };
+        assert_eq!(process_upload(&services.context(), &acme, long_lived).await.err().unwrap().code, Some("ttl_too_long"));
+
+        assert!(process_upload(&services.context(), &acme, services.upload("text", &[7; 32])).await.is_ok());
+        let error = process_upload(&services.context(), &acme, services.upload("text", &[7; 32])).await.err().unwrap();
+        assert_eq!((error.kind, error.code), (ErrorKind::Forbidden, Some("quota_exceeded")));
+        // Other tenants have no limits
+        let faked = UploadRequest { redaction_strategy: Some("fake".to_string()), ..services.upload("text", &[7; 32]) };
+        assert!(process_upload(&services.context(), &globex, faked).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_retained_originals_are_redacted_again_as_new_files() {
+        let services = Services::new();
+        let context = services.context();
+        let caller = Caller { principal: Some("alice".to_string()), ..Caller::default() };
+        let document = "Mail jane@example.com or call 555-010-0199";
+        let retained = UploadRequest { retain_original: true, ..services.upload(document, &[6; 32]) };
+        let source = process_upload(&context, &caller, retained).await.unwrap().file_id;
+
+        let emails_only = ReprocessRequest { redaction_strategy: Some("mask".to_string()), entities: Some(vec!["EMAIL_ADDRESS".to_string()]), ..ReprocessRequest::default() };
+        let response = reprocess_file(&context, &caller, &source, emails_only).await.unwrap();
+        assert_ne!(response.file_id, source);
+        assert!(response.deprecations.is_empty());
+        let storage = services.storage.read().await;
+        let file = fetch_download(storage.as_ref(), &caller, &response.file_id).unwrap();
+        assert!(file.content.ends_with("or call 555-010-0199") && !file.content.contains("jane"));
+        // Under the source's session key, and retained in turn
+        assert_eq!(file.session_key, Some(SecretBytes::new(vec![6; 32])));
+        assert!(storage.get_metadata(&response.file_id).unwrap().original.is_some());
+        assert_eq!(fetch_download(storage.as_ref(), &caller, &source).unwrap().content, "Mail <EMAIL_ADDRESS> or call <PHONE_NUMBER>");
+        drop(storage);
+
+        // Only retained originals, and only for callers who may unredact
+        let unretained = process_upload(&context, &caller, services.upload(document, &[6; 32])).await.unwrap().file_id;
+        let error = reprocess_file(&context, &caller, &unretained, ReprocessRequest::default()).await.err().unwrap();
+        assert_eq!((error.kind, error.code), (ErrorKind::Conflict, Some("original_not_retained")));
+        let uploader = Caller { scopes: Some(vec![Scope::Upload, Scope::Download]), ..caller.clone() };
+        assert_eq!(reprocess_file(&context, &uploader, &source, ReprocessRequest::default()).await.err().unwrap().code, Some("scope_denied"));
+        let stranger = Caller { principal: Some("mallory".to_string()), scopes: Some(vec![Scope::Upload, Scope::Unredact]), ..Caller::default() };
+        assert_eq!(reprocess_file(&context, &stranger, &response.file_id, ReprocessRequest::default()).await.err().unwrap().kind, ErrorKind::Forbidden);
+
+        let unstored = UploadRequest { retain_original: true, retention: Retention::None, ..services.upload(document, &[6; 32]) };
+        assert_eq!(process_upload(&context, &caller, unstored).await.err().unwrap().kind, ErrorKind::BadRequest);
+    }
+
+    #[tokio::test]
+    async fn test_originals_are_read_with_the_original_scope_until_purged() {
+        let services = Services::new();
+        let context = services.context();
+        let uploader = Caller { principal: Some("alice".to_string()), ..Caller::default() };
+        let document = "Mail jane@example.com or call 555-010-0199";
+        let retained = UploadRequest { retain_original: true, ..services.upload(document, &[6; 32]) };
+        let file_id = process_upload(&context, &uploader, retained).await.unwrap().file_id;
+
+        // Gateway identities carry no scopes, so they are never granted it
+        let storage = services.storage.read().await;
+        assert_eq!(original_file(&services.crypto, storage.as_ref(), &uploader, &file_id).err().unwrap().code, Some("scope_denied"));
+        let legal = Caller { scopes: Some(vec![Scope::Unredact, Scope::Original]), ..uploader.clone() };
+        let original = original_file(&services.crypto, storage.as_ref(), &legal, &file_id).unwrap();
+        assert_eq!(original.content, document);
+        let stranger = Caller { principal: Some("mallory".to_string()), ..legal.clone() };
+        assert_eq!(original_file(&services.crypto, storage.as_ref(), &stranger, &file_id).err().unwrap().kind, ErrorKind::Forbidden);
+        drop(storage);
+
+        // Past its retention the original goes, and the redacted file stays
+        let created_at = services.storage.read().await.get_metadata(&file_id).unwrap().created_at;
+        let mut storage = services.storage.write().await;
+        assert!(purge_originals(storage.as_mut(), 3600, created_at + 60).is_empty());
+        assert_eq!(purge_originals(storage.as_mut(), 3600, created_at + 3600), std::slice::from_ref(&file_id));
+        let error = original_file(&services.crypto, storage.as_ref(), &legal, &file_id).err().unwrap();
+        assert_eq!((error.kind, error.code), (ErrorKind::Gone, Some("original_purged")));
+        assert!(fetch_download(storage.as_ref(), &uploader, &file_id).is_ok());
+        drop(storage);
+        assert_eq!(reprocess_file(&context, &legal, &file_id, ReprocessRequest::default()).await.err().unwrap().code, Some("original_purged"));
+    }
+
+    #[tokio::test]
+    async fn test_identical_uploads_reuse_the_stored_file() {
+        let mut services = Services::new();
+        services.policy.deduplication = Deduplication::Reuse;
+        let context = services.context();
+        let alice = Caller { principal: Some("alice".to_string()), tenant: Some("acme".to_string()), scopes: None };
+        let document = "Mail jane@example.com";
+
+        let first = process_upload(&context, &alice, services.upload(document, &[1; 32])).await.unwrap();
+        assert!(!first.deduplicated);
+        // Under another session key; inline content comes from the stored file
+        let inline = UploadRequest { response_mode: ResponseMode::Inline, ..services.upload(document, &[2; 32]) };
+        let second = process_upload(&context, &alice, inline).await.unwrap();
+        assert!(second.deduplicated);
+        assert_eq!((second.file_id.as_str(), second.content.as_deref()), (first.file_id.as_str(), Some("Mail <EMAIL_ADDRESS>")));
+        assert_eq!(second.report_summary.unwrap().total_entities, 1);
+        assert_eq!(services.storage.read().await.file_ids().len(), 1);
+
+        // Other settings, other tenants and uploads asking for a file of their own are redacted
+        let masked = UploadRequest { redaction_strategy: Some("mask".to_string()), ..services.upload(document, &[1; 32]) };
+        assert!(!process_upload(&context, &alice, masked).await.unwrap().deduplicated);
+        let globex = Caller { tenant: Some("globex".to_string()), ..alice.clone() };
+        assert!(!process_upload(&context, &globex, services.upload(document, &[1; 32])).await.unwrap().deduplicated);
+        let tracked = UploadRequest { external_id: Some("claim-1".to_string()), ..services.upload(document, &[1; 32]) };
+        assert!(!process_upload(&context, &alice, tracked).await.unwrap().deduplicated);
+
+        // Only the uploader's own files are reused, even where others of the tenant could
+        // read them, and anonymous uploads are always redacted
+        let scoped = |principal: &str| Caller { principal: Some(principal.to_string()), tenant: Some("acme".to_string()), scopes: Some(vec![Scope::Upload, Scope::Download]) };
+        let private = process_upload(&context, &scoped("bob"), services.upload("Call 555-010-0199", &[1; 32])).await.unwrap();
+        assert!(!process_upload(&context, &scoped("mallory"), services.upload("Call 555-010-0199", &[1; 32])).await.unwrap().deduplicated);
+        assert_eq!(process_upload(&context, &scoped("bob"), services.upload("Call 555-010-0199", &[1; 32])).await.unwrap().file_id, private.file_id);
+        let carol = Caller { principal: Some("carol".to_string()), ..alice.clone() };
+        assert!(!process_upload(&context, &carol, services.upload(document, &[1; 32])).await.unwrap().deduplicated);
+        let anonymous = Caller::default();
+        assert!(!process_upload(&context, &anonymous, services.upload(document, &[1; 32])).await.unwrap().deduplicated);
+        assert!(!process_upload(&context, &anonymous, services.upload(document, &[1; 32])).await.unwrap().deduplicated);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_envelopes_fail_before_decryption() {
+        let services = Services::new();
+        let context = services.context();
+        let caller = Caller::default();
+        let upload = || services.upload("Mail jane@example.com", &[5; 32]);
+        let code = |result: Result<UploadResponse, OperationError>| result.err().map(|e| (e.kind.status(), e.code));
+
+        let unpadded = UploadRequest { encrypted_data: "QUJD".repeat(8) + "QQ", ..upload() };
+        assert_eq!(code(process_upload(&context, &caller, unpadded).await), Some((400, Some("invalid_payload"))));
+        let truncated = UploadRequest { encrypted_data: "QUJD".to_string(), ..upload() };
+        assert_eq!(code(process_upload(&context, &caller, truncated).await), Some((400, Some("invalid_payload"))));
+        let short_nonce = UploadRequest { nonce: Some(BASE64.encode([1u8; 8])), ..upload() };
+        assert_eq!(code(process_upload(&context, &caller, short_nonce).await), Some((400, Some("invalid_payload"))));
+        let wrapped = UploadRequest { encrypted_session_key: Some(BASE64.encode([1u8; 128])), ..upload() };
+        assert_eq!(code(process_upload(&context, &caller, wrapped).await), Some((400, Some("invalid_payload"))));
+
+        // A well-formed envelope around a session key of the wrong size
+        let short_key = UploadRequest { encrypted_session_key: Some(services.crypto.wrap_session_key(&[5; 16]).unwrap()), ..upload() };
+        assert_eq!(code(process_upload(&context, &caller, short_key).await), Some((400, Some("session_key_failed"))));
+        assert_eq!(ErrorKind::from_status(413).map(ErrorKind::code), Some("payload_too_large"));
+    }
+}
@@ -0,0 +1,82 @@
+use anyhow::Result;
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::crypto::{self, CryptoService};
+use crate::envelope;
+use crate::structured::ContentType;
+
+// The plaintext of an upload that set `retain_original`, kept so it can be redacted
+// again with other settings or handed to `original_file`. Sealed under its own
+// ChaCha20-Poly1305 key with the file id bound as AAD. That key is wrapped under the
+// escrow key when the service has one, else to the service public key like pseudonyms.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RetainedOriginal {
+    pub wrapped_key: String,
+    pub ciphertext: String,
+    pub nonce: String,
+    // How the plaintext was read, so redacting it again reads it the same way
+    #[serde(default)]
+    pub content_type: ContentType,
+    // Id of the escrow key `wrapped_key` is wrapped under; None when it is wrapped to the
+    // service key
+    #[serde(default)]
+    pub escrow_key_id: Option<String>,
+}
+
+impl RetainedOriginal {
+    pub fn seal(crypto: &CryptoService, file_id: &str, plaintext: &str, content_type: ContentType) -> Result<Self> {
+        let mut key = Zeroizing::new([0u8; 32]);
+        OsRng.fill_bytes(key.as_mut());
+        let (ciphertext, nonce) = crypto::encrypt_with_session_key(plaintext.as_bytes(), key.as_ref(), &aad(file_id))?;
+        let (wrapped_key, escrow_key_id) = match crypto.escrow_original_key(key.as_ref(), &aad(file_id))? {
+            Some((escrow_key_id, wrapped_key)) => (wrapped_key, Some(escrow_key_id)),
+            None => (envelope::wrap_session_key(&crypto.get_public_key()?, key.as_ref())?, None),
+        };
+
+        Ok(Self { wrapped_key, ciphertext, nonce, content_type, escrow_key_id })
+    }
+
+    pub fn open(&self, crypto: &CryptoService, file_id: &str) -> Result<String> {
+        let key = match &self.escrow_key_id {
+            Some(escrow_key_id) => crypto.recover_original_key(escrow_key_id, &self.wrapped_key, &aad(file_id))?,
+            None => crypto.decrypt_session_key(&self.wrapped_key)?,
+        };
+        crypto.decrypt_file_with_session_key(&self.ciphertext, &key, Some(&self.nonce), &aad(file_id))
+    }
+}
+
+// Distinct from the pseudonyms' AAD, so neither opens in place of the other
+fn aad(file_id: &str) -> Vec<u8> {
+    format!("original:{}", file_id).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_originals_are_sealed_to_their_file() {
+        let crypto = CryptoService::new().unwrap();
+        let public_key = crypto.get_public_key().unwrap();
+        let original = RetainedOriginal::seal(&crypto, "f1", "Call Jane on 555-0100", ContentType::Text).unwrap();
+        assert!(!original.ciphertext.contains("Jane"));
+        assert_eq!(original.open(&crypto, "f1").unwrap(), "Call Jane on 555-0100");
+        assert!(original.open(&crypto, "f2").is_err());
+
+        // Pseudonyms sealed to the same file do not open as its original
+        let pseudonyms = crate::pseudonym::PseudonymMap::default().seal(&public_key, "f1").unwrap();
+        let swapped = RetainedOriginal { wrapped_key: pseudonyms.wrapped_key, ciphertext: pseudonyms.ciphertext, nonce: pseudonyms.nonce, content_type: ContentType::Text, escrow_key_id: None };
+        assert!(swapped.open(&crypto, "f1").is_err());
+
+        // Under an escrow key, only a service holding that key opens it
+        let escrowed = CryptoService::new().unwrap().with_original_escrow_key([5; 32]);
+        let original = RetainedOriginal::seal(&escrowed, "f1", "Call Jane on 555-0100", ContentType::Text).unwrap();
+        assert!(original.escrow_key_id.is_some());
+        assert_eq!(original.open(&escrowed, "f1").unwrap(), "Call Jane on 555-0100");
+        assert!(original.open(&escrowed, "f2").is_err());
+        assert!(original.open(&crypto, "f1").is_err());
+        assert!(original.open(&CryptoService::new().unwrap().with_original_escrow_key([6; 32]), "f1").is_err());
+    }
+}
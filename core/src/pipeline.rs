@@ -0,0 +1,289 @@
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::collections::HashMap;
+
+use crate::envelope;
+use crate::report::{self, Detection};
+
+const MIN_HASH_KEY_LEN: usize = 16;
+
+// One step of an entity pipeline, applied to the output of the step before it
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Operator {
+    // Drop separator characters and, by default, lowercase
+    Normalize {
+        #[serde(default = "default_strip")]
+        strip: String,
+        #[serde(default = "default_true")]
+        lowercase: bool,
+    },
+    // Check the value; one that fails is not a real entity of this type
+    Validate {
+        checksum: Checksum,
+        #[serde(default)]
+        on_fail: OnFail,
+    },
+    // Hex HMAC-SHA256 under `key`, so equal values map to equal tokens
+    Hash { key: String },
+    // Keep the first `length` characters, or the last with `from_end`
+    Truncate {
+        length: usize,
+        #[serde(default)]
+        from_end: bool,
+    },
+    Mask {
+        #[serde(default = "default_mask_char")]
+        char: char,
+        #[serde(default)]
+        keep_last: usize,
+    },
+    // A fixed value, `<ENTITY_TYPE>` by default
+    Replace { value: Option<String> },
+    // `format` with `{}` replaced by the value, e.g. `SSN-{}`
+    Template { format: String },
+}
+
+fn default_strip() -> String {
+    " -./()".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_mask_char() -> char {
+    '*'
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Checksum {
+    Luhn,
+    // ISO 13616 mod-97
+    Iban,
+    // Area, group and serial ranges the SSA never issues
+    UsSsn,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnFail {
+    // Redact it with `<ENTITY_TYPE>` anyway
+    #[default]
+    Replace,
+    // Leave the original text, treating the detection as a false positive
+    Keep,
+}
+
+impl Checksum {
+    fn is_valid(self, value: &str) -> bool {
+        match self {
+            Checksum::Luhn => {
+                if value.len() < 2 || !value.bytes().all(|b| b.is_ascii_digit()) {
+                    return false;
+                }
+                let sum: u32 = value.bytes().rev().enumerate()
+                    .map(|(i, b)| {
+                        let digit = u32::from(b - b'0');
+                        match i % 2 {
+                            0 => digit,
+                            _ if digit * 2 > 9 => digit * 2 - 9,
+                            _ => digit * 2,
+                        }
+                    })
+                    .sum();
+                sum.is_multiple_of(10)
+            }
+            Checksum::Iban => {
+                if !(15..=34).contains(&value.len()) || !value.bytes().all(|b| b.is_ascii_alphanumeric()) {
+                    return false;
+                }
+                let rearranged = value[4..].bytes().chain(value[..4].bytes());
+                let remainder = rearranged.fold(0u32, |remainder, b| {
+                    match b.to_ascii_uppercase() {
+                        b'0'..=b'9' => (remainder * 10 + u32::from(b - b'0')) % 97,
+                        letter => (remainder * 100 + u32::from(letter - b'A' + 10)) % 97,
+                    }
+                });
+                remainder == 1
+            }
+            Checksum::UsSsn => {
+                if value.len() != 9 || !value.bytes().all(|b| b.is_ascii_digit()) {
+                    return false;
+                }
+                let (area, group, serial) = (&value[..3], &value[3..5], &value[5..]);
+                area != "000" && area != "666" && !area.starts_with('9') && group != "00" && serial != "0000"
+            }
+        }
+    }
+}
+
+// A tenant's pipelines: ordered operators per entity type, and `default` for the
+// types without their own (`<ENTITY_TYPE>` when unset). They replace the upload's
+// redaction strategy for that tenant.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineSet {
+    #[serde(default)]
+    pub entities: HashMap<String, Vec<Operator>>,
+    #[serde(default)]
+    pub default: Vec<Operator>,
+}
+
+impl PipelineSet {
+    pub fn validate(&self) -> Result<()> {
+        let pipelines = self.entities.iter()
+            .map(|(entity_type, operators)| (entity_type.as_str(), operators))
+            .chain(std::iter::once(("default", &self.default)));
+
+        for (name, operators) in pipelines {
+            if name != "default" && operators.is_empty() {
+                return Err(anyhow!("Pipeline for {} has no operators", name));
+            }
+            let mut transformed = false;
+            for operator in operators {
+                match operator {
+                    Operator::Normalize { .. } => {}
+                    Operator::Validate { .. } if transformed => {
+                        return Err(anyhow!("Pipeline for {}: validate must come before hash, truncate, mask, replace and template", name));
+                    }
+                    Operator::Validate { .. } => {}
+                    Operator::Hash { key } if key.len() < MIN_HASH_KEY_LEN => {
+                        return Err(anyhow!("Pipeline for {}: hash keys must be at least {} bytes", name, MIN_HASH_KEY_LEN));
+                    }
+                    Operator::Truncate { length: 0, .. } => {
+                        return Err(anyhow!("Pipeline for {}: truncate length must be positive", name));
+                    }
+                    Operator::Template { format } if !format.contains("{}") => {
+                        return Err(anyhow!("Pipeline for {}: template format needs a {{}} placeholder", name));
+                    }
+                    _ => transformed = true,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Run an entity's value through its pipeline
+    pub fn apply(&self, entity_type: &str, value: &str) -> String {
+        let operators = self.entities.get(entity_type).unwrap_or(&self.default);
+        if operators.is_empty() {
+            return tag(entity_type);
+        }
+
+        let mut current = value.to_string();
+        for operator in operators {
+            current = match operator {
+                Operator::Normalize { strip, lowercase } => {
+                    let stripped: String = current.chars().filter(|c| !strip.contains(*c)).collect();
+                    if *lowercase { stripped.to_lowercase() } else { stripped }
+                }
+                Operator::Validate { checksum, on_fail } => {
+                    if !checksum.is_valid(&current) {
+                        return match on_fail {
+                            OnFail::Replace => tag(entity_type),
+                            OnFail::Keep => value.to_string(),
+                        };
+                    }
+                    current
+                }
+                Operator::Hash { key } => {
+                    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC takes keys of any length");
+                    mac.update(current.as_bytes());
+                    envelope::hex(&mac.finalize().into_bytes())
+                }
+                Operator::Truncate { length, from_end: false } => current.chars().take(*length).collect(),
+                Operator::Truncate { length, from_end: true } => {
+                    let skip = current.chars().count().saturating_sub(*length);
+                    current.chars().skip(skip).collect()
+                }
+                Operator::Mask { char, keep_last } => {
+                    let count = current.chars().count();
+                    let masked = count.saturating_sub(*keep_last);
+                    std::iter::repeat_n(*char, masked).chain(current.chars().skip(masked)).collect()
+                }
+                Operator::Replace { value } => value.clone().unwrap_or_else(|| tag(entity_type)),
+                Operator::Template { format } => format.replacen("{}", &current, 1),
+            };
+        }
+        current
+    }
+
+    // Rewrite every detection in `text` through its pipeline. None when a detection
+    // does not fall on character boundaries of `text`.
+    pub fn apply_to_text(&self, text: &str, detections: &[Detection]) -> Option<String> {
+        report::rewrite_detections(text, detections, |detection, value| self.apply(&detection.entity_type, value))
+    }
+}
+
+fn tag(entity_type: &str) -> String {
+    format!("<{}>", entity_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detection(entity_type: &str, start: usize, end: usize) -> Detection {
+        Detection { entity_type: entity_type.to_string(), start, end, score: 1.0 }
+    }
+
+    #[test]
+    fn test_pipelines_compose_operators() {
+        let pipelines: PipelineSet = serde_json::from_value(serde_json::json!({
+            "entities": {
+                "CREDIT_CARD": [
+                    { "op": "normalize" },
+                    { "op": "validate", "checksum": "luhn", "on_fail": "keep" },
+                    { "op": "mask", "keep_last": 4 }
+                ],
+                "US_SSN": [
+                    { "op": "normalize" },
+                    { "op": "validate", "checksum": "us_ssn" },
+                    { "op": "hash", "key": "0123456789abcdef" },
+                    { "op": "truncate", "length": 8 },
+                    { "op": "template", "format": "SSN-{}" }
+                ]
+            }
+        }))
+        .unwrap();
+        pipelines.validate().unwrap();
+
+        assert_eq!(pipelines.apply("CREDIT_CARD", "4111 1111 1111 1111"), "************1111");
+        assert_eq!(pipelines.apply("CREDIT_CARD", "4111 1111 1111 1112"), "4111 1111 1111 1112");
+        assert_eq!(pipelines.apply("US_SSN", "000-12-3456"), "<US_SSN>");
+        assert_eq!(pipelines.apply("PERSON", "Jane"), "<PERSON>");
+
+        let ssn = pipelines.apply("US_SSN", "123-45-6789");
+        assert!(ssn.starts_with("SSN-") && ssn.len() == 12, "{}", ssn);
+        assert_eq!(pipelines.apply("US_SSN", "123 45 6789"), ssn);
+
+        let text = "Jane paid with 4111-1111-1111-1111.";
+        let output = pipelines.apply_to_text(text, &[detection("CREDIT_CARD", 15, 34), detection("PERSON", 0, 4)]).unwrap();
+        assert_eq!(output, "<PERSON> paid with ************1111.");
+        assert!(pipelines.apply_to_text("café", &[detection("PERSON", 0, 4)]).is_none());
+        assert!(Checksum::Iban.is_valid("GB82WEST12345698765432"));
+    }
+
+    #[test]
+    fn test_invalid_pipelines_are_rejected() {
+        let invalid = [
+            serde_json::json!({ "entities": { "US_SSN": [{ "op": "hash", "key": "short" }] } }),
+            serde_json::json!({ "entities": { "US_SSN": [{ "op": "mask" }, { "op": "validate", "checksum": "us_ssn" }] } }),
+            serde_json::json!({ "entities": { "US_SSN": [{ "op": "template", "format": "SSN" }] } }),
+            serde_json::json!({ "entities": { "US_SSN": [] } }),
+        ];
+        for pipelines in invalid {
+            let pipelines: PipelineSet = serde_json::from_value(pipelines).unwrap();
+            assert!(pipelines.validate().is_err());
+        }
+
+        let unknown = serde_json::json!({ "entities": { "US_SSN": [{ "op": "encrypt" }] } });
+        assert!(serde_json::from_value::<PipelineSet>(unknown).is_err());
+        let misspelled = serde_json::json!({ "entities": { "US_SSN": [{ "op": "truncate", "lenght": 4 }] } });
+        assert!(serde_json::from_value::<PipelineSet>(misspelled).is_err());
+    }
+}
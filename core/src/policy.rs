@@ -0,0 +1,449 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use tracing::info;
+
+use crate::bidi::BidiMode;
+use crate::boundary::{BoundaryAdjuster, BoundaryRules};
+use crate::pipeline::PipelineSet;
+use crate::report::RedactionReport;
+use crate::resilience::StageTimeouts;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+// Holds an artifact for review when the upload has at least `min_count` detections of
+// the listed entity types, or of any type at `min_severity` or above (both when both
+// are given)
+#[derive(Clone, Deserialize)]
+pub struct BlockingRule {
+    pub name: String,
+    #[serde(default)]
+    pub entity_types: Vec<String>,
+    pub min_severity: Option<Severity>,
+    #[serde(default = "default_min_count")]
+    pub min_count: usize,
+}
+
+fn default_min_count() -> usize {
+    1
+}
+
+// Why a stored artifact is withheld until a reviewer releases it
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ReviewHold {
+    // Names of the rules that matched
+    pub rules: Vec<String>,
+    pub max_severity: Option<Severity>,
+}
+
+// What a strict upload that was not confidently handled gets: it fails, or is stored
+// and held for review
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StrictAction {
+    #[default]
+    Reject,
+    Review,
+}
+
+// What an upload identical to one already stored in its tenant, under the same policy
+// and settings, gets: it is redacted again, or answered with the stored file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Deduplication {
+    #[default]
+    Reprocess,
+    Reuse,
+}
+
+// What one tenant may store and how: quotas on what it has stored at once, the TTLs of its
+// files, and the redaction strategies it may ask for (any when empty)
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct TenantLimits {
+    pub max_files: Option<usize>,
+    // Bytes of redacted output
+    pub max_stored_bytes: Option<usize>,
+    // For uploads that set no `ttl_seconds`, in place of `DEFAULT_FILE_TTL_SECONDS`
+    pub default_ttl_seconds: Option<u64>,
+    // Files expire by then at the latest; uploads may not ask for longer
+    pub max_ttl_seconds: Option<u64>,
+    #[serde(default)]
+    pub strategies: Vec<String>,
+}
+
+impl TenantLimits {
+    pub fn allows_strategy(&self, strategy: &str) -> bool {
+        self.strategies.is_empty() || self.strategies.iter().any(|allowed| allowed == strategy)
+    }
+
+    // TTL of a file the tenant uploads with `requested`, where storage would give it `fallback`
+    pub fn ttl_seconds(&self, requested: Option<u64>, fallback: Option<u64>) -> Option<u64> {
+        let ttl = requested.or(self.default_ttl_seconds).or(fallback);
+        match self.max_ttl_seconds {
+            Some(max) => Some(ttl.map_or(max, |ttl| ttl.min(max))),
+            None => ttl,
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.max_files == Some(0) || self.max_stored_bytes == Some(0) {
+            return Err(anyhow!("quotas must be positive"));
+        }
+        if self.default_ttl_seconds == Some(0) || self.max_ttl_seconds == Some(0) {
+            return Err(anyhow!("TTLs must be positive"));
+        }
+        if let (Some(default), Some(max)) = (self.default_ttl_seconds, self.max_ttl_seconds) {
+            if default > max {
+                return Err(anyhow!("default_ttl_seconds is above max_ttl_seconds"));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Strict uploads, those asking with `strict: true` and all of `tenants`, may not keep
+// output the analyzer was unsure of: detections of `entity_types` (any when empty)
+// scoring below `min_score`, or text a fallback backend analyzed
+#[derive(Clone, Debug, Deserialize)]
+pub struct StrictPolicy {
+    #[serde(default)]
+    pub entity_types: Vec<String>,
+    #[serde(default = "default_strict_min_score")]
+    pub min_score: f64,
+    #[serde(default)]
+    pub action: StrictAction,
+    #[serde(default)]
+    pub tenants: HashSet<String>,
+}
+
+fn default_strict_min_score() -> f64 {
+    0.85
+}
+
+impl Default for StrictPolicy {
+    fn default() -> Self {
+        Self { entity_types: Vec::new(), min_score: default_strict_min_score(), action: StrictAction::default(), tenants: HashSet::new() }
+    }
+}
+
+impl StrictPolicy {
+    pub fn applies(&self, requested: bool, tenant: Option<&str>) -> bool {
+        requested || tenant.is_some_and(|tenant| self.tenants.contains(tenant))
+    }
+
+    // Why the redaction cannot be trusted, if it cannot; never the values
+    pub fn violations(&self, report: &RedactionReport) -> Vec<String> {
+        let mut violations: Vec<String> = report.detections.iter()
+            .filter(|detection| self.entity_types.is_empty() || self.entity_types.contains(&detection.entity_type))
+            .filter(|detection| detection.score < self.min_score)
+            .map(|detection| format!("{} at bytes {}..{} scored {:.2}", detection.entity_type, detection.start, detection.end, detection.score))
+            .collect();
+        if report.fallback {
+            violations.push("a fallback backend analyzed part of the text".to_string());
+        }
+        violations
+    }
+}
+
+// What happens to a synchronous upload that arrives outside its tenant's processing
+// window: it fails, or is queued as a job. Queued jobs wait for the window either way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutsideWindow {
+    #[default]
+    Reject,
+    Queue,
+}
+
+// Daily UTC window in which a tenant's data may be processed, as `"HH:MM"` times in
+// minutes after midnight. One whose end is before its start runs past midnight.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ProcessingWindow {
+    #[serde(deserialize_with = "time_of_day")]
+    pub start: u32,
+    #[serde(deserialize_with = "time_of_day")]
+    pub end: u32,
+    #[serde(default)]
+    pub outside: OutsideWindow,
+}
+
+impl ProcessingWindow {
+    // Whether the window is open at `now`, in Unix seconds
+    pub fn contains(&self, now: u64) -> bool {
+        let minute = (now / 60 % MINUTES_PER_DAY) as u32;
+        match self.start < self.end {
+            true => (self.start..self.end).contains(&minute),
+            false => minute >= self.start || minute < self.end,
+        }
+    }
+
+    // Seconds until the window next opens; zero while it is open
+    pub fn opens_in(&self, now: u64) -> u64 {
+        if self.contains(now) {
+            return 0;
+        }
+        let start = u64::from(self.start) * 60;
+        (start + SECONDS_PER_DAY - now % SECONDS_PER_DAY) % SECONDS_PER_DAY
+    }
+}
+
+impl fmt::Display for ProcessingWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}-{:02}:{:02} UTC", self.start / 60, self.start % 60, self.end / 60, self.end % 60)
+    }
+}
+
+const MINUTES_PER_DAY: u64 = 24 * 60;
+const SECONDS_PER_DAY: u64 = MINUTES_PER_DAY * 60;
+
+// `"HH:MM"`, with `"24:00"` for the end of the day
+fn time_of_day<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    let minutes = value.split_once(':')
+        .and_then(|(hours, minutes)| Some((hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?)))
+        .filter(|(hours, minutes)| *minutes < 60 && (*hours < 24 || (*hours == 24 && *minutes == 0)))
+        .map(|(hours, minutes)| hours * 60 + minutes);
+    minutes.ok_or_else(|| serde::de::Error::custom(format!("invalid time of day {:?}, expected HH:MM", value)))
+}
+
+// Severities per entity type and the blocking rules evaluated against each upload's
+// report, loaded from `POLICY_PATH`:
+// { "severities": { "US_PASSPORT": "critical" }, "default_severity": "medium",
+//   "rules": [{ "name": "passport_review", "entity_types": ["US_PASSPORT"] }],
+//   "pipelines": { "acme": { "entities": { "US_SSN": [{ "op": "normalize" }, ...] } } },
+//   "bidi": "strip", "delivery_only": ["acme"],
+//   "processing_windows": { "acme": { "start": "00:00", "end": "06:00", "outside": "queue" } },
+//   "strict": { "entity_types": ["PERSON"], "min_score": 0.85, "action": "review", "tenants": ["acme"] },
+//   "boundaries": { "URL": { "mode": "expand", "joiners": ":/.?=&%-" }, "PERSON": { "mode": "contract" } },
+//   "tenants": { "acme": { "max_files": 10000, "max_ttl_seconds": 2592000, "strategies": ["replace", "mask"] } },
+//   "version": "2024-06" }
+// Pipelines, processing windows and tenant limits are per tenant, with `*` for tenants
+// without their own.
+#[derive(Clone, Deserialize)]
+pub struct RedactionPolicy {
+    #[serde(default)]
+    pub severities: HashMap<String, Severity>,
+    #[serde(default = "default_severity")]
+    pub default_severity: Severity,
+    #[serde(default)]
+    pub rules: Vec<BlockingRule>,
+    #[serde(default)]
+    pub pipelines: HashMap<String, PipelineSet>,
+    // Bidi control characters in uploads are stripped unless set to `neutralize` or `keep`
+    #[serde(default)]
+    pub bidi: BidiMode,
+    #[serde(default = "StageTimeouts::from_env")]
+    pub stage_timeouts: StageTimeouts,
+    // Tenants whose outputs may only leave through their delivery target (the storage
+    // bucket or a callback), never through downloads
+    #[serde(default)]
+    pub delivery_only: HashSet<String>,
+    #[serde(default)]
+    pub processing_windows: HashMap<String, ProcessingWindow>,
+    #[serde(default)]
+    pub strict: StrictPolicy,
+    // How detections starting or ending inside a word are moved, per entity type
+    #[serde(default)]
+    pub boundaries: BoundaryRules,
+    #[serde(default)]
+    pub deduplication: Deduplication,
+    #[serde(default)]
+    pub tenants: HashMap<String, TenantLimits>,
+    // Recorded in processing manifests; a digest of the policy file unless it names one
+    #[serde(default)]
+    pub version: String,
+}
+
+fn default_severity() -> Severity {
+    Severity::Medium
+}
+
+impl Default for RedactionPolicy {
+    fn default() -> Self {
+        Self { severities: HashMap::new(), default_severity: default_severity(), rules: Vec::new(), pipelines: HashMap::new(), bidi: BidiMode::default(), stage_timeouts: StageTimeouts::from_env(), delivery_only: HashSet::new(), processing_windows: HashMap::new(), strict: StrictPolicy::default(), boundaries: BoundaryRules::default(), deduplication: Deduplication::default(), tenants: HashMap::new(), version: "default".to_string() }
+    }
+}
+
+impl RedactionPolicy {
+    pub fn from_env() -> Result<Self> {
+        let Ok(path) = std::env::var("POLICY_PATH") else {
+            return Ok(Self::default());
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read redaction policy from {}: {}", path, e))?;
+        let mut policy: Self = serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("Invalid redaction policy: {}", e))?;
+        if policy.version.is_empty() {
+            let digest: String = Sha256::digest(contents.as_bytes()).iter().take(8).map(|b| format!("{:02x}", b)).collect();
+            policy.version = format!("sha256:{}", digest);
+        }
+        if let Some(rule) = policy.rules.iter().find(|rule| rule.entity_types.is_empty() && rule.min_severity.is_none()) {
+            return Err(anyhow!("Blocking rule {} needs entity_types or min_severity", rule.name));
+        }
+        policy.stage_timeouts.validate().map_err(|e| anyhow!("Invalid stage_timeouts: {}", e))?;
+        for (tenant, pipelines) in &policy.pipelines {
+            pipelines.validate().map_err(|e| anyhow!("Invalid pipelines for tenant {}: {}", tenant, e))?;
+        }
+        if let Some(tenant) = policy.processing_windows.iter().find(|(_, window)| window.start == window.end).map(|(tenant, _)| tenant) {
+            return Err(anyhow!("Processing window for tenant {} is empty", tenant));
+        }
+        if !(0.0..=1.0).contains(&policy.strict.min_score) {
+            return Err(anyhow!("strict.min_score must be between 0 and 1"));
+        }
+        for (tenant, limits) in &policy.tenants {
+            limits.validate().map_err(|e| anyhow!("Invalid limits for tenant {}: {}", tenant, e))?;
+        }
+
+        info!(
+            "Loaded redaction policy with {} severities, {} blocking rule(s) and pipelines for {} tenant(s)",
+            policy.severities.len(), policy.rules.len(), policy.pipelines.len()
+        );
+        Ok(policy)
+    }
+
+    // Pipelines replacing the redaction strategy for the tenant's uploads, if any
+    pub fn pipelines_for(&self, tenant: Option<&str>) -> Option<&PipelineSet> {
+        tenant.and_then(|tenant| self.pipelines.get(tenant)).or_else(|| self.pipelines.get("*"))
+    }
+
+    // Quotas, TTLs and strategies of the tenant's uploads; none are limited without an entry
+    pub fn limits_for(&self, tenant: Option<&str>) -> Option<&TenantLimits> {
+        tenant.and_then(|tenant| self.tenants.get(tenant)).or_else(|| self.tenants.get("*"))
+    }
+
+    // Adjusts detections to word boundaries, unless every entity type is kept as found
+    pub fn boundaries(&self) -> Option<&dyn BoundaryAdjuster> {
+        Some(&self.boundaries as &dyn BoundaryAdjuster).filter(|_| !self.boundaries.is_empty())
+    }
+
+    // Whether the tenant's redacted outputs may be downloaded, previewed or returned inline
+    pub fn downloads_allowed(&self, tenant: Option<&str>) -> bool {
+        tenant.is_none_or(|tenant| !self.delivery_only.contains(tenant))
+    }
+
+    pub fn processing_window(&self, tenant: Option<&str>) -> Option<&ProcessingWindow> {
+        tenant.and_then(|tenant| self.processing_windows.get(tenant)).or_else(|| self.processing_windows.get("*"))
+    }
+
+    // The tenant's processing window, when it is closed at `now`
+    pub fn closed_window(&self, tenant: Option<&str>, now: u64) -> Option<&ProcessingWindow> {
+        self.processing_window(tenant).filter(|window| !window.contains(now))
+    }
+
+    pub fn severity(&self, entity_type: &str) -> Severity {
+        self.severities.get(entity_type).copied().unwrap_or(self.default_severity)
+    }
+
+    // Highest severity among the detected entity types
+    pub fn max_severity(&self, report: &RedactionReport) -> Option<Severity> {
+        report.entities.keys().map(|entity_type| self.severity(entity_type)).max()
+    }
+
+    // The hold an upload with this report gets, if any rule matches
+    pub fn evaluate(&self, report: &RedactionReport) -> Option<ReviewHold> {
+        let rules: Vec<String> = self.rules.iter()
+            .filter(|rule| {
+                let count: usize = report.entities.iter()
+                    .filter(|(entity_type, _)| rule.entity_types.is_empty() || rule.entity_types.contains(entity_type))
+                    .filter(|(entity_type, _)| rule.min_severity.is_none_or(|min| self.severity(entity_type) >= min))
+                    .map(|(_, count)| count)
+                    .sum();
+                count >= rule.min_count.max(1)
+            })
+            .map(|rule| rule.name.clone())
+            .collect();
+
+        (!rules.is_empty()).then(|| ReviewHold { rules, max_severity: self.max_severity(report) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::Detection;
+
+    #[test]
+    fn test_rules_hold_matching_reports() {
+        let policy: RedactionPolicy = serde_json::from_value(serde_json::json!({
+            "severities": { "US_PASSPORT": "critical", "EMAIL_ADDRESS": "low" },
+            "rules": [
+                { "name": "passport_review", "entity_types": ["US_PASSPORT"] },
+                { "name": "bulk_pii", "min_severity": "medium", "min_count": 10 }
+            ]
+        }))
+        .unwrap();
+
+        let report = |entities: &[(&str, usize)]| RedactionReport {
+            entities: entities.iter().map(|(entity_type, count)| (entity_type.to_string(), *count)).collect(),
+            ..RedactionReport::default()
+        };
+
+        let hold = policy.evaluate(&report(&[("US_PASSPORT", 1), ("EMAIL_ADDRESS", 3)])).unwrap();
+        assert_eq!(hold.rules, vec!["passport_review"]);
+        assert_eq!(hold.max_severity, Some(Severity::Critical));
+
+        // Low-severity detections do not count towards `bulk_pii`
+        assert!(policy.evaluate(&report(&[("EMAIL_ADDRESS", 50)])).is_none());
+        let hold = policy.evaluate(&report(&[("PERSON", 6), ("PHONE_NUMBER", 4)])).unwrap();
+        assert_eq!(hold.rules, vec!["bulk_pii"]);
+        assert_eq!(hold.max_severity, Some(Severity::Medium));
+    }
+
+    #[test]
+    fn test_strict_uploads_flag_unsure_detections() {
+        let policy: RedactionPolicy = serde_json::from_value(serde_json::json!({
+            "strict": { "entity_types": ["PERSON"], "min_score": 0.8, "tenants": ["acme"] }
+        }))
+        .unwrap();
+        let strict = &policy.strict;
+        assert!(strict.applies(false, Some("acme")) && strict.applies(true, None) && !strict.applies(false, Some("globex")));
+
+        let detection = |entity_type: &str, score: f64| Detection { entity_type: entity_type.to_string(), start: 0, end: 8, score };
+        let mut report = RedactionReport {
+            detections: vec![detection("PERSON", 0.95), detection("PERSON", 0.41), detection("LOCATION", 0.3)],
+            ..RedactionReport::default()
+        };
+        assert_eq!(strict.violations(&report), ["PERSON at bytes 0..8 scored 0.41"]);
+        report.detections.truncate(1);
+        assert!(strict.violations(&report).is_empty());
+        report.fallback = true;
+        assert_eq!(strict.violations(&report), ["a fallback backend analyzed part of the text"]);
+        assert_eq!(strict.action, StrictAction::Reject);
+    }
+
+    #[test]
+    fn test_processing_windows_can_run_past_midnight() {
+        let policy: RedactionPolicy = serde_json::from_value(serde_json::json!({
+            "processing_windows": {
+                "acme": { "start": "00:00", "end": "06:00" },
+                "*": { "start": "22:30", "end": "02:00", "outside": "queue" }
+            }
+        }))
+        .unwrap();
+        let at = |hours: u64, minutes: u64| 19_000 * SECONDS_PER_DAY + hours * 3600 + minutes * 60;
+
+        let acme = policy.processing_window(Some("acme")).unwrap();
+        assert_eq!((acme.contains(at(5, 59)), acme.contains(at(6, 0))), (true, false));
+        assert_eq!(acme.opens_in(at(23, 0)), 3600);
+        assert_eq!(acme.to_string(), "00:00-06:00 UTC");
+
+        let other = policy.closed_window(Some("other"), at(12, 0)).unwrap();
+        assert_eq!((other.outside, other.opens_in(at(12, 0))), (OutsideWindow::Queue, 10 * 3600 + 1800));
+        assert!(policy.closed_window(None, at(23, 0)).is_none());
+        assert!(policy.closed_window(None, at(1, 59)).is_none());
+
+        assert!(serde_json::from_value::<ProcessingWindow>(serde_json::json!({ "start": "25:00", "end": "06:00" })).is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+
+use crate::report::Detection;
+
+// Newest `schema_version` of the sidecar's responses this service understands; responses
+// without one are version 1
+pub const SCHEMA_VERSION: u64 = 1;
+
+// A Presidio response in none of the shapes this service understands, or one that
+// contradicts itself, such as offsets past the end of the text
+#[derive(Debug)]
+pub struct ContractMismatch {
+    pub route: &'static str,
+    pub reason: String,
+}
+
+impl fmt::Display for ContractMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Presidio {} response does not match the contract: {}", self.route, self.reason)
+    }
+}
+
+impl std::error::Error for ContractMismatch {}
+
+// A `/redact` response. Only the sidecar's shape reports where in the input each entity
+// was, so `detections` is None for the others.
+pub struct Redacted {
+    pub shape: &'static str,
+    pub text: String,
+    pub detections: Option<Vec<Detection>>,
+}
+
+// A `/analyze` response
+pub struct Analyzed {
+    pub shape: &'static str,
+    pub detections: Vec<Detection>,
+}
+
+// An analyzer result, in characters of the input
+#[derive(Deserialize)]
+struct EntityDetail {
+    entity_type: String,
+    start: usize,
+    end: usize,
+    #[serde(default)]
+    score: f64,
+}
+
+// An anonymizer operator result, in characters of the output
+#[derive(Deserialize)]
+struct AnonymizerItem {
+    entity_type: String,
+    start: usize,
+    end: usize,
+}
+
+// The shapes `/redact` may answer in:
+// - the sidecar's `{"redacted_text", "entity_details"}`;
+// - Presidio anonymizer's own `{"text", "items"}`.
+pub fn parse_redact(input: &str, response: Value) -> Result<Redacted, ContractMismatch> {
+    let mismatch = |reason: String| ContractMismatch { route: "/redact", reason };
+    let Value::Object(mut fields) = response else {
+        return Err(mismatch(format!("expected an object, got {}", kind(&response))));
+    };
+    check_version(&fields).map_err(mismatch)?;
+
+    if let Some(redacted) = fields.remove("redacted_text") {
+        let Value::String(text) = redacted else {
+            return Err(mismatch(format!("redacted_text is {}, not a string", kind(&redacted))));
+        };
+        let details = fields.remove("entity_details").unwrap_or(Value::Array(Vec::new()));
+        let detections = entity_details(input, details, "entity_details").map_err(mismatch)?;
+        return Ok(Redacted { shape: "sidecar", text, detections: Some(detections) });
+    }
+
+    if let (Some(Value::String(text)), Some(items)) = (fields.remove("text"), fields.remove("items")) {
+        let items: Vec<AnonymizerItem> = parse_array(items, "items").map_err(mismatch)?;
+        let length = text.chars().count();
+        for (index, item) in items.iter().enumerate() {
+            if item.start > item.end || item.end > length {
+                return Err(mismatch(format!(
+                    "items[{}] ({}) spans {}..{}, outside the {} characters of text",
+                    index, item.entity_type, item.start, item.end, length
+                )));
+            }
+        }
+        return Ok(Redacted { shape: "anonymizer", text, detections: None });
+    }
+
+    Err(mismatch(format!(
+        "expected redacted_text, or text and items; got fields {}",
+        field_names(fields.keys())
+    )))
+}
+
+// The shapes `/analyze` may answer in:
+// - the sidecar's `{"entity_details"}`;
+// - Presidio analyzer's own bare array of results.
+pub fn parse_analyze(input: &str, response: Value) -> Result<Analyzed, ContractMismatch> {
+    let mismatch = |reason: String| ContractMismatch { route: "/analyze", reason };
+    match response {
+        Value::Array(_) => {
+            let detections = entity_details(input, response, "results").map_err(mismatch)?;
+            Ok(Analyzed { shape: "analyzer", detections })
+        }
+        Value::Object(mut fields) => {
+            check_version(&fields).map_err(mismatch)?;
+            let Some(details) = fields.remove("entity_details") else {
+                return Err(mismatch(format!("expected entity_details; got fields {}", field_names(fields.keys()))));
+            };
+            let detections = entity_details(input, details, "entity_details").map_err(mismatch)?;
+            Ok(Analyzed { shape: "sidecar", detections })
+        }
+        other => Err(mismatch(format!("expected an object or an array, got {}", kind(&other)))),
+    }
+}
+
+fn check_version(fields: &serde_json::Map<String, Value>) -> Result<(), String> {
+    match fields.get("schema_version") {
+        None => Ok(()),
+        Some(Value::Number(version)) => match version.as_u64() {
+            Some(version) if (1..=SCHEMA_VERSION).contains(&version) => Ok(()),
+            _ => Err(format!("schema_version {} is not supported; this service understands up to {}", version, SCHEMA_VERSION)),
+        },
+        Some(other) => Err(format!("schema_version is {}, not a number", kind(other))),
+    }
+}
+
+// Analyzer results with their character offsets mapped to bytes of `input`
+fn entity_details(input: &str, details: Value, field: &str) -> Result<Vec<Detection>, String> {
+    let details: Vec<EntityDetail> = parse_array(details, field)?;
+    let char_offsets: Vec<usize> = input.char_indices()
+        .map(|(offset, _)| offset)
+        .chain(std::iter::once(input.len()))
+        .collect();
+    details.into_iter()
+        .enumerate()
+        .map(|(index, detail)| match (char_offsets.get(detail.start), char_offsets.get(detail.end)) {
+            (Some(&start), Some(&end)) if start <= end => Ok(Detection { entity_type: detail.entity_type, start, end, score: detail.score }),
+            _ => Err(format!(
+                "{}[{}] ({}) spans {}..{}, outside the {} characters of the input",
+                field, index, detail.entity_type, detail.start, detail.end, char_offsets.len() - 1
+            )),
+        })
+        .collect()
+}
+
+// An array of `T`, naming the element that does not fit
+fn parse_array<T: for<'de> Deserialize<'de>>(value: Value, field: &str) -> Result<Vec<T>, String> {
+    let Value::Array(elements) = value else {
+        return Err(format!("{} is {}, not an array", field, kind(&value)));
+    };
+    elements.into_iter()
+        .enumerate()
+        .map(|(index, element)| serde_json::from_value(element).map_err(|e| format!("{}[{}]: {}", field, index, e)))
+        .collect()
+}
+
+fn field_names<'a>(names: impl Iterator<Item = &'a String>) -> String {
+    let names: Vec<&str> = names.map(String::as_str).collect();
+    match names.is_empty() {
+        true => "none".to_string(),
+        false => names.join(", "),
+    }
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_known_shapes_are_understood() {
+        let input = "Café owner jane@example.com";
+        let sidecar = parse_redact(input, json!({
+            "schema_version": 1,
+            "redacted_text": "Café owner <EMAIL_ADDRESS>",
+            "entity_details": [{ "entity_type": "EMAIL_ADDRESS", "start": 11, "end": 27, "score": 1.0 }],
+        }))
+        .unwrap();
+        let detection = &sidecar.detections.unwrap()[0];
+        // Character offsets become byte offsets
+        assert_eq!(&input[detection.start..detection.end], "jane@example.com");
+
+        let anonymizer = parse_redact(input, json!({
+            "text": "Café owner <EMAIL_ADDRESS>",
+            "items": [{ "operator": "replace", "entity_type": "EMAIL_ADDRESS", "start": 11, "end": 26, "text": "<EMAIL_ADDRESS>" }],
+        }))
+        .unwrap();
+        assert_eq!((anonymizer.shape, anonymizer.detections.is_none()), ("anonymizer", true));
+
+        let analyzer = parse_analyze(input, json!([{ "entity_type": "EMAIL_ADDRESS", "start": 11, "end": 27, "score": 1.0, "analysis_explanation": null }])).unwrap();
+        assert_eq!((analyzer.shape, analyzer.detections.len()), ("analyzer", 1));
+        assert_eq!(parse_analyze(input, json!({ "entity_details": [] })).unwrap().shape, "sidecar");
+    }
+
+    #[test]
+    fn test_mismatches_say_what_is_wrong() {
+        let reason = |result: Result<Redacted, ContractMismatch>| result.err().unwrap().reason;
+        assert_eq!(reason(parse_redact("x", json!({ "anonymized": "x" }))), "expected redacted_text, or text and items; got fields anonymized");
+        assert_eq!(reason(parse_redact("x", json!({ "redacted_text": 1 }))), "redacted_text is a number, not a string");
+        assert_eq!(
+            reason(parse_redact("x", json!({ "redacted_text": "x", "schema_version": 2 }))),
+            "schema_version 2 is not supported; this service understands up to 1"
+        );
+        assert_eq!(
+            reason(parse_redact("abc", json!({ "redacted_text": "x", "entity_details": [{ "entity_type": "PERSON", "start": 1, "end": 9 }] }))),
+            "entity_details[0] (PERSON) spans 1..9, outside the 3 characters of the input"
+        );
+        let missing = parse_analyze("abc", json!([{ "entity_type": "PERSON", "start": 0 }])).err().unwrap();
+        assert_eq!(missing.to_string(), "Presidio /analyze response does not match the contract: results[0]: missing field `end`");
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::{anyhow, Result};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::bidi;
+use crate::crypto::{self, CryptoService};
+use crate::envelope;
+use crate::report::{self, Detection};
+
+// Tokens handed out by the `pseudonymize` strategy. Each distinct value of an entity
+// type gets its own numbered token, e.g. `<PERSON_1>`, reused wherever it appears.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PseudonymMap {
+    // Token to original value
+    tokens: BTreeMap<String, String>,
+    // Only needed while tokens are handed out
+    #[serde(skip)]
+    assigned: HashMap<(String, String), String>,
+    #[serde(skip)]
+    counters: HashMap<String, usize>,
+}
+
+// A pseudonym map encrypted under its own ChaCha20-Poly1305 key, wrapped to the service
+// public key with RSA-OAEP-SHA256, with the file id bound as AAD
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SealedPseudonyms {
+    pub wrapped_key: String,
+    pub ciphertext: String,
+    pub nonce: String,
+}
+
+impl PseudonymMap {
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    pub fn token_for(&mut self, entity_type: &str, value: &str) -> String {
+        let key = (entity_type.to_string(), value.to_string());
+        if let Some(token) = self.assigned.get(&key) {
+            return token.clone();
+        }
+        let counter = self.counters.entry(entity_type.to_string()).or_default();
+        *counter += 1;
+        let token = format!("<{}_{}>", entity_type, counter);
+        self.tokens.insert(token.clone(), value.to_string());
+        self.assigned.insert(key, token.clone());
+        token
+    }
+
+    // Replace every detection in `text` with its token. None when a detection does not
+    // fall on character boundaries of `text`.
+    pub fn apply_to_text(&mut self, text: &str, detections: &[Detection]) -> Option<String> {
+        report::rewrite_detections(text, detections, |detection, value| self.token_for(&detection.entity_type, value))
+    }
+
+    // Put the original values back in place of the tokens, and drop the isolates tokens
+    // get in right-to-left text
+    pub fn unredact(&self, text: &str) -> String {
+        let mut output = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('<') {
+            output.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let original = rest.find('>').and_then(|end| Some((end, self.tokens.get(&rest[..=end])?)));
+            match original {
+                Some((end, original)) => {
+                    let len = bidi::unwrap_isolated(&mut output, rest, end + 1);
+                    output.push_str(original);
+                    rest = &rest[len..];
+                }
+                None => {
+                    output.push('<');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        output.push_str(rest);
+        output
+    }
+
+    pub fn seal(&self, public_key_pem: &str, file_id: &str) -> Result<SealedPseudonyms> {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        let plaintext = serde_json::to_vec(self).map_err(|e| anyhow!("Failed to encode pseudonyms: {}", e))?;
+        let (ciphertext, nonce) = crypto::encrypt_with_session_key(&plaintext, &key, file_id.as_bytes())?;
+
+        Ok(SealedPseudonyms {
+            wrapped_key: envelope::wrap_session_key(public_key_pem, &key)?,
+            ciphertext,
+            nonce,
+        })
+    }
+}
+
+impl SealedPseudonyms {
+    pub fn open(&self, crypto: &CryptoService, file_id: &str) -> Result<PseudonymMap> {
+        let key = crypto.decrypt_session_key(&self.wrapped_key)?;
+        let plaintext = crypto.decrypt_file_with_session_key(&self.ciphertext, &key, Some(&self.nonce), file_id.as_bytes())?;
+        serde_json::from_str(&plaintext).map_err(|e| anyhow!("Invalid pseudonyms: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pseudonyms_are_consistent_and_reversible() {
+        let detection = |entity_type: &str, start, end| Detection { entity_type: entity_type.to_string(), start, end, score: 1.0 };
+        let text = "jane@example.com wrote to bob@example.com, cc jane@example.com";
+        let detections = [detection("EMAIL_ADDRESS", 0, 16), detection("EMAIL_ADDRESS", 26, 41), detection("EMAIL_ADDRESS", 46, 62)];
+
+        let mut pseudonyms = PseudonymMap::default();
+        let redacted = pseudonyms.apply_to_text(text, &detections).unwrap();
+        assert_eq!(redacted, "<EMAIL_ADDRESS_1> wrote to <EMAIL_ADDRESS_2>, cc <EMAIL_ADDRESS_1>");
+        assert_eq!(pseudonyms.unredact(&redacted), text);
+        assert_eq!(pseudonyms.unredact("<a <EMAIL_ADDRESS_3> <EMAIL_ADDRESS_2"), "<a <EMAIL_ADDRESS_3> <EMAIL_ADDRESS_2");
+
+        let crypto = CryptoService::new().unwrap();
+        let sealed = pseudonyms.seal(&crypto.get_public_key().unwrap(), "f1").unwrap();
+        assert!(!sealed.ciphertext.contains("jane"));
+        assert_eq!(sealed.open(&crypto, "f1").unwrap().unredact(&redacted), text);
+        // Bound to the file it was stored with
+        assert!(sealed.open(&crypto, "f2").is_err());
+    }
+}
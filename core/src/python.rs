@@ -0,0 +1,61 @@
+// Python bindings for the parts of the pipeline that run without the service: client
+// envelope sealing and template extraction. Built with maturin (see core/pyproject.toml).
+
+use std::collections::HashMap;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::envelope;
+use crate::extract::{KeepRule, TemplateExtractor};
+
+// Seal plaintext for upload; returns a dict with the envelope and checksum fields of
+// the upload request
+#[pyfunction]
+fn seal_envelope<'py>(py: Python<'py>, public_key_pem: &str, plaintext: &[u8]) -> PyResult<Bound<'py, PyDict>> {
+    let sealed = envelope::seal(public_key_pem, plaintext)
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+
+    let dict = PyDict::new(py);
+    dict.set_item("encrypted_data", sealed.encrypted_data)?;
+    dict.set_item("encrypted_session_key", sealed.encrypted_session_key)?;
+    dict.set_item("nonce", sealed.nonce)?;
+    dict.set_item("ciphertext_sha256", sealed.ciphertext_sha256)?;
+    dict.set_item("plaintext_sha256", sealed.plaintext_sha256)?;
+    Ok(dict)
+}
+
+// The `extract` strategy: keep only the fields selected by `rules`, each a dict with
+// `name` and one of `field` or `pattern`
+#[pyfunction]
+fn extract(text: &str, rules: Vec<HashMap<String, String>>) -> PyResult<String> {
+    let rules = rules.into_iter()
+        .map(|mut rule| {
+            let name = rule.remove("name")
+                .ok_or_else(|| PyValueError::new_err("Keep rule is missing a name"))?;
+            Ok(KeepRule {
+                name,
+                field: rule.remove("field"),
+                pattern: rule.remove("pattern"),
+            })
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+
+    let extractor = TemplateExtractor::new(&rules)
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    Ok(extractor.extract(text))
+}
+
+#[pyfunction]
+fn sha256_hex(data: &[u8]) -> String {
+    envelope::hex(&crate::crypto::sha256(data))
+}
+
+#[pymodule]
+fn sentient_redactor_core(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(seal_envelope, m)?)?;
+    m.add_function(wrap_pyfunction!(extract, m)?)?;
+    m.add_function(wrap_pyfunction!(sha256_hex, m)?)?;
+    Ok(())
+}
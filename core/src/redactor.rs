@@ -0,0 +1,654 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::backend::{Analysis, EntityFilter, FallbackChain, RedactionBackend};
+use crate::bidi::{self, BidiMode};
+use crate::chunking::ChunkedBackend;
+use crate::boundary::BoundaryAdjuster;
+use crate::config::AppConfig;
+use crate::labels::LabelCatalog;
+use crate::language::Languages;
+use crate::masking::{HashMasking, HASH};
+use crate::pipeline::PipelineSet;
+use crate::presidio::{self, ContractMismatch};
+use crate::pseudonym::PseudonymMap;
+pub use crate::report::RedactionReport;
+use crate::report::{rewrite_detections, Detection};
+use crate::resilience::{CircuitBreaker, CircuitStatus, RetryPolicy};
+use crate::rules::{self, CustomRules, RegexEngine};
+use crate::settings::SharedSettings;
+use crate::spans::Segment;
+use crate::upstream;
+
+// Sentence the Presidio contract check redacts at startup, and the value it must find
+const CANARY_EMAIL: &str = "canary@example.com";
+const CONTRACT_CANARY: &str = "Send the contract check to canary@example.com.";
+
+// Strategy whose tokens can be reversed; see `pseudonymize_segments`
+pub const PSEUDONYMIZE: &str = "pseudonymize";
+
+#[derive(Clone, Copy)]
+pub struct RedactionOptions<'a> {
+    pub strategy: &'a str,
+    pub tenant: Option<&'a str>,
+    pub language: &'a str,
+    // The tenant's entity pipelines, used instead of `strategy` when set
+    pub pipelines: Option<&'a PipelineSet>,
+    // Entity types and minimum score the caller wants redacted
+    pub filter: EntityFilter<'a>,
+    // How bidi control characters in the text are handled
+    pub bidi: BidiMode,
+    // The request's own patterns and deny-list terms
+    pub custom: Option<&'a CustomRules>,
+    // Moves detections that start or end inside a word to its boundaries
+    pub boundaries: Option<&'a dyn BoundaryAdjuster>,
+    // Surrogates of the `hash` strategy, which needs them
+    pub hashing: Option<&'a HashMasking>,
+}
+
+pub struct RedactorService {
+    backend: Box<dyn RedactionBackend>,
+    labels: LabelCatalog,
+    languages: Languages,
+}
+
+impl RedactorService {
+    pub fn new() -> Result<Self> {
+        Self::from_config(&AppConfig::from_env()?)
+    }
+
+    pub fn from_config(config: &AppConfig) -> Result<Self> {
+        Self::from_settings(config, config.runtime_settings()?.shared())
+    }
+
+    // As `from_config`, reaching Presidio at whatever URL `settings` hold as they change
+    pub fn from_settings(config: &AppConfig, settings: SharedSettings) -> Result<Self> {
+        let backend = backends(config, settings)?;
+        let labels = LabelCatalog::from_env()?;
+
+        info!("RedactorService initialized with redaction backend: {}", backend.name());
+
+        Ok(Self::with_backend(backend, labels).with_languages(config.languages()?))
+    }
+
+    pub fn with_backend(backend: Box<dyn RedactionBackend>, labels: LabelCatalog) -> Self {
+        Self { backend, labels, languages: Languages::default() }
+    }
+
+    pub fn with_languages(self, languages: Languages) -> Self {
+        Self { languages, ..self }
+    }
+
+    pub fn languages(&self) -> &Languages {
+        &self.languages
+    }
+
+    pub fn backend_name(&self) -> &str {
+        self.backend.name()
+    }
+
+    pub fn entities(&self) -> Vec<&str> {
+        self.backend.entities()
+    }
+
+    pub fn circuits(&self) -> Vec<CircuitStatus> {
+        self.backend.circuits()
+    }
+
+    // Confirm remote backends answer in shapes this service understands; a
+    // `ContractMismatch` among the errors tells a misbehaving upstream from an unreachable one
+    pub async fn check_contract(&self) -> Result<()> {
+        self.backend.check_contract().await
+    }
+
+    pub async fn ping(&self) -> Result<()> {
+        self.backend.ping().await
+    }
+
+    // Redact resolved segments: analyzed segments go through Presidio (with localized
+    // labels for the replace strategy), protected ones pass through, forced ones are masked
+    pub async fn redact_segments(&self, segments: &[Segment<'_>], options: &RedactionOptions<'_>) -> Result<String> {
+        let (output, _) = self.redact_segments_with_report(segments, options).await?;
+        Ok(output)
+    }
+
+    // Same as `redact_segments`, also reporting what was redacted (never the values)
+    pub async fn redact_segments_with_report(
+        &self,
+        segments: &[Segment<'_>],
+        options: &RedactionOptions<'_>,
+    ) -> Result<(String, RedactionReport)> {
+        let mut pseudonyms = PseudonymMap::default();
+        let (outputs, report) = self.redact(segments, options, &mut pseudonyms).await?;
+        Ok((joined(outputs, options.bidi), report))
+    }
+
+    // The `pseudonymize` strategy, also returning the map that reverses its tokens
+    pub async fn pseudonymize_segments(
+        &self,
+        segments: &[Segment<'_>],
+        options: &RedactionOptions<'_>,
+    ) -> Result<(String, RedactionReport, PseudonymMap)> {
+        let options = RedactionOptions { strategy: PSEUDONYMIZE, ..*options };
+        let mut pseudonyms = PseudonymMap::default();
+        let (outputs, report) = self.redact(segments, &options, &mut pseudonyms).await?;
+        Ok((joined(outputs, options.bidi), report, pseudonyms))
+    }
+
+    // Redact independent fields, such as the string values of a JSON document, returning
+    // each one's output. Detections are reported against the fields laid end to end, and
+    // pseudonyms (returned for that strategy) are shared across fields.
+    pub async fn redact_fields(
+        &self,
+        fields: &[&str],
+        options: &RedactionOptions<'_>,
+    ) -> Result<(Vec<String>, RedactionReport, PseudonymMap)> {
+        let segments: Vec<Segment<'_>> = fields.iter().map(|field| Segment::Analyze(field)).collect();
+        let mut pseudonyms = PseudonymMap::default();
+        let (mut outputs, report) = self.redact(&segments, options, &mut pseudonyms).await?;
+        if options.bidi == BidiMode::Neutralize {
+            for output in &mut outputs {
+                *output = bidi::close_open_runs(output).into_owned();
+            }
+        }
+        Ok((outputs, report, pseudonyms))
+    }
+
+    async fn redact(
+        &self,
+        segments: &[Segment<'_>],
+        options: &RedactionOptions<'_>,
+        pseudonyms: &mut PseudonymMap,
+    ) -> Result<(Vec<String>, RedactionReport)> {
+        let pseudonymize = options.strategy == PSEUDONYMIZE;
+        let hashing = match options.strategy == HASH {
+            true => Some(options.hashing.ok_or_else(|| anyhow!("The hash strategy needs a masking key"))?),
+            false => None,
+        };
+        // Backends know neither pseudonyms nor surrogates; they are assigned here from the detections
+        let backend_strategy = if pseudonymize || hashing.is_some() { "replace" } else { options.strategy };
+        // Markers are isolated so they cannot reorder right-to-left text around them
+        let isolate = options.bidi != BidiMode::Keep && segments.iter().any(|segment| match segment {
+            Segment::Analyze(text) | Segment::Keep(text) | Segment::Redact(text) => bidi::has_rtl(text),
+        });
+        let isolated = |text: &str| if isolate { bidi::isolate_markers(text) } else { text.to_string() };
+        let mut outputs = Vec::with_capacity(segments.len());
+        let mut report = RedactionReport::default();
+        // Segments tile the plaintext in order, so this is each one's offset into it
+        let mut offset = 0;
+
+        for segment in segments {
+            let mut output = String::new();
+            match segment {
+                Segment::Analyze(text) if text.trim().is_empty() => output.push_str(text),
+                Segment::Analyze(original) => {
+                    // Detections are found in the sanitized text and reported against the original
+                    let sanitized = bidi::sanitize(original, options.bidi);
+                    let text = sanitized.text.as_ref();
+                    let Analysis { mut redacted, mut detections, fallback } = self.backend.analyze(text, backend_strategy, options.filter).await?;
+                    report.fallback |= fallback;
+                    // The backend redacted the detections where it found them, so moved ones
+                    // are redacted again from the text
+                    if options.boundaries.is_some_and(|boundaries| boundaries.adjust(text, &mut detections)) {
+                        redacted = rewrite_detections(text, &detections, |detection, _| rules::replacement(&detection.entity_type, backend_strategy))
+                            .ok_or_else(|| anyhow!("Detections do not fall on character boundaries"))?;
+                    }
+                    detections.extend(custom_detections(options.custom, text, &detections));
+                    detections.sort_by_key(|detection| detection.start);
+                    let tokenized = match (pseudonymize, hashing) {
+                        (true, _) => Some(pseudonyms.apply_to_text(text, &detections)),
+                        (false, Some(hashing)) => Some(hashing.apply_to_text(text, &detections)),
+                        (false, None) => None,
+                    };
+                    let tokenized = tokenized.map(|tokenized| tokenized.ok_or_else(|| anyhow!("Detections do not fall on character boundaries"))).transpose()?;
+                    let piped = options.pipelines.and_then(|pipelines| pipelines.apply_to_text(text, &detections));
+                    for mut detection in detections {
+                        *report.entities.entry(detection.entity_type.clone()).or_default() += 1;
+                        detection.start = sanitized.original_start(detection.start) + offset;
+                        detection.end = sanitized.original_end(detection.end) + offset;
+                        report.detections.push(detection);
+                    }
+                    // Tokens and pipelines cover the custom matches; the backend's output does not
+                    let redacted = match options.custom {
+                        Some(rules) if tokenized.is_none() && piped.is_none() => rules.apply(&redacted, options.strategy),
+                        _ => redacted,
+                    };
+                    if let Some(tokenized) = tokenized {
+                        output.push_str(&isolated(&tokenized));
+                    } else if let Some(piped) = piped {
+                        output.push_str(&self.labels.localize(&isolated(&piped), options.tenant, options.language));
+                    } else if options.strategy == "replace" {
+                        output.push_str(&self.labels.localize(&isolated(&redacted), options.tenant, options.language));
+                    } else {
+                        output.push_str(&isolated(&redacted));
+                    }
+                }
+                // Protected text is not analyzed, but its bidi controls are handled all the same
+                Segment::Keep(text) => {
+                    report.protected_segments += 1;
+                    output.push_str(&bidi::sanitize(text, options.bidi).text);
+                }
+                Segment::Redact(text) => {
+                    report.forced_redactions += 1;
+                    let marker = match hashing {
+                        _ if pseudonymize => pseudonyms.token_for("REDACTED", text),
+                        Some(hashing) => hashing.surrogate("REDACTED", text),
+                        None => forced_redaction_marker(options.strategy).to_string(),
+                    };
+                    output.push_str(&isolated(&marker));
+                }
+            }
+            offset += match segment {
+                Segment::Analyze(text) | Segment::Keep(text) | Segment::Redact(text) => text.len(),
+            };
+            outputs.push(output);
+        }
+
+        Ok((outputs, report))
+    }
+
+    // Only the detection phase, for dry runs: what redacting `segments` would remove,
+    // against the text the segments tile, in order. Protected and forced spans are not analyzed.
+    pub async fn detect_segments(
+        &self,
+        segments: &[Segment<'_>],
+        filter: EntityFilter<'_>,
+        mode: BidiMode,
+        custom: Option<&CustomRules>,
+        boundaries: Option<&dyn BoundaryAdjuster>,
+    ) -> Result<Vec<Detection>> {
+        let mut detections = Vec::new();
+        let mut offset = 0;
+        for segment in segments {
+            if let Segment::Analyze(original) = segment {
+                if !original.trim().is_empty() {
+                    let sanitized = bidi::sanitize(original, mode);
+                    let mut found = self.backend.detect(&sanitized.text, filter).await?;
+                    if let Some(boundaries) = boundaries {
+                        boundaries.adjust(&sanitized.text, &mut found);
+                    }
+                    found.extend(custom_detections(custom, &sanitized.text, &found));
+                    for mut detection in found {
+                        detection.start = sanitized.original_start(detection.start) + offset;
+                        detection.end = sanitized.original_end(detection.end) + offset;
+                        detections.push(detection);
+                    }
+                }
+            }
+            offset += match segment {
+                Segment::Analyze(text) | Segment::Keep(text) | Segment::Redact(text) => text.len(),
+            };
+        }
+        detections.sort_by_key(|detection| detection.start);
+        Ok(detections)
+    }
+
+    pub async fn redact_text_with_strategy(&self, text: &str, strategy: &str, filter: EntityFilter<'_>) -> Result<String> {
+        Ok(self.backend.analyze(text, strategy, filter).await?.redacted)
+    }
+}
+
+// `redaction_backend` lists the backends to try in order, e.g. `presidio,regex` to fall
+// back to the built-in rules when Presidio is unreachable. Defaults to `presidio`. Long
+// texts are split into chunks in front of the whole chain.
+fn backends(config: &AppConfig, settings: SharedSettings) -> Result<Box<dyn RedactionBackend>> {
+    let mut backends: Vec<Box<dyn RedactionBackend>> = Vec::new();
+    for name in config.redaction_backend.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        match name {
+            "presidio" => backends.push(Box::new(PresidioBackend::from_config(config, settings.clone())?)),
+            "regex" => backends.push(Box::new(RegexEngine::new())),
+            "mock" => {
+                warn!("Using the mock redaction backend, which only detects test fixtures; do not use it in production");
+                backends.push(Box::new(RegexEngine::mock()));
+            }
+            "onnx" => backends.push(ner_backend(config)?),
+            other => return Err(anyhow!("Unknown redaction backend: {}", other)),
+        }
+    }
+
+    let backend: Box<dyn RedactionBackend> = match backends.len() {
+        1 => backends.remove(0),
+        _ => Box::new(FallbackChain::new(backends)?),
+    };
+    let chunked = ChunkedBackend::new(backend, config.redaction_chunk_bytes, config.redaction_chunk_overlap_bytes, config.redaction_chunk_concurrency)?;
+    Ok(Box::new(chunked))
+}
+
+#[cfg(feature = "onnx")]
+fn ner_backend(config: &AppConfig) -> Result<Box<dyn RedactionBackend>> {
+    let dir = config.ner_model_dir.as_deref().ok_or_else(|| anyhow!("The onnx redaction backend needs ner_model_dir"))?;
+    Ok(Box::new(crate::ner::NerBackend::load(dir)?))
+}
+
+#[cfg(not(feature = "onnx"))]
+fn ner_backend(_config: &AppConfig) -> Result<Box<dyn RedactionBackend>> {
+    Err(anyhow!("The onnx redaction backend needs the service built with the onnx feature"))
+}
+
+// Presidio analyzer/anonymizer service over HTTP. Transient failures (connection
+// errors, timeouts, 429 and 5xx) are retried with backoff, and a circuit breaker fails
+// calls fast while Presidio keeps failing. Its URL is read from the shared settings on
+// every call, so a change through the admin API applies to the next one.
+pub struct PresidioBackend {
+    client: Client,
+    settings: SharedSettings,
+    retry: RetryPolicy,
+    breaker: CircuitBreaker,
+}
+
+// A failed call to Presidio, and whether trying again may help
+struct PresidioError {
+    error: anyhow::Error,
+    transient: bool,
+}
+
+impl PresidioBackend {
+    pub fn from_config(config: &AppConfig, settings: SharedSettings) -> Result<Self> {
+        let timeout = Duration::from_secs(config.presidio_timeout_seconds);
+        let client = upstream::client_builder_with_tls("PRESIDIO", timeout, config.presidio_tls()?)?
+            .build()
+            .map_err(|e| anyhow!("Failed to create HTTP client for PRESIDIO: {}", e))?;
+        info!("Presidio backend using URL: {}", settings.load().presidio_url);
+
+        Ok(Self {
+            client,
+            settings,
+            retry: RetryPolicy::from_env("PRESIDIO"),
+            breaker: CircuitBreaker::from_env("presidio", "PRESIDIO"),
+        })
+    }
+
+    async fn request(&self, path: &str, body: &Value) -> Result<Value, PresidioError> {
+        let response = self.client
+            .post(format!("{}{}", self.settings.load().presidio_url, path))
+            .json(body)
+            .send()
+            .await
+            .map_err(|e| PresidioError { error: anyhow!("Presidio request failed: {}", e), transient: true })?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(PresidioError {
+                error: anyhow!("Presidio error ({}): {}", status, error_text),
+                transient: status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+            });
+        }
+
+        response.json().await
+            .map_err(|e| PresidioError { error: anyhow!("Failed to parse response: {}", e), transient: false })
+    }
+
+    // `request` behind the circuit breaker, retrying transient failures
+    async fn call(&self, path: &str, body: Value) -> Result<Value> {
+        self.breaker.allow()?;
+        let mut attempt = 0;
+        let result = loop {
+            match self.request(path, &body).await {
+                Err(e) if e.transient && attempt < self.retry.max_retries => {
+                    let delay = self.retry.delay(attempt);
+                    warn!("{}; retrying in {} ms", e.error, delay.as_millis());
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => break result,
+            }
+        };
+        // Requests Presidio rejected show that it is up
+        self.breaker.record(result.as_ref().map_or_else(|e| !e.transient, |_| true));
+        result.map_err(|e| e.error)
+    }
+}
+
+#[async_trait]
+impl RedactionBackend for PresidioBackend {
+    fn name(&self) -> &str {
+        "presidio"
+    }
+
+    async fn analyze(&self, text: &str, strategy: &str, filter: EntityFilter<'_>) -> Result<Analysis> {
+        let result = self.call("/redact", json!({
+            "text": text,
+            "strategy": strategy,
+            "entities": filter.entities,
+            "score_threshold": filter.score_threshold,
+            "language": filter.language
+        }))
+        .await?;
+
+        let redacted = presidio::parse_redact(text, result)?;
+        // Offsets into the output cannot be mapped back to the input, so they are asked for
+        let detections = match redacted.detections {
+            Some(detections) => detections,
+            None => self.detect(text, filter).await?,
+        };
+        Ok(Analysis { redacted: redacted.text, detections, fallback: false })
+    }
+
+    async fn detect(&self, text: &str, filter: EntityFilter<'_>) -> Result<Vec<Detection>> {
+        let result = self.call("/analyze", json!({
+            "text": text,
+            "entities": filter.entities,
+            "score_threshold": filter.score_threshold,
+            "language": filter.language
+        }))
+        .await?;
+        Ok(presidio::parse_analyze(text, result)?.detections)
+    }
+
+    // Redact and analyze a canary sentence, without retries or the circuit breaker
+    async fn check_contract(&self) -> Result<()> {
+        let body = json!({ "text": CONTRACT_CANARY, "strategy": "replace" });
+        let redacted = presidio::parse_redact(CONTRACT_CANARY, self.request("/redact", &body).await.map_err(|e| e.error)?)?;
+        if redacted.text.contains(CANARY_EMAIL) {
+            return Err(ContractMismatch { route: "/redact", reason: "the canary email address was left in the redacted text".to_string() }.into());
+        }
+        let body = json!({ "text": CONTRACT_CANARY });
+        let analyzed = presidio::parse_analyze(CONTRACT_CANARY, self.request("/analyze", &body).await.map_err(|e| e.error)?)?;
+        if !analyzed.detections.iter().any(|detection| &CONTRACT_CANARY[detection.start..detection.end] == CANARY_EMAIL) {
+            return Err(ContractMismatch { route: "/analyze", reason: "the canary email address was not detected where it is".to_string() }.into());
+        }
+        info!("Presidio contract check passed: /redact answers in the {} shape, /analyze in the {} shape", redacted.shape, analyzed.shape);
+        Ok(())
+    }
+
+    // Presidio's own health route, once and outside the circuit breaker, so the probe
+    // sees Presidio come back before the breaker does
+    async fn ping(&self) -> Result<()> {
+        let response = self.client
+            .get(format!("{}/health", self.settings.load().presidio_url))
+            .send()
+            .await
+            .map_err(|e| anyhow!("Presidio request failed: {}", e))?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            status => Err(anyhow!("Presidio health check returned {}", status)),
+        }
+    }
+
+    fn circuits(&self) -> Vec<CircuitStatus> {
+        vec![self.breaker.status()]
+    }
+}
+
+// Matches of the request's own rules that the backend's detections do not already cover
+fn custom_detections(custom: Option<&CustomRules>, text: &str, detections: &[Detection]) -> Vec<Detection> {
+    let Some(rules) = custom else { return Vec::new() };
+    rules.detect(text)
+        .into_iter()
+        .filter(|found| !detections.iter().any(|detection| detection.start < found.end && found.start < detection.end))
+        .collect()
+}
+
+// Segment outputs as one text, with runs left open closed in `Neutralize` mode
+fn joined(outputs: Vec<String>, mode: BidiMode) -> String {
+    let output = outputs.concat();
+    match mode {
+        BidiMode::Neutralize => bidi::close_open_runs(&output).into_owned(),
+        _ => output,
+    }
+}
+
+fn forced_redaction_marker(strategy: &str) -> &'static str {
+    match strategy {
+        "mask" => "****",
+        "custom" => "[REDACTED]",
+        _ => "<REDACTED>",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boundary::BoundaryRules;
+    use crate::rules::CustomPattern;
+
+    // Finds `jane` wherever it occurs, as an analyzer might inside identifiers
+    struct Substrings;
+
+    #[async_trait]
+    impl RedactionBackend for Substrings {
+        fn name(&self) -> &str {
+            "substrings"
+        }
+
+        async fn analyze(&self, text: &str, strategy: &str, _filter: EntityFilter<'_>) -> Result<Analysis> {
+            let detections: Vec<Detection> = text.match_indices("jane")
+                .map(|(start, value)| Detection { entity_type: "PERSON".to_string(), start, end: start + value.len(), score: 0.7 })
+                .collect();
+            let redacted = rewrite_detections(text, &detections, |detection, _| rules::replacement(&detection.entity_type, strategy)).unwrap();
+            Ok(Analysis { redacted, detections, fallback: false })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_redactor_service() {
+        let redactor = RedactorService::new().unwrap();
+        let text = "My name is John Doe and my email is john@example.com";
+        let redacted = redactor.redact_text_with_strategy(text, "replace", EntityFilter::default()).await.unwrap();
+        println!("Redacted: {}", redacted);
+        assert!(!redacted.contains("John Doe"));
+        assert!(!redacted.contains("john@example.com"));
+        assert!(redacted.contains("<PERSON>"));
+        assert!(redacted.contains("<EMAIL_ADDRESS>"));
+    }
+
+    #[tokio::test]
+    async fn test_regex_backend_runs_without_presidio() {
+        let redactor = RedactorService::with_backend(Box::new(RegexEngine::new()), LabelCatalog::default());
+        assert_eq!(redactor.backend_name(), "regex");
+
+        let segments = [Segment::Keep("Ref: "), Segment::Analyze("Reach me at john@example.com"), Segment::Redact("MRN 42")];
+        let options = RedactionOptions { strategy: "replace", tenant: None, language: "en", pipelines: None, filter: EntityFilter::default(), bidi: BidiMode::default(), custom: None, boundaries: None, hashing: None };
+        let (redacted, report) = redactor.redact_segments_with_report(&segments, &options).await.unwrap();
+        assert_eq!(redacted, "Ref: Reach me at <EMAIL_ADDRESS><REDACTED>");
+        assert_eq!(report.entities["EMAIL_ADDRESS"], 1);
+
+        // Offsets are into the whole plaintext, not the analyzed segment
+        let detection = &report.detections[0];
+        assert_eq!((detection.start, detection.end), (17, 33));
+
+        let pipelines: PipelineSet = serde_json::from_value(json!({
+            "entities": { "EMAIL_ADDRESS": [{ "op": "mask", "keep_last": 12 }] }
+        }))
+        .unwrap();
+        let options = RedactionOptions { pipelines: Some(&pipelines), ..options };
+        let (redacted, _) = redactor.redact_segments_with_report(&segments, &options).await.unwrap();
+        assert_eq!(redacted, "Ref: Reach me at ****@example.com<REDACTED>");
+
+        let (redacted, _, pseudonyms) = redactor.pseudonymize_segments(&segments, &options).await.unwrap();
+        assert_eq!(redacted, "Ref: Reach me at <EMAIL_ADDRESS_1><REDACTED_1>");
+        assert_eq!(pseudonyms.unredact(&redacted), "Ref: Reach me at john@example.comMRN 42");
+
+        // Hash surrogates cover the forced redaction too, and need a key
+        let hashing = HashMasking::new([3; 32], Some(8)).unwrap();
+        let hashed = RedactionOptions { strategy: HASH, pipelines: None, hashing: Some(&hashing), ..options };
+        let (redacted, _) = redactor.redact_segments_with_report(&segments, &hashed).await.unwrap();
+        let expected = format!("Ref: Reach me at {}{}", hashing.surrogate("EMAIL_ADDRESS", "john@example.com"), hashing.surrogate("REDACTED", "MRN 42"));
+        assert_eq!(redacted, expected);
+        assert!(redactor.redact_segments(&segments, &RedactionOptions { hashing: None, ..hashed }).await.is_err());
+
+        // Fields come back one by one, and share pseudonyms
+        let fields = ["john@example.com", "cc john@example.com", "42"];
+        let options = RedactionOptions { strategy: PSEUDONYMIZE, pipelines: None, ..options };
+        let (outputs, report, _) = redactor.redact_fields(&fields, &options).await.unwrap();
+        assert_eq!(outputs, ["<EMAIL_ADDRESS_1>", "cc <EMAIL_ADDRESS_1>", "42"]);
+        assert_eq!((report.detections[1].start, report.detections[1].end), (19, 35));
+    }
+
+    #[tokio::test]
+    async fn test_bidi_controls_cannot_hide_entities() {
+        let redactor = RedactorService::with_backend(Box::new(RegexEngine::new()), LabelCatalog::default());
+        // An override inside the email splits it for detection and reverses how it renders
+        let text = "مرحبا، راسلني على jane@exa\u{202e}mple.com أو שלום 192.168.0.1";
+        let segments = [Segment::Analyze(text), Segment::Redact("ملف")];
+        let options = RedactionOptions { strategy: "replace", tenant: None, language: "en", pipelines: None, filter: EntityFilter::default(), bidi: BidiMode::Strip, custom: None, boundaries: None, hashing: None };
+        let (redacted, report) = redactor.redact_segments_with_report(&segments, &options).await.unwrap();
+        assert_eq!(
+            redacted,
+            "مرحبا، راسلني على \u{2068}<EMAIL_ADDRESS>\u{2069} أو שלום \u{2068}<IP_ADDRESS>\u{2069}\u{2068}<REDACTED>\u{2069}"
+        );
+        // Reported against the text as uploaded, control included
+        let detection = &report.detections[0];
+        assert_eq!(&text[detection.start..detection.end], "jane@exa\u{202e}mple.com");
+
+        let (redacted, _, pseudonyms) = redactor.pseudonymize_segments(&segments, &options).await.unwrap();
+        assert_eq!(pseudonyms.unredact(&redacted), "مرحبا، راسلني على jane@example.com أو שלום 192.168.0.1ملف");
+
+        // Kept, the override is an unterminated run reversing the rest of the line
+        let options = RedactionOptions { bidi: BidiMode::Neutralize, ..options };
+        let (redacted, _) = redactor.redact_segments_with_report(&segments, &options).await.unwrap();
+        assert!(redacted.starts_with("مرحبا، راسلني على jane@exa\u{202b}mple.com أو"), "{}", redacted);
+        assert!(redacted.ends_with("\u{2068}<REDACTED>\u{2069}\u{202c}"), "{}", redacted);
+    }
+
+    #[tokio::test]
+    async fn test_custom_rules_run_after_the_backend() {
+        let redactor = RedactorService::with_backend(Box::new(RegexEngine::new()), LabelCatalog::default());
+        let patterns = [CustomPattern { name: "EMPLOYEE_ID".to_string(), regex: r"EMP-\d+".to_string(), replacement: Some("[EMPLOYEE]".to_string()) }];
+        // The term overlaps the email the backend found, which keeps it
+        let custom = CustomRules::new(&patterns, &["Falcon".to_string()]).unwrap();
+        let text = "EMP-42 on Falcon: falcon@example.com";
+        let segments = [Segment::Analyze(text)];
+        let options = RedactionOptions { strategy: "replace", tenant: None, language: "en", pipelines: None, filter: EntityFilter::default(), bidi: BidiMode::default(), custom: Some(&custom), boundaries: None, hashing: None };
+
+        let (redacted, report) = redactor.redact_segments_with_report(&segments, &options).await.unwrap();
+        assert_eq!(redacted, "[EMPLOYEE] on <DENY_LIST>: <EMAIL_ADDRESS>");
+        assert_eq!((report.entities["EMPLOYEE_ID"], report.entities["DENY_LIST"], report.entities["EMAIL_ADDRESS"]), (1, 1, 1));
+
+        let (redacted, _, pseudonyms) = redactor.pseudonymize_segments(&segments, &options).await.unwrap();
+        assert_eq!(redacted, "<EMPLOYEE_ID_1> on <DENY_LIST_1>: <EMAIL_ADDRESS_1>");
+        assert_eq!(pseudonyms.unredact(&redacted), text);
+
+        let detections = redactor.detect_segments(&segments, EntityFilter::default(), BidiMode::default(), Some(&custom), None).await.unwrap();
+        assert_eq!(detections.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_boundaries_redact_whole_words() {
+        let redactor = RedactorService::with_backend(Box::new(Substrings), LabelCatalog::default());
+        let text = "Ping jane at ops_jane_oncall, see https://wiki.example.com/jane.";
+        let segments = [Segment::Analyze(text)];
+        let options = RedactionOptions { strategy: "custom", tenant: None, language: "en", pipelines: None, filter: EntityFilter::default(), bidi: BidiMode::default(), custom: None, boundaries: None, hashing: None };
+        let (redacted, _) = redactor.redact_segments_with_report(&segments, &options).await.unwrap();
+        assert_eq!(redacted, "Ping [REDACTED_NAME] at ops_[REDACTED_NAME]_oncall, see https://wiki.example.com/[REDACTED_NAME].");
+
+        let boundaries: BoundaryRules = serde_json::from_value(json!({ "PERSON": { "mode": "expand", "joiners": ":/." } })).unwrap();
+        let options = RedactionOptions { boundaries: Some(&boundaries), ..options };
+        let (redacted, report) = redactor.redact_segments_with_report(&segments, &options).await.unwrap();
+        assert_eq!(redacted, "Ping [REDACTED_NAME] at [REDACTED_NAME], see [REDACTED_NAME]");
+        assert_eq!(&text[report.detections[2].start..report.detections[2].end], "https://wiki.example.com/jane.");
+
+        let boundaries: BoundaryRules = serde_json::from_value(json!({ "PERSON": { "mode": "contract" } })).unwrap();
+        let options = RedactionOptions { boundaries: Some(&boundaries), ..options };
+        let (redacted, report) = redactor.redact_segments_with_report(&segments, &options).await.unwrap();
+        assert_eq!(redacted, "Ping [REDACTED_NAME] at ops_jane_oncall, see https://wiki.example.com/[REDACTED_NAME].");
+        assert_eq!(report.entities["PERSON"], 2);
+        let detections = redactor.detect_segments(&segments, EntityFilter::default(), BidiMode::default(), None, Some(&boundaries)).await.unwrap();
+        assert_eq!(detections.len(), 2);
+    }
+}
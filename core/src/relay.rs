@@ -0,0 +1,154 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use tracing::info;
+
+// Outer envelope added by a relay/gateway around a client upload. The client signs the
+// digest of its ciphertext; the relay countersigns the digest plus the client signature.
+#[derive(Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RelayEnvelope {
+    pub relay_id: String,
+    pub client_id: String,
+    pub client_signature: String,
+    pub relay_signature: String,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RelayIdentities {
+    pub relay_id: String,
+    pub client_id: String,
+}
+
+// Ed25519 public keys of known relays and clients, loaded from a JSON file shaped as
+// { "relays": { "<id>": "<base64 key>" }, "clients": { "<id>": "<base64 key>" } }
+#[derive(Default)]
+pub struct RelayRegistry {
+    relays: HashMap<String, VerifyingKey>,
+    clients: HashMap<String, VerifyingKey>,
+}
+
+#[derive(Deserialize)]
+struct RegistryFile {
+    #[serde(default)]
+    relays: HashMap<String, String>,
+    #[serde(default)]
+    clients: HashMap<String, String>,
+}
+
+impl RelayRegistry {
+    pub fn from_env() -> Result<Self> {
+        let Ok(path) = std::env::var("RELAY_IDENTITIES_PATH") else {
+            return Ok(Self::default());
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read relay identities from {}: {}", path, e))?;
+        let file: RegistryFile = serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("Invalid relay identities file: {}", e))?;
+
+        let registry = Self {
+            relays: parse_keys(file.relays)?,
+            clients: parse_keys(file.clients)?,
+        };
+        info!(
+            "Loaded {} relay and {} client identities from {}",
+            registry.relays.len(),
+            registry.clients.len(),
+            path
+        );
+        Ok(registry)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.relays.is_empty()
+    }
+
+    // Verify both layers of the envelope against the ciphertext as received: the base64
+    // text of a JSON upload, or the raw bytes of a binary one. The inner tag only proves
+    // the client produced this exact ciphertext; it reveals nothing about the plaintext,
+    // which stays sealed under the session key.
+    pub fn verify(&self, envelope: &RelayEnvelope, ciphertext_as_sent: &[u8]) -> Result<RelayIdentities> {
+        let relay_key = self.relays.get(&envelope.relay_id)
+            .ok_or_else(|| anyhow!("Unknown relay: {}", envelope.relay_id))?;
+        let client_key = self.clients.get(&envelope.client_id)
+            .ok_or_else(|| anyhow!("Unknown client: {}", envelope.client_id))?;
+
+        let ciphertext_digest = Sha256::digest(ciphertext_as_sent);
+        let client_signature = decode_signature(&envelope.client_signature)?;
+        let relay_signature = decode_signature(&envelope.relay_signature)?;
+
+        let mut relay_message = ciphertext_digest.to_vec();
+        relay_message.extend_from_slice(&client_signature.to_bytes());
+        relay_key.verify(&relay_message, &relay_signature)
+            .map_err(|_| anyhow!("Relay signature verification failed for {}", envelope.relay_id))?;
+
+        client_key.verify(&ciphertext_digest, &client_signature)
+            .map_err(|_| anyhow!("Client integrity tag verification failed for {}", envelope.client_id))?;
+
+        Ok(RelayIdentities {
+            relay_id: envelope.relay_id.clone(),
+            client_id: envelope.client_id.clone(),
+        })
+    }
+}
+
+fn parse_keys(encoded: HashMap<String, String>) -> Result<HashMap<String, VerifyingKey>> {
+    encoded.into_iter()
+        .map(|(id, key)| {
+            let bytes = BASE64.decode(&key)
+                .ok()
+                .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                .ok_or_else(|| anyhow!("Invalid Ed25519 public key for {}", id))?;
+            let key = VerifyingKey::from_bytes(&bytes)
+                .map_err(|e| anyhow!("Invalid Ed25519 public key for {}: {}", id, e))?;
+            Ok((id, key))
+        })
+        .collect()
+}
+
+fn decode_signature(encoded: &str) -> Result<Signature> {
+    let bytes = BASE64.decode(encoded)
+        .map_err(|e| anyhow!("Invalid base64 signature: {}", e))?;
+    Signature::from_slice(&bytes).map_err(|e| anyhow!("Invalid signature: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[test]
+    fn test_verify_double_wrapped_upload() {
+        let client = SigningKey::from_bytes(&[1u8; 32]);
+        let relay = SigningKey::from_bytes(&[2u8; 32]);
+        let registry = RelayRegistry {
+            relays: HashMap::from([("gw-1".to_string(), relay.verifying_key())]),
+            clients: HashMap::from([("device-7".to_string(), client.verifying_key())]),
+        };
+
+        let encrypted_data = "c2VhbGVkIGNpcGhlcnRleHQ=";
+        let digest = Sha256::digest(encrypted_data.as_bytes());
+        let client_signature = client.sign(&digest);
+        let mut relay_message = digest.to_vec();
+        relay_message.extend_from_slice(&client_signature.to_bytes());
+
+        let envelope = RelayEnvelope {
+            relay_id: "gw-1".to_string(),
+            client_id: "device-7".to_string(),
+            client_signature: BASE64.encode(client_signature.to_bytes()),
+            relay_signature: BASE64.encode(relay.sign(&relay_message).to_bytes()),
+        };
+
+        let identities = registry.verify(&envelope, encrypted_data.as_bytes()).unwrap();
+        assert_eq!(identities.relay_id, "gw-1");
+        assert_eq!(identities.client_id, "device-7");
+
+        // Any change to the ciphertext breaks the client's tag
+        assert!(registry.verify(&envelope, b"dGFtcGVyZWQ=").is_err());
+    }
+}
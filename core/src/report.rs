@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::heatmap::Heatmap;
+use crate::policy::Severity;
+
+// Counts of what a redaction removed or kept, and where the analyzer found each entity
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RedactionReport {
+    pub entities: BTreeMap<String, usize>,
+    pub forced_redactions: usize,
+    pub protected_segments: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub detections: Vec<Detection>,
+    // Some text was analyzed by a fallback backend, e.g. the regex engine while Presidio
+    // was unreachable
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fallback: bool,
+    // Served on its own by `GET /files/:file_id/heatmap`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heatmap: Option<Heatmap>,
+    // Embedded metadata of a PDF or DOCX upload, left out of its output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<ScrubbedMetadata>,
+}
+
+// What the metadata scrub removed from a document: the names of its properties and the
+// number of comments and tracked changes, never their values
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ScrubbedMetadata {
+    // e.g. `creator` or `lastModifiedBy`, and `custom:<name>` for custom properties
+    pub properties: Vec<String>,
+    // XMP packets, which repeat the properties and can carry edit history
+    pub xmp: bool,
+    pub comments: usize,
+    // Insertions are kept in the text and deletions dropped, as if all were accepted
+    pub tracked_insertions: usize,
+    pub tracked_deletions: usize,
+}
+
+impl ScrubbedMetadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+// One analyzer detection as a half-open byte range into the plaintext; never the value
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Detection {
+    pub entity_type: String,
+    pub start: usize,
+    pub end: usize,
+    pub score: f64,
+}
+
+// Replace each detection in `text` with `replace(detection, value)`. Overlapping
+// detections are covered by the earlier, longer one. None when a detection does not
+// fall on character boundaries of `text`.
+pub fn rewrite_detections(
+    text: &str,
+    detections: &[Detection],
+    mut replace: impl FnMut(&Detection, &str) -> String,
+) -> Option<String> {
+    let mut detections: Vec<&Detection> = detections.iter().collect();
+    detections.sort_by_key(|detection| (detection.start, std::cmp::Reverse(detection.end)));
+
+    let mut output = String::with_capacity(text.len());
+    let mut copied = 0;
+    for detection in detections {
+        if detection.start < copied {
+            continue;
+        }
+        let value = text.get(detection.start..detection.end)?;
+        output.push_str(text.get(copied..detection.start)?);
+        output.push_str(&replace(detection, value));
+        copied = detection.end;
+    }
+    output.push_str(text.get(copied..)?);
+    Some(output)
+}
+
+// What the upload response carries of the report
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ReportSummary {
+    pub entities: BTreeMap<String, usize>,
+    pub total_entities: usize,
+    pub forced_redactions: usize,
+    pub protected_segments: usize,
+    // Under the redaction policy, set by the upload pipeline
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_severity: Option<Severity>,
+}
+
+impl RedactionReport {
+    pub fn summary(&self) -> ReportSummary {
+        ReportSummary {
+            entities: self.entities.clone(),
+            total_entities: self.entities.values().sum(),
+            forced_redactions: self.forced_redactions,
+            protected_segments: self.protected_segments,
+            max_severity: None,
+        }
+    }
+}
+
+// Filter over stored reports. Matches files with at least `min_count` detections of
+// `entity` (any detection when unset), created within [from, to], in `tenant`.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ReportQuery {
+    pub entity: Option<String>,
+    #[serde(default)]
+    pub min_count: usize,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+impl ReportQuery {
+    pub fn matches(&self, report: &RedactionReport, created_at: u64) -> bool {
+        let count = match &self.entity {
+            Some(entity) => report.entities.get(entity).copied().unwrap_or_default(),
+            None => report.entities.values().sum(),
+        };
+        count >= self.min_count.max(1)
+            && self.from.is_none_or(|from| created_at >= from)
+            && self.to.is_none_or(|to| created_at <= to)
+    }
+}
@@ -0,0 +1,314 @@
+use anyhow::{anyhow, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const DEFAULT_MAX_RETRIES: u32 = 2;
+const DEFAULT_RETRY_BASE_MS: u64 = 200;
+const DEFAULT_RETRY_MAX_MS: u64 = 5000;
+const DEFAULT_BREAKER_FAILURES: u32 = 5;
+const DEFAULT_BREAKER_OPEN_SECONDS: u64 = 30;
+const DEFAULT_DECRYPT_MS: u64 = 10_000;
+const DEFAULT_ANALYZE_MS: u64 = 120_000;
+const DEFAULT_ANONYMIZE_MS: u64 = 10_000;
+const DEFAULT_STORE_MS: u64 = 30_000;
+const DEFAULT_DELIVER_MS: u64 = 30_000;
+
+// Returned without calling a backend whose circuit is open, so callers can answer 503
+// at once instead of waiting out a timeout
+#[derive(Debug)]
+pub struct BackendUnavailable {
+    pub backend: String,
+}
+
+impl fmt::Display for BackendUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Redaction backend {} is unavailable; its circuit breaker is open", self.backend)
+    }
+}
+
+impl std::error::Error for BackendUnavailable {}
+
+// Stages of handling a file, each with its own time budget
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    // Unwrapping the session key, decrypting and checking the checksums
+    Decrypt,
+    // Calls to the redaction backend, retries included
+    Analyze,
+    // Local work around the backend calls: spans, structured fields, extraction,
+    // sealing pseudonyms and the heatmap
+    Anonymize,
+    // Waiting for the storage and writing the file
+    Store,
+    // Waiting for the storage and reading a download
+    Deliver,
+}
+
+impl Stage {
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Decrypt => "decrypt",
+            Stage::Analyze => "analyze",
+            Stage::Anonymize => "anonymize",
+            Stage::Store => "store",
+            Stage::Deliver => "deliver",
+        }
+    }
+
+    // Error code of a stage that overran; analysis keeps `backend_timeout`
+    pub fn timeout_code(self) -> &'static str {
+        match self {
+            Stage::Decrypt => "decrypt_timeout",
+            Stage::Analyze => "backend_timeout",
+            Stage::Anonymize => "anonymize_timeout",
+            Stage::Store => "store_timeout",
+            Stage::Deliver => "deliver_timeout",
+        }
+    }
+}
+
+// Budgets of the stages in milliseconds. The policy file's `stage_timeouts` sets them,
+// falling back to `STAGE_<NAME>_TIMEOUT_MS` for any it leaves out.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default = "StageTimeouts::from_env")]
+pub struct StageTimeouts {
+    pub decrypt_ms: u64,
+    pub analyze_ms: u64,
+    pub anonymize_ms: u64,
+    pub store_ms: u64,
+    pub deliver_ms: u64,
+}
+
+impl StageTimeouts {
+    pub fn from_env() -> Self {
+        let budget = |name: &str, default: u64| {
+            env_number(&format!("STAGE_{}_TIMEOUT_MS", name)).filter(|ms| *ms > 0).unwrap_or(default)
+        };
+        Self {
+            decrypt_ms: budget("DECRYPT", DEFAULT_DECRYPT_MS),
+            analyze_ms: budget("ANALYZE", DEFAULT_ANALYZE_MS),
+            anonymize_ms: budget("ANONYMIZE", DEFAULT_ANONYMIZE_MS),
+            store_ms: budget("STORE", DEFAULT_STORE_MS),
+            deliver_ms: budget("DELIVER", DEFAULT_DELIVER_MS),
+        }
+    }
+
+    pub fn budget(&self, stage: Stage) -> Duration {
+        Duration::from_millis(match stage {
+            Stage::Decrypt => self.decrypt_ms,
+            Stage::Analyze => self.analyze_ms,
+            Stage::Anonymize => self.anonymize_ms,
+            Stage::Store => self.store_ms,
+            Stage::Deliver => self.deliver_ms,
+        })
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let stages = [Stage::Decrypt, Stage::Analyze, Stage::Anonymize, Stage::Store, Stage::Deliver];
+        match stages.into_iter().find(|stage| self.budget(*stage).is_zero()) {
+            Some(stage) => Err(anyhow!("The {} stage timeout must be positive", stage.name())),
+            None => Ok(()),
+        }
+    }
+}
+
+// A stage that ran past its time budget
+#[derive(Debug)]
+pub struct DeadlineExceeded {
+    pub stage: Stage,
+    pub budget: Duration,
+}
+
+impl fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The {} stage did not finish within {} ms", self.stage.name(), self.budget.as_millis())
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+// Retries of transient backend failures, with exponential backoff and full jitter
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    // `<PREFIX>_MAX_RETRIES`, `<PREFIX>_RETRY_BASE_MS` and `<PREFIX>_RETRY_MAX_MS`
+    pub fn from_env(prefix: &str) -> Self {
+        Self {
+            max_retries: env_number(&format!("{}_MAX_RETRIES", prefix)).unwrap_or(DEFAULT_MAX_RETRIES as u64) as u32,
+            base_delay: Duration::from_millis(env_number(&format!("{}_RETRY_BASE_MS", prefix)).unwrap_or(DEFAULT_RETRY_BASE_MS)),
+            max_delay: Duration::from_millis(env_number(&format!("{}_RETRY_MAX_MS", prefix)).unwrap_or(DEFAULT_RETRY_MAX_MS)),
+        }
+    }
+
+    // Wait before retry `attempt` (from 0): uniform in [0, min(max, base * 2^attempt)]
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let ceiling = self.base_delay.saturating_mul(2u32.saturating_pow(attempt)).min(self.max_delay);
+        ceiling.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    // Calls fail fast until the open period ends
+    Open,
+    // The open period ended; one trial call decides between closed and open
+    HalfOpen,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CircuitStatus {
+    pub backend: String,
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    // Until the next trial call, while open
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_in_seconds: Option<u64>,
+}
+
+struct Circuit {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    // Start of the trial call in flight, while half open
+    trial_started: Option<Instant>,
+}
+
+// Opens after `failure_threshold` failed calls in a row, failing calls fast for
+// `open_duration`, then lets one trial call through
+pub struct CircuitBreaker {
+    backend: String,
+    failure_threshold: u32,
+    open_duration: Duration,
+    circuit: Mutex<Circuit>,
+}
+
+impl CircuitBreaker {
+    pub fn new(backend: &str, failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            backend: backend.to_string(),
+            failure_threshold,
+            open_duration,
+            circuit: Mutex::new(Circuit { consecutive_failures: 0, opened_at: None, trial_started: None }),
+        }
+    }
+
+    // `<PREFIX>_BREAKER_FAILURES` and `<PREFIX>_BREAKER_OPEN_SECONDS`
+    pub fn from_env(backend: &str, prefix: &str) -> Self {
+        Self::new(
+            backend,
+            env_number(&format!("{}_BREAKER_FAILURES", prefix)).filter(|failures| *failures > 0).unwrap_or(DEFAULT_BREAKER_FAILURES as u64) as u32,
+            Duration::from_secs(env_number(&format!("{}_BREAKER_OPEN_SECONDS", prefix)).unwrap_or(DEFAULT_BREAKER_OPEN_SECONDS)),
+        )
+    }
+
+    // Whether a call may go ahead. While half open, only the trial call may; a trial
+    // that never reported back is replaced after another open period.
+    pub fn allow(&self) -> Result<(), BackendUnavailable> {
+        let mut circuit = self.circuit.lock().unwrap();
+        let Some(opened_at) = circuit.opened_at else {
+            return Ok(());
+        };
+        let trial_due = opened_at.elapsed() >= self.open_duration
+            && circuit.trial_started.is_none_or(|started| started.elapsed() >= self.open_duration);
+        if !trial_due {
+            return Err(BackendUnavailable { backend: self.backend.clone() });
+        }
+        circuit.trial_started = Some(Instant::now());
+        Ok(())
+    }
+
+    // Outcome of an allowed call. Failures are calls that failed after their retries.
+    pub fn record(&self, success: bool) {
+        let mut circuit = self.circuit.lock().unwrap();
+        if success {
+            *circuit = Circuit { consecutive_failures: 0, opened_at: None, trial_started: None };
+            return;
+        }
+        circuit.consecutive_failures += 1;
+        if circuit.trial_started.is_some() || circuit.consecutive_failures >= self.failure_threshold {
+            circuit.opened_at = Some(Instant::now());
+            circuit.trial_started = None;
+        }
+    }
+
+    pub fn status(&self) -> CircuitStatus {
+        let circuit = self.circuit.lock().unwrap();
+        let (state, retry_in) = match circuit.opened_at {
+            None => (CircuitState::Closed, None),
+            Some(opened_at) => match self.open_duration.checked_sub(opened_at.elapsed()) {
+                Some(remaining) if circuit.trial_started.is_none() => (CircuitState::Open, Some(remaining.as_secs())),
+                _ => (CircuitState::HalfOpen, None),
+            },
+        };
+        CircuitStatus {
+            backend: self.backend.clone(),
+            state,
+            consecutive_failures: circuit.consecutive_failures,
+            retry_in_seconds: retry_in,
+        }
+    }
+}
+
+fn env_number(name: &str) -> Option<u64> {
+    std::env::var(name).ok().and_then(|value| value.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breaker_opens_and_lets_one_trial_through() {
+        let breaker = CircuitBreaker::new("presidio", 2, Duration::from_millis(50));
+        breaker.record(false);
+        assert!(breaker.allow().is_ok());
+        breaker.record(false);
+        assert_eq!(breaker.status().state, CircuitState::Open);
+        assert!(breaker.allow().is_err());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.allow().is_ok());
+        assert_eq!(breaker.status().state, CircuitState::HalfOpen);
+        // Only the trial goes through, and its failure opens the circuit again
+        assert!(breaker.allow().is_err());
+        breaker.record(false);
+        assert_eq!(breaker.status().state, CircuitState::Open);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.allow().is_ok());
+        breaker.record(true);
+        let status = breaker.status();
+        assert_eq!((status.state, status.consecutive_failures), (CircuitState::Closed, 0));
+    }
+
+    #[test]
+    fn test_stage_timeouts_fill_in_from_defaults() {
+        let timeouts: StageTimeouts = serde_json::from_str(r#"{ "store_ms": 2500 }"#).unwrap();
+        assert_eq!(timeouts.budget(Stage::Store), Duration::from_millis(2500));
+        assert_eq!(timeouts.budget(Stage::Analyze), Duration::from_millis(DEFAULT_ANALYZE_MS));
+        assert!(timeouts.validate().is_ok());
+
+        let zero: StageTimeouts = serde_json::from_str(r#"{ "deliver_ms": 0 }"#).unwrap();
+        assert!(zero.validate().unwrap_err().to_string().contains("deliver"));
+    }
+
+    #[test]
+    fn test_backoff_is_capped_and_jittered() {
+        let policy = RetryPolicy { max_retries: 5, base_delay: Duration::from_millis(100), max_delay: Duration::from_millis(300) };
+        for _ in 0..50 {
+            assert!(policy.delay(0) <= Duration::from_millis(100));
+            assert!(policy.delay(4) <= Duration::from_millis(300));
+        }
+    }
+}
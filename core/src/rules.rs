@@ -0,0 +1,401 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::net::Ipv6Addr;
+
+use crate::backend::{Analysis, EntityFilter, RedactionBackend};
+use crate::bidi;
+use crate::report::Detection;
+
+// Most patterns and deny-list terms one request may carry
+pub const MAX_CUSTOM_PATTERNS: usize = 20;
+pub const MAX_DENY_LIST_TERMS: usize = 100;
+// Entity type deny-list matches are reported as
+pub const DENY_LIST: &str = "DENY_LIST";
+// Compiled size limit of a request's patterns, so one cannot take up unbounded memory
+const CUSTOM_SIZE_LIMIT: usize = 1 << 20;
+// Names and places the mock backend detects, for test documents to use
+pub const MOCK_PERSONS: [&str; 5] = ["John Smith", "Jane Doe", "Jane Roe", "Alice Johnson", "Bob Jones"];
+pub const MOCK_LOCATIONS: [&str; 5] = ["Chicago", "New York", "London", "Paris", "Springfield"];
+
+// Pure-Rust rule engine for the common structured identifiers, so the service can run
+// without Presidio. It does not detect names or locations.
+pub struct RegexEngine {
+    rules: Vec<Rule>,
+    name: &'static str,
+}
+
+struct Rule {
+    entity_type: &'static str,
+    regex: Regex,
+    // Extra check on a candidate match, for formats a regex cannot validate
+    validate: Option<fn(&str) -> bool>,
+}
+
+impl RegexEngine {
+    pub fn new() -> Self {
+        let rule = |entity_type, pattern: &str, validate| Rule {
+            entity_type,
+            regex: Regex::new(pattern).expect("built-in pattern is valid"),
+            validate,
+        };
+
+        // Earlier rules win when matches of equal length overlap
+        let rules = vec![
+            rule("EMAIL_ADDRESS", r"\b[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}\b", None),
+            rule(
+                "IP_ADDRESS",
+                r"\b(?:(?:25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\.){3}(?:25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\b",
+                None,
+            ),
+            rule("IP_ADDRESS", r"(?i)\b(?:[0-9a-f]{1,4}:){1,7}:?[0-9a-f:]*[0-9a-f]\b", Some(is_ipv6)),
+            rule("US_SSN", r"\b\d{3}-\d{2}-\d{4}\b", Some(is_valid_ssn)),
+            rule("CREDIT_CARD", r"\b\d(?:[ -]?\d){12,18}\b", Some(passes_luhn)),
+            rule(
+                "PHONE_NUMBER",
+                r"(?:\+\d{1,3}[ .-]?)?(?:\(\d{3}\)|\b\d{3})[ .-]?\d{3}[ .-]\d{4}\b",
+                None,
+            ),
+        ];
+
+        Self { rules, name: "regex" }
+    }
+
+    // The `mock` backend, for tests without Presidio: the rules above, plus ISO and
+    // spelled-out dates, URLs, and the names and places in `MOCK_PERSONS` and
+    // `MOCK_LOCATIONS`. The same text always gets the same detections.
+    pub fn mock() -> Self {
+        let fixture = |values: &[&str]| format!(r"\b(?:{})\b", values.iter().map(|value| regex::escape(value)).collect::<Vec<_>>().join("|"));
+        let rule = |entity_type, pattern: &str| Rule {
+            entity_type,
+            regex: Regex::new(pattern).expect("mock pattern is valid"),
+            validate: None,
+        };
+        let mut engine = Self::new();
+        engine.rules.extend([
+            rule("URL", r#"\bhttps?://[^\s<>"]*[^\s<>".,;:!?)]"#),
+            rule("PERSON", &fixture(&MOCK_PERSONS)),
+            rule("LOCATION", &fixture(&MOCK_LOCATIONS)),
+            rule("DATE_TIME", r"\b\d{4}-\d{2}-\d{2}\b"),
+            rule(
+                "DATE_TIME",
+                r"\b(?:January|February|March|April|May|June|July|August|September|October|November|December) \d{1,2}, \d{4}\b",
+            ),
+        ]);
+        engine.name = "mock";
+        engine
+    }
+
+    // Redact every detection `filter` allows, longest match first where detections overlap
+    pub fn redact(&self, text: &str, strategy: &str, filter: EntityFilter<'_>) -> Analysis {
+        let mut detections: Vec<(usize, usize, &'static str)> = Vec::new();
+        // Rule matches are exact, so every detection scores 1.0
+        for rule in self.rules.iter().filter(|rule| filter.allows(rule.entity_type, 1.0)) {
+            for found in rule.regex.find_iter(text) {
+                if rule.validate.is_none_or(|validate| validate(found.as_str())) {
+                    detections.push((found.start(), found.end(), rule.entity_type));
+                }
+            }
+        }
+        detections.sort_by_key(|(start, end, _)| (*start, std::cmp::Reverse(*end)));
+
+        let mut redacted = String::with_capacity(text.len());
+        let mut kept = Vec::new();
+        let mut cursor = 0;
+        for (start, end, entity_type) in detections {
+            if start < cursor {
+                continue;
+            }
+            redacted.push_str(&text[cursor..start]);
+            redacted.push_str(&replacement(entity_type, strategy));
+            kept.push(Detection { entity_type: entity_type.to_string(), start, end, score: 1.0 });
+            cursor = end;
+        }
+        redacted.push_str(&text[cursor..]);
+
+        Analysis { redacted, detections: kept, fallback: false }
+    }
+}
+
+impl Default for RegexEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl RedactionBackend for RegexEngine {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn entities(&self) -> Vec<&str> {
+        let mut entities: Vec<&str> = Vec::new();
+        for rule in &self.rules {
+            if !entities.contains(&rule.entity_type) {
+                entities.push(rule.entity_type);
+            }
+        }
+        entities
+    }
+
+    async fn analyze(&self, text: &str, strategy: &str, filter: EntityFilter<'_>) -> Result<Analysis> {
+        Ok(self.redact(text, strategy, filter))
+    }
+}
+
+// A recognizer sent with a request, for identifiers the backend does not know
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CustomPattern {
+    // Entity type its matches are reported as, e.g. `EMPLOYEE_ID`
+    pub name: String,
+    pub regex: String,
+    // What matches are replaced with; `<NAME>` when unset
+    pub replacement: Option<String>,
+}
+
+struct CustomRule {
+    entity_type: String,
+    regex: Regex,
+    replacement: Option<String>,
+}
+
+impl CustomRule {
+    fn marker(&self, strategy: &str) -> String {
+        match (&self.replacement, strategy) {
+            (Some(replacement), _) => replacement.clone(),
+            (None, "mask") => "****".to_string(),
+            (None, _) => format!("<{}>", self.entity_type),
+        }
+    }
+}
+
+// A request's own patterns and deny-list terms, applied after the backend pass
+pub struct CustomRules {
+    rules: Vec<CustomRule>,
+}
+
+impl CustomRules {
+    pub fn new(patterns: &[CustomPattern], deny_list: &[String]) -> Result<Self> {
+        if patterns.len() > MAX_CUSTOM_PATTERNS {
+            return Err(anyhow!("At most {} custom patterns are allowed", MAX_CUSTOM_PATTERNS));
+        }
+        if deny_list.len() > MAX_DENY_LIST_TERMS {
+            return Err(anyhow!("At most {} deny-list terms are allowed", MAX_DENY_LIST_TERMS));
+        }
+        let compile = |pattern: &str| RegexBuilder::new(pattern).size_limit(CUSTOM_SIZE_LIMIT).build();
+
+        let mut rules = Vec::with_capacity(patterns.len() + 1);
+        for pattern in patterns {
+            let is_entity_type = !pattern.name.is_empty()
+                && pattern.name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+            if !is_entity_type {
+                return Err(anyhow!("Custom pattern name {:?} must be upper-case letters, digits and underscores", pattern.name));
+            }
+            let regex = compile(&pattern.regex).map_err(|e| anyhow!("Invalid regex for {}: {}", pattern.name, e))?;
+            rules.push(CustomRule { entity_type: pattern.name.clone(), regex, replacement: pattern.replacement.clone() });
+        }
+
+        // Terms match as whole literals, ignoring case, longest first where they share a prefix
+        let mut terms: Vec<&str> = deny_list.iter().map(|term| term.trim()).filter(|term| !term.is_empty()).collect();
+        if !terms.is_empty() {
+            terms.sort_by_key(|term| std::cmp::Reverse(term.len()));
+            let alternation = terms.iter().map(|term| regex::escape(term)).collect::<Vec<_>>().join("|");
+            let regex = compile(&format!("(?i){}", alternation)).map_err(|e| anyhow!("Invalid deny list: {}", e))?;
+            rules.push(CustomRule { entity_type: DENY_LIST.to_string(), regex, replacement: None });
+        }
+
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // Matches in `text`, longest first where they overlap
+    pub fn detect(&self, text: &str) -> Vec<Detection> {
+        self.matches(text)
+            .into_iter()
+            .map(|(start, end, rule)| Detection { entity_type: rule.entity_type.clone(), start, end, score: 1.0 })
+            .collect()
+    }
+
+    // Replace matches in the backend's output, leaving its redaction markers alone
+    pub fn apply(&self, text: &str, strategy: &str) -> String {
+        let mut markers = Vec::new();
+        for (start, _) in text.match_indices(['<', '[']) {
+            let len = bidi::marker_len(&text[start..]);
+            if len > 0 {
+                markers.push((start, start + len));
+            }
+        }
+        let mut output = String::with_capacity(text.len());
+        let mut copied = 0;
+        for (start, end, rule) in self.matches(text) {
+            if markers.iter().any(|(marker_start, marker_end)| start < *marker_end && *marker_start < end) {
+                continue;
+            }
+            output.push_str(&text[copied..start]);
+            output.push_str(&rule.marker(strategy));
+            copied = end;
+        }
+        output.push_str(&text[copied..]);
+        output
+    }
+
+    fn matches(&self, text: &str) -> Vec<(usize, usize, &CustomRule)> {
+        let mut found = Vec::new();
+        for rule in &self.rules {
+            for matched in rule.regex.find_iter(text).filter(|matched| !matched.is_empty()) {
+                found.push((matched.start(), matched.end(), rule));
+            }
+        }
+        found.sort_by_key(|(start, end, _)| (*start, std::cmp::Reverse(*end)));
+
+        let mut kept: Vec<(usize, usize, &CustomRule)> = Vec::with_capacity(found.len());
+        for candidate in found {
+            if kept.last().is_none_or(|(_, end, _)| candidate.0 >= *end) {
+                kept.push(candidate);
+            }
+        }
+        kept
+    }
+}
+
+// Same replacements as the Presidio service's strategies, with the first of its fake values
+pub(crate) fn replacement(entity_type: &str, strategy: &str) -> String {
+    match strategy {
+        "mask" => "****".to_string(),
+        "fake" => match entity_type {
+            "PERSON" => "Alice Johnson",
+            "EMAIL_ADDRESS" => "user1@example.com",
+            "PHONE_NUMBER" => "555-0101",
+            "CREDIT_CARD" => "4111-1111-1111-1111",
+            "US_SSN" => "123-45-6789",
+            "IP_ADDRESS" => "192.168.1.1",
+            "LOCATION" => "New York, NY",
+            "DATE_TIME" => "2023-01-01",
+            "URL" => "https://example.com",
+            _ => return format!("<{}>", entity_type),
+        }
+        .to_string(),
+        "custom" => match entity_type {
+            "PERSON" => "[REDACTED_NAME]",
+            "EMAIL_ADDRESS" => "[REDACTED_EMAIL]",
+            "PHONE_NUMBER" => "[REDACTED_PHONE]",
+            "CREDIT_CARD" => "[REDACTED_CREDIT_CARD]",
+            "US_SSN" => "[REDACTED_SSN]",
+            "IP_ADDRESS" => "[REDACTED_IP]",
+            "LOCATION" => "[REDACTED_LOCATION]",
+            "DATE_TIME" => "[REDACTED_DATE]",
+            "URL" => "[REDACTED_URL]",
+            _ => return format!("<{}>", entity_type),
+        }
+        .to_string(),
+        _ => format!("<{}>", entity_type),
+    }
+}
+
+fn is_ipv6(candidate: &str) -> bool {
+    candidate.parse::<Ipv6Addr>().is_ok()
+}
+
+// Area 000, 666 and 900-999, group 00 and serial 0000 are never issued
+fn is_valid_ssn(candidate: &str) -> bool {
+    let parts: Vec<&str> = candidate.split('-').collect();
+    let [area, group, serial] = parts[..] else {
+        return false;
+    };
+    area != "000" && area != "666" && !area.starts_with('9') && group != "00" && serial != "0000"
+}
+
+fn passes_luhn(candidate: &str) -> bool {
+    let digits: Vec<u32> = candidate.chars().filter_map(|c| c.to_digit(10)).collect();
+    let sum: u32 = digits.iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &digit)| match i % 2 {
+            1 if digit * 2 > 9 => digit * 2 - 9,
+            1 => digit * 2,
+            _ => digit,
+        })
+        .sum();
+    (13..=19).contains(&digits.len()) && sum.is_multiple_of(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_structured_identifiers() {
+        let engine = RegexEngine::new();
+        let text = "Email jane.roe@example.com or call (555) 123-4567. SSN 123-45-6789, \
+                    card 4111 1111 1111 1111, host 10.0.0.12 / fe80::1ff:fe23:4567:890a.";
+        let analysis = engine.redact(text, "replace", EntityFilter::default());
+
+        assert_eq!(
+            analysis.redacted,
+            "Email <EMAIL_ADDRESS> or call <PHONE_NUMBER>. SSN <US_SSN>, \
+             card <CREDIT_CARD>, host <IP_ADDRESS> / <IP_ADDRESS>."
+        );
+        assert_eq!(analysis.detections.len(), 6);
+        let ssn = &analysis.detections[2];
+        assert_eq!((ssn.entity_type.as_str(), &text[ssn.start..ssn.end]), ("US_SSN", "123-45-6789"));
+
+        assert_eq!(engine.redact("SSN 123-45-6789", "custom", EntityFilter::default()).redacted, "SSN [REDACTED_SSN]");
+        assert_eq!(engine.redact("SSN 123-45-6789", "mask", EntityFilter::default()).redacted, "SSN ****");
+
+        let entities = ["US_SSN".to_string()];
+        let only_ssn = EntityFilter { entities: Some(&entities), score_threshold: None, language: None };
+        assert_eq!(engine.redact("jane@example.com, SSN 123-45-6789", "replace", only_ssn).redacted, "jane@example.com, SSN <US_SSN>");
+    }
+
+    #[test]
+    fn test_mock_detects_its_fixtures_the_same_every_time() {
+        let engine = RegexEngine::mock();
+        let text = "Jane Doe moved to Chicago on 2024-03-01 (see https://example.org/move). Mail jane@example.com.";
+        let analysis = engine.redact(text, "replace", EntityFilter::default());
+        assert_eq!(analysis.redacted, "<PERSON> moved to <LOCATION> on <DATE_TIME> (see <URL>). Mail <EMAIL_ADDRESS>.");
+        assert_eq!(engine.redact(text, "fake", EntityFilter::default()).redacted, engine.redact(text, "fake", EntityFilter::default()).redacted);
+        assert_eq!(engine.name(), "mock");
+        assert!(engine.entities().contains(&"PERSON") && !RegexEngine::new().entities().contains(&"PERSON"));
+        // Only the fixture names are known
+        assert!(engine.redact("Jim Beam", "replace", EntityFilter::default()).detections.is_empty());
+    }
+
+    #[test]
+    fn test_rejects_invalid_candidates() {
+        let engine = RegexEngine::new();
+        // Fails the Luhn check, and an SSN area that is never issued
+        let analysis = engine.redact("Order 4111 1111 1111 1112, ref 000-12-3456", "replace", EntityFilter::default());
+        assert!(analysis.detections.is_empty());
+    }
+
+    #[test]
+    fn test_custom_rules_leave_backend_markers_alone() {
+        let patterns = [CustomPattern { name: "EMPLOYEE_ID".to_string(), regex: r"\bEMP-\d{5}\b".to_string(), replacement: None }];
+        let rules = CustomRules::new(&patterns, &["Project Falcon".to_string(), "email".to_string()]).unwrap();
+
+        let found = rules.detect("EMP-12345 leads project falcon");
+        let types: Vec<&str> = found.iter().map(|detection| detection.entity_type.as_str()).collect();
+        assert_eq!(types, ["EMPLOYEE_ID", "DENY_LIST"]);
+        assert_eq!((found[1].start, found[1].end), (16, 30));
+
+        // A term inside a marker the backend wrote is not a match
+        assert_eq!(
+            rules.apply("EMP-12345 sent an email to <EMAIL_ADDRESS>", "replace"),
+            "<EMPLOYEE_ID> sent an <DENY_LIST> to <EMAIL_ADDRESS>"
+        );
+        assert_eq!(rules.apply("EMP-12345", "mask"), "****");
+
+        let invalid = |name: &str, regex: &str| {
+            CustomRules::new(&[CustomPattern { name: name.to_string(), regex: regex.to_string(), replacement: None }], &[]).is_err()
+        };
+        assert!(invalid("employee id", r"\d+"));
+        assert!(invalid("EMPLOYEE_ID", "(unclosed"));
+        assert!(invalid("EMPLOYEE_ID", r"\w{1000}{1000}"));
+    }
+}
@@ -0,0 +1,537 @@
+use anyhow::{anyhow, Result};
+use aws_config::{BehaviorVersion, Region};
+use aws_sdk_s3::{error::DisplayErrorContext, primitives::ByteStream, Client};
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::{Duration, SystemTime};
+use tokio::runtime::Handle;
+use tracing::{info, warn};
+
+use crate::config::AppConfig;
+use crate::crypto::CryptoService;
+use crate::envelope;
+use crate::report::{RedactionReport, ReportQuery};
+use crate::storage::{self, EncryptedContent, FileMetadata, FileStorage, IndexEntry, Storage, StorageLimits};
+use crate::usage::UsageTotals;
+
+const METADATA_SUFFIX: &str = "/metadata.json";
+const CONTENT_SUFFIX: &str = "/content.json";
+
+// Where `S3Storage` keeps files. `endpoint` points at an S3-compatible service such as
+// MinIO, which usually also needs path-style addressing. Credentials come from the
+// usual AWS sources: environment, profile, web identity or instance metadata.
+#[derive(Clone, Debug)]
+pub struct S3Config {
+    pub bucket: String,
+    // Prepended to every object key, e.g. `redactor/`
+    pub prefix: String,
+    pub endpoint: Option<String>,
+    pub region: String,
+    pub force_path_style: bool,
+}
+
+impl S3Config {
+    pub fn from_config(config: &AppConfig) -> Result<Self> {
+        let bucket = config.s3_bucket.clone().ok_or_else(|| anyhow!("S3 storage needs S3_BUCKET"))?;
+        Ok(Self {
+            bucket,
+            prefix: config.s3_prefix.clone(),
+            endpoint: config.s3_endpoint.clone(),
+            region: config.s3_region.clone(),
+            force_path_style: config.s3_force_path_style,
+        })
+    }
+
+    // The bucket erasures are propagated to, on the storage bucket's endpoint and region
+    pub fn deletion_target(config: &AppConfig) -> Option<Self> {
+        Some(Self {
+            bucket: config.deletion_s3_bucket.clone()?,
+            prefix: config.deletion_s3_prefix.clone(),
+            endpoint: config.s3_endpoint.clone(),
+            region: config.s3_region.clone(),
+            force_path_style: config.s3_force_path_style,
+        })
+    }
+}
+
+// A bucket redacted outputs were delivered to, such as one downstream systems read
+// from. A file's copies are the objects under `<prefix><file_id>`.
+pub struct DeliveryBucket {
+    client: Client,
+    config: S3Config,
+}
+
+impl DeliveryBucket {
+    pub async fn new(config: S3Config) -> Self {
+        Self { client: client(&config).await, config }
+    }
+
+    pub fn bucket(&self) -> &str {
+        &self.config.bucket
+    }
+
+    // Delete every copy of the file, returning how many there were
+    pub async fn delete_file(&self, file_id: &str) -> Result<usize> {
+        if file_id.is_empty() || file_id.contains(['/', '\\']) {
+            return Err(anyhow!("Invalid file id: {}", file_id));
+        }
+        let prefix = format!("{}{}", self.config.prefix, file_id);
+        let mut pages = self.client.list_objects_v2()
+            .bucket(&self.config.bucket)
+            .prefix(&prefix)
+            .into_paginator()
+            .send();
+        let mut keys = Vec::new();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| anyhow!("Failed to list bucket {}: {}", self.config.bucket, DisplayErrorContext(e)))?;
+            keys.extend(page.contents().iter().filter_map(|object| object.key()).map(str::to_string));
+        }
+        for key in &keys {
+            self.client.delete_object()
+                .bucket(&self.config.bucket)
+                .key(key)
+                .send()
+                .await
+                .map_err(|e| anyhow!("Failed to delete {} from bucket {}: {}", key, self.config.bucket, DisplayErrorContext(e)))?;
+        }
+        Ok(keys.len())
+    }
+}
+
+// Files in an S3-compatible bucket, encrypted at rest like `DiskStorage`: per file,
+// `<file_id>/content.json` holds the sealed content and `<file_id>/metadata.json` the
+// wrapped key and sealed metadata. Everything is loaded into memory on open, and
+// writes go through to the bucket.
+pub struct S3Storage {
+    cache: FileStorage,
+    client: Client,
+    // Runtime the synchronous `Storage` calls block on
+    runtime: Handle,
+    config: S3Config,
+    public_key_pem: String,
+    // Wrapped keys of the files in the bucket
+    wrapped_keys: HashMap<String, String>,
+    // Unwrapped per-file keys
+    keys: HashMap<String, Vec<u8>>,
+    // Usage totals as last written, to skip unchanged snapshots
+    persisted_usage: UsageTotals,
+    // Newest metadata write seen when listing, so refreshes reload only files changed since
+    last_modified: Option<SystemTime>,
+}
+
+impl S3Storage {
+    // Must be called on a multi-threaded Tokio runtime, which the calls through
+    // `Storage` block in place on
+    pub async fn open(config: S3Config, crypto: &CryptoService) -> Result<Self> {
+        let mut storage = Self {
+            cache: FileStorage::new(),
+            client: client(&config).await,
+            runtime: Handle::current(),
+            config,
+            public_key_pem: crypto.get_public_key()?,
+            wrapped_keys: HashMap::new(),
+            keys: HashMap::new(),
+            persisted_usage: UsageTotals::default(),
+            last_modified: None,
+        };
+
+        for (file_id, modified) in storage.list_files().await? {
+            storage.load(crypto, file_id).await?;
+            storage.last_modified = storage.last_modified.max(modified);
+        }
+        storage.load_usage(crypto).await?;
+
+        info!("Opened S3 storage in bucket {} with {} file(s)", storage.config.bucket, storage.wrapped_keys.len());
+        Ok(storage)
+    }
+
+    // TTL given to new files stored without one of their own
+    pub fn with_default_ttl(mut self, ttl_seconds: Option<u64>) -> Self {
+        self.cache = self.cache.with_default_ttl(ttl_seconds);
+        self
+    }
+
+    pub fn with_limits(mut self, limits: StorageLimits) -> Self {
+        self.cache = self.cache.with_limits(limits);
+        self
+    }
+
+    async fn load(&mut self, crypto: &CryptoService, file_id: String) -> Result<()> {
+        let entry = self.get(&self.object_key(&file_id, METADATA_SUFFIX)?).await?
+            .ok_or_else(|| anyhow!("Metadata of file {} is gone", file_id))?;
+        let entry: IndexEntry = serde_json::from_str(&entry)
+            .map_err(|e| anyhow!("Invalid metadata object for {}: {}", file_id, e))?;
+        let content = self.get(&self.object_key(&file_id, CONTENT_SUFFIX)?).await?
+            .ok_or_else(|| anyhow!("Content of file {} is gone", file_id))?;
+        let content: EncryptedContent = serde_json::from_str(&content)
+            .map_err(|e| anyhow!("Invalid content object for {}: {}", file_id, e))?;
+        let (metadata, key) = storage::open_file(crypto, &file_id, &entry, &content)?;
+
+        self.cache.restore(&file_id, metadata);
+        self.keys.insert(file_id.clone(), key);
+        self.wrapped_keys.insert(file_id, entry.wrapped_key);
+        Ok(())
+    }
+
+    async fn load_usage(&mut self, crypto: &CryptoService) -> Result<()> {
+        if let Some(usage) = self.get(&self.key_for("usage.json")).await? {
+            *self.cache.usage_mut() = storage::open_usage(crypto, &usage)?;
+            self.persisted_usage = self.cache.usage().clone();
+        }
+        Ok(())
+    }
+
+    fn key_for(&self, name: &str) -> String {
+        format!("{}{}", self.config.prefix, name)
+    }
+
+    fn object_key(&self, file_id: &str, suffix: &str) -> Result<String> {
+        if file_id.is_empty() || file_id.starts_with('.') || file_id.contains(['/', '\\']) {
+            return Err(anyhow!("Invalid file id for S3 storage: {}", file_id));
+        }
+        Ok(self.key_for(&format!("{}{}", file_id, suffix)))
+    }
+
+    // The `Storage` trait is synchronous, so its calls block the worker thread on S3, as
+    // `DiskStorage` blocks on the file system
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        block_on(&self.runtime, future)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        let object = match self.client.get_object().bucket(&self.config.bucket).key(key).send().await {
+            Ok(object) => object,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+            Err(e) => return Err(anyhow!("Failed to read {} from S3: {}", key, DisplayErrorContext(e))),
+        };
+        let body = object.body.collect().await
+            .map_err(|e| anyhow!("Failed to read {} from S3: {}", key, e))?;
+        String::from_utf8(body.to_vec()).map(Some).map_err(|e| anyhow!("Invalid object {}: {}", key, e))
+    }
+
+    async fn put(&self, key: &str, body: Vec<u8>) -> Result<()> {
+        self.client.put_object()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .content_type("application/json")
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to write {} to S3: {}", key, DisplayErrorContext(e)))?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.client.delete_object()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to delete {} from S3: {}", key, DisplayErrorContext(e)))?;
+        Ok(())
+    }
+
+    // Keys under the prefix, with when each was last written and its size
+    async fn list(&self) -> Result<Vec<(String, (Option<SystemTime>, u64))>> {
+        let mut pages = self.client.list_objects_v2()
+            .bucket(&self.config.bucket)
+            .prefix(&self.config.prefix)
+            .into_paginator()
+            .send();
+        let mut objects = Vec::new();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| anyhow!("Failed to list bucket {}: {}", self.config.bucket, DisplayErrorContext(e)))?;
+            for object in page.contents() {
+                let Some(key) = object.key() else {
+                    continue;
+                };
+                let modified = object.last_modified().and_then(|modified| SystemTime::try_from(*modified).ok());
+                objects.push((key.to_string(), (modified, object.size().unwrap_or_default().max(0) as u64)));
+            }
+        }
+        Ok(objects)
+    }
+
+    // Ids of the stored files, by their metadata objects, with when each was last written
+    async fn list_files(&self) -> Result<Vec<(String, Option<SystemTime>)>> {
+        let files = self.list().await?
+            .into_iter()
+            .filter_map(|(key, (modified, _))| {
+                let file_id = key.strip_prefix(&self.config.prefix)?.strip_suffix(METADATA_SUFFIX)?;
+                Some((file_id.to_string(), modified))
+            })
+            .collect();
+        Ok(files)
+    }
+
+    // Metadata first, so a crash in between leaves at worst an orphaned content object
+    fn remove_from_bucket(&mut self, file_id: &str) {
+        self.keys.remove(file_id);
+        if self.wrapped_keys.remove(file_id).is_none() {
+            return;
+        }
+        for suffix in [METADATA_SUFFIX, CONTENT_SUFFIX] {
+            let removed = self.object_key(file_id, suffix).and_then(|key| self.block_on(self.delete(&key)));
+            if let Err(e) = removed {
+                warn!("Failed to remove file {} from S3: {}", file_id, e);
+                return;
+            }
+        }
+    }
+}
+
+// For calls that need `&mut S3Storage` while they block
+fn block_on<F: Future>(runtime: &Handle, future: F) -> F::Output {
+    tokio::task::block_in_place(|| runtime.block_on(future))
+}
+
+async fn client(config: &S3Config) -> Client {
+    let shared = aws_config::defaults(BehaviorVersion::latest())
+        .region(Region::new(config.region.clone()))
+        .load()
+        .await;
+    let mut builder = aws_sdk_s3::config::Builder::from(&shared).force_path_style(config.force_path_style);
+    if let Some(endpoint) = &config.endpoint {
+        builder = builder.endpoint_url(endpoint);
+    }
+    Client::from_conf(builder.build())
+}
+
+impl Storage for S3Storage {
+    fn store_file(&mut self, file_id: &str, file_name: &str, content: &str) -> &mut FileMetadata {
+        self.cache.store_file(file_id, file_name, content)
+    }
+
+    fn get_metadata(&self, file_id: &str) -> Option<&FileMetadata> {
+        self.cache.get_metadata(file_id)
+    }
+
+    fn get_metadata_mut(&mut self, file_id: &str) -> Option<&mut FileMetadata> {
+        self.cache.get_metadata_mut(file_id)
+    }
+
+    fn delete_file(&mut self, file_id: &str) -> bool {
+        let deleted = self.cache.delete_file(file_id);
+        self.remove_from_bucket(file_id);
+        deleted
+    }
+
+    fn expire_file(&mut self, file_id: &str) -> bool {
+        let deleted = self.cache.expire_file(file_id);
+        self.remove_from_bucket(file_id);
+        deleted
+    }
+
+    fn was_expired(&self, file_id: &str) -> bool {
+        self.cache.was_expired(file_id)
+    }
+
+    fn file_ids(&self) -> Vec<String> {
+        self.cache.file_ids()
+    }
+
+    fn set_report(&mut self, file_id: &str, report: RedactionReport) {
+        self.cache.set_report(file_id, report);
+    }
+
+    fn search_reports(&self, query: &ReportQuery) -> Vec<String> {
+        self.cache.search_reports(query)
+    }
+
+    fn find_by_external_id(&self, tenant: Option<&str>, external_id: &str) -> Option<&str> {
+        self.cache.find_by_external_id(tenant, external_id)
+    }
+
+    fn find_by_content(&self, tenant: Option<&str>, content_key: &str) -> Vec<&str> {
+        self.cache.find_by_content(tenant, content_key)
+    }
+
+    // Content is written before metadata, which is what `open` looks for, so a crash in
+    // between leaves at worst an orphaned content object
+    fn persist(&mut self, file_id: &str) -> Result<()> {
+        let Some(metadata) = self.cache.get_metadata(file_id) else {
+            return Err(anyhow!("File {} is not stored", file_id));
+        };
+        let content_key = self.object_key(file_id, CONTENT_SUFFIX)?;
+        let metadata_key = self.object_key(file_id, METADATA_SUFFIX)?;
+
+        let key = self.keys.entry(file_id.to_string()).or_insert_with(storage::new_file_key);
+        let wrapped_key = match self.wrapped_keys.get(file_id) {
+            Some(wrapped_key) => wrapped_key.clone(),
+            None => envelope::wrap_session_key(&self.public_key_pem, key)?,
+        };
+
+        let (content, entry) = storage::seal_file(file_id, metadata, key, wrapped_key.clone())?;
+        let content = serde_json::to_vec(&content)
+            .map_err(|e| anyhow!("Failed to serialize file {}: {}", file_id, e))?;
+        let entry = serde_json::to_vec(&entry)
+            .map_err(|e| anyhow!("Failed to serialize metadata of file {}: {}", file_id, e))?;
+        self.block_on(async {
+            self.put(&content_key, content).await?;
+            self.put(&metadata_key, entry).await
+        })?;
+
+        self.wrapped_keys.insert(file_id.to_string(), wrapped_key);
+        Ok(())
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "s3"
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        let probe = self.key_for("ready-probe");
+        self.block_on(async {
+            self.put(&probe, b"ready".to_vec()).await?;
+            self.delete(&probe).await
+        })
+    }
+
+    fn usage(&self) -> &UsageTotals {
+        self.cache.usage()
+    }
+
+    fn usage_mut(&mut self) -> &mut UsageTotals {
+        self.cache.usage_mut()
+    }
+
+    fn limits(&self) -> &StorageLimits {
+        self.cache.limits()
+    }
+
+    fn touch(&self, file_id: &str) {
+        self.cache.touch(file_id);
+    }
+
+    fn eviction_candidates(&self, keep: &str) -> Vec<String> {
+        self.cache.eviction_candidates(keep)
+    }
+
+    fn persist_usage(&mut self) -> Result<()> {
+        if *self.cache.usage() == self.persisted_usage {
+            return Ok(());
+        }
+        let usage = storage::seal_usage(&self.public_key_pem, self.cache.usage())?;
+        self.block_on(self.put(&self.key_for("usage.json"), usage))?;
+        self.persisted_usage = self.cache.usage().clone();
+        Ok(())
+    }
+
+    // Rewrites each file's objects, sealed under its existing key
+    fn rewrap(&mut self, crypto: &CryptoService) -> Result<usize> {
+        self.public_key_pem = crypto.get_public_key()?;
+        let file_ids: Vec<String> = self.wrapped_keys.keys().cloned().collect();
+        for file_id in &file_ids {
+            self.wrapped_keys.remove(file_id);
+            self.persist(file_id)?;
+        }
+        let usage = storage::seal_usage(&self.public_key_pem, self.cache.usage())?;
+        self.block_on(self.put(&self.key_for("usage.json"), usage))?;
+        self.persisted_usage = self.cache.usage().clone();
+        Ok(file_ids.len())
+    }
+
+    // Content objects no stored file refers to, such as those of a crash between
+    // `persist` writing content and metadata
+    fn collect_orphans(&mut self, min_age: Duration) -> Result<(usize, u64)> {
+        let objects = self.block_on(self.list())?;
+        let mut reclaimed = (0, 0);
+        for (key, (modified, size)) in objects {
+            let Some(file_id) = key.strip_prefix(&self.config.prefix).and_then(|key| key.strip_suffix(CONTENT_SUFFIX)) else {
+                continue;
+            };
+            let age = modified.and_then(|modified| modified.elapsed().ok()).unwrap_or_default();
+            if self.wrapped_keys.contains_key(file_id) || self.cache.get_metadata(file_id).is_some() || age < min_age {
+                continue;
+            }
+            match self.block_on(self.delete(&key)) {
+                Ok(()) => {
+                    info!("Removed orphaned S3 object {}", key);
+                    reclaimed.0 += 1;
+                    reclaimed.1 += size;
+                }
+                Err(e) => warn!("Failed to remove orphaned S3 object {}: {}", key, e),
+            }
+        }
+        Ok(reclaimed)
+    }
+
+    // Files whose metadata object is new, or was written since the last listing, are
+    // loaded again. One that cannot be read yet is left for the next refresh.
+    fn refresh(&mut self, crypto: &CryptoService) -> Result<usize> {
+        let runtime = self.runtime.clone();
+        let files: HashMap<String, Option<SystemTime>> = self.block_on(self.list_files())?.into_iter().collect();
+        let removed: Vec<String> = self.wrapped_keys.keys().filter(|file_id| !files.contains_key(*file_id)).cloned().collect();
+        for file_id in &removed {
+            self.cache.forget(file_id);
+            self.keys.remove(file_id);
+            self.wrapped_keys.remove(file_id);
+        }
+        let mut changed = removed.len();
+        let seen = self.last_modified;
+        for (file_id, modified) in files {
+            let unchanged = self.wrapped_keys.contains_key(&file_id) && seen.is_some_and(|seen| modified.is_some_and(|modified| modified < seen));
+            if unchanged {
+                continue;
+            }
+            match block_on(&runtime, self.load(crypto, file_id.clone())) {
+                Ok(()) => changed += 1,
+                Err(e) => warn!("Failed to load file {} from the shared bucket: {}", file_id, e),
+            }
+            self.last_modified = self.last_modified.max(modified);
+        }
+        block_on(&runtime, self.load_usage(crypto))?;
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Against MinIO, e.g. `docker run -p 9000:9000 minio/minio server /data`, with
+    // `AWS_ACCESS_KEY_ID=minioadmin AWS_SECRET_ACCESS_KEY=minioadmin` and
+    // `cargo test -p sentient-redactor-core --features s3 -- --ignored s3`
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs an S3-compatible server at S3_TEST_ENDPOINT"]
+    async fn test_s3_storage_round_trips_through_the_bucket() {
+        let crypto = CryptoService::generate(false).unwrap();
+        let config = S3Config {
+            bucket: std::env::var("S3_TEST_BUCKET").unwrap_or_else(|_| "redactor-test".to_string()),
+            prefix: format!("test-{}/", crate::operations::new_file_id()),
+            endpoint: Some(std::env::var("S3_TEST_ENDPOINT").unwrap_or_else(|_| "http://localhost:9000".to_string())),
+            region: "us-east-1".to_string(),
+            force_path_style: true,
+        };
+
+        // Already there when the test runs again
+        let _ = client(&config).await.create_bucket().bucket(&config.bucket).send().await;
+        let mut storage = S3Storage::open(config.clone(), &crypto).await.unwrap();
+        let metadata = storage.store_file("f1", "notes.txt", "<PERSON> called");
+        metadata.tenant = Some("acme".to_string());
+        let mut report = RedactionReport::default();
+        report.entities.insert("PERSON".to_string(), 1);
+        storage.set_report("f1", report);
+        storage.persist("f1").unwrap();
+        storage.store_file("f2", "gone.txt", "deleted");
+        storage.persist("f2").unwrap();
+        storage.delete_file("f2");
+        storage.usage_mut().record(Some("acme"), 14, None);
+        storage.persist_usage().unwrap();
+
+        // A crash after the content was written
+        storage.block_on(storage.put(&storage.object_key("f3", CONTENT_SUFFIX).unwrap(), b"{}".to_vec())).unwrap();
+
+        let mut reopened = S3Storage::open(config, &crypto).await.unwrap();
+        assert_eq!(reopened.file_ids(), ["f1"]);
+        let metadata = reopened.get_metadata("f1").unwrap();
+        assert_eq!((metadata.content.as_str(), metadata.tenant.as_deref()), ("<PERSON> called", Some("acme")));
+        let query = ReportQuery { entity: Some("PERSON".to_string()), tenant: Some("acme".to_string()), ..ReportQuery::default() };
+        assert_eq!(reopened.search_reports(&query), ["f1"]);
+        assert_eq!(reopened.usage().all.files, 1);
+
+        assert_eq!(reopened.collect_orphans(Duration::ZERO).unwrap(), (1, 2));
+        reopened.delete_file("f1");
+        assert!(reopened.block_on(reopened.list()).unwrap().iter().all(|(key, _)| key.ends_with("usage.json")));
+    }
+}
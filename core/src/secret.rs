@@ -0,0 +1,186 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::io::{self, Read};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use zeroize::{Zeroize, Zeroizing};
+
+const READ_CHUNK_BYTES: usize = 8192;
+
+static LIVE_SECRETS: AtomicUsize = AtomicUsize::new(0);
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+// Secrets alive in the process and their total size. Outside an upload in progress only
+// the session keys of stored files remain, one per file stored with a key: nothing else
+// decrypted outlives the request that decrypted it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Retained {
+    pub secrets: usize,
+    pub bytes: usize,
+}
+
+pub fn retained() -> Retained {
+    Retained { secrets: LIVE_SECRETS.load(Ordering::SeqCst), bytes: LIVE_BYTES.load(Ordering::SeqCst) }
+}
+
+// Counts one secret in `retained` for as long as it lives
+struct Tracked(usize);
+
+impl Tracked {
+    fn new(len: usize) -> Self {
+        LIVE_SECRETS.fetch_add(1, Ordering::SeqCst);
+        LIVE_BYTES.fetch_add(len, Ordering::SeqCst);
+        Self(len)
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        LIVE_SECRETS.fetch_sub(1, Ordering::SeqCst);
+        LIVE_BYTES.fetch_sub(self.0, Ordering::SeqCst);
+    }
+}
+
+// Decrypted key material or plaintext, zeroized when dropped. It cannot be changed in
+// place, so no reallocation leaves a copy behind, and its Debug output hides it.
+pub struct SecretBytes {
+    bytes: Zeroizing<Vec<u8>>,
+    _tracked: Tracked,
+}
+
+impl SecretBytes {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self::from_zeroizing(Zeroizing::new(bytes))
+    }
+
+    fn from_zeroizing(bytes: Zeroizing<Vec<u8>>) -> Self {
+        Self { _tracked: Tracked::new(bytes.len()), bytes }
+    }
+
+    // All of `reader`, into buffers that are zeroized as they are outgrown
+    pub fn read_from(mut reader: impl Read) -> io::Result<Self> {
+        let mut bytes = Zeroizing::new(Vec::new());
+        let mut chunk = Zeroizing::new([0u8; READ_CHUNK_BYTES]);
+        loop {
+            let read = match reader.read(chunk.as_mut_slice()) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if bytes.len() + read > bytes.capacity() {
+                let mut grown = Zeroizing::new(Vec::with_capacity((bytes.len() + read).max(bytes.capacity() * 2)));
+                grown.extend_from_slice(&bytes);
+                bytes = grown;
+            }
+            bytes.extend_from_slice(&chunk[..read]);
+        }
+        Ok(Self::from_zeroizing(bytes))
+    }
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+// A separate copy, zeroized and counted on its own
+impl Clone for SecretBytes {
+    fn clone(&self) -> Self {
+        Self::new(self.bytes.to_vec())
+    }
+}
+
+// As a byte sequence, the encoding of the `Vec<u8>` it replaces in stored metadata
+impl Serialize for SecretBytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.bytes.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretBytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(Self::new)
+    }
+}
+
+impl PartialEq for SecretBytes {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl Eq for SecretBytes {}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretBytes({} bytes)", self.bytes.len())
+    }
+}
+
+// A decrypted plaintext as text, zeroized when dropped
+pub struct SecretText {
+    text: Zeroizing<String>,
+    _tracked: Tracked,
+}
+
+impl SecretText {
+    pub fn new(text: String) -> Self {
+        Self { _tracked: Tracked::new(text.len()), text: Zeroizing::new(text) }
+    }
+
+    // Takes the bytes over without copying them; on failure they are zeroized
+    pub fn from_utf8(mut bytes: SecretBytes) -> Result<Self, std::str::Utf8Error> {
+        match String::from_utf8(std::mem::take(&mut *bytes.bytes)) {
+            Ok(text) => Ok(Self::new(text)),
+            Err(e) => {
+                let error = e.utf8_error();
+                e.into_bytes().zeroize();
+                Err(error)
+            }
+        }
+    }
+}
+
+impl Deref for SecretText {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl fmt::Debug for SecretText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretText({} bytes)", self.text.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secrets_are_counted_while_alive_and_hidden() {
+        let text = "Mail jane@example.com about the claim. ".repeat(500);
+        let read = SecretBytes::read_from(text.as_bytes()).unwrap();
+        assert_eq!(&*read, text.as_bytes());
+        assert_eq!(format!("{:?}", read), format!("SecretBytes({} bytes)", text.len()));
+        // Other tests hold secrets of their own, so only a lower bound holds here
+        assert!(retained().bytes >= text.len());
+
+        let text = SecretText::from_utf8(read).unwrap();
+        assert!(text.starts_with("Mail jane@example.com"));
+        assert!(!format!("{:?}", text).contains("jane"));
+        assert!(SecretText::from_utf8(SecretBytes::new(vec![0xff, 0xfe])).is_err());
+
+        // Stored metadata keeps the encoding of a plain byte vector
+        let key = SecretBytes::new(vec![7, 8]);
+        assert_eq!(serde_json::to_string(&key).unwrap(), "[7,8]");
+        assert_eq!(serde_json::from_str::<SecretBytes>("[7,8]").unwrap(), key.clone());
+    }
+}
@@ -0,0 +1,161 @@
+use rand::{rngs::OsRng, RngCore};
+use serde::Serialize;
+use std::time::Instant;
+use tracing::{info, warn};
+
+use crate::caller::Caller;
+use crate::crypto;
+use crate::operations::{self, ErrorKind, OperationError, UploadContext, UploadRequest};
+
+// Synthetic values, one per entity type, with the sentence each appears in. None of them
+// belongs to a real person: reserved phone, IP and email ranges, test card numbers.
+const SAMPLES: &[(&str, &str, &str)] = &[
+    ("PERSON", "John Smith", "The account holder is John Smith."),
+    ("EMAIL_ADDRESS", "canary@example.com", "Send the statement to canary@example.com today."),
+    ("PHONE_NUMBER", "(212) 555-0143", "Call the branch at (212) 555-0143 for details."),
+    ("US_SSN", "536-22-8726", "His social security number is 536-22-8726."),
+    ("CREDIT_CARD", "4111 1111 1111 1111", "The card on file is 4111 1111 1111 1111."),
+    ("IP_ADDRESS", "203.0.113.42", "The last login came from 203.0.113.42."),
+    ("IBAN_CODE", "GB82 WEST 1234 5698 7654 32", "Refunds go to IBAN GB82 WEST 1234 5698 7654 32."),
+    ("URL", "https://canary.example.org/profile", "The profile is at https://canary.example.org/profile."),
+    ("LOCATION", "Chicago", "He moved to Chicago last year."),
+    ("DATE_TIME", "March 3, 1985", "He was born on March 3, 1985."),
+];
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SelftestReport {
+    // Whether every entity passed
+    pub passed: bool,
+    pub backend: String,
+    pub duration_ms: f64,
+    pub entities: Vec<EntityResult>,
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct EntityResult {
+    pub entity_type: String,
+    // Detected, and its value gone from the output
+    pub passed: bool,
+    pub detected: usize,
+    // Whether the synthetic value was still in the redacted output
+    pub leaked: bool,
+}
+
+// Entity types the self-test can check: those with a sample that the backend detects,
+// narrowed to `requested` when given
+pub fn entity_types(supported: &[&str], requested: Option<&[String]>) -> Vec<&'static str> {
+    SAMPLES.iter()
+        .map(|(entity_type, _, _)| *entity_type)
+        .filter(|entity_type| supported.contains(entity_type))
+        .filter(|entity_type| requested.is_none_or(|requested| requested.iter().any(|requested| requested == entity_type)))
+        .collect()
+}
+
+// Upload a synthetic document covering `entity_types` through the whole pipeline,
+// encrypted under the service's own key, as `caller`. The stored file is deleted once
+// it has been checked; the upload still counts toward the caller's tenant usage.
+pub async fn run(context: &UploadContext<'_>, caller: &Caller, entity_types: &[&str]) -> Result<SelftestReport, OperationError> {
+    let started = Instant::now();
+    let samples: Vec<_> = SAMPLES.iter().filter(|(entity_type, _, _)| entity_types.contains(entity_type)).collect();
+    if samples.is_empty() {
+        return Err(OperationError::new(ErrorKind::BadRequest, "No entity types to test"));
+    }
+    let document = samples.iter().map(|(_, _, sentence)| *sentence).collect::<Vec<_>>().join("\n");
+
+    let mut session_key = [0u8; 32];
+    OsRng.fill_bytes(&mut session_key);
+    let internal = |e: anyhow::Error| OperationError::new(ErrorKind::Internal, format!("Failed to encrypt the self-test document: {}", e));
+    let encrypted_session_key = context.crypto.wrap_session_key(&session_key).map_err(internal)?;
+    let (encrypted_data, nonce) = crypto::encrypt_with_session_key(document.as_bytes(), &session_key, &[]).map_err(internal)?;
+
+    let request = UploadRequest {
+        encrypted_data,
+        encrypted_session_key: Some(encrypted_session_key),
+        nonce: Some(nonce),
+        file_name: Some("selftest".to_string()),
+        entities: Some(samples.iter().map(|(entity_type, _, _)| entity_type.to_string()).collect()),
+        ..UploadRequest::default()
+    };
+    let response = operations::process_upload(context, caller, request).await?;
+
+    let content = {
+        let mut storage = context.storage.write().await;
+        let content = storage.get_metadata(&response.file_id).map(|metadata| metadata.content.clone());
+        storage.delete_file(&response.file_id);
+        content.ok_or_else(|| OperationError::new(ErrorKind::Internal, "The self-test file was not stored"))?
+    };
+
+    let counts = response.report.map(|report| report.entities).unwrap_or_default();
+    let entities: Vec<EntityResult> = samples.iter()
+        .map(|(entity_type, value, _)| {
+            let detected = counts.get(*entity_type).copied().unwrap_or_default();
+            let leaked = content.contains(value);
+            EntityResult { entity_type: entity_type.to_string(), passed: detected > 0 && !leaked, detected, leaked }
+        })
+        .collect();
+
+    let passed = entities.iter().all(|entity| entity.passed);
+    if passed {
+        info!("Redaction self-test passed for {} entity type(s)", entities.len());
+    } else {
+        let failed: Vec<&str> = entities.iter().filter(|entity| !entity.passed).map(|entity| entity.entity_type.as_str()).collect();
+        warn!("Redaction self-test failed for {}", failed.join(", "));
+    }
+    Ok(SelftestReport {
+        passed,
+        backend: response.profile.backend,
+        duration_ms: started.elapsed().as_secs_f64() * 1000.0,
+        entities,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::DEFAULT_MAX_DECOMPRESSED_BYTES;
+    use crate::config::AppConfig;
+    use crate::deprecation::ProtocolDeprecations;
+    use crate::labels::LabelCatalog;
+    use crate::policy::RedactionPolicy;
+    use crate::redactor::RedactorService;
+    use crate::relay::RelayRegistry;
+    use crate::rules::RegexEngine;
+    use crate::session::SessionManager;
+    use crate::storage::{FileStorage, Storage};
+    use crate::CryptoService;
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn test_regex_entities_pass_and_leave_no_file() {
+        let redactor = RedactorService::with_backend(Box::new(RegexEngine::new()), LabelCatalog::default());
+        let crypto = CryptoService::generate(false).unwrap();
+        let storage: RwLock<Box<dyn Storage>> = RwLock::new(Box::new(FileStorage::new()));
+        let context = UploadContext {
+            crypto: &crypto,
+            redactor: &redactor,
+            relays: &RelayRegistry::from_env().unwrap(),
+            storage: &storage,
+            policy: &RedactionPolicy::default(),
+            sessions: &SessionManager::new(60, 10),
+            deprecations: &ProtocolDeprecations::default(),
+            max_decompressed_bytes: DEFAULT_MAX_DECOMPRESSED_BYTES,
+            settings: Arc::new(AppConfig::default().runtime_settings().unwrap()),
+        };
+
+        // The regex backend has no rule for names, so they are not tested
+        let types = entity_types(&redactor.entities(), None);
+        assert_eq!(types, ["EMAIL_ADDRESS", "PHONE_NUMBER", "US_SSN", "CREDIT_CARD", "IP_ADDRESS"]);
+
+        let report = run(&context, &Caller::default(), &types).await.unwrap();
+        assert!(report.passed, "{:?}", report.entities.iter().map(|entity| (&entity.entity_type, entity.detected)).collect::<Vec<_>>());
+        assert_eq!((report.backend.as_str(), report.entities.len()), ("regex", 5));
+        assert!(storage.read().await.file_ids().is_empty());
+
+        let requested = ["US_SSN".to_string(), "PERSON".to_string()];
+        assert_eq!(entity_types(&redactor.entities(), Some(&requested)), ["US_SSN"]);
+        assert!(run(&context, &Caller::default(), &[]).await.is_err());
+    }
+}
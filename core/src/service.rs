@@ -0,0 +1,226 @@
+use anyhow::{anyhow, Result};
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower::Service;
+
+use crate::caller::Caller;
+use crate::crypto::CryptoService;
+use crate::operations::ErrorKind;
+use crate::backend::EntityFilter;
+use crate::bidi::BidiMode;
+use crate::masking::{HashMasking, HASH};
+use crate::redactor::{RedactionOptions, RedactionReport, RedactorService};
+use crate::rules::{CustomPattern, CustomRules};
+use crate::spans::{self, ByteSpan};
+
+const DEFAULT_BODY_LIMIT: usize = 16 * 1024 * 1024;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum RedactionInput {
+    Plaintext {
+        text: String,
+    },
+    // Same envelope as the upload endpoint: ChaCha20-Poly1305 ciphertext and an
+    // RSA-OAEP-wrapped session key
+    Envelope {
+        encrypted_data: String,
+        encrypted_session_key: String,
+        nonce: Option<String>,
+    },
+}
+
+#[derive(Deserialize)]
+pub struct RedactionRequest {
+    #[serde(flatten)]
+    pub input: RedactionInput,
+    #[serde(default = "default_strategy")]
+    pub strategy: String,
+    #[serde(default = "default_language")]
+    pub language: String,
+    // Taken from the resolved caller when the request arrives over HTTP
+    #[serde(skip)]
+    pub tenant: Option<String>,
+    #[serde(default)]
+    pub protected_spans: Vec<ByteSpan>,
+    #[serde(default)]
+    pub force_redact_spans: Vec<ByteSpan>,
+    #[serde(default)]
+    pub custom_patterns: Vec<CustomPattern>,
+    #[serde(default)]
+    pub deny_list: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct RedactionOutput {
+    pub redacted: String,
+    pub report: RedactionReport,
+}
+
+fn default_strategy() -> String {
+    "replace".to_string()
+}
+
+fn default_language() -> String {
+    "en".to_string()
+}
+
+// The redaction pipeline as a tower `Service`. It accepts typed `RedactionRequest`s, or
+// JSON `http::Request`s so it can be mounted with `Router::route_service` behind the host
+// server's own authentication layers. Envelopes are only accepted when a `CryptoService`
+// is attached.
+#[derive(Clone)]
+pub struct RedactionService {
+    redactor: Arc<RedactorService>,
+    crypto: Option<Arc<CryptoService>>,
+    body_limit: usize,
+}
+
+impl RedactionService {
+    pub fn new(redactor: Arc<RedactorService>) -> Self {
+        Self {
+            redactor,
+            crypto: None,
+            body_limit: DEFAULT_BODY_LIMIT,
+        }
+    }
+
+    pub fn with_crypto(mut self, crypto: Arc<CryptoService>) -> Self {
+        self.crypto = Some(crypto);
+        self
+    }
+
+    pub fn with_body_limit(mut self, body_limit: usize) -> Self {
+        self.body_limit = body_limit;
+        self
+    }
+
+    pub async fn redact(&self, request: RedactionRequest) -> Result<RedactionOutput> {
+        let text = match request.input {
+            RedactionInput::Plaintext { text } => text,
+            RedactionInput::Envelope { encrypted_data, encrypted_session_key, nonce } => {
+                let crypto = self.crypto.as_ref()
+                    .ok_or_else(|| anyhow!("Encrypted envelopes are not accepted by this service"))?;
+                let session_key = crypto.decrypt_session_key(&encrypted_session_key)?;
+                crypto.decrypt_file_with_session_key(&encrypted_data, &session_key, nonce.as_deref(), &[])?
+            }
+        };
+
+        let segments = spans::resolve_segments(&text, &request.protected_spans, &request.force_redact_spans)?;
+        let custom = CustomRules::new(&request.custom_patterns, &request.deny_list)?;
+        // Surrogates are keyed by the attached `CryptoService`
+        let hashing = match (&self.crypto, request.strategy == HASH) {
+            (Some(crypto), true) => Some(HashMasking::new(crypto.masking_key(request.tenant.as_deref()), None)?),
+            _ => None,
+        };
+        let options = RedactionOptions {
+            strategy: &request.strategy,
+            tenant: request.tenant.as_deref(),
+            language: &request.language,
+            pipelines: None,
+            filter: EntityFilter::default(),
+            bidi: BidiMode::default(),
+            custom: Some(&custom).filter(|custom| !custom.is_empty()),
+            boundaries: None,
+            hashing: hashing.as_ref(),
+        };
+        let (redacted, report) = self.redactor.redact_segments_with_report(&segments, &options).await?;
+
+        Ok(RedactionOutput { redacted, report })
+    }
+}
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+impl Service<RedactionRequest> for RedactionService {
+    type Response = RedactionOutput;
+    type Error = anyhow::Error;
+    type Future = BoxFuture<Result<RedactionOutput>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: RedactionRequest) -> Self::Future {
+        let service = self.clone();
+        Box::pin(async move { service.redact(request).await })
+    }
+}
+
+impl Service<Request<Body>> for RedactionService {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let service = self.clone();
+        Box::pin(async move {
+            let error = |kind: ErrorKind, error: String| {
+                let status = StatusCode::from_u16(kind.status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                (status, Json(serde_json::json!({ "error": error, "code": kind.code() }))).into_response()
+            };
+
+            let tenant = match request.extensions().get::<Caller>() {
+                Some(caller) => caller.tenant.clone(),
+                None => Caller::from_headers(request.headers()).tenant,
+            };
+            let body = match axum::body::to_bytes(request.into_body(), service.body_limit).await {
+                Ok(body) => body,
+                Err(e) => return Ok(error(ErrorKind::PayloadTooLarge, format!("Failed to read body: {}", e))),
+            };
+            let mut request: RedactionRequest = match serde_json::from_slice(&body) {
+                Ok(request) => request,
+                Err(e) => return Ok(error(ErrorKind::BadRequest, format!("Invalid request: {}", e))),
+            };
+            request.tenant = tenant;
+
+            Ok(match service.redact(request).await {
+                Ok(output) => Json(output).into_response(),
+                Err(e) => error(ErrorKind::BadRequest, format!("Redaction failed: {}", e)),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_http_requests() {
+        let service = RedactionService::new(Arc::new(RedactorService::new().unwrap()));
+
+        // A fully force-redacted document never reaches the analyzer
+        let request = Request::post("/redact")
+            .body(Body::from(r#"{"text": "4111 1111", "force_redact_spans": [{"start": 0, "end": 9}]}"#))
+            .unwrap();
+        let response = service.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let output: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(output["redacted"], "<REDACTED>");
+        assert_eq!(output["report"]["forced_redactions"], 1);
+
+        // Envelopes need a CryptoService
+        let request = Request::post("/redact")
+            .body(Body::from(r#"{"encrypted_data": "AA==", "encrypted_session_key": "AA=="}"#))
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
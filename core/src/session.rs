@@ -0,0 +1,359 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use chacha20poly1305::{aead::Aead, ChaCha20Poly1305, Key, KeyInit, Nonce};
+use hkdf::Hkdf;
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::caller::Caller;
+use crate::crypto::CryptoService;
+use crate::envelope;
+use crate::secret::SecretBytes;
+
+const SESSION_KEY_INFO: &[u8] = b"sentient-redactor x25519 session key v1";
+const STREAM_KEY_INFO: &[u8] = b"sentient-redactor stream key v1";
+const DEFAULT_TTL_SECONDS: u64 = 3600;
+const DEFAULT_MAX_UPLOADS: usize = 1000;
+// Live sessions kept; the one closest to expiry makes room for a new one
+const MAX_SESSIONS: usize = 10_000;
+
+// How the client establishes a session: an X25519 public key for an ephemeral key
+// agreement, or a session key RSA-wrapped to the service key as for a single upload
+#[derive(Default, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SessionRequest {
+    pub client_public_key: Option<String>,
+    pub encrypted_session_key: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SessionGrant {
+    pub session_id: String,
+    pub key_exchange: &'static str,
+    // Server's ephemeral X25519 public key, base64
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_public_key: Option<String>,
+    // RSA signature by the service key over client key, server key and session id, so
+    // clients can tie the exchange to the (attested) service key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    pub expires_at: u64,
+    pub max_uploads: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SessionError {
+    Unknown,
+    Expired,
+    // Used for `max_uploads` uploads already
+    Exhausted,
+    NonceRequired,
+    NonceReused,
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            SessionError::Unknown => "Unknown session",
+            SessionError::Expired => "Session has expired",
+            SessionError::Exhausted => "Session has reached its upload limit",
+            SessionError::NonceRequired => "Uploads under a session need a nonce",
+            SessionError::NonceReused => "Nonce was already used in this session",
+        };
+        f.write_str(message)
+    }
+}
+
+struct Session {
+    key: Zeroizing<Vec<u8>>,
+    expires_at: u64,
+    // Only the caller that negotiated the session can upload under it
+    principal: Option<String>,
+    tenant: Option<String>,
+    // Nonces seen so far; a reused nonce under the same key would break ChaCha20-Poly1305
+    nonces: HashSet<String>,
+    // Establishment or the last upload, whichever is later
+    last_used: u64,
+}
+
+// Symmetric keys negotiated once per handshake and reused by the uploads that name the
+// session, instead of unwrapping an RSA-wrapped key on every upload. Sessions live in
+// memory for `SESSION_TTL_SECONDS` and `SESSION_MAX_UPLOADS` uploads.
+pub struct SessionManager {
+    sessions: Mutex<HashMap<String, Session>>,
+    ttl_seconds: u64,
+    max_uploads: usize,
+}
+
+impl SessionManager {
+    pub fn from_env() -> Self {
+        let env_number = |name: &str, default| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|value| *value > 0)
+                .unwrap_or(default)
+        };
+
+        Self::new(
+            env_number("SESSION_TTL_SECONDS", DEFAULT_TTL_SECONDS),
+            env_number("SESSION_MAX_UPLOADS", DEFAULT_MAX_UPLOADS as u64) as usize,
+        )
+    }
+
+    pub fn new(ttl_seconds: u64, max_uploads: usize) -> Self {
+        Self { sessions: Mutex::new(HashMap::new()), ttl_seconds, max_uploads }
+    }
+
+    pub fn establish(&self, crypto: &CryptoService, caller: &Caller, request: &SessionRequest) -> Result<SessionGrant> {
+        let mut token = [0u8; 16];
+        OsRng.fill_bytes(&mut token);
+        let session_id = envelope::hex(&token);
+
+        let (key, key_exchange, server_public_key, signature) = match (&request.client_public_key, &request.encrypted_session_key) {
+            (Some(client_public_key), None) => {
+                let client_bytes: [u8; 32] = BASE64.decode(client_public_key)
+                    .map_err(|e| anyhow!("Invalid base64 client_public_key: {}", e))?
+                    .try_into()
+                    .map_err(|_| anyhow!("client_public_key must be a 32-byte X25519 key"))?;
+
+                let secret = EphemeralSecret::random_from_rng(OsRng);
+                let server_public_key = PublicKey::from(&secret);
+                let shared = secret.diffie_hellman(&PublicKey::from(client_bytes));
+                if !shared.was_contributory() {
+                    return Err(anyhow!("client_public_key is a low-order point"));
+                }
+
+                let key = Zeroizing::new(derive_session_key(shared.as_bytes(), &session_id, &client_bytes, server_public_key.as_bytes())?);
+                let transcript = [client_bytes.as_slice(), server_public_key.as_bytes(), session_id.as_bytes()].concat();
+                (key, "x25519-hkdf-sha256", Some(BASE64.encode(server_public_key.as_bytes())), Some(crypto.sign(&transcript)))
+            }
+            (None, Some(encrypted_session_key)) => {
+                let key = Zeroizing::new(crypto.decrypt_session_key(encrypted_session_key)?.to_vec());
+                if key.len() != 32 {
+                    return Err(anyhow!("Session key must be 32 bytes"));
+                }
+                (key, "rsa-oaep-sha256", None, None)
+            }
+            _ => return Err(anyhow!("Provide exactly one of client_public_key or encrypted_session_key")),
+        };
+
+        let now = now();
+        let expires_at = now + self.ttl_seconds;
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            sessions.retain(|_, session| session.expires_at > now);
+            // Evict the session closest to expiry; its client re-handshakes
+            if sessions.len() >= MAX_SESSIONS {
+                let oldest = sessions.iter().min_by_key(|(_, session)| session.expires_at).map(|(id, _)| id.clone());
+                if let Some(oldest) = oldest {
+                    sessions.remove(&oldest);
+                }
+            }
+            sessions.insert(session_id.clone(), Session {
+                key,
+                expires_at,
+                principal: caller.principal.clone(),
+                tenant: caller.tenant.clone(),
+                nonces: HashSet::new(),
+                last_used: now,
+            });
+        }
+
+        info!("Established {} session {}", key_exchange, session_id);
+        Ok(SessionGrant {
+            session_id,
+            key_exchange,
+            server_public_key,
+            signature,
+            expires_at,
+            max_uploads: self.max_uploads,
+        })
+    }
+
+    // The session's key for one upload under `nonce`, which is spent even if the upload
+    // fails afterwards
+    pub fn upload_key(&self, caller: &Caller, session_id: &str, nonce: Option<&str>) -> Result<SecretBytes, SessionError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(session_id)
+            .filter(|session| session.principal == caller.principal && session.tenant == caller.tenant)
+            .ok_or(SessionError::Unknown)?;
+
+        if session.expires_at <= now() {
+            sessions.remove(session_id);
+            return Err(SessionError::Expired);
+        }
+        if session.nonces.len() >= self.max_uploads {
+            return Err(SessionError::Exhausted);
+        }
+        let nonce = nonce.ok_or(SessionError::NonceRequired)?;
+        if !session.nonces.insert(nonce.to_string()) {
+            return Err(SessionError::NonceReused);
+        }
+        session.last_used = now();
+        Ok(SecretBytes::new(session.key.to_vec()))
+    }
+
+    // Drop sessions that expired, used up their uploads, or saw no upload for
+    // `idle_seconds`. Returns how many went and the bytes of keys and nonces they held.
+    pub fn sweep(&self, idle_seconds: u64) -> (usize, u64) {
+        let now = now();
+        let mut reclaimed = (0, 0);
+        self.sessions.lock().unwrap().retain(|_, session| {
+            let live = session.expires_at > now
+                && session.nonces.len() < self.max_uploads
+                && now.saturating_sub(session.last_used) < idle_seconds;
+            if !live {
+                reclaimed.0 += 1;
+                reclaimed.1 += (session.key.len() + session.nonces.iter().map(String::len).sum::<usize>()) as u64;
+            }
+            live
+        });
+        reclaimed
+    }
+
+    // Forget every session at shutdown, zeroizing its key. Returns how many there were.
+    pub fn clear(&self) -> usize {
+        let mut sessions = self.sessions.lock().unwrap();
+        let count = sessions.len();
+        for session in sessions.values_mut() {
+            session.key.zeroize();
+        }
+        sessions.clear();
+        count
+    }
+}
+
+// HKDF-SHA256 over the shared secret, salted with the session id and bound to both
+// public keys
+pub fn derive_session_key(shared_secret: &[u8], session_id: &str, client_public_key: &[u8], server_public_key: &[u8]) -> Result<Vec<u8>> {
+    let mut key = vec![0u8; 32];
+    Hkdf::<Sha256>::new(Some(session_id.as_bytes()), shared_secret)
+        .expand_multi_info(&[SESSION_KEY_INFO, client_public_key, server_public_key], &mut key)
+        .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+// Frames of one streamed redaction under a session. Each stream has its own key, derived
+// from the session key and the stream's nonce (claimed like an upload's), so frames can
+// use counter nonces: byte 0 is the direction (0 client to server, 1 back), bytes 4..12
+// the frame number, big-endian.
+pub struct StreamCipher {
+    cipher: ChaCha20Poly1305,
+    // Direction byte of the frames this side seals
+    outgoing: u8,
+    sealed: u64,
+    opened: u64,
+}
+
+impl StreamCipher {
+    pub fn server(session_key: &[u8], stream_nonce: &[u8]) -> Result<Self> {
+        Self::new(session_key, stream_nonce, 1)
+    }
+
+    pub fn client(session_key: &[u8], stream_nonce: &[u8]) -> Result<Self> {
+        Self::new(session_key, stream_nonce, 0)
+    }
+
+    fn new(session_key: &[u8], stream_nonce: &[u8], outgoing: u8) -> Result<Self> {
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(stream_nonce), session_key)
+            .expand(STREAM_KEY_INFO, &mut key)
+            .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+        Ok(Self { cipher: ChaCha20Poly1305::new(Key::from_slice(&key)), outgoing, sealed: 0, opened: 0 })
+    }
+
+    pub fn seal(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = frame_nonce(self.outgoing, self.sealed);
+        self.sealed += 1;
+        self.cipher.encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|e| anyhow!("Encryption failed: {}", e))
+    }
+
+    pub fn open(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = frame_nonce(1 - self.outgoing, self.opened);
+        self.opened += 1;
+        self.cipher.decrypt(Nonce::from_slice(&nonce), ciphertext)
+            .map_err(|_| anyhow!("Frame {} failed to decrypt", self.opened - 1))
+    }
+}
+
+fn frame_nonce(direction: u8, counter: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[0] = direction;
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_x25519_sessions_share_a_key_with_the_client() {
+        let crypto = CryptoService::new().unwrap();
+        let sessions = SessionManager::new(3600, 2);
+        let alice = Caller { principal: Some("alice".to_string()), tenant: None, scopes: None };
+
+        let client_secret = EphemeralSecret::random_from_rng(OsRng);
+        let client_public_key = PublicKey::from(&client_secret);
+        let request = SessionRequest { client_public_key: Some(BASE64.encode(client_public_key.as_bytes())), encrypted_session_key: None };
+        let grant = sessions.establish(&crypto, &alice, &request).unwrap();
+
+        let server_bytes: [u8; 32] = BASE64.decode(grant.server_public_key.as_ref().unwrap()).unwrap().try_into().unwrap();
+        let shared = client_secret.diffie_hellman(&PublicKey::from(server_bytes));
+        let client_key = derive_session_key(shared.as_bytes(), &grant.session_id, client_public_key.as_bytes(), &server_bytes).unwrap();
+
+        assert_eq!(*sessions.upload_key(&alice, &grant.session_id, Some("bm9uY2UtMQ==")).unwrap(), client_key);
+        assert_eq!(sessions.upload_key(&alice, &grant.session_id, Some("bm9uY2UtMQ==")), Err(SessionError::NonceReused));
+        assert_eq!(sessions.upload_key(&alice, &grant.session_id, None), Err(SessionError::NonceRequired));
+
+        let bob = Caller { principal: Some("bob".to_string()), ..alice.clone() };
+        assert_eq!(sessions.upload_key(&bob, &grant.session_id, Some("bm9uY2UtMg==")), Err(SessionError::Unknown));
+
+        assert!(sessions.upload_key(&alice, &grant.session_id, Some("bm9uY2UtMg==")).is_ok());
+        assert_eq!(sessions.upload_key(&alice, &grant.session_id, Some("bm9uY2UtMw==")), Err(SessionError::Exhausted));
+
+        let short_lived = SessionManager::new(0, 1);
+        let expired = short_lived.establish(&crypto, &alice, &request).unwrap();
+        assert_eq!(short_lived.upload_key(&alice, &expired.session_id, Some("bm9uY2UtNA==")), Err(SessionError::Expired));
+
+        // The exhausted session is swept with its key and two nonces, the idle one only
+        // once it has been idle long enough
+        let idle = sessions.establish(&crypto, &alice, &request).unwrap();
+        assert_eq!(sessions.sweep(60), (1, 32 + 24));
+        assert_eq!(sessions.sweep(0), (1, 32));
+        assert_eq!(sessions.upload_key(&alice, &idle.session_id, Some("bm9uY2UtNQ==")), Err(SessionError::Unknown));
+        sessions.establish(&crypto, &alice, &request).unwrap();
+        assert_eq!(sessions.clear(), 1);
+
+        let mut client = StreamCipher::client(&client_key, b"stream-nonce").unwrap();
+        let mut server = StreamCipher::server(&client_key, b"stream-nonce").unwrap();
+        let first = client.seal(b"Mail jane@").unwrap();
+        let second = client.seal(b"example.com").unwrap();
+        assert_eq!(server.open(&first).unwrap(), b"Mail jane@");
+        assert_eq!(server.open(&second).unwrap(), b"example.com");
+        assert!(server.open(&second).is_err());
+        let reply = server.seal(b"Mail <EMAIL_ADDRESS>").unwrap();
+        assert_eq!(client.open(&reply).unwrap(), b"Mail <EMAIL_ADDRESS>");
+
+        let low_order = SessionRequest { client_public_key: Some(BASE64.encode([0u8; 32])), encrypted_session_key: None };
+        assert!(sessions.establish(&crypto, &alice, &low_order).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::{anyhow, Result};
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::backend::EntityFilter;
+
+// Strategies uploads naming none may default to; the others need options of their own
+pub const DEFAULT_STRATEGIES: [&str; 4] = ["replace", "mask", "fake", "hash"];
+
+// The settings every part of the service reads, swapped whole when the admin API
+// changes them
+pub type SharedSettings = Arc<ArcSwap<RuntimeSettings>>;
+
+// The part of `AppConfig` that can change while the service runs, through
+// `PUT /admin/config`. Requests take a snapshot when they start, so each sees one
+// version of it throughout.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(deny_unknown_fields)]
+pub struct RuntimeSettings {
+    pub presidio_url: String,
+    // Of uploads that set no `redaction_strategy`, `entities` or `score_threshold`
+    pub default_strategy: String,
+    pub default_entities: Option<Vec<String>>,
+    pub default_score_threshold: Option<f32>,
+    // Of uploads that set no `ttl_seconds`; kept until deleted when unset
+    pub default_file_ttl_seconds: Option<u64>,
+    // Requests per client per minute, and the burst allowed; unlimited when unset
+    pub rate_limit_per_minute: Option<u32>,
+    pub rate_limit_burst: Option<u32>,
+}
+
+impl RuntimeSettings {
+    pub fn shared(self) -> SharedSettings {
+        Arc::new(ArcSwap::from_pointee(self))
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !self.presidio_url.starts_with("http://") && !self.presidio_url.starts_with("https://") {
+            return Err(anyhow!("presidio_url must be an http or https URL"));
+        }
+        if !DEFAULT_STRATEGIES.contains(&self.default_strategy.as_str()) {
+            return Err(anyhow!("default_strategy must be one of {}", DEFAULT_STRATEGIES.join(", ")));
+        }
+        let filter = EntityFilter { entities: self.default_entities.as_deref(), score_threshold: self.default_score_threshold, language: None };
+        filter.validate().map_err(|e| anyhow!("Invalid defaults: {}", e))?;
+
+        let positive = [
+            ("default_file_ttl_seconds", self.default_file_ttl_seconds.unwrap_or(1)),
+            ("rate_limit_per_minute", self.rate_limit_per_minute.unwrap_or(1) as u64),
+            ("rate_limit_burst", self.rate_limit_burst.unwrap_or(1) as u64),
+        ];
+        match positive.iter().find(|(_, value)| *value == 0) {
+            Some((name, _)) => Err(anyhow!("{} must be positive", name)),
+            None => Ok(()),
+        }
+    }
+
+    // Names of the settings that differ in `other`, for the audit log
+    pub fn changes(&self, other: &Self) -> Vec<&'static str> {
+        [
+            ("presidio_url", self.presidio_url != other.presidio_url),
+            ("default_strategy", self.default_strategy != other.default_strategy),
+            ("default_entities", self.default_entities != other.default_entities),
+            ("default_score_threshold", self.default_score_threshold != other.default_score_threshold),
+            ("default_file_ttl_seconds", self.default_file_ttl_seconds != other.default_file_ttl_seconds),
+            ("rate_limit_per_minute", self.rate_limit_per_minute != other.rate_limit_per_minute),
+            ("rate_limit_burst", self.rate_limit_burst != other.rate_limit_burst),
+        ]
+        .into_iter()
+        .filter_map(|(name, changed)| changed.then_some(name))
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+
+    #[test]
+    fn test_updates_are_validated_and_diffed() {
+        let current = AppConfig::default().runtime_settings().unwrap();
+        assert_eq!((current.presidio_url.as_str(), current.default_strategy.as_str()), ("http://localhost:8001", "replace"));
+
+        let update = RuntimeSettings {
+            presidio_url: "https://presidio.internal".to_string(),
+            default_entities: Some(vec!["EMAIL_ADDRESS".to_string()]),
+            rate_limit_per_minute: Some(60),
+            ..current.clone()
+        };
+        assert!(update.validate().is_ok());
+        assert_eq!(current.changes(&update), ["presidio_url", "default_entities", "rate_limit_per_minute"]);
+        assert!(current.changes(&current).is_empty());
+
+        let shared = current.clone().shared();
+        shared.store(Arc::new(update.clone()));
+        assert_eq!(shared.load().presidio_url, "https://presidio.internal");
+
+        let invalid = [
+            RuntimeSettings { presidio_url: "presidio:3000".to_string(), ..update.clone() },
+            RuntimeSettings { default_strategy: "extract".to_string(), ..update.clone() },
+            RuntimeSettings { default_entities: Some(vec!["SHOE_SIZE".to_string()]), ..update.clone() },
+            RuntimeSettings { default_score_threshold: Some(1.5), ..update.clone() },
+            RuntimeSettings { rate_limit_per_minute: Some(0), ..update.clone() },
+        ];
+        assert!(invalid.iter().all(|settings| settings.validate().is_err()));
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+// Half-open byte range [start, end) into the decrypted plaintext
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ByteSpan {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Segment<'a> {
+    // Sent through the redaction backend
+    Analyze(&'a str),
+    // Client-protected region, passed through untouched
+    Keep(&'a str),
+    // Client-forced region, replaced without analysis
+    Redact(&'a str),
+}
+
+// Split text into analyzed, protected, and force-redacted segments. Spans must fall on
+// UTF-8 boundaries, overlapping spans of the same kind are merged, and a region cannot
+// be both protected and force-redacted.
+pub fn resolve_segments<'a>(
+    text: &'a str,
+    protected: &[ByteSpan],
+    forced: &[ByteSpan],
+) -> Result<Vec<Segment<'a>>> {
+    let protected = normalize(text, protected)?;
+    let forced = normalize(text, forced)?;
+
+    let mut regions: Vec<(ByteSpan, bool)> = protected.iter().map(|span| (*span, true))
+        .chain(forced.iter().map(|span| (*span, false)))
+        .collect();
+    regions.sort_by_key(|(span, _)| span.start);
+
+    if let Some(pair) = regions.windows(2).find(|pair| pair[1].0.start < pair[0].0.end) {
+        return Err(anyhow!(
+            "Protected and force-redacted spans overlap at bytes {}..{}",
+            pair[1].0.start,
+            pair[0].0.end
+        ));
+    }
+
+    let mut segments = Vec::new();
+    let mut cursor = 0;
+    for (span, keep) in regions {
+        if span.start > cursor {
+            segments.push(Segment::Analyze(&text[cursor..span.start]));
+        }
+        let region = &text[span.start..span.end];
+        segments.push(if keep { Segment::Keep(region) } else { Segment::Redact(region) });
+        cursor = span.end;
+    }
+    if cursor < text.len() || segments.is_empty() {
+        segments.push(Segment::Analyze(&text[cursor..]));
+    }
+
+    Ok(segments)
+}
+
+fn normalize(text: &str, spans: &[ByteSpan]) -> Result<Vec<ByteSpan>> {
+    let mut sorted = spans.to_vec();
+    for span in &sorted {
+        if span.start >= span.end || span.end > text.len() {
+            return Err(anyhow!(
+                "Invalid span {}..{} for {} bytes of text",
+                span.start,
+                span.end,
+                text.len()
+            ));
+        }
+        if !text.is_char_boundary(span.start) || !text.is_char_boundary(span.end) {
+            return Err(anyhow!(
+                "Span {}..{} does not fall on UTF-8 character boundaries",
+                span.start,
+                span.end
+            ));
+        }
+    }
+    sorted.sort_by_key(|span| span.start);
+
+    let mut merged: Vec<ByteSpan> = Vec::with_capacity(sorted.len());
+    for span in sorted {
+        match merged.last_mut() {
+            Some(last) if span.start <= last.end => last.end = last.end.max(span.end),
+            _ => merged.push(span),
+        }
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(start: usize, end: usize) -> ByteSpan {
+        ByteSpan { start, end }
+    }
+
+    #[test]
+    fn test_resolve_segments() {
+        let text = "Call John. Signed: ACME Corp. SSN 123";
+        let segments = resolve_segments(text, &[span(11, 29), span(19, 23)], &[span(34, 37)]).unwrap();
+
+        assert_eq!(segments, vec![
+            Segment::Analyze("Call John. "),
+            Segment::Keep("Signed: ACME Corp."),
+            Segment::Analyze(" SSN "),
+            Segment::Redact("123"),
+        ]);
+    }
+
+    #[test]
+    fn test_invalid_spans_rejected() {
+        let text = "héllo world";
+        assert!(resolve_segments(text, &[span(0, 2)], &[]).is_err());
+        assert!(resolve_segments(text, &[span(3, 99)], &[]).is_err());
+        assert!(resolve_segments(text, &[span(0, 5)], &[span(4, 8)]).is_err());
+        assert_eq!(resolve_segments(text, &[], &[]).unwrap(), vec![Segment::Analyze(text)]);
+    }
+}
@@ -0,0 +1,1033 @@
+use anyhow::{anyhow, Result};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::acl::FileAcl;
+use crate::crypto::{self, CryptoService};
+use crate::document::DocumentFormat;
+use crate::envelope;
+use crate::manifest::ProcessingManifest;
+use crate::original::RetainedOriginal;
+use crate::policy::ReviewHold;
+use crate::pseudonym::SealedPseudonyms;
+use crate::relay::RelayIdentities;
+use crate::report::{RedactionReport, ReportQuery};
+use crate::secret::SecretBytes;
+use crate::usage::UsageTotals;
+use crate::views::DownloadFormat;
+
+// How long ids of expired files are remembered, so lookups can answer "gone"
+const EXPIRED_RETENTION_SECONDS: u64 = 30 * 24 * 3600;
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct FileMetadata {
+    pub file_name: String,
+    // Stored separately from the rest of the metadata by `DiskStorage` and `S3Storage`
+    #[serde(skip)]
+    pub content: String,
+    pub size: usize,
+    pub relay: Option<RelayIdentities>,
+    pub owner: Option<String>,
+    pub acl: Option<FileAcl>,
+    // Tenant of the uploader; client `external_id`s are unique within it
+    pub tenant: Option<String>,
+    pub external_id: Option<String>,
+    // Redaction strategy the upload used
+    #[serde(default)]
+    pub strategy: Option<String>,
+    // ISO 639-1 code of the language the upload was redacted in
+    #[serde(default)]
+    pub language: Option<String>,
+    pub created_at: u64,
+    // What the redaction found; never the values themselves
+    pub report: Option<RedactionReport>,
+    // Upload session key, kept so downloads can be re-encrypted for the client; zeroized
+    // when the file is deleted
+    pub session_key: Option<SecretBytes>,
+    // Set when a blocking rule matched; the file is withheld until released
+    #[serde(default)]
+    pub review_hold: Option<ReviewHold>,
+    // Seconds after `created_at` at which the file expires and is purged
+    #[serde(default)]
+    pub ttl_seconds: Option<u64>,
+    // Reverses the tokens of a `pseudonymize` upload; opened only by `unredact_file`
+    #[serde(default)]
+    pub pseudonyms: Option<SealedPseudonyms>,
+    // Plaintext of an upload that set `retain_original`; opened only by `reprocess_file`
+    // and `original_file`
+    #[serde(default)]
+    pub original: Option<RetainedOriginal>,
+    // When the original was purged at the end of its retention, while the file stays
+    #[serde(default)]
+    pub original_purged_at: Option<u64>,
+    // Original format of a PDF or DOCX upload; the stored content is its redacted text
+    #[serde(default)]
+    pub document_format: Option<DocumentFormat>,
+    // Signed provenance, fixed when the file is stored; files stored before manifests have none
+    #[serde(default)]
+    pub manifest: Option<ProcessingManifest>,
+    // Digest of the plaintext and the settings it was redacted with, kept while the
+    // policy reuses the output of identical uploads
+    #[serde(default)]
+    pub content_key: Option<String>,
+    // Set once the file moved to the cold tier
+    #[serde(default)]
+    pub cold_tier: Option<ColdTier>,
+    // Download views rendered so far, dropped with the file and never written to disk
+    #[serde(skip)]
+    pub views: BTreeMap<DownloadFormat, String>,
+}
+
+// When a file moved to the cold tier, and what reaching it there costs
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ColdTier {
+    // Unix seconds
+    pub moved_at: u64,
+    // Round trip to the cold backend, measured when the file moved
+    pub latency_ms: u64,
+}
+
+impl FileMetadata {
+    pub fn expires_at(&self) -> Option<u64> {
+        self.ttl_seconds.map(|ttl| self.created_at.saturating_add(ttl))
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at().is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+// Which files make room when storage is over its limits
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    // The files stored first
+    #[default]
+    OldestFirst,
+    // The files downloaded least recently, or never
+    Lru,
+}
+
+impl EvictionPolicy {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "oldest" => Ok(Self::OldestFirst),
+            "lru" => Ok(Self::Lru),
+            other => Err(anyhow!("Unknown eviction policy {}; expected oldest or lru", other)),
+        }
+    }
+}
+
+// Bounds on what storage holds, as the files are also kept in memory. Storing a file
+// that takes storage over `max_bytes` or `max_files` evicts others; a file over
+// `max_file_bytes` is refused.
+#[derive(Clone, Debug, Default)]
+pub struct StorageLimits {
+    pub max_bytes: Option<u64>,
+    pub max_files: Option<usize>,
+    pub max_file_bytes: Option<usize>,
+    pub eviction: EvictionPolicy,
+}
+
+const UNLIMITED: StorageLimits = StorageLimits {
+    max_bytes: None,
+    max_files: None,
+    max_file_bytes: None,
+    eviction: EvictionPolicy::OldestFirst,
+};
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// Backend for redacted artifacts and their metadata. `FileStorage` keeps them in memory,
+// `DiskStorage` persists them encrypted; embedding services can plug in their own.
+pub trait Storage: Send + Sync {
+    // Store a file, returning its metadata so callers can record provenance and access
+    fn store_file(&mut self, file_id: &str, file_name: &str, content: &str) -> &mut FileMetadata;
+    fn get_metadata(&self, file_id: &str) -> Option<&FileMetadata>;
+    fn get_metadata_mut(&mut self, file_id: &str) -> Option<&mut FileMetadata>;
+    fn delete_file(&mut self, file_id: &str) -> bool;
+    fn file_ids(&self) -> Vec<String>;
+    // Attach a file's redaction report and index it for `search_reports`
+    fn set_report(&mut self, file_id: &str, report: RedactionReport);
+    // Ids of files whose report matches the query
+    fn search_reports(&self, query: &ReportQuery) -> Vec<String>;
+    // File id stored under a client-supplied external id within a tenant
+    fn find_by_external_id(&self, tenant: Option<&str>, external_id: &str) -> Option<&str>;
+    // Ids of files within a tenant stored from the same plaintext and settings
+    fn find_by_content(&self, tenant: Option<&str>, content_key: &str) -> Vec<&str>;
+    // Ids of files whose TTL has run out by `now`
+    fn expired_file_ids(&self, now: u64) -> Vec<String> {
+        self.file_ids()
+            .into_iter()
+            .filter(|file_id| self.get_metadata(file_id).is_some_and(|metadata| metadata.is_expired(now)))
+            .collect()
+    }
+    // Delete a file whose TTL ran out. Backends that remember the id answer
+    // `was_expired` for it afterwards.
+    fn expire_file(&mut self, file_id: &str) -> bool {
+        self.delete_file(file_id)
+    }
+    fn was_expired(&self, _file_id: &str) -> bool {
+        false
+    }
+    // Write a file through to durable storage. Callers call it once they are done
+    // changing what `store_file` or `get_metadata_mut` returned.
+    fn persist(&mut self, _file_id: &str) -> Result<()> {
+        Ok(())
+    }
+    // Where files live, as named in erasure receipts
+    fn backend_name(&self) -> &'static str {
+        "memory"
+    }
+    // Running totals of stored uploads
+    fn usage(&self) -> &UsageTotals;
+    fn usage_mut(&mut self) -> &mut UsageTotals;
+    // Write the totals through to durable storage. Called periodically rather than on
+    // every upload.
+    fn persist_usage(&mut self) -> Result<()> {
+        Ok(())
+    }
+    // Remove what writes that never finished left behind, once untouched for `min_age`.
+    // Returns how many leftovers went and their size in bytes.
+    fn collect_orphans(&mut self, _min_age: Duration) -> Result<(usize, u64)> {
+        Ok((0, 0))
+    }
+    // Wrap stored files, and those written from now on, to the current service key, so
+    // earlier keys can be retired. Returns how many files were rewrapped.
+    fn rewrap(&mut self, _crypto: &CryptoService) -> Result<usize> {
+        Ok(0)
+    }
+    // Write and remove a probe where files are kept, for the readiness probe
+    fn check_writable(&self) -> Result<()> {
+        Ok(())
+    }
+    // Pick up what other instances sharing the storage stored, changed or removed since it
+    // was opened or last refreshed, for read-only replicas. Returns how many files changed.
+    fn refresh(&mut self, _crypto: &CryptoService) -> Result<usize> {
+        Ok(0)
+    }
+    // Move files stored before `created_before` to a cold tier, for backends that have
+    // one. Returns the ids of the files moved.
+    fn move_to_cold(&mut self, _created_before: u64) -> Vec<String> {
+        Vec::new()
+    }
+    fn limits(&self) -> &StorageLimits {
+        &UNLIMITED
+    }
+    // Note that a file was downloaded, for least-recently-used eviction
+    fn touch(&self, _file_id: &str) {}
+    // Files to delete, in order, for storage to be within its limits. `keep`, which was
+    // just stored, is never one of them.
+    fn eviction_candidates(&self, _keep: &str) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+#[derive(Default)]
+pub struct FileStorage {
+    files: HashMap<String, FileMetadata>,
+    // Entity type -> ids of files whose report detected it
+    entity_index: HashMap<String, BTreeSet<String>>,
+    // Ids of purged expired files -> when they were purged
+    expired: HashMap<String, u64>,
+    usage: UsageTotals,
+    // TTL given to files stored without one of their own
+    default_ttl: Option<u64>,
+    limits: StorageLimits,
+    // Ticks of `clock` at which each file was stored and last downloaded, to order
+    // files for eviction
+    stored_at: HashMap<String, u64>,
+    used_at: Mutex<HashMap<String, u64>>,
+    clock: AtomicU64,
+}
+
+impl FileStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_default_ttl(mut self, ttl_seconds: Option<u64>) -> Self {
+        self.default_ttl = ttl_seconds;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: StorageLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    // Put back a file loaded from durable storage, report included
+    pub(crate) fn restore(&mut self, file_id: &str, mut metadata: FileMetadata) {
+        let report = metadata.report.take();
+        self.stored_at.insert(file_id.to_string(), self.clock.fetch_add(1, Ordering::Relaxed));
+        self.files.insert(file_id.to_string(), metadata);
+        if let Some(report) = report {
+            self.set_report(file_id, report);
+        }
+    }
+
+    fn unindex(&mut self, file_id: &str) {
+        let Some(report) = self.files.get(file_id).and_then(|metadata| metadata.report.as_ref()) else {
+            return;
+        };
+        for entity in report.entities.keys() {
+            if let Some(file_ids) = self.entity_index.get_mut(entity) {
+                file_ids.remove(file_id);
+            }
+        }
+    }
+
+    pub fn get_file(&self, file_id: &str) -> Option<(String, String)> {
+        self.files.get(file_id).map(|metadata| {
+            (metadata.file_name.clone(), metadata.content.clone())
+        })
+    }
+
+    // Drop a file another instance removed, remembering it as expired if its TTL ran out
+    pub(crate) fn forget(&mut self, file_id: &str) {
+        match self.files.get(file_id).is_some_and(|metadata| metadata.is_expired(now())) {
+            true => self.expire_file(file_id),
+            false => self.delete_file(file_id),
+        };
+    }
+}
+
+impl Storage for FileStorage {
+    fn store_file(&mut self, file_id: &str, file_name: &str, content: &str) -> &mut FileMetadata {
+        let metadata = FileMetadata {
+            file_name: file_name.to_string(),
+            content: content.to_string(),
+            size: content.len(),
+            relay: None,
+            owner: None,
+            acl: None,
+            tenant: None,
+            external_id: None,
+            strategy: None,
+            language: None,
+            created_at: now(),
+            report: None,
+            session_key: None,
+            review_hold: None,
+            ttl_seconds: self.default_ttl,
+            pseudonyms: None,
+            original: None,
+            original_purged_at: None,
+            document_format: None,
+            manifest: None,
+            content_key: None,
+            cold_tier: None,
+            views: BTreeMap::new(),
+        };
+
+        self.unindex(file_id);
+        self.expired.remove(file_id);
+        self.stored_at.insert(file_id.to_string(), self.clock.fetch_add(1, Ordering::Relaxed));
+        self.used_at.get_mut().unwrap().remove(file_id);
+        self.files.entry(file_id.to_string())
+            .insert_entry(metadata)
+            .into_mut()
+    }
+
+    fn get_metadata(&self, file_id: &str) -> Option<&FileMetadata> {
+        self.files.get(file_id)
+    }
+
+    fn get_metadata_mut(&mut self, file_id: &str) -> Option<&mut FileMetadata> {
+        self.files.get_mut(file_id)
+    }
+
+    fn delete_file(&mut self, file_id: &str) -> bool {
+        self.unindex(file_id);
+        self.stored_at.remove(file_id);
+        self.used_at.get_mut().unwrap().remove(file_id);
+        self.files.remove(file_id).is_some()
+    }
+
+    fn file_ids(&self) -> Vec<String> {
+        self.files.keys().cloned().collect()
+    }
+
+    fn set_report(&mut self, file_id: &str, report: RedactionReport) {
+        if !self.files.contains_key(file_id) {
+            return;
+        }
+        self.unindex(file_id);
+        for entity in report.entities.keys() {
+            self.entity_index.entry(entity.clone()).or_default().insert(file_id.to_string());
+        }
+        if let Some(metadata) = self.files.get_mut(file_id) {
+            metadata.report = Some(report);
+        }
+    }
+
+    fn search_reports(&self, query: &ReportQuery) -> Vec<String> {
+        let candidates: Vec<&String> = match &query.entity {
+            Some(entity) => self.entity_index.get(entity).map(|ids| ids.iter().collect()).unwrap_or_default(),
+            None => self.files.keys().collect(),
+        };
+
+        candidates.into_iter()
+            .filter(|file_id| {
+                self.files.get(file_id.as_str()).is_some_and(|metadata| {
+                    metadata.tenant == query.tenant
+                        && metadata.report.as_ref().is_some_and(|report| query.matches(report, metadata.created_at))
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn find_by_external_id(&self, tenant: Option<&str>, external_id: &str) -> Option<&str> {
+        self.files.iter()
+            .find(|(_, metadata)| {
+                metadata.tenant.as_deref() == tenant && metadata.external_id.as_deref() == Some(external_id)
+            })
+            .map(|(file_id, _)| file_id.as_str())
+    }
+
+    fn find_by_content(&self, tenant: Option<&str>, content_key: &str) -> Vec<&str> {
+        self.files.iter()
+            .filter(|(_, metadata)| {
+                metadata.tenant.as_deref() == tenant && metadata.content_key.as_deref() == Some(content_key)
+            })
+            .map(|(file_id, _)| file_id.as_str())
+            .collect()
+    }
+
+    fn expire_file(&mut self, file_id: &str) -> bool {
+        let now = now();
+        self.expired.retain(|_, purged_at| *purged_at + EXPIRED_RETENTION_SECONDS > now);
+        let deleted = self.delete_file(file_id);
+        if deleted {
+            self.expired.insert(file_id.to_string(), now);
+        }
+        deleted
+    }
+
+    fn was_expired(&self, file_id: &str) -> bool {
+        self.expired.contains_key(file_id)
+    }
+
+    fn usage(&self) -> &UsageTotals {
+        &self.usage
+    }
+
+    fn usage_mut(&mut self) -> &mut UsageTotals {
+        &mut self.usage
+    }
+
+    fn limits(&self) -> &StorageLimits {
+        &self.limits
+    }
+
+    fn touch(&self, file_id: &str) {
+        if self.limits.eviction == EvictionPolicy::Lru && self.files.contains_key(file_id) {
+            let tick = self.clock.fetch_add(1, Ordering::Relaxed);
+            self.used_at.lock().unwrap().insert(file_id.to_string(), tick);
+        }
+    }
+
+    fn eviction_candidates(&self, keep: &str) -> Vec<String> {
+        let mut files = self.files.len();
+        let mut bytes: u64 = self.files.values().map(|metadata| metadata.size as u64).sum();
+        let over = |files: usize, bytes: u64| {
+            self.limits.max_files.is_some_and(|max| files > max) || self.limits.max_bytes.is_some_and(|max| bytes > max)
+        };
+        if !over(files, bytes) {
+            return Vec::new();
+        }
+
+        let used_at = self.used_at.lock().unwrap();
+        let mut candidates: Vec<(&String, &FileMetadata)> = self.files.iter().filter(|(file_id, _)| *file_id != keep).collect();
+        match self.limits.eviction {
+            EvictionPolicy::OldestFirst => candidates.sort_by_key(|(file_id, metadata)| (metadata.created_at, self.stored_at.get(*file_id).copied())),
+            EvictionPolicy::Lru => candidates.sort_by_key(|(file_id, _)| {
+                used_at.get(*file_id).or_else(|| self.stored_at.get(*file_id)).copied()
+            }),
+        }
+        let mut evicted = Vec::new();
+        for (file_id, metadata) in candidates {
+            if !over(files, bytes) {
+                break;
+            }
+            files -= 1;
+            bytes -= metadata.size as u64;
+            evicted.push(file_id.clone());
+        }
+        evicted
+    }
+}
+
+// Persistent storage under a directory, encrypted at rest. Every file has its own
+// ChaCha20-Poly1305 key, wrapped to the service public key with RSA-OAEP-SHA256.
+// `index.json` holds each file's wrapped key and encrypted metadata; content lives in
+// `<file_id>.enc`. Everything is decrypted into an in-memory cache on open, so only
+// writes touch the disk, and files written under another service key fail to open.
+// Usage totals are kept in `usage.json` under a key of their own.
+pub struct DiskStorage {
+    cache: FileStorage,
+    dir: PathBuf,
+    public_key_pem: String,
+    index: HashMap<String, IndexEntry>,
+    // Unwrapped per-file keys
+    keys: HashMap<String, Vec<u8>>,
+    // Usage totals as last written, to skip unchanged snapshots
+    persisted_usage: UsageTotals,
+}
+
+// A file's metadata, sealed under the file's own key, which is wrapped to the service key
+#[derive(Deserialize, Serialize)]
+pub(crate) struct IndexEntry {
+    pub(crate) wrapped_key: String,
+    metadata: String,
+    metadata_nonce: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct EncryptedContent {
+    encrypted_data: String,
+    nonce: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct EncryptedUsage {
+    wrapped_key: String,
+    encrypted_data: String,
+    nonce: String,
+}
+
+impl DiskStorage {
+    pub fn open(dir: impl Into<PathBuf>, crypto: &CryptoService) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| anyhow!("Failed to create storage directory {}: {}", dir.display(), e))?;
+
+        let index = read_index(&dir)?;
+
+        let mut storage = Self {
+            cache: FileStorage::new(),
+            dir,
+            public_key_pem: crypto.get_public_key()?,
+            index: HashMap::new(),
+            keys: HashMap::new(),
+            persisted_usage: UsageTotals::default(),
+        };
+        for (file_id, entry) in index {
+            storage.load(crypto, file_id, entry)?;
+        }
+        storage.load_usage(crypto)?;
+
+        info!("Opened disk storage at {} with {} file(s)", storage.dir.display(), storage.index.len());
+        Ok(storage)
+    }
+
+    // TTL given to new files stored without one of their own
+    pub fn with_default_ttl(mut self, ttl_seconds: Option<u64>) -> Self {
+        self.cache.default_ttl = ttl_seconds;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: StorageLimits) -> Self {
+        self.cache = self.cache.with_limits(limits);
+        self
+    }
+
+    fn load(&mut self, crypto: &CryptoService, file_id: String, entry: IndexEntry) -> Result<()> {
+        let content = std::fs::read_to_string(self.content_path(&file_id)?)
+            .map_err(|e| anyhow!("Failed to read file {}: {}", file_id, e))?;
+        let content: EncryptedContent = serde_json::from_str(&content)
+            .map_err(|e| anyhow!("Invalid content file for {}: {}", file_id, e))?;
+        let (metadata, key) = open_file(crypto, &file_id, &entry, &content)?;
+
+        self.cache.restore(&file_id, metadata);
+        self.keys.insert(file_id.clone(), key);
+        self.index.insert(file_id, entry);
+        Ok(())
+    }
+
+    fn load_usage(&mut self, crypto: &CryptoService) -> Result<()> {
+        let contents = match std::fs::read_to_string(self.dir.join("usage.json")) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(anyhow!("Failed to read usage totals: {}", e)),
+        };
+        self.cache.usage = open_usage(crypto, &contents)?;
+        self.persisted_usage = self.cache.usage.clone();
+        Ok(())
+    }
+
+    fn content_path(&self, file_id: &str) -> Result<PathBuf> {
+        if file_id.is_empty() || file_id.starts_with('.') || file_id.contains(['/', '\\']) {
+            return Err(anyhow!("Invalid file id for disk storage: {}", file_id));
+        }
+        Ok(self.dir.join(format!("{}.enc", file_id)))
+    }
+
+    fn write_index(&self) -> Result<()> {
+        let index = serde_json::to_vec(&self.index)
+            .map_err(|e| anyhow!("Failed to serialize storage index: {}", e))?;
+        write_atomic(&self.dir.join("index.json"), &index)
+    }
+
+    fn remove_from_disk(&mut self, file_id: &str) {
+        self.keys.remove(file_id);
+        if self.index.remove(file_id).is_some() {
+            if let Err(e) = self.write_index() {
+                warn!("Failed to remove file {} from the storage index: {}", file_id, e);
+            }
+            if let Ok(path) = self.content_path(file_id) {
+                if let Err(e) = std::fs::remove_file(path) {
+                    warn!("Failed to remove content of file {}: {}", file_id, e);
+                }
+            }
+        }
+    }
+
+    pub fn get_file(&self, file_id: &str) -> Option<(String, String)> {
+        self.cache.get_file(file_id)
+    }
+}
+
+impl Storage for DiskStorage {
+    fn store_file(&mut self, file_id: &str, file_name: &str, content: &str) -> &mut FileMetadata {
+        self.cache.store_file(file_id, file_name, content)
+    }
+
+    fn get_metadata(&self, file_id: &str) -> Option<&FileMetadata> {
+        self.cache.get_metadata(file_id)
+    }
+
+    fn get_metadata_mut(&mut self, file_id: &str) -> Option<&mut FileMetadata> {
+        self.cache.get_metadata_mut(file_id)
+    }
+
+    fn delete_file(&mut self, file_id: &str) -> bool {
+        let deleted = self.cache.delete_file(file_id);
+        self.remove_from_disk(file_id);
+        deleted
+    }
+
+    fn expire_file(&mut self, file_id: &str) -> bool {
+        let deleted = self.cache.expire_file(file_id);
+        self.remove_from_disk(file_id);
+        deleted
+    }
+
+    fn was_expired(&self, file_id: &str) -> bool {
+        self.cache.was_expired(file_id)
+    }
+
+    fn file_ids(&self) -> Vec<String> {
+        self.cache.file_ids()
+    }
+
+    fn set_report(&mut self, file_id: &str, report: RedactionReport) {
+        self.cache.set_report(file_id, report);
+    }
+
+    fn search_reports(&self, query: &ReportQuery) -> Vec<String> {
+        self.cache.search_reports(query)
+    }
+
+    fn find_by_external_id(&self, tenant: Option<&str>, external_id: &str) -> Option<&str> {
+        self.cache.find_by_external_id(tenant, external_id)
+    }
+
+    fn find_by_content(&self, tenant: Option<&str>, content_key: &str) -> Vec<&str> {
+        self.cache.find_by_content(tenant, content_key)
+    }
+
+    // Content is written before the index, so a crash in between leaves at worst an
+    // unreferenced content file. A file's key never changes once it is in the index.
+    fn persist(&mut self, file_id: &str) -> Result<()> {
+        let Some(metadata) = self.cache.files.get(file_id) else {
+            return Err(anyhow!("File {} is not stored", file_id));
+        };
+        let content_path = self.content_path(file_id)?;
+
+        let key = self.keys.entry(file_id.to_string()).or_insert_with(new_file_key);
+        let wrapped_key = match self.index.get(file_id) {
+            Some(entry) => entry.wrapped_key.clone(),
+            None => envelope::wrap_session_key(&self.public_key_pem, key)?,
+        };
+
+        let (content, entry) = seal_file(file_id, metadata, key, wrapped_key)?;
+        let content = serde_json::to_vec(&content)
+            .map_err(|e| anyhow!("Failed to serialize file {}: {}", file_id, e))?;
+        write_atomic(&content_path, &content)?;
+
+        self.index.insert(file_id.to_string(), entry);
+        self.write_index()
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "disk"
+    }
+
+    // A `.tmp` name, so orphan collection removes one a crash leaves behind
+    fn check_writable(&self) -> Result<()> {
+        let probe = self.dir.join("ready-probe.tmp");
+        std::fs::write(&probe, b"ready")
+            .and_then(|_| std::fs::remove_file(&probe))
+            .map_err(|e| anyhow!("Storage directory {} is not writable: {}", self.dir.display(), e))
+    }
+
+    fn usage(&self) -> &UsageTotals {
+        self.cache.usage()
+    }
+
+    fn usage_mut(&mut self) -> &mut UsageTotals {
+        self.cache.usage_mut()
+    }
+
+    fn limits(&self) -> &StorageLimits {
+        self.cache.limits()
+    }
+
+    fn touch(&self, file_id: &str) {
+        self.cache.touch(file_id);
+    }
+
+    fn eviction_candidates(&self, keep: &str) -> Vec<String> {
+        self.cache.eviction_candidates(keep)
+    }
+
+    fn persist_usage(&mut self) -> Result<()> {
+        if self.cache.usage == self.persisted_usage {
+            return Ok(());
+        }
+        let usage = seal_usage(&self.public_key_pem, &self.cache.usage)?;
+        write_atomic(&self.dir.join("usage.json"), &usage)?;
+        self.persisted_usage = self.cache.usage.clone();
+        Ok(())
+    }
+
+    // Only the index and usage totals change: file keys stay the same, wrapped anew
+    fn rewrap(&mut self, crypto: &CryptoService) -> Result<usize> {
+        self.public_key_pem = crypto.get_public_key()?;
+        for (file_id, entry) in self.index.iter_mut() {
+            let key = self.keys.get(file_id).ok_or_else(|| anyhow!("Key of file {} is not loaded", file_id))?;
+            entry.wrapped_key = envelope::wrap_session_key(&self.public_key_pem, key)?;
+        }
+        self.write_index()?;
+        let usage = seal_usage(&self.public_key_pem, &self.cache.usage)?;
+        write_atomic(&self.dir.join("usage.json"), &usage)?;
+        self.persisted_usage = self.cache.usage.clone();
+        Ok(self.index.len())
+    }
+
+    // Temporary files of interrupted atomic writes, and content files no stored file
+    // refers to, such as those of a crash between `persist` writing content and index
+    fn collect_orphans(&mut self, min_age: Duration) -> Result<(usize, u64)> {
+        let entries = std::fs::read_dir(&self.dir)
+            .map_err(|e| anyhow!("Failed to list storage directory {}: {}", self.dir.display(), e))?;
+        let mut reclaimed = (0, 0);
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let orphan = match name.rsplit_once('.') {
+                Some((_, "tmp")) => true,
+                Some((file_id, "enc")) => !self.index.contains_key(file_id) && !self.cache.files.contains_key(file_id),
+                _ => false,
+            };
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let age = metadata.modified().ok().and_then(|modified| modified.elapsed().ok()).unwrap_or_default();
+            if !orphan || !metadata.is_file() || age < min_age {
+                continue;
+            }
+            match std::fs::remove_file(entry.path()) {
+                Ok(()) => {
+                    info!("Removed orphaned storage file {}", name);
+                    reclaimed.0 += 1;
+                    reclaimed.1 += metadata.len();
+                }
+                Err(e) => warn!("Failed to remove orphaned storage file {}: {}", name, e),
+            }
+        }
+        Ok(reclaimed)
+    }
+
+    // Files whose index entry is new or was rewritten are loaded again. A file whose
+    // content cannot be read yet is left for the next refresh.
+    fn refresh(&mut self, crypto: &CryptoService) -> Result<usize> {
+        let index = read_index(&self.dir)?;
+        let removed: Vec<String> = self.index.keys().filter(|file_id| !index.contains_key(*file_id)).cloned().collect();
+        for file_id in &removed {
+            self.cache.forget(file_id);
+            self.keys.remove(file_id);
+            self.index.remove(file_id);
+        }
+        let mut changed = removed.len();
+        for (file_id, entry) in index {
+            if self.index.get(&file_id).is_some_and(|loaded| loaded.metadata == entry.metadata) {
+                continue;
+            }
+            match self.load(crypto, file_id.clone(), entry) {
+                Ok(()) => changed += 1,
+                Err(e) => warn!("Failed to load file {} from the shared storage: {}", file_id, e),
+            }
+        }
+        self.load_usage(crypto)?;
+        Ok(changed)
+    }
+}
+
+fn read_index(dir: &Path) -> Result<HashMap<String, IndexEntry>> {
+    match std::fs::read_to_string(dir.join("index.json")) {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|e| anyhow!("Invalid storage index: {}", e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(anyhow!("Failed to read storage index: {}", e)),
+    }
+}
+
+pub(crate) fn new_file_key() -> Vec<u8> {
+    let mut key = vec![0u8; 32];
+    OsRng.fill_bytes(&mut key);
+    key
+}
+
+// Encrypt a file's content and metadata under its key, for durable storage
+pub(crate) fn seal_file(file_id: &str, metadata: &FileMetadata, key: &[u8], wrapped_key: String) -> Result<(EncryptedContent, IndexEntry)> {
+    let (encrypted_data, nonce) = crypto::encrypt_with_session_key(metadata.content.as_bytes(), key, &aad(file_id, "content"))?;
+    let metadata_json = serde_json::to_vec(metadata)
+        .map_err(|e| anyhow!("Failed to serialize metadata of file {}: {}", file_id, e))?;
+    let (metadata, metadata_nonce) = crypto::encrypt_with_session_key(&metadata_json, key, &aad(file_id, "metadata"))?;
+    Ok((EncryptedContent { encrypted_data, nonce }, IndexEntry { wrapped_key, metadata, metadata_nonce }))
+}
+
+// The reverse of `seal_file`, returning the metadata with its content and the file's key
+pub(crate) fn open_file(crypto: &CryptoService, file_id: &str, entry: &IndexEntry, content: &EncryptedContent) -> Result<(FileMetadata, Vec<u8>)> {
+    let key = crypto.decrypt_session_key(&entry.wrapped_key)
+        .map_err(|e| anyhow!("Failed to unwrap the key of file {}: {}", file_id, e))?;
+
+    let metadata = crypto.decrypt_file_with_session_key(&entry.metadata, &key, Some(&entry.metadata_nonce), &aad(file_id, "metadata"))?;
+    let mut metadata: FileMetadata = serde_json::from_str(&metadata)
+        .map_err(|e| anyhow!("Invalid metadata for file {}: {}", file_id, e))?;
+    metadata.content = crypto.decrypt_file_with_session_key(&content.encrypted_data, &key, Some(&content.nonce), &aad(file_id, "content"))?;
+    Ok((metadata, key.to_vec()))
+}
+
+// Usage totals encrypted under a fresh key wrapped to the service key, as JSON
+pub(crate) fn seal_usage(public_key_pem: &str, usage: &UsageTotals) -> Result<Vec<u8>> {
+    let key = new_file_key();
+    let usage = serde_json::to_vec(usage)
+        .map_err(|e| anyhow!("Failed to serialize usage totals: {}", e))?;
+    let (encrypted_data, nonce) = crypto::encrypt_with_session_key(&usage, &key, b"usage")?;
+    let usage = EncryptedUsage { wrapped_key: envelope::wrap_session_key(public_key_pem, &key)?, encrypted_data, nonce };
+    serde_json::to_vec(&usage).map_err(|e| anyhow!("Failed to serialize usage totals: {}", e))
+}
+
+pub(crate) fn open_usage(crypto: &CryptoService, contents: &str) -> Result<UsageTotals> {
+    let usage: EncryptedUsage = serde_json::from_str(contents)
+        .map_err(|e| anyhow!("Invalid usage totals: {}", e))?;
+    let key = crypto.decrypt_session_key(&usage.wrapped_key)
+        .map_err(|e| anyhow!("Failed to unwrap the usage totals key: {}", e))?;
+    let usage = crypto.decrypt_file_with_session_key(&usage.encrypted_data, &key, Some(&usage.nonce), b"usage")?;
+    serde_json::from_str(&usage).map_err(|e| anyhow!("Invalid usage totals: {}", e))
+}
+
+// Binds each ciphertext to its file and role, so stored blobs cannot be swapped around
+fn aad(file_id: &str, part: &str) -> Vec<u8> {
+    format!("{}:{}", file_id, part).into_bytes()
+}
+
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, contents)
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_and_retrieve_file() {
+        let mut storage = FileStorage::new();
+        let file_id = "test-123";
+        let file_name = "test.txt";
+        let content = "Hello, World!";
+
+        storage.store_file(file_id, file_name, content);
+        
+        let retrieved = storage.get_file(file_id);
+        assert!(retrieved.is_some());
+        
+        let (retrieved_name, retrieved_content) = retrieved.unwrap();
+        assert_eq!(retrieved_name, file_name);
+        assert_eq!(retrieved_content, content);
+    }
+
+    #[test]
+    fn test_delete_file() {
+        let mut storage = FileStorage::new();
+        let file_id = "test-123";
+        
+        storage.store_file(file_id, "test.txt", "content");
+        assert!(storage.get_file(file_id).is_some());
+        
+        assert!(storage.delete_file(file_id));
+        assert!(storage.get_file(file_id).is_none());
+    }
+
+    #[test]
+    fn test_search_reports_uses_entity_index() {
+        let mut storage = FileStorage::new();
+        for (file_id, cards) in [("f1", 6), ("f2", 1)] {
+            storage.store_file(file_id, "statement.txt", "<CREDIT_CARD>");
+            let mut report = RedactionReport::default();
+            report.entities.insert("CREDIT_CARD".to_string(), cards);
+            storage.set_report(file_id, report);
+        }
+
+        let query = ReportQuery {
+            entity: Some("CREDIT_CARD".to_string()),
+            min_count: 5,
+            ..ReportQuery::default()
+        };
+        assert_eq!(storage.search_reports(&query), ["f1"]);
+
+        let tenant_query = ReportQuery { tenant: Some("acme".to_string()), ..query.clone() };
+        assert!(storage.search_reports(&tenant_query).is_empty());
+
+        storage.delete_file("f1");
+        assert!(storage.search_reports(&query).is_empty());
+        assert!(storage.entity_index["CREDIT_CARD"].contains("f2"));
+    }
+
+    #[test]
+    fn test_storage_over_its_limits_evicts_by_policy() {
+        let limits = StorageLimits { max_bytes: Some(10), max_files: Some(3), ..StorageLimits::default() };
+        let mut storage = FileStorage::new().with_limits(limits.clone());
+        for file_id in ["f1", "f2", "f3"] {
+            storage.store_file(file_id, "notes.txt", "abc");
+        }
+        assert!(storage.eviction_candidates("f3").is_empty());
+
+        // Downloads do not matter to oldest-first
+        storage.touch("f1");
+        storage.store_file("f4", "notes.txt", "abc");
+        assert_eq!(storage.eviction_candidates("f4"), ["f1"]);
+        storage.store_file("f4", "notes.txt", "abcdefgh");
+        assert_eq!(storage.eviction_candidates("f4"), ["f1", "f2", "f3"]);
+
+        let mut storage = FileStorage::new().with_limits(StorageLimits { eviction: EvictionPolicy::Lru, ..limits });
+        for file_id in ["f1", "f2", "f3"] {
+            storage.store_file(file_id, "notes.txt", "abc");
+        }
+        storage.touch("f1");
+        storage.store_file("f4", "notes.txt", "abc");
+        assert_eq!(storage.eviction_candidates("f4"), ["f2"]);
+        storage.delete_file("f2");
+        assert!(storage.eviction_candidates("f4").is_empty());
+    }
+
+    #[test]
+    fn test_disk_storage_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let crypto = CryptoService::new().unwrap();
+
+        let mut storage = DiskStorage::open(dir.path(), &crypto).unwrap();
+        let metadata = storage.store_file("f1", "claim.txt", "<PERSON> filed claim 42");
+        metadata.owner = Some("alice".to_string());
+        let mut report = RedactionReport::default();
+        report.entities.insert("PERSON".to_string(), 1);
+        storage.set_report("f1", report);
+        storage.persist("f1").unwrap();
+        storage.store_file("f2", "other.txt", "gone soon");
+        storage.persist("f2").unwrap();
+        assert!(storage.delete_file("f2"));
+        let report = storage.get_metadata("f1").and_then(|metadata| metadata.report.clone());
+        storage.usage_mut().record(Some("acme"), 22, report.as_ref());
+        storage.persist_usage().unwrap();
+        drop(storage);
+
+        let on_disk = std::fs::read_to_string(dir.path().join("f1.enc")).unwrap()
+            + &std::fs::read_to_string(dir.path().join("index.json")).unwrap()
+            + &std::fs::read_to_string(dir.path().join("usage.json")).unwrap();
+        assert!(!on_disk.contains("claim 42") && !on_disk.contains("alice") && !on_disk.contains("acme"));
+
+        let storage = DiskStorage::open(dir.path(), &crypto).unwrap();
+        assert_eq!(storage.get_file("f1").unwrap(), ("claim.txt".to_string(), "<PERSON> filed claim 42".to_string()));
+        assert_eq!(storage.get_metadata("f1").unwrap().owner.as_deref(), Some("alice"));
+        let query = ReportQuery { entity: Some("PERSON".to_string()), ..ReportQuery::default() };
+        assert_eq!(storage.search_reports(&query), ["f1"]);
+        assert!(storage.get_file("f2").is_none());
+        let usage = storage.usage();
+        assert_eq!((usage.all.files, usage.all.bytes, usage.tenants["acme"].entities["PERSON"]), (1, 22, 1));
+
+        // Leftovers of unfinished writes are collected, stored files are not
+        let mut storage = storage;
+        std::fs::write(dir.path().join("f3.enc"), "orphaned").unwrap();
+        std::fs::write(dir.path().join("index.tmp"), "{}").unwrap();
+        assert_eq!(storage.collect_orphans(Duration::from_secs(3600)).unwrap(), (0, 0));
+        assert_eq!(storage.collect_orphans(Duration::ZERO).unwrap(), (2, 10));
+        assert!(dir.path().join("f1.enc").exists() && !dir.path().join("f3.enc").exists());
+
+        // The readiness probe leaves nothing behind
+        storage.check_writable().unwrap();
+        assert!(!dir.path().join("ready-probe.tmp").exists());
+
+        // Without the service key the files cannot be read
+        assert!(DiskStorage::open(dir.path(), &CryptoService::new().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_rewrapped_files_outlive_the_old_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let crypto = CryptoService::new().unwrap();
+        let mut storage = DiskStorage::open(dir.path(), &crypto).unwrap();
+        storage.store_file("f1", "claim.txt", "<PERSON> filed claim 42");
+        storage.persist("f1").unwrap();
+
+        let old = crypto.key_id();
+        crypto.rotate().unwrap();
+        assert_eq!(storage.rewrap(&crypto).unwrap(), 1);
+        crypto.retire(&old).unwrap();
+        drop(storage);
+
+        let storage = DiskStorage::open(dir.path(), &crypto).unwrap();
+        assert_eq!(storage.get_file("f1").unwrap().1, "<PERSON> filed claim 42");
+    }
+    #[test]
+    fn test_replicas_pick_up_the_writers_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let crypto = CryptoService::new().unwrap();
+        let mut writer = DiskStorage::open(dir.path(), &crypto).unwrap();
+        writer.store_file("f1", "claim.txt", "<PERSON> filed claim 42");
+        writer.persist("f1").unwrap();
+        let mut replica = DiskStorage::open(dir.path(), &crypto).unwrap();
+        assert_eq!(replica.refresh(&crypto).unwrap(), 0);
+
+        writer.store_file("f2", "other.txt", "second");
+        writer.persist("f2").unwrap();
+        writer.get_metadata_mut("f1").unwrap().owner = Some("alice".to_string());
+        writer.persist("f1").unwrap();
+        assert!(writer.delete_file("f2"));
+        writer.store_file("f3", "third.txt", "third");
+        writer.persist("f3").unwrap();
+
+        assert_eq!(replica.refresh(&crypto).unwrap(), 2);
+        assert_eq!(replica.get_metadata("f1").unwrap().owner.as_deref(), Some("alice"));
+        assert!(replica.get_file("f2").is_none());
+        assert_eq!(replica.get_file("f3").unwrap().1, "third");
+
+        assert!(writer.delete_file("f1"));
+        assert_eq!(replica.refresh(&crypto).unwrap(), 1);
+        assert!(replica.get_file("f1").is_none());
+    }
+}
@@ -0,0 +1,305 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::report::Detection;
+
+// How an upload's plaintext is read. Structured content has only its string values
+// redacted, each on its own, and everything else (keys, numbers, layout) passed through
+// byte for byte.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ContentType {
+    #[default]
+    Text,
+    Json,
+    // RFC 4180, comma-separated, with a header row that is never redacted
+    Csv,
+}
+
+// A string value of a structured document
+#[derive(Debug, PartialEq)]
+pub struct Field {
+    // Byte range of the value as written, quotes included
+    pub start: usize,
+    pub end: usize,
+    // The value unescaped
+    pub value: String,
+    // Where `value` appears as is in the document, when it has no escapes
+    pub verbatim: Option<usize>,
+    quoted: bool,
+}
+
+#[derive(Debug, PartialEq)]
+enum PathPart {
+    Key(String),
+    Index(usize),
+}
+
+enum Container {
+    Object { expect_key: bool },
+    Array,
+}
+
+// String values of `text` to redact. `selectors` restricts them to JSON paths (dotted,
+// `*` for any key or index, selecting everything beneath) or CSV columns; all when empty.
+pub fn fields(text: &str, content_type: ContentType, selectors: &[String]) -> Result<Vec<Field>> {
+    match content_type {
+        ContentType::Text => Err(anyhow!("Text has no fields")),
+        ContentType::Json => json_fields(text, selectors),
+        ContentType::Csv => csv_fields(text, selectors),
+    }
+}
+
+// `text` with every field replaced by its redacted value, encoded as the format needs
+pub fn splice(text: &str, fields: &[Field], redacted: &[String], content_type: ContentType) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut copied = 0;
+    for (field, value) in fields.iter().zip(redacted) {
+        output.push_str(&text[copied..field.start]);
+        match content_type {
+            ContentType::Json => output.push_str(&serde_json::to_string(value).unwrap_or_default()),
+            _ if field.quoted || value.contains([',', '"', '\n', '\r']) => {
+                output.push('"');
+                output.push_str(&value.replace('"', "\"\""));
+                output.push('"');
+            }
+            _ => output.push_str(value),
+        }
+        copied = field.end;
+    }
+    output.push_str(&text[copied..]);
+    output
+}
+
+// Move detections made on the fields laid end to end onto the document. A detection in a
+// field with escapes covers the whole field, since its offsets do not carry over.
+pub fn map_detections(fields: &[Field], detections: &mut [Detection]) {
+    let mut starts = Vec::with_capacity(fields.len());
+    let mut offset = 0;
+    for field in fields {
+        starts.push(offset);
+        offset += field.value.len();
+    }
+    for detection in detections {
+        let index = starts.partition_point(|start| *start <= detection.start).saturating_sub(1);
+        let (Some(field), Some(start)) = (fields.get(index), starts.get(index)) else {
+            continue;
+        };
+        match field.verbatim {
+            Some(verbatim) => {
+                detection.end = verbatim + (detection.end - start).min(field.value.len());
+                detection.start = verbatim + (detection.start - start);
+            }
+            None => (detection.start, detection.end) = (field.start, field.end),
+        }
+    }
+}
+
+fn json_fields(text: &str, selectors: &[String]) -> Result<Vec<Field>> {
+    // Checked up front, so the scan below can take the syntax for granted
+    serde_json::from_str::<serde::de::IgnoredAny>(text).map_err(|e| anyhow!("Invalid JSON: {}", e))?;
+    let selectors: Vec<Vec<&str>> = selectors.iter().map(|selector| selector.split('.').collect()).collect();
+    let selected = |path: &[PathPart]| selectors.is_empty() || selectors.iter().any(|selector| {
+        selector.len() <= path.len() && selector.iter().zip(path).all(|(part, step)| match step {
+            _ if *part == "*" => true,
+            PathPart::Key(key) => key == part,
+            PathPart::Index(index) => part.parse() == Ok(*index),
+        })
+    });
+
+    let bytes = text.as_bytes();
+    let mut fields = Vec::new();
+    let mut containers = Vec::new();
+    let mut path = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' => containers.push(Container::Object { expect_key: true }),
+            b'[' => {
+                containers.push(Container::Array);
+                path.push(PathPart::Index(0));
+            }
+            b'}' => {
+                if let Some(Container::Object { expect_key: false }) = containers.pop() {
+                    path.pop();
+                }
+            }
+            b']' => {
+                containers.pop();
+                path.pop();
+            }
+            b',' => match containers.last_mut() {
+                Some(Container::Object { expect_key }) => {
+                    *expect_key = true;
+                    path.pop();
+                }
+                _ => {
+                    if let Some(PathPart::Index(index)) = path.last_mut() {
+                        *index += 1;
+                    }
+                }
+            },
+            b'"' => {
+                let end = string_end(bytes, i);
+                let raw = &text[i..end];
+                let value: String = serde_json::from_str(raw)?;
+                match containers.last_mut() {
+                    Some(Container::Object { expect_key }) if *expect_key => {
+                        *expect_key = false;
+                        path.push(PathPart::Key(value));
+                    }
+                    _ if selected(&path) => {
+                        let verbatim = (!raw.contains('\\')).then_some(i + 1);
+                        fields.push(Field { start: i, end, value, verbatim, quoted: true });
+                    }
+                    _ => {}
+                }
+                i = end;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    Ok(fields)
+}
+
+// End of the JSON string starting at `start`, past its closing quote
+fn string_end(bytes: &[u8], start: usize) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+fn csv_fields(text: &str, columns: &[String]) -> Result<Vec<Field>> {
+    let bytes = text.as_bytes();
+    let mut fields = Vec::new();
+    let mut names = Vec::new();
+    // Set once the header row is read
+    let mut selected: Option<Vec<bool>> = None;
+    let mut column = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let (value, quoted) = if bytes[i] == b'"' {
+            let mut value = String::new();
+            i += 1;
+            let mut copied = i;
+            loop {
+                match bytes.get(i) {
+                    None => return Err(anyhow!("Invalid CSV: unterminated quote at byte {}", start)),
+                    Some(b'"') if bytes.get(i + 1) == Some(&b'"') => {
+                        value.push_str(&text[copied..=i]);
+                        i += 2;
+                        copied = i;
+                    }
+                    Some(b'"') => break,
+                    Some(_) => i += 1,
+                }
+            }
+            value.push_str(&text[copied..i]);
+            i += 1;
+            if !matches!(bytes.get(i), None | Some(b',' | b'\n' | b'\r')) {
+                return Err(anyhow!("Invalid CSV: text after a closing quote at byte {}", i));
+            }
+            (value, true)
+        } else {
+            i = text[i..].find([',', '\n', '\r']).map_or(text.len(), |end| i + end);
+            (text[start..i].to_string(), false)
+        };
+
+        match &selected {
+            None => names.push(value),
+            Some(selected) if selected.get(column).copied().unwrap_or(false) && !value.is_empty() => {
+                let verbatim = match quoted {
+                    false => Some(start),
+                    true => (i - start == value.len() + 2).then_some(start + 1),
+                };
+                fields.push(Field { start, end: i, value, verbatim, quoted });
+            }
+            Some(_) => {}
+        }
+
+        column += 1;
+        let row_ends = bytes.get(i) != Some(&b',');
+        i += match (bytes.get(i), bytes.get(i + 1)) {
+            (Some(b'\r'), Some(b'\n')) => 2,
+            (Some(_), _) => 1,
+            (None, _) => 0,
+        };
+        if row_ends {
+            if selected.is_none() {
+                check_columns(columns, &names)?;
+                selected = Some(names.iter().map(|name| columns.is_empty() || columns.contains(name)).collect());
+            }
+            column = 0;
+        }
+    }
+    Ok(fields)
+}
+
+fn check_columns(columns: &[String], names: &[String]) -> Result<()> {
+    match columns.iter().find(|column| !names.contains(column)) {
+        Some(column) => Err(anyhow!("Unknown CSV column: {}", column)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_string_values_are_selected_by_path() {
+        let text = r#"{"id": 7, "name": "Jane Roe", "contacts": [{"email": "jane@example.com"}, {"email": "j\u0040x.org"}], "ok": true}"#;
+        let all = fields(text, ContentType::Json, &[]).unwrap();
+        assert_eq!(all.iter().map(|field| field.value.as_str()).collect::<Vec<_>>(), ["Jane Roe", "jane@example.com", "j@x.org"]);
+
+        let emails = fields(text, ContentType::Json, &["contacts.*.email".to_string()]).unwrap();
+        assert_eq!(emails.len(), 2);
+        assert_eq!(&text[emails[0].verbatim.unwrap()..][..16], "jane@example.com");
+        assert_eq!(emails[1].verbatim, None);
+        let second = fields(text, ContentType::Json, &["contacts.1".to_string()]).unwrap();
+        assert_eq!(second.iter().map(|field| field.value.as_str()).collect::<Vec<_>>(), ["j@x.org"]);
+
+        let redacted = ["<EMAIL_ADDRESS>".to_string(), "say \"hi\"".to_string()];
+        assert_eq!(
+            splice(text, &emails, &redacted, ContentType::Json),
+            r#"{"id": 7, "name": "Jane Roe", "contacts": [{"email": "<EMAIL_ADDRESS>"}, {"email": "say \"hi\""}], "ok": true}"#
+        );
+        assert!(fields("{\"a\": ", ContentType::Json, &[]).is_err());
+    }
+
+    #[test]
+    fn test_csv_cells_are_selected_by_column() {
+        let text = "id,name,notes\r\n1,Jane Roe,\"Call 555-123-4567, \"\"urgent\"\"\"\n2,,plain\n";
+        let cells = fields(text, ContentType::Csv, &["name".to_string(), "notes".to_string()]).unwrap();
+        assert_eq!(cells.iter().map(|field| field.value.as_str()).collect::<Vec<_>>(), ["Jane Roe", "Call 555-123-4567, \"urgent\"", "plain"]);
+        assert_eq!((cells[0].verbatim, cells[1].verbatim), (Some(17), None));
+
+        let redacted = ["<PERSON>".to_string(), "Call <PHONE_NUMBER>, \"urgent\"".to_string(), "a,b".to_string()];
+        assert_eq!(
+            splice(text, &cells, &redacted, ContentType::Csv),
+            "id,name,notes\r\n1,<PERSON>,\"Call <PHONE_NUMBER>, \"\"urgent\"\"\"\n2,,\"a,b\"\n"
+        );
+
+        // Detections land on the cells they were found in
+        let mut detections = vec![
+            Detection { entity_type: "PERSON".to_string(), start: 0, end: 8, score: 0.9 },
+            Detection { entity_type: "PHONE_NUMBER".to_string(), start: 13, end: 25, score: 0.9 },
+        ];
+        map_detections(&cells, &mut detections);
+        assert_eq!(&text[detections[0].start..detections[0].end], "Jane Roe");
+        assert_eq!((detections[1].start, detections[1].end), (cells[1].start, cells[1].end));
+
+        assert!(fields(text, ContentType::Csv, &["email".to_string()]).is_err());
+        assert!(fields("a\n\"open", ContentType::Csv, &[]).is_err());
+    }
+}
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::report::RedactionReport;
+
+// Running totals of stored uploads. They are kept by the storage backend, so with
+// `DiskStorage` they survive restarts, unlike the Prometheus counters.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UsageTotals {
+    #[serde(flatten)]
+    pub all: Usage,
+    // Uploads of callers without a tenant only count toward `all`
+    #[serde(default)]
+    pub tenants: BTreeMap<String, Usage>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Usage {
+    pub files: u64,
+    // Plaintext bytes
+    pub bytes: u64,
+    // Detections by entity type
+    #[serde(default)]
+    pub entities: BTreeMap<String, u64>,
+}
+
+impl Usage {
+    fn add(&mut self, bytes: usize, report: Option<&RedactionReport>) {
+        self.files += 1;
+        self.bytes += bytes as u64;
+        for (entity_type, count) in report.map(|report| &report.entities).into_iter().flatten() {
+            *self.entities.entry(entity_type.clone()).or_default() += *count as u64;
+        }
+    }
+}
+
+impl UsageTotals {
+    pub fn record(&mut self, tenant: Option<&str>, bytes: usize, report: Option<&RedactionReport>) {
+        self.all.add(bytes, report);
+        if let Some(tenant) = tenant {
+            self.tenants.entry(tenant.to_string()).or_default().add(bytes, report);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::bidi;
+use crate::report::RedactionReport;
+
+// Representation a redacted file is downloaded in. Everything but `txt` is rendered
+// from the stored text on first request.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadFormat {
+    #[default]
+    Txt,
+    // The text with the byte range of every redaction marker
+    Json,
+    // A standalone page with the markers highlighted and the report's counts
+    Html,
+}
+
+impl DownloadFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            DownloadFormat::Txt => "text/plain; charset=utf-8",
+            DownloadFormat::Json => "application/json",
+            DownloadFormat::Html => "text/html; charset=utf-8",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            DownloadFormat::Txt => "txt",
+            DownloadFormat::Json => "json",
+            DownloadFormat::Html => "html",
+        }
+    }
+}
+
+// A redaction marker in the redacted text, as a half-open byte range
+#[derive(Debug, PartialEq, Serialize)]
+pub struct MarkerSpan<'a> {
+    pub start: usize,
+    pub end: usize,
+    // `EMAIL_ADDRESS` for `<EMAIL_ADDRESS>`, `EMAIL_ADDRESS_1` for a pseudonym token,
+    // `REDACTED` for a forced redaction
+    pub label: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonView<'a> {
+    file_id: &'a str,
+    content: &'a str,
+    spans: Vec<MarkerSpan<'a>>,
+    entities: BTreeMap<String, usize>,
+}
+
+// Markers of the `replace` and `pseudonymize` strategies and of forced redactions.
+// `mask`, `hash` and `fake` output has nothing to tell it from the text around it.
+pub fn marker_spans(text: &str) -> Vec<MarkerSpan<'_>> {
+    let mut spans = Vec::new();
+    let mut offset = 0;
+    while let Some(found) = text[offset..].find(['<', '[']) {
+        let start = offset + found;
+        let len = bidi::marker_len(&text[start..]);
+        if len > 0 {
+            spans.push(MarkerSpan { start, end: start + len, label: &text[start + 1..start + len - 1] });
+        }
+        offset = start + len.max(1);
+    }
+    spans
+}
+
+pub fn render(format: DownloadFormat, file_id: &str, content: &str, report: Option<&RedactionReport>) -> String {
+    let entities = report.map(|report| report.entities.clone()).unwrap_or_default();
+    match format {
+        DownloadFormat::Txt => content.to_string(),
+        DownloadFormat::Json => {
+            let view = JsonView { file_id, content, spans: marker_spans(content), entities };
+            serde_json::to_string(&view).unwrap_or_default()
+        }
+        DownloadFormat::Html => render_html(file_id, content, &entities),
+    }
+}
+
+fn render_html(file_id: &str, content: &str, entities: &BTreeMap<String, usize>) -> String {
+    let mut html = String::with_capacity(content.len() * 2);
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str(&format!("<title>Redacted file {}</title>\n", escape(file_id)));
+    html.push_str("<style>mark.redaction { background: #222; color: #fff; padding: 0 2px; }</style>\n");
+    html.push_str("</head>\n<body>\n<table class=\"report\">\n<tr><th>Entity</th><th>Count</th></tr>\n");
+    for (entity_type, count) in entities {
+        html.push_str(&format!("<tr><td>{}</td><td>{}</td></tr>\n", escape(entity_type), count));
+    }
+    html.push_str("</table>\n<pre>");
+
+    let mut copied = 0;
+    for span in marker_spans(content) {
+        html.push_str(&escape(&content[copied..span.start]));
+        html.push_str(&format!(
+            "<mark class=\"redaction\" data-label=\"{}\">{}</mark>",
+            escape(span.label),
+            escape(&content[span.start..span.end])
+        ));
+        copied = span.end;
+    }
+    html.push_str(&escape(&content[copied..]));
+    html.push_str("</pre>\n</body>\n</html>\n");
+    html
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_views_mark_redactions() {
+        let content = "Mail <EMAIL_ADDRESS> or <EMAIL_ADDRESS_1>, [REDACTED] if x<y & <b>";
+        let spans = marker_spans(content);
+        assert_eq!(spans.iter().map(|span| span.label).collect::<Vec<_>>(), ["EMAIL_ADDRESS", "EMAIL_ADDRESS_1", "REDACTED"]);
+        assert_eq!(&content[spans[0].start..spans[0].end], "<EMAIL_ADDRESS>");
+
+        let report = RedactionReport { entities: BTreeMap::from([("EMAIL_ADDRESS".to_string(), 2)]), ..RedactionReport::default() };
+        let json: serde_json::Value = serde_json::from_str(&render(DownloadFormat::Json, "f1", content, Some(&report))).unwrap();
+        assert_eq!(json["spans"][2], serde_json::json!({ "start": 43, "end": 53, "label": "REDACTED" }));
+        assert_eq!(json["entities"]["EMAIL_ADDRESS"], 2);
+
+        // Text that only looks like markup is escaped
+        let html = render(DownloadFormat::Html, "f1", content, Some(&report));
+        assert!(html.contains("Mail <mark class=\"redaction\" data-label=\"EMAIL_ADDRESS\">&lt;EMAIL_ADDRESS&gt;</mark>"));
+        assert!(html.contains("if x&lt;y &amp; &lt;b&gt;</pre>"));
+        assert!(html.contains("<tr><td>EMAIL_ADDRESS</td><td>2</td></tr>"));
+    }
+}
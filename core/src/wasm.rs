@@ -0,0 +1,20 @@
+// JS bindings for browser clients, so they seal uploads with the same code the service
+// is tested against. Built with `wasm-pack build core -- --no-default-features --features wasm`.
+
+use wasm_bindgen::prelude::*;
+
+use crate::envelope;
+
+// Seal plaintext for upload; resolves to an object with the envelope and checksum
+// fields of the upload request
+#[wasm_bindgen(js_name = sealEnvelope)]
+pub fn seal_envelope(public_key_pem: &str, plaintext: &[u8]) -> Result<JsValue, JsError> {
+    let sealed = envelope::seal(public_key_pem, plaintext)
+        .map_err(|e| JsError::new(&e.to_string()))?;
+    serde_wasm_bindgen::to_value(&sealed).map_err(|e| JsError::new(&e.to_string()))
+}
+
+#[wasm_bindgen(js_name = sha256Hex)]
+pub fn sha256_hex(data: &[u8]) -> String {
+    envelope::hex(&crate::crypto::sha256(data))
+}
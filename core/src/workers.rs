@@ -0,0 +1,113 @@
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+const DEFAULT_QUEUE_DEPTH: usize = 64;
+
+// Returned at once when every worker is busy and the queue is full, so callers can
+// answer 503 instead of slowing down every request
+#[derive(Debug)]
+pub struct CryptoSaturated {
+    pub capacity: usize,
+}
+
+impl fmt::Display for CryptoSaturated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The crypto workers are saturated with {} jobs; retry shortly", self.capacity)
+    }
+}
+
+impl std::error::Error for CryptoSaturated {}
+
+// Blocking threads for RSA unwrapping and large payload decryption, kept off the async
+// executor. At most `workers` jobs run at once and `queue_depth` more wait; any beyond
+// that are refused with `CryptoSaturated`.
+pub struct CryptoPool {
+    workers: Arc<Semaphore>,
+    capacity: usize,
+    pending: Arc<AtomicUsize>,
+}
+
+// Counts a job as pending until it finishes, even when its caller stops waiting
+struct Pending(Arc<AtomicUsize>);
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl CryptoPool {
+    pub fn new(workers: usize, queue_depth: usize) -> Self {
+        let workers = workers.max(1);
+        Self { workers: Arc::new(Semaphore::new(workers)), capacity: workers + queue_depth, pending: Arc::new(AtomicUsize::new(0)) }
+    }
+
+    // `CRYPTO_WORKERS` (default: one per CPU) and `CRYPTO_QUEUE_DEPTH` (default 64)
+    pub fn from_env() -> Self {
+        let env = |name: &str| std::env::var(name).ok().and_then(|value| value.parse::<usize>().ok());
+        let workers = env("CRYPTO_WORKERS")
+            .filter(|workers| *workers > 0)
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |cpus| cpus.get()));
+        Self::new(workers, env("CRYPTO_QUEUE_DEPTH").unwrap_or(DEFAULT_QUEUE_DEPTH))
+    }
+
+    // Jobs running or waiting for a worker
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    pub async fn run<T, F>(&self, job: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        self.pending
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| (pending < self.capacity).then_some(pending + 1))
+            .map_err(|_| CryptoSaturated { capacity: self.capacity })?;
+        let pending = Pending(self.pending.clone());
+        let permit = self.workers.clone().acquire_owned().await.map_err(|_| anyhow!("The crypto pool is closed"))?;
+        tokio::task::spawn_blocking(move || {
+            let _held = (permit, pending);
+            job()
+        })
+        .await
+        .map_err(|e| anyhow!("A crypto worker failed: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_jobs_past_the_queue_are_refused() {
+        let pool = Arc::new(CryptoPool::new(1, 1));
+        assert_eq!(pool.run(|| 2 + 2).await.unwrap(), 4);
+
+        // One job holds the worker and one waits; a third finds no room
+        let (release, blocked) = mpsc::channel::<()>();
+        let running = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(move || blocked.recv().unwrap()).await }
+        });
+        let queued = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(|| "queued").await }
+        });
+        while pool.pending() < 2 {
+            tokio::task::yield_now().await;
+        }
+        let refused = pool.run(|| ()).await.unwrap_err();
+        assert!(refused.downcast_ref::<CryptoSaturated>().is_some());
+
+        release.send(()).unwrap();
+        running.await.unwrap().unwrap();
+        assert_eq!(queued.await.unwrap().unwrap(), "queued");
+        assert_eq!(pool.pending(), 0);
+        assert!(pool.run(|| ()).await.is_ok());
+    }
+}
@@ -14,6 +14,9 @@ import string
 
 app = Flask(__name__)
 
+# Version of the response shapes below; the redactor refuses versions newer than it knows
+SCHEMA_VERSION = 1
+
 # Configure NLP engine with better language support
 nlp_configuration = {
     "nlp_engine_name": "spacy",
@@ -107,13 +110,17 @@ def redact():
         data = request.get_json()
         text = data.get('text', '')
         strategy = data.get('strategy', 'replace')  # Default to replace strategy
-        
+        entities = data.get('entities')  # None analyzes every supported entity
+        score_threshold = data.get('score_threshold')
+        if score_threshold is None:
+            score_threshold = 0.4  # Lower threshold to catch more entities
         
         # Analyze the text with comprehensive entity detection
         results = analyzer.analyze(
             text=text, 
             language="en",
-            score_threshold=0.4  # Lower threshold to catch more entities
+            entities=entities,
+            score_threshold=score_threshold
         )
         
         # Get anonymization configuration based on strategy
@@ -131,6 +138,7 @@ def redact():
             anonymized = anonymizer.anonymize(text=text, analyzer_results=results)
         
         return jsonify({
+            "schema_version": SCHEMA_VERSION,
             "redacted_text": anonymized.text,
             "strategy_used": strategy,
             "entities_found": [result.entity_type for result in results],
@@ -148,6 +156,38 @@ def redact():
     except Exception as e:
         return jsonify({"error": str(e)}), 500
 
+@app.route('/analyze', methods=['POST'])
+def analyze():
+    """Detect PII without anonymizing it"""
+    try:
+        data = request.get_json()
+        text = data.get('text', '')
+        score_threshold = data.get('score_threshold')
+        if score_threshold is None:
+            score_threshold = 0.4
+
+        results = analyzer.analyze(
+            text=text,
+            language="en",
+            entities=data.get('entities'),
+            score_threshold=score_threshold
+        )
+
+        return jsonify({
+            "schema_version": SCHEMA_VERSION,
+            "entity_details": [
+                {
+                    "entity_type": result.entity_type,
+                    "start": result.start,
+                    "end": result.end,
+                    "score": result.score
+                }
+                for result in results
+            ]
+        })
+    except Exception as e:
+        return jsonify({"error": str(e)}), 500
+
 @app.route('/strategies', methods=['GET'])
 def get_strategies():
     """Get available redaction strategies"""
@@ -0,0 +1,30 @@
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts, response::Response};
+use subtle::ConstantTimeEq;
+
+use sentient_redactor_core::operations::ErrorKind;
+
+// Guard for operator-only endpoints: the `X-Admin-Token` header must match `ADMIN_TOKEN`.
+// Admin endpoints are disabled when `ADMIN_TOKEN` is unset.
+pub struct Admin;
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Admin {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let reject = |error: &str| crate::api_error(ErrorKind::Forbidden, error);
+
+        let Some(expected) = std::env::var("ADMIN_TOKEN").ok().filter(|token| !token.is_empty()) else {
+            return Err(reject("Admin endpoints are disabled"));
+        };
+        let provided = parts.headers.get("X-Admin-Token")
+            .map(|value| value.as_bytes())
+            .unwrap_or_default();
+
+        if bool::from(provided.ct_eq(expected.as_bytes())) {
+            Ok(Admin)
+        } else {
+            Err(reject("Invalid admin token"))
+        }
+    }
+}
@@ -0,0 +1,437 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+use utoipa::{IntoParams, ToSchema};
+
+use sentient_redactor_core::upstream;
+
+// Operations whose records carry an erasure receipt
+pub const ERASURE_OPERATIONS: [&str; 3] = ["file.delete", "file.expire", "file.evict"];
+// Hash that the first record of a fresh chain points back to
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+// Records per page of `GET /audit`, by default and at most
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+
+// One audited operation. Records describe who did what to which file, never content.
+// Each record carries the hash of its predecessor, so edits, deletions and reordering
+// anywhere in the trail break every later hash.
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct AuditRecord {
+    #[serde(default)]
+    pub sequence: u64,
+    pub timestamp: u64,
+    pub operation: String,
+    pub actor: Option<String>,
+    pub file_id: Option<String>,
+    pub result: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+    #[serde(default)]
+    pub prev_hash: String,
+    #[serde(default)]
+    pub hash: String,
+}
+
+impl AuditRecord {
+    pub fn new(operation: &str, actor: Option<&str>, file_id: Option<&str>, result: &str) -> Self {
+        Self {
+            sequence: 0,
+            timestamp: now(),
+            operation: operation.to_string(),
+            actor: actor.map(str::to_string),
+            file_id: file_id.map(str::to_string),
+            result: result.to_string(),
+            details: None,
+            prev_hash: String::new(),
+            hash: String::new(),
+        }
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    // SHA-256 over the record's JSON encoding with `hash` left empty
+    fn compute_hash(&self) -> String {
+        let mut unhashed = self.clone();
+        unhashed.hash = String::new();
+        let encoded = serde_json::to_vec(&unhashed).unwrap_or_default();
+        hex_digest(&encoded)
+    }
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct AnchorReceipt {
+    pub sequence: u64,
+    pub hash: String,
+    pub anchored_at: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ChainVerification {
+    pub valid: bool,
+    pub records: usize,
+    pub head_hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_invalid_sequence: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_anchor: Option<AnchorReceipt>,
+}
+
+// Filters of `GET /audit`. Records come back oldest first; `after` pages on from the
+// last sequence returned.
+#[derive(Default, Deserialize, IntoParams)]
+pub struct AuditQuery {
+    pub operation: Option<String>,
+    pub actor: Option<String>,
+    pub file_id: Option<String>,
+    pub result: Option<String>,
+    // Unix time of the oldest record wanted
+    pub since: Option<u64>,
+    pub after: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn matches(&self, record: &AuditRecord) -> bool {
+        let equal = |filter: &Option<String>, value: Option<&str>| filter.as_deref().is_none_or(|filter| value == Some(filter));
+        equal(&self.operation, Some(&record.operation))
+            && equal(&self.actor, record.actor.as_deref())
+            && equal(&self.file_id, record.file_id.as_deref())
+            && equal(&self.result, Some(&record.result))
+            && self.since.is_none_or(|since| record.timestamp >= since)
+            && self.after.is_none_or(|after| record.sequence > after)
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AuditPage {
+    pub records: Vec<AuditRecord>,
+    // Pass as `after` for the next page; absent on the last one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_after: Option<u64>,
+}
+
+// Append-only audit trail, kept in memory and mirrored to a JSONL file when
+// `AUDIT_LOG_PATH` is set
+pub struct AuditLog {
+    records: Vec<AuditRecord>,
+    sink: Option<(String, File)>,
+    head_hash: String,
+    next_sequence: u64,
+    last_anchor: Option<AnchorReceipt>,
+    // Latest erasure record of each file, persisted ones included, so receipts are
+    // looked up without reading the file
+    receipts: HashMap<String, AuditRecord>,
+    // Outcome of the last verification, so a broken chain alerts once, not per check
+    intact: AtomicBool,
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        Self {
+            records: Vec::new(),
+            sink: None,
+            head_hash: GENESIS_HASH.to_string(),
+            next_sequence: 0,
+            last_anchor: None,
+            receipts: HashMap::new(),
+            intact: AtomicBool::new(true),
+        }
+    }
+
+    pub fn from_env() -> Result<Self> {
+        let mut log = Self::new();
+
+        if let Ok(path) = std::env::var("AUDIT_LOG_PATH") {
+            // Continue the chain from the last record already in the file
+            let records = read_records(&path)?;
+            if let Some(last) = records.last() {
+                log.head_hash = last.hash.clone();
+                log.next_sequence = last.sequence + 1;
+            }
+            for record in records {
+                log.index_receipt(&record);
+            }
+
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .map_err(|e| anyhow!("Failed to open audit log {}: {}", path, e))?;
+            info!("Writing audit records to {}", path);
+            log.sink = Some((path, file));
+        }
+
+        Ok(log)
+    }
+
+    pub fn record(&mut self, mut record: AuditRecord) {
+        record.sequence = self.next_sequence;
+        record.prev_hash = self.head_hash.clone();
+        record.hash = record.compute_hash();
+        self.next_sequence += 1;
+        self.head_hash = record.hash.clone();
+
+        if let Some((_, sink)) = &mut self.sink {
+            let line = serde_json::to_string(&record).unwrap_or_default();
+            if let Err(e) = writeln!(sink, "{}", line) {
+                warn!("Failed to write audit record: {}", e);
+            }
+        }
+        self.index_receipt(&record);
+        self.records.push(record);
+    }
+
+    // Most recent erasure record of a file
+    pub fn receipt(&self, file_id: &str) -> Option<&AuditRecord> {
+        self.receipts.get(file_id)
+    }
+
+    fn index_receipt(&mut self, record: &AuditRecord) {
+        if let (true, Some(file_id)) = (ERASURE_OPERATIONS.contains(&record.operation.as_str()), &record.file_id) {
+            self.receipts.insert(file_id.clone(), record.clone());
+        }
+    }
+
+    // Records matching `query`, read from the persisted file when there is one, as
+    // memory only holds those of this run
+    pub fn query(&self, query: &AuditQuery) -> Result<AuditPage> {
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        let mut persisted = Vec::new();
+        let records = self.records_or(&mut persisted)?;
+
+        let mut matching = records.iter().filter(|record| query.matches(record));
+        let page: Vec<AuditRecord> = matching.by_ref().take(limit).cloned().collect();
+        let next_after = match matching.next() {
+            Some(_) => page.last().map(|record| record.sequence),
+            None => None,
+        };
+        Ok(AuditPage { records: page, next_after })
+    }
+
+    // The whole trail: read into `persisted` when there is a file, otherwise the records
+    // held in memory
+    fn records_or<'a>(&'a self, persisted: &'a mut Vec<AuditRecord>) -> Result<&'a [AuditRecord]> {
+        match &self.sink {
+            Some((path, _)) => {
+                *persisted = read_records(path)?;
+                Ok(persisted)
+            }
+            None => Ok(&self.records),
+        }
+    }
+
+    // Check the whole chain: the persisted file when there is one, otherwise the
+    // records held in memory
+    pub fn verify(&self) -> Result<ChainVerification> {
+        let mut verification = match &self.sink {
+            Some((path, _)) => verify_chain(&read_records(path)?),
+            None => verify_chain(&self.records),
+        };
+        verification.last_anchor = self.last_anchor.clone();
+        Ok(verification)
+    }
+
+    // Note the outcome of a verification; true when the chain was intact until now
+    pub fn newly_broken(&self, verification: &ChainVerification) -> bool {
+        self.intact.swap(verification.valid, Ordering::SeqCst) && !verification.valid
+    }
+}
+
+fn verify_chain(records: &[AuditRecord]) -> ChainVerification {
+    let mut expected_prev = GENESIS_HASH.to_string();
+
+    for (index, record) in records.iter().enumerate() {
+        if record.sequence != index as u64
+            || record.prev_hash != expected_prev
+            || record.hash != record.compute_hash()
+        {
+            return ChainVerification {
+                valid: false,
+                records: records.len(),
+                head_hash: expected_prev,
+                first_invalid_sequence: Some(index as u64),
+                last_anchor: None,
+            };
+        }
+        expected_prev = record.hash.clone();
+    }
+
+    ChainVerification {
+        valid: true,
+        records: records.len(),
+        head_hash: expected_prev,
+        first_invalid_sequence: None,
+        last_anchor: None,
+    }
+}
+
+fn read_records(path: &str) -> Result<Vec<AuditRecord>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(anyhow!("Failed to read audit log {}: {}", path, e)),
+    };
+
+    contents.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(|e| anyhow!("Invalid audit record in {}: {}", path, e)))
+        .collect()
+}
+
+// Periodically publishes the chain head to an external endpoint (a webhook, an object
+// store's pre-signed PUT URL, or a transparency log front end), so a rewritten log can
+// be caught by comparing against the anchored hashes
+pub struct AuditAnchor {
+    client: reqwest::Client,
+    url: String,
+    interval: Duration,
+}
+
+impl AuditAnchor {
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(url) = std::env::var("AUDIT_ANCHOR_URL") else {
+            return Ok(None);
+        };
+
+        let interval = std::env::var("AUDIT_ANCHOR_INTERVAL_SECONDS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(300);
+
+        Ok(Some(Self {
+            client: upstream::build_client("AUDIT_ANCHOR", Duration::from_secs(10))?,
+            url,
+            interval: Duration::from_secs(interval),
+        }))
+    }
+
+    pub fn spawn(self, log: Arc<RwLock<AuditLog>>) {
+        info!("Anchoring audit chain head to {} every {:?}", self.url, self.interval);
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(self.interval);
+            loop {
+                ticker.tick().await;
+
+                let (sequence, hash) = {
+                    let log = log.read().await;
+                    let already_anchored = log.last_anchor.as_ref()
+                        .is_some_and(|anchor| anchor.hash == log.head_hash);
+                    if log.next_sequence == 0 || already_anchored {
+                        continue;
+                    }
+                    (log.next_sequence - 1, log.head_hash.clone())
+                };
+
+                let anchored_at = now();
+                let body = serde_json::json!({
+                    "sequence": sequence,
+                    "hash": hash,
+                    "anchored_at": anchored_at,
+                });
+                match self.client.post(&self.url).json(&body).send().await {
+                    Ok(response) if response.status().is_success() => {
+                        info!("Anchored audit chain head at sequence {}", sequence);
+                        log.write().await.last_anchor = Some(AnchorReceipt { sequence, hash, anchored_at });
+                    }
+                    Ok(response) => warn!("Audit anchor rejected chain head: {}", response.status()),
+                    Err(e) => warn!("Failed to anchor audit chain head: {}", e),
+                }
+            }
+        });
+    }
+}
+
+fn hex_digest(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_are_appended_in_order() {
+        let mut log = AuditLog::new();
+        log.record(AuditRecord::new("share.create", Some("alice"), Some("f1"), "success"));
+        log.record(
+            AuditRecord::new("share.redeem", None, Some("f1"), "denied")
+                .with_details(serde_json::json!({ "reason": "expired" })),
+        );
+
+        let records = &log.records;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].operation, "share.create");
+        assert_eq!(records[1].result, "denied");
+        assert_eq!(records[1].details.as_ref().unwrap()["reason"], "expired");
+        assert!(log.receipt("f1").is_none());
+
+        log.record(AuditRecord::new("file.delete", Some("alice"), Some("f1"), "success"));
+        log.record(AuditRecord::new("file.expire", None, Some("f1"), "expired"));
+        assert_eq!(log.receipt("f1").unwrap().operation, "file.expire");
+        assert!(log.receipt("f2").is_none());
+    }
+
+    #[test]
+    fn test_chain_detects_tampering() {
+        let mut log = AuditLog::new();
+        for file_id in ["f1", "f2", "f3"] {
+            log.record(AuditRecord::new("file.delete", Some("alice"), Some(file_id), "success"));
+        }
+        assert_eq!(log.records[1].prev_hash, log.records[0].hash);
+
+        let verification = log.verify().unwrap();
+        assert!(verification.valid);
+        assert_eq!(verification.head_hash, log.records[2].hash);
+
+        assert!(!log.newly_broken(&verification));
+
+        log.records[1].actor = Some("mallory".to_string());
+        let verification = log.verify().unwrap();
+        assert!(!verification.valid);
+        assert_eq!(verification.first_invalid_sequence, Some(1));
+        assert!(log.newly_broken(&verification));
+        assert!(!log.newly_broken(&verification));
+
+        log.records.remove(1);
+        assert_eq!(log.verify().unwrap().first_invalid_sequence, Some(1));
+    }
+
+    #[test]
+    fn test_queries_filter_and_page() {
+        let mut log = AuditLog::new();
+        for (operation, actor) in [("file.upload", "alice"), ("file.download", "bob"), ("file.upload", "bob"), ("file.upload", "alice")] {
+            log.record(AuditRecord::new(operation, Some(actor), Some("f1"), "success"));
+        }
+
+        let uploads = AuditQuery { operation: Some("file.upload".to_string()), limit: Some(1), ..Default::default() };
+        let page = log.query(&uploads).unwrap();
+        assert_eq!((page.records[0].sequence, page.next_after), (0, Some(0)));
+        let page = log.query(&AuditQuery { after: page.next_after, ..uploads }).unwrap();
+        assert_eq!((page.records[0].sequence, page.next_after), (2, Some(2)));
+
+        let by_alice = AuditQuery { actor: Some("alice".to_string()), after: Some(0), ..Default::default() };
+        let page = log.query(&by_alice).unwrap();
+        assert_eq!(page.records.iter().map(|record| record.sequence).collect::<Vec<_>>(), [3]);
+        assert!(page.next_after.is_none());
+    }
+}
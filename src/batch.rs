@@ -0,0 +1,175 @@
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::future::Future;
+use utoipa::ToSchema;
+
+use sentient_redactor_core::operations::{OperationError, UploadRequest, UploadResponse};
+
+pub const MAX_BATCH_ITEMS: usize = 100;
+// Fields that must differ between documents encrypted under one session key
+const PER_ITEM_FIELDS: [&str; 3] = ["encrypted_data", "nonce", "expectation"];
+
+// Upload fields shared by every document at the top level, such as the session key,
+// and each document's own fields under `items`, taking precedence over the shared ones
+#[derive(Deserialize, ToSchema)]
+pub struct BatchUploadRequest {
+    pub items: Vec<Map<String, Value>>,
+    #[serde(flatten)]
+    pub shared: Map<String, Value>,
+}
+
+// The outcome of one document, at its position in the request
+#[derive(Serialize, ToSchema)]
+pub struct BatchItemResult {
+    pub index: usize,
+    #[serde(flatten)]
+    pub upload: Option<UploadResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<BatchItemError>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BatchItemError {
+    pub message: String,
+    pub code: &'static str,
+}
+
+impl BatchItemResult {
+    pub fn new(index: usize, result: Result<UploadResponse, OperationError>) -> Self {
+        match result {
+            Ok(upload) => Self { index, upload: Some(upload), error: None },
+            Err(e) => Self {
+                index,
+                upload: None,
+                error: Some(BatchItemError { code: e.code.unwrap_or(e.kind.code()), message: e.message }),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BatchUploadResponse {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub items: Vec<BatchItemResult>,
+}
+
+impl BatchUploadResponse {
+    pub fn new(items: Vec<BatchItemResult>) -> Self {
+        let failed = items.iter().filter(|item| item.error.is_some()).count();
+        Self { succeeded: items.len() - failed, failed, items }
+    }
+}
+
+// One upload per item, the shared fields filled in. An item that is not a valid upload
+// fails on its own; only a malformed batch is refused as a whole.
+pub fn uploads(request: BatchUploadRequest) -> Result<Vec<Result<UploadRequest, String>>, String> {
+    if request.items.is_empty() {
+        return Err("A batch upload needs at least one item".to_string());
+    }
+    if request.items.len() > MAX_BATCH_ITEMS {
+        return Err(format!("A batch upload is limited to {} items", MAX_BATCH_ITEMS));
+    }
+    if let Some(field) = PER_ITEM_FIELDS.iter().find(|field| request.shared.contains_key(**field)) {
+        return Err(format!("{} must be given per item, not for the whole batch", field));
+    }
+
+    Ok(request.items.into_iter()
+        .map(|item| {
+            let mut upload = request.shared.clone();
+            upload.extend(item);
+            serde_json::from_value(Value::Object(upload)).map_err(|e| format!("Invalid item: {}", e))
+        })
+        .collect())
+}
+
+// `BATCH_UPLOAD_MAX_BYTES` bounds batch upload bodies; defaults to 64 MiB
+pub fn max_bytes() -> usize {
+    std::env::var("BATCH_UPLOAD_MAX_BYTES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(64 * 1024 * 1024)
+}
+
+// `BATCH_UPLOAD_CONCURRENCY` bounds the documents of one batch redacted at once; defaults to 4
+pub fn concurrency() -> usize {
+    std::env::var("BATCH_UPLOAD_CONCURRENCY")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(4)
+}
+
+// Run `task` over `items` with at most `limit` running at once; results are in item order
+pub async fn run_bounded<I, T, F, Fut>(items: Vec<I>, limit: usize, task: F) -> Vec<T>
+where
+    F: Fn(I) -> Fut,
+    Fut: Future<Output = T>,
+{
+    let mut pending = items.into_iter().enumerate();
+    let mut running = FuturesUnordered::new();
+    let mut results = Vec::with_capacity(pending.len());
+    loop {
+        while running.len() < limit.max(1) {
+            let Some((index, item)) = pending.next() else { break };
+            let future = task(item);
+            running.push(async move { (index, future.await) });
+        }
+        match running.next().await {
+            Some(result) => results.push(result),
+            None => break,
+        }
+    }
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_items_inherit_shared_fields() {
+        let request: BatchUploadRequest = serde_json::from_value(serde_json::json!({
+            "encrypted_session_key": "key",
+            "redaction_strategy": "mask",
+            "items": [
+                { "encrypted_data": "a", "nonce": "n1", "file_name": "a.txt" },
+                { "encrypted_data": "b", "nonce": "n2", "redaction_strategy": "replace" },
+                { "encrypted_data": "c", "score_threshold": "high" },
+            ],
+        }))
+        .unwrap();
+        let uploads = uploads(request).unwrap();
+        let first = uploads[0].as_ref().unwrap();
+        assert_eq!((first.encrypted_session_key.as_deref(), first.redaction_strategy.as_deref()), (Some("key"), Some("mask")));
+        assert_eq!(first.file_name.as_deref(), Some("a.txt"));
+        assert_eq!(uploads[1].as_ref().unwrap().redaction_strategy.as_deref(), Some("replace"));
+        assert!(uploads[2].is_err());
+
+        let shared_nonce = serde_json::json!({ "nonce": "n", "items": [{ "encrypted_data": "a" }] });
+        assert!(super::uploads(serde_json::from_value(shared_nonce).unwrap()).is_err());
+        let empty = serde_json::json!({ "items": [] });
+        assert!(super::uploads(serde_json::from_value(empty).unwrap()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_runs_are_bounded_and_ordered() {
+        let (running, peak) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        let results = run_bounded((0..10u64).collect(), 3, |item| {
+            let (running, peak) = (&running, &peak);
+            async move {
+                peak.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                // Later items finish first
+                tokio::time::sleep(std::time::Duration::from_millis(10 - item)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                item * 2
+            }
+        })
+        .await;
+        assert_eq!(results, (0..10).map(|item| item * 2).collect::<Vec<_>>());
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use utoipa::ToSchema;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use sentient_redactor_core::{
+    caller::Caller,
+    operations::{self, ErrorKind},
+    storage::Storage,
+};
+
+pub const MAX_BULK_FILES: usize = 500;
+
+// Select files either explicitly or by a prefix of the caller's external ids
+#[derive(Deserialize, ToSchema)]
+pub struct BulkDownloadRequest {
+    pub file_ids: Option<Vec<String>>,
+    pub external_id_prefix: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ManifestEntry {
+    pub file_id: String,
+    // `included`, `not_found` or `forbidden`
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+// Entries to archive, as (path, content), plus the manifest describing every requested file
+pub struct BulkSelection {
+    pub files: Vec<(String, String)>,
+    pub manifest: Vec<ManifestEntry>,
+}
+
+// Resolve the request against storage, applying each file's download ACL. Files the
+// caller cannot read are left out of the archive but listed in the manifest.
+pub fn select(storage: &dyn Storage, caller: &Caller, request: &BulkDownloadRequest) -> Result<BulkSelection, String> {
+    let file_ids = match (&request.file_ids, &request.external_id_prefix) {
+        (Some(file_ids), None) => file_ids.clone(),
+        (None, Some(prefix)) => {
+            let mut file_ids: Vec<String> = storage.file_ids()
+                .into_iter()
+                .filter(|file_id| {
+                    storage.get_metadata(file_id).is_some_and(|metadata| {
+                        metadata.tenant == caller.tenant
+                            && metadata.external_id.as_deref().is_some_and(|id| id.starts_with(prefix.as_str()))
+                    })
+                })
+                .collect();
+            file_ids.sort();
+            file_ids
+        }
+        _ => return Err("Provide either file_ids or external_id_prefix".to_string()),
+    };
+    if file_ids.len() > MAX_BULK_FILES {
+        return Err(format!("A bulk download is limited to {} files", MAX_BULK_FILES));
+    }
+
+    let mut selection = BulkSelection { files: Vec::new(), manifest: Vec::new() };
+    for file_id in file_ids {
+        match operations::fetch_download(storage, caller, &file_id) {
+            Ok(file) => {
+                let path = format!("files/{}", file.file_name.replace(['/', '\\'], "_"));
+                let sha256 = Sha256::digest(file.content.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+                selection.manifest.push(ManifestEntry {
+                    file_id,
+                    status: "included",
+                    path: Some(path.clone()),
+                    sha256: Some(sha256),
+                });
+                selection.files.push((path, file.content));
+            }
+            Err(e) => selection.manifest.push(ManifestEntry {
+                file_id,
+                status: match (e.kind, e.code) {
+                    (_, Some("review_required")) => "held",
+                    (ErrorKind::Gone, _) => "expired",
+                    (ErrorKind::Forbidden, _) => "forbidden",
+                    _ => "not_found",
+                },
+                path: None,
+                sha256: None,
+            }),
+        }
+    }
+
+    Ok(selection)
+}
+
+// Write the archive as a zip stream: the selected files, then `manifest.json`
+pub fn write_archive(selection: BulkSelection, writer: impl Write) -> zip::result::ZipResult<()> {
+    let mut zip = ZipWriter::new_stream(writer);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    for (path, content) in &selection.files {
+        zip.start_file(path.as_str(), options)?;
+        zip.write_all(content.as_bytes())?;
+    }
+
+    zip.start_file("manifest.json", options)?;
+    let manifest = serde_json::json!({ "entries": selection.manifest });
+    zip.write_all(serde_json::to_string_pretty(&manifest).unwrap_or_default().as_bytes())?;
+
+    zip.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sentient_redactor_core::{acl::FileAcl, storage::FileStorage};
+    use std::io::{Cursor, Read};
+
+    #[test]
+    fn test_archive_skips_unauthorized_files() {
+        let mut storage = FileStorage::new();
+        storage.store_file("f1", "a_replace_redacted_f1.txt", "<PERSON> called");
+        let private = storage.store_file("f2", "b_replace_redacted_f2.txt", "secret");
+        private.owner = Some("bob".to_string());
+        private.acl = Some(FileAcl::default());
+
+        let caller = Caller { principal: Some("alice".to_string()), tenant: None, scopes: None };
+        let request = BulkDownloadRequest {
+            file_ids: Some(vec!["f1".to_string(), "f2".to_string(), "f3".to_string()]),
+            external_id_prefix: None,
+        };
+        let selection = select(&storage, &caller, &request).unwrap();
+        let statuses: Vec<_> = selection.manifest.iter().map(|entry| entry.status).collect();
+        assert_eq!(statuses, ["included", "forbidden", "not_found"]);
+
+        let mut archive = Vec::new();
+        write_archive(selection, &mut archive).unwrap();
+        let mut zip = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+        assert_eq!(zip.len(), 2);
+
+        let mut content = String::new();
+        zip.by_name("files/a_replace_redacted_f1.txt").unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "<PERSON> called");
+        assert!(zip.by_name("manifest.json").is_ok());
+    }
+}
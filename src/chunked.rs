@@ -0,0 +1,158 @@
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+
+use sentient_redactor_core::{
+    crypto::{StreamOpener, STREAM_TAG_LEN},
+    redactor::{RedactionOptions, RedactionReport, RedactorService},
+};
+
+use crate::stream::StreamRedactor;
+
+const DEFAULT_CHUNK_BYTES: usize = 64 * 1024;
+const MAX_CHUNK_BYTES: usize = 16 * 1024 * 1024;
+
+pub enum ChunkError {
+    // The ciphertext does not open, or not to UTF-8 text
+    Decryption(anyhow::Error),
+    Redaction(anyhow::Error),
+}
+
+// Decrypts and redacts an upload as its ciphertext arrives. Body bytes are cut into
+// sealed chunks, each opened with the STREAM construction and fed to the stream
+// redactor, so only one chunk and the redactor's lookahead are held besides the output.
+pub struct ChunkedUpload {
+    opener: StreamOpener,
+    redactor: StreamRedactor,
+    // Sealed size of every chunk but the last
+    sealed_chunk: usize,
+    // Ciphertext received past the last whole chunk
+    pending: Vec<u8>,
+    // Start of a UTF-8 character cut off at the end of the last chunk
+    partial: Vec<u8>,
+    ciphertext_digest: Sha256,
+    plaintext_digest: Sha256,
+    ciphertext_bytes: usize,
+    plaintext_bytes: usize,
+    redacted: String,
+}
+
+pub struct ChunkedOutput {
+    pub redacted: String,
+    pub report: RedactionReport,
+    pub ciphertext_sha256: [u8; 32],
+    pub plaintext_sha256: [u8; 32],
+    pub ciphertext_bytes: usize,
+    pub plaintext_bytes: usize,
+}
+
+impl ChunkedUpload {
+    pub fn new(opener: StreamOpener, redactor: StreamRedactor, chunk_size: usize) -> Self {
+        Self {
+            opener,
+            redactor,
+            sealed_chunk: chunk_size + STREAM_TAG_LEN,
+            pending: Vec::new(),
+            partial: Vec::new(),
+            ciphertext_digest: Sha256::new(),
+            plaintext_digest: Sha256::new(),
+            ciphertext_bytes: 0,
+            plaintext_bytes: 0,
+            redacted: String::new(),
+        }
+    }
+
+    // Plaintext bytes per chunk: the upload's `chunk_size`, or `UPLOAD_CHUNK_BYTES`
+    pub fn chunk_size(requested: Option<usize>) -> Result<usize> {
+        let chunk_size = requested.unwrap_or_else(|| {
+            std::env::var("UPLOAD_CHUNK_BYTES")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(DEFAULT_CHUNK_BYTES)
+        });
+        if !(1..=MAX_CHUNK_BYTES).contains(&chunk_size) {
+            return Err(anyhow!("chunk_size must be between 1 and {} bytes", MAX_CHUNK_BYTES));
+        }
+        Ok(chunk_size)
+    }
+
+    pub async fn push(&mut self, redactor: &RedactorService, options: &RedactionOptions<'_>, bytes: &[u8]) -> Result<(), ChunkError> {
+        self.ciphertext_digest.update(bytes);
+        self.ciphertext_bytes += bytes.len();
+        self.pending.extend_from_slice(bytes);
+
+        // A whole chunk may still be the last one until more ciphertext follows it
+        while self.pending.len() > self.sealed_chunk {
+            let chunk: Vec<u8> = self.pending.drain(..self.sealed_chunk).collect();
+            let plaintext = self.opener.open_chunk(&chunk, false).map_err(ChunkError::Decryption)?;
+            self.redact(redactor, options, &plaintext).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn finish(mut self, redactor: &RedactorService, options: &RedactionOptions<'_>) -> Result<ChunkedOutput, ChunkError> {
+        let last = std::mem::take(&mut self.pending);
+        let plaintext = self.opener.open_chunk(&last, true).map_err(ChunkError::Decryption)?;
+        self.redact(redactor, options, &plaintext).await?;
+        if !self.partial.is_empty() {
+            return Err(ChunkError::Decryption(anyhow!("Plaintext ends inside a UTF-8 character")));
+        }
+
+        let (tail, report) = self.redactor.finish(redactor, options).await.map_err(ChunkError::Redaction)?;
+        self.redacted.push_str(&tail);
+        Ok(ChunkedOutput {
+            redacted: self.redacted,
+            report,
+            ciphertext_sha256: self.ciphertext_digest.finalize().into(),
+            plaintext_sha256: self.plaintext_digest.finalize().into(),
+            ciphertext_bytes: self.ciphertext_bytes,
+            plaintext_bytes: self.plaintext_bytes,
+        })
+    }
+
+    async fn redact(&mut self, redactor: &RedactorService, options: &RedactionOptions<'_>, plaintext: &[u8]) -> Result<(), ChunkError> {
+        self.plaintext_digest.update(plaintext);
+        self.plaintext_bytes += plaintext.len();
+        // Checked here so the redactor only ever fails on redaction
+        self.partial.extend_from_slice(plaintext);
+        let valid = match std::str::from_utf8(&self.partial) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => return Err(ChunkError::Decryption(anyhow!("Invalid UTF-8"))),
+        };
+        let text: Vec<u8> = self.partial.drain(..valid).collect();
+        let redacted = self.redactor.push(redactor, options, &text).await.map_err(ChunkError::Redaction)?;
+        self.redacted.push_str(&redacted);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sentient_redactor_core::{bidi::BidiMode, crypto, labels::LabelCatalog, rules::RegexEngine, EntityFilter};
+
+    #[tokio::test]
+    async fn test_chunked_upload_redacts_across_chunks() {
+        let redactor = RedactorService::with_backend(Box::new(RegexEngine::new()), LabelCatalog::default());
+        let options = RedactionOptions { strategy: "replace", tenant: None, language: "en", pipelines: None, filter: EntityFilter::default(), bidi: BidiMode::default(), custom: None, boundaries: None, hashing: None };
+        let key = [7u8; 32];
+        let plaintext = "Reach Jane at jane.doe@example.com or 555-123-4567, café hours only. ".repeat(20);
+        let sealed = crypto::seal_stream(plaintext.as_bytes(), &key, b"prefix7", 32).unwrap();
+
+        let mut upload = ChunkedUpload::new(StreamOpener::new(&key, b"prefix7").unwrap(), StreamRedactor::new(64), 32);
+        // Body pieces that line up with neither chunks nor characters
+        for piece in sealed.chunks(45) {
+            assert!(upload.push(&redactor, &options, piece).await.is_ok());
+        }
+        let output = upload.finish(&redactor, &options).await.ok().unwrap();
+        assert_eq!(output.redacted, "Reach Jane at <EMAIL_ADDRESS> or <PHONE_NUMBER>, café hours only. ".repeat(20));
+        assert_eq!((output.report.entities["EMAIL_ADDRESS"], output.report.detections.len()), (20, 40));
+        assert_eq!(output.plaintext_sha256, crypto::sha256(plaintext.as_bytes()));
+        assert_eq!(output.ciphertext_sha256, crypto::sha256(&sealed));
+
+        // Dropping the last chunk is caught
+        let mut truncated = ChunkedUpload::new(StreamOpener::new(&key, b"prefix7").unwrap(), StreamRedactor::new(64), 32);
+        assert!(truncated.push(&redactor, &options, &sealed[..(32 + STREAM_TAG_LEN) * 3]).await.is_ok());
+        assert!(matches!(truncated.finish(&redactor, &options).await, Err(ChunkError::Decryption(_))));
+    }
+}
@@ -0,0 +1,61 @@
+use anyhow::Result;
+use clap::Parser;
+use figment::providers::Serialized;
+use serde::Serialize;
+use std::net::IpAddr;
+
+use sentient_redactor_core::config::AppConfig;
+
+// Flags override the config file and the environment; unset ones are left out so they
+// do not mask either
+#[derive(Parser, Serialize)]
+#[command(version, about = "Decrypts, redacts and stores client uploads inside a TEE")]
+pub struct Cli {
+    #[arg(long, env = "CONFIG_PATH", help = "TOML config file")]
+    #[serde(skip)]
+    pub config: Option<String>,
+    #[arg(long, help = "Address to listen on")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bind_addr: Option<IpAddr>,
+    #[arg(long, help = "Port to listen on")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[arg(long, help = "Port to serve the gRPC interface on")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grpc_port: Option<u16>,
+    #[arg(long, help = "Body limit of POST /upload in bytes")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_upload_bytes: Option<usize>,
+    #[arg(long, help = "Body limit in bytes of routes without their own")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_request_bytes: Option<usize>,
+    #[arg(long, help = "Directory to persist files in")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage_dir: Option<String>,
+    #[arg(long, help = "Base URL of the Presidio service")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presidio_url: Option<String>,
+}
+
+impl Cli {
+    pub fn load_config(&self) -> Result<AppConfig> {
+        AppConfig::extract(AppConfig::figment(self.config.as_deref()).merge(Serialized::defaults(self)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_override_the_defaults() {
+        let cli = Cli::try_parse_from(["sentient-redactor-service", "--port", "8443", "--storage-dir", "/var/lib/redactor"]).unwrap();
+        let config = cli.load_config().unwrap();
+        assert_eq!(config.port, 8443);
+        assert_eq!(config.storage_dir.as_deref(), Some("/var/lib/redactor"));
+        // Flags not given leave the lower layers alone
+        assert_eq!(config.bind_addr.to_string(), "0.0.0.0");
+
+        assert!(Cli::try_parse_from(["sentient-redactor-service", "--port", "http"]).is_err());
+    }
+}
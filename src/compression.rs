@@ -0,0 +1,75 @@
+use tower_http::compression::{
+    predicate::{NotForContentType, Predicate, SizeAbove},
+    CompressionLayer,
+};
+use tower_http::decompression::RequestDecompressionLayer;
+use tracing::info;
+
+const DEFAULT_MIN_RESPONSE_SIZE: u16 = 1024;
+
+pub struct CompressionConfig {
+    pub min_response_size: u16,
+}
+
+impl CompressionConfig {
+    pub fn from_env() -> Self {
+        let min_response_size = std::env::var("COMPRESSION_MIN_SIZE")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_MIN_RESPONSE_SIZE);
+
+        info!("Response compression enabled for bodies above {} bytes", min_response_size);
+
+        Self { min_response_size }
+    }
+
+    // Compress responses with gzip or zstd, negotiated via Accept-Encoding,
+    // once they exceed the configured size
+    pub fn response_layer(&self) -> CompressionLayer<impl Predicate> {
+        let predicate = SizeAbove::new(self.min_response_size)
+            .and(NotForContentType::GRPC)
+            .and(NotForContentType::IMAGES)
+            .and(NotForContentType::SSE);
+
+        CompressionLayer::new()
+            .gzip(true)
+            .zstd(true)
+            .compress_when(predicate)
+    }
+
+    // Decompress request bodies sent with Content-Encoding: gzip or zstd,
+    // rejecting any other encoding with 415
+    pub fn request_layer(&self) -> RequestDecompressionLayer {
+        RequestDecompressionLayer::new().gzip(true).zstd(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_large_response_is_compressed() {
+        let config = CompressionConfig { min_response_size: 16 };
+        let app = Router::new()
+            .route("/big", get(|| async { "x".repeat(4096) }))
+            .route("/small", get(|| async { "ok" }))
+            .layer(config.response_layer());
+
+        let request = |uri: &str| {
+            Request::builder()
+                .uri(uri)
+                .header("Accept-Encoding", "gzip")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(request("/big")).await.unwrap();
+        assert_eq!(response.headers()["content-encoding"], "gzip");
+
+        let response = app.oneshot(request("/small")).await.unwrap();
+        assert!(response.headers().get("content-encoding").is_none());
+    }
+}
@@ -0,0 +1,165 @@
+use anyhow::{anyhow, Result};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use reqwest::{Client, Url};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::any::Any;
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use sentient_redactor_core::{config::AppConfig, upstream};
+
+thread_local! {
+    // Where the last panic on this thread happened, kept by the hook from `install_hook`
+    static PANIC_LOCATION: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+// Note where each panic happens before the previous hook logs it. Reports name the
+// source line this way, as panic messages can quote request data.
+pub fn install_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let location = info.location().map(|location| format!("{}:{}", location.file(), location.line()));
+        PANIC_LOCATION.with(|last| *last.borrow_mut() = location);
+        previous(info);
+    }));
+}
+
+// Set on the response `CatchPanicLayer` makes for a handler that panicked
+#[derive(Clone, Debug)]
+pub struct Panicked {
+    pub location: Option<String>,
+    // Groups reports of the same crash without carrying its message
+    pub fingerprint: String,
+}
+
+// For `CatchPanicLayer`, which polls the handler on the thread that panicked
+pub fn panic_response(panic: Box<dyn Any + Send + 'static>) -> Response {
+    let message = match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message,
+        (_, Some(message)) => message.as_str(),
+        _ => "",
+    };
+    let location = PANIC_LOCATION.with(|last| last.borrow_mut().take());
+    let fingerprint = fingerprint(location.as_deref(), message);
+    let mut response = StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    response.extensions_mut().insert(Panicked { location, fingerprint });
+    response
+}
+
+// Digest of where the panic happened and its message with each number masked, so the
+// same bug hit with other values groups together
+fn fingerprint(location: Option<&str>, message: &str) -> String {
+    let mut masked = String::with_capacity(message.len());
+    for c in message.chars() {
+        match c.is_ascii_digit() {
+            true if masked.ends_with('#') => {}
+            true => masked.push('#'),
+            false => masked.push(c),
+        }
+    }
+    let digest = Sha256::digest(format!("{}\n{}", location.unwrap_or_default(), masked));
+    digest.iter().take(8).map(|b| format!("{:02x}", b)).collect()
+}
+
+// What a crash report says: where and on which route, never the message, the request or
+// the caller
+#[derive(Serialize)]
+pub struct CrashReport {
+    pub service: &'static str,
+    pub version: &'static str,
+    pub request_id: String,
+    pub method: String,
+    // The route template, e.g. `/download/:file_id`, not the path with its ids
+    pub route: String,
+    pub location: Option<String>,
+    pub fingerprint: String,
+    pub timestamp: u64,
+}
+
+impl CrashReport {
+    pub fn new(request_id: &str, method: &str, route: &str, panicked: Panicked) -> Self {
+        Self {
+            service: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            request_id: request_id.to_string(),
+            method: method.to_string(),
+            route: route.to_string(),
+            location: panicked.location,
+            fingerprint: panicked.fingerprint,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        }
+    }
+}
+
+// POSTs crash reports to `CRASH_REPORT_URL`; without it crashes are only logged and counted
+pub struct CrashReporter {
+    client: Client,
+    url: Option<Url>,
+}
+
+impl CrashReporter {
+    pub fn from_config(config: &AppConfig) -> Result<Self> {
+        let url = config.crash_report_url.as_deref()
+            .map(|url| Url::parse(url).map_err(|e| anyhow!("Invalid CRASH_REPORT_URL: {}", e)))
+            .transpose()?;
+        let client = upstream::client_builder("CRASH_REPORT", Duration::from_secs(10))?
+            .build()
+            .map_err(|e| anyhow!("Failed to create HTTP client for CRASH_REPORT: {}", e))?;
+        Ok(Self { client, url })
+    }
+
+    // Sent in the background, once; the panic is in the log and the metrics either way
+    pub fn spawn(self: &Arc<Self>, report: CrashReport) {
+        let Some(url) = self.url.clone() else { return };
+        let reporter = self.clone();
+        tokio::spawn(async move {
+            match reporter.client.post(url).json(&report).send().await {
+                Ok(response) if response.status().is_success() => info!("Reported the crash of request {}", report.request_id),
+                Ok(response) => warn!("Crash report for request {} was rejected with {}", report.request_id, response.status()),
+                Err(e) => warn!("Crash report for request {} failed: {}", report.request_id, e),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use tower::ServiceExt;
+    use tower_http::catch_panic::CatchPanicLayer;
+
+    #[tokio::test]
+    async fn test_panics_become_marked_responses() {
+        install_hook();
+        let app = Router::new()
+            .route("/boom/:id", get(|axum::extract::Path(id): axum::extract::Path<u32>| async move {
+                if id > 0 {
+                    panic!("file {} is missing", id);
+                }
+                "ok"
+            }))
+            .layer(CatchPanicLayer::custom(panic_response));
+        let request = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let response = app.clone().oneshot(request("/boom/7")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let panicked = response.extensions().get::<Panicked>().cloned().unwrap();
+        assert!(panicked.location.as_deref().is_some_and(|location| location.starts_with("src/crashes.rs:")));
+
+        // The same bug with another value groups together; the message is not kept
+        let again = app.clone().oneshot(request("/boom/42")).await.unwrap();
+        assert_eq!(again.extensions().get::<Panicked>().unwrap().fingerprint, panicked.fingerprint);
+        let report = CrashReport::new("req-1", "GET", "/boom/:id", panicked);
+        assert!(!serde_json::to_string(&report).unwrap().contains("missing"));
+
+        let response = app.oneshot(request("/boom/0")).await.unwrap();
+        assert!(response.extensions().get::<Panicked>().is_none());
+    }
+}
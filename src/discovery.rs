@@ -0,0 +1,219 @@
+use anyhow::{anyhow, Result};
+use reqwest::{Client, Url};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use sentient_redactor_core::{config::AppConfig, upstream};
+
+// Version of the upload envelope protocol, bumped when clients need changes to talk to
+// an instance
+pub const PROTOCOL_VERSION: &str = "1";
+
+const DEFAULT_SERVICE_NAME: &str = "sentient-redactor";
+const DEFAULT_CHECK_INTERVAL: &str = "10s";
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(2);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+// What an instance advertises so gateways can route to the ones able to serve a
+// request: in its Consul registration and on `/ready`
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct ServiceLabels {
+    pub protocol_version: &'static str,
+    pub version: &'static str,
+    // Redaction backends, comma-separated in the order they are tried
+    pub backends: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grpc_port: Option<u16>,
+    // Set on replicas that serve reads only, so writes can be routed elsewhere
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub read_only: bool,
+}
+
+impl ServiceLabels {
+    pub fn new(config: &AppConfig, backends: &str) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            version: env!("CARGO_PKG_VERSION"),
+            backends: backends.to_string(),
+            grpc_port: config.grpc_port,
+            read_only: config.read_only,
+        }
+    }
+
+    // As Consul service meta, whose values are all strings
+    fn meta(&self) -> BTreeMap<&'static str, String> {
+        let mut meta = BTreeMap::from([
+            ("protocol_version", self.protocol_version.to_string()),
+            ("version", self.version.to_string()),
+            ("backends", self.backends.clone()),
+        ]);
+        if let Some(port) = self.grpc_port {
+            meta.insert("grpc_port", port.to_string());
+        }
+        if self.read_only {
+            meta.insert("read_only", "true".to_string());
+        }
+        meta
+    }
+}
+
+// Self-registration with the Consul agent at `CONSUL_HTTP_ADDR`, with an HTTP check on
+// `/ready` so the instance only takes traffic once its key is provisioned
+pub struct ConsulRegistration {
+    client: Client,
+    agent: Url,
+    token: Option<String>,
+    service_id: String,
+    service: Value,
+}
+
+impl ConsulRegistration {
+    // None unless `CONSUL_HTTP_ADDR` is set
+    pub fn from_env(config: &AppConfig, labels: &ServiceLabels) -> Result<Option<Self>> {
+        let Some(agent) = std::env::var("CONSUL_HTTP_ADDR").ok().filter(|agent| !agent.is_empty()) else {
+            return Ok(None);
+        };
+        let agent = match agent.contains("://") {
+            true => agent,
+            false => format!("http://{}", agent),
+        };
+        let agent = Url::parse(&agent).map_err(|e| anyhow!("Invalid CONSUL_HTTP_ADDR: {}", e))?;
+
+        let env = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let name = env("CONSUL_SERVICE_NAME").unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_string());
+        // The agent fills in its own address when none is given
+        let address = env("CONSUL_SERVICE_ADDRESS")
+            .or_else(|| (!config.bind_addr.is_unspecified()).then(|| config.bind_addr.to_string()));
+        let instance = env("HOSTNAME").unwrap_or_else(|| "local".to_string());
+        let service_id = format!("{}-{}-{}", name, instance, config.port);
+        let interval = env("CONSUL_CHECK_INTERVAL").unwrap_or_else(|| DEFAULT_CHECK_INTERVAL.to_string());
+        let tls = config.tls_cert_path.is_some();
+        let service = service_definition(&service_id, &name, address.as_deref(), config.port, tls, &interval, labels);
+
+        let client = upstream::client_builder("CONSUL", Duration::from_secs(10))?
+            .build()
+            .map_err(|e| anyhow!("Failed to create HTTP client for CONSUL: {}", e))?;
+
+        Ok(Some(Self { client, agent, token: env("CONSUL_HTTP_TOKEN"), service_id, service }))
+    }
+
+    // Register in the background, retrying with backoff while the agent is unreachable
+    pub fn spawn(self: &std::sync::Arc<Self>) {
+        let registration = self.clone();
+        tokio::spawn(async move {
+            let mut delay = FIRST_RETRY_DELAY;
+            loop {
+                match registration.call("register", registration.service.clone()).await {
+                    Ok(()) => {
+                        info!("Registered with Consul as {}", registration.service_id);
+                        return;
+                    }
+                    Err(e) => warn!("Consul registration failed: {}; retrying in {} s", e, delay.as_secs()),
+                }
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            }
+        });
+    }
+
+    // On shutdown, so gateways stop routing here before the check goes critical
+    pub async fn deregister(&self) {
+        match self.call(&format!("deregister/{}", self.service_id), Value::Null).await {
+            Ok(()) => info!("Deregistered {} from Consul", self.service_id),
+            Err(e) => warn!("Consul deregistration failed: {}", e),
+        }
+    }
+
+    async fn call(&self, path: &str, body: Value) -> Result<()> {
+        let url = self.agent.join(&format!("/v1/agent/service/{}", path))?;
+        let mut request = self.client.put(url);
+        if !body.is_null() {
+            request = request.json(&body);
+        }
+        if let Some(token) = &self.token {
+            request = request.header("X-Consul-Token", token);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            return Err(anyhow!("Consul agent returned {}: {}", status, response.text().await.unwrap_or_default()));
+        }
+        Ok(())
+    }
+}
+
+// Body of `PUT /v1/agent/service/register`; the `/ready` check uses HTTPS when the
+// instance serves TLS
+fn service_definition(id: &str, name: &str, address: Option<&str>, port: u16, tls: bool, interval: &str, labels: &ServiceLabels) -> Value {
+    let check_host = address.unwrap_or("127.0.0.1");
+    let check_addr = match check_host.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, port).to_string(),
+        Err(_) => format!("{}:{}", check_host, port),
+    };
+    let mut tags = vec![format!("protocol-v{}", labels.protocol_version)];
+    if labels.grpc_port.is_some() {
+        tags.push("grpc".to_string());
+    }
+    if labels.read_only {
+        tags.push("read-only".to_string());
+    }
+
+    let mut service = json!({
+        "ID": id,
+        "Name": name,
+        "Port": port,
+        "Tags": tags,
+        "Meta": labels.meta(),
+        "Check": {
+            "HTTP": format!("{}://{}/ready", if tls { "https" } else { "http" }, check_addr),
+            "Interval": interval,
+            "Timeout": "5s",
+            // Instances that went away without deregistering are dropped eventually
+            "DeregisterCriticalServiceAfter": "10m"
+        }
+    });
+    if let Some(address) = address {
+        service["Address"] = json!(address);
+    }
+    service
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(grpc_port: Option<u16>) -> ServiceLabels {
+        ServiceLabels { protocol_version: PROTOCOL_VERSION, version: "0.1.0", backends: "presidio,regex".to_string(), grpc_port, read_only: false }
+    }
+
+    #[test]
+    fn test_registration_carries_labels_and_a_readiness_check() {
+        let service = service_definition("redactor-a-10003", "redactor", Some("fd00::7"), 10003, false, "10s", &labels(Some(10004)));
+        assert_eq!(service["Address"], "fd00::7");
+        assert_eq!(service["Check"]["HTTP"], "http://[fd00::7]:10003/ready");
+        assert_eq!(service["Tags"], json!(["protocol-v1", "grpc"]));
+        assert_eq!(service["Meta"], json!({
+            "protocol_version": "1",
+            "version": "0.1.0",
+            "backends": "presidio,regex",
+            "grpc_port": "10004"
+        }));
+
+        // Without an address the agent's own is used, and checks stay local
+        let service = service_definition("redactor-a-10003", "redactor", None, 10003, true, "10s", &labels(None));
+        assert!(service.get("Address").is_none());
+        assert_eq!(service["Check"]["HTTP"], "https://127.0.0.1:10003/ready");
+        assert_eq!(service["Tags"], json!(["protocol-v1"]));
+
+        // Replicas are told apart, so writes are routed to other instances
+        let replica = ServiceLabels { read_only: true, ..labels(None) };
+        let service = service_definition("redactor-b-10003", "redactor", None, 10003, false, "10s", &replica);
+        assert_eq!(service["Tags"], json!(["protocol-v1", "read-only"]));
+        assert_eq!(service["Meta"]["read_only"], "true");
+    }
+}
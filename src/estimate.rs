@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use utoipa::ToSchema;
+
+use sentient_redactor_core::operations::UploadProfile;
+
+// Recent uploads kept per pipeline for the fit
+const WINDOW: usize = 200;
+// Used until a pipeline has enough uploads to fit
+const DEFAULT_OVERHEAD_MS: f64 = 50.0;
+const DEFAULT_MS_PER_KIB: f64 = 2.0;
+// Poly1305 tag appended to every ciphertext
+const TAG_BYTES: usize = 16;
+
+#[derive(Deserialize, ToSchema)]
+pub struct EstimateRequest {
+    // Plaintext size, or `encrypted_sample` to derive it from the ciphertext
+    pub size_bytes: Option<usize>,
+    // Base64 ciphertext of the file; only its length is used
+    pub encrypted_sample: Option<String>,
+    pub format: Option<String>,
+    pub redaction_strategy: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Estimate {
+    pub size_bytes: usize,
+    pub pipeline: String,
+    pub estimated_ms: f64,
+    // Quota cost: processing time in whole seconds, at least 1
+    pub compute_seconds: u64,
+    // Uploads the estimate is fitted on; 0 means built-in defaults were used
+    pub samples: usize,
+}
+
+// Processing time of recent uploads per pipeline (the redaction backend, or `template`
+// for extraction), fitted as a fixed overhead plus a cost per byte
+#[derive(Default)]
+pub struct ThroughputStats {
+    samples: Mutex<HashMap<String, VecDeque<(f64, f64)>>>,
+}
+
+impl ThroughputStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, profile: &UploadProfile) {
+        let mut samples = self.samples.lock().unwrap();
+        let window = samples.entry(profile.backend.clone()).or_default();
+        if window.len() == WINDOW {
+            window.pop_front();
+        }
+        window.push_back((profile.plaintext_bytes as f64, profile.total_ms));
+    }
+
+    pub fn estimate(&self, pipeline: &str, size_bytes: usize) -> Estimate {
+        let samples = self.samples.lock().unwrap();
+        let window = samples.get(pipeline);
+        let fit = window.and_then(fit);
+
+        let (overhead_ms, ms_per_byte) = fit.unwrap_or((DEFAULT_OVERHEAD_MS, DEFAULT_MS_PER_KIB / 1024.0));
+        let estimated_ms = overhead_ms + ms_per_byte * size_bytes as f64;
+        Estimate {
+            size_bytes,
+            pipeline: pipeline.to_string(),
+            estimated_ms: (estimated_ms * 10.0).round() / 10.0,
+            compute_seconds: (estimated_ms / 1000.0).ceil().max(1.0) as u64,
+            samples: if fit.is_some() { window.map_or(0, VecDeque::len) } else { 0 },
+        }
+    }
+}
+
+// Plaintext size of a base64 ciphertext, without decoding it
+pub fn sample_plaintext_bytes(encrypted_sample: &str) -> usize {
+    let encoded = encrypted_sample.trim_end_matches('=');
+    (encoded.len() * 3 / 4).saturating_sub(TAG_BYTES)
+}
+
+// Least-squares line through (bytes, ms), with both terms kept non-negative. Needs two
+// uploads of different sizes.
+fn fit(window: &VecDeque<(f64, f64)>) -> Option<(f64, f64)> {
+    if window.len() < 2 {
+        return None;
+    }
+    let n = window.len() as f64;
+    let mean_x = window.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = window.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let variance: f64 = window.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    if variance == 0.0 {
+        return None;
+    }
+
+    let covariance: f64 = window.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+    let slope = (covariance / variance).max(0.0);
+    Some(((mean_y - slope * mean_x).max(0.0), slope))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimates_follow_recorded_throughput() {
+        let stats = ThroughputStats::new();
+        let default = stats.estimate("presidio", 1024 * 1024);
+        assert_eq!((default.samples, default.compute_seconds), (0, 3));
+
+        // 100 ms overhead plus 1 ms per KiB
+        for kib in [10, 100, 1000] {
+            let profile = UploadProfile {
+                backend: "presidio".to_string(),
+                plaintext_bytes: kib * 1024,
+                total_ms: 100.0 + kib as f64,
+                ..UploadProfile::default()
+            };
+            stats.record(&profile);
+        }
+
+        let estimate = stats.estimate("presidio", 2000 * 1024);
+        assert_eq!(estimate.samples, 3);
+        assert!((estimate.estimated_ms - 2100.0).abs() < 1.0, "{}", estimate.estimated_ms);
+        assert_eq!(estimate.compute_seconds, 3);
+        assert_eq!(stats.estimate("template", 10).samples, 0);
+
+        assert_eq!(sample_plaintext_bytes("AAAAAAAAAAAAAAAAAAAAAAAA"), 2);
+    }
+}
@@ -0,0 +1,218 @@
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+use sentient_redactor_core::{
+    caller::Caller,
+    operations::{ErrorKind, OperationError, UploadRequest},
+};
+
+pub const DEFAULT_TTL_SECONDS: u64 = 3600;
+pub const MAX_TTL_SECONDS: u64 = 86400;
+
+// Constraints an orchestrator sets on an upload it hands out to an end client
+#[derive(Clone, Deserialize, ToSchema)]
+pub struct ExpectationRequest {
+    // Largest ciphertext the upload may carry, in bytes
+    pub max_bytes: Option<usize>,
+    // Redaction the upload must use; uploads that leave these out get them
+    pub redaction_strategy: Option<String>,
+    pub entities: Option<Vec<String>>,
+    // Principal allowed to upload; anyone in the orchestrator's tenant when unset
+    pub uploader: Option<String>,
+    pub ttl_seconds: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpectationStatus {
+    Pending,
+    // An upload referencing it is being processed
+    Claimed,
+    Fulfilled,
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct ExpectationView {
+    pub status: ExpectationStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<String>,
+    pub expires_at: u64,
+}
+
+struct Expectation {
+    constraints: ExpectationRequest,
+    creator: Option<String>,
+    tenant: Option<String>,
+    view: ExpectationView,
+}
+
+// Uploads registered ahead of time; each is fulfilled by exactly one upload that
+// references its token and meets its constraints
+pub struct ExpectationStore {
+    expectations: HashMap<String, Expectation>,
+}
+
+impl ExpectationStore {
+    pub fn new() -> Self {
+        Self {
+            expectations: HashMap::new(),
+        }
+    }
+
+    // Register an expected upload for `ttl_seconds`, returning its token and expiry
+    pub fn create(&mut self, creator: &Caller, constraints: ExpectationRequest, ttl_seconds: u64) -> (String, u64) {
+        let now = now();
+        // Expired ones go, except those still held by an upload in progress
+        self.expectations.retain(|_, expectation| expectation.view.expires_at > now || expectation.view.status == ExpectationStatus::Claimed);
+
+        let mut token_bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut token_bytes);
+        let token = token_bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+
+        let expires_at = now + ttl_seconds;
+        self.expectations.insert(token.clone(), Expectation {
+            constraints,
+            creator: creator.principal.clone(),
+            tenant: creator.tenant.clone(),
+            view: ExpectationView { status: ExpectationStatus::Pending, file_id: None, expires_at },
+        });
+
+        (token, expires_at)
+    }
+
+    // The expectation, for the orchestrator that registered it
+    pub fn get(&self, token: &str, caller: &Caller) -> Option<ExpectationView> {
+        self.expectations.get(token)
+            .filter(|expectation| expectation.creator == caller.principal && expectation.tenant == caller.tenant)
+            .map(|expectation| expectation.view.clone())
+    }
+
+    // Check an upload of `size` ciphertext bytes against the expectation and hold it
+    // for that upload, filling in the redaction it requires
+    pub fn claim(&mut self, token: &str, caller: &Caller, upload: &mut UploadRequest, size: usize) -> Result<(), OperationError> {
+        let expectation = self.expectations.get_mut(token).ok_or_else(|| {
+            OperationError::new(ErrorKind::NotFound, "Upload expectation not found").with_code("expectation_not_found")
+        })?;
+        let violated = |kind: ErrorKind, code: &'static str, message: String| Err(OperationError::new(kind, message).with_code(code));
+
+        if expectation.view.status != ExpectationStatus::Pending {
+            return violated(ErrorKind::Conflict, "expectation_used", "Upload expectation was already used".to_string());
+        }
+        if expectation.view.expires_at <= now() {
+            return violated(ErrorKind::Gone, "expectation_expired", "Upload expectation has expired".to_string());
+        }
+        let constraints = &expectation.constraints;
+        let uploader_allowed = constraints.uploader.as_ref().is_none_or(|uploader| caller.principal.as_ref() == Some(uploader));
+        if !uploader_allowed || expectation.tenant.as_ref().is_some_and(|tenant| caller.tenant.as_ref() != Some(tenant)) {
+            return violated(ErrorKind::Forbidden, "expectation_uploader", "Caller may not fulfil this upload expectation".to_string());
+        }
+        if let Some(max_bytes) = constraints.max_bytes.filter(|max_bytes| size > *max_bytes) {
+            return violated(ErrorKind::PayloadTooLarge, "expectation_too_large", format!("Upload expectation allows at most {} bytes", max_bytes));
+        }
+
+        if let Some(strategy) = &constraints.redaction_strategy {
+            match &upload.redaction_strategy {
+                Some(used) if used != strategy => {
+                    return violated(ErrorKind::BadRequest, "expectation_policy", format!("Upload expectation requires the {} strategy", strategy));
+                }
+                Some(_) => {}
+                None => upload.redaction_strategy = Some(strategy.clone()),
+            }
+        }
+        if let Some(entities) = &constraints.entities {
+            match &upload.entities {
+                Some(used) if !entities.iter().all(|entity| used.contains(entity)) => {
+                    return violated(ErrorKind::BadRequest, "expectation_policy", format!("Upload expectation requires redacting {}", entities.join(", ")));
+                }
+                Some(_) => {}
+                None => upload.entities = Some(entities.clone()),
+            }
+        }
+
+        expectation.view.status = ExpectationStatus::Claimed;
+        Ok(())
+    }
+
+    // Record the outcome of a claimed upload; a failed one leaves the expectation open
+    pub fn settle(&mut self, token: &str, file_id: Option<&str>) {
+        if let Some(expectation) = self.expectations.get_mut(token) {
+            expectation.view.status = match file_id {
+                Some(_) => ExpectationStatus::Fulfilled,
+                None => ExpectationStatus::Pending,
+            };
+            expectation.view.file_id = file_id.map(str::to_string);
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caller(principal: &str, tenant: &str) -> Caller {
+        Caller { principal: Some(principal.to_string()), tenant: Some(tenant.to_string()), scopes: None }
+    }
+
+    fn upload(strategy: Option<&str>) -> UploadRequest {
+        serde_json::from_value(serde_json::json!({ "redaction_strategy": strategy })).unwrap()
+    }
+
+    fn constraints() -> ExpectationRequest {
+        ExpectationRequest {
+            max_bytes: Some(1024),
+            redaction_strategy: Some("mask".to_string()),
+            entities: Some(vec!["EMAIL_ADDRESS".to_string()]),
+            uploader: Some("client".to_string()),
+            ttl_seconds: None,
+        }
+    }
+
+    #[test]
+    fn test_expectations_are_fulfilled_once() {
+        let mut store = ExpectationStore::new();
+        let orchestrator = caller("platform", "acme");
+        let (token, _) = store.create(&orchestrator, constraints(), 60);
+
+        let mut request = upload(None);
+        store.claim(&token, &caller("client", "acme"), &mut request, 512).unwrap();
+        assert_eq!(request.redaction_strategy.as_deref(), Some("mask"));
+        assert_eq!(request.entities, Some(vec!["EMAIL_ADDRESS".to_string()]));
+        assert_eq!(store.claim(&token, &caller("client", "acme"), &mut upload(None), 512).unwrap_err().code, Some("expectation_used"));
+
+        // A failed upload can be retried; a stored one uses the expectation up
+        store.settle(&token, None);
+        store.claim(&token, &caller("client", "acme"), &mut upload(None), 512).unwrap();
+        store.settle(&token, Some("f1"));
+        let view = store.get(&token, &orchestrator).unwrap();
+        assert_eq!((view.status, view.file_id.as_deref()), (ExpectationStatus::Fulfilled, Some("f1")));
+        assert!(store.get(&token, &caller("client", "acme")).is_none());
+    }
+
+    #[test]
+    fn test_uploads_must_meet_the_constraints() {
+        let mut store = ExpectationStore::new();
+        let (token, _) = store.create(&caller("platform", "acme"), constraints(), 60);
+        let code = |store: &mut ExpectationStore, caller: &Caller, strategy: Option<&str>, size: usize| {
+            store.claim(&token, caller, &mut upload(strategy), size).unwrap_err().code
+        };
+
+        assert_eq!(code(&mut store, &caller("intruder", "acme"), None, 10), Some("expectation_uploader"));
+        assert_eq!(code(&mut store, &caller("client", "other"), None, 10), Some("expectation_uploader"));
+        assert_eq!(code(&mut store, &caller("client", "acme"), None, 2048), Some("expectation_too_large"));
+        assert_eq!(code(&mut store, &caller("client", "acme"), Some("replace"), 10), Some("expectation_policy"));
+
+        let (expired, _) = store.create(&caller("platform", "acme"), constraints(), 0);
+        let error = store.claim(&expired, &caller("client", "acme"), &mut upload(None), 10).unwrap_err();
+        assert_eq!(error.code, Some("expectation_expired"));
+    }
+}
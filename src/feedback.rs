@@ -0,0 +1,242 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedbackKind {
+    FalsePositive,
+    FalseNegative,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct FeedbackRequest {
+    pub recognizer: String,
+    pub kind: FeedbackKind,
+    pub value: Option<String>,
+    pub comment: Option<String>,
+    pub reviewer: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct FeedbackEntry {
+    // Of the file; summaries only aggregate the caller's own tenant
+    #[serde(skip)]
+    pub tenant: Option<String>,
+    pub file_id: String,
+    pub recognizer: String,
+    pub kind: FeedbackKind,
+    pub value: Option<String>,
+    pub comment: Option<String>,
+    pub reviewer: Option<String>,
+    pub created_at: u64,
+}
+
+#[derive(Default, Serialize, ToSchema)]
+pub struct RecognizerFeedback {
+    pub recognizer: String,
+    pub false_positives: usize,
+    pub false_negatives: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AllowlistSuggestion {
+    pub recognizer: String,
+    pub value: String,
+    pub occurrences: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct FeedbackSummary {
+    pub total: usize,
+    pub recognizers: Vec<RecognizerFeedback>,
+    pub allowlist_suggestions: Vec<AllowlistSuggestion>,
+}
+
+// An entry as written to the feedback file, with the tenant responses leave out
+#[derive(Serialize, Deserialize)]
+struct StoredEntry {
+    tenant: Option<String>,
+    #[serde(flatten)]
+    entry: FeedbackEntry,
+}
+
+// Feedback entries, kept in memory and mirrored to a JSONL file when a path is given
+pub struct FeedbackStore {
+    entries: Vec<FeedbackEntry>,
+    sink: Option<File>,
+}
+
+impl FeedbackStore {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            sink: None,
+        }
+    }
+
+    // Entries already in the file at `path` are loaded, and new ones appended to it
+    pub fn open(path: Option<&str>) -> Result<Self> {
+        let mut store = Self::new();
+        let Some(path) = path else {
+            return Ok(store);
+        };
+
+        match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+                    let stored: StoredEntry = serde_json::from_str(line)
+                        .map_err(|e| anyhow!("Invalid feedback entry in {}: {}", path, e))?;
+                    store.entries.push(FeedbackEntry { tenant: stored.tenant, ..stored.entry });
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(anyhow!("Failed to read feedback from {}: {}", path, e)),
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| anyhow!("Failed to open feedback file {}: {}", path, e))?;
+        info!("Loaded {} feedback entries from {}", store.entries.len(), path);
+        store.sink = Some(file);
+        Ok(store)
+    }
+
+    pub fn record(&mut self, tenant: Option<&str>, file_id: &str, request: FeedbackRequest) -> FeedbackEntry {
+        // A missed entity's value is itself PII, so only false positives keep the flagged text
+        let value = match request.kind {
+            FeedbackKind::FalsePositive => request.value.map(|v| v.trim().to_string()),
+            FeedbackKind::FalseNegative => None,
+        };
+
+        let entry = FeedbackEntry {
+            tenant: tenant.map(str::to_string),
+            file_id: file_id.to_string(),
+            recognizer: request.recognizer,
+            kind: request.kind,
+            value: value.filter(|v| !v.is_empty()),
+            comment: request.comment,
+            reviewer: request.reviewer,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+
+        if let Some(sink) = &mut self.sink {
+            let stored = StoredEntry { tenant: entry.tenant.clone(), entry: entry.clone() };
+            let line = serde_json::to_string(&stored).unwrap_or_default();
+            if let Err(e) = writeln!(sink, "{}", line) {
+                warn!("Failed to write feedback entry: {}", e);
+            }
+        }
+        self.entries.push(entry.clone());
+        entry
+    }
+
+    // Aggregate the feedback on `tenant`'s files per recognizer, suggesting allowlist
+    // entries for values reported as false positives at least `min_occurrences` times
+    pub fn summary(&self, tenant: Option<&str>, min_occurrences: usize) -> FeedbackSummary {
+        let mut recognizers: BTreeMap<&str, RecognizerFeedback> = BTreeMap::new();
+        let mut false_positive_values: HashMap<(&str, &str), usize> = HashMap::new();
+        let entries: Vec<&FeedbackEntry> = self.entries.iter().filter(|entry| entry.tenant.as_deref() == tenant).collect();
+
+        for entry in &entries {
+            let stats = recognizers.entry(&entry.recognizer).or_insert_with(|| RecognizerFeedback {
+                recognizer: entry.recognizer.clone(),
+                ..Default::default()
+            });
+
+            match entry.kind {
+                FeedbackKind::FalsePositive => stats.false_positives += 1,
+                FeedbackKind::FalseNegative => stats.false_negatives += 1,
+            }
+
+            if let Some(value) = &entry.value {
+                *false_positive_values.entry((&entry.recognizer, value)).or_default() += 1;
+            }
+        }
+
+        let mut allowlist_suggestions: Vec<AllowlistSuggestion> = false_positive_values
+            .into_iter()
+            .filter(|(_, occurrences)| *occurrences >= min_occurrences)
+            .map(|((recognizer, value), occurrences)| AllowlistSuggestion {
+                recognizer: recognizer.to_string(),
+                value: value.to_string(),
+                occurrences,
+            })
+            .collect();
+        allowlist_suggestions.sort_by(|a, b| {
+            b.occurrences.cmp(&a.occurrences).then_with(|| a.value.cmp(&b.value))
+        });
+
+        FeedbackSummary {
+            total: entries.len(),
+            recognizers: recognizers.into_values().collect(),
+            allowlist_suggestions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(recognizer: &str, kind: FeedbackKind, value: Option<&str>) -> FeedbackRequest {
+        FeedbackRequest {
+            recognizer: recognizer.to_string(),
+            kind,
+            value: value.map(str::to_string),
+            comment: None,
+            reviewer: None,
+        }
+    }
+
+    #[test]
+    fn test_summary_suggests_repeated_false_positives() {
+        let mut store = FeedbackStore::new();
+        for _ in 0..3 {
+            store.record(None, "f1", request("PERSON", FeedbackKind::FalsePositive, Some("Jordan")));
+        }
+        store.record(None, "f2", request("PERSON", FeedbackKind::FalsePositive, Some("Paris")));
+        store.record(None, "f2", request("US_SSN", FeedbackKind::FalseNegative, None));
+
+        let summary = store.summary(None, 3);
+        assert_eq!(summary.total, 5);
+        assert_eq!(summary.recognizers.len(), 2);
+        assert_eq!(summary.recognizers[0].recognizer, "PERSON");
+        assert_eq!(summary.recognizers[0].false_positives, 4);
+        assert_eq!(summary.recognizers[1].false_negatives, 1);
+        assert_eq!(summary.allowlist_suggestions.len(), 1);
+        assert_eq!(summary.allowlist_suggestions[0].value, "Jordan");
+    }
+
+    #[test]
+    fn test_entries_survive_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("feedback.jsonl");
+        let path = path.to_str();
+
+        let mut store = FeedbackStore::open(path).unwrap();
+        store.record(Some("acme"), "f1", request("LOCATION", FeedbackKind::FalsePositive, Some("Chicago")));
+        drop(store);
+
+        let store = FeedbackStore::open(path).unwrap();
+        assert_eq!(store.summary(Some("acme"), 1).allowlist_suggestions[0].value, "Chicago");
+        assert_eq!(store.summary(None, 1).total, 0);
+    }
+
+    #[test]
+    fn test_false_negative_value_is_not_retained() {
+        let mut store = FeedbackStore::new();
+        let entry = store.record(None, "f1", request("EMAIL_ADDRESS", FeedbackKind::FalseNegative, Some("a@b.c")));
+        assert!(entry.value.is_none());
+    }
+}
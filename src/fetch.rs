@@ -0,0 +1,138 @@
+use anyhow::{anyhow, Result};
+use reqwest::{redirect::Policy, Client, Url};
+use std::fmt;
+use std::time::Duration;
+
+use sentient_redactor_core::upstream;
+
+const DEFAULT_MAX_BYTES: usize = 100 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum FetchError {
+    // Scheme or host not on the allowlist
+    NotAllowed(String),
+    TooLarge(usize),
+    Upstream(String),
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::NotAllowed(reason) => write!(f, "{}", reason),
+            FetchError::TooLarge(limit) => write!(f, "Blob exceeds the {} byte limit", limit),
+            FetchError::Upstream(reason) => write!(f, "Fetching blob failed: {}", reason),
+        }
+    }
+}
+
+// Fetches already-encrypted blobs from client-supplied (typically pre-signed) URLs.
+// Only HTTPS URLs on allowlisted hosts are fetched, redirects are not followed, and the
+// body is capped at `max_bytes` while streaming.
+pub struct BlobFetcher {
+    client: Client,
+    allowed_hosts: Vec<String>,
+    max_bytes: usize,
+}
+
+impl BlobFetcher {
+    pub fn from_env() -> Result<Self> {
+        let allowed_hosts = allowed_hosts("UPLOAD_URL_ALLOWED_HOSTS");
+
+        let max_bytes = match std::env::var("UPLOAD_URL_MAX_BYTES") {
+            Ok(value) => value.parse()
+                .map_err(|e| anyhow!("Invalid UPLOAD_URL_MAX_BYTES: {}", e))?,
+            Err(_) => DEFAULT_MAX_BYTES,
+        };
+
+        let client = upstream::client_builder("UPLOAD_URL", Duration::from_secs(120))?
+            .redirect(Policy::none())
+            .build()
+            .map_err(|e| anyhow!("Failed to create HTTP client for UPLOAD_URL: {}", e))?;
+
+        Ok(Self {
+            client,
+            allowed_hosts,
+            max_bytes,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.allowed_hosts.is_empty()
+    }
+
+    pub async fn fetch(&self, url: &str) -> Result<Vec<u8>, FetchError> {
+        let url = self.check_url(url)?;
+
+        let mut response = self.client.get(url).send().await
+            .map_err(|e| FetchError::Upstream(e.to_string()))?;
+        if !response.status().is_success() {
+            return Err(FetchError::Upstream(format!("source returned {}", response.status())));
+        }
+        if response.content_length().is_some_and(|length| length > self.max_bytes as u64) {
+            return Err(FetchError::TooLarge(self.max_bytes));
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| FetchError::Upstream(e.to_string()))? {
+            if body.len() + chunk.len() > self.max_bytes {
+                return Err(FetchError::TooLarge(self.max_bytes));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+
+    fn check_url(&self, url: &str) -> Result<Url, FetchError> {
+        check_allowed_url(url, &self.allowed_hosts).map_err(FetchError::NotAllowed)
+    }
+}
+
+// Comma-separated hosts in the environment variable `name`
+pub fn allowed_hosts(name: &str) -> Vec<String> {
+    std::env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(|host| host.trim().to_ascii_lowercase())
+        .filter(|host| !host.is_empty())
+        .collect()
+}
+
+// `url` if it is https on an allowed host. Entries match a host exactly, or any
+// subdomain when written as `*.example.com`.
+pub fn check_allowed_url(url: &str, allowed_hosts: &[String]) -> Result<Url, String> {
+    let url = Url::parse(url).map_err(|e| format!("Invalid URL: {}", e))?;
+    if url.scheme() != "https" {
+        return Err("Only https URLs are allowed".to_string());
+    }
+
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    let allowed = allowed_hosts.iter().any(|allowed| match allowed.strip_prefix("*.") {
+        Some(domain) => host.ends_with(&format!(".{}", domain)),
+        None => host == *allowed,
+    });
+    if !allowed {
+        return Err(format!("Host {} is not allowed", host));
+    }
+
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_allowlist() {
+        let fetcher = BlobFetcher {
+            client: Client::new(),
+            allowed_hosts: vec!["uploads.example.com".to_string(), "*.s3.amazonaws.com".to_string()],
+            max_bytes: DEFAULT_MAX_BYTES,
+        };
+
+        assert!(fetcher.check_url("https://uploads.example.com/blob?sig=1").is_ok());
+        assert!(fetcher.check_url("https://bucket.s3.amazonaws.com/key").is_ok());
+        assert!(fetcher.check_url("http://uploads.example.com/blob").is_err());
+        assert!(fetcher.check_url("https://s3.amazonaws.com.evil.test/key").is_err());
+        assert!(fetcher.check_url("https://169.254.169.254/latest").is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+use sentient_redactor_core::caller::Caller;
+
+const DEFAULT_RELOAD_SECONDS: u64 = 10;
+
+// Flags reserved for subsystems under development, reported even when unconfigured
+pub const EXPERIMENTAL_FLAGS: &[&str] = &["llm_backend", "pq_crypto", "image_pipeline"];
+
+// How far a flag is rolled out. It is on for a caller when it is enabled for everyone,
+// when the caller's tenant is listed, or when the caller falls within `percentage`.
+#[derive(Clone, Default, Deserialize)]
+pub struct FlagRule {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub tenants: Vec<String>,
+    #[serde(default)]
+    pub percentage: u8,
+}
+
+impl FlagRule {
+    fn is_enabled(&self, flag: &str, caller: &Caller) -> bool {
+        if self.enabled || caller.tenant.as_ref().is_some_and(|tenant| self.tenants.contains(tenant)) {
+            return true;
+        }
+        // Bucketed by tenant, or principal when there is none, so a caller sees a
+        // stable answer and each flag rolls out to a different slice
+        let Some(subject) = caller.tenant.as_ref().or(caller.principal.as_ref()) else {
+            return false;
+        };
+        let digest = Sha256::digest(format!("{}:{}", flag, subject).as_bytes());
+        u16::from_be_bytes([digest[0], digest[1]]) % 100 < u16::from(self.percentage)
+    }
+
+    // Share of callers the flag is on for, ignoring tenant allowlists
+    pub fn rollout_percent(&self) -> u8 {
+        if self.enabled { 100 } else { self.percentage }
+    }
+}
+
+// Runtime switches for experimental subsystems, read from the JSON file at
+// `FEATURE_FLAGS_PATH` ({ "<flag>": { "enabled", "tenants", "percentage" } }) and
+// re-read whenever it changes. Unknown flags are off.
+pub struct FeatureFlags {
+    path: Option<PathBuf>,
+    flags: RwLock<HashMap<String, FlagRule>>,
+    modified: RwLock<Option<SystemTime>>,
+}
+
+impl FeatureFlags {
+    pub fn from_env() -> Result<Self> {
+        let path = std::env::var("FEATURE_FLAGS_PATH").ok().map(PathBuf::from);
+        let flags = Self { path, flags: RwLock::default(), modified: RwLock::default() };
+        if flags.path.is_some() {
+            flags.reload()?;
+        }
+        Ok(flags)
+    }
+
+    pub fn is_enabled(&self, flag: &str, caller: &Caller) -> bool {
+        self.flags.read().unwrap()
+            .get(flag)
+            .is_some_and(|rule| rule.is_enabled(flag, caller))
+    }
+
+    // State of every configured or reserved flag for this caller
+    pub fn evaluate(&self, caller: &Caller) -> BTreeMap<String, bool> {
+        let configured: Vec<String> = self.flags.read().unwrap().keys().cloned().collect();
+        EXPERIMENTAL_FLAGS.iter()
+            .map(|flag| flag.to_string())
+            .chain(configured)
+            .map(|flag| {
+                let enabled = self.is_enabled(&flag, caller);
+                (flag, enabled)
+            })
+            .collect()
+    }
+
+    pub fn rollouts(&self) -> BTreeMap<String, u8> {
+        self.flags.read().unwrap()
+            .iter()
+            .map(|(flag, rule)| (flag.clone(), rule.rollout_percent()))
+            .collect()
+    }
+
+    // Re-read the file if it changed since the last load
+    pub fn reload(&self) -> Result<bool> {
+        let Some(path) = &self.path else {
+            return Ok(false);
+        };
+        let modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| anyhow!("Failed to stat feature flags at {}: {}", path.display(), e))?;
+        if *self.modified.read().unwrap() == Some(modified) {
+            return Ok(false);
+        }
+
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read feature flags from {}: {}", path.display(), e))?;
+        let flags: HashMap<String, FlagRule> = serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("Invalid feature flags file: {}", e))?;
+        if let Some((flag, _)) = flags.iter().find(|(_, rule)| rule.percentage > 100) {
+            return Err(anyhow!("Feature flag {} has a percentage above 100", flag));
+        }
+
+        info!("Loaded {} feature flag(s)", flags.len());
+        *self.flags.write().unwrap() = flags;
+        *self.modified.write().unwrap() = Some(modified);
+        Ok(true)
+    }
+
+    // Poll the file every `FEATURE_FLAGS_RELOAD_SECONDS`. A file that fails to load
+    // leaves the previous flags in place.
+    pub fn spawn_reload(self: &Arc<Self>) {
+        if self.path.is_none() {
+            return;
+        }
+        let interval = std::env::var("FEATURE_FLAGS_RELOAD_SECONDS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .filter(|seconds| *seconds > 0)
+            .unwrap_or(DEFAULT_RELOAD_SECONDS);
+
+        let flags = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+            loop {
+                ticker.tick().await;
+                if let Err(e) = flags.reload() {
+                    warn!("Keeping previous feature flags: {}", e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caller(principal: &str, tenant: Option<&str>) -> Caller {
+        Caller { principal: Some(principal.to_string()), tenant: tenant.map(str::to_string), scopes: None }
+    }
+
+    #[test]
+    fn test_flags_roll_out_by_tenant_and_percentage_and_reload() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), r#"{ "pq_crypto": { "tenants": ["acme"] }, "llm_backend": { "percentage": 50 } }"#).unwrap();
+        let flags = FeatureFlags { path: Some(file.path().to_path_buf()), flags: RwLock::default(), modified: RwLock::default() };
+        flags.reload().unwrap();
+
+        assert!(flags.is_enabled("pq_crypto", &caller("alice", Some("acme"))));
+        assert!(!flags.is_enabled("pq_crypto", &caller("alice", Some("globex"))));
+        assert!(!flags.is_enabled("image_pipeline", &caller("alice", Some("acme"))));
+        assert_eq!(flags.evaluate(&caller("alice", Some("acme"))).len(), 3);
+
+        let on = (0..1000).filter(|i| flags.is_enabled("llm_backend", &caller(&format!("user-{}", i), None))).count();
+        assert!((400..600).contains(&on), "{} of 1000 callers in a 50% rollout", on);
+        assert!(!flags.is_enabled("llm_backend", &Caller::default()));
+
+        std::fs::write(file.path(), r#"{ "pq_crypto": { "enabled": true }, "broken": { "percentage": 101 } }"#).unwrap();
+        touch(file.path(), 1);
+        assert!(flags.reload().is_err());
+        assert_eq!(flags.rollouts()["llm_backend"], 50);
+
+        std::fs::write(file.path(), r#"{ "pq_crypto": { "enabled": true } }"#).unwrap();
+        touch(file.path(), 2);
+        assert!(flags.reload().unwrap());
+        assert!(flags.is_enabled("pq_crypto", &caller("bob", Some("globex"))));
+        assert_eq!(flags.rollouts().len(), 1);
+    }
+
+    // Some filesystems keep coarse mtimes; make sure each write registers as a change
+    fn touch(path: &std::path::Path, seconds_ahead: u64) {
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(seconds_ahead)).unwrap();
+    }
+}
@@ -0,0 +1,400 @@
+use axum::http::{Method, Uri};
+use futures_util::{stream, Stream, StreamExt};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio_rustls::rustls::ServerConfig;
+use tonic::{metadata::{MetadataMap, MetadataValue}, Request, Response, Status, Streaming};
+use tracing::{info, warn};
+
+use sentient_redactor_core::{
+    auth::{AuthChain, AuthRequest},
+    caller::Caller,
+    operations::{self, ErrorKind, OperationError, UploadRequest},
+    views::DownloadFormat,
+};
+
+use super::{check_downloads_allowed, check_processing_window, fetch_file, handshake_response, key_unavailable, output_signature, ratelimit, replica::ReadOnlyMode, run_upload, tls, upload_context, AppState};
+use redactor_server::{Redactor, RedactorServer};
+
+include!(concat!(env!("OUT_DIR"), "/redactor.Redactor.rs"));
+
+// Downloads are streamed in pieces of this size
+const DOWNLOAD_CHUNK_BYTES: usize = 64 * 1024;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct HandshakeRequest {
+    // Client challenge bound into the attestation report
+    #[prost(string, tag = "1")]
+    pub nonce: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct HandshakeReply {
+    #[prost(string, tag = "1")]
+    pub public_key: String,
+    #[prost(string, tag = "2")]
+    pub kid: String,
+    #[prost(string, tag = "3")]
+    pub algorithm: String,
+    // The attestation evidence as JSON, empty when attestation is disabled
+    #[prost(string, tag = "4")]
+    pub attestation: String,
+    #[prost(string, repeated, tag = "5")]
+    pub ciphers: Vec<String>,
+    #[prost(string, tag = "6")]
+    pub signing_key: String,
+    #[prost(string, tag = "7")]
+    pub signing_key_signature: String,
+}
+
+// The first message carries `metadata`, the JSON body of `POST /upload` without
+// `encrypted_data`; the ciphertext follows in `data` across any number of messages
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct UploadChunk {
+    #[prost(string, tag = "1")]
+    pub metadata: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct UploadReply {
+    #[prost(string, tag = "1")]
+    pub file_id: String,
+    #[prost(string, tag = "2")]
+    pub filename: String,
+    #[prost(string, tag = "3")]
+    pub message: String,
+    // The whole upload response, as `POST /upload` returns it
+    #[prost(string, tag = "4")]
+    pub response: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DownloadRequest {
+    #[prost(string, tag = "1")]
+    pub file_id: String,
+    // `txt` when empty
+    #[prost(string, tag = "2")]
+    pub format: String,
+    #[prost(bool, tag = "3")]
+    pub encrypted: bool,
+    #[prost(string, optional, tag = "4")]
+    pub encrypted_session_key: Option<String>,
+}
+
+// `file_name` and `content_type` are set on the first chunk only
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DownloadChunk {
+    #[prost(string, tag = "1")]
+    pub file_name: String,
+    #[prost(string, tag = "2")]
+    pub content_type: String,
+    #[prost(bytes = "vec", tag = "3")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AnalyzeRequest {
+    // As for `UploadChunk`
+    #[prost(string, tag = "1")]
+    pub metadata: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub data: Vec<u8>,
+    #[prost(bool, tag = "3")]
+    pub mask_snippets: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AnalyzeReply {
+    #[prost(string, tag = "1")]
+    pub backend: String,
+    #[prost(btree_map = "string, uint64", tag = "2")]
+    pub entities: BTreeMap<String, u64>,
+    #[prost(message, repeated, tag = "3")]
+    pub detections: Vec<Detection>,
+    #[prost(bool, tag = "4")]
+    pub masked: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Detection {
+    #[prost(string, tag = "1")]
+    pub entity_type: String,
+    #[prost(uint64, tag = "2")]
+    pub start: u64,
+    #[prost(uint64, tag = "3")]
+    pub end: u64,
+    #[prost(double, tag = "4")]
+    pub score: f64,
+    #[prost(string, tag = "5")]
+    pub snippet: String,
+}
+
+// The RPCs share the HTTP routes' state, so both see the same keys, sessions and files
+struct GrpcService {
+    state: AppState,
+    auth_chain: Arc<AuthChain>,
+    max_upload_bytes: usize,
+}
+
+// Serve the gRPC interface on `addr` until the process exits, over TLS when configured
+pub async fn serve(
+    addr: SocketAddr,
+    state: AppState,
+    auth_chain: Arc<AuthChain>,
+    max_upload_bytes: usize,
+    tls: Option<Arc<ServerConfig>>,
+    shutdown: impl Future<Output = ()>,
+) {
+    let service = GrpcService { state, auth_chain, max_upload_bytes };
+    // Analyze sends its ciphertext in one message, so it gets the upload limit
+    let server = RedactorServer::new(service).max_decoding_message_size(max_upload_bytes.max(4 * 1024 * 1024));
+
+    let router = tonic::transport::Server::builder().add_service(server);
+    info!("gRPC interface listening on {}{}", addr, if tls.is_some() { " with TLS" } else { "" });
+    let result = match tls {
+        Some(config) => match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => router.serve_with_incoming_shutdown(tls::incoming(listener, config), shutdown).await,
+            Err(e) => {
+                warn!("Failed to bind the gRPC interface to {}: {}", addr, e);
+                return;
+            }
+        },
+        None => router.serve_with_shutdown(addr, shutdown).await,
+    };
+    if let Err(e) = result {
+        warn!("gRPC server stopped: {}", e);
+    }
+}
+
+impl GrpcService {
+    // The caller, resolved through the same auth chain as HTTP requests. Body-signing
+    // providers see no body, so HMAC-signed requests are rejected.
+    // The authenticated caller, once it is within its rate limit
+    async fn caller(&self, rpc: &str, metadata: &MetadataMap, remote: Option<SocketAddr>) -> Result<Caller, Status> {
+        let headers = metadata.clone().into_headers();
+        let uri = Uri::try_from(format!("/redactor.Redactor/{}", rpc)).map_err(|e| Status::internal(e.to_string()))?;
+        let auth_request = AuthRequest { method: &Method::POST, uri: &uri, headers: &headers, body_sha256: None };
+        let caller = self.auth_chain.authenticate(&auth_request).await.map_err(Status::unauthenticated)?;
+        let client = ratelimit::client_key(&caller, remote.map(|addr| addr.ip()));
+        self.state.rate_limiter.check(&client).map_err(|e| status(e.error))?;
+        Ok(caller)
+    }
+}
+
+#[tonic::async_trait]
+impl Redactor for GrpcService {
+    async fn handshake(&self, request: Request<HandshakeRequest>) -> Result<Response<HandshakeReply>, Status> {
+        let nonce = Some(request.get_ref().nonce.as_str()).filter(|nonce| !nonce.is_empty());
+        let response = handshake_response(&self.state, nonce, None).await.map_err(status)?;
+        let attestation = match &response.attestation {
+            Some(evidence) => serde_json::to_string(evidence).map_err(|e| Status::internal(e.to_string()))?,
+            None => String::new(),
+        };
+
+        Ok(Response::new(HandshakeReply {
+            public_key: response.public_key,
+            kid: response.kid,
+            algorithm: response.algorithm.to_string(),
+            attestation,
+            ciphers: response.ciphers.iter().map(|cipher| cipher.to_string()).collect(),
+            signing_key: response.signing_key,
+            signing_key_signature: response.signing_key_signature,
+        }))
+    }
+
+    async fn upload(&self, request: Request<Streaming<UploadChunk>>) -> Result<Response<UploadReply>, Status> {
+        if let Some(maintenance) = self.state.maintenance.active() {
+            return Err(Status::unavailable(maintenance.message.unwrap_or_else(|| "Under maintenance".to_string())));
+        }
+        if self.state.read_only.is_some() {
+            return Err(status(ReadOnlyMode::refused()));
+        }
+        let caller = self.caller("Upload", request.metadata(), request.remote_addr()).await?;
+        check_processing_window(&self.state, &caller).map_err(status)?;
+        let _permit = self.state.rate_limiter.pipeline().map_err(|e| status(e.error))?;
+        let upload = read_upload(request.into_inner(), self.max_upload_bytes).await?;
+
+        let crypto_service = self.state.key_provisioner.get().ok_or_else(|| status(key_unavailable()))?;
+        let response = run_upload(&self.state, crypto_service, &caller, upload).await.map_err(status)?;
+        let body = serde_json::to_string(&response).map_err(|e| Status::internal(e.to_string()))?;
+
+        Ok(Response::new(UploadReply {
+            file_id: response.file_id,
+            filename: response.filename,
+            message: response.message,
+            response: body,
+        }))
+    }
+
+    type DownloadStream = Pin<Box<dyn Stream<Item = Result<DownloadChunk, Status>> + Send>>;
+
+    async fn download(&self, request: Request<DownloadRequest>) -> Result<Response<Self::DownloadStream>, Status> {
+        let caller = self.caller("Download", request.metadata(), request.remote_addr()).await?;
+        let DownloadRequest { file_id, format, encrypted, encrypted_session_key } = request.into_inner();
+        check_downloads_allowed(&self.state, &caller, Some(&file_id), "grpc").await.map_err(status)?;
+        let format = match format.as_str() {
+            "" => DownloadFormat::Txt,
+            format => serde_json::from_value(serde_json::Value::from(format))
+                .map_err(|_| Status::invalid_argument(format!("Unknown download format: {}", format)))?,
+        };
+
+        let file = fetch_file(&self.state, &caller, &file_id, format, "grpc").await.map_err(status)?;
+        let relay = file.relay.clone();
+        let signature = output_signature(&self.state, &file_id, file.content.as_bytes());
+        let (file_name, content_type, content) = if encrypted {
+            let crypto_service = self.state.key_provisioner.get().ok_or_else(|| status(key_unavailable()))?;
+            let file_name = file.file_name.clone();
+            let download = operations::encrypt_download(crypto_service, &file_id, file, encrypted_session_key.as_deref())
+                .map_err(status)?;
+            let body = serde_json::to_vec(&download).map_err(|e| Status::internal(e.to_string()))?;
+            (file_name, "application/json", body)
+        } else {
+            (file.file_name, format.content_type(), file.content.into_bytes())
+        };
+
+        let mut chunks: Vec<DownloadChunk> = content
+            .chunks(DOWNLOAD_CHUNK_BYTES)
+            .map(|data| DownloadChunk { data: data.to_vec(), ..Default::default() })
+            .collect();
+        if chunks.is_empty() {
+            chunks.push(DownloadChunk::default());
+        }
+        chunks[0].file_name = file_name;
+        chunks[0].content_type = content_type.to_string();
+
+        let mut response = Response::new(Box::pin(stream::iter(chunks.into_iter().map(Ok))) as Self::DownloadStream);
+        if let Some(signature) = signature.and_then(|signature| signature.header().parse().ok()) {
+            response.metadata_mut().insert("x-signature", signature);
+        }
+        if let Some(relay) = relay {
+            if let (Ok(relay_id), Ok(client_id)) = (relay.relay_id.parse(), relay.client_id.parse()) {
+                response.metadata_mut().insert("x-relay-id", relay_id);
+                response.metadata_mut().insert("x-origin-client-id", client_id);
+            }
+        }
+        Ok(response)
+    }
+
+    async fn analyze(&self, request: Request<AnalyzeRequest>) -> Result<Response<AnalyzeReply>, Status> {
+        if self.state.read_only.is_some() {
+            return Err(status(ReadOnlyMode::refused()));
+        }
+        let caller = self.caller("Analyze", request.metadata(), request.remote_addr()).await?;
+        check_processing_window(&self.state, &caller).map_err(status)?;
+        let _permit = self.state.rate_limiter.pipeline().map_err(|e| status(e.error))?;
+        let AnalyzeRequest { metadata, data, mask_snippets } = request.into_inner();
+        let upload = upload_request(&metadata, data).map_err(status)?;
+
+        let crypto_service = self.state.key_provisioner.get().ok_or_else(|| status(key_unavailable()))?;
+        let context = upload_context(&self.state, crypto_service);
+        let analysis = operations::analyze_upload(&context, &caller, upload, mask_snippets).await.map_err(status)?;
+
+        Ok(Response::new(AnalyzeReply {
+            backend: analysis.backend,
+            entities: analysis.entities.into_iter().map(|(entity, count)| (entity, count as u64)).collect(),
+            detections: analysis.detections.into_iter().map(|detection| Detection {
+                entity_type: detection.entity_type,
+                start: detection.start as u64,
+                end: detection.end as u64,
+                score: detection.score,
+                snippet: detection.snippet,
+            }).collect(),
+            masked: analysis.masked,
+        }))
+    }
+}
+
+// Collect a streamed upload: metadata first, then ciphertext up to `max_bytes`
+async fn read_upload<S>(mut chunks: S, max_bytes: usize) -> Result<UploadRequest, Status>
+where
+    S: Stream<Item = Result<UploadChunk, Status>> + Unpin,
+{
+    let first = match chunks.next().await {
+        Some(first) => first?,
+        None => return Err(Status::invalid_argument("The upload stream is empty")),
+    };
+    let (mut ciphertext, mut data) = (Vec::new(), first.data);
+    loop {
+        if ciphertext.len() + data.len() > max_bytes {
+            return Err(Status::resource_exhausted(format!("The upload is over {} bytes", max_bytes)));
+        }
+        ciphertext.extend_from_slice(&data);
+        let Some(chunk) = chunks.next().await else { break };
+        let chunk = chunk?;
+        if !chunk.metadata.is_empty() {
+            return Err(Status::invalid_argument("Only the first upload message may carry metadata"));
+        }
+        data = chunk.data;
+    }
+    upload_request(&first.metadata, ciphertext).map_err(status)
+}
+
+fn upload_request(metadata: &str, ciphertext: Vec<u8>) -> Result<UploadRequest, OperationError> {
+    let bad_request = |error: String| OperationError::new(ErrorKind::BadRequest, error);
+    let mut upload: UploadRequest = serde_json::from_str(metadata)
+        .map_err(|e| bad_request(format!("Invalid upload metadata: {}", e)))?;
+    if !upload.encrypted_data.is_empty() {
+        return Err(bad_request("Send the ciphertext in data, not as encrypted_data".to_string()));
+    }
+    upload.ciphertext = Some(ciphertext);
+    Ok(upload)
+}
+
+// The gRPC status of an operation error, with its code in `x-error-code`
+fn status(e: OperationError) -> Status {
+    let code = match e.kind {
+        ErrorKind::BadRequest | ErrorKind::UnsupportedMediaType | ErrorKind::Unprocessable => tonic::Code::InvalidArgument,
+        ErrorKind::Unauthorized => tonic::Code::Unauthenticated,
+        ErrorKind::Forbidden => tonic::Code::PermissionDenied,
+        ErrorKind::NotFound | ErrorKind::Gone => tonic::Code::NotFound,
+        ErrorKind::Conflict | ErrorKind::UpgradeRequired => tonic::Code::FailedPrecondition,
+        ErrorKind::PayloadTooLarge | ErrorKind::TooManyRequests => tonic::Code::ResourceExhausted,
+        ErrorKind::Internal => tonic::Code::Internal,
+        ErrorKind::MethodNotAllowed => tonic::Code::Unimplemented,
+        ErrorKind::BadGateway | ErrorKind::Unavailable => tonic::Code::Unavailable,
+        ErrorKind::Timeout => tonic::Code::DeadlineExceeded,
+    };
+    let mut status = Status::new(code, e.message);
+    status.metadata_mut().insert("x-error-code", MetadataValue::from_static(e.code.unwrap_or(e.kind.code())));
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks(parts: &[(&str, &[u8])]) -> impl Stream<Item = Result<UploadChunk, Status>> + Unpin {
+        let parts: Vec<_> = parts.iter().map(|(metadata, data)| UploadChunk { metadata: metadata.to_string(), data: data.to_vec() }).collect();
+        stream::iter(parts).map(Ok)
+    }
+
+    async fn rejected(chunks: impl Stream<Item = Result<UploadChunk, Status>> + Unpin) -> tonic::Code {
+        read_upload(chunks, 7).await.err().unwrap().code()
+    }
+
+    #[tokio::test]
+    async fn test_streamed_uploads_are_reassembled_within_the_limit() {
+        let metadata = r#"{"file_name": "notes.txt", "nonce": "AAAAAAAAAAAAAAAA"}"#;
+        let upload = read_upload(chunks(&[(metadata, b"abc"), ("", b"def"), ("", b"g")]), 7).await.unwrap();
+        assert_eq!(upload.file_name.as_deref(), Some("notes.txt"));
+        assert_eq!(upload.ciphertext.as_deref(), Some(&b"abcdefg"[..]));
+
+        assert_eq!(rejected(chunks(&[(metadata, b"abc"), ("", b"defgh")])).await, tonic::Code::ResourceExhausted);
+        assert_eq!(rejected(chunks(&[(metadata, b"abcdefgh")])).await, tonic::Code::ResourceExhausted);
+        assert_eq!(rejected(chunks(&[(metadata, b""), (metadata, b"abc")])).await, tonic::Code::InvalidArgument);
+        assert_eq!(rejected(chunks(&[(r#"{"encrypted_data": "YWJj"}"#, b"")])).await, tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn test_operation_errors_keep_their_code() {
+        let error = status(OperationError::new(ErrorKind::Conflict, "Held for review").with_code("review_required"));
+        assert_eq!(error.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(error.metadata().get("x-error-code").unwrap(), "review_required");
+        assert_eq!(status(key_unavailable()).code(), tonic::Code::Unavailable);
+    }
+}
@@ -0,0 +1,281 @@
+use serde_json::{json, Value};
+use std::future::IntoFuture;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+use sentient_redactor_core::{auth::AuthChain, config::AppConfig, crypto::CryptoService, envelope};
+
+use crate::provisioning::KeyProvisioner;
+use crate::{build_state, router, spawn_background, AppState};
+
+// The whole service on a local port, for end-to-end tests without Presidio: the `mock`
+// redaction backend, memory storage, and a service key provisioned before the first
+// request. Everything else is configured as in production, from `config` and the
+// environment. The server stops when the `TestApp` is dropped.
+pub struct TestApp {
+    pub url: String,
+    pub state: AppState,
+    client: reqwest::Client,
+    shutdown: CancellationToken,
+}
+
+impl TestApp {
+    pub async fn spawn() -> Self {
+        Self::with_config(AppConfig { redaction_backend: "mock".to_string(), ..AppConfig::default() }).await
+    }
+
+    pub async fn with_config(config: AppConfig) -> Self {
+        Self::with_auth(config, AuthChain::from_env().expect("Failed to configure authentication")).await
+    }
+
+    // With callers resolved by `auth_chain`, for tests of scoped credentials
+    pub async fn with_auth(config: AppConfig, auth_chain: AuthChain) -> Self {
+        let crypto = CryptoService::generate(config.allow_legacy_zero_nonce).expect("Failed to generate the test service key");
+        let key_provisioner = Arc::new(KeyProvisioner::from_env().with_service(crypto));
+        let state = build_state(&config, key_provisioner).await;
+        spawn_background(&state, &config);
+        let app = router(&state, &config, Arc::new(auth_chain));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let shutdown = CancellationToken::new();
+        tokio::spawn(
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                .with_graceful_shutdown(shutdown.clone().cancelled_owned())
+                .into_future(),
+        );
+        Self { url, state, client: reqwest::Client::new(), shutdown }
+    }
+
+    // `plaintext` sealed to the service key as a client would, merged into `fields`
+    pub async fn sealed_upload(&self, plaintext: &str, fields: Value) -> Value {
+        let handshake: Value = self.get_json("/handshake").await;
+        let public_key = handshake["public_key"].as_str().expect("The handshake has a public key");
+        let mut body = serde_json::to_value(envelope::seal(public_key, plaintext.as_bytes()).unwrap()).unwrap();
+        if let (Some(body), Value::Object(fields)) = (body.as_object_mut(), fields) {
+            body.extend(fields);
+        }
+        body
+    }
+
+    // A request to `path`, for tests that set headers such as `X-Tenant-Id`
+    pub fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.client.request(method, format!("{}{}", self.url, path))
+    }
+
+    // Upload `plaintext` sealed to the service key, returning the status and JSON body
+    pub async fn upload(&self, plaintext: &str, fields: Value) -> (u16, Value) {
+        let body = self.sealed_upload(plaintext, fields).await;
+        send(self.request(reqwest::Method::POST, "/upload").json(&body)).await
+    }
+
+    // The redacted text of a stored file
+    pub async fn download(&self, file_id: &str) -> String {
+        let response = self.client.get(format!("{}/download/{}", self.url, file_id)).send().await.unwrap();
+        assert!(response.status().is_success(), "Download of {} failed with {}", file_id, response.status());
+        response.text().await.unwrap()
+    }
+
+    pub async fn get_json(&self, path: &str) -> Value {
+        self.client.get(format!("{}{}", self.url, path)).send().await.unwrap().json().await.unwrap()
+    }
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
+}
+
+// The status and JSON body of a request's response
+pub async fn send(request: reqwest::RequestBuilder) -> (u16, Value) {
+    let response = request.send().await.unwrap();
+    (response.status().as_u16(), response.json().await.unwrap_or(Value::Null))
+}
+
+// Fields of an upload that stores its output under its own TTL, for tests to extend
+pub fn stored(ttl_seconds: u64) -> Value {
+    json!({ "ttl_seconds": ttl_seconds })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_uploads_are_redacted_end_to_end_by_the_mock_backend() {
+        let app = TestApp::spawn().await;
+        let health = app.get_json("/health").await;
+        assert_eq!(health["status"], "healthy");
+
+        let (status, response) = app.upload("Jane Doe (jane@example.com) lives in Chicago.", stored(600)).await;
+        assert_eq!(status, 200, "{}", response);
+        let file_id = response["file_id"].as_str().unwrap();
+        assert_eq!(app.download(file_id).await, "<PERSON> (<EMAIL_ADDRESS>) lives in <LOCATION>.");
+        assert!(response["expires_at"].is_u64());
+
+        // The same text is redacted the same way every time
+        let (_, again) = app.upload("Jane Doe (jane@example.com) lives in Chicago.", json!({ "redaction_strategy": "fake" })).await;
+        let (_, once_more) = app.upload("Jane Doe (jane@example.com) lives in Chicago.", json!({ "redaction_strategy": "fake" })).await;
+        let faked = app.download(again["file_id"].as_str().unwrap()).await;
+        assert_eq!(faked, app.download(once_more["file_id"].as_str().unwrap()).await);
+        assert!(!faked.contains("jane@example.com"));
+
+        let (status, refused) = app.upload("text", json!({ "entities": ["SHOE_SIZE"] })).await;
+        assert_eq!((status, refused["code"].as_str()), (400, Some("invalid_entity_filter")));
+        assert_eq!(app.state.redactor_service.backend_name(), "mock");
+    }
+
+    #[tokio::test]
+    async fn test_expired_files_have_erasure_receipts() {
+        let app = TestApp::spawn().await;
+        let (_, response) = app.upload("Call Jane Doe on 555-123-4567", stored(60)).await;
+        let file_id = response["file_id"].as_str().unwrap();
+        let expires_at = response["expires_at"].as_u64().unwrap();
+
+        crate::sweep_expired(&app.state, expires_at + 1, None).await;
+        let receipt = app.get_json(&format!("/audit/receipts/{}", file_id)).await;
+        assert_eq!((receipt["receipt"]["file_id"].as_str(), receipt["receipt"]["reason"].as_str()), (Some(file_id), Some("expired")));
+    }
+
+    #[tokio::test]
+    async fn test_evicted_files_have_erasure_receipts() {
+        let app = TestApp::with_config(AppConfig {
+            redaction_backend: "mock".to_string(),
+            storage_max_files: Some(1),
+            ..AppConfig::default()
+        })
+        .await;
+        let (_, first) = app.upload("Write to jane@example.com", json!({})).await;
+        let (status, _) = app.upload("Write to bob@example.com", json!({})).await;
+        assert_eq!(status, 200);
+
+        let file_id = first["file_id"].as_str().unwrap();
+        let receipt = app.get_json(&format!("/audit/receipts/{}", file_id)).await;
+        assert_eq!((receipt["receipt"]["file_id"].as_str(), receipt["receipt"]["reason"].as_str()), (Some(file_id), Some("evicted")));
+    }
+
+    #[tokio::test]
+    async fn test_feedback_summaries_stay_within_the_tenant() {
+        use sentient_redactor_core::{auth::{ApiKey, ApiKeyProvider, HeaderProvider}, caller::Scope, crypto};
+
+        let key = |tenant: &str| ApiKey { key_id: tenant.to_string(), principal: None, tenant: Some(tenant.to_string()), scopes: vec![Scope::Upload, Scope::Download] };
+        let provider = ApiKeyProvider::new([(crypto::sha256(b"acme-key"), key("acme")), (crypto::sha256(b"globex-key"), key("globex"))].into());
+        let chain = AuthChain::new(vec![Box::new(provider), Box::new(HeaderProvider)]);
+        let app = TestApp::with_auth(AppConfig { redaction_backend: "mock".to_string(), ..AppConfig::default() }, chain).await;
+        let with_key = |method, path: &str, key| app.request(method, path).header("X-API-Key", key);
+        let body = app.sealed_upload("Jane Doe moved to Chicago", json!({})).await;
+        let (_, response) = send(with_key(reqwest::Method::POST, "/upload", "acme-key").json(&body)).await;
+        let feedback = format!("/files/{}/feedback", response["file_id"].as_str().unwrap());
+        let flagged = json!({ "recognizer": "LOCATION", "kind": "false_positive", "value": "Chicago" });
+        let (status, _) = send(with_key(reqwest::Method::POST, &feedback, "acme-key").json(&flagged)).await;
+        assert_eq!(status, 201);
+
+        let summary_path = "/feedback/summary?min_occurrences=1";
+        let (_, own) = send(with_key(reqwest::Method::GET, summary_path, "acme-key")).await;
+        assert_eq!(own["allowlist_suggestions"][0]["value"], "Chicago");
+        let (_, other) = send(with_key(reqwest::Method::GET, summary_path, "globex-key")).await;
+        assert_eq!((other["total"].as_u64(), other["allowlist_suggestions"].as_array().map(Vec::len)), (Some(0), Some(0)));
+
+        // Naming a tenant in a header proves nothing
+        let (status, _) = send(app.request(reqwest::Method::GET, summary_path).header("X-Tenant-Id", "acme")).await;
+        assert_eq!(status, 401);
+        let (status, _) = send(app.request(reqwest::Method::GET, summary_path)).await;
+        assert_eq!(status, 401);
+    }
+
+    #[tokio::test]
+    async fn test_erasure_receipts_stay_within_the_tenant() {
+        let app = TestApp::spawn().await;
+        let as_alice = |method, path: &str| app.request(method, path).header("X-Principal-Id", "alice").header("X-Tenant-Id", "acme");
+        let body = app.sealed_upload("Write to jane@example.com", json!({})).await;
+        let (_, response) = send(as_alice(reqwest::Method::POST, "/upload").json(&body)).await;
+        let file_id = response["file_id"].as_str().unwrap();
+        let (status, _) = send(as_alice(reqwest::Method::DELETE, &format!("/files/{}", file_id))).await;
+        assert_eq!(status, 204);
+
+        let receipt_path = format!("/audit/receipts/{}", file_id);
+        let (status, receipt) = send(as_alice(reqwest::Method::GET, &receipt_path)).await;
+        assert_eq!((status, receipt["receipt"]["reason"].as_str()), (200, Some("deleted")));
+        let (status, _) = send(app.request(reqwest::Method::GET, &receipt_path).header("X-Tenant-Id", "globex")).await;
+        assert_eq!(status, 404);
+    }
+
+    #[tokio::test]
+    async fn test_uploads_from_urls_need_the_upload_scope() {
+        use sentient_redactor_core::{auth::{ApiKey, ApiKeyProvider}, caller::Scope, crypto};
+
+        let reader = ApiKey { key_id: "reader".to_string(), principal: None, tenant: None, scopes: vec![Scope::Download] };
+        let provider = ApiKeyProvider::new([(crypto::sha256(b"reader-key"), reader)].into());
+        let app = TestApp::with_auth(AppConfig { redaction_backend: "mock".to_string(), ..AppConfig::default() }, AuthChain::new(vec![Box::new(provider)])).await;
+
+        let body = json!({ "source_url": "https://blobs.example.com/upload.bin", "encrypted_data": "" });
+        let (status, refused) = send(app.request(reqwest::Method::POST, "/upload/from-url").header("X-API-Key", "reader-key").json(&body)).await;
+        assert_eq!((status, refused["code"].as_str()), (403, Some("scope_denied")));
+    }
+
+    #[tokio::test]
+    async fn test_signed_bodies_stream_against_their_digest() {
+        use hmac::{Hmac, Mac};
+        use sentient_redactor_core::{auth::{self, HmacProvider, SigningKey}, crypto};
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let secret = vec![7u8; 32];
+        let key = SigningKey { secret: secret.clone(), principal: "partner".to_string(), tenant: None };
+        let chain = AuthChain::new(vec![Box::new(HmacProvider::new([("k1".to_string(), key)].into(), 300))]);
+        let app = TestApp::with_auth(AppConfig { redaction_backend: "mock".to_string(), ..AppConfig::default() }, chain).await;
+        let body = serde_json::to_vec(&app.sealed_upload(&"Jane Doe lives in Chicago. ".repeat(40_000), json!({})).await).unwrap();
+        let signed = |digest: [u8; 32], body: Vec<u8>| {
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+            let uri = axum::http::Uri::from_static("/upload");
+            let mut mac = <Hmac<sha2::Sha256>>::new_from_slice(&secret).unwrap();
+            mac.update(auth::string_to_sign(&axum::http::Method::POST, &uri, timestamp, &digest).as_bytes());
+            app.request(reqwest::Method::POST, "/upload")
+                .header("Content-Type", "application/json")
+                .header("X-Auth-Key-Id", "k1")
+                .header("X-Auth-Timestamp", timestamp.to_string())
+                .header("X-Auth-Signature", envelope::hex(&mac.finalize().into_bytes()))
+                .body(body)
+        };
+
+        // Past the buffered size, the digest has to be sent
+        let (status, refused) = send(signed(crypto::sha256(&body), body.clone())).await;
+        assert_eq!((status, refused["code"].as_str()), (413, Some("payload_too_large")));
+        let (status, stored) = send(signed(crypto::sha256(&body), body.clone()).header(auth::BODY_SHA256, envelope::hex(&crypto::sha256(&body)))).await;
+        assert_eq!(status, 200, "{}", stored);
+
+        // Still a valid upload, but not the one signed
+        let mut swapped = body.clone();
+        swapped.push(b' ');
+        let (status, _) = send(signed(crypto::sha256(&body), swapped).header(auth::BODY_SHA256, envelope::hex(&crypto::sha256(&body)))).await;
+        assert_ne!(status, 200);
+    }
+
+    #[tokio::test]
+    async fn test_idempotent_uploads_are_read_within_the_body_limit() {
+        let app = TestApp::with_config(AppConfig { redaction_backend: "mock".to_string(), max_upload_bytes: 4096, ..AppConfig::default() }).await;
+        let body = app.sealed_upload(&"Jane Doe lives in Chicago. ".repeat(400), json!({})).await;
+        let upload = || app.request(reqwest::Method::POST, "/upload").header("Idempotency-Key", "retry-1").json(&body);
+        let (status, refused) = send(upload()).await;
+        assert_eq!((status, refused["code"].as_str()), (413, Some("payload_too_large")));
+
+        let body = app.sealed_upload("Jane Doe lives in Chicago.", json!({})).await;
+        let upload = || app.request(reqwest::Method::POST, "/upload").header("Idempotency-Key", "retry-2").json(&body);
+        let (_, first) = send(upload()).await;
+        let (status, replayed) = send(upload()).await;
+        assert_eq!((status, &replayed["file_id"]), (200, &first["file_id"]));
+    }
+
+    #[tokio::test]
+    async fn test_downloads_name_files_with_any_characters() {
+        let app = TestApp::spawn().await;
+        let (_, response) = app.upload("Jane Doe", json!({ "file_name": "Résumé \"final\"\r\n.txt" })).await;
+        let file_id = response["file_id"].as_str().unwrap();
+        let download = app.request(reqwest::Method::GET, &format!("/download/{}", file_id)).send().await.unwrap();
+        assert_eq!(download.status(), 200);
+        let disposition = download.headers()["Content-Disposition"].to_str().unwrap();
+        assert!(disposition.starts_with("attachment; filename=\"R_sum_ _final___.txt_replace_redacted_"), "{}", disposition);
+        assert!(disposition.contains("; filename*=UTF-8''R%C3%A9sum%C3%A9%20%22final%22%0D%0A.txt_replace_redacted_"));
+    }
+}
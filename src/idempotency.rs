@@ -0,0 +1,229 @@
+use axum::body::{Body, Bytes};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::Response;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use sentient_redactor_core::caller::Caller;
+use sentient_redactor_core::operations::{ErrorKind, OperationError};
+
+pub const HEADER: &str = "Idempotency-Key";
+// Set on responses replayed from the cache
+pub const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+const MAX_KEY_BYTES: usize = 255;
+
+// The key of a JSON body, for clients that cannot set headers
+#[derive(Deserialize)]
+struct KeyField {
+    idempotency_key: Option<String>,
+}
+
+// Who sent a request, the key they gave it and a digest of what they sent. A key reused
+// with another payload is another request, and is processed.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RequestKey {
+    principal: Option<String>,
+    tenant: Option<String>,
+    key: String,
+    payload: [u8; 32],
+}
+
+impl RequestKey {
+    pub fn new(caller: &Caller, key: &str, route: &str, query: Option<&str>, body: &[u8]) -> Self {
+        let mut hasher = Sha256::new();
+        for part in [route.as_bytes(), query.unwrap_or_default().as_bytes()] {
+            hasher.update((part.len() as u64).to_be_bytes());
+            hasher.update(part);
+        }
+        hasher.update(body);
+        Self {
+            principal: caller.principal.clone(),
+            tenant: caller.tenant.clone(),
+            key: key.to_string(),
+            payload: hasher.finalize().into(),
+        }
+    }
+}
+
+// The key a request carries, in the header or, for JSON bodies, the `idempotency_key`
+// field; both must agree when both are set
+pub fn request_key(headers: &HeaderMap, json_body: Option<&[u8]>) -> Result<Option<String>, OperationError> {
+    let invalid = |message: &str| OperationError::new(ErrorKind::BadRequest, message).with_code("invalid_idempotency_key");
+    let header = headers.get(HEADER)
+        .map(|value| value.to_str().map(str::to_string).map_err(|_| invalid("Idempotency-Key must be visible ASCII")))
+        .transpose()?;
+    let field = json_body
+        .and_then(|body| serde_json::from_slice::<KeyField>(body).ok())
+        .and_then(|field| field.idempotency_key);
+    let key = match (header, field) {
+        (Some(header), Some(field)) if header != field => return Err(invalid("Idempotency-Key and idempotency_key differ")),
+        (header, field) => header.or(field),
+    };
+    match key {
+        Some(key) if key.is_empty() || key.len() > MAX_KEY_BYTES || !key.bytes().all(|b| b.is_ascii_graphic()) => {
+            Err(invalid("Idempotency keys are 1 to 255 visible ASCII characters"))
+        }
+        key => Ok(key),
+    }
+}
+
+// A successful response, kept to be sent again
+#[derive(Clone)]
+pub struct StoredResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl StoredResponse {
+    // The response as it will be replayed, and a copy of it to send now
+    pub async fn buffer(response: Response) -> Result<(Self, Response), OperationError> {
+        let (parts, body) = response.into_parts();
+        let body = axum::body::to_bytes(body, usize::MAX).await
+            .map_err(|e| OperationError::new(ErrorKind::Internal, format!("Failed to buffer the response: {}", e)))?;
+        let stored = Self { status: parts.status, headers: parts.headers.clone(), body: body.clone() };
+        Ok((stored, Response::from_parts(parts, Body::from(body))))
+    }
+
+    fn replay(&self) -> Response {
+        let mut response = Response::new(Body::from(self.body.clone()));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers.clone();
+        response.headers_mut().insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+        response
+    }
+}
+
+enum Entry {
+    InFlight,
+    Done { at: Instant, response: StoredResponse },
+}
+
+pub enum Claim<'a> {
+    // Not seen within the window; the request runs, and its response is kept when it succeeds
+    First(Pending<'a>),
+    Replay(Response),
+    // The same request is still being processed
+    InFlight,
+}
+
+// A claimed request still running. Dropping it before `complete`, as when the client
+// hangs up, releases the key so a retry runs.
+pub struct Pending<'a> {
+    cache: &'a IdempotencyCache,
+    key: Option<RequestKey>,
+}
+
+impl Pending<'_> {
+    pub fn complete(mut self, response: Option<StoredResponse>) {
+        let Some(key) = self.key.take() else { return };
+        let mut entries = self.cache.entries.lock().unwrap();
+        match response {
+            Some(response) => entries.insert(key, Entry::Done { at: Instant::now(), response }),
+            None => entries.remove(&key),
+        };
+    }
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.cache.entries.lock().unwrap().remove(&key);
+        }
+    }
+}
+
+// Responses to uploads sent with an idempotency key, replayed to retries within
+// `IDEMPOTENCY_WINDOW_SECONDS` instead of storing the upload again
+pub struct IdempotencyCache {
+    window: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<RequestKey, Entry>>,
+}
+
+impl IdempotencyCache {
+    pub fn new(window: Duration, max_entries: usize) -> Self {
+        Self { window, max_entries, entries: Mutex::new(HashMap::new()) }
+    }
+
+    // `IDEMPOTENCY_WINDOW_SECONDS` (default 86400) and `IDEMPOTENCY_MAX_ENTRIES` (default 10000)
+    pub fn from_env() -> Self {
+        let env = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|value| *value > 0)
+                .unwrap_or(default)
+        };
+        Self::new(Duration::from_secs(env("IDEMPOTENCY_WINDOW_SECONDS", 86400)), env("IDEMPOTENCY_MAX_ENTRIES", 10000) as usize)
+    }
+
+    pub fn claim(&self, key: RequestKey) -> Claim<'_> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| !matches!(entry, Entry::Done { at, .. } if at.elapsed() >= self.window));
+        match entries.get(&key) {
+            Some(Entry::Done { response, .. }) => return Claim::Replay(response.replay()),
+            Some(Entry::InFlight) => return Claim::InFlight,
+            None => {}
+        }
+        // At capacity, the oldest responses go first
+        while entries.len() >= self.max_entries {
+            let oldest = entries.iter()
+                .filter_map(|(key, entry)| match entry {
+                    Entry::Done { at, .. } => Some((key.clone(), *at)),
+                    Entry::InFlight => None,
+                })
+                .min_by_key(|(_, at)| *at);
+            let Some((oldest, _)) = oldest else { break };
+            entries.remove(&oldest);
+        }
+        entries.insert(key.clone(), Entry::InFlight);
+        Claim::First(Pending { cache: self, key: Some(key) })
+    }
+}
+
+pub fn in_flight() -> OperationError {
+    OperationError::new(ErrorKind::Conflict, "A request with this idempotency key is still being processed; retry once it completes")
+        .with_code("idempotency_key_in_use")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_retries_replay_the_first_response() {
+        let cache = IdempotencyCache::new(Duration::from_secs(60), 10);
+        let caller = Caller { principal: Some("svc-claims".to_string()), ..Caller::default() };
+        let key = RequestKey::new(&caller, "retry-1", "/upload", None, b"{\"encrypted_data\":\"...\"}");
+
+        let Claim::First(pending) = cache.claim(key.clone()) else { panic!("first request was not run") };
+        assert!(matches!(cache.claim(key.clone()), Claim::InFlight));
+        let (stored, _) = StoredResponse::buffer(Response::new(Body::from("{\"file_id\":\"f1\"}"))).await.unwrap();
+        pending.complete(Some(stored));
+
+        let Claim::Replay(replayed) = cache.claim(key.clone()) else { panic!("retry was not replayed") };
+        assert_eq!(replayed.headers()[REPLAYED_HEADER], "true");
+        assert_eq!(axum::body::to_bytes(replayed.into_body(), usize::MAX).await.unwrap(), "{\"file_id\":\"f1\"}");
+
+        // Another payload, or another caller, under the same key is another request
+        let other_payload = RequestKey::new(&caller, "retry-1", "/upload", None, b"{}");
+        assert!(matches!(cache.claim(other_payload), Claim::First(_)));
+        let other_caller = RequestKey::new(&Caller::default(), "retry-1", "/upload", None, b"{\"encrypted_data\":\"...\"}");
+        assert!(matches!(cache.claim(other_caller), Claim::First(_)));
+
+        // A request dropped before completing leaves nothing to replay
+        let dropped = RequestKey::new(&caller, "retry-2", "/upload", None, b"{}");
+        drop(cache.claim(dropped.clone()));
+        assert!(matches!(cache.claim(dropped), Claim::First(_)));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(HEADER, HeaderValue::from_static("a"));
+        assert!(request_key(&headers, Some(b"{\"idempotency_key\":\"b\"}")).is_err());
+        assert_eq!(request_key(&HeaderMap::new(), Some(b"{\"idempotency_key\":\"b\"}")).unwrap().as_deref(), Some("b"));
+        assert!(request_key(&HeaderMap::new(), Some(b"{\"idempotency_key\":\"\"}")).is_err());
+    }
+}
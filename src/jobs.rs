@@ -0,0 +1,1033 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, watch, Semaphore};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use sentient_redactor_core::{
+    caller::Caller,
+    chunking::{self, ChunkProgress, ProgressReporter, UploadProgress},
+    operations::{self, OperationError, StageTiming, UploadRequest, UploadResponse},
+    policy::RedactionPolicy,
+};
+
+use crate::alerts::Alerter;
+
+const DEFAULT_WORKERS: usize = 4;
+const DEFAULT_QUEUE_CAPACITY: usize = 100;
+const DEFAULT_RETENTION_SECONDS: u64 = 3600;
+const DEFAULT_MAX_WAIT_SECONDS: u64 = 30;
+const DEFAULT_DRAIN_SECONDS: u64 = 30;
+// How often idle workers look again at jobs waiting for a processing window
+const WINDOW_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// Times a job may be picked up before a restart that interrupts it again fails it
+const MAX_JOB_ATTEMPTS: u32 = 2;
+const JOB_INTERRUPTED: &str = "job_interrupted";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Processing,
+    Done,
+    Failed,
+}
+
+#[derive(Clone, Deserialize, Serialize, ToSchema)]
+pub struct JobView {
+    pub job_id: String,
+    pub status: JobStatus,
+    pub created_at: u64,
+    pub updated_at: u64,
+    // Assigned when the job is queued; the file exists once the job is done
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<String>,
+    // Unix time the tenant's processing window opens, for jobs queued while it was closed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub held_until: Option<u64>,
+    // Pipeline stages finished so far, in order, with their durations
+    #[serde(skip_serializing_if = "Vec::is_empty", skip_deserializing)]
+    pub stages: Vec<StageTiming>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl JobView {
+    pub fn is_pending(&self) -> bool {
+        matches!(self.status, JobStatus::Queued | JobStatus::Processing)
+    }
+}
+
+// What happened to a job, streamed by `GET /jobs/:id/events`
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum JobEventKind {
+    // A worker picked the job up
+    Processing,
+    // A stage of the upload's pipeline, such as `decryption` or `storage`, finished
+    // after `ms`
+    Stage { stage: &'static str, ms: f64 },
+    // `chunk` of the `chunks` of the text being analyzed is done, and `entities` were
+    // found in the upload so far
+    Progress { chunk: usize, chunks: usize, entities: usize },
+    Completed { file_id: String },
+    Failed {
+        error: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        code: Option<&'static str>,
+    },
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct JobEvent {
+    pub job_id: String,
+    #[serde(flatten)]
+    pub kind: JobEventKind,
+}
+
+impl JobEvent {
+    pub fn name(&self) -> &'static str {
+        match self.kind {
+            JobEventKind::Processing => "processing",
+            JobEventKind::Stage { .. } => "stage",
+            JobEventKind::Progress { .. } => "progress",
+            JobEventKind::Completed { .. } => "completed",
+            JobEventKind::Failed { .. } => "failed",
+        }
+    }
+
+    // Nothing follows a job's completion or failure
+    pub fn is_final(&self) -> bool {
+        matches!(self.kind, JobEventKind::Completed { .. } | JobEventKind::Failed { .. })
+    }
+}
+
+// What `GET /jobs/:id/result` answers with
+pub enum JobOutcome {
+    Pending(JobView),
+    // The body the synchronous upload would have returned
+    Done(Value),
+    Failed { status: u16, code: Option<&'static str>, message: String },
+}
+
+struct Job {
+    view: JobView,
+    // Only the submitting principal, within its tenant, can see the job
+    principal: Option<String>,
+    tenant: Option<String>,
+    response: Option<Value>,
+    failure: Option<(u16, Option<&'static str>)>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct QueuedUpload {
+    job_id: String,
+    caller: Caller,
+    request: UploadRequest,
+    profile: bool,
+    // Times a worker picked the job up, counting runs a restart interrupted
+    #[serde(default)]
+    attempts: u32,
+}
+
+// A job as it was at one of its changes of state, a line of `JOB_JOURNAL_PATH`. Only the
+// line queueing a job carries its upload, and only finished ones their outcome.
+#[derive(Deserialize, Serialize)]
+struct JournalEntry<U> {
+    #[serde(flatten)]
+    view: JobView,
+    principal: Option<String>,
+    tenant: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upload: Option<U>,
+    // Base64 of a binary upload's ciphertext, which the upload itself leaves out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ciphertext: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    failure_status: Option<u16>,
+}
+
+#[derive(Debug)]
+pub struct QueueFull;
+
+// Jobs waiting for a worker, one queue per tenant. Tenants are served by start-time fair
+// queuing: each job is tagged with where its tenant's share of the workers reaches it,
+// so a tenant with a deep backlog only delays others by its weight. A job that has
+// waited `max_wait` goes first whatever its tag, so low weights cannot starve. Tenants
+// outside their processing window are skipped until it opens.
+struct FairQueue<T> {
+    tenants: HashMap<Option<String>, TenantQueue<T>>,
+    weights: HashMap<String, u32>,
+    max_wait: Duration,
+    // Start tag of the last job served
+    virtual_time: f64,
+    len: usize,
+}
+
+struct TenantQueue<T> {
+    jobs: VecDeque<Waiting<T>>,
+    // Where the tenant's next job starts
+    next_start: f64,
+}
+
+struct Waiting<T> {
+    item: T,
+    start: f64,
+    queued_at: Instant,
+}
+
+// A tenant's share of the queue, for `GET /metrics`
+pub struct TenantBacklog {
+    pub tenant: Option<String>,
+    pub weight: u32,
+    pub queued: usize,
+    pub oldest_wait_seconds: f64,
+}
+
+impl<T> FairQueue<T> {
+    fn new(weights: HashMap<String, u32>, max_wait: Duration) -> Self {
+        Self { tenants: HashMap::new(), weights, max_wait, virtual_time: 0.0, len: 0 }
+    }
+
+    // Unlisted tenants, and callers without one, weigh 1
+    fn weight(&self, tenant: &Option<String>) -> u32 {
+        tenant.as_ref().and_then(|tenant| self.weights.get(tenant)).copied().unwrap_or(1)
+    }
+
+    fn queued(&self, tenant: &Option<String>) -> usize {
+        self.tenants.get(tenant).map_or(0, |queue| queue.jobs.len())
+    }
+
+    fn push(&mut self, tenant: Option<String>, item: T) {
+        let cost = 1.0 / f64::from(self.weight(&tenant));
+        let virtual_time = self.virtual_time;
+        let queue = self.tenants.entry(tenant).or_insert_with(|| TenantQueue { jobs: VecDeque::new(), next_start: 0.0 });
+        let start = queue.next_start.max(virtual_time);
+        queue.next_start = start + cost;
+        queue.jobs.push_back(Waiting { item, start, queued_at: Instant::now() });
+        self.len += 1;
+    }
+
+    fn pop(&mut self, open: impl Fn(&Option<String>) -> bool) -> Option<T> {
+        let heads = self.tenants.iter()
+            .filter(|(tenant, _)| open(tenant))
+            .filter_map(|(tenant, queue)| Some((tenant, queue.jobs.front()?)));
+        let starving = heads.clone()
+            .filter(|(_, head)| head.queued_at.elapsed() >= self.max_wait)
+            .min_by_key(|(_, head)| head.queued_at);
+        let next = starving.or_else(|| {
+            heads.min_by(|(_, a), (_, b)| a.start.total_cmp(&b.start).then(a.queued_at.cmp(&b.queued_at)))
+        });
+        let tenant = next?.0.clone();
+
+        let queue = self.tenants.get_mut(&tenant)?;
+        let waiting = queue.jobs.pop_front()?;
+        if queue.jobs.is_empty() {
+            self.tenants.remove(&tenant);
+        }
+        self.virtual_time = self.virtual_time.max(waiting.start);
+        self.len -= 1;
+        Some(waiting.item)
+    }
+
+    fn backlog(&self) -> Vec<TenantBacklog> {
+        self.tenants
+            .iter()
+            .map(|(tenant, queue)| TenantBacklog {
+                tenant: tenant.clone(),
+                weight: self.weight(tenant),
+                queued: queue.jobs.len(),
+                oldest_wait_seconds: queue.jobs.front().map_or(0.0, |head| head.queued_at.elapsed().as_secs_f64()),
+            })
+            .collect()
+    }
+}
+
+// Uploads queued for background processing by a pool of `JOB_WORKERS` tasks, shared
+// between tenants by `JOB_TENANT_WEIGHTS`. Jobs live in memory: finished ones are kept
+// `JOB_RETENTION_SECONDS` for polling. At shutdown the workers get
+// `SHUTDOWN_DRAIN_SECONDS` to finish what is queued; jobs left then are lost, in which
+// case clients resubmit.
+pub struct JobQueue {
+    jobs: Mutex<HashMap<String, Job>>,
+    queue: Mutex<FairQueue<QueuedUpload>>,
+    // One permit per queued job
+    ready: Semaphore,
+    capacity: usize,
+    tenant_capacity: usize,
+    started: AtomicBool,
+    workers: usize,
+    retention_seconds: u64,
+    drain_timeout: Duration,
+    // Bumped whenever a job finishes, for `wait_for_file`
+    finished: watch::Sender<u64>,
+    // Source of the tenants' processing windows
+    policy: Option<Arc<RedactionPolicy>>,
+    // Told of every failed job, to alert when too many fail
+    alerts: Option<Arc<Alerter>>,
+    // Where jobs' progress goes, for clients following it
+    events: Option<broadcast::Sender<JobEvent>>,
+    // Where each job's changes of state are appended, to recover the jobs after a restart
+    journal: Option<Mutex<File>>,
+}
+
+impl JobQueue {
+    pub fn from_env() -> Self {
+        let env_number = |name: &str, default| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|value| *value > 0)
+                .unwrap_or(default)
+        };
+
+        let capacity = env_number("JOB_QUEUE_CAPACITY", DEFAULT_QUEUE_CAPACITY as u64) as usize;
+        let max_wait = Duration::from_secs(env_number("JOB_MAX_WAIT_SECONDS", DEFAULT_MAX_WAIT_SECONDS));
+        let weights = tenant_weights(&std::env::var("JOB_TENANT_WEIGHTS").unwrap_or_default());
+
+        Self {
+            jobs: Mutex::new(HashMap::new()),
+            queue: Mutex::new(FairQueue::new(weights, max_wait)),
+            ready: Semaphore::new(0),
+            capacity,
+            tenant_capacity: env_number("JOB_TENANT_QUEUE_CAPACITY", capacity as u64) as usize,
+            started: AtomicBool::new(false),
+            workers: env_number("JOB_WORKERS", DEFAULT_WORKERS as u64) as usize,
+            retention_seconds: env_number("JOB_RETENTION_SECONDS", DEFAULT_RETENTION_SECONDS),
+            drain_timeout: Duration::from_secs(env_number("SHUTDOWN_DRAIN_SECONDS", DEFAULT_DRAIN_SECONDS)),
+            finished: watch::Sender::new(0),
+            policy: None,
+            alerts: None,
+            events: None,
+            journal: None,
+        }
+    }
+
+    // Hold each tenant's jobs until its processing window is open
+    pub fn with_policy(mut self, policy: Arc<RedactionPolicy>) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    pub fn with_alerts(mut self, alerts: Arc<Alerter>) -> Self {
+        self.alerts = Some(alerts);
+        self
+    }
+
+    pub fn with_events(mut self, events: broadcast::Sender<JobEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    // Recover the jobs journaled at `path` by an earlier run, then journal jobs there.
+    // Queued jobs are queued again and finished ones kept for polling. A job a restart
+    // interrupted is retried from its encrypted upload, and failed if that is interrupted
+    // too. The file is rewritten with only the jobs recovered.
+    pub fn with_journal(mut self, path: Option<&str>) -> Result<Self> {
+        let Some(path) = path else {
+            return Ok(self);
+        };
+
+        let mut order = Vec::new();
+        let mut latest: HashMap<String, JournalEntry<QueuedUpload>> = HashMap::new();
+        match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+                    let mut entry: JournalEntry<QueuedUpload> = serde_json::from_str(line)
+                        .map_err(|e| anyhow!("Invalid job journal entry in {}: {}", path, e))?;
+                    let job_id = entry.view.job_id.clone();
+                    match latest.remove(&job_id) {
+                        Some(previous) if entry.upload.is_none() => {
+                            entry.upload = previous.upload;
+                            entry.ciphertext = previous.ciphertext;
+                        }
+                        Some(_) => {}
+                        None => order.push(job_id.clone()),
+                    }
+                    if entry.view.status == JobStatus::Processing {
+                        if let Some(upload) = &mut entry.upload {
+                            upload.attempts += 1;
+                        }
+                    }
+                    latest.insert(job_id, entry);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(anyhow!("Failed to read job journal {}: {}", path, e)),
+        }
+
+        let now = now();
+        let mut lines = Vec::new();
+        let (mut requeued, mut interrupted) = (0, 0);
+        for job_id in order {
+            let Some(mut entry) = latest.remove(&job_id) else {
+                continue;
+            };
+            let upload = entry.upload.take().filter(|upload| upload.attempts < MAX_JOB_ATTEMPTS);
+            let ciphertext = entry.ciphertext.take();
+            let mut failure = entry.failure_status.map(|status| (status, None));
+
+            match upload {
+                Some(mut upload) if entry.view.is_pending() => {
+                    entry.view.status = JobStatus::Queued;
+                    entry.view.held_until = self.held_until(entry.tenant.as_deref(), now);
+                    entry.view.stages.clear();
+                    lines.push(serde_json::to_string(&JournalEntry {
+                        upload: Some(&upload),
+                        ciphertext: ciphertext.clone(),
+                        view: entry.view.clone(),
+                        principal: entry.principal.clone(),
+                        tenant: entry.tenant.clone(),
+                        response: None,
+                        failure_status: None,
+                    })?);
+                    upload.request.file_id = entry.view.file_id.clone();
+                    upload.request.ciphertext = match ciphertext {
+                        Some(ciphertext) => Some(BASE64.decode(ciphertext)
+                            .map_err(|e| anyhow!("Invalid ciphertext of job {} in {}: {}", job_id, path, e))?),
+                        None => None,
+                    };
+                    let tenant = upload.caller.tenant.clone();
+                    self.queue.get_mut().unwrap().push(tenant, upload);
+                    requeued += 1;
+                }
+                _ if entry.view.is_pending() => {
+                    warn!("Upload job {} was interrupted by a restart and is failed", job_id);
+                    entry.view.status = JobStatus::Failed;
+                    entry.view.updated_at = now;
+                    entry.view.error = Some("The service restarted while the job was processing".to_string());
+                    entry.failure_status = Some(500);
+                    failure = Some((500, Some(JOB_INTERRUPTED)));
+                    lines.push(serde_json::to_string(&entry)?);
+                    interrupted += 1;
+                }
+                _ if entry.view.updated_at + self.retention_seconds > now => {
+                    lines.push(serde_json::to_string(&entry)?);
+                }
+                _ => continue,
+            }
+
+            self.jobs.get_mut().unwrap().insert(job_id, Job {
+                view: entry.view,
+                principal: entry.principal,
+                tenant: entry.tenant,
+                response: entry.response,
+                failure,
+            });
+        }
+
+        // Written aside and moved into place, so a crash while compacting loses nothing
+        let compacted = format!("{}.tmp", path);
+        std::fs::write(&compacted, lines.iter().map(|line| format!("{}\n", line)).collect::<String>())
+            .and_then(|_| std::fs::rename(&compacted, path))
+            .map_err(|e| anyhow!("Failed to compact job journal {}: {}", path, e))?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| anyhow!("Failed to open job journal {}: {}", path, e))?;
+
+        self.ready.add_permits(requeued);
+        self.journal = Some(Mutex::new(file));
+        info!("Recovered {} upload job(s) from {}: {} queued again, {} failed as interrupted", lines.len(), path, requeued, interrupted);
+        Ok(self)
+    }
+
+    // Whether pending jobs are resumed after a restart
+    pub fn is_journaled(&self) -> bool {
+        self.journal.is_some()
+    }
+
+    // Append the job as it now is to the journal, with its upload when it is being queued
+    fn journal(&self, job_id: &str, upload: Option<&QueuedUpload>) {
+        let Some(journal) = &self.journal else {
+            return;
+        };
+        let line = {
+            let jobs = self.jobs.lock().unwrap();
+            let Some(job) = jobs.get(job_id) else {
+                return;
+            };
+            serde_json::to_string(&JournalEntry {
+                view: job.view.clone(),
+                principal: job.principal.clone(),
+                tenant: job.tenant.clone(),
+                ciphertext: upload.and_then(|upload| upload.request.ciphertext.as_ref()).map(|c| BASE64.encode(c)),
+                upload,
+                response: job.response.clone(),
+                failure_status: job.failure.map(|(status, _)| status),
+            })
+        };
+        match line {
+            Ok(line) => {
+                if let Err(e) = writeln!(journal.lock().unwrap(), "{}", line) {
+                    warn!("Failed to journal upload job {}: {}", job_id, e);
+                }
+            }
+            Err(e) => warn!("Failed to journal upload job {}: {}", job_id, e),
+        }
+    }
+
+    fn emit(&self, job_id: &str, kind: JobEventKind) {
+        if let Some(events) = &self.events {
+            // Fails only when no one is following
+            let _ = events.send(JobEvent { job_id: job_id.to_string(), kind });
+        }
+    }
+
+    // Reports each finished stage of a job's upload, also kept on the job, and each
+    // analyzed chunk, with the entities of all chunks so far
+    fn progress_reporter(self: &Arc<Self>, job_id: &str) -> ProgressReporter {
+        let queue = self.clone();
+        let job_id = job_id.to_string();
+        let entities = AtomicUsize::new(0);
+        Arc::new(move |progress| match progress {
+            UploadProgress::Stage { stage, ms } => {
+                queue.update(&job_id, |job| job.view.stages.push(StageTiming { stage, ms }));
+                queue.emit(&job_id, JobEventKind::Stage { stage, ms });
+            }
+            UploadProgress::Chunk(ChunkProgress { chunk, chunks, entities: found }) => {
+                let entities = entities.fetch_add(found, Ordering::Relaxed) + found;
+                queue.emit(&job_id, JobEventKind::Progress { chunk, chunks, entities });
+            }
+        })
+    }
+
+    // When the tenant's processing window next opens, if it is closed at `now`
+    fn held_until(&self, tenant: Option<&str>, now: u64) -> Option<u64> {
+        let window = self.policy.as_ref()?.closed_window(tenant, now)?;
+        Some(now + window.opens_in(now))
+    }
+
+    // Start the worker pool, each running `process` on one queued upload at a time
+    pub fn spawn_workers<F, Fut>(self: &Arc<Self>, process: F)
+    where
+        F: Fn(Caller, UploadRequest) -> Fut + Clone + Send + Sync + 'static,
+        Fut: Future<Output = Result<UploadResponse, OperationError>> + Send,
+    {
+        if self.started.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        for _ in 0..self.workers {
+            let queue = self.clone();
+            let process = process.clone();
+            tokio::spawn(async move {
+                loop {
+                    let Ok(permit) = queue.ready.acquire().await else {
+                        return;
+                    };
+                    permit.forget();
+                    let now = now();
+                    let (next, queued) = {
+                        let mut waiting = queue.queue.lock().unwrap();
+                        (waiting.pop(|tenant| queue.held_until(tenant.as_deref(), now).is_none()), waiting.len)
+                    };
+                    let Some(upload) = next else {
+                        // Every queued job is outside its processing window
+                        if queued > 0 {
+                            queue.ready.add_permits(1);
+                            tokio::time::sleep(WINDOW_POLL_INTERVAL).await;
+                        }
+                        continue;
+                    };
+                    queue.update(&upload.job_id, |job| job.view.status = JobStatus::Processing);
+                    queue.journal(&upload.job_id, None);
+                    queue.emit(&upload.job_id, JobEventKind::Processing);
+                    let progress = queue.progress_reporter(&upload.job_id);
+                    let result = chunking::report_progress(progress, process(upload.caller, upload.request)).await;
+                    queue.finish(&upload.job_id, result, upload.profile);
+                }
+            });
+        }
+        info!("Started {} upload job worker(s)", self.workers);
+    }
+
+    pub fn submit(&self, caller: Caller, mut request: UploadRequest, profile: bool) -> Result<JobView, QueueFull> {
+        let now = now();
+        let mut token = [0u8; 16];
+        OsRng.fill_bytes(&mut token);
+        let job_id = token.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        let file_id = operations::new_file_id();
+        request.file_id = Some(file_id.clone());
+
+        let view = JobView {
+            job_id: job_id.clone(),
+            status: JobStatus::Queued,
+            created_at: now,
+            updated_at: now,
+            file_id: Some(file_id),
+            held_until: self.held_until(caller.tenant.as_deref(), now),
+            stages: Vec::new(),
+            error: None,
+        };
+
+        // Registered before it is queued, so a worker always finds it
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            jobs.retain(|_, job| {
+                !matches!(job.view.status, JobStatus::Done | JobStatus::Failed)
+                    || job.view.updated_at + self.retention_seconds > now
+            });
+            jobs.insert(job_id.clone(), Job {
+                view: view.clone(),
+                principal: caller.principal.clone(),
+                tenant: caller.tenant.clone(),
+                response: None,
+                failure: None,
+            });
+        }
+
+        let tenant = caller.tenant.clone();
+        {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.len >= self.capacity || queue.queued(&tenant) >= self.tenant_capacity {
+                warn!("Upload job queue is full for tenant {}", tenant.as_deref().unwrap_or("none"));
+                self.jobs.lock().unwrap().remove(&job_id);
+                return Err(QueueFull);
+            }
+            let upload = QueuedUpload { job_id: job_id.clone(), caller, request, profile, attempts: 0 };
+            self.journal(&job_id, Some(&upload));
+            queue.push(tenant, upload);
+        }
+        self.ready.add_permits(1);
+
+        info!("Queued upload job {}", job_id);
+        Ok(view)
+    }
+
+    pub fn backlog(&self) -> Vec<TenantBacklog> {
+        self.queue.lock().unwrap().backlog()
+    }
+
+    pub fn status(&self, job_id: &str, caller: &Caller) -> Option<JobView> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.get(job_id)
+            .filter(|job| is_visible(job, caller))
+            .map(|job| job.view.clone())
+    }
+
+    // The caller's job producing `file_id`, while it is kept
+    pub fn for_file(&self, file_id: &str, caller: &Caller) -> Option<JobView> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.values()
+            .find(|job| job.view.file_id.as_deref() == Some(file_id) && is_visible(job, caller))
+            .map(|job| job.view.clone())
+    }
+
+    // Wait up to `timeout` for the caller's job producing `file_id` to finish, returning
+    // it as it then is. None when there is no such job.
+    pub async fn wait_for_file(&self, file_id: &str, caller: &Caller, timeout: Duration) -> Option<JobView> {
+        // Subscribed before the first look, so a job finishing in between is not missed
+        let mut finished = self.finished.subscribe();
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let job = self.for_file(file_id, caller)?;
+            if !job.is_pending() {
+                return Some(job);
+            }
+            if !matches!(tokio::time::timeout_at(deadline, finished.changed()).await, Ok(Ok(()))) {
+                return Some(job);
+            }
+        }
+    }
+
+    // Wait for the workers to finish the jobs they can run, at shutdown once nothing more
+    // is submitted. Returns the ids of jobs still pending when `SHUTDOWN_DRAIN_SECONDS`
+    // run out, or that are held outside their processing window.
+    pub async fn drain(&self) -> Vec<String> {
+        let mut finished = self.finished.subscribe();
+        let deadline = tokio::time::Instant::now() + self.drain_timeout;
+        loop {
+            let now = now();
+            let runnable = self.started.load(Ordering::SeqCst) && self.jobs.lock().unwrap().values().any(|job| {
+                job.view.status == JobStatus::Processing
+                    || (job.view.status == JobStatus::Queued && self.held_until(job.tenant.as_deref(), now).is_none())
+            });
+            if !runnable || tokio::time::timeout_at(deadline, finished.changed()).await.is_err() {
+                break;
+            }
+        }
+        let jobs = self.jobs.lock().unwrap();
+        jobs.values().filter(|job| job.view.is_pending()).map(|job| job.view.job_id.clone()).collect()
+    }
+
+    pub fn outcome(&self, job_id: &str, caller: &Caller) -> Option<JobOutcome> {
+        let jobs = self.jobs.lock().unwrap();
+        let job = jobs.get(job_id).filter(|job| is_visible(job, caller))?;
+
+        Some(match (&job.response, job.failure) {
+            (Some(response), _) => JobOutcome::Done(response.clone()),
+            (None, Some((status, code))) => JobOutcome::Failed {
+                status,
+                code,
+                message: job.view.error.clone().unwrap_or_default(),
+            },
+            (None, None) => JobOutcome::Pending(job.view.clone()),
+        })
+    }
+
+    fn update(&self, job_id: &str, apply: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(job_id) {
+            apply(job);
+            job.view.updated_at = now();
+        }
+    }
+
+    fn finish(&self, job_id: &str, result: Result<UploadResponse, OperationError>, profile: bool) {
+        match result {
+            Ok(response) => {
+                info!("Upload job {} produced file_id {}", job_id, response.file_id);
+                let mut body = serde_json::to_value(&response).unwrap_or_default();
+                if profile {
+                    body["profile"] = serde_json::to_value(&response.profile).unwrap_or_default();
+                }
+                self.update(job_id, |job| {
+                    job.view.status = JobStatus::Done;
+                    job.view.file_id = Some(response.file_id.clone());
+                    job.response = Some(body);
+                });
+                self.journal(job_id, None);
+                self.emit(job_id, JobEventKind::Completed { file_id: response.file_id });
+            }
+            Err(e) => {
+                warn!("Upload job {} failed: {}", job_id, e);
+                if let Some(alerts) = &self.alerts {
+                    alerts.job_failed();
+                }
+                self.update(job_id, |job| {
+                    job.view.status = JobStatus::Failed;
+                    job.view.error = Some(e.message.clone());
+                    job.failure = Some((e.kind.status(), e.code));
+                });
+                self.journal(job_id, None);
+                self.emit(job_id, JobEventKind::Failed { error: e.message, code: e.code });
+            }
+        }
+        self.finished.send_modify(|finished| *finished += 1);
+    }
+}
+
+// `acme=4,bulk=1`; entries that do not parse to a positive weight are skipped
+fn tenant_weights(value: &str) -> HashMap<String, u32> {
+    let mut weights = HashMap::new();
+    for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        match entry.split_once('=').map(|(tenant, weight)| (tenant.trim(), weight.trim().parse::<u32>())) {
+            Some((tenant, Ok(weight))) if weight > 0 => {
+                weights.insert(tenant.to_string(), weight);
+            }
+            _ => warn!("Ignoring invalid JOB_TENANT_WEIGHTS entry: {}", entry),
+        }
+    }
+    weights
+}
+
+fn is_visible(job: &Job, caller: &Caller) -> bool {
+    job.principal == caller.principal && job.tenant == caller.tenant
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sentient_redactor_core::operations::{ErrorKind, UploadProfile};
+    use std::time::Duration;
+
+    fn upload(file_name: &str) -> UploadRequest {
+        serde_json::from_value(serde_json::json!({ "file_name": file_name })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_jobs_report_their_outcome_to_the_submitter() {
+        let queue = Arc::new(JobQueue::from_env());
+        queue.spawn_workers(|_caller: Caller, request: UploadRequest| async move {
+            match request.file_name.as_deref() {
+                Some("ok") => Ok(UploadResponse {
+                    file_id: "f1".to_string(),
+                    filename: "ok_replace_redacted_f1.txt".to_string(),
+                    message: "File uploaded and redacted successfully".to_string(),
+                    relay: None,
+                    external_id: None,
+                    report_summary: None,
+                    review_hold: None,
+                    document_format: None,
+                    language: None,
+                    expires_at: None,
+                    deprecations: Vec::new(),
+                    deduplicated: false,
+                    content_sha256: None,
+                    content: None,
+                    output: None,
+                    report: None,
+                    profile: UploadProfile::default(),
+                }),
+                _ => Err(OperationError::new(ErrorKind::BadRequest, "Decryption failed")),
+            }
+        });
+
+        let alice = Caller { principal: Some("alice".to_string()), tenant: None, scopes: None };
+        let done = queue.submit(alice.clone(), upload("ok"), false).unwrap();
+        let failed = queue.submit(alice.clone(), upload("bad"), false).unwrap();
+        assert_eq!(done.status, JobStatus::Queued);
+
+        for _ in 0..100 {
+            let finished = [&done, &failed].iter().all(|job| {
+                matches!(queue.status(&job.job_id, &alice).unwrap().status, JobStatus::Done | JobStatus::Failed)
+            });
+            if finished {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(queue.status(&done.job_id, &alice).unwrap().file_id.as_deref(), Some("f1"));
+        assert!(matches!(queue.outcome(&done.job_id, &alice), Some(JobOutcome::Done(body)) if body["file_id"] == "f1"));
+        assert!(matches!(queue.outcome(&failed.job_id, &alice), Some(JobOutcome::Failed { status: 400, .. })));
+
+        let bob = Caller { principal: Some("bob".to_string()), tenant: None, scopes: None };
+        assert!(queue.status(&done.job_id, &bob).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_jobs_stream_their_progress() {
+        use sentient_redactor_core::{backend::RedactionBackend, chunking::ChunkedBackend, rules::RegexEngine, EntityFilter};
+
+        let (events, mut received) = broadcast::channel(64);
+        let queue = Arc::new(JobQueue::from_env().with_events(events));
+        queue.spawn_workers(|_caller: Caller, request: UploadRequest| async move {
+            // A pipeline stage, then the pipeline's backend chunking a long text
+            let mut profile = UploadProfile::default();
+            profile.record("decryption", std::time::Instant::now());
+            let text = "Mail jane@example.com about the claim. ".repeat(6);
+            let backend = ChunkedBackend::new(Box::new(RegexEngine::new()), 80, 20, 2).unwrap();
+            backend.detect(&text, EntityFilter::default()).await.unwrap();
+            let file_id = request.file_id.unwrap();
+            Ok(UploadResponse {
+                filename: format!("long_replace_redacted_{}.txt", file_id),
+                file_id,
+                message: "File uploaded and redacted successfully".to_string(),
+                relay: None,
+                external_id: None,
+                report_summary: None,
+                review_hold: None,
+                document_format: None,
+                language: None,
+                expires_at: None,
+                deprecations: Vec::new(),
+                deduplicated: false,
+                content_sha256: None,
+                content: None,
+                output: None,
+                report: None,
+                profile,
+            })
+        });
+
+        let alice = Caller { principal: Some("alice".to_string()), tenant: None, scopes: None };
+        let job = queue.submit(alice.clone(), upload("long"), false).unwrap();
+        let mut names = Vec::new();
+        let mut last_progress = None;
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(5), received.recv()).await.unwrap().unwrap();
+            assert_eq!(event.job_id, job.job_id);
+            names.push(event.name());
+            if let JobEventKind::Progress { chunk, chunks, entities } = event.kind {
+                last_progress = Some((chunk, chunks, entities));
+            }
+            if event.is_final() {
+                assert!(matches!(&event.kind, JobEventKind::Completed { file_id } if Some(file_id) == job.file_id.as_ref()));
+                break;
+            }
+        }
+        assert_eq!((names[0], names[1], names[2], names.last().copied()), ("processing", "stage", "progress", Some("completed")));
+        assert_eq!(queue.status(&job.job_id, &alice).unwrap().stages[0].stage, "decryption");
+        let (chunk, chunks, entities) = last_progress.unwrap();
+        assert!(chunks > 1 && chunk == chunks && entities >= 6);
+    }
+
+    #[tokio::test]
+    async fn test_downloads_can_wait_for_the_file_of_a_queued_upload() {
+        let queue = Arc::new(JobQueue::from_env());
+        queue.spawn_workers(|_caller: Caller, request: UploadRequest| async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let file_id = request.file_id.unwrap();
+            Ok(UploadResponse {
+                filename: format!("slow_replace_redacted_{}.txt", file_id),
+                file_id,
+                message: "File uploaded and redacted successfully".to_string(),
+                relay: None,
+                external_id: None,
+                report_summary: None,
+                review_hold: None,
+                document_format: None,
+                language: None,
+                expires_at: None,
+                deprecations: Vec::new(),
+                deduplicated: false,
+                content_sha256: None,
+                content: None,
+                output: None,
+                report: None,
+                profile: UploadProfile::default(),
+            })
+        });
+
+        let alice = Caller { principal: Some("alice".to_string()), tenant: None, scopes: None };
+        let job = queue.submit(alice.clone(), upload("slow"), false).unwrap();
+        let file_id = job.file_id.clone().unwrap();
+
+        let pending = queue.wait_for_file(&file_id, &alice, Duration::from_millis(10)).await.unwrap();
+        assert!(pending.is_pending());
+        let bob = Caller { principal: Some("bob".to_string()), tenant: None, scopes: None };
+        assert!(queue.wait_for_file(&file_id, &bob, Duration::ZERO).await.is_none());
+
+        let done = queue.wait_for_file(&file_id, &alice, Duration::from_secs(5)).await.unwrap();
+        assert_eq!((done.status, done.file_id.as_deref()), (JobStatus::Done, Some(file_id.as_str())));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_runnable_jobs() {
+        let policy: RedactionPolicy = serde_json::from_value(serde_json::json!({
+            "processing_windows": { "acme": { "start": "00:00", "end": "00:01" } }
+        }))
+        .unwrap();
+        let queue = Arc::new(JobQueue::from_env().with_policy(Arc::new(policy)));
+        let alice = Caller { principal: Some("alice".to_string()), tenant: None, scopes: None };
+        let pending = queue.submit(alice.clone(), upload("slow"), false).unwrap();
+        // Without workers nothing can finish
+        assert_eq!(queue.drain().await, vec![pending.job_id.clone()]);
+
+        queue.spawn_workers(|_caller: Caller, _request: UploadRequest| async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Err(OperationError::new(ErrorKind::BadRequest, "Decryption failed"))
+        });
+        let held = Caller { principal: None, tenant: Some("acme".to_string()), scopes: None };
+        let held_job = queue.submit(held, upload("batch"), false).unwrap();
+        let unfinished = queue.drain().await;
+
+        // Past the window's single minute, the held job is the one left
+        if held_job.held_until.is_some() {
+            assert_eq!(unfinished, [held_job.job_id]);
+        }
+        assert_eq!(queue.status(&pending.job_id, &alice).unwrap().status, JobStatus::Failed);
+    }
+
+    #[test]
+    fn test_tenants_share_the_queue_by_weight() {
+        let bulk = Some("bulk".to_string());
+        let acme = Some("acme".to_string());
+        let mut queue = FairQueue::new(tenant_weights("acme=3, bulk=1, broken"), Duration::from_secs(60));
+        for i in 0..100 {
+            queue.push(bulk.clone(), format!("bulk-{}", i));
+        }
+        assert_eq!(queue.pop(|_| true).as_deref(), Some("bulk-0"));
+        for i in 0..6 {
+            queue.push(acme.clone(), format!("acme-{}", i));
+        }
+        queue.push(None, "anonymous".to_string());
+
+        // Later tenants are not stuck behind the backlog, and get three turns per bulk one
+        let served: Vec<String> = (0..9).filter_map(|_| queue.pop(|_| true)).collect();
+        assert_eq!(served.iter().filter(|job| job.starts_with("acme")).count(), 6);
+        assert!(served.contains(&"anonymous".to_string()));
+        assert_eq!(queue.backlog().iter().map(|backlog| (backlog.tenant.clone(), backlog.weight)).collect::<Vec<_>>(), [(bulk, 1)]);
+
+        // Past the wait limit, the oldest job goes first whatever the weights
+        let mut queue = FairQueue::new(tenant_weights("acme=100"), Duration::ZERO);
+        queue.push(None, "first");
+        std::thread::sleep(Duration::from_millis(2));
+        queue.push(acme.clone(), "second");
+        assert_eq!((queue.pop(|_| true), queue.pop(|_| true)), (Some("first"), Some("second")));
+        assert_eq!(queue.len, 0);
+    }
+
+    #[test]
+    fn test_jobs_wait_for_their_processing_window() {
+        let acme = Some("acme".to_string());
+        let mut queue = FairQueue::new(HashMap::new(), Duration::ZERO);
+        queue.push(acme.clone(), "batch");
+        std::thread::sleep(Duration::from_millis(2));
+        queue.push(None, "interactive");
+
+        // Waiting longest does not let a job past its closed window
+        let closed = |tenant: &Option<String>| *tenant != acme;
+        assert_eq!(queue.pop(closed), Some("interactive"));
+        assert_eq!((queue.pop(closed), queue.len), (None, 1));
+        assert_eq!(queue.pop(|_| true), Some("batch"));
+
+        let policy: RedactionPolicy = serde_json::from_value(serde_json::json!({
+            "processing_windows": { "acme": { "start": "00:00", "end": "06:00" } }
+        }))
+        .unwrap();
+        let jobs = JobQueue::from_env().with_policy(Arc::new(policy));
+        let noon = 19_000 * 86400 + 12 * 3600;
+        assert_eq!(jobs.held_until(Some("acme"), noon), Some(noon + 12 * 3600));
+        assert_eq!(jobs.held_until(Some("other"), noon), None);
+    }
+
+    #[tokio::test]
+    async fn test_pending_jobs_are_recovered_after_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("jobs.jsonl");
+        let path = path.to_str();
+        let alice = Caller { principal: Some("alice".to_string()), tenant: None, scopes: None };
+
+        let queue = JobQueue::from_env().with_journal(path).unwrap();
+        let mut binary = upload("binary");
+        binary.ciphertext = Some(vec![1, 2, 3]);
+        let queued = queue.submit(alice.clone(), binary, false).unwrap();
+        let running = queue.submit(alice.clone(), upload("running"), false).unwrap();
+        let failed = queue.submit(alice.clone(), upload("bad"), false).unwrap();
+        queue.update(&running.job_id, |job| job.view.status = JobStatus::Processing);
+        queue.journal(&running.job_id, None);
+        queue.finish(&failed.job_id, Err(OperationError::new(ErrorKind::BadRequest, "Decryption failed")), false);
+        drop(queue);
+
+        // The interrupted job gets a second run, which a restart interrupts as well
+        let queue = JobQueue::from_env().with_journal(path).unwrap();
+        assert_eq!(queue.status(&running.job_id, &alice).unwrap().status, JobStatus::Queued);
+        queue.update(&running.job_id, |job| job.view.status = JobStatus::Processing);
+        queue.journal(&running.job_id, None);
+        drop(queue);
+
+        let queue = Arc::new(JobQueue::from_env().with_journal(path).unwrap());
+        assert!(matches!(
+            queue.outcome(&running.job_id, &alice),
+            Some(JobOutcome::Failed { status: 500, code: Some(JOB_INTERRUPTED), .. })
+        ));
+        assert!(matches!(queue.outcome(&failed.job_id, &alice), Some(JobOutcome::Failed { status: 400, .. })));
+
+        queue.spawn_workers(|_caller: Caller, request: UploadRequest| async move {
+            assert_eq!(request.ciphertext.as_deref(), Some(&[1u8, 2, 3][..]));
+            Err(OperationError::new(ErrorKind::BadRequest, request.file_id.unwrap_or_default()))
+        });
+        for _ in 0..100 {
+            if !queue.status(&queued.job_id, &alice).unwrap().is_pending() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // Rerun under the file id it was given when first queued
+        let view = queue.status(&queued.job_id, &alice).unwrap();
+        assert_eq!(view.status, JobStatus::Failed);
+        assert_eq!(view.error, queued.file_id);
+    }
+}
@@ -1,214 +1,3720 @@
 use axum::{
-    extract::State,
-    http::{HeaderMap, StatusCode},
-    response::IntoResponse,
-    routing::{get, post},
-    Json, Router,
+    body::{Body, Bytes},
+    extract::{multipart::Field, ConnectInfo, DefaultBodyLimit, MatchedPath, Multipart, Path, Query, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{sse::{Event, KeepAlive, Sse}, IntoResponse, Response},
+    routing::{delete, get, patch, post},
+    Extension, Json, Router,
 };
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use futures_util::StreamExt;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tracing::{info, warn};
-use uuid::Uuid;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, RwLock};
+use tokio_util::io::{ReaderStream, SyncIoBridge};
+use tokio_util::sync::CancellationToken;
+use tower::ServiceBuilder;
+use tower_http::catch_panic::CatchPanicLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tracing::{error, info, warn};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use utoipa_swagger_ui::SwaggerUi;
 
-mod crypto;
-mod redactor;
-mod storage;
+mod admin;
+mod alerts;
+mod audit;
+mod batch;
+mod bulk;
+mod chunked;
+mod cli;
+mod compression;
+mod crashes;
+mod discovery;
+mod estimate;
+mod expectations;
+mod feedback;
+mod fetch;
+mod flags;
+mod grpc;
+#[cfg(test)]
+mod harness;
+mod idempotency;
+mod jobs;
+mod maintenance;
+mod metrics;
+mod notifier;
+mod openapi;
+mod profiling;
+mod propagation;
+mod provisioning;
+mod quality;
+mod ratelimit;
+mod readiness;
+mod replica;
+mod replication;
+mod shares;
+mod simple;
+mod stream;
+mod tiering;
+mod tls;
 
-use crypto::CryptoService;
-use redactor::RedactorService;
-use storage::FileStorage;
+use admin::Admin;
+use alerts::{Alert, Alerter, Severity};
+use audit::{AuditAnchor, AuditLog, AuditPage, AuditQuery, AuditRecord, ChainVerification};
+use batch::{BatchItemResult, BatchUploadRequest, BatchUploadResponse};
+use bulk::BulkDownloadRequest;
+use chunked::{ChunkError, ChunkedUpload};
+use clap::Parser;
+use cli::Cli;
+use compression::CompressionConfig;
+use crashes::{CrashReport, CrashReporter, Panicked};
+use discovery::{ConsulRegistration, ServiceLabels};
+use estimate::{Estimate, EstimateRequest, ThroughputStats};
+use expectations::{ExpectationRequest, ExpectationStore, ExpectationView};
+use feedback::{FeedbackEntry, FeedbackRequest, FeedbackStore, FeedbackSummary};
+use fetch::{BlobFetcher, FetchError};
+use flags::FeatureFlags;
+use idempotency::{Claim, IdempotencyCache, RequestKey, StoredResponse};
+use jobs::{JobEvent, JobOutcome, JobQueue, JobView};
+use maintenance::{MaintenanceMode, MaintenanceRequest, MaintenanceStatus};
+use metrics::Metrics;
+use notifier::{Callback, Delivery, DeliveryQuery, Notifier};
+use openapi::{ApiDoc, MultipartUpload};
+use profiling::SlowUploadLog;
+use propagation::{DeletionNotice, DeletionPropagator, TargetStatus};
+use provisioning::KeyProvisioner;
+use quality::QualitySampler;
+use ratelimit::{RateLimiter, Throttled};
+use readiness::DependencyStatus;
+use replica::ReadOnlyMode;
+use replication::{Replicator, StorageTarget};
+use sentient_redactor_core::{
+    acl::{self, AclOperation, AclPatch, FileAcl},
+    attestation::Attester,
+    auth::{self, AuthChain, Credentialed},
+    caller::{Caller, Scope},
+    compression::Compression,
+    config::AppConfig,
+    crypto::{self, CryptoService, PayloadCipher, StreamOpener},
+    deprecation::{Deprecation, ProtocolDeprecations},
+    document::DocumentFormat,
+    erasure::ErasureReceipt,
+    escrow::EscrowBundle,
+    EntityFilter,
+    heatmap::Heatmap,
+    manifest::{self, OutputSignature},
+    masking::{self, HashMasking},
+    operations::{
+        self, redaction_error, AnalysisResponse, DownloadedFile, EncryptedDownload, ErrorKind, ExternalIdMatch, FileFilter, FileList, HandshakeResponse,
+        OperationError, RedactedUpload, ReportMatch, ReprocessRequest, UploadContext, UploadProfile, UploadRequest, UploadResponse,
+    },
+    policy::{OutsideWindow, RedactionPolicy},
+    presidio::ContractMismatch,
+    redactor::RedactorService,
+    relay::RelayRegistry,
+    s3::{S3Config, S3Storage},
+    secret::{self, Retained},
+    report::ReportQuery,
+    resilience::{CircuitState, CircuitStatus, Stage},
+    redactor::{RedactionOptions, PSEUDONYMIZE},
+    selftest::{self, SelftestReport},
+    session::{SessionManager, SessionRequest, StreamCipher},
+    settings::{RuntimeSettings, SharedSettings},
+    storage::{DiskStorage, FileStorage, Storage, StorageLimits},
+    usage::{Usage, UsageTotals},
+    structured::ContentType,
+    views::DownloadFormat,
+};
+use shares::{ShareError, ShareStore};
+use simple::{SimpleFields, SimpleMode};
+use tiering::ColdTiering;
 
 #[derive(Clone)]
 struct AppState {
-    crypto_service: Arc<CryptoService>,
+    key_provisioner: Arc<KeyProvisioner>,
     redactor_service: Arc<RedactorService>,
-    file_storage: Arc<RwLock<FileStorage>>,
+    file_storage: Arc<RwLock<Box<dyn Storage>>>,
+    feedback_store: Arc<RwLock<FeedbackStore>>,
+    relay_registry: Arc<RelayRegistry>,
+    share_store: Arc<RwLock<ShareStore>>,
+    expectations: Arc<RwLock<ExpectationStore>>,
+    audit_log: Arc<RwLock<AuditLog>>,
+    blob_fetcher: Arc<BlobFetcher>,
+    simple_mode: Arc<SimpleMode>,
+    metrics: Arc<Metrics>,
+    notifier: Arc<Notifier>,
+    slow_uploads: Arc<SlowUploadLog>,
+    jobs: Arc<JobQueue>,
+    // Progress of upload jobs, for `GET /jobs/:job_id/events`
+    job_events: broadcast::Sender<JobEvent>,
+    alerts: Arc<Alerter>,
+    propagation: Arc<DeletionPropagator>,
+    attester: Arc<Attester>,
+    maintenance: Arc<MaintenanceMode>,
+    flags: Arc<FeatureFlags>,
+    throughput: Arc<ThroughputStats>,
+    policy: Arc<RedactionPolicy>,
+    sessions: Arc<SessionManager>,
+    deprecations: Arc<ProtocolDeprecations>,
+    labels: Arc<ServiceLabels>,
+    rate_limiter: Arc<RateLimiter>,
+    // What `PUT /admin/config` can change while the service runs
+    settings: SharedSettings,
+    crashes: Arc<CrashReporter>,
+    idempotency: Arc<IdempotencyCache>,
+    // What an upload's compressed plaintext may inflate to
+    max_decompressed_bytes: usize,
+    // Default `min_occurrences` of feedback summaries
+    feedback_allowlist_min_occurrences: usize,
+    // Set on read-only replicas
+    read_only: Option<Arc<ReadOnlyMode>>,
+    // Set when stored files are copied to a replica backend
+    replicator: Option<Arc<Replicator>>,
 }
 
-#[derive(Deserialize)]
-struct UploadRequest {
-    encrypted_data: String,
-    encrypted_session_key: String,
-    file_name: Option<String>,
-    redaction_strategy: Option<String>,
+#[derive(Deserialize, ToSchema)]
+struct UploadBody {
+    #[serde(flatten)]
+    upload: UploadRequest,
+    #[serde(flatten)]
+    simple: SimpleFields,
 }
 
+// Upload response marked as produced by developer simple mode
 #[derive(Serialize)]
-struct UploadResponse {
-    file_id: String,
-    filename: String,
-    message: String,
+struct SimpleUploadResponse {
+    #[serde(flatten)]
+    upload: UploadResponse,
+    mode: &'static str,
 }
 
+// Upload response with the stage breakdown, for `?profile=true`
 #[derive(Serialize)]
+struct ProfiledUploadResponse {
+    #[serde(flatten)]
+    upload: UploadResponse,
+    profile: UploadProfile,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct HandshakeQuery {
+    // Client challenge bound into the attestation report, proving it is fresh
+    nonce: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct StreamQuery {
+    strategy: Option<String>,
+    // Hex digits kept of each `hash` surrogate
+    hash_length: Option<usize>,
+    language: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AnalyzeQuery {
+    // Replace each snippet's characters with `*`, leaving only types, positions and scores
+    #[serde(default)]
+    mask_snippets: bool,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct UploadQuery {
+    #[serde(default)]
+    profile: bool,
+    // Queue the upload and answer with a job id instead of waiting for the redaction
+    #[serde(default, rename = "async")]
+    run_async: bool,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct UploadFromUrlRequest {
+    source_url: String,
+    #[serde(flatten)]
+    upload: UploadRequest,
+}
+
+// Every error body: a message for people and a stable code for clients, the specific
+// one when the failure has one, otherwise that of its status
+#[derive(Serialize, ToSchema)]
 struct ErrorResponse {
     error: String,
+    code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    link: Option<&'static str>,
+    // Set on failures operators are asked about, matching the `X-Request-Id` header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+// Upload refused while maintenance mode is on, with when it is expected to end
+#[derive(Serialize, ToSchema)]
+struct MaintenanceResponse {
+    error: Option<String>,
+    code: &'static str,
+    eta: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct HealthResponse {
+    // `healthy`, or `degraded` while a backend's circuit is open
+    status: &'static str,
+    service: &'static str,
+    circuits: Vec<CircuitStatus>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ReadinessResponse {
+    ready: bool,
+    // Attempts at provisioning the service key so far
+    attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_error: Option<String>,
+    dependencies: BTreeMap<&'static str, DependencyStatus>,
+    labels: ServiceLabels,
+}
+
+// What this instance supports, for clients to pick modes without trial and error
+#[derive(Serialize, ToSchema)]
+struct Capabilities {
+    protocol_version: &'static str,
+    key_exchange: Vec<&'static str>,
+    ciphers: Vec<&'static str>,
+    deprecations: Vec<Deprecation>,
+    redaction_strategies: &'static [&'static str],
+    content_encodings: &'static [&'static str],
+    document_formats: &'static [&'static str],
+    simple_mode: bool,
+    // Whether the caller's tenant may download redacted content
+    downloads: bool,
+    attestation: Option<&'static str>,
+    feature_flags: BTreeMap<String, bool>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct HeatmapResponse {
+    file_id: String,
+    heatmap: Heatmap,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ReleaseResponse {
+    file_id: String,
+    released: bool,
+    // Rules the lifted hold was placed by
+    rules: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SearchResponse {
+    files: Vec<ReportMatch>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ExpectationCreated {
+    token: String,
+    expires_at: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct TenantStats {
+    tenant: String,
+    totals: Usage,
+}
+
+// `GET /admin/stats`: every tenant's totals, or one tenant's with `?tenant=`
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+enum StatsResponse {
+    All(UsageTotals),
+    Tenant(TenantStats),
+}
+
+#[derive(Serialize, ToSchema)]
+struct KeyList {
+    // `kid` new uploads are wrapped to
+    current: String,
+    // Every key still accepted, the current one included
+    keys: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct RotatedKey {
+    kid: String,
+    // Stored files rewrapped to the new key
+    rewrapped: usize,
+}
+
+// Who may read an erasure receipt: whoever could see the file, as recorded with it
+#[derive(Deserialize)]
+struct ReceiptAccess {
+    tenant: Option<String>,
+    acl: Option<FileAcl>,
+}
+
+// A signed erasure receipt, plus where propagation to delivery targets has got since it
+// was issued. The propagation fields are not covered by the signature.
+#[derive(Serialize, ToSchema)]
+struct ErasureReceiptResponse {
+    #[serde(flatten)]
+    receipt: ErasureReceipt,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    propagation_complete: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    propagation: Option<Vec<TargetStatus>>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct FeedbackSummaryQuery {
+    min_occurrences: Option<usize>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct ShareRequest {
+    ttl_seconds: Option<u64>,
+    password: Option<String>,
+}
+
+// Why a retained original is read, kept in the audit trail
+#[derive(Deserialize, ToSchema)]
+struct OriginalRequest {
+    reason: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ShareResponse {
+    token: String,
+    url: String,
+    expires_at: u64,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SearchQuery {
+    entity: Option<String>,
+    min_count: Option<usize>,
+    from: Option<u64>,
+    to: Option<u64>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ListQuery {
+    name_prefix: Option<String>,
+    created_after: Option<u64>,
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DownloadQuery {
+    #[serde(default)]
+    encrypted: bool,
+    #[serde(default)]
+    format: DownloadFormat,
+    // Embed the file's signed processing manifest
+    #[serde(default)]
+    manifest: bool,
+    // How long to wait for the file's upload job, e.g. `30s` or `500ms`
+    #[serde(default, deserialize_with = "wait_duration")]
+    #[param(value_type = Option<String>)]
+    wait: Option<Duration>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DocumentQuery {
+    #[serde(default)]
+    encrypted: bool,
+    // Serve a zip of the document and its signed processing manifest
+    #[serde(default)]
+    manifest: bool,
+}
+
+// `<n>s`, `<n>ms` or bare seconds, up to `MAX_DOWNLOAD_WAIT_SECONDS`
+fn wait_duration<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    let parsed = match value.strip_suffix("ms") {
+        Some(millis) => millis.parse().map(Duration::from_millis),
+        None => value.strip_suffix('s').unwrap_or(&value).parse().map(Duration::from_secs),
+    };
+    match parsed {
+        Ok(wait) if wait <= Duration::from_secs(MAX_DOWNLOAD_WAIT_SECONDS) => Ok(Some(wait)),
+        _ => Err(serde::de::Error::custom(format!(
+            "wait must be a duration such as 30s or 500ms, at most {}s",
+            MAX_DOWNLOAD_WAIT_SECONDS
+        ))),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SignatureQuery {
+    #[serde(default)]
+    format: DownloadFormat,
+    // Sign the download with its processing manifest embedded
+    #[serde(default)]
+    manifest: bool,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PreviewQuery {
+    bytes: Option<usize>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct StatsQuery {
+    tenant: Option<String>,
 }
 
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SelftestQuery {
+    // Comma-separated entity types to test instead of all the backend detects
+    entities: Option<String>,
+    // Run under this tenant's pipelines
+    tenant: Option<String>,
+}
+
+const DEFAULT_SEARCH_LIMIT: usize = 100;
+// Job events kept for subscribers that fall behind
+const JOB_EVENT_CAPACITY: usize = 1024;
+const MAX_SEARCH_LIMIT: usize = 1000;
+const DEFAULT_PREVIEW_BYTES: usize = 4096;
+const MAX_PREVIEW_BYTES: usize = 65536;
+const DEFAULT_SHARE_TTL_SECONDS: u64 = 3600;
+const MAX_SHARE_TTL_SECONDS: u64 = 86400;
+const MAX_ORIGINAL_REASON_CHARS: usize = 500;
+const DEFAULT_ORPHAN_GC_SECONDS: u64 = 300;
+const DEFAULT_SESSION_IDLE_SECONDS: u64 = 900;
+const MAX_DOWNLOAD_WAIT_SECONDS: u64 = 60;
+// Longest plain-text rejection kept as the message of its JSON error body
+const MAX_REJECTION_BYTES: usize = 4096;
+// Set on each request by `SetRequestIdLayer` unless the client sent one, and echoed on the response
+const REQUEST_ID: &str = "x-request-id";
+// Hex SHA-256 of a download's body, as `content_sha256` in the upload response
+const CONTENT_SHA256: &str = "X-Content-SHA256";
+const DEFAULT_ORPHAN_FILE_AGE_SECONDS: u64 = 3600;
+
 
 
 #[tokio::main]
 async fn main() {
     // Initialize tracing
     tracing_subscriber::fmt::init();
+    crashes::install_hook();
 
     info!("Starting Sentient TEE Redactor Service...");
+    let config = Cli::parse().load_config().expect("Failed to load configuration");
 
     // Initialize services
-    let crypto_service = Arc::new(CryptoService::new());
-    let redactor_service = Arc::new(RedactorService::new());
-    let file_storage = Arc::new(RwLock::new(FileStorage::new()));
+    let key_provisioner = Arc::new(KeyProvisioner::from_env().with_legacy_zero_nonce(config.allow_legacy_zero_nonce));
+    let state = build_state(&config, key_provisioner.clone()).await;
+    match config.presidio_contract_check.as_str() {
+        "strict" => check_presidio_contract(state.redactor_service.clone(), true).await,
+        "warn" => {
+            tokio::spawn(check_presidio_contract(state.redactor_service.clone(), false));
+        }
+        _ => {}
+    }
+    key_provisioner.clone().spawn();
+    spawn_background(&state, &config);
+
+    let auth_chain = Arc::new(AuthChain::from_env().expect("Failed to configure authentication"));
+    let app = router(&state, &config, auth_chain.clone());
+
+    // Both interfaces stop taking requests on the same signal
+    let shutdown = CancellationToken::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal().await;
+            shutdown.cancel();
+        }
+    });
+
+    let server_tls = config.server_tls().expect("Failed to configure TLS");
+    let grpc_server = config.grpc_socket_addr().map(|addr| {
+        let shutdown = shutdown.clone().cancelled_owned();
+        tokio::spawn(grpc::serve(addr, state.clone(), auth_chain.clone(), config.max_upload_bytes, server_tls.clone(), shutdown))
+    });
+
+    // Start server
+    let listener = tokio::net::TcpListener::bind(config.socket_addr()).await.unwrap();
+    let scheme = if server_tls.is_some() { "https" } else { "http" };
+    info!("Server listening on {}://{}", scheme, config.socket_addr());
+
+    let consul = ConsulRegistration::from_env(&config, &state.labels)
+        .expect("Failed to configure Consul registration")
+        .map(Arc::new);
+    if let Some(consul) = &consul {
+        consul.spawn();
+    }
+
+    match server_tls {
+        Some(server_tls) => tls::serve(listener, server_tls, app, shutdown.cancelled_owned()).await,
+        None => axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+            .with_graceful_shutdown(shutdown.cancelled_owned())
+            .await
+            .unwrap(),
+    }
+    if let Some(consul) = consul {
+        consul.deregister().await;
+    }
+    if let Some(grpc_server) = grpc_server {
+        let _ = grpc_server.await;
+    }
+    drain(&state).await;
+}
+
+// Every service the handlers share, from `config` and the environment. The service key
+// comes from `key_provisioner` once it is provisioned.
+async fn build_state(config: &AppConfig, key_provisioner: Arc<KeyProvisioner>) -> AppState {
+    let settings = config.runtime_settings().expect("Invalid configuration").shared();
+    let redactor_service = Arc::new(RedactorService::from_settings(config, settings.clone()).expect("Failed to initialize redactor service"));
+    let limits = config.storage_limits().expect("Invalid storage limits");
+    let file_storage: Arc<RwLock<Box<dyn Storage>>> = Arc::new(RwLock::new(Box::new(
+        FileStorage::new().with_default_ttl(config.default_file_ttl_seconds).with_limits(limits.clone()),
+    )));
+    let replica = StorageTarget::replica(config);
+    let primary = match &replica {
+        Some(replica) if config.storage_failover => {
+            warn!("Failing over: serving from the replica {} instead of the primary storage", replica);
+            Some(replica.clone())
+        }
+        _ => StorageTarget::primary(config).expect("Invalid S3 storage configuration"),
+    };
+    match primary {
+        Some(StorageTarget::Disk(dir)) => {
+            spawn_disk_storage(dir, config.default_file_ttl_seconds, limits, file_storage.clone(), key_provisioner.clone()).await;
+        }
+        Some(StorageTarget::S3(s3)) => {
+            spawn_s3_storage(s3, config.default_file_ttl_seconds, limits, file_storage.clone(), key_provisioner.clone()).await;
+        }
+        None => {}
+    }
+    // Only the instance taking uploads copies them, and not while serving from the replica
+    let replicator = replica.filter(|_| !config.storage_failover && !config.read_only).map(|target| Arc::new(Replicator::new(target)));
+    let feedback_store = Arc::new(RwLock::new(FeedbackStore::open(config.feedback_path.as_deref()).expect("Failed to open the feedback store")));
+    let relay_registry = Arc::new(RelayRegistry::from_env().expect("Failed to load relay identities"));
+    let share_store = Arc::new(RwLock::new(ShareStore::new()));
+    let audit_log = Arc::new(RwLock::new(AuditLog::from_env().expect("Failed to open audit log")));
+    let blob_fetcher = Arc::new(BlobFetcher::from_env().expect("Failed to configure upload from URL"));
+    let simple_mode = Arc::new(SimpleMode::from_env().expect("Failed to configure simple mode"));
+    let metrics = Arc::new(Metrics::new().expect("Failed to initialize metrics"));
+    let slow_uploads = Arc::new(SlowUploadLog::from_env());
+    let policy = Arc::new(RedactionPolicy::from_env().expect("Failed to load redaction policy"));
+    let alerts = Arc::new(Alerter::from_config(config).expect("Failed to configure operator alerts"));
+    alerts.watch_circuits(redactor_service.clone());
+    let (job_events, _) = broadcast::channel(JOB_EVENT_CAPACITY);
+    let jobs = Arc::new(
+        JobQueue::from_env()
+            .with_policy(policy.clone())
+            .with_alerts(alerts.clone())
+            .with_events(job_events.clone())
+            .with_journal(config.job_journal_path.as_deref())
+            .expect("Failed to recover upload jobs"),
+    );
+    let attester = Arc::new(Attester::from_env().expect("Failed to configure attestation"));
+    if let Some(anchor) = AuditAnchor::from_env().expect("Failed to configure audit anchoring") {
+        anchor.spawn(audit_log.clone());
+    }
 
-    let state = AppState {
-        crypto_service,
+    let labels = Arc::new(ServiceLabels::new(config, redactor_service.backend_name()));
+    AppState {
+        key_provisioner,
         redactor_service,
         file_storage,
-    };
+        feedback_store,
+        relay_registry,
+        share_store,
+        expectations: Arc::new(RwLock::new(ExpectationStore::new())),
+        audit_log,
+        blob_fetcher,
+        simple_mode,
+        metrics,
+        notifier: Arc::new(
+            Notifier::from_env()
+                .and_then(|notifier| notifier.with_outbox(config.callback_outbox_path.as_deref()))
+                .expect("Failed to configure callbacks"),
+        ),
+        slow_uploads,
+        jobs,
+        job_events,
+        alerts,
+        propagation: Arc::new(DeletionPropagator::from_config(config).await.expect("Failed to configure deletion propagation")),
+        attester,
+        maintenance: Arc::new(MaintenanceMode::new()),
+        flags: Arc::new(FeatureFlags::from_env().expect("Failed to load feature flags")),
+        throughput: Arc::new(ThroughputStats::new()),
+        policy,
+        sessions: Arc::new(SessionManager::new(config.session_ttl_seconds, config.session_max_uploads)),
+        deprecations: Arc::new(config.protocol_deprecations().expect("Invalid protocol deprecations")),
+        labels,
+        rate_limiter: Arc::new(RateLimiter::from_settings(&settings.load()).expect("Failed to configure rate limiting")),
+        settings,
+        crashes: Arc::new(CrashReporter::from_config(config).expect("Failed to configure crash reports")),
+        idempotency: Arc::new(IdempotencyCache::from_env()),
+        max_decompressed_bytes: config.max_decompressed_bytes,
+        feedback_allowlist_min_occurrences: config.feedback_allowlist_min_occurrences,
+        read_only: ReadOnlyMode::from_config(config).map(Arc::new),
+        replicator,
+    }
+}
+
+// Background work: flag reloads, and the expiry sweep, usage snapshots, orphan cleanup
+// and job workers, or on replicas the storage refresh
+fn spawn_background(state: &AppState, config: &AppConfig) {
+    let worker_state = state.clone();
+    state.flags.spawn_reload();
+    // Replicas leave expiry, usage and cleanup to the instance that writes the storage
+    match &state.read_only {
+        Some(read_only) => read_only.spawn_refresh(state.file_storage.clone(), state.key_provisioner.clone()),
+        None => {
+            spawn_expiry_sweep(state.clone(), config.file_expiry_sweep_seconds, config.original_retention_seconds);
+            spawn_usage_snapshots(state.file_storage.clone(), config.usage_snapshot_seconds);
+            spawn_orphan_gc(state.clone());
+            state.notifier.resume();
+            if let Some(replicator) = &state.replicator {
+                replicator.spawn(state.file_storage.clone(), state.key_provisioner.clone());
+            }
+            if let Some(tiering) = ColdTiering::from_config(config) {
+                spawn_cold_tiering(state.clone(), tiering);
+            }
+            if let Some(sampler) = QualitySampler::from_config(config).expect("Failed to configure quality sampling") {
+                spawn_quality_sampling(state.clone(), Arc::new(sampler));
+            }
+            state.jobs.spawn_workers(move |caller, upload| {
+                let state = worker_state.clone();
+                async move {
+                    let crypto_service = state.key_provisioner.get().ok_or_else(|| {
+                        OperationError::new(ErrorKind::Internal, "Service key is not provisioned yet")
+                    })?;
+                    run_upload(&state, crypto_service, &caller, upload).await
+                }
+            });
+        }
+    }
+}
 
+fn router(state: &AppState, config: &AppConfig, auth_chain: Arc<AuthChain>) -> Router {
+    let compression = CompressionConfig::from_env();
 
-    // Build router
-    let app = Router::new()
-        .route("/health", get(health_check))
-        .route("/handshake", get(handshake))
-        .route("/upload", post(upload_file))
+    // JSON metadata routes (listings, reports) are compressed when large
+    let metadata_routes = Router::new()
+        .route("/handshake", get(handshake).post(create_session))
+        .route("/capabilities", get(capabilities))
+        .route("/feedback/summary", get(feedback_summary))
+        .layer(compression.response_layer());
+
+    // Text downloads are compressed like metadata when the client accepts it; zips and
+    // documents already are
+    let download_routes = Router::new()
         .route("/download/:file_id", get(download_file))
-        .with_state(state);
+        .route("/files/:file_id/preview", get(preview_file))
+        .route("/files/:file_id/unredact", post(unredact_file))
+        .route("/files/:file_id/original", post(original_file))
+        .route("/share/:token", get(redeem_share))
+        .layer(compression.response_layer());
 
-    // Start server
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:10003").await.unwrap();
-    info!("Server listening on http://0.0.0.0:10003");
+    // Probes stay reachable when authentication is required. `/health` and `/ready` are
+    // the older names of the liveness and readiness probes.
+    let probe_routes = Router::new()
+        .route("/health", get(health_check))
+        .route("/health/live", get(health_check))
+        .route("/ready", get(readiness_check))
+        .route("/health/ready", get(readiness_check))
+        .merge(SwaggerUi::new("/docs").url("/openapi.json", ApiDoc::openapi()));
+
+    // Routes that run a redaction pipeline, bounded by `MAX_CONCURRENT_REDACTIONS`. Those
+    // storing files replay retries sent with an idempotency key; the layer reads the body
+    // under the route's own limit, and sees bodies still compressed.
+    let idempotent = |limit: usize| middleware::from_fn_with_state((state.clone(), limit), idempotent);
+    let pipeline_routes = Router::new()
+        .route("/upload", post(upload_file).layer(ServiceBuilder::new().layer(DefaultBodyLimit::max(config.max_upload_bytes)).layer(idempotent(config.max_upload_bytes)).layer(compression.request_layer())))
+        .route("/upload/from-url", post(upload_from_url).layer(idempotent(config.max_request_bytes)))
+        .route("/analyze", post(analyze_upload).layer(ServiceBuilder::new().layer(DefaultBodyLimit::max(config.max_upload_bytes)).layer(compression.request_layer())))
+        .route("/redact/stream", post(redact_stream))
+        .route("/upload/multipart", post(upload_multipart).layer(ServiceBuilder::new().layer(DefaultBodyLimit::max(config.multipart_max_bytes)).layer(idempotent(config.multipart_max_bytes)).layer(compression.request_layer())))
+        .route("/upload/stream", post(upload_stream).layer(DefaultBodyLimit::max(config.stream_upload_max_bytes)))
+        .route("/upload/batch", post(upload_batch).layer(ServiceBuilder::new().layer(DefaultBodyLimit::max(batch::max_bytes())).layer(idempotent(batch::max_bytes())).layer(compression.request_layer())))
+        .route("/files/:file_id/reprocess", post(reprocess_file).layer(idempotent(config.max_request_bytes)))
+        .route_layer(middleware::from_fn_with_state(state.clone(), bound_pipelines));
 
-    axum::serve(listener, app).await.unwrap();
+    Router::new()
+        .route("/metrics", get(metrics_handler))
+        .route("/uploads/expectations", post(create_expectation))
+        .route("/uploads/expectations/:token", get(get_expectation))
+        .route("/estimate", post(estimate_upload))
+        .route("/download/bulk", post(download_bulk))
+        .route("/files", get(list_files))
+        .route("/files/:file_id", delete(delete_file))
+        .route("/files/by-external/:external_id", get(find_by_external_id))
+        .route("/files/search", get(search_reports))
+        .route("/files/:file_id/report", get(get_report))
+        .route("/files/:file_id/heatmap", get(get_heatmap))
+        .route("/files/:file_id/release", post(release_file))
+        .route("/files/:file_id/document", get(download_document))
+        .route("/files/:file_id/signature", get(get_signature))
+        .route("/jobs/:job_id", get(job_status))
+        .route("/jobs/:job_id/result", get(job_result))
+        .route("/jobs/:job_id/events", get(job_event_stream))
+        .route("/files/:file_id/feedback", post(submit_feedback))
+        .route("/files/:file_id/acl", patch(update_acl))
+        .route("/files/:file_id/share", post(create_share))
+        .route("/audit", get(list_audit_records))
+        .route("/audit/receipts/:file_id", get(get_erasure_receipt))
+        .route("/audit/verify", get(verify_audit_chain))
+        .route("/admin/config", get(get_runtime_config).put(set_runtime_config))
+        .route("/admin/escrow", get(export_escrow))
+        .route("/admin/keys", get(list_keys))
+        .route("/admin/keys/rotate", post(rotate_key))
+        .route("/admin/keys/:kid", delete(retire_key))
+        .route("/admin/maintenance", get(get_maintenance).put(set_maintenance))
+        .route("/admin/stats", get(get_stats))
+        .route("/admin/webhooks/deliveries", get(list_deliveries))
+        .route("/admin/webhooks/deliveries/:delivery_id/retry", post(resend_delivery))
+        .route("/admin/secrets", get(get_retained_secrets))
+        .route("/admin/selftest/redaction", get(redaction_selftest))
+        .merge(pipeline_routes)
+        .merge(metadata_routes)
+        .merge(download_routes)
+        // Upload routes above set their own limits
+        .layer(DefaultBodyLimit::max(config.max_request_bytes))
+        // Inside authentication, so callers are counted by principal
+        .layer(middleware::from_fn_with_state(state.clone(), rate_limit))
+        .layer(middleware::from_fn_with_state(auth_chain.clone(), auth::middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), reject_writes))
+        .merge(probe_routes)
+        .layer(CatchPanicLayer::custom(crashes::panic_response))
+        .layer(middleware::from_fn_with_state(state.clone(), error_boundary))
+        .layer(middleware::from_fn(coded_rejections))
+        .layer(middleware::from_fn_with_state(state.metrics.clone(), track_requests))
+        // Outermost, so every response and crash report carries the request's ID
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .with_state(state.clone())
 }
 
-async fn health_check() -> impl IntoResponse {
-    Json(serde_json::json!({
-        "status": "healthy",
-        "service": "sentient-tee-redactor"
-    }))
+// Once requests in flight have been answered: let the job workers finish, write usage
+// totals through, and zeroize session and service keys before the process exits
+async fn drain(state: &AppState) {
+    let unfinished = state.jobs.drain().await;
+    if !unfinished.is_empty() {
+        let fate = if state.jobs.is_journaled() { "resume at the next start" } else { "are lost" };
+        warn!("{} upload job(s) did not finish before shutdown and {}: {}", unfinished.len(), fate, unfinished.join(", "));
+    }
+    if state.read_only.is_none() {
+        if let Err(e) = state.file_storage.write().await.persist_usage() {
+            warn!("Failed to persist usage totals at shutdown: {}", e);
+        }
+    }
+    let sessions = state.sessions.clear();
+    if let Some(crypto_service) = state.key_provisioner.get() {
+        crypto_service.forget_keys();
+    }
+    info!("Shutdown complete; cleared {} session(s) and the service keys", sessions);
 }
 
-async fn handshake(State(state): State<AppState>) -> impl IntoResponse {
-    match state.crypto_service.get_public_key() {
-        Ok(public_key) => {
-            Json(serde_json::json!({
-                "public_key": public_key,
-                "algorithm": "RSA-2048"
-            })).into_response()
+// Ctrl-C, or the SIGTERM orchestrators send before stopping a container
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
         }
-        Err(e) => {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("Failed to get public key: {}", e),
-                }),
-            )
-                .into_response()
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
         }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
     }
+    info!("Shutting down");
 }
 
-async fn upload_file(
-    State(state): State<AppState>,
-    Json(payload): Json<UploadRequest>,
-) -> impl IntoResponse {
-    let file_id = Uuid::new_v4().to_string();
-    
-    info!("Processing upload for file_id: {}", file_id);
+// Degraded while a redaction backend's circuit is open; uploads then fail fast with 503
+#[utoipa::path(
+    get, path = "/health/live", tag = "probes", security(()),
+    responses((status = 200, description = "Liveness, and the state of each backend's circuit; also served at `/health`", body = HealthResponse))
+)]
+async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
+    let circuits = state.redactor_service.circuits();
+    let degraded = circuits.iter().any(|circuit| circuit.state != CircuitState::Closed);
+    Json(HealthResponse {
+        status: if degraded { "degraded" } else { "healthy" },
+        service: "sentient-tee-redactor",
+        circuits,
+    })
+}
 
-    // Decrypt the session key first
-    let session_key = match state.crypto_service.decrypt_session_key(&payload.encrypted_session_key) {
-        Ok(key) => key,
-        Err(e) => {
-            warn!("Session key decryption failed for file_id {}: {}", file_id, e);
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: format!("Session key decryption failed: {}", e),
-                }),
-            )
-                .into_response();
+// A Presidio answering in a shape the service does not understand fails every upload,
+// so in strict mode it stops the service from starting. An unreachable one only warns,
+// as it may still be starting.
+async fn check_presidio_contract(redactor_service: Arc<RedactorService>, strict: bool) {
+    let Err(e) = redactor_service.check_contract().await else { return };
+    if strict && e.downcast_ref::<ContractMismatch>().is_some() {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+    warn!("Presidio contract check failed: {}", e);
+}
+
+// Disk storage is unwrapped with the service key, so it opens once the key is
+// provisioned. The storage lock is taken before provisioning starts and held until
+// then, so no upload can land in the in-memory placeholder.
+async fn spawn_disk_storage(
+    dir: String,
+    default_ttl: Option<u64>,
+    limits: StorageLimits,
+    storage: Arc<RwLock<Box<dyn Storage>>>,
+    key_provisioner: Arc<KeyProvisioner>,
+) {
+    let mut storage = storage.write_owned().await;
+    tokio::spawn(async move {
+        let crypto_service = key_provisioner.wait().await;
+        match DiskStorage::open(&dir, crypto_service) {
+            Ok(disk) => *storage = Box::new(disk.with_default_ttl(default_ttl).with_limits(limits)),
+            Err(e) => {
+                error!("Failed to open disk storage at {}: {}", dir, e);
+                std::process::exit(1);
+            }
+        }
+    });
+}
+
+// Same as `spawn_disk_storage`, for a bucket
+async fn spawn_s3_storage(
+    config: S3Config,
+    default_ttl: Option<u64>,
+    limits: StorageLimits,
+    storage: Arc<RwLock<Box<dyn Storage>>>,
+    key_provisioner: Arc<KeyProvisioner>,
+) {
+    let mut storage = storage.write_owned().await;
+    tokio::spawn(async move {
+        let crypto_service = key_provisioner.wait().await;
+        let bucket = config.bucket.clone();
+        match S3Storage::open(config, crypto_service).await {
+            Ok(s3) => *storage = Box::new(s3.with_default_ttl(default_ttl).with_limits(limits)),
+            Err(e) => {
+                error!("Failed to open S3 storage in bucket {}: {}", bucket, e);
+                std::process::exit(1);
+            }
+        }
+    });
+}
+
+// Purge files whose TTL ran out every `interval` seconds, and originals older than
+// `original_retention`
+fn spawn_expiry_sweep(state: AppState, interval: u64, original_retention: Option<u64>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            sweep_expired(&state, now, original_retention).await;
+        }
+    });
+}
+
+// One pass of the expiry sweep, with an erasure receipt in the audit log for each
+// purged file, as for deletes
+async fn sweep_expired(state: &AppState, now: u64, original_retention: Option<u64>) {
+    let mut storage = state.file_storage.write().await;
+    let mut records = Vec::new();
+    let mut notices = Vec::new();
+    for file_id in storage.expired_file_ids(now) {
+        records.push(erasure_record(state, storage.as_ref(), &file_id, "file.expire", None, "expired"));
+        notices.extend(storage.get_metadata(&file_id).map(|metadata| DeletionNotice::new(&file_id, metadata, "expired")));
+        storage.expire_file(&file_id);
+        info!("Purged expired file_id: {}", file_id);
+    }
+    if let Some(retention) = original_retention {
+        for file_id in operations::purge_originals(storage.as_mut(), retention, now) {
+            records.push(AuditRecord::new("file.original_purge", None, Some(&file_id), "success")
+                .with_details(serde_json::json!({ "retention_seconds": retention })));
         }
+    }
+    drop(storage);
+
+    let mut audit_log = state.audit_log.write().await;
+    for record in records {
+        audit_log.record(record);
+    }
+    drop(audit_log);
+    for notice in notices {
+        state.propagation.spawn(notice, state.audit_log.clone(), state.alerts.clone());
+    }
+}
+
+// Write the storage backend's usage totals through every `interval` seconds
+fn spawn_usage_snapshots(storage: Arc<RwLock<Box<dyn Storage>>>, interval: u64) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            if let Err(e) = storage.write().await.persist_usage() {
+                warn!("Failed to persist usage totals: {}", e);
+            }
+        }
+    });
+}
+
+// Put cold storage behind the primary once it is open, then every
+// `cold_tier_sweep_seconds` move the files older than `cold_tier_after_days` there,
+// auditing each move
+fn spawn_cold_tiering(state: AppState, tiering: ColdTiering) {
+    tokio::spawn(async move {
+        tiering.attach(&state.file_storage, state.key_provisioner.clone()).await;
+        let mut ticker = tokio::time::interval(tiering.interval);
+        loop {
+            ticker.tick().await;
+            let moved = state.file_storage.write().await.move_to_cold(tiering.cutoff(unix_now()));
+            if moved.is_empty() {
+                continue;
+            }
+            info!("Moved {} file(s) to cold storage", moved.len());
+            state.metrics.record_cold_tier(moved.len());
+            let mut audit_log = state.audit_log.write().await;
+            for file_id in moved {
+                audit_log.record(AuditRecord::new("file.move_cold", None, Some(&file_id), "success"));
+            }
+        }
+    });
+}
+
+// Every `quality_sample_seconds`, score the live backend against the reference one over
+// the retained originals of recent uploads. Runs are audited, since they open originals.
+fn spawn_quality_sampling(state: AppState, sampler: Arc<QualitySampler>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(sampler.interval);
+        // The first tick is immediate; nothing is worth sampling right at startup
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let Some(crypto_service) = state.key_provisioner.get() else {
+                continue;
+            };
+            let documents = sampler.sample(state.file_storage.read().await.as_ref(), crypto_service);
+            if documents.is_empty() {
+                continue;
+            }
+
+            let file_ids: Vec<&str> = documents.iter().map(|document| document.file_id.as_str()).collect();
+            let (outcome, details) = match sampler.run(&state.redactor_service, &documents).await {
+                Ok((score, previous)) => {
+                    state.metrics.record_quality(&score, previous.as_ref());
+                    info!("Quality sample of {} document(s): precision {:.3}, recall {:.3}", score.documents, score.precision, score.recall);
+                    ("success", serde_json::json!({ "file_ids": file_ids, "precision": score.precision, "recall": score.recall }))
+                }
+                Err(e) => {
+                    warn!("Quality sampling failed: {}", e);
+                    ("failure", serde_json::json!({ "file_ids": file_ids, "reason": e.to_string() }))
+                }
+            };
+            state.audit_log.write().await.record(AuditRecord::new("quality.sample", None, None, outcome).with_details(details));
+        }
+    });
+}
+
+// Every `ORPHAN_GC_SECONDS`, drop sessions idle for `SESSION_IDLE_SECONDS` (or expired or
+// used up) and storage files left by writes that stopped `ORPHAN_FILE_AGE_SECONDS` ago
+fn spawn_orphan_gc(state: AppState) {
+    let env_seconds = |name: &str, default| {
+        std::env::var(name)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .filter(|seconds| *seconds > 0)
+            .unwrap_or(default)
     };
+    let interval = env_seconds("ORPHAN_GC_SECONDS", DEFAULT_ORPHAN_GC_SECONDS);
+    let session_idle = env_seconds("SESSION_IDLE_SECONDS", DEFAULT_SESSION_IDLE_SECONDS);
+    let file_age = Duration::from_secs(env_seconds("ORPHAN_FILE_AGE_SECONDS", DEFAULT_ORPHAN_FILE_AGE_SECONDS));
 
-    // Decrypt the file using the session key
-    let decrypted_content = match state.crypto_service.decrypt_file_with_session_key(&payload.encrypted_data, &session_key) {
-        Ok(content) => content,
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            let (sessions, session_bytes) = state.sessions.sweep(session_idle);
+            state.metrics.record_gc("session", sessions, session_bytes);
+
+            match state.file_storage.write().await.collect_orphans(file_age) {
+                Ok((files, file_bytes)) => state.metrics.record_gc("storage_file", files, file_bytes),
+                Err(e) => warn!("Failed to collect orphaned storage files: {}", e),
+            }
+            if sessions > 0 {
+                info!("Dropped {} abandoned sessions", sessions);
+            }
+        }
+    });
+}
+
+// Audit record for a file about to be erased, carrying its erasure receipt. Issued
+// before the content is gone; its digests are all that remain.
+fn erasure_record(state: &AppState, storage: &dyn Storage, file_id: &str, action: &str, principal: Option<&str>, reason: &str) -> AuditRecord {
+    let record = AuditRecord::new(action, principal, Some(file_id), "success");
+    let details = state.key_provisioner.get()
+        .ok_or_else(|| anyhow::anyhow!("Service key is not provisioned yet"))
+        .and_then(|crypto_service| {
+            let metadata = storage.get_metadata(file_id).ok_or_else(|| anyhow::anyhow!("File is not stored"))?;
+            let receipt = ErasureReceipt::issue(crypto_service, file_id, metadata, reason, &[storage.backend_name()], &state.propagation.target_names())?;
+            // Unsigned, alongside the receipt: who could see the file, and so its receipt
+            let mut details = serde_json::to_value(receipt)?;
+            details["tenant"] = serde_json::json!(metadata.tenant);
+            details["acl"] = serde_json::to_value(&metadata.acl)?;
+            Ok(details)
+        });
+    match details {
+        Ok(details) => record.with_details(details),
         Err(e) => {
-            warn!("File decryption failed for file_id {}: {}", file_id, e);
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: format!("File decryption failed: {}", e),
-                }),
-            )
-                .into_response();
+            warn!("Failed to issue erasure receipt for file_id {}: {}", file_id, e);
+            record
         }
+    }
+}
+
+// Also carries the instance's labels, for gateways that discover instances by probing
+// Ready once the service key is provisioned, the redaction backend answers and storage
+// takes writes, each reported with how long it took
+#[utoipa::path(
+    get, path = "/health/ready", tag = "probes", security(()),
+    responses(
+        (status = 200, description = "Ready; also served at `/ready`", body = ReadinessResponse),
+        (status = 503, description = "A dependency is down", body = ReadinessResponse),
+    )
+)]
+async fn readiness_check(State(state): State<AppState>) -> impl IntoResponse {
+    let status = state.key_provisioner.status();
+    let mut dependencies = BTreeMap::new();
+    let key = match (status.ready, &status.last_error) {
+        (true, _) => Ok(()),
+        (false, Some(e)) => Err(anyhow::anyhow!("Not provisioned yet: {}", e)),
+        (false, None) => Err(anyhow::anyhow!("Not provisioned yet")),
     };
+    dependencies.insert("service_key", DependencyStatus::new("provisioner", Instant::now(), key));
 
-    // Perform redaction with optional strategy
-    let strategy = payload.redaction_strategy.unwrap_or_else(|| "replace".to_string());
-    let redacted_content = match state.redactor_service.redact_text_with_strategy(&decrypted_content, &strategy).await {
-        Ok(content) => content,
-        Err(e) => {
-            warn!("Redaction failed for file_id {}: {}", file_id, e);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("Redaction failed: {}", e),
-                }),
-            )
-                .into_response();
+    let redactor_service = &state.redactor_service;
+    dependencies.insert("redaction_backend", readiness::check(redactor_service.backend_name(), redactor_service.ping()).await);
+
+    // Disk and S3 storage stay locked while they open
+    let started = Instant::now();
+    let storage = match state.file_storage.try_read() {
+        Ok(storage) => {
+            let check = match &state.read_only {
+                Some(read_only) => read_only.status(),
+                None => storage.check_writable(),
+            };
+            DependencyStatus::new(storage.backend_name(), started, check)
         }
+        Err(_) => DependencyStatus::new("pending", started, Err(anyhow::anyhow!("Storage is still opening"))),
     };
+    dependencies.insert("storage", storage);
 
-    // Store the redacted file
-    let name = payload.file_name.as_deref().unwrap_or("file");
-    let final_file_name = format!("{}_{}_redacted_{}.txt", name, strategy, file_id);
-    {
-        let mut storage = state.file_storage.write().await;
-        storage.store_file(&file_id, &final_file_name, &redacted_content);
+    let ready = readiness::all_up(&dependencies);
+    let code = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    let body = ReadinessResponse {
+        ready,
+        attempts: status.attempts,
+        last_error: status.last_error,
+        dependencies,
+        labels: state.labels.as_ref().clone(),
+    };
+    (code, Json(body))
+}
+
+#[utoipa::path(
+    get, path = "/metrics", tag = "probes",
+    responses((status = 200, description = "Prometheus metrics", body = String, content_type = "text/plain"))
+)]
+async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    state.metrics.record_feature_flags(&state.flags.rollouts());
+    state.metrics.record_job_queue(&state.jobs.backlog());
+    state.metrics.record_retained_secrets(secret::retained());
+    if let Some(replicator) = &state.replicator {
+        let (pending, lag) = replicator.lag();
+        state.metrics.record_replication(pending, lag);
+    }
+    // Skipped while disk storage waits for the service key
+    if let Ok(storage) = state.file_storage.try_read() {
+        let file_ids = storage.file_ids();
+        let bytes = file_ids.iter().filter_map(|file_id| storage.get_metadata(file_id)).map(|metadata| metadata.size).sum();
+        state.metrics.record_storage(file_ids.len(), bytes, storage.limits());
+    }
+    (
+        [("Content-Type", "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+}
+
+// Turn away callers over `RATE_LIMIT_PER_MINUTE`
+async fn rate_limit(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let caller = request.extensions().get::<Caller>().cloned().unwrap_or_default();
+    let addr = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| addr.ip());
+    match state.rate_limiter.check(&ratelimit::client_key(&caller, addr)) {
+        Ok(()) => next.run(request).await,
+        Err(e) => throttled(e),
+    }
+}
+
+// On a read-only replica, refuse routes that would write before they are handled.
+// Outside authentication, so refused callers learn to go elsewhere without a credential.
+async fn reject_writes(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let route = request.extensions().get::<MatchedPath>().map(|path| path.as_str());
+    match (&state.read_only, route) {
+        (Some(_), Some(route)) if !ReadOnlyMode::serves(request.method(), route) => operation_error(ReadOnlyMode::refused()),
+        _ => next.run(request).await,
+    }
+}
+
+// Run a request carrying an idempotency key once: retries of it within the window get
+// its response back instead of storing the upload again. Only successful responses are
+// kept, so a retry after a failure runs anew. Bodies are read up to `limit`, the route's
+// body limit, and only when they may carry a key: in the header, or in a JSON body.
+async fn idempotent(State((state, limit)): State<(AppState, usize)>, request: Request, next: Next) -> Response {
+    let json = request.headers().get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/json"));
+    if !json && !request.headers().contains_key(idempotency::HEADER) {
+        return next.run(request).await;
+    }
+    let (parts, body) = request.into_parts();
+    let body = match axum::body::to_bytes(body, limit).await {
+        Ok(body) => body,
+        Err(e) => return api_error(ErrorKind::PayloadTooLarge, format!("Failed to read the request body: {}", e)),
+    };
+    let key = match idempotency::request_key(&parts.headers, json.then_some(&body[..])) {
+        Ok(Some(key)) => key,
+        Ok(None) => return next.run(Request::from_parts(parts, Body::from(body))).await,
+        Err(e) => return operation_error(e),
+    };
+    let caller = parts.extensions.get::<Caller>().cloned().unwrap_or_else(|| Caller::from_headers(&parts.headers));
+    let route = parts.extensions.get::<MatchedPath>().map_or(parts.uri.path(), MatchedPath::as_str);
+    let pending = match state.idempotency.claim(RequestKey::new(&caller, &key, route, parts.uri.query(), &body)) {
+        Claim::First(pending) => pending,
+        Claim::Replay(response) => {
+            info!("Replaying the response to a request with idempotency key {}", key);
+            state.metrics.record_replayed_upload();
+            return response;
+        }
+        Claim::InFlight => return operation_error(idempotency::in_flight()),
+    };
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    if !response.status().is_success() {
+        pending.complete(None);
+        return response;
+    }
+    match StoredResponse::buffer(response).await {
+        Ok((stored, response)) => {
+            pending.complete(Some(stored));
+            response
+        }
+        Err(e) => operation_error(e),
+    }
+}
+
+// Hold a pipeline slot while the request is handled
+async fn bound_pipelines(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    match state.rate_limiter.pipeline() {
+        Ok(_permit) => next.run(request).await,
+        Err(e) => throttled(e),
+    }
+}
+
+fn throttled(e: Throttled) -> Response {
+    let mut response = operation_error(e.error);
+    response.headers_mut().insert("Retry-After", e.retry_after.into());
+    response
+}
+
+// Count every request by route, and downloads of redacted content by kind
+async fn track_requests(State(metrics): State<Arc<Metrics>>, request: Request, next: Next) -> Response {
+    let route = request.extensions().get::<MatchedPath>().map(|path| path.as_str().to_string());
+    let response = next.run(request).await;
+
+    let route = route.as_deref().unwrap_or("unmatched");
+    let status = response.status();
+    metrics.record_request(route, status.as_u16());
+    let download = match route {
+        "/download/:file_id" => Some("file"),
+        "/download/bulk" => Some("bulk"),
+        "/share/:token" => Some("share"),
+        "/files/:file_id/unredact" => Some("unredact"),
+        "/files/:file_id/original" => Some("original"),
+        "/files/:file_id/document" => Some("document"),
+        _ => None,
+    };
+    if let Some(kind) = download {
+        metrics.record_download(kind, status.is_success());
+    }
+    response
+}
+
+// A handler that panicked gets the JSON error body with its request's ID, instead of
+// the bare 500 of `CatchPanicLayer`; the panic is counted and reported
+async fn error_boundary(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let request_id = request.headers().get(REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let method = request.method().to_string();
+    let route = request.extensions().get::<MatchedPath>().map(|path| path.as_str().to_string());
+    let response = next.run(request).await;
+    let Some(panicked) = response.extensions().get::<Panicked>().cloned() else {
+        return response;
+    };
+
+    let route = route.as_deref().unwrap_or("unmatched");
+    error!("Handler for {} {} panicked at {}; request_id {}", method, route, panicked.location.as_deref().unwrap_or("an unknown location"), request_id);
+    state.metrics.record_panic(route);
+    state.crashes.spawn(CrashReport::new(&request_id, &method, route, panicked));
+    let body = ErrorResponse {
+        error: "Internal error; quote the request_id when reporting it".to_string(),
+        code: ErrorKind::Internal.code(),
+        link: None,
+        request_id: Some(request_id),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(body)).into_response()
+}
+
+// Axum's own rejections, such as a body over the limit, malformed JSON or a missing
+// route, are plain text; give them the JSON error body of every other failure
+async fn coded_rejections(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let status = response.status();
+    let json = response.headers().get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/json"));
+    let Some(kind) = ErrorKind::from_status(status.as_u16()).filter(|_| !json) else {
+        return response;
+    };
+    let body = axum::body::to_bytes(response.into_body(), MAX_REJECTION_BYTES).await.unwrap_or_default();
+    match String::from_utf8_lossy(&body).trim() {
+        "" => api_error(kind, status.canonical_reason().unwrap_or_default()),
+        message => api_error(kind, message),
     }
+}
+
+// Map a core operation error onto its HTTP status and JSON error body
+fn operation_error(e: OperationError) -> Response {
+    let status = StatusCode::from_u16(e.kind.status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let code = e.code.unwrap_or(e.kind.code());
+    (status, Json(ErrorResponse { error: e.message, code, link: e.link, request_id: None })).into_response()
+}
+
+// An error the handler raises itself, with the code of its kind
+fn api_error(kind: ErrorKind, message: impl Into<String>) -> Response {
+    operation_error(OperationError::new(kind, message))
+}
+
+// Returned by key-dependent endpoints while the key pair is still being provisioned
+fn key_not_provisioned() -> Response {
+    operation_error(key_unavailable())
+}
+
+fn key_unavailable() -> OperationError {
+    OperationError::new(ErrorKind::Unavailable, "Service key is not provisioned yet").with_code("key_not_provisioned")
+}
 
-    info!("Successfully processed file_id: {}", file_id);
+// Refuse content leaving by `route` for tenants whose outputs are delivery-only, with an
+// audit record of the attempt
+async fn check_downloads_allowed(state: &AppState, caller: &Caller, file_id: Option<&str>, route: &str) -> Result<(), OperationError> {
+    if state.policy.downloads_allowed(caller.tenant.as_deref()) {
+        return Ok(());
+    }
+    warn!("Download by {} refused for delivery-only tenant {:?}", route, caller.tenant);
+    state.audit_log.write().await.record(
+        AuditRecord::new("file.download", caller.principal.as_deref(), file_id, "denied")
+            .with_details(serde_json::json!({ "reason": "downloads_disabled", "route": route, "tenant": caller.tenant })),
+    );
+    Err(operations::downloads_disabled())
+}
 
+// Returned by upload endpoints while maintenance mode is on
+fn under_maintenance(status: MaintenanceStatus) -> Response {
+    let mut headers = HeaderMap::new();
+    if let Some(seconds) = MaintenanceMode::retry_after(&status) {
+        headers.insert("Retry-After", seconds.into());
+    }
     (
-        StatusCode::OK,
-        Json(UploadResponse {
-            file_id,
-            filename: final_file_name,
-            message: "File uploaded and redacted successfully".to_string(),
-        }),
+        StatusCode::SERVICE_UNAVAILABLE,
+        headers,
+        Json(MaintenanceResponse { error: status.message, code: "maintenance", eta: status.eta }),
     )
         .into_response()
 }
 
-async fn download_file(
+#[utoipa::path(
+    get, path = "/handshake", tag = "handshake", params(HandshakeQuery),
+    responses(
+        (status = 200, description = "The service public key, with attestation evidence when enabled", body = HandshakeResponse),
+        (status = 502, description = "Attestation failed", body = ErrorResponse),
+        (status = 503, description = "The service key is not provisioned yet", body = ErrorResponse),
+    )
+)]
+async fn handshake(State(state): State<AppState>, Query(query): Query<HandshakeQuery>) -> impl IntoResponse {
+    match handshake_response(&state, query.nonce.as_deref(), None).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => operation_error(e),
+    }
+}
+
+// A handshake that also negotiates a session, whose key uploads reuse via `session_id`
+#[utoipa::path(
+    post, path = "/handshake", tag = "handshake", params(HandshakeQuery), request_body = SessionRequest,
+    responses(
+        (status = 200, description = "The handshake plus a negotiated session", body = HandshakeResponse),
+        (status = 400, description = "Session negotiation failed", body = ErrorResponse),
+        (status = 503, description = "The service key is not provisioned yet", body = ErrorResponse),
+    )
+)]
+async fn create_session(
     State(state): State<AppState>,
-    axum::extract::Path(file_id): axum::extract::Path<String>,
+    caller: Caller,
+    Query(query): Query<HandshakeQuery>,
+    Json(payload): Json<SessionRequest>,
 ) -> impl IntoResponse {
-    let storage = state.file_storage.read().await;
-    
-    match storage.get_file(&file_id) {
-        Some((file_name, content)) => {
-            let mut headers = HeaderMap::new();
-            headers.insert(
-                "Content-Disposition",
-                format!("attachment; filename=\"{}\"", file_name).parse().unwrap(),
-            );
-            headers.insert("Content-Type", "text/plain".parse().unwrap());
-            
-            (StatusCode::OK, headers, content).into_response()
+    match handshake_response(&state, query.nonce.as_deref(), Some((&caller, &payload))).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => operation_error(e),
+    }
+}
+
+async fn handshake_response(
+    state: &AppState,
+    nonce: Option<&str>,
+    session: Option<(&Caller, &SessionRequest)>,
+) -> Result<HandshakeResponse, OperationError> {
+    let crypto_service = state.key_provisioner.get().ok_or_else(key_unavailable)?;
+
+    let mut response = operations::handshake(crypto_service)?;
+    if let Some((caller, request)) = session {
+        match state.sessions.establish(crypto_service, caller, request) {
+            Ok(grant) => response.session = Some(grant),
+            Err(e) => {
+                warn!("Session negotiation failed: {}", e);
+                return Err(OperationError::new(ErrorKind::BadRequest, format!("Session negotiation failed: {}", e)));
+            }
         }
-        None => {
-            (
-                StatusCode::NOT_FOUND,
-                Json(ErrorResponse {
-                    error: "File not found".to_string(),
-                }),
-            )
-                .into_response()
+    }
+    match state.attester.attest(&response.public_key, nonce).await {
+        Ok(attestation) => response.attestation = attestation,
+        Err(e) => {
+            warn!("Attestation failed: {}", e);
+            return Err(OperationError::new(ErrorKind::BadGateway, format!("Attestation failed: {}", e)));
+        }
+    }
+
+    Ok(response)
+}
+
+#[utoipa::path(
+    get, path = "/capabilities", tag = "handshake",
+    responses((status = 200, description = "Modes this instance supports", body = Capabilities))
+)]
+async fn capabilities(State(state): State<AppState>, caller: Caller) -> impl IntoResponse {
+    let mut key_exchange = vec!["rsa-oaep-sha256", "x25519-hkdf-sha256"];
+    if state.key_provisioner.get().is_some_and(CryptoService::psk_enabled) {
+        key_exchange.push("psk-hkdf-sha256");
+    }
+
+    Json(Capabilities {
+        protocol_version: discovery::PROTOCOL_VERSION,
+        key_exchange,
+        ciphers: PayloadCipher::ALL.iter().map(PayloadCipher::name).collect(),
+        deprecations: state.deprecations.deprecations().to_vec(),
+        redaction_strategies: &["replace", "mask", "fake", "custom", "extract", "hash"],
+        content_encodings: &["gzip", "zstd"],
+        document_formats: &["pdf", "docx"],
+        simple_mode: state.simple_mode.is_enabled(),
+        downloads: state.policy.downloads_allowed(caller.tenant.as_deref()),
+        attestation: state.attester.format(),
+        feature_flags: state.flags.evaluate(&caller),
+    })
+}
+
+#[utoipa::path(
+    post, path = "/upload", tag = "uploads", params(UploadQuery), request_body = UploadBody,
+    responses(
+        (status = 200, description = "Redacted and stored; with `?profile=true` the response carries a `profile`", body = UploadResponse),
+        (status = 202, description = "Queued, with `?async=true`", body = JobView),
+        (status = 400, description = "Invalid upload", body = ErrorResponse),
+        (status = 403, description = "The credential may not upload", body = ErrorResponse),
+        (status = 422, description = "Checksum mismatch or strict-mode failure", body = ErrorResponse),
+        (status = 503, description = "Maintenance mode, or the service key is not provisioned yet", body = MaintenanceResponse),
+    )
+)]
+async fn upload_file(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(query): Query<UploadQuery>,
+    Json(payload): Json<UploadBody>,
+) -> impl IntoResponse {
+    if let Some(status) = state.maintenance.active() {
+        return under_maintenance(status);
+    }
+
+    let UploadBody { mut upload, simple } = payload;
+    if !simple.is_used() {
+        return process_upload(state, caller, upload, query).await;
+    }
+
+    // Developer simple mode: plaintext or a raw session key, audited and marked as such
+    let Some(crypto_service) = state.key_provisioner.get() else {
+        return key_not_provisioned();
+    };
+    if let Err(e) = check_processing_window(&state, &caller) {
+        return operation_error(e);
+    }
+    if let Err(e) = state.simple_mode.apply(simple, &mut upload, crypto_service) {
+        warn!("Simple mode upload rejected: {}", e);
+        state.audit_log.write().await.record(
+            AuditRecord::new("upload.simple", caller.principal.as_deref(), None, "denied")
+                .with_details(serde_json::json!({ "reason": e.to_string() })),
+        );
+        return api_error(ErrorKind::BadRequest, e.to_string());
+    }
+
+    match run_upload(&state, crypto_service, &caller, upload).await {
+        Ok(response) => {
+            state.audit_log.write().await.record(AuditRecord::new(
+                "upload.simple",
+                caller.principal.as_deref(),
+                Some(&response.file_id),
+                "success",
+            ));
+            (
+                StatusCode::OK,
+                [("X-Redactor-Mode", "simple")],
+                Json(SimpleUploadResponse {
+                    upload: response,
+                    mode: "simple",
+                }),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            state.audit_log.write().await.record(
+                AuditRecord::new("upload.simple", caller.principal.as_deref(), None, "failure")
+                    .with_details(serde_json::json!({ "reason": e.message })),
+            );
+            operation_error(e)
+        }
+    }
+}
+
+// Fetch an already-encrypted blob from a client-supplied URL, then run the normal pipeline
+#[utoipa::path(
+    post, path = "/upload/from-url", tag = "uploads", params(UploadQuery), request_body = UploadFromUrlRequest,
+    responses(
+        (status = 200, description = "Fetched, redacted and stored", body = UploadResponse),
+        (status = 202, description = "Queued, with `?async=true`", body = JobView),
+        (status = 400, description = "Invalid upload, or the URL is not allowed", body = ErrorResponse),
+        (status = 403, description = "The credential may not upload", body = ErrorResponse),
+        (status = 413, description = "The fetched blob is too large", body = ErrorResponse),
+        (status = 502, description = "Fetching the blob failed", body = ErrorResponse),
+    )
+)]
+async fn upload_from_url(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(query): Query<UploadQuery>,
+    Json(payload): Json<UploadFromUrlRequest>,
+) -> impl IntoResponse {
+    if let Some(status) = state.maintenance.active() {
+        return under_maintenance(status);
+    }
+    // Before fetching, so a credential that may not upload cannot make the service
+    // download anything. Read-only replicas refuse the route before it is handled.
+    if !caller.allows(Scope::Upload) {
+        return operation_error(OperationError::new(ErrorKind::Forbidden, "This credential may not upload files").with_code("scope_denied"));
+    }
+    if !state.blob_fetcher.is_enabled() {
+        return api_error(ErrorKind::BadRequest, "Upload from URL is not enabled on this service");
+    }
+    if !payload.upload.encrypted_data.is_empty() {
+        return api_error(ErrorKind::BadRequest, "Provide either encrypted_data or source_url, not both");
+    }
+
+    let blob = match state.blob_fetcher.fetch(&payload.source_url).await {
+        Ok(blob) => blob,
+        Err(e) => {
+            warn!("Fetching upload source failed: {}", e);
+            let kind = match e {
+                FetchError::NotAllowed(_) => ErrorKind::BadRequest,
+                FetchError::TooLarge(_) => ErrorKind::PayloadTooLarge,
+                FetchError::Upstream(_) => ErrorKind::BadGateway,
+            };
+            return api_error(kind, e.to_string());
+        }
+    };
+    info!("Fetched {} byte blob for upload from URL", blob.len());
+
+    let mut upload = payload.upload;
+    upload.encrypted_data = BASE64.encode(blob);
+    process_upload(state, caller, upload, query).await
+}
+
+// Binary variant of `/upload`: the raw ciphertext in a `file` part and the other upload
+// fields as JSON in a `metadata` part, so large files are not inflated by base64
+#[utoipa::path(
+    post, path = "/upload/multipart", tag = "uploads", params(UploadQuery),
+    request_body(content = MultipartUpload, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Redacted and stored", body = UploadResponse),
+        (status = 202, description = "Queued, with `?async=true`", body = JobView),
+        (status = 400, description = "Invalid upload or multipart body", body = ErrorResponse),
+    )
+)]
+async fn upload_multipart(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(query): Query<UploadQuery>,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    if let Some(status) = state.maintenance.active() {
+        return under_maintenance(status);
+    }
+    let bad_request = |error: String| api_error(ErrorKind::BadRequest, error);
+
+    let (mut upload, mut ciphertext, mut file_type) = (None, None, None);
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return bad_request(format!("Invalid multipart body: {}", e)),
+        };
+        let name = field.name().unwrap_or_default().to_string();
+        if name == "file" {
+            file_type = field.content_type().and_then(DocumentFormat::from_declared);
+        }
+        let bytes = match field.bytes().await {
+            Ok(bytes) => bytes,
+            Err(e) => return bad_request(format!("Failed to read the {} part: {}", name, e)),
+        };
+        match name.as_str() {
+            "metadata" => match serde_json::from_slice::<UploadRequest>(&bytes) {
+                Ok(metadata) => upload = Some(metadata),
+                Err(e) => return bad_request(format!("Invalid metadata part: {}", e)),
+            },
+            "file" => ciphertext = Some(Vec::from(bytes)),
+            _ => {}
+        }
+    }
+
+    let (Some(mut upload), Some(ciphertext)) = (upload, ciphertext) else {
+        return bad_request("A multipart upload needs a metadata part and a file part".to_string());
+    };
+    if !upload.encrypted_data.is_empty() {
+        return bad_request("Send the ciphertext in the file part, not as encrypted_data".to_string());
+    }
+    upload.ciphertext = Some(ciphertext);
+    // A document type on the file part stands in for `document_type`
+    if upload.document_type.is_none() {
+        upload.document_type = file_type.map(|format| format.extension().to_string());
+    }
+    process_upload(state, caller, upload, query).await
+}
+
+// Streamed upload: a metadata part, then a file part sealed in chunks that is decrypted
+// and redacted as it arrives, so large files are never held whole
+#[utoipa::path(
+    post, path = "/upload/stream", tag = "uploads", params(UploadQuery),
+    request_body(content = MultipartUpload, content_type = "multipart/form-data",
+        description = "The metadata part first, then the file part sealed in chunks"),
+    responses(
+        (status = 200, description = "Redacted and stored", body = UploadResponse),
+        (status = 400, description = "Invalid upload, or a field streamed uploads do not support", body = ErrorResponse),
+        (status = 422, description = "Checksum mismatch", body = ErrorResponse),
+    )
+)]
+async fn upload_stream(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(query): Query<UploadQuery>,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    if let Some(status) = state.maintenance.active() {
+        return under_maintenance(status);
+    }
+    let Some(crypto_service) = state.key_provisioner.get() else {
+        return key_not_provisioned();
+    };
+    let bad_request = |error: &str| api_error(ErrorKind::BadRequest, error.to_string());
+    if query.run_async {
+        return bad_request("Streamed uploads cannot run asynchronously");
+    }
+    if let Err(e) = check_processing_window(&state, &caller) {
+        return operation_error(e);
+    }
+
+    // The metadata comes first, so the file part can be read as it arrives
+    let upload = match multipart.next_field().await {
+        Ok(Some(field)) if field.name() == Some("metadata") => match field.bytes().await {
+            Ok(bytes) => serde_json::from_slice::<UploadRequest>(&bytes),
+            Err(e) => return bad_request(&format!("Failed to read the metadata part: {}", e)),
+        },
+        Ok(_) => return bad_request("A streamed upload starts with the metadata part"),
+        Err(e) => return bad_request(&format!("Invalid multipart body: {}", e)),
+    };
+    let upload = match upload {
+        Ok(upload) => upload,
+        Err(e) => return bad_request(&format!("Invalid metadata part: {}", e)),
+    };
+    let file = match multipart.next_field().await {
+        Ok(Some(field)) if field.name() == Some("file") => field,
+        Ok(_) => return bad_request("The metadata part must be followed by the file part"),
+        Err(e) => return bad_request(&format!("Invalid multipart body: {}", e)),
+    };
+
+    let result = run_chunked_upload(&state, crypto_service, &caller, upload, file).await;
+    observe_upload(&state, &result);
+    evict_for(&state, &result).await;
+    match result {
+        Ok(response) if query.profile => {
+            let profile = response.profile.clone();
+            (StatusCode::OK, Json(ProfiledUploadResponse { upload: response, profile })).into_response()
+        }
+        Ok(response) => (StatusCode::OK, Json(response)).into_response(),
+        Err(e) => operation_error(e),
+    }
+}
+
+// Several documents encrypted under one session key, redacted concurrently. Each one
+// succeeds or fails on its own, so the batch as a whole only fails when it is malformed.
+#[utoipa::path(
+    post, path = "/upload/batch", tag = "uploads", request_body = BatchUploadRequest,
+    responses(
+        (status = 200, description = "The outcome of each document", body = BatchUploadResponse),
+        (status = 400, description = "Malformed batch", body = ErrorResponse),
+    )
+)]
+async fn upload_batch(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(payload): Json<BatchUploadRequest>,
+) -> impl IntoResponse {
+    if let Some(status) = state.maintenance.active() {
+        return under_maintenance(status);
+    }
+    let Some(crypto_service) = state.key_provisioner.get() else {
+        return key_not_provisioned();
+    };
+    if let Err(e) = check_processing_window(&state, &caller) {
+        return operation_error(e);
+    }
+    let uploads = match batch::uploads(payload) {
+        Ok(uploads) => uploads,
+        Err(e) => return api_error(ErrorKind::BadRequest, e),
+    };
+
+    let (state, caller) = (&state, &caller);
+    let items = batch::run_bounded(uploads, batch::concurrency(), |upload| async move {
+        match upload {
+            Ok(upload) => run_upload(state, crypto_service, caller, upload).await,
+            Err(e) => Err(OperationError::new(ErrorKind::BadRequest, e)),
+        }
+    })
+    .await;
+    let items = items.into_iter().enumerate().map(|(index, result)| BatchItemResult::new(index, result)).collect();
+    (StatusCode::OK, Json(BatchUploadResponse::new(items))).into_response()
+}
+
+async fn run_chunked_upload(
+    state: &AppState,
+    crypto_service: &CryptoService,
+    caller: &Caller,
+    mut request: UploadRequest,
+    mut file: Field<'_>,
+) -> Result<UploadResponse, OperationError> {
+    let started = Instant::now();
+    let context = upload_context(state, crypto_service);
+    let bad_request = |error: String| OperationError::new(ErrorKind::BadRequest, error);
+    operations::apply_defaults(&context.settings, &mut request);
+    operations::validate_upload(&context, caller, &request).await?;
+
+    let strategy = request.redaction_strategy.clone().unwrap_or_else(|| "replace".to_string());
+    if strategy == "extract" || strategy == PSEUDONYMIZE {
+        return Err(bad_request(format!("The {} strategy cannot be streamed", strategy)));
+    }
+    let whole_upload_only = [
+        ("encrypted_data", !request.encrypted_data.is_empty()),
+        ("relay", request.relay.is_some()),
+        ("expectation", request.expectation.is_some()),
+        ("callback_url", request.callback_url.is_some()),
+        ("protected_spans", request.protected_spans.is_some()),
+        ("force_redact_spans", request.force_redact_spans.is_some()),
+        ("custom_patterns", request.custom_patterns.is_some()),
+        ("deny_list", request.deny_list.is_some()),
+        ("content_type", request.content_type != ContentType::Text),
+        ("document_type", request.document_type.is_some()),
+        ("retain_original", request.retain_original),
+        ("compression", request.compression != Compression::None),
+    ];
+    if let Some((field, _)) = whole_upload_only.iter().find(|(_, set)| *set) {
+        return Err(bad_request(format!("{} is not supported on streamed uploads", field)));
+    }
+    let parse_digest = |digest: &Option<String>| {
+        digest.as_deref()
+            .map(crypto::parse_sha256_hex)
+            .transpose()
+            .map_err(|e| bad_request(format!("Invalid checksum: {}", e)))
+    };
+    let language = state.redactor_service.languages().fixed(request.language.as_deref()).map_err(operations::unsupported_language)?;
+    request.language = Some(language.clone());
+    let ciphertext_sha256 = parse_digest(&request.ciphertext_sha256)?;
+    let plaintext_sha256 = parse_digest(&request.plaintext_sha256)?;
+    let chunk_size = ChunkedUpload::chunk_size(request.chunk_size).map_err(|e| bad_request(e.to_string()))?;
+    let nonce_prefix = request.nonce.as_deref().and_then(|nonce| BASE64.decode(nonce).ok())
+        .ok_or_else(|| bad_request("Streamed uploads need nonce, the 7-byte STREAM nonce prefix in base64".to_string()))?;
+
+    let file_id = operations::new_file_id();
+    info!("Processing streamed upload for file_id: {}", file_id);
+    let mut profile = UploadProfile { backend: state.redactor_service.backend_name().to_string(), ..UploadProfile::default() };
+    let mark = profile.record("validation", started);
+    let session_key = operations::recover_session_key(&context, caller, &request, &file_id).await?;
+    let opener = StreamOpener::new(&session_key, &nonce_prefix).map_err(|e| bad_request(e.to_string()))?;
+    let mark = profile.record("session_key", mark);
+
+    let hashing = operations::hash_masking(crypto_service, caller, &request)?;
+    let options = RedactionOptions {
+        strategy: &strategy,
+        tenant: caller.tenant.as_deref(),
+        language: &language,
+        pipelines: state.policy.pipelines_for(caller.tenant.as_deref()),
+        filter: EntityFilter { entities: request.entities.as_deref(), score_threshold: request.score_threshold, language: Some(&language) },
+        bidi: state.policy.bidi,
+        custom: None,
+        boundaries: state.policy.boundaries(),
+        hashing: hashing.as_ref(),
+    };
+    let chunk_failed = |e: ChunkError| match e {
+        ChunkError::Decryption(e) => {
+            warn!("File decryption failed for file_id {}: {}", file_id, e);
+            bad_request(format!("File decryption failed: {}", e)).with_code("decryption_failed")
+        }
+        ChunkError::Redaction(e) => redaction_error(&file_id, e),
+    };
+    let redactor = state.redactor_service.as_ref();
+    let mut upload = ChunkedUpload::new(opener, stream::StreamRedactor::from_env(), chunk_size);
+    loop {
+        match file.chunk().await {
+            Ok(Some(bytes)) => upload.push(redactor, &options, &bytes).await.map_err(chunk_failed)?,
+            Ok(None) => break,
+            Err(e) => return Err(bad_request(format!("Failed to read the file part: {}", e))),
+        }
+    }
+    let output = upload.finish(redactor, &options).await.map_err(chunk_failed)?;
+
+    // Checksums can only be compared once the whole stream is in
+    if ciphertext_sha256.is_some_and(|expected| output.ciphertext_sha256 != expected) {
+        return Err(OperationError::new(ErrorKind::Unprocessable, "Ciphertext does not match ciphertext_sha256; it was corrupted in transit")
+            .with_code("ciphertext_checksum_mismatch"));
+    }
+    if plaintext_sha256.is_some_and(|expected| output.plaintext_sha256 != expected) {
+        return Err(OperationError::new(ErrorKind::Unprocessable, "Decrypted content does not match plaintext_sha256")
+            .with_code("plaintext_checksum_mismatch"));
+    }
+    profile.record("redaction", mark);
+    profile.ciphertext_bytes = output.ciphertext_bytes;
+    profile.plaintext_bytes = output.plaintext_bytes;
+    profile.redacted_bytes = output.redacted.len();
+
+    operations::store_upload(&context, caller, request, RedactedUpload {
+        file_id,
+        strategy,
+        content: output.redacted,
+        report: Some(output.report),
+        pseudonyms: None,
+        original: None,
+        session_key,
+        relay: None,
+        document_format: None,
+        profile,
+        started,
+        content_key: None,
+    })
+    .await
+}
+
+async fn process_upload(state: AppState, caller: Caller, payload: UploadRequest, mut query: UploadQuery) -> Response {
+    let Some(crypto_service) = state.key_provisioner.get() else {
+        return key_not_provisioned();
+    };
+    // Outside the tenant's processing window, uploads are queued or refused as configured
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    if let Some(window) = state.policy.closed_window(caller.tenant.as_deref(), now) {
+        match window.outside {
+            OutsideWindow::Queue => query.run_async = true,
+            OutsideWindow::Reject if !query.run_async => return operation_error(operations::outside_processing_window(window, now)),
+            OutsideWindow::Reject => {}
+        }
+    }
+
+    if query.run_async {
+        // Checked up front, as a job failing on it could not report back
+        if let Some(Err(e)) = payload.callback_url.as_deref().map(|url| state.notifier.check(url)) {
+            return operation_error(callback_not_allowed(e));
+        }
+        return match state.jobs.submit(caller, payload, query.profile) {
+            Ok(job) => (
+                StatusCode::ACCEPTED,
+                [("Location", format!("/jobs/{}", job.job_id))],
+                Json(job),
+            )
+                .into_response(),
+            Err(_) => api_error(ErrorKind::Unavailable, "Upload job queue is full, retry later"),
+        };
+    }
+
+    match run_upload(&state, crypto_service, &caller, payload).await {
+        Ok(response) if query.profile => {
+            let profile = response.profile.clone();
+            (StatusCode::OK, Json(ProfiledUploadResponse { upload: response, profile })).into_response()
+        }
+        Ok(response) => (StatusCode::OK, Json(response)).into_response(),
+        Err(e) => operation_error(e),
+    }
+}
+
+// For uploads that cannot be queued: refused outside the tenant's processing window
+fn check_processing_window(state: &AppState, caller: &Caller) -> Result<(), OperationError> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    match state.policy.closed_window(caller.tenant.as_deref(), now) {
+        Some(window) => Err(operations::outside_processing_window(window, now)),
+        None => Ok(()),
+    }
+}
+
+async fn run_upload(
+    state: &AppState,
+    crypto_service: &CryptoService,
+    caller: &Caller,
+    mut payload: UploadRequest,
+) -> Result<UploadResponse, OperationError> {
+    for deprecation in state.deprecations.used_by(&payload) {
+        state.metrics.record_deprecated_mode(&deprecation.mode, !deprecation.is_retired());
+    }
+    let callback = match payload.callback_url.as_deref().map(|url| state.notifier.check(url)).transpose() {
+        Ok(callback) => callback,
+        Err(e) => return Err(callback_not_allowed(e)),
+    };
+    // Known up front, so a failed upload can be reported under it
+    let file_id = callback.is_some().then(|| payload.file_id.get_or_insert_with(operations::new_file_id).clone());
+
+    let expectation = payload.expectation.clone();
+    let claimed = match &expectation {
+        Some(token) => {
+            let size = payload.ciphertext.as_ref().map_or(payload.encrypted_data.len() / 4 * 3, Vec::len);
+            state.expectations.write().await.claim(token, caller, &mut payload, size)
+        }
+        None => Ok(()),
+    };
+
+    let result = match claimed {
+        Ok(()) => {
+            let result = operations::process_upload(&upload_context(state, crypto_service), caller, payload).await;
+            if let Some(token) = &expectation {
+                let file_id = result.as_ref().ok().map(|response| response.file_id.as_str());
+                state.expectations.write().await.settle(token, file_id);
+            }
+            result
+        }
+        Err(e) => Err(e),
+    };
+    if let (Some(url), Some(file_id)) = (callback, &file_id) {
+        state.notifier.spawn(url, Callback::new(file_id, &result));
+    }
+    state.audit_log.write().await.record(upload_record(caller, file_id.as_deref(), &result));
+    // A reused file was reported when it was held
+    if let Ok(UploadResponse { file_id, review_hold: Some(hold), deduplicated: false, .. }) = &result {
+        let alert = Alert::new(Severity::Warning, "upload.held", format!("file_id {} is held for review", file_id))
+            .with_details(serde_json::json!({ "file_id": file_id, "tenant": caller.tenant, "rules": hold.rules, "max_severity": hold.max_severity }));
+        state.alerts.raise(alert);
+    }
+    observe_upload(state, &result);
+    evict_for(state, &result).await;
+    result
+}
+
+// Entity counts of a stored upload, or the error code of a failed one
+fn upload_record(caller: &Caller, file_id: Option<&str>, result: &Result<UploadResponse, OperationError>) -> AuditRecord {
+    let (file_id, details) = match result {
+        Ok(response) => {
+            let summary = response.report_summary.as_ref();
+            let details = serde_json::json!({
+                "tenant": caller.tenant,
+                "entities": summary.map(|summary| &summary.entities),
+                "total_entities": summary.map(|summary| summary.total_entities),
+                "deduplicated": response.deduplicated,
+            });
+            (Some(response.file_id.as_str()), details)
+        }
+        Err(e) => (file_id, serde_json::json!({ "tenant": caller.tenant, "code": e.code.unwrap_or(e.kind.code()) })),
+    };
+    AuditRecord::new("file.upload", caller.principal.as_deref(), file_id, audit_result(result)).with_details(details)
+}
+
+// `denied` for refusals, so they stand apart from failures
+fn audit_result<T>(result: &Result<T, OperationError>) -> &'static str {
+    match result {
+        Ok(_) => "success",
+        Err(e) if matches!(e.kind, ErrorKind::Unauthorized | ErrorKind::Forbidden) => "denied",
+        Err(_) => "failure",
+    }
+}
+
+fn callback_not_allowed(reason: String) -> OperationError {
+    OperationError::new(ErrorKind::BadRequest, format!("callback_url is not allowed: {}", reason)).with_code("callback_not_allowed")
+}
+
+fn upload_context<'a>(state: &'a AppState, crypto_service: &'a CryptoService) -> UploadContext<'a> {
+    UploadContext {
+        crypto: crypto_service,
+        redactor: &state.redactor_service,
+        relays: &state.relay_registry,
+        storage: &state.file_storage,
+        policy: &state.policy,
+        sessions: &state.sessions,
+        deprecations: &state.deprecations,
+        max_decompressed_bytes: state.max_decompressed_bytes,
+        settings: state.settings.load_full(),
+    }
+}
+
+fn observe_upload(state: &AppState, result: &Result<UploadResponse, OperationError>) {
+    match result {
+        // Nothing was redacted, so there is no timing or throughput to record
+        Ok(response) if response.deduplicated => state.metrics.record_deduplicated_upload(),
+        Ok(response) => {
+            state.metrics.record_upload(&response.profile, response.report.as_ref());
+            state.slow_uploads.observe(&response.file_id, &response.profile);
+            state.throughput.record(&response.profile);
+        }
+        Err(e) => state.metrics.record_upload_failure(e),
+    }
+}
+
+// Make room once an upload is stored, when storage is over its limits. Evicted files
+// get an erasure receipt and deletion notices, as expired files do.
+async fn evict_for(state: &AppState, result: &Result<UploadResponse, OperationError>) {
+    let Ok(response) = result else { return };
+    let mut storage = state.file_storage.write().await;
+    let mut records = Vec::new();
+    let mut notices = Vec::new();
+    for file_id in storage.eviction_candidates(&response.file_id) {
+        records.push(erasure_record(state, storage.as_ref(), &file_id, "file.evict", None, "evicted"));
+        notices.extend(storage.get_metadata(&file_id).map(|metadata| DeletionNotice::new(&file_id, metadata, "evicted")));
+        storage.delete_file(&file_id);
+        info!("Evicted file_id {} to keep storage within its limits", file_id);
+    }
+    drop(storage);
+    if records.is_empty() {
+        return;
+    }
+
+    state.metrics.record_evictions(records.len());
+    let mut audit_log = state.audit_log.write().await;
+    for record in records {
+        audit_log.record(record);
+    }
+    drop(audit_log);
+    for notice in notices {
+        state.propagation.spawn(notice, state.audit_log.clone(), state.alerts.clone());
+    }
+}
+
+// Dry run of an upload: what would be redacted, with nothing stored
+#[utoipa::path(
+    post, path = "/analyze", tag = "uploads", params(AnalyzeQuery), request_body = UploadRequest,
+    responses(
+        (status = 200, description = "What would be redacted; nothing is stored", body = AnalysisResponse),
+        (status = 400, description = "Invalid upload", body = ErrorResponse),
+    )
+)]
+async fn analyze_upload(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(query): Query<AnalyzeQuery>,
+    Json(payload): Json<UploadRequest>,
+) -> impl IntoResponse {
+    let Some(crypto_service) = state.key_provisioner.get() else {
+        return key_not_provisioned();
+    };
+    if let Err(e) = check_processing_window(&state, &caller) {
+        return operation_error(e);
+    }
+    let context = upload_context(&state, crypto_service);
+    match operations::analyze_upload(&context, &caller, payload, query.mask_snippets).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => operation_error(e),
+    }
+}
+
+// Expected processing time and quota cost of an upload, from recent throughput
+#[utoipa::path(
+    post, path = "/estimate", tag = "uploads", request_body = EstimateRequest,
+    responses(
+        (status = 200, description = "Expected processing time and quota cost", body = Estimate),
+        (status = 400, description = "Invalid estimate request", body = ErrorResponse),
+    )
+)]
+async fn estimate_upload(State(state): State<AppState>, Json(request): Json<EstimateRequest>) -> impl IntoResponse {
+    let bad_request = |error: String| api_error(ErrorKind::BadRequest, error);
+
+    if let Some(format) = request.format.as_deref().filter(|format| *format != "text") {
+        return bad_request(format!("Unsupported format: {}; only text is processed", format));
+    }
+    let size_bytes = match (request.size_bytes, request.encrypted_sample.as_deref()) {
+        (Some(size_bytes), None) => size_bytes,
+        (None, Some(sample)) => estimate::sample_plaintext_bytes(sample),
+        _ => return bad_request("Provide either size_bytes or encrypted_sample".to_string()),
+    };
+
+    // Extraction skips the analyzer, so it is timed separately
+    let pipeline = match request.redaction_strategy.as_deref() {
+        Some("extract") => "template",
+        _ => state.redactor_service.backend_name(),
+    };
+    Json(state.throughput.estimate(pipeline, size_bytes)).into_response()
+}
+
+#[utoipa::path(
+    get, path = "/jobs/{job_id}", tag = "jobs", params(("job_id" = String, Path, description = "Id of an upload job")),
+    responses(
+        (status = 200, description = "The job's status", body = JobView),
+        (status = 404, description = "Job not found", body = ErrorResponse),
+    )
+)]
+async fn job_status(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(job_id): Path<String>,
+) -> impl IntoResponse {
+    match state.jobs.status(&job_id, &caller) {
+        Some(job) => Json(job).into_response(),
+        None => job_not_found(),
+    }
+}
+
+// The upload response once the job is done, its error once it failed, and 202 with the
+// job status while it is still queued or processing
+#[utoipa::path(
+    get, path = "/jobs/{job_id}/result", tag = "jobs", params(("job_id" = String, Path, description = "Id of an upload job")),
+    responses(
+        (status = 200, description = "The upload response", body = UploadResponse),
+        (status = 202, description = "Still queued or processing", body = JobView),
+        (status = 404, description = "Job not found", body = ErrorResponse),
+    )
+)]
+async fn job_result(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(job_id): Path<String>,
+) -> impl IntoResponse {
+    match state.jobs.outcome(&job_id, &caller) {
+        Some(outcome) => job_outcome(outcome),
+        None => job_not_found(),
+    }
+}
+
+// Server-sent events following a job: its status now, then `processing`, `progress`
+// for each analyzed chunk, and `completed` or `failed`, after which the stream ends
+#[utoipa::path(
+    get, path = "/jobs/{job_id}/events", tag = "jobs", params(("job_id" = String, Path, description = "Id of an upload job")),
+    responses(
+        (status = 200, description = "`text/event-stream` of the job's progress", content_type = "text/event-stream", body = String),
+        (status = 404, description = "Job not found", body = ErrorResponse),
+    )
+)]
+async fn job_event_stream(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(job_id): Path<String>,
+) -> Response {
+    // Subscribed before the first look, so an event in between is not missed
+    let events = state.job_events.subscribe();
+    let Some(job) = state.jobs.status(&job_id, &caller) else {
+        return job_not_found();
+    };
+    let status = Event::default().event("status").json_data(&job).unwrap_or_default();
+    let following = job.is_pending().then_some((events, state, caller));
+    let updates = futures_util::stream::unfold(following, move |following| {
+        let job_id = job_id.clone();
+        async move {
+            let (mut events, state, caller) = following?;
+            loop {
+                match events.recv().await {
+                    Ok(event) if event.job_id == job_id => {
+                        let sse = Event::default().event(event.name()).json_data(&event).unwrap_or_default();
+                        let following = (!event.is_final()).then_some((events, state, caller));
+                        return Some((sse, following));
+                    }
+                    Ok(_) => {}
+                    // Progress may be skipped, but not the end: when events were missed,
+                    // a finished job is told as it now is
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        let job = state.jobs.status(&job_id, &caller).filter(|job| !job.is_pending());
+                        if let Some(job) = job {
+                            return Some((Event::default().event("status").json_data(&job).unwrap_or_default(), None));
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        }
+    });
+    let stream = futures_util::stream::once(async { status }).chain(updates).map(Ok::<_, Infallible>);
+    Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+}
+
+fn job_outcome(outcome: JobOutcome) -> Response {
+    match outcome {
+        JobOutcome::Done(response) => (StatusCode::OK, Json(response)).into_response(),
+        JobOutcome::Failed { status, code, message } => {
+            let kind = ErrorKind::from_status(status).unwrap_or(ErrorKind::Internal);
+            let error = OperationError::new(kind, message);
+            operation_error(match code {
+                Some(code) => error.with_code(code),
+                None => error,
+            })
+        }
+        JobOutcome::Pending(job) => (StatusCode::ACCEPTED, Json(job)).into_response(),
+    }
+}
+
+fn job_not_found() -> Response {
+    api_error(ErrorKind::NotFound, "Job not found")
+}
+
+// Redact text as it arrives, answering with redacted chunks over a chunked response.
+// With `X-Session-Id`, the body and the response are newline-separated base64 frames
+// under a key derived for this stream from the session key and `X-Stream-Nonce`.
+#[utoipa::path(
+    post, path = "/redact/stream", tag = "uploads",
+    params(
+        StreamQuery,
+        ("X-Session-Id" = Option<String>, Header, description = "Session whose key the body and response frames are sealed under"),
+        ("X-Stream-Nonce" = Option<String>, Header, description = "At least 12 random bytes in base64, for session streams"),
+    ),
+    request_body(content = String, content_type = "text/plain", description = "Text, or newline-separated base64 frames with `X-Session-Id`"),
+    responses(
+        (status = 200, description = "Redacted text as it is released, or sealed frames as `application/x-ndjson`", body = String, content_type = "text/plain"),
+        (status = 400, description = "Invalid stream options", body = ErrorResponse),
+        (status = 403, description = "The credential may not upload", body = ErrorResponse),
+    )
+)]
+async fn redact_stream(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(query): Query<StreamQuery>,
+    request_headers: HeaderMap,
+    body: Body,
+) -> impl IntoResponse {
+    if !caller.allows(Scope::Upload) {
+        return api_error(ErrorKind::Forbidden, "This credential may not upload files");
+    }
+    if let Err(e) = check_processing_window(&state, &caller) {
+        return operation_error(e);
+    }
+    let strategy = query.strategy.unwrap_or_else(|| "replace".to_string());
+    // Pseudonyms need a stored file to be reversed from
+    if strategy == "extract" || strategy == PSEUDONYMIZE {
+        return api_error(ErrorKind::BadRequest, format!("The {} strategy cannot be streamed", strategy));
+    }
+    let language = match state.redactor_service.languages().fixed(query.language.as_deref()) {
+        Ok(language) => language,
+        Err(e) => return operation_error(operations::unsupported_language(e)),
+    };
+    let hashing = match state.key_provisioner.get() {
+        Some(crypto_service) => {
+            let request = UploadRequest { redaction_strategy: Some(strategy.clone()), hash_length: query.hash_length, ..UploadRequest::default() };
+            match operations::hash_masking(crypto_service, &caller, &request) {
+                Ok(hashing) => hashing,
+                Err(e) => return operation_error(e),
+            }
+        }
+        None if strategy == masking::HASH => return operation_error(key_unavailable()),
+        None => None,
+    };
+
+    let header = |name: &str| request_headers.get(name).and_then(|value| value.to_str().ok());
+    let cipher = match header("X-Session-Id") {
+        Some(session_id) => {
+            let Some(stream_nonce) = header("X-Stream-Nonce").and_then(|nonce| BASE64.decode(nonce).ok()).filter(|nonce| nonce.len() >= 12) else {
+                return api_error(ErrorKind::BadRequest, "Session streams need X-Stream-Nonce, at least 12 random bytes in base64");
+            };
+            let session_key = match state.sessions.upload_key(&caller, session_id, header("X-Stream-Nonce")) {
+                Ok(session_key) => session_key,
+                Err(e) => return operation_error(operations::session_error(e)),
+            };
+            match StreamCipher::server(&session_key, &stream_nonce) {
+                Ok(cipher) => Some(cipher),
+                Err(e) => return operation_error(OperationError::new(ErrorKind::Internal, e.to_string())),
+            }
+        }
+        None => None,
+    };
+
+    let encrypted = cipher.is_some();
+    let (sender, receiver) = tokio::sync::mpsc::channel::<std::io::Result<Bytes>>(16);
+    tokio::spawn(async move {
+        let settings = StreamSettings { strategy, hashing, language };
+        let result = pump_stream(&state, &caller, settings, body, cipher, &sender).await;
+        if let Err(e) = result {
+            warn!("Redaction stream failed: {}", e);
+            // Aborts the response, so the client cannot mistake it for a complete one
+            let _ = sender.send(Err(std::io::Error::other(e.to_string()))).await;
+        }
+    });
+
+    let content_type = if encrypted { "application/x-ndjson" } else { "text/plain; charset=utf-8" };
+    let chunks = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    (StatusCode::OK, [("Content-Type", content_type)], Body::from_stream(chunks)).into_response()
+}
+
+// How a `/redact/stream` body is redacted
+struct StreamSettings {
+    strategy: String,
+    hashing: Option<HashMasking>,
+    language: String,
+}
+
+async fn pump_stream(
+    state: &AppState,
+    caller: &Caller,
+    StreamSettings { strategy, hashing, language }: StreamSettings,
+    body: Body,
+    mut cipher: Option<StreamCipher>,
+    sender: &tokio::sync::mpsc::Sender<std::io::Result<Bytes>>,
+) -> anyhow::Result<()> {
+    let options = RedactionOptions {
+        strategy: &strategy,
+        tenant: caller.tenant.as_deref(),
+        language: &language,
+        pipelines: state.policy.pipelines_for(caller.tenant.as_deref()),
+        filter: EntityFilter { language: Some(&language), ..EntityFilter::default() },
+        bidi: state.policy.bidi,
+        custom: None,
+        boundaries: state.policy.boundaries(),
+        hashing: hashing.as_ref(),
+    };
+    let redactor = state.redactor_service.as_ref();
+    let mut redactor_stream = stream::StreamRedactor::from_env();
+    let mut frames = Vec::new();
+
+    let mut body = body.into_data_stream();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        let plaintext = match &mut cipher {
+            // Frames may span body chunks; only whole lines are opened
+            Some(frame_cipher) => {
+                frames.extend_from_slice(&chunk);
+                let Some(end) = frames.iter().rposition(|b| *b == b'\n') else {
+                    continue;
+                };
+                let lines: Vec<u8> = frames.drain(..=end).collect();
+                open_frames(frame_cipher, &lines)?
+            }
+            None => chunk.to_vec(),
+        };
+        let redacted = redactor_stream.push(redactor, &options, &plaintext).await?;
+        if let Some(bytes) = encode_chunk(redacted, &mut cipher)? {
+            if sender.send(Ok(bytes)).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    if let Some(frame_cipher) = &mut cipher {
+        let plaintext = open_frames(frame_cipher, &frames)?;
+        let redacted = redactor_stream.push(redactor, &options, &plaintext).await?;
+        if let Some(bytes) = encode_chunk(redacted, &mut cipher)? {
+            let _ = sender.send(Ok(bytes)).await;
+        }
+    }
+    let (redacted, _) = redactor_stream.finish(redactor, &options).await?;
+    if let Some(bytes) = encode_chunk(redacted, &mut cipher)? {
+        let _ = sender.send(Ok(bytes)).await;
+    }
+    Ok(())
+}
+
+// Response body bytes for released text: as is, or sealed into a frame
+fn encode_chunk(text: String, cipher: &mut Option<StreamCipher>) -> anyhow::Result<Option<Bytes>> {
+    if text.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(match cipher {
+        Some(cipher) => Bytes::from(format!("{}\n", BASE64.encode(cipher.seal(text.as_bytes())?))),
+        None => Bytes::from(text),
+    }))
+}
+
+// Decrypt newline-separated base64 frames, in order
+fn open_frames(cipher: &mut StreamCipher, lines: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut plaintext = Vec::new();
+    for line in lines.split(|b| *b == b'\n').map(|line| line.trim_ascii()).filter(|line| !line.is_empty()) {
+        let frame = BASE64.decode(line).map_err(|e| anyhow::anyhow!("Invalid base64 frame: {}", e))?;
+        plaintext.extend(cipher.open(&frame)?);
+    }
+    Ok(plaintext)
+}
+
+#[utoipa::path(
+    get, path = "/download/{file_id}", tag = "downloads",
+    params(("file_id" = String, Path, description = "Id of a stored file"), DownloadQuery, ("X-Encrypted-Session-Key" = Option<String>, Header, description = "Key to encrypt an `encrypted` download under, wrapped to the service key")),
+    responses(
+        (status = 200, description = "The redacted file, or with `encrypted=true` the file encrypted for the client", content(
+            (String = "text/plain"),
+            (EncryptedDownload = "application/json"),
+        ), headers(
+            ("X-Signature" = String, description = "`t=<timestamp>,kid=<key id>,ed25519=<signature>` over the redacted file before encryption"),
+            ("X-Content-SHA256" = String, description = "Hex SHA-256 of the body, for plain downloads"),
+        )),
+        (status = 202, description = "The file's upload job is still running after `wait`", body = JobView),
+        (status = 403, description = "Access denied, or downloads are disabled for the tenant", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    )
+)]
+async fn download_file(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(file_id): Path<String>,
+    Query(query): Query<DownloadQuery>,
+    request_headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Err(e) = check_downloads_allowed(&state, &caller, Some(&file_id), "download").await {
+        return operation_error(e);
+    }
+    // The file of a queued upload can be waited for instead of polled
+    if let Some(wait) = query.wait {
+        let job = state.jobs.wait_for_file(&file_id, &caller, wait).await;
+        if let Some(outcome @ (JobOutcome::Pending(_) | JobOutcome::Failed { .. })) =
+            job.and_then(|job| state.jobs.outcome(&job.job_id, &caller))
+        {
+            return job_outcome(outcome);
+        }
+    }
+
+    let file = match fetch_file(&state, &caller, &file_id, query.format, "download").await {
+        Ok(file) if query.manifest => operations::embed_manifest(file, query.format),
+        result => result,
+    };
+    match file {
+        Ok(file) => {
+            let signature = output_signature(&state, &file_id, file.content.as_bytes());
+            with_signature(file_response(&state, &file_id, file, query.format, query.encrypted, &request_headers), signature)
+        }
+        Err(e) => operation_error(e),
+    }
+}
+
+// Detached signature of what `GET /download/:file_id` serves with the same format and
+// manifest, for systems that receive the file without its headers
+#[utoipa::path(
+    get, path = "/files/{file_id}/signature", tag = "downloads",
+    params(("file_id" = String, Path, description = "Id of a stored file"), SignatureQuery),
+    responses(
+        (status = 200, description = "Ed25519 signature of the download", body = OutputSignature),
+        (status = 403, description = "Access denied, or downloads are disabled for the tenant", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    )
+)]
+async fn get_signature(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(file_id): Path<String>,
+    Query(query): Query<SignatureQuery>,
+) -> impl IntoResponse {
+    let Some(crypto_service) = state.key_provisioner.get() else {
+        return key_not_provisioned();
+    };
+    if let Err(e) = check_downloads_allowed(&state, &caller, Some(&file_id), "signature").await {
+        return operation_error(e);
+    }
+    let file = match fetch_file(&state, &caller, &file_id, query.format, "signature").await {
+        Ok(file) if query.manifest => operations::embed_manifest(file, query.format),
+        result => result,
+    };
+    match file {
+        Ok(file) => Json(OutputSignature::issue(crypto_service, &file_id, file.content.as_bytes(), unix_now())).into_response(),
+        Err(e) => operation_error(e),
+    }
+}
+
+// Signature of a redacted output as served, before any encryption for the client
+fn output_signature(state: &AppState, file_id: &str, content: &[u8]) -> Option<OutputSignature> {
+    let crypto_service = state.key_provisioner.get()?;
+    Some(OutputSignature::issue(crypto_service, file_id, content, unix_now()))
+}
+
+fn with_signature(mut response: Response, signature: Option<OutputSignature>) -> Response {
+    if let (true, Some(signature)) = (response.status().is_success(), signature) {
+        if let Ok(value) = signature.header().parse() {
+            response.headers_mut().insert("X-Signature", value);
+        }
+    }
+    response
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+// The file in `format`, whose view is rendered and cached on first request. Every
+// attempt is audited under the `route` it came from.
+async fn fetch_file(state: &AppState, caller: &Caller, file_id: &str, format: DownloadFormat, route: &str) -> Result<DownloadedFile, OperationError> {
+    let result = render_file(state, caller, file_id, format).await;
+    state.audit_log.write().await.record(
+        AuditRecord::new("file.download", caller.principal.as_deref(), Some(file_id), audit_result(&result))
+            .with_details(serde_json::json!({ "route": route, "format": format, "tenant": caller.tenant })),
+    );
+    result
+}
+
+async fn render_file(state: &AppState, caller: &Caller, file_id: &str, format: DownloadFormat) -> Result<DownloadedFile, OperationError> {
+    // A storage stuck behind slow writes fails the download instead of holding it open
+    let deliver = state.policy.stage_timeouts.budget(Stage::Deliver);
+    let storage = operations::within(Stage::Deliver, deliver, async { Ok(state.file_storage.read().await) })
+        .await
+        .map_err(|deadline| operations::stage_timeout(&deadline))?;
+    let (file, rendered) = operations::fetch_download_as(storage.as_ref(), caller, file_id, format)?;
+    drop(storage);
+
+    if let Some(view) = rendered {
+        operations::cache_view(state.file_storage.write().await.as_mut(), file_id, format, view);
+    }
+    Ok(file)
+}
+
+// Original text of a pseudonymized file, in the same forms as a download
+#[utoipa::path(
+    post, path = "/files/{file_id}/unredact", tag = "downloads",
+    params(("file_id" = String, Path, description = "Id of a stored file"), DownloadQuery, ("X-Encrypted-Session-Key" = Option<String>, Header, description = "Key to encrypt an `encrypted` download under, wrapped to the service key")),
+    responses(
+        (status = 200, description = "The original text of a pseudonymized file", content(
+            (String = "text/plain"),
+            (EncryptedDownload = "application/json"),
+        )),
+        (status = 400, description = "Only txt without a manifest is served", body = ErrorResponse),
+        (status = 403, description = "Access denied", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    )
+)]
+async fn unredact_file(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(file_id): Path<String>,
+    Query(query): Query<DownloadQuery>,
+    request_headers: HeaderMap,
+) -> impl IntoResponse {
+    let Some(crypto_service) = state.key_provisioner.get() else {
+        return key_not_provisioned();
+    };
+    if let Err(e) = check_downloads_allowed(&state, &caller, Some(&file_id), "unredact").await {
+        return operation_error(e);
+    }
+    // Views mark redactions, of which the original text has none
+    if query.format != DownloadFormat::Txt {
+        return api_error(ErrorKind::BadRequest, "Unredacted files are only served as txt");
+    }
+    // Manifests vouch for the redacted text
+    if query.manifest {
+        return api_error(ErrorKind::BadRequest, "Unredacted files carry no processing manifest");
+    }
+    let result = operations::unredact_file(crypto_service, state.file_storage.read().await.as_ref(), &caller, &file_id);
+
+    let outcome = if result.is_ok() { "success" } else { "denied" };
+    let mut record = AuditRecord::new("file.unredact", caller.principal.as_deref(), Some(&file_id), outcome);
+    if let Err(e) = &result {
+        record = record.with_details(serde_json::json!({ "reason": e.message }));
+    }
+    state.audit_log.write().await.record(record);
+
+    match result {
+        Ok(file) => file_response(&state, &file_id, file, DownloadFormat::Txt, query.encrypted, &request_headers),
+        Err(e) => operation_error(e),
+    }
+}
+
+// The retained original of a file, in the same forms as a download. Every attempt is
+// audited with the reason given for it.
+#[utoipa::path(
+    post, path = "/files/{file_id}/original", tag = "downloads", request_body = OriginalRequest,
+    params(("file_id" = String, Path, description = "Id of a stored file"), DownloadQuery, ("X-Encrypted-Session-Key" = Option<String>, Header, description = "Key to encrypt an `encrypted` download under, wrapped to the service key")),
+    responses(
+        (status = 200, description = "The text the file was uploaded with", content(
+            (String = "text/plain"),
+            (EncryptedDownload = "application/json"),
+        )),
+        (status = 400, description = "No reason was given, or a format other than txt was asked for", body = ErrorResponse),
+        (status = 403, description = "The credential lacks the `original` scope, or the caller may not unredact the file", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+        (status = 409, description = "The file was uploaded without retain_original", body = ErrorResponse),
+        (status = 410, description = "The file, or its original, was purged", body = ErrorResponse),
+    )
+)]
+async fn original_file(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(file_id): Path<String>,
+    Query(query): Query<DownloadQuery>,
+    request_headers: HeaderMap,
+    Json(payload): Json<OriginalRequest>,
+) -> impl IntoResponse {
+    let Some(crypto_service) = state.key_provisioner.get() else {
+        return key_not_provisioned();
+    };
+    let reason = payload.reason.trim();
+    if reason.is_empty() || reason.chars().count() > MAX_ORIGINAL_REASON_CHARS {
+        return api_error(ErrorKind::BadRequest, format!("reason must be 1 to {} characters", MAX_ORIGINAL_REASON_CHARS));
+    }
+    if let Err(e) = check_downloads_allowed(&state, &caller, Some(&file_id), "original").await {
+        return operation_error(e);
+    }
+    if query.format != DownloadFormat::Txt || query.manifest {
+        return api_error(ErrorKind::BadRequest, "Originals are only served as txt, without a manifest");
+    }
+    let result = operations::original_file(crypto_service, state.file_storage.read().await.as_ref(), &caller, &file_id);
+
+    let mut details = serde_json::json!({ "reason": reason, "tenant": caller.tenant });
+    if let Err(e) = &result {
+        details["denied"] = serde_json::json!(e.message);
+    }
+    state.audit_log.write().await.record(
+        AuditRecord::new("file.original", caller.principal.as_deref(), Some(&file_id), audit_result(&result)).with_details(details),
+    );
+
+    match result {
+        Ok(file) => file_response(&state, &file_id, file, DownloadFormat::Txt, query.encrypted, &request_headers),
+        Err(e) => operation_error(e),
+    }
+}
+
+// Redact a file's retained original again with other settings, as a new file
+#[utoipa::path(
+    post, path = "/files/{file_id}/reprocess", tag = "uploads", params(("file_id" = String, Path, description = "Id of a stored file")), request_body = ReprocessRequest,
+    responses(
+        (status = 201, description = "The retained original redacted again, as a new file", body = UploadResponse),
+        (status = 403, description = "The caller may not unredact the file", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+        (status = 409, description = "The file has no retained original", body = ErrorResponse),
+    )
+)]
+async fn reprocess_file(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(file_id): Path<String>,
+    Json(payload): Json<ReprocessRequest>,
+) -> impl IntoResponse {
+    if let Some(status) = state.maintenance.active() {
+        return under_maintenance(status);
+    }
+    let Some(crypto_service) = state.key_provisioner.get() else {
+        return key_not_provisioned();
+    };
+    if let Err(e) = check_processing_window(&state, &caller) {
+        return operation_error(e);
+    }
+    let strategy = payload.redaction_strategy.clone().unwrap_or_else(|| state.settings.load().default_strategy.clone());
+    let result = operations::reprocess_file(&upload_context(&state, crypto_service), &caller, &file_id, payload).await;
+
+    // Recorded against the source; `file_id` in the details is the new file
+    let details = match &result {
+        Ok(response) => serde_json::json!({ "file_id": response.file_id, "strategy": strategy, "tenant": caller.tenant }),
+        Err(e) => serde_json::json!({ "reason": e.message }),
+    };
+    state.audit_log.write().await.record(
+        AuditRecord::new("file.reprocess", caller.principal.as_deref(), Some(&file_id), audit_result(&result)).with_details(details),
+    );
+    observe_upload(&state, &result);
+    evict_for(&state, &result).await;
+
+    match result {
+        Ok(response) => (StatusCode::CREATED, Json(response)).into_response(),
+        Err(e) => operation_error(e),
+    }
+}
+
+fn file_response(
+    state: &AppState,
+    file_id: &str,
+    file: DownloadedFile,
+    format: DownloadFormat,
+    encrypted: bool,
+    request_headers: &HeaderMap,
+) -> Response {
+    // Encrypted mode: the upload's session key, or one the client wraps to the service key
+    if encrypted {
+        let Some(crypto_service) = state.key_provisioner.get() else {
+            return key_not_provisioned();
+        };
+        let encrypted_session_key = request_headers
+            .get("X-Encrypted-Session-Key")
+            .and_then(|value| value.to_str().ok());
+        return match operations::encrypt_download(crypto_service, file_id, file, encrypted_session_key) {
+            Ok(download) => Json(download).into_response(),
+            Err(e) => operation_error(e),
+        };
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert("Content-Disposition", content_disposition(&file.file_name));
+    headers.insert("Content-Type", format.content_type().parse().unwrap());
+    headers.insert(CONTENT_SHA256, manifest::content_digest(file.content.as_bytes()).parse().unwrap());
+    insert_relay_headers(&mut headers, &file);
+
+    (StatusCode::OK, headers, file.content).into_response()
+}
+
+// An attachment named after the client's file name, which may hold any character: an
+// ASCII `filename` with the others replaced, and the exact name percent-encoded in
+// `filename*` (RFC 6266)
+fn content_disposition(file_name: &str) -> HeaderValue {
+    const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+    let ascii: String = file_name.chars()
+        .map(|c| if (c.is_ascii_graphic() || c == ' ') && c != '"' && c != '\\' { c } else { '_' })
+        .collect();
+    let value = format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", ascii, utf8_percent_encode(file_name, ATTR_CHAR));
+    HeaderValue::from_str(&value).unwrap_or_else(|_| HeaderValue::from_static("attachment"))
+}
+
+fn insert_relay_headers(headers: &mut HeaderMap, file: &DownloadedFile) {
+    if let Some(relay) = &file.relay {
+        if let (Ok(relay_id), Ok(client_id)) = (relay.relay_id.parse(), relay.client_id.parse()) {
+            headers.insert("X-Relay-Id", relay_id);
+            headers.insert("X-Origin-Client-Id", client_id);
+        }
+    }
+}
+
+// A PDF or DOCX upload's redacted text rebuilt as a document in its original format
+#[utoipa::path(
+    get, path = "/files/{file_id}/document", tag = "downloads",
+    params(("file_id" = String, Path, description = "Id of a stored file"), DocumentQuery, ("X-Encrypted-Session-Key" = Option<String>, Header, description = "Key to encrypt an `encrypted` download under, wrapped to the service key")),
+    responses(
+        (status = 200, description = "The redacted PDF or DOCX, or a zip of it and its manifest", content(
+            (Vec<u8> = "application/octet-stream"),
+            (EncryptedDownload = "application/json"),
+        ), headers(
+            ("X-Signature" = String, description = "`t=<timestamp>,kid=<key id>,ed25519=<signature>` over the document before encryption"),
+            ("X-Content-SHA256" = String, description = "Hex SHA-256 of the body, for plain downloads"),
+        )),
+        (status = 403, description = "Access denied, or downloads are disabled for the tenant", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    )
+)]
+async fn download_document(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(file_id): Path<String>,
+    Query(query): Query<DocumentQuery>,
+    request_headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Err(e) = check_downloads_allowed(&state, &caller, Some(&file_id), "document").await {
+        return operation_error(e);
+    }
+    let file = match fetch_file(&state, &caller, &file_id, DownloadFormat::Txt, "document").await {
+        Ok(file) => file,
+        Err(e) => return operation_error(e),
+    };
+    let (mut file_name, mut content) = match operations::rebuild_document(&file_id, &file) {
+        Ok(document) => document,
+        Err(e) => return operation_error(e),
+    };
+    let mut content_type = file.document_format.map_or("application/octet-stream", DocumentFormat::content_type);
+    if query.manifest {
+        (file_name, content) = match operations::bundle_manifest(&file_id, &file, &file_name, &content) {
+            Ok(bundle) => bundle,
+            Err(e) => return operation_error(e),
+        };
+        content_type = "application/zip";
+    }
+    let signature = output_signature(&state, &file_id, &content);
+
+    if query.encrypted {
+        let Some(crypto_service) = state.key_provisioner.get() else {
+            return key_not_provisioned();
+        };
+        let encrypted_session_key = request_headers
+            .get("X-Encrypted-Session-Key")
+            .and_then(|value| value.to_str().ok());
+        return match operations::encrypt_bytes(crypto_service, &file_id, file_name, &content, file.session_key, encrypted_session_key) {
+            Ok(download) => with_signature(Json(download).into_response(), signature),
+            Err(e) => operation_error(e),
+        };
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert("Content-Disposition", content_disposition(&file_name));
+    headers.insert("Content-Type", content_type.parse().unwrap());
+    headers.insert(CONTENT_SHA256, manifest::content_digest(&content).parse().unwrap());
+    insert_relay_headers(&mut headers, &file);
+
+    with_signature((StatusCode::OK, headers, content).into_response(), signature)
+}
+
+#[utoipa::path(
+    get, path = "/files/{file_id}/preview", tag = "files", params(("file_id" = String, Path, description = "Id of a stored file"), PreviewQuery),
+    responses(
+        (status = 200, description = "The start of the redacted file", body = String, content_type = "text/plain",
+            headers(("X-Preview-Truncated" = bool), ("X-Total-Size" = usize))),
+        (status = 403, description = "Access denied, or downloads are disabled for the tenant", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    )
+)]
+async fn preview_file(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(file_id): Path<String>,
+    Query(query): Query<PreviewQuery>,
+) -> impl IntoResponse {
+    if let Err(e) = check_downloads_allowed(&state, &caller, Some(&file_id), "preview").await {
+        return operation_error(e);
+    }
+    let max_bytes = query.bytes.unwrap_or(DEFAULT_PREVIEW_BYTES).min(MAX_PREVIEW_BYTES);
+    let storage = state.file_storage.read().await;
+
+    match operations::fetch_preview(storage.as_ref(), &caller, &file_id, max_bytes) {
+        Ok(preview) => (
+            StatusCode::OK,
+            [
+                ("Content-Type", "text/plain; charset=utf-8".to_string()),
+                ("X-Preview-Truncated", preview.truncated.to_string()),
+                ("X-Total-Size", preview.total_size.to_string()),
+            ],
+            preview.content,
+        )
+            .into_response(),
+        Err(e) => operation_error(e),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/files/{file_id}/report", tag = "files", params(("file_id" = String, Path, description = "Id of a stored file")),
+    responses(
+        (status = 200, description = "The file's redaction report", body = ReportMatch),
+        (status = 403, description = "Access denied", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    )
+)]
+async fn get_report(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(file_id): Path<String>,
+) -> impl IntoResponse {
+    let storage = state.file_storage.read().await;
+
+    match operations::fetch_report(storage.as_ref(), &caller, &file_id) {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => operation_error(e),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/files/{file_id}/heatmap", tag = "files", params(("file_id" = String, Path, description = "Id of a stored file")),
+    responses(
+        (status = 200, description = "Where in the file its detections are", body = HeatmapResponse),
+        (status = 403, description = "Access denied", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    )
+)]
+async fn get_heatmap(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(file_id): Path<String>,
+) -> impl IntoResponse {
+    let storage = state.file_storage.read().await;
+
+    match operations::fetch_heatmap(storage.as_ref(), &caller, &file_id) {
+        Ok(heatmap) => Json(HeatmapResponse { file_id, heatmap }).into_response(),
+        Err(e) => operation_error(e),
+    }
+}
+
+#[utoipa::path(
+    post, path = "/files/{file_id}/release", tag = "files", params(("file_id" = String, Path, description = "Id of a stored file")),
+    responses(
+        (status = 200, description = "The file's review hold is lifted", body = ReleaseResponse),
+        (status = 403, description = "Access denied", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    )
+)]
+async fn release_file(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(file_id): Path<String>,
+) -> impl IntoResponse {
+    let result = operations::release_hold(state.file_storage.write().await.as_mut(), &caller, &file_id);
+
+    match result {
+        Ok(hold) => {
+            state.audit_log.write().await.record(
+                AuditRecord::new("file.release", caller.principal.as_deref(), Some(&file_id), "success")
+                    .with_details(serde_json::json!({ "rules": hold.rules })),
+            );
+            Json(ReleaseResponse { file_id, released: true, rules: hold.rules }).into_response()
+        }
+        Err(e) => {
+            state.audit_log.write().await.record(
+                AuditRecord::new("file.release", caller.principal.as_deref(), Some(&file_id), "denied")
+                    .with_details(serde_json::json!({ "reason": e.message })),
+            );
+            operation_error(e)
+        }
+    }
+}
+
+// Zip of the selected redacted artifacts plus a manifest, streamed as it is written
+#[utoipa::path(
+    post, path = "/download/bulk", tag = "downloads", request_body = BulkDownloadRequest,
+    responses(
+        (status = 200, description = "Zip of the selected files and a manifest", body = Vec<u8>, content_type = "application/zip"),
+        (status = 400, description = "Invalid selection", body = ErrorResponse),
+        (status = 403, description = "Downloads are disabled for the tenant", body = ErrorResponse),
+    )
+)]
+async fn download_bulk(
+    State(state): State<AppState>,
+    caller: Caller,
+    Json(payload): Json<BulkDownloadRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = check_downloads_allowed(&state, &caller, None, "bulk").await {
+        return operation_error(e);
+    }
+    let selection = match bulk::select(state.file_storage.read().await.as_ref(), &caller, &payload) {
+        Ok(selection) => selection,
+        Err(e) => {
+            return api_error(ErrorKind::BadRequest, e);
+        }
+    };
+    info!("Bulk download of {} files", selection.files.len());
+    {
+        let mut audit_log = state.audit_log.write().await;
+        for entry in &selection.manifest {
+            let result = match entry.status {
+                "included" => "success",
+                "forbidden" => "denied",
+                _ => "failure",
+            };
+            audit_log.record(
+                AuditRecord::new("file.download", caller.principal.as_deref(), Some(&entry.file_id), result)
+                    .with_details(serde_json::json!({ "route": "bulk", "status": entry.status, "tenant": caller.tenant })),
+            );
+        }
+    }
+
+    let (reader, writer) = tokio::io::duplex(64 * 1024);
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = bulk::write_archive(selection, SyncIoBridge::new(writer)) {
+            warn!("Writing bulk download archive failed: {}", e);
+        }
+    });
+
+    (
+        StatusCode::OK,
+        [
+            ("Content-Type", "application/zip"),
+            ("Content-Disposition", "attachment; filename=\"redacted_files.zip\""),
+        ],
+        Body::from_stream(ReaderStream::new(reader)),
+    )
+        .into_response()
+}
+
+// Search redaction reports (never content) in the caller's tenant
+#[utoipa::path(
+    get, path = "/files/search", tag = "files", params(SearchQuery),
+    responses((status = 200, description = "Reports of matching files in the caller's tenant", body = SearchResponse))
+)]
+async fn search_reports(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(query): Query<SearchQuery>,
+) -> impl IntoResponse {
+    let limit = query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT).min(MAX_SEARCH_LIMIT);
+    let storage = state.file_storage.read().await;
+    let report_query = ReportQuery {
+        entity: query.entity,
+        min_count: query.min_count.unwrap_or(1),
+        from: query.from,
+        to: query.to,
+        tenant: None,
+    };
+    let matches = operations::search_reports(storage.as_ref(), &caller, report_query, limit);
+    Json(SearchResponse { files: matches })
+}
+
+#[utoipa::path(
+    get, path = "/files", tag = "files", params(ListQuery),
+    responses(
+        (status = 200, description = "A page of the caller's files", body = FileList),
+        (status = 400, description = "Invalid filter", body = ErrorResponse),
+    )
+)]
+async fn list_files(
+    State(state): State<AppState>,
+    caller: Caller,
+    Query(query): Query<ListQuery>,
+) -> impl IntoResponse {
+    let limit = query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT).min(MAX_SEARCH_LIMIT);
+    let filter = FileFilter { name_prefix: query.name_prefix, created_after: query.created_after };
+    match operations::list_files(state.file_storage.read().await.as_ref(), &caller, &filter, query.offset, limit) {
+        Ok(files) => Json(files).into_response(),
+        Err(e) => operation_error(e),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/files/by-external/{external_id}", tag = "files",
+    params(("external_id" = String, Path, description = "The client's own identifier of the file")),
+    responses(
+        (status = 200, description = "The file with this external id", body = ExternalIdMatch),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    )
+)]
+async fn find_by_external_id(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(external_id): Path<String>,
+) -> impl IntoResponse {
+    let storage = state.file_storage.read().await;
+
+    match operations::find_by_external_id(storage.as_ref(), &caller, &external_id) {
+        Ok(found) => Json(found).into_response(),
+        Err(e) => operation_error(e),
+    }
+}
+
+#[utoipa::path(
+    post, path = "/files/{file_id}/feedback", tag = "feedback", params(("file_id" = String, Path, description = "Id of a stored file")), request_body = FeedbackRequest,
+    responses(
+        (status = 201, description = "Feedback recorded", body = FeedbackEntry),
+        (status = 403, description = "Access denied", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    )
+)]
+async fn submit_feedback(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(file_id): Path<String>,
+    Json(payload): Json<FeedbackRequest>,
+) -> impl IntoResponse {
+    let tenant = match operations::find_file(state.file_storage.read().await.as_ref(), &caller, &file_id) {
+        Some(metadata) if !acl::is_allowed(metadata.acl.as_ref(), metadata.owner.as_deref(), &caller, AclOperation::Review) => {
+            return api_error(ErrorKind::Forbidden, "Access denied");
+        }
+        Some(metadata) => metadata.tenant.clone(),
+        None => {
+            return api_error(ErrorKind::NotFound, "File not found");
+        }
+    };
+
+    let entry = state.feedback_store.write().await.record(tenant.as_deref(), &file_id, payload);
+    info!("Recorded {:?} feedback for recognizer {} on file_id: {}", entry.kind, entry.recognizer, file_id);
+
+    (StatusCode::CREATED, Json(entry)).into_response()
+}
+
+#[utoipa::path(
+    get, path = "/feedback/summary", tag = "feedback", params(FeedbackSummaryQuery),
+    responses(
+        (status = 200, description = "Feedback on the caller's tenant's files per recognizer, with allowlist suggestions", body = FeedbackSummary),
+        (status = 401, description = "The caller presented no credentials and no admin token", body = ErrorResponse),
+    )
+)]
+async fn feedback_summary(
+    State(state): State<AppState>,
+    admin: Result<Admin, Response>,
+    credentialed: Option<Extension<Credentialed>>,
+    caller: Caller,
+    Query(query): Query<FeedbackSummaryQuery>,
+) -> impl IntoResponse {
+    // Suggestions quote flagged document text, so they are only served to callers who
+    // proved who they are, not to anyone naming a tenant in `X-Tenant-Id`
+    if credentialed.is_none() && admin.is_err() && !caller.granted(Scope::Admin) {
+        return api_error(ErrorKind::Unauthorized, "Feedback summaries need a caller authenticated by credentials, or the admin token");
+    }
+    let min_occurrences = query.min_occurrences.unwrap_or(state.feedback_allowlist_min_occurrences);
+
+    Json(state.feedback_store.read().await.summary(caller.tenant.as_deref(), min_occurrences)).into_response()
+}
+
+#[utoipa::path(
+    patch, path = "/files/{file_id}/acl", tag = "files", params(("file_id" = String, Path, description = "Id of a stored file")), request_body = AclPatch,
+    responses(
+        (status = 200, description = "The updated access-control list", body = FileAcl),
+        (status = 403, description = "Only the uploader can change the access-control list", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    )
+)]
+async fn update_acl(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(file_id): Path<String>,
+    Json(payload): Json<AclPatch>,
+) -> impl IntoResponse {
+    let mut storage = state.file_storage.write().await;
+
+    if operations::find_file(storage.as_ref(), &caller, &file_id).is_none() {
+        return api_error(ErrorKind::NotFound, "File not found");
+    }
+    let Some(metadata) = storage.get_metadata_mut(&file_id) else {
+        return api_error(ErrorKind::NotFound, "File not found");
+    };
+
+    // Only the uploader manages the ACL
+    if metadata.owner.is_none() || metadata.owner != caller.principal {
+        warn!("ACL update for file_id {} denied", file_id);
+        return api_error(ErrorKind::Forbidden, "Only the uploader can change the access-control list");
+    }
+
+    let acl = metadata.acl.get_or_insert_with(FileAcl::default);
+    acl.apply(payload);
+    let acl = acl.clone();
+    if let Err(e) = storage.persist(&file_id) {
+        error!("Failed to persist ACL for file_id {}: {}", file_id, e);
+        return api_error(ErrorKind::Internal, "Failed to store the access-control list");
+    }
+    info!("Updated ACL for file_id: {}", file_id);
+
+    Json(acl).into_response()
+}
+
+#[utoipa::path(
+    delete, path = "/files/{file_id}", tag = "files", params(("file_id" = String, Path, description = "Id of a stored file")),
+    responses(
+        (status = 204, description = "Deleted; the erasure receipt is at `/audit/receipts/{file_id}`"),
+        (status = 403, description = "Access denied", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    )
+)]
+async fn delete_file(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(file_id): Path<String>,
+) -> impl IntoResponse {
+    let mut storage = state.file_storage.write().await;
+
+    match operations::find_file(storage.as_ref(), &caller, &file_id) {
+        Some(metadata) if !acl::is_allowed(metadata.acl.as_ref(), metadata.owner.as_deref(), &caller, AclOperation::Delete) => {
+            warn!("Deletion of file_id {} denied by ACL", file_id);
+            api_error(ErrorKind::Forbidden, "Access denied")
+        }
+        Some(metadata) => {
+            let notice = DeletionNotice::new(&file_id, metadata, "deleted");
+            let record = erasure_record(&state, storage.as_ref(), &file_id, "file.delete", caller.principal.as_deref(), "deleted");
+            storage.delete_file(&file_id);
+            info!("Deleted file_id: {}", file_id);
+            state.audit_log.write().await.record(record);
+            state.propagation.spawn(notice, state.audit_log.clone(), state.alerts.clone());
+
+            StatusCode::NO_CONTENT.into_response()
+        }
+        None => {
+            api_error(ErrorKind::NotFound, "File not found")
+        }
+    }
+}
+
+#[utoipa::path(
+    post, path = "/files/{file_id}/share", tag = "shares", params(("file_id" = String, Path, description = "Id of a stored file")), request_body = ShareRequest,
+    responses(
+        (status = 201, description = "A one-time download link", body = ShareResponse),
+        (status = 400, description = "Invalid ttl_seconds", body = ErrorResponse),
+        (status = 403, description = "Access denied, or downloads are disabled for the tenant", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    )
+)]
+async fn create_share(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(file_id): Path<String>,
+    Json(payload): Json<ShareRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = check_downloads_allowed(&state, &caller, Some(&file_id), "share").await {
+        return operation_error(e);
+    }
+    match operations::find_file(state.file_storage.read().await.as_ref(), &caller, &file_id) {
+        Some(metadata) if !acl::is_allowed(metadata.acl.as_ref(), metadata.owner.as_deref(), &caller, AclOperation::Download) => {
+            warn!("Share creation for file_id {} denied by ACL", file_id);
+            state.audit_log.write().await.record(AuditRecord::new(
+                "share.create",
+                caller.principal.as_deref(),
+                Some(&file_id),
+                "denied",
+            ));
+            return api_error(ErrorKind::Forbidden, "Access denied");
+        }
+        Some(metadata) if metadata.review_hold.is_some() => return operation_error(operations::review_required()),
+        Some(_) => {}
+        None => {
+            return api_error(ErrorKind::NotFound, "File not found");
+        }
+    }
+
+    let ttl_seconds = payload.ttl_seconds.unwrap_or(DEFAULT_SHARE_TTL_SECONDS);
+    if ttl_seconds == 0 || ttl_seconds > MAX_SHARE_TTL_SECONDS {
+        return api_error(ErrorKind::BadRequest, format!("ttl_seconds must be between 1 and {}", MAX_SHARE_TTL_SECONDS));
+    }
+
+    let password = payload.password.as_deref().filter(|password| !password.is_empty());
+    let (token, expires_at) = state.share_store.write().await.create(&file_id, ttl_seconds, password);
+    state.audit_log.write().await.record(
+        AuditRecord::new("share.create", caller.principal.as_deref(), Some(&file_id), "success")
+            .with_details(serde_json::json!({
+                "expires_at": expires_at,
+                "password_protected": password.is_some(),
+            })),
+    );
+    info!("Created share link for file_id: {}", file_id);
+
+    (
+        StatusCode::CREATED,
+        Json(ShareResponse {
+            url: format!("/share/{}", token),
+            token,
+            expires_at,
+        }),
+    )
+        .into_response()
+}
+
+// Register an upload an end client is to make, with the constraints it must meet
+#[utoipa::path(
+    post, path = "/uploads/expectations", tag = "uploads", request_body = ExpectationRequest,
+    responses(
+        (status = 201, description = "Token for the expected upload to reference", body = ExpectationCreated),
+        (status = 400, description = "Invalid constraints", body = ErrorResponse),
+        (status = 403, description = "The credential may not upload", body = ErrorResponse),
+    )
+)]
+async fn create_expectation(State(state): State<AppState>, caller: Caller, Json(payload): Json<ExpectationRequest>) -> impl IntoResponse {
+    if !caller.allows(Scope::Upload) {
+        return api_error(ErrorKind::Forbidden, "This credential may not upload files");
+    }
+    let ttl_seconds = payload.ttl_seconds.unwrap_or(expectations::DEFAULT_TTL_SECONDS);
+    if ttl_seconds == 0 || ttl_seconds > expectations::MAX_TTL_SECONDS {
+        return api_error(ErrorKind::BadRequest, format!("ttl_seconds must be between 1 and {}", expectations::MAX_TTL_SECONDS));
+    }
+    if payload.max_bytes == Some(0) {
+        return api_error(ErrorKind::BadRequest, "max_bytes must be positive");
+    }
+
+    let details = serde_json::json!({
+        "max_bytes": payload.max_bytes,
+        "redaction_strategy": payload.redaction_strategy,
+        "entities": payload.entities,
+        "uploader": payload.uploader,
+    });
+    let (token, expires_at) = state.expectations.write().await.create(&caller, payload, ttl_seconds);
+    state.audit_log.write().await.record(
+        AuditRecord::new("expectation.create", caller.principal.as_deref(), None, "success").with_details(details),
+    );
+
+    (StatusCode::CREATED, Json(ExpectationCreated { token, expires_at })).into_response()
+}
+
+// Whether the expected upload happened, and its `file_id` once it has
+#[utoipa::path(
+    get, path = "/uploads/expectations/{token}", tag = "uploads",
+    params(("token" = String, Path, description = "Token of an upload expectation")),
+    responses(
+        (status = 200, description = "Whether the expected upload happened", body = ExpectationView),
+        (status = 404, description = "Upload expectation not found", body = ErrorResponse),
+    )
+)]
+async fn get_expectation(State(state): State<AppState>, caller: Caller, Path(token): Path<String>) -> impl IntoResponse {
+    match state.expectations.read().await.get(&token, &caller) {
+        Some(expectation) => Json(expectation).into_response(),
+        None => api_error(ErrorKind::NotFound, "Upload expectation not found"),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/share/{token}", tag = "shares",
+    params(
+        ("token" = String, Path, description = "Token of a share link"),
+        ("X-Share-Password" = Option<String>, Header, description = "Password of a protected link"),
+    ),
+    responses(
+        (status = 200, description = "The redacted file", body = String, content_type = "text/plain"),
+        (status = 401, description = "A password is required, or it is wrong", body = ErrorResponse),
+        (status = 404, description = "Link or file not found", body = ErrorResponse),
+        (status = 410, description = "The link expired", body = ErrorResponse),
+    )
+)]
+async fn redeem_share(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let password = headers.get("X-Share-Password").and_then(|value| value.to_str().ok());
+
+    let file_id = match state.share_store.write().await.redeem(&token, password) {
+        Ok(file_id) => file_id,
+        Err(e) => {
+            let (kind, reason) = match e {
+                ShareError::NotFound => (ErrorKind::NotFound, "not_found"),
+                ShareError::Expired => (ErrorKind::Gone, "expired"),
+                ShareError::PasswordRequired => (ErrorKind::Unauthorized, "password_required"),
+                ShareError::WrongPassword => (ErrorKind::Unauthorized, "wrong_password"),
+            };
+            state.audit_log.write().await.record(
+                AuditRecord::new("share.redeem", None, None, "denied")
+                    .with_details(serde_json::json!({ "reason": reason })),
+            );
+            return api_error(kind, "Share link is invalid, expired or already used");
+        }
+    };
+
+    let storage = state.file_storage.read().await;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    // Links do not outlive the file's TTL
+    let Some(metadata) = storage.get_metadata(&file_id).filter(|metadata| !metadata.is_expired(now)) else {
+        state.audit_log.write().await.record(
+            AuditRecord::new("share.redeem", None, Some(&file_id), "denied")
+                .with_details(serde_json::json!({ "reason": "file_deleted" })),
+        );
+        return api_error(ErrorKind::NotFound, "File not found");
+    };
+    // Links made before the tenant became delivery-only stop working
+    if !state.policy.downloads_allowed(metadata.tenant.as_deref()) {
+        state.audit_log.write().await.record(
+            AuditRecord::new("share.redeem", None, Some(&file_id), "denied")
+                .with_details(serde_json::json!({ "reason": "downloads_disabled" })),
+        );
+        return operation_error(operations::downloads_disabled());
+    }
+
+    state.audit_log.write().await.record(AuditRecord::new("share.redeem", None, Some(&file_id), "success"));
+    info!("Share link redeemed for file_id: {}", file_id);
+
+    let mut headers = HeaderMap::new();
+    headers.insert("Content-Disposition", content_disposition(&metadata.file_name));
+    headers.insert("Content-Type", "text/plain".parse().unwrap());
+    headers.insert(CONTENT_SHA256, manifest::content_digest(metadata.content.as_bytes()).parse().unwrap());
+
+    (StatusCode::OK, headers, metadata.content.clone()).into_response()
+}
+
+#[utoipa::path(
+    get, path = "/audit/receipts/{file_id}", tag = "audit", params(("file_id" = String, Path, description = "Id of a stored file")),
+    responses(
+        (status = 200, description = "The file's signed erasure receipt", body = ErasureReceiptResponse),
+        (status = 404, description = "No erasure receipt for this file, or the file was not visible to the caller", body = ErrorResponse),
+    )
+)]
+async fn get_erasure_receipt(
+    State(state): State<AppState>,
+    caller: Caller,
+    Path(file_id): Path<String>,
+) -> impl IntoResponse {
+    let record = state.audit_log.read().await.receipt(&file_id).cloned();
+    let Some(details) = record.and_then(|record| record.details) else {
+        return api_error(ErrorKind::NotFound, "No erasure receipt for this file");
+    };
+    // Receipts of files another tenant could not see are not found, as the files were not
+    let visible = serde_json::from_value::<ReceiptAccess>(details.clone())
+        .is_ok_and(|access| acl::is_visible(access.tenant.as_deref(), access.acl.as_ref(), &caller));
+    if !visible {
+        return api_error(ErrorKind::NotFound, "No erasure receipt for this file");
+    }
+    let receipt = match serde_json::from_value::<ErasureReceipt>(details) {
+        Ok(receipt) => receipt,
+        Err(e) => return api_error(ErrorKind::Internal, format!("Failed to read the erasure receipt: {}", e)),
+    };
+    // Unsigned: where propagation to delivery targets has got since the receipt was issued
+    let propagation = state.propagation.status(&file_id);
+    Json(ErasureReceiptResponse {
+        receipt,
+        propagation_complete: propagation.as_deref().map(propagation::is_complete),
+        propagation,
+    })
+    .into_response()
+}
+
+// Rehashes the whole trail, so only for the admin token or keys with the `admin` scope
+#[utoipa::path(
+    get, path = "/audit/verify", tag = "audit",
+    security(("admin_token" = []), ("api_key" = []), ("signed_request" = []), ("principal_header" = [])),
+    responses(
+        (status = 200, description = "Whether the audit chain is intact", body = ChainVerification),
+        (status = 403, description = "Needs the admin token or the admin scope", body = ErrorResponse),
+        (status = 500, description = "The audit trail could not be read", body = ErrorResponse),
+    )
+)]
+async fn verify_audit_chain(
+    State(state): State<AppState>,
+    admin: Result<Admin, Response>,
+    caller: Caller,
+) -> Response {
+    if admin.is_err() && !caller.granted(Scope::Admin) {
+        return operation_error(
+            OperationError::new(ErrorKind::Forbidden, "Verifying the audit trail needs the admin token or the admin scope")
+                .with_code("scope_denied"),
+        );
+    }
+    let audit_log = state.audit_log.read().await;
+    match audit_log.verify() {
+        Ok(verification) => {
+            // Alert when the chain breaks, not again on every check while it stays broken
+            if audit_log.newly_broken(&verification) {
+                let sequence = verification.first_invalid_sequence.unwrap_or_default();
+                let alert = Alert::new(Severity::Critical, "audit.chain_invalid", format!("The audit chain is broken at sequence {}", sequence))
+                    .with_details(serde_json::json!({ "first_invalid_sequence": sequence }));
+                state.alerts.raise(alert);
+            }
+            Json(verification).into_response()
+        }
+        Err(e) => {
+            api_error(ErrorKind::Internal, format!("Failed to verify audit chain: {}", e))
+        }
+    }
+}
+
+// Records of the audit trail, for the admin token or keys with the `admin` scope
+#[utoipa::path(
+    get, path = "/audit", tag = "audit", params(AuditQuery),
+    security(("admin_token" = []), ("api_key" = []), ("signed_request" = []), ("principal_header" = [])),
+    responses(
+        (status = 200, description = "A page of audit records", body = AuditPage),
+        (status = 403, description = "Needs the admin token or the admin scope", body = ErrorResponse),
+    )
+)]
+async fn list_audit_records(
+    State(state): State<AppState>,
+    admin: Result<Admin, Response>,
+    caller: Caller,
+    Query(query): Query<AuditQuery>,
+) -> Response {
+    if admin.is_err() && !caller.granted(Scope::Admin) {
+        return operation_error(
+            OperationError::new(ErrorKind::Forbidden, "Reading the audit trail needs the admin token or the admin scope")
+                .with_code("scope_denied"),
+        );
+    }
+    match state.audit_log.read().await.query(&query) {
+        Ok(page) => Json(page).into_response(),
+        Err(e) => api_error(ErrorKind::Internal, format!("Failed to read audit trail: {}", e)),
+    }
+}
+
+// Usage totals kept by the storage backend, for all tenants or just `tenant`
+#[utoipa::path(
+    get, path = "/admin/stats", tag = "admin", params(StatsQuery), security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Usage totals, of all tenants or of `tenant`", body = StatsResponse),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+    )
+)]
+async fn get_stats(State(state): State<AppState>, _admin: Admin, Query(query): Query<StatsQuery>) -> Response {
+    let storage = state.file_storage.read().await;
+    let usage = storage.usage();
+    match query.tenant {
+        Some(tenant) => {
+            let totals = usage.tenants.get(&tenant).cloned().unwrap_or_default();
+            Json(StatsResponse::Tenant(TenantStats { tenant, totals })).into_response()
+        }
+        None => Json(StatsResponse::All(usage.clone())).into_response(),
+    }
+}
+
+// Upload callbacks not delivered yet, and those given up on
+#[utoipa::path(
+    get, path = "/admin/webhooks/deliveries", tag = "admin", params(DeliveryQuery), security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Pending and failed callback deliveries, oldest first", body = [Delivery]),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+    )
+)]
+async fn list_deliveries(State(state): State<AppState>, _admin: Admin, Query(query): Query<DeliveryQuery>) -> Json<Vec<Delivery>> {
+    Json(state.notifier.deliveries(query.status))
+}
+
+// Send a failed callback again, under the same delivery id
+#[utoipa::path(
+    post, path = "/admin/webhooks/deliveries/{delivery_id}/retry", tag = "admin",
+    params(("delivery_id" = String, Path, description = "Id of a callback delivery")), security(("admin_token" = [])),
+    responses(
+        (status = 202, description = "The delivery is being attempted again", body = Delivery),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+        (status = 404, description = "Unknown or already delivered", body = ErrorResponse),
+        (status = 409, description = "The delivery is still pending", body = ErrorResponse),
+    )
+)]
+async fn resend_delivery(State(state): State<AppState>, _admin: Admin, Path(delivery_id): Path<String>) -> Response {
+    match state.notifier.resend(&delivery_id) {
+        Ok(delivery) => {
+            state.audit_log.write().await.record(
+                AuditRecord::new("webhooks.resend", None, Some(&delivery.file_id), "success")
+                    .with_details(serde_json::json!({ "delivery_id": delivery.delivery_id })),
+            );
+            (StatusCode::ACCEPTED, Json(delivery)).into_response()
+        }
+        Err(e) => operation_error(e),
+    }
+}
+
+// Decrypted keys and plaintexts still in memory, to confirm none outlive their upload
+// beyond the session keys of stored files
+#[utoipa::path(
+    get, path = "/admin/secrets", tag = "admin", security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Secrets held in memory; while no upload is in progress, only the session keys of stored files", body = Retained),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+    )
+)]
+async fn get_retained_secrets(_admin: Admin) -> Json<Retained> {
+    Json(secret::retained())
+}
+
+// Canary upload of synthetic PII through the live pipeline; `500` when any entity type
+// is not redacted
+#[utoipa::path(
+    get, path = "/admin/selftest/redaction", tag = "admin", params(SelftestQuery), security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Every entity type was redacted", body = SelftestReport),
+        (status = 500, description = "Some entity type was not redacted", body = SelftestReport),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+    )
+)]
+async fn redaction_selftest(State(state): State<AppState>, _admin: Admin, Query(query): Query<SelftestQuery>) -> Response {
+    let Some(crypto_service) = state.key_provisioner.get() else {
+        return key_not_provisioned();
+    };
+    let requested: Option<Vec<String>> = query.entities.map(|entities| {
+        entities.split(',').map(|entity| entity.trim().to_string()).filter(|entity| !entity.is_empty()).collect()
+    });
+    let entity_types = selftest::entity_types(&state.redactor_service.entities(), requested.as_deref());
+    let caller = Caller { principal: Some("selftest".to_string()), tenant: query.tenant, scopes: None };
+
+    match selftest::run(&upload_context(&state, crypto_service), &caller, &entity_types).await {
+        Ok(report) => {
+            let outcome = if report.passed { "success" } else { "failure" };
+            let failed: Vec<&str> = report.entities.iter().filter(|entity| !entity.passed).map(|entity| entity.entity_type.as_str()).collect();
+            state.audit_log.write().await.record(
+                AuditRecord::new("selftest.redaction", None, None, outcome)
+                    .with_details(serde_json::json!({ "tenant": caller.tenant, "failed": failed })),
+            );
+            let status = if report.passed { StatusCode::OK } else { StatusCode::INTERNAL_SERVER_ERROR };
+            (status, Json(report)).into_response()
+        }
+        Err(e) => operation_error(e),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/admin/maintenance", tag = "admin", security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Whether uploads are paused", body = MaintenanceStatus),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+    )
+)]
+async fn get_maintenance(State(state): State<AppState>, _admin: Admin) -> impl IntoResponse {
+    Json(state.maintenance.status())
+}
+
+#[utoipa::path(
+    put, path = "/admin/maintenance", tag = "admin", request_body = MaintenanceRequest, security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Maintenance mode set", body = MaintenanceStatus),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+    )
+)]
+async fn set_maintenance(
+    State(state): State<AppState>,
+    _admin: Admin,
+    Json(payload): Json<MaintenanceRequest>,
+) -> impl IntoResponse {
+    let status = state.maintenance.set(payload);
+    let outcome = if status.enabled { "enabled" } else { "disabled" };
+    info!("Maintenance mode {}", outcome);
+    state.audit_log.write().await.record(
+        AuditRecord::new("maintenance.set", None, None, outcome)
+            .with_details(serde_json::json!({ "message": status.message, "eta": status.eta })),
+    );
+    Json(status)
+}
+
+#[utoipa::path(
+    get, path = "/admin/config", tag = "admin", security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "The settings that can change while the service runs", body = RuntimeSettings),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+    )
+)]
+async fn get_runtime_config(State(state): State<AppState>, _admin: Admin) -> impl IntoResponse {
+    Json(state.settings.load_full().as_ref().clone())
+}
+
+// Replace the runtime settings whole. Requests already running finish under the ones
+// they started with.
+#[utoipa::path(
+    put, path = "/admin/config", tag = "admin", request_body = RuntimeSettings, security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "The settings now in force", body = RuntimeSettings),
+        (status = 400, description = "Invalid settings", body = ErrorResponse),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+    )
+)]
+async fn set_runtime_config(
+    State(state): State<AppState>,
+    _admin: Admin,
+    Json(payload): Json<RuntimeSettings>,
+) -> Response {
+    if let Err(e) = payload.validate() {
+        return operation_error(OperationError::new(ErrorKind::BadRequest, e.to_string()).with_code("invalid_settings"));
+    }
+    // Held across the update, so concurrent ones are applied and recorded in one order
+    let mut audit_log = state.audit_log.write().await;
+    let previous = state.settings.swap(Arc::new(payload.clone()));
+    state.rate_limiter.set_rate(payload.rate_limit_per_minute, payload.rate_limit_burst);
+    let changed = previous.changes(&payload);
+    info!("Runtime settings updated: {}", if changed.is_empty() { "no changes".to_string() } else { changed.join(", ") });
+    audit_log.record(
+        AuditRecord::new("config.update", None, None, "success")
+            .with_details(serde_json::json!({ "changed": changed, "settings": &payload })),
+    );
+    Json(payload).into_response()
+}
+
+#[utoipa::path(
+    get, path = "/admin/escrow", tag = "admin", security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "The key escrow bundle", body = EscrowBundle),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+        (status = 404, description = "Key escrow is not configured", body = ErrorResponse),
+    )
+)]
+async fn export_escrow(State(state): State<AppState>, _admin: Admin) -> impl IntoResponse {
+    match state.key_provisioner.get().and_then(CryptoService::escrow_bundle) {
+        Some(bundle) => {
+            info!("Exported key escrow bundle");
+            state.audit_log.write().await.record(AuditRecord::new("escrow.export", None, None, "success"));
+            Json(bundle.clone()).into_response()
+        }
+        None => {
+            api_error(ErrorKind::NotFound, "Key escrow is not configured")
+        }
+    }
+}
+
+#[utoipa::path(
+    get, path = "/admin/keys", tag = "admin", security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Service keys still accepted", body = KeyList),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+    )
+)]
+async fn list_keys(State(state): State<AppState>, _admin: Admin) -> Response {
+    let Some(crypto_service) = state.key_provisioner.get() else {
+        return key_not_provisioned();
+    };
+    Json(KeyList { current: crypto_service.key_id(), keys: crypto_service.key_ids() }).into_response()
+}
+
+// Make a fresh key current and rewrap stored files to it; earlier keys stay accepted
+// until retired
+#[utoipa::path(
+    post, path = "/admin/keys/rotate", tag = "admin", security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "A fresh key is current and stored files are rewrapped to it", body = RotatedKey),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+        (status = 500, description = "Rotation failed", body = ErrorResponse),
+    )
+)]
+async fn rotate_key(State(state): State<AppState>, _admin: Admin) -> Response {
+    let Some(crypto_service) = state.key_provisioner.get() else {
+        return key_not_provisioned();
+    };
+    let provisioner = state.key_provisioner.clone();
+    let rotated = tokio::task::spawn_blocking(move || provisioner.get().map(CryptoService::rotate))
+        .await
+        .map_err(|e| anyhow::anyhow!("Key rotation task failed: {}", e))
+        .and_then(|rotated| rotated.unwrap_or_else(|| Err(anyhow::anyhow!("Service key is not provisioned yet"))));
+    let rotated = match rotated {
+        Ok(kid) => state.file_storage.write().await.rewrap(crypto_service).map(|rewrapped| RotatedKey { kid, rewrapped }),
+        Err(e) => Err(e),
+    };
+
+    let (outcome, details) = match &rotated {
+        Ok(rotated) => ("success", serde_json::json!(rotated)),
+        Err(e) => ("failure", serde_json::json!({ "reason": e.to_string() })),
+    };
+    state.audit_log.write().await.record(AuditRecord::new("keys.rotate", None, None, outcome).with_details(details));
+    match rotated {
+        Ok(rotated) => Json(rotated).into_response(),
+        Err(e) => {
+            error!("Key rotation failed: {}", e);
+            api_error(ErrorKind::Internal, format!("Key rotation failed: {}", e))
+        }
+    }
+}
+
+// Stop accepting a key that is no longer current; uploads still wrapped to it then fail
+#[utoipa::path(
+    delete, path = "/admin/keys/{kid}", tag = "admin", params(("kid" = String, Path, description = "Id of a service key")),
+    security(("admin_token" = [])),
+    responses(
+        (status = 204, description = "The key is no longer accepted"),
+        (status = 403, description = "Invalid admin token", body = ErrorResponse),
+        (status = 404, description = "Unknown key id", body = ErrorResponse),
+        (status = 409, description = "The key is current", body = ErrorResponse),
+    )
+)]
+async fn retire_key(State(state): State<AppState>, _admin: Admin, Path(kid): Path<String>) -> Response {
+    let Some(crypto_service) = state.key_provisioner.get() else {
+        return key_not_provisioned();
+    };
+    if !crypto_service.has_key(&kid) {
+        return api_error(ErrorKind::NotFound, format!("Unknown key id: {}", kid));
+    }
+    if crypto_service.key_id() == kid {
+        let error = "The current key cannot be retired; rotate first".to_string();
+        return api_error(ErrorKind::Conflict, error);
+    }
+
+    let retired = crypto_service.retire(&kid);
+    let outcome = if retired.is_ok() { "success" } else { "failure" };
+    state.audit_log.write().await.record(
+        AuditRecord::new("keys.retire", None, None, outcome).with_details(serde_json::json!({ "kid": kid })),
+    );
+    match retired {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            error!("Failed to retire key {}: {}", kid, e);
+            api_error(ErrorKind::Internal, format!("Failed to retire the key: {}", e))
         }
     }
 }
@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+const DEFAULT_MESSAGE: &str = "Uploads are paused for maintenance";
+
+#[derive(Clone, Default, Serialize, ToSchema)]
+pub struct MaintenanceStatus {
+    pub enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    // Expected end, unix seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eta: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<u64>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct MaintenanceRequest {
+    pub enabled: bool,
+    pub message: Option<String>,
+    pub eta: Option<u64>,
+}
+
+// Admin-toggled switch that stops new uploads, e.g. during a storage migration.
+// Downloads, deletes and health checks keep working.
+#[derive(Default)]
+pub struct MaintenanceMode {
+    status: RwLock<MaintenanceStatus>,
+}
+
+impl MaintenanceMode {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn status(&self) -> MaintenanceStatus {
+        self.status.read().unwrap().clone()
+    }
+
+    // The status uploads are rejected with, while maintenance is on
+    pub fn active(&self) -> Option<MaintenanceStatus> {
+        Some(self.status()).filter(|status| status.enabled)
+    }
+
+    pub fn set(&self, request: MaintenanceRequest) -> MaintenanceStatus {
+        let mut status = self.status.write().unwrap();
+        *status = if request.enabled {
+            MaintenanceStatus {
+                enabled: true,
+                message: Some(request.message.unwrap_or_else(|| DEFAULT_MESSAGE.to_string())),
+                eta: request.eta,
+                // Kept when only the message or ETA is updated
+                since: status.since.or_else(|| Some(now())),
+            }
+        } else {
+            MaintenanceStatus::default()
+        };
+        status.clone()
+    }
+
+    // Seconds until the ETA, for `Retry-After`
+    pub fn retry_after(status: &MaintenanceStatus) -> Option<u64> {
+        status.eta.map(|eta| eta.saturating_sub(now()).max(1))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toggle_keeps_start_time_until_cleared() {
+        let mode = MaintenanceMode::new();
+        assert!(mode.active().is_none());
+
+        let status = mode.set(MaintenanceRequest { enabled: true, message: None, eta: None });
+        assert_eq!(status.message.as_deref(), Some(DEFAULT_MESSAGE));
+        let since = status.since;
+
+        let eta = now() + 600;
+        let status = mode.set(MaintenanceRequest { enabled: true, message: Some("Migrating storage".to_string()), eta: Some(eta) });
+        assert_eq!(status.since, since);
+        assert!(MaintenanceMode::retry_after(&status).is_some_and(|seconds| seconds > 500));
+
+        mode.set(MaintenanceRequest { enabled: false, message: None, eta: None });
+        assert!(mode.active().is_none());
+        assert!(mode.status().since.is_none());
+    }
+}
@@ -0,0 +1,403 @@
+use anyhow::{anyhow, Result};
+use prometheus::{
+    exponential_buckets, Encoder, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::jobs::TenantBacklog;
+use crate::quality::QualityScore;
+
+use sentient_redactor_core::{
+    operations::{OperationError, UploadProfile},
+    report::RedactionReport,
+    secret::Retained,
+    storage::StorageLimits,
+};
+
+// Prometheus metrics served on `GET /metrics`. Histograms show how documents are
+// distributed, so shifts in size or entity mix are visible without querying reports.
+pub struct Metrics {
+    registry: Registry,
+    uploads: IntCounterVec,
+    upload_failures: IntCounterVec,
+    deprecated_mode_uploads: IntCounterVec,
+    downloads: IntCounterVec,
+    requests: IntCounterVec,
+    panics: IntCounterVec,
+    entities_per_document: HistogramVec,
+    document_size_bytes: Histogram,
+    backend_duration_seconds: HistogramVec,
+    stored_files: IntGauge,
+    stored_bytes: IntGauge,
+    storage_limit: IntGaugeVec,
+    storage_evictions: IntCounter,
+    gc_reclaimed: IntCounterVec,
+    gc_reclaimed_bytes: IntCounterVec,
+    feature_flag_rollout: IntGaugeVec,
+    job_queue_depth: IntGaugeVec,
+    job_queue_oldest_wait_seconds: GaugeVec,
+    job_queue_weight: IntGaugeVec,
+    retained_secrets: IntGauge,
+    retained_secret_bytes: IntGauge,
+    quality_score: GaugeVec,
+    quality_score_delta: GaugeVec,
+    quality_sampled_documents: IntGauge,
+    replication_pending_files: IntGauge,
+    replication_lag_seconds: prometheus::Gauge,
+    cold_tier_moved_files: IntCounter,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new_custom(Some("redactor".to_string()), None)
+            .map_err(|e| anyhow!("Failed to create metrics registry: {}", e))?;
+
+        let uploads = IntCounterVec::new(Opts::new("uploads_total", "Uploads by result"), &["result"])
+            .map_err(|e| anyhow!("Failed to create upload counter: {}", e))?;
+        let upload_failures = IntCounterVec::new(Opts::new("upload_failures_total", "Failed uploads by reason"), &["reason"])
+            .map_err(|e| anyhow!("Failed to create upload failure counter: {}", e))?;
+        // Uploads in a deprecated protocol mode: `accepted` before its sunset, `rejected` after
+        let deprecated_mode_uploads = IntCounterVec::new(
+            Opts::new("deprecated_mode_uploads_total", "Uploads using a deprecated protocol mode, by mode and result"),
+            &["mode", "result"],
+        )
+        .map_err(|e| anyhow!("Failed to create deprecated mode counter: {}", e))?;
+        let downloads = IntCounterVec::new(
+            Opts::new("downloads_total", "Downloads of redacted content by kind and result"),
+            &["kind", "result"],
+        )
+        .map_err(|e| anyhow!("Failed to create download counter: {}", e))?;
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Requests by route and status code"),
+            &["route", "status"],
+        )
+        .map_err(|e| anyhow!("Failed to create request counter: {}", e))?;
+        let panics = IntCounterVec::new(Opts::new("panics_total", "Requests whose handler panicked, by route"), &["route"])
+            .map_err(|e| anyhow!("Failed to create panic counter: {}", e))?;
+        // Observed per document for each entity type it contains
+        let entities_per_document = HistogramVec::new(
+            HistogramOpts::new("entities_per_document", "Entities detected per document, by entity type")
+                .buckets(vec![1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0]),
+            &["entity_type"],
+        )
+        .map_err(|e| anyhow!("Failed to create entity histogram: {}", e))?;
+        let document_size_bytes = Histogram::with_opts(
+            HistogramOpts::new("document_size_bytes", "Plaintext size of uploaded documents")
+                .buckets(exponential_buckets(256.0, 4.0, 10).map_err(|e| anyhow!("Invalid buckets: {}", e))?),
+        )
+        .map_err(|e| anyhow!("Failed to create size histogram: {}", e))?;
+        // The redaction stage of each upload, which is dominated by the Presidio call
+        let backend_duration_seconds = HistogramVec::new(
+            HistogramOpts::new("backend_duration_seconds", "Time uploads spent in the redaction backend, by backend")
+                .buckets(exponential_buckets(0.005, 2.0, 14).map_err(|e| anyhow!("Invalid buckets: {}", e))?),
+            &["backend"],
+        )
+        .map_err(|e| anyhow!("Failed to create backend latency histogram: {}", e))?;
+        let stored_files = IntGauge::new("stored_files", "Redacted files currently stored")
+            .map_err(|e| anyhow!("Failed to create stored files gauge: {}", e))?;
+        let stored_bytes = IntGauge::new("stored_bytes", "Size of the redacted files currently stored")
+            .map_err(|e| anyhow!("Failed to create stored bytes gauge: {}", e))?;
+        // Only the limits that are configured: `bytes`, `files` and `file_bytes`
+        let storage_limit = IntGaugeVec::new(Opts::new("storage_limit", "Configured storage limits, by limit"), &["limit"])
+            .map_err(|e| anyhow!("Failed to create storage limit gauge: {}", e))?;
+        let storage_evictions = IntCounter::new("storage_evictions_total", "Files evicted to keep storage within its limits")
+            .map_err(|e| anyhow!("Failed to create eviction counter: {}", e))?;
+        let gc_reclaimed = IntCounterVec::new(
+            Opts::new("gc_reclaimed_total", "Abandoned sessions and orphaned storage files removed, by kind"),
+            &["kind"],
+        )
+        .map_err(|e| anyhow!("Failed to create reclaimed counter: {}", e))?;
+        let gc_reclaimed_bytes = IntCounterVec::new(
+            Opts::new("gc_reclaimed_bytes_total", "Bytes freed by removing abandoned sessions and orphaned storage files, by kind"),
+            &["kind"],
+        )
+        .map_err(|e| anyhow!("Failed to create reclaimed bytes counter: {}", e))?;
+
+        let feature_flag_rollout = IntGaugeVec::new(
+            Opts::new("feature_flag_rollout_percent", "Share of callers each feature flag is on for, ignoring tenant allowlists"),
+            &["flag"],
+        )
+        .map_err(|e| anyhow!("Failed to create feature flag gauge: {}", e))?;
+
+        // Tenants without a tenant id are labelled `none`
+        let job_queue_depth = IntGaugeVec::new(Opts::new("job_queue_depth", "Upload jobs waiting for a worker, by tenant"), &["tenant"])
+            .map_err(|e| anyhow!("Failed to create job queue gauge: {}", e))?;
+        let job_queue_oldest_wait_seconds = GaugeVec::new(
+            Opts::new("job_queue_oldest_wait_seconds", "How long each tenant's oldest queued upload job has waited"),
+            &["tenant"],
+        )
+        .map_err(|e| anyhow!("Failed to create job wait gauge: {}", e))?;
+        let job_queue_weight = IntGaugeVec::new(Opts::new("job_queue_weight", "Scheduling weight of each tenant with queued jobs"), &["tenant"])
+            .map_err(|e| anyhow!("Failed to create job weight gauge: {}", e))?;
+        // Decrypted keys and plaintexts alive at scrape time; zero while no upload is in progress
+        let retained_secrets = IntGauge::new("retained_secrets", "Decrypted session keys and plaintexts held in memory, including stored files' session keys")
+            .map_err(|e| anyhow!("Failed to create retained secrets gauge: {}", e))?;
+        let retained_secret_bytes = IntGauge::new("retained_secret_bytes", "Size of the decrypted session keys and plaintexts held in memory")
+            .map_err(|e| anyhow!("Failed to create retained secret bytes gauge: {}", e))?;
+        // Of the live backend against the quality reference backend, from the last sampling run
+        let quality_score = GaugeVec::new(
+            Opts::new("quality_score", "Precision and recall of the live backend against the reference backend"),
+            &["measure"],
+        )
+        .map_err(|e| anyhow!("Failed to create quality gauge: {}", e))?;
+        let quality_score_delta = GaugeVec::new(
+            Opts::new("quality_score_delta", "Change in precision and recall since the previous sampling run"),
+            &["measure"],
+        )
+        .map_err(|e| anyhow!("Failed to create quality delta gauge: {}", e))?;
+        // Files stored, changed or removed that the replica has not caught up with
+        let replication_pending_files = IntGauge::new("replication_pending_files", "Stored files not copied to the replica yet")
+            .map_err(|e| anyhow!("Failed to create replication gauge: {}", e))?;
+        let replication_lag_seconds = prometheus::Gauge::new("replication_lag_seconds", "How long the oldest change not copied to the replica has waited")
+            .map_err(|e| anyhow!("Failed to create replication lag gauge: {}", e))?;
+        let cold_tier_moved_files = IntCounter::new("cold_tier_moved_files_total", "Files moved to cold storage")
+            .map_err(|e| anyhow!("Failed to create cold tier counter: {}", e))?;
+        let quality_sampled_documents = IntGauge::new("quality_sampled_documents", "Documents scored in the last sampling run")
+            .map_err(|e| anyhow!("Failed to create quality sample gauge: {}", e))?;
+
+        registry.register(Box::new(uploads.clone()))
+            .and_then(|_| registry.register(Box::new(upload_failures.clone())))
+            .and_then(|_| registry.register(Box::new(deprecated_mode_uploads.clone())))
+            .and_then(|_| registry.register(Box::new(downloads.clone())))
+            .and_then(|_| registry.register(Box::new(requests.clone())))
+            .and_then(|_| registry.register(Box::new(panics.clone())))
+            .and_then(|_| registry.register(Box::new(entities_per_document.clone())))
+            .and_then(|_| registry.register(Box::new(document_size_bytes.clone())))
+            .and_then(|_| registry.register(Box::new(backend_duration_seconds.clone())))
+            .and_then(|_| registry.register(Box::new(stored_files.clone())))
+            .and_then(|_| registry.register(Box::new(stored_bytes.clone())))
+            .and_then(|_| registry.register(Box::new(storage_limit.clone())))
+            .and_then(|_| registry.register(Box::new(storage_evictions.clone())))
+            .and_then(|_| registry.register(Box::new(gc_reclaimed.clone())))
+            .and_then(|_| registry.register(Box::new(gc_reclaimed_bytes.clone())))
+            .and_then(|_| registry.register(Box::new(feature_flag_rollout.clone())))
+            .and_then(|_| registry.register(Box::new(job_queue_depth.clone())))
+            .and_then(|_| registry.register(Box::new(job_queue_oldest_wait_seconds.clone())))
+            .and_then(|_| registry.register(Box::new(job_queue_weight.clone())))
+            .and_then(|_| registry.register(Box::new(retained_secrets.clone())))
+            .and_then(|_| registry.register(Box::new(retained_secret_bytes.clone())))
+            .and_then(|_| registry.register(Box::new(quality_score.clone())))
+            .and_then(|_| registry.register(Box::new(quality_score_delta.clone())))
+            .and_then(|_| registry.register(Box::new(quality_sampled_documents.clone())))
+            .and_then(|_| registry.register(Box::new(replication_pending_files.clone())))
+            .and_then(|_| registry.register(Box::new(replication_lag_seconds.clone())))
+            .and_then(|_| registry.register(Box::new(cold_tier_moved_files.clone())))
+            .map_err(|e| anyhow!("Failed to register metrics: {}", e))?;
+
+        Ok(Self {
+            registry,
+            uploads,
+            upload_failures,
+            deprecated_mode_uploads,
+            downloads,
+            requests,
+            panics,
+            entities_per_document,
+            document_size_bytes,
+            backend_duration_seconds,
+            stored_files,
+            stored_bytes,
+            storage_limit,
+            storage_evictions,
+            gc_reclaimed,
+            gc_reclaimed_bytes,
+            feature_flag_rollout,
+            job_queue_depth,
+            job_queue_oldest_wait_seconds,
+            job_queue_weight,
+            retained_secrets,
+            retained_secret_bytes,
+            quality_score,
+            quality_score_delta,
+            quality_sampled_documents,
+            replication_pending_files,
+            replication_lag_seconds,
+            cold_tier_moved_files,
+        })
+    }
+
+    pub fn record_upload(&self, profile: &UploadProfile, report: Option<&RedactionReport>) {
+        self.uploads.with_label_values(&["success"]).inc();
+        self.document_size_bytes.observe(profile.plaintext_bytes as f64);
+        if let Some(stage) = profile.stages.iter().find(|stage| stage.stage == "redaction") {
+            self.backend_duration_seconds
+                .with_label_values(&[&profile.backend])
+                .observe(stage.ms / 1000.0);
+        }
+        for (entity_type, count) in report.map(|report| &report.entities).into_iter().flatten() {
+            self.entities_per_document
+                .with_label_values(&[entity_type])
+                .observe(*count as f64);
+        }
+    }
+
+    pub fn record_deduplicated_upload(&self) {
+        self.uploads.with_label_values(&["deduplicated"]).inc();
+    }
+
+    // Retries answered with the response to an earlier request under the same idempotency key
+    pub fn record_replayed_upload(&self) {
+        self.uploads.with_label_values(&["replayed"]).inc();
+    }
+
+    pub fn record_upload_failure(&self, error: &OperationError) {
+        self.uploads.with_label_values(&["failure"]).inc();
+        let reason = match error.code {
+            Some("session_key_failed" | "decryption_failed" | "decrypt_timeout") => "decryption",
+            Some("redaction_failed" | "backend_unavailable" | "backend_timeout" | "anonymize_timeout") => "redaction",
+            Some("ciphertext_checksum_mismatch" | "plaintext_checksum_mismatch") => "checksum",
+            Some("upgrade_required") => "deprecated",
+            Some("crypto_saturated") => "saturated",
+            Some("invalid_payload") => "validation",
+            Some(code) if code.starts_with("expectation_") => "expectation",
+            _ => "other",
+        };
+        self.upload_failures.with_label_values(&[reason]).inc();
+    }
+
+    pub fn record_deprecated_mode(&self, mode: &str, accepted: bool) {
+        let result = if accepted { "accepted" } else { "rejected" };
+        self.deprecated_mode_uploads.with_label_values(&[mode, result]).inc();
+    }
+
+    // `kind` is how the content left: `file`, `share`, `bulk` or `unredact`
+    pub fn record_download(&self, kind: &str, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        self.downloads.with_label_values(&[kind, result]).inc();
+    }
+
+    // `route` is the matched route pattern, so file ids do not become labels
+    pub fn record_request(&self, route: &str, status: u16) {
+        self.requests.with_label_values(&[route, &status.to_string()]).inc();
+    }
+
+    pub fn record_panic(&self, route: &str) {
+        self.panics.with_label_values(&[route]).inc();
+    }
+
+    pub fn record_storage(&self, files: usize, bytes: usize, limits: &StorageLimits) {
+        self.stored_files.set(files as i64);
+        self.stored_bytes.set(bytes as i64);
+        let configured = [
+            ("bytes", limits.max_bytes),
+            ("files", limits.max_files.map(|max| max as u64)),
+            ("file_bytes", limits.max_file_bytes.map(|max| max as u64)),
+        ];
+        for (limit, value) in configured {
+            if let Some(value) = value {
+                self.storage_limit.with_label_values(&[limit]).set(value as i64);
+            }
+        }
+    }
+
+    pub fn record_retained_secrets(&self, retained: Retained) {
+        self.retained_secrets.set(retained.secrets as i64);
+        self.retained_secret_bytes.set(retained.bytes as i64);
+    }
+
+    pub fn record_evictions(&self, count: usize) {
+        self.storage_evictions.inc_by(count as u64);
+    }
+
+    // `kind` is what was collected: `session` or `storage_file`
+    pub fn record_gc(&self, kind: &str, count: usize, bytes: u64) {
+        self.gc_reclaimed.with_label_values(&[kind]).inc_by(count as u64);
+        self.gc_reclaimed_bytes.with_label_values(&[kind]).inc_by(bytes);
+    }
+
+    // Replaces the flag gauges, so flags removed on reload disappear
+    pub fn record_feature_flags(&self, rollouts: &BTreeMap<String, u8>) {
+        self.feature_flag_rollout.reset();
+        for (flag, percent) in rollouts {
+            self.feature_flag_rollout.with_label_values(&[flag]).set(i64::from(*percent));
+        }
+    }
+
+    // Replaces the queue gauges, so tenants whose jobs have all started disappear
+    pub fn record_job_queue(&self, backlog: &[TenantBacklog]) {
+        self.job_queue_depth.reset();
+        self.job_queue_oldest_wait_seconds.reset();
+        self.job_queue_weight.reset();
+        for tenant in backlog {
+            let label = [tenant.tenant.as_deref().unwrap_or("none")];
+            self.job_queue_depth.with_label_values(&label).set(tenant.queued as i64);
+            self.job_queue_oldest_wait_seconds.with_label_values(&label).set(tenant.oldest_wait_seconds);
+            self.job_queue_weight.with_label_values(&label).set(i64::from(tenant.weight));
+        }
+    }
+
+    pub fn record_replication(&self, pending: usize, lag: Duration) {
+        self.replication_pending_files.set(pending as i64);
+        self.replication_lag_seconds.set(lag.as_secs_f64());
+    }
+
+    pub fn record_cold_tier(&self, moved: usize) {
+        self.cold_tier_moved_files.inc_by(moved as u64);
+    }
+
+    // Deltas are only set once there is a previous run to compare with
+    pub fn record_quality(&self, score: &QualityScore, previous: Option<&QualityScore>) {
+        self.quality_sampled_documents.set(score.documents as i64);
+        for (measure, value, earlier) in [
+            ("precision", score.precision, previous.map(|previous| previous.precision)),
+            ("recall", score.recall, previous.map(|previous| previous.recall)),
+        ] {
+            self.quality_score.with_label_values(&[measure]).set(value);
+            if let Some(earlier) = earlier {
+                self.quality_score_delta.with_label_values(&[measure]).set(value - earlier);
+            }
+        }
+    }
+
+    // Text exposition format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            tracing::warn!("Failed to encode metrics: {}", e);
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sentient_redactor_core::operations::{ErrorKind, StageTiming};
+
+    #[test]
+    fn test_histograms_are_rendered() {
+        let metrics = Metrics::new().unwrap();
+        let mut report = RedactionReport::default();
+        report.entities.insert("US_SSN".to_string(), 12);
+        let profile = UploadProfile {
+            plaintext_bytes: 4096,
+            backend: "presidio".to_string(),
+            stages: vec![StageTiming { stage: "redaction", ms: 250.0 }],
+            ..UploadProfile::default()
+        };
+        metrics.record_upload(&profile, Some(&report));
+        metrics.record_upload_failure(&OperationError::new(ErrorKind::Internal, "Redaction failed"));
+        metrics.record_gc("session", 2, 64);
+        metrics.record_deprecated_mode("zero-nonce", false);
+        let score = QualityScore { documents: 20, precision: 0.75, recall: 0.5 };
+        metrics.record_quality(&score, Some(&QualityScore { precision: 1.0, ..score }));
+        metrics.record_replication(3, Duration::from_millis(1500));
+        metrics.record_cold_tier(4);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("redactor_entities_per_document_bucket{entity_type=\"US_SSN\",le=\"20\"} 1"));
+        assert!(rendered.contains("redactor_document_size_bytes_count 1"));
+        assert!(rendered.contains("redactor_backend_duration_seconds_bucket{backend=\"presidio\",le=\"0.32\"} 1"));
+        assert!(rendered.contains("redactor_uploads_total{result=\"failure\"} 1"));
+        assert!(rendered.contains("redactor_upload_failures_total{reason=\"other\"} 1"));
+        assert!(rendered.contains("redactor_gc_reclaimed_bytes_total{kind=\"session\"} 64"));
+        assert!(rendered.contains("redactor_deprecated_mode_uploads_total{mode=\"zero-nonce\",result=\"rejected\"} 1"));
+        assert!(rendered.contains("redactor_quality_score{measure=\"recall\"} 0.5"));
+        assert!(rendered.contains("redactor_quality_score_delta{measure=\"precision\"} -0.25"));
+        assert!(rendered.contains("redactor_replication_pending_files 3"));
+        assert!(rendered.contains("redactor_replication_lag_seconds 1.5"));
+        assert!(rendered.contains("redactor_cold_tier_moved_files_total 4"));
+    }
+}
@@ -0,0 +1,440 @@
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use reqwest::{redirect::Policy, Client, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+use utoipa::{IntoParams, ToSchema};
+
+use sentient_redactor_core::{
+    operations::{ErrorKind, OperationError, UploadResponse},
+    report::ReportSummary,
+    upstream,
+};
+
+use crate::fetch;
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+// Body POSTed to an upload's `callback_url` once it is redacted or has failed
+#[derive(Serialize)]
+pub struct Callback {
+    // Same on every attempt, also sent as `X-Redactor-Delivery`, so receivers can drop
+    // repeats
+    pub delivery_id: String,
+    pub file_id: String,
+    // `done` or `failed`
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report_summary: Option<ReportSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<CallbackError>,
+    pub timestamp: u64,
+}
+
+#[derive(Serialize)]
+pub struct CallbackError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl Callback {
+    pub fn new(file_id: &str, result: &Result<UploadResponse, OperationError>) -> Self {
+        let (status, report_summary, error) = match result {
+            Ok(response) => ("done", response.report_summary.clone(), None),
+            Err(e) => {
+                let error = CallbackError { code: e.code.unwrap_or(e.kind.code()), message: e.message.clone() };
+                ("failed", None, Some(error))
+            }
+        };
+        let mut token = [0u8; 16];
+        OsRng.fill_bytes(&mut token);
+        let delivery_id = token.iter().map(|b| format!("{:02x}", b)).collect();
+        Self { delivery_id, file_id: file_id.to_string(), status, report_summary, error, timestamp: now() }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    // Every attempt failed; `POST /admin/webhooks/deliveries/:id/retry` sends it again
+    Failed,
+}
+
+// A callback waiting to be delivered, or given up on, as kept in the outbox
+#[derive(Clone, Deserialize, Serialize, ToSchema)]
+pub struct Delivery {
+    pub delivery_id: String,
+    pub file_id: String,
+    pub url: String,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    pub created_at: u64,
+    pub updated_at: u64,
+    // The body, signed and sent unchanged on every attempt
+    #[schema(value_type = Object)]
+    pub callback: Value,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct DeliveryQuery {
+    // Only deliveries with this status
+    pub status: Option<DeliveryStatus>,
+}
+
+// Delivers upload callbacks to hosts on `CALLBACK_ALLOWED_HOSTS`, signed with
+// `CALLBACK_SECRET` and retried with backoff. Disabled unless both are set.
+pub struct Notifier {
+    client: Client,
+    allowed_hosts: Vec<String>,
+    secret: Option<Vec<u8>>,
+    max_attempts: u32,
+    // Callbacks not delivered yet, and those given up on
+    deliveries: Mutex<HashMap<String, Delivery>>,
+    // Where each delivery's changes are appended, so pending ones are retried after a restart
+    outbox: Option<Mutex<File>>,
+}
+
+impl Notifier {
+    pub fn from_env() -> Result<Self> {
+        let max_attempts = match std::env::var("CALLBACK_MAX_ATTEMPTS") {
+            Ok(value) => value.parse().ok().filter(|attempts| *attempts > 0)
+                .ok_or_else(|| anyhow!("Invalid CALLBACK_MAX_ATTEMPTS: {}", value))?,
+            Err(_) => DEFAULT_MAX_ATTEMPTS,
+        };
+        let client = upstream::client_builder("CALLBACK", Duration::from_secs(10))?
+            .redirect(Policy::none())
+            .build()
+            .map_err(|e| anyhow!("Failed to create HTTP client for CALLBACK: {}", e))?;
+
+        Ok(Self {
+            client,
+            allowed_hosts: fetch::allowed_hosts("CALLBACK_ALLOWED_HOSTS"),
+            secret: std::env::var("CALLBACK_SECRET").ok().filter(|secret| !secret.is_empty()).map(String::into_bytes),
+            max_attempts,
+            deliveries: Mutex::new(HashMap::new()),
+            outbox: None,
+        })
+    }
+
+    // Keep deliveries in the outbox at `path`, loading those an earlier run left pending
+    // or failed. The file is rewritten with only those.
+    pub fn with_outbox(mut self, path: Option<&str>) -> Result<Self> {
+        let Some(path) = path else {
+            return Ok(self);
+        };
+
+        let mut deliveries = HashMap::new();
+        match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+                    let delivery: Delivery = serde_json::from_str(line)
+                        .map_err(|e| anyhow!("Invalid callback delivery in {}: {}", path, e))?;
+                    deliveries.insert(delivery.delivery_id.clone(), delivery);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(anyhow!("Failed to read callback outbox {}: {}", path, e)),
+        }
+        deliveries.retain(|_, delivery| delivery.status != DeliveryStatus::Delivered);
+
+        // Written aside and moved into place, so a crash while compacting loses nothing
+        let mut lines = String::new();
+        for delivery in deliveries.values() {
+            lines.push_str(&serde_json::to_string(delivery)?);
+            lines.push('\n');
+        }
+        let compacted = format!("{}.tmp", path);
+        std::fs::write(&compacted, lines)
+            .and_then(|_| std::fs::rename(&compacted, path))
+            .map_err(|e| anyhow!("Failed to compact callback outbox {}: {}", path, e))?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| anyhow!("Failed to open callback outbox {}: {}", path, e))?;
+
+        info!("Loaded {} undelivered callback(s) from {}", deliveries.len(), path);
+        self.deliveries = Mutex::new(deliveries);
+        self.outbox = Some(Mutex::new(file));
+        Ok(self)
+    }
+
+    // Whether uploads may name `url` as their callback
+    pub fn check(&self, url: &str) -> Result<Url, String> {
+        if self.secret.is_none() || self.allowed_hosts.is_empty() {
+            return Err("Callbacks are not enabled on this service".to_string());
+        }
+        fetch::check_allowed_url(url, &self.allowed_hosts)
+    }
+
+    // Deliver in the background, so a slow receiver does not hold up the upload
+    pub fn spawn(self: &Arc<Self>, url: Url, callback: Callback) {
+        let callback_body = match serde_json::to_value(&callback) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Failed to encode callback for file_id {}: {}", callback.file_id, e);
+                return;
+            }
+        };
+        let now = now();
+        let delivery = Delivery {
+            delivery_id: callback.delivery_id,
+            file_id: callback.file_id,
+            url: url.to_string(),
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            last_error: None,
+            created_at: now,
+            updated_at: now,
+            callback: callback_body,
+        };
+        let delivery_id = delivery.delivery_id.clone();
+        self.write(&delivery);
+        self.deliveries.lock().unwrap().insert(delivery_id.clone(), delivery);
+
+        let notifier = self.clone();
+        tokio::spawn(async move { notifier.deliver(&delivery_id).await });
+    }
+
+    // Retry the deliveries an earlier run left pending, at startup
+    pub fn resume(self: &Arc<Self>) {
+        let pending: Vec<String> = self.deliveries.lock().unwrap().values()
+            .filter(|delivery| delivery.status == DeliveryStatus::Pending)
+            .map(|delivery| delivery.delivery_id.clone())
+            .collect();
+        if !pending.is_empty() {
+            info!("Resuming {} pending callback delivery(ies)", pending.len());
+        }
+        for delivery_id in pending {
+            let notifier = self.clone();
+            tokio::spawn(async move { notifier.deliver(&delivery_id).await });
+        }
+    }
+
+    // Pending and failed deliveries, oldest first
+    pub fn deliveries(&self, status: Option<DeliveryStatus>) -> Vec<Delivery> {
+        let mut deliveries: Vec<Delivery> = self.deliveries.lock().unwrap().values()
+            .filter(|delivery| status.is_none_or(|status| delivery.status == status))
+            .cloned()
+            .collect();
+        deliveries.sort_by(|a, b| (a.created_at, &a.delivery_id).cmp(&(b.created_at, &b.delivery_id)));
+        deliveries
+    }
+
+    // Send a failed delivery again, with a fresh set of attempts
+    pub fn resend(self: &Arc<Self>, delivery_id: &str) -> Result<Delivery, OperationError> {
+        let delivery = {
+            let mut deliveries = self.deliveries.lock().unwrap();
+            let delivery = deliveries.get_mut(delivery_id).ok_or_else(|| {
+                OperationError::new(ErrorKind::NotFound, format!("Unknown delivery id: {}", delivery_id))
+            })?;
+            if delivery.status != DeliveryStatus::Failed {
+                return Err(OperationError::new(ErrorKind::Conflict, "The delivery is still being attempted")
+                    .with_code("delivery_pending"));
+            }
+            delivery.status = DeliveryStatus::Pending;
+            delivery.attempts = 0;
+            delivery.updated_at = now();
+            delivery.clone()
+        };
+        self.write(&delivery);
+
+        let notifier = self.clone();
+        let id = delivery_id.to_string();
+        tokio::spawn(async move { notifier.deliver(&id).await });
+        Ok(delivery)
+    }
+
+    async fn deliver(&self, delivery_id: &str) {
+        let Some(secret) = &self.secret else { return };
+        let Some(delivery) = self.deliveries.lock().unwrap().get(delivery_id).cloned() else {
+            return;
+        };
+        let file_id = delivery.file_id;
+        let (url, body) = match (Url::parse(&delivery.url), serde_json::to_vec(&delivery.callback)) {
+            (Ok(url), Ok(body)) => (url, body),
+            _ => {
+                warn!("Callback for file_id {} cannot be sent", file_id);
+                self.update(delivery_id, |delivery| delivery.status = DeliveryStatus::Failed);
+                return;
+            }
+        };
+        let signature = sign(secret, &body);
+
+        let mut delay = FIRST_RETRY_DELAY;
+        let mut attempts = delivery.attempts;
+        while attempts < self.max_attempts {
+            attempts += 1;
+            let request = self.client.post(url.clone())
+                .header("Content-Type", "application/json")
+                .header("X-Redactor-Signature", format!("sha256={}", signature))
+                .header("X-Redactor-Delivery", delivery_id)
+                .body(body.clone());
+            let error = match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    info!("Delivered callback for file_id {} on attempt {}", file_id, attempts);
+                    self.update(delivery_id, |delivery| {
+                        delivery.status = DeliveryStatus::Delivered;
+                        delivery.attempts = attempts;
+                        delivery.last_error = None;
+                    });
+                    return;
+                }
+                Ok(response) => format!("Rejected with {}", response.status()),
+                Err(e) => e.to_string(),
+            };
+            warn!("Callback for file_id {} failed: {}", file_id, error);
+            self.update(delivery_id, |delivery| {
+                delivery.attempts = attempts;
+                delivery.last_error = Some(error);
+            });
+            if attempts < self.max_attempts {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+        warn!("Gave up on the callback for file_id {} after {} attempts", file_id, attempts);
+        self.update(delivery_id, |delivery| delivery.status = DeliveryStatus::Failed);
+    }
+
+    // Apply a change to a delivery and write it to the outbox. Delivered ones are no
+    // longer kept.
+    fn update(&self, delivery_id: &str, apply: impl FnOnce(&mut Delivery)) {
+        let delivery = {
+            let mut deliveries = self.deliveries.lock().unwrap();
+            let Some(delivery) = deliveries.get_mut(delivery_id) else {
+                return;
+            };
+            apply(delivery);
+            delivery.updated_at = now();
+            let delivery = delivery.clone();
+            if delivery.status == DeliveryStatus::Delivered {
+                deliveries.remove(delivery_id);
+            }
+            delivery
+        };
+        self.write(&delivery);
+    }
+
+    fn write(&self, delivery: &Delivery) {
+        let Some(outbox) = &self.outbox else {
+            return;
+        };
+        let line = serde_json::to_string(delivery).unwrap_or_default();
+        if let Err(e) = writeln!(outbox.lock().unwrap(), "{}", line) {
+            warn!("Failed to write callback delivery {} to the outbox: {}", delivery.delivery_id, e);
+        }
+    }
+}
+
+// Hex HMAC-SHA256 of the body, for receivers to check against `X-Redactor-Signature`
+fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sentient_redactor_core::operations::ErrorKind;
+
+    #[test]
+    fn test_callbacks_are_signed_and_report_failures() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+
+        let failed = Callback::new("f1", &Err(OperationError::new(ErrorKind::BadRequest, "File decryption failed").with_code("decryption_failed")));
+        let body = serde_json::to_value(&failed).unwrap();
+        assert_eq!((body["status"].as_str(), body["error"]["code"].as_str()), (Some("failed"), Some("decryption_failed")));
+        assert!(body.get("report_summary").is_none());
+    }
+
+    #[test]
+    fn test_callback_urls_need_an_enabled_notifier() {
+        let mut notifier = Notifier {
+            client: Client::new(),
+            allowed_hosts: vec!["hooks.example.com".to_string()],
+            secret: None,
+            max_attempts: 1,
+            deliveries: Mutex::new(HashMap::new()),
+            outbox: None,
+        };
+        assert!(notifier.check("https://hooks.example.com/redactor").is_err());
+
+        notifier.secret = Some(b"secret".to_vec());
+        assert!(notifier.check("https://hooks.example.com/redactor").is_ok());
+        assert!(notifier.check("https://attacker.example.net/").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_undelivered_callbacks_outlive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("outbox.jsonl");
+        let path = path.to_str();
+        let open = |secret: Option<&[u8]>| {
+            let notifier = Notifier {
+                client: Client::new(),
+                allowed_hosts: vec!["hooks.example.com".to_string()],
+                secret: secret.map(<[u8]>::to_vec),
+                max_attempts: 1,
+                deliveries: Mutex::new(HashMap::new()),
+                outbox: None,
+            };
+            Arc::new(notifier.with_outbox(path).unwrap())
+        };
+
+        // Without a secret nothing is sent, so the delivery stays pending
+        let notifier = open(None);
+        let callback = Callback::new("f1", &Err(OperationError::new(ErrorKind::BadRequest, "File decryption failed")));
+        let delivery_id = callback.delivery_id.clone();
+        notifier.spawn(Url::parse("https://hooks.example.com/redactor").unwrap(), callback);
+        notifier.update(&delivery_id, |delivery| delivery.attempts = 1);
+        drop(notifier);
+
+        // Its attempts are used up, so once resumed it is failed rather than sent
+        let notifier = open(Some(b"secret"));
+        let pending = notifier.deliveries(Some(DeliveryStatus::Pending));
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].callback["delivery_id"], delivery_id.as_str());
+        notifier.deliver(&delivery_id).await;
+        assert_eq!(notifier.deliveries(Some(DeliveryStatus::Failed)).len(), 1);
+        drop(notifier);
+
+        let notifier = open(None);
+        let failed = notifier.deliveries(None);
+        assert_eq!((failed.len(), failed[0].status), (1, DeliveryStatus::Failed));
+        let resent = notifier.resend(&delivery_id).unwrap();
+        assert_eq!((resent.status, resent.attempts), (DeliveryStatus::Pending, 0));
+        assert!(matches!(notifier.resend(&delivery_id), Err(e) if e.code == Some("delivery_pending")));
+        assert!(matches!(notifier.resend("unknown"), Err(e) if e.kind.status() == 404));
+
+        notifier.update(&delivery_id, |delivery| delivery.status = DeliveryStatus::Delivered);
+        assert!(notifier.deliveries(None).is_empty());
+        drop(notifier);
+        assert!(open(None).deliveries(None).is_empty());
+    }
+}
@@ -0,0 +1,145 @@
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{Modify, OpenApi, ToSchema};
+
+use sentient_redactor_core::operations::UploadRequest;
+
+// The HTTP API as OpenAPI 3.1, served at `/openapi.json` and browsable at `/docs`.
+// Request and response bodies are the handlers' own types, so the spec follows them.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Sentient Redactor Service",
+        description = "Redacts PII from files encrypted to the service key. Every route but the probes needs a credential of one of the configured `AUTH_PROVIDERS`; admin routes need `X-Admin-Token`. Errors are `ErrorResponse` bodies whose `code` is stable.",
+    ),
+    paths(
+        crate::health_check,
+        crate::readiness_check,
+        crate::metrics_handler,
+        crate::handshake,
+        crate::create_session,
+        crate::capabilities,
+        crate::upload_file,
+        crate::upload_from_url,
+        crate::upload_multipart,
+        crate::upload_stream,
+        crate::upload_batch,
+        crate::analyze_upload,
+        crate::redact_stream,
+        crate::estimate_upload,
+        crate::create_expectation,
+        crate::get_expectation,
+        crate::job_status,
+        crate::job_result,
+        crate::job_event_stream,
+        crate::download_file,
+        crate::download_bulk,
+        crate::download_document,
+        crate::get_signature,
+        crate::unredact_file,
+        crate::original_file,
+        crate::list_files,
+        crate::search_reports,
+        crate::find_by_external_id,
+        crate::preview_file,
+        crate::get_report,
+        crate::get_heatmap,
+        crate::release_file,
+        crate::reprocess_file,
+        crate::update_acl,
+        crate::delete_file,
+        crate::submit_feedback,
+        crate::feedback_summary,
+        crate::create_share,
+        crate::redeem_share,
+        crate::list_audit_records,
+        crate::get_erasure_receipt,
+        crate::verify_audit_chain,
+        crate::get_stats,
+        crate::list_deliveries,
+        crate::resend_delivery,
+        crate::get_retained_secrets,
+        crate::get_runtime_config,
+        crate::set_runtime_config,
+        crate::redaction_selftest,
+        crate::get_maintenance,
+        crate::set_maintenance,
+        crate::export_escrow,
+        crate::list_keys,
+        crate::rotate_key,
+        crate::retire_key,
+    ),
+    modifiers(&SecuritySchemes),
+    security(("api_key" = []), ("signed_request" = []), ("principal_header" = [])),
+    tags(
+        (name = "probes", description = "Liveness, readiness and metrics"),
+        (name = "handshake", description = "Service key, sessions and capabilities"),
+        (name = "uploads", description = "Encrypted uploads and their redaction"),
+        (name = "jobs", description = "Uploads queued with `?async=true`"),
+        (name = "files", description = "Stored files, their reports and access control"),
+        (name = "downloads", description = "Redacted content"),
+        (name = "shares", description = "One-time download links"),
+        (name = "feedback", description = "Reviewer feedback on detections"),
+        (name = "audit", description = "Audit trail and erasure receipts"),
+        (name = "admin", description = "Operator endpoints"),
+    )
+)]
+pub struct ApiDoc;
+
+// Body of the multipart upload routes. Handlers read the parts themselves; this only
+// describes them.
+#[allow(dead_code)]
+#[derive(ToSchema)]
+pub struct MultipartUpload {
+    // The upload's fields, without `encrypted_data`
+    metadata: UploadRequest,
+    // The raw ciphertext
+    #[schema(value_type = String, format = Binary)]
+    file: Vec<u8>,
+}
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        let header = |name: &str, description: &str| {
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(name, description)))
+        };
+        components.add_security_scheme("api_key", header("X-API-Key", "A static key of the `api_keys` provider"));
+        components.add_security_scheme(
+            "signed_request",
+            header("X-Auth-Signature", "HMAC-SHA256 of the request under the shared secret of `X-Auth-Key-Id`, with `X-Auth-Timestamp`; the `hmac` provider"),
+        );
+        components.add_security_scheme(
+            "principal_header",
+            header("X-Principal-Id", "Identity set by a trusted gateway, with `X-Tenant-Id`; the `headers` provider"),
+        );
+        components.add_security_scheme("admin_token", header("X-Admin-Token", "The operator token set in `ADMIN_TOKEN`"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spec_covers_routes_and_bodies() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        for path in ["/upload", "/files/{file_id}/reprocess", "/download/{file_id}", "/files/{file_id}/signature", "/admin/keys/rotate", "/health/ready"] {
+            assert!(spec["paths"][path].is_object(), "{} is not in the spec", path);
+        }
+        let schemas = &spec["components"]["schemas"];
+        for schema in ["UploadRequest", "UploadResponse", "ErrorResponse", "ReprocessRequest", "Capabilities", "ErasureReceiptResponse", "OutputSignature"] {
+            assert!(schemas[schema].is_object(), "{} is not in the spec", schema);
+        }
+        // Fields the service fills in itself are not part of the request
+        let upload = &schemas["UploadRequest"]["properties"];
+        assert!(upload["redaction_strategy"].is_object());
+        assert!(upload["ciphertext"].is_null() && upload["file_id"].is_null());
+
+        // Probes are open, admin routes need the admin token
+        assert_eq!(spec["paths"]["/health/live"]["get"]["security"], serde_json::json!([{}]));
+        assert_eq!(spec["paths"]["/admin/stats"]["get"]["security"], serde_json::json!([{ "admin_token": [] }]));
+        assert!(spec["components"]["securitySchemes"]["api_key"].is_object());
+    }
+}
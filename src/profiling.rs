@@ -0,0 +1,61 @@
+use std::time::Duration;
+use tracing::warn;
+
+use sentient_redactor_core::operations::UploadProfile;
+
+const DEFAULT_SLOW_UPLOAD_MS: u64 = 10_000;
+
+// Logs the stage breakdown of uploads slower than `SLOW_UPLOAD_THRESHOLD_MS`
+// (0 disables it)
+pub struct SlowUploadLog {
+    threshold: Option<Duration>,
+}
+
+impl SlowUploadLog {
+    pub fn from_env() -> Self {
+        let threshold_ms = std::env::var("SLOW_UPLOAD_THRESHOLD_MS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_SLOW_UPLOAD_MS);
+
+        Self {
+            threshold: (threshold_ms > 0).then(|| Duration::from_millis(threshold_ms)),
+        }
+    }
+
+    pub fn is_slow(&self, profile: &UploadProfile) -> bool {
+        self.threshold
+            .is_some_and(|threshold| profile.total_ms >= threshold.as_secs_f64() * 1000.0)
+    }
+
+    pub fn observe(&self, file_id: &str, profile: &UploadProfile) {
+        if self.is_slow(profile) {
+            warn!(
+                file_id,
+                total_ms = profile.total_ms,
+                backend = %profile.backend,
+                ciphertext_bytes = profile.ciphertext_bytes,
+                plaintext_bytes = profile.plaintext_bytes,
+                analyzed_chunks = profile.analyzed_chunks,
+                stages = %serde_json::to_string(&profile.stages).unwrap_or_default(),
+                "Slow upload"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threshold_selects_slow_uploads() {
+        let log = SlowUploadLog { threshold: Some(Duration::from_millis(500)) };
+        let profile = |total_ms| UploadProfile { total_ms, ..UploadProfile::default() };
+        assert!(log.is_slow(&profile(750.0)));
+        assert!(!log.is_slow(&profile(120.0)));
+
+        let disabled = SlowUploadLog { threshold: None };
+        assert!(!disabled.is_slow(&profile(60_000.0)));
+    }
+}
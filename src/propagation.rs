@@ -0,0 +1,316 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use reqwest::{redirect::Policy, Client, Url};
+use serde::Serialize;
+use serde_json::json;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use sentient_redactor_core::{
+    config::AppConfig,
+    s3::{DeliveryBucket, S3Config},
+    storage::FileMetadata,
+    upstream,
+};
+
+use crate::alerts::{Alert, Alerter, Severity};
+use crate::audit::{AuditLog, AuditRecord};
+
+const MAX_ATTEMPTS: u32 = 5;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(2);
+// Files whose propagation is tracked; the oldest finished ones are dropped beyond this
+const MAX_TRACKED_FILES: usize = 10_000;
+
+// What delivery targets are told about an erased file. Like audit records, it never
+// carries file content.
+#[derive(Clone, Serialize)]
+pub struct DeletionNotice {
+    pub file_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    // `deleted` or `expired`
+    pub reason: String,
+    pub deleted_at: u64,
+}
+
+impl DeletionNotice {
+    pub fn new(file_id: &str, metadata: &FileMetadata, reason: &str) -> Self {
+        Self {
+            file_id: file_id.to_string(),
+            tenant: metadata.tenant.clone(),
+            external_id: metadata.external_id.clone(),
+            reason: reason.to_string(),
+            deleted_at: now(),
+        }
+    }
+}
+
+// Somewhere copies of redacted files were delivered, told to drop them when the
+// service erases its own
+#[async_trait]
+pub trait DeletionTarget: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn propagate(&self, notice: &DeletionNotice) -> Result<()>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PropagationState {
+    Pending,
+    Done,
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct TargetStatus {
+    pub target: &'static str,
+    pub state: PropagationState,
+    pub attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub updated_at: u64,
+}
+
+// Tells every configured target about each erasure in the background, retrying with
+// backoff, and keeps where each one got for erasure receipts. The outcome is also
+// written to the audit log as `file.delete.propagate`, and failures raise an alert.
+pub struct DeletionPropagator {
+    targets: Vec<Arc<dyn DeletionTarget>>,
+    statuses: Mutex<HashMap<String, Vec<TargetStatus>>>,
+    first_retry_delay: Duration,
+}
+
+impl DeletionPropagator {
+    pub async fn from_config(config: &AppConfig) -> Result<Self> {
+        let mut targets: Vec<Arc<dyn DeletionTarget>> = Vec::new();
+        if let Some(url) = &config.deletion_webhook_url {
+            let secret = config.deletion_webhook_secret.clone().unwrap_or_default();
+            targets.push(Arc::new(WebhookTarget::new(url, secret.into_bytes())?));
+        }
+        if let Some(bucket) = S3Config::deletion_target(config) {
+            targets.push(Arc::new(BucketTarget(DeliveryBucket::new(bucket).await)));
+        }
+        for target in &targets {
+            info!("Erasures are propagated to the {} delivery target", target.name());
+        }
+        Ok(Self::new(targets))
+    }
+
+    fn new(targets: Vec<Arc<dyn DeletionTarget>>) -> Self {
+        Self { targets, statuses: Mutex::new(HashMap::new()), first_retry_delay: FIRST_RETRY_DELAY }
+    }
+
+    // Names of the targets erasures go to, for the receipt
+    pub fn target_names(&self) -> Vec<&'static str> {
+        self.targets.iter().map(|target| target.name()).collect()
+    }
+
+    // Where propagation of the file's erasure has got, if it was propagated
+    pub fn status(&self, file_id: &str) -> Option<Vec<TargetStatus>> {
+        self.statuses.lock().unwrap().get(file_id).cloned()
+    }
+
+    pub fn spawn(self: &Arc<Self>, notice: DeletionNotice, audit_log: Arc<RwLock<AuditLog>>, alerts: Arc<Alerter>) {
+        if self.targets.is_empty() {
+            return;
+        }
+        let propagator = self.clone();
+        tokio::spawn(async move {
+            let statuses = propagator.propagate(&notice).await;
+            let failed: Vec<_> = statuses.iter().filter(|status| status.state == PropagationState::Failed).map(|status| status.target).collect();
+            let outcome = if failed.is_empty() { "success" } else { "failure" };
+            audit_log.write().await.record(
+                AuditRecord::new("file.delete.propagate", None, Some(&notice.file_id), outcome)
+                    .with_details(json!({ "targets": statuses })),
+            );
+            if !failed.is_empty() {
+                let summary = format!("Erasure of file {} did not reach {}", notice.file_id, failed.join(", "));
+                alerts.raise(Alert::new(Severity::Warning, "erasure.propagation_failed", summary)
+                    .with_details(json!({ "file_id": notice.file_id, "targets": failed })));
+            }
+        });
+    }
+
+    // Tell every target, concurrently, returning how each one ended up
+    async fn propagate(&self, notice: &DeletionNotice) -> Vec<TargetStatus> {
+        self.track(&notice.file_id);
+        let deliveries = self.targets.iter().enumerate().map(|(index, target)| self.deliver(index, target.as_ref(), notice));
+        futures_util::future::join_all(deliveries).await;
+        self.status(&notice.file_id).unwrap_or_default()
+    }
+
+    async fn deliver(&self, index: usize, target: &dyn DeletionTarget, notice: &DeletionNotice) {
+        let mut delay = self.first_retry_delay;
+        for attempt in 1..=MAX_ATTEMPTS {
+            let result = target.propagate(notice).await;
+            let done = result.is_ok();
+            let state = match result {
+                Ok(()) => PropagationState::Done,
+                Err(_) if attempt < MAX_ATTEMPTS => PropagationState::Pending,
+                Err(_) => PropagationState::Failed,
+            };
+            let error = result.err().map(|e| e.to_string());
+            if let Some(e) = &error {
+                warn!("Propagating erasure of file_id {} to {} failed on attempt {}: {}", notice.file_id, target.name(), attempt, e);
+            }
+            self.update(&notice.file_id, index, state, attempt, error);
+            if done {
+                info!("Propagated erasure of file_id {} to {}", notice.file_id, target.name());
+                return;
+            }
+            if attempt < MAX_ATTEMPTS {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+    }
+
+    fn track(&self, file_id: &str) {
+        let pending = self.targets.iter()
+            .map(|target| TargetStatus { target: target.name(), state: PropagationState::Pending, attempts: 0, error: None, updated_at: now() })
+            .collect();
+        let mut statuses = self.statuses.lock().unwrap();
+        if statuses.len() >= MAX_TRACKED_FILES {
+            let oldest = statuses.iter()
+                .filter(|(_, targets)| targets.iter().all(|status| status.state != PropagationState::Pending))
+                .min_by_key(|(_, targets)| targets.iter().map(|status| status.updated_at).max())
+                .map(|(file_id, _)| file_id.clone());
+            if let Some(oldest) = oldest {
+                statuses.remove(&oldest);
+            }
+        }
+        statuses.insert(file_id.to_string(), pending);
+    }
+
+    fn update(&self, file_id: &str, index: usize, state: PropagationState, attempts: u32, error: Option<String>) {
+        if let Some(status) = self.statuses.lock().unwrap().get_mut(file_id).and_then(|targets| targets.get_mut(index)) {
+            *status = TargetStatus { target: status.target, state, attempts, error, updated_at: now() };
+        }
+    }
+}
+
+// Whether every target has confirmed the erasure
+pub fn is_complete(statuses: &[TargetStatus]) -> bool {
+    statuses.iter().all(|status| status.state == PropagationState::Done)
+}
+
+// POSTs the notice as JSON, signed like upload callbacks
+struct WebhookTarget {
+    client: Client,
+    url: Url,
+    secret: Vec<u8>,
+}
+
+impl WebhookTarget {
+    fn new(url: &str, secret: Vec<u8>) -> Result<Self> {
+        let url = Url::parse(url).map_err(|e| anyhow!("Invalid deletion_webhook_url: {}", e))?;
+        let client = upstream::client_builder("DELETION_WEBHOOK", Duration::from_secs(10))?
+            .redirect(Policy::none())
+            .build()
+            .map_err(|e| anyhow!("Failed to create HTTP client for DELETION_WEBHOOK: {}", e))?;
+        Ok(Self { client, url, secret })
+    }
+}
+
+#[async_trait]
+impl DeletionTarget for WebhookTarget {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn propagate(&self, notice: &DeletionNotice) -> Result<()> {
+        let body = serde_json::to_vec(notice)?;
+        let response = self.client.post(self.url.clone())
+            .header("Content-Type", "application/json")
+            .header("X-Redactor-Signature", format!("sha256={}", sign(&self.secret, &body)))
+            .body(body)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Deletion webhook answered {}", response.status()));
+        }
+        Ok(())
+    }
+}
+
+// Deletes the copies delivered to a bucket
+struct BucketTarget(DeliveryBucket);
+
+#[async_trait]
+impl DeletionTarget for BucketTarget {
+    fn name(&self) -> &'static str {
+        "s3"
+    }
+
+    async fn propagate(&self, notice: &DeletionNotice) -> Result<()> {
+        let deleted = self.0.delete_file(&notice.file_id).await?;
+        info!("Deleted {} delivered objects of file_id {} from bucket {}", deleted, notice.file_id, self.0.bucket());
+        Ok(())
+    }
+}
+
+// Hex HMAC-SHA256 of the body, for receivers to check against `X-Redactor-Signature`
+fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    // Fails its first `failures` calls
+    struct Flaky {
+        name: &'static str,
+        failures: u32,
+        calls: AtomicU32,
+    }
+
+    #[async_trait]
+    impl DeletionTarget for Flaky {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn propagate(&self, _notice: &DeletionNotice) -> Result<()> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err(anyhow!("{} is unavailable", self.name));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_propagation_is_retried_and_tracked_per_target() {
+        let flaky = |name, failures| Arc::new(Flaky { name, failures, calls: AtomicU32::new(0) }) as Arc<dyn DeletionTarget>;
+        let mut propagator = DeletionPropagator::new(vec![flaky("webhook", 2), flaky("s3", MAX_ATTEMPTS)]);
+        propagator.first_retry_delay = Duration::from_millis(1);
+        assert_eq!(propagator.target_names(), ["webhook", "s3"]);
+        assert!(propagator.status("f1").is_none());
+
+        let notice = DeletionNotice { file_id: "f1".to_string(), tenant: None, external_id: None, reason: "deleted".to_string(), deleted_at: now() };
+        let statuses = propagator.propagate(&notice).await;
+        let summary: Vec<_> = statuses.iter().map(|status| (status.target, status.state, status.attempts)).collect();
+        assert_eq!(summary, [("webhook", PropagationState::Done, 3), ("s3", PropagationState::Failed, MAX_ATTEMPTS)]);
+        assert_eq!(statuses[1].error.as_deref(), Some("s3 is unavailable"));
+        assert!(!is_complete(&statuses));
+        assert!(is_complete(&propagator.status("f1").unwrap()[..1]));
+    }
+}
@@ -0,0 +1,146 @@
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Notify, OnceCell};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use sentient_redactor_core::crypto::CryptoService;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Default, Serialize, ToSchema)]
+pub struct ProvisioningStatus {
+    pub ready: bool,
+    pub attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+// Provisions the service key pair in the background. Until a key is available and has
+// passed its wrap/unwrap self-test the service reports not-ready, and failures are
+// retried with exponential backoff instead of aborting startup.
+pub struct KeyProvisioner {
+    service: OnceCell<CryptoService>,
+    provisioned: Notify,
+    status: Mutex<ProvisioningStatus>,
+    max_backoff: Duration,
+    allow_legacy_zero_nonce: bool,
+}
+
+impl KeyProvisioner {
+    pub fn from_env() -> Self {
+        let max_backoff = std::env::var("KEY_PROVISIONING_MAX_BACKOFF_SECONDS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(60);
+
+        Self {
+            service: OnceCell::new(),
+            provisioned: Notify::new(),
+            status: Mutex::new(ProvisioningStatus::default()),
+            max_backoff: Duration::from_secs(max_backoff),
+            allow_legacy_zero_nonce: false,
+        }
+    }
+
+    pub fn with_legacy_zero_nonce(mut self, allow: bool) -> Self {
+        self.allow_legacy_zero_nonce = allow;
+        self
+    }
+
+    // Provisioned from the start with `service`, for tests that bring their own key
+    #[cfg(test)]
+    pub fn with_service(self, service: CryptoService) -> Self {
+        let _ = self.service.set(service);
+        self.status.lock().unwrap().ready = true;
+        self
+    }
+
+    pub fn get(&self) -> Option<&CryptoService> {
+        self.service.get()
+    }
+
+    // Resolves once the key is in place
+    pub async fn wait(&self) -> &CryptoService {
+        loop {
+            let provisioned = self.provisioned.notified();
+            if let Some(service) = self.service.get() {
+                return service;
+            }
+            provisioned.await;
+        }
+    }
+
+    pub fn status(&self) -> ProvisioningStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    // Returns true once the key is in place
+    fn record_attempt(&self, attempt: anyhow::Result<CryptoService>, backoff: Duration) -> bool {
+        let mut status = self.status.lock().unwrap();
+        status.attempts += 1;
+        match attempt {
+            Ok(service) => {
+                let _ = self.service.set(service);
+                self.provisioned.notify_waiters();
+                status.ready = true;
+                status.last_error = None;
+                info!("Service key provisioned after {} attempt(s)", status.attempts);
+                true
+            }
+            Err(e) => {
+                warn!("Key provisioning attempt {} failed, retrying in {:?}: {}", status.attempts, backoff, e);
+                status.last_error = Some(e.to_string());
+                false
+            }
+        }
+    }
+
+    pub fn spawn(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut backoff = INITIAL_BACKOFF;
+            let allow_legacy_zero_nonce = self.allow_legacy_zero_nonce;
+            loop {
+                let attempt = tokio::task::spawn_blocking(move || {
+                    let service = CryptoService::generate(allow_legacy_zero_nonce)?;
+                    service.self_test()?;
+                    Ok::<_, anyhow::Error>(service)
+                })
+                .await
+                .unwrap_or_else(|e| Err(anyhow::anyhow!("Key provisioning task failed: {}", e)));
+
+                if self.record_attempt(attempt, backoff) {
+                    return;
+                }
+
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(self.max_backoff);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reports_ready_after_provisioning() {
+        let provisioner = Arc::new(KeyProvisioner::from_env());
+        assert!(!provisioner.status().ready);
+        assert!(provisioner.get().is_none());
+
+        provisioner.clone().spawn();
+        for _ in 0..300 {
+            if provisioner.status().ready {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        assert!(provisioner.status().ready);
+        assert!(provisioner.get().is_some());
+        provisioner.wait().await;
+    }
+}
@@ -0,0 +1,197 @@
+use anyhow::{anyhow, Result};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::warn;
+
+use sentient_redactor_core::{
+    bidi::BidiMode,
+    config::AppConfig,
+    report::Detection,
+    spans::Segment,
+    CryptoService, EntityFilter, RedactorService, Storage,
+};
+
+// How the live backend's detections compare to the reference backend's, taken as the
+// truth, over the documents of one sample
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QualityScore {
+    pub documents: usize,
+    // Share of the live detections the reference also found
+    pub precision: f64,
+    // Share of the reference detections the live backend also found
+    pub recall: f64,
+}
+
+// A retained original opened for sampling
+pub struct SampledDocument {
+    pub file_id: String,
+    pub text: String,
+    pub language: Option<String>,
+}
+
+// Every `quality_sample_seconds`, re-runs the retained originals of the most recent
+// uploads through `quality_reference_backend`, so a drop in detection quality after an
+// engine upgrade shows in the metrics rather than in leaked PII
+pub struct QualitySampler {
+    reference: RedactorService,
+    sample_size: usize,
+    pub interval: Duration,
+    // Score of the previous run, which the next run's deltas are taken against
+    previous: Mutex<Option<QualityScore>>,
+}
+
+impl QualitySampler {
+    // None unless `quality_reference_backend` is set
+    pub fn from_config(config: &AppConfig) -> Result<Option<Self>> {
+        let Some(backend) = &config.quality_reference_backend else {
+            return Ok(None);
+        };
+        let reference_config = AppConfig {
+            redaction_backend: backend.clone(),
+            presidio_url: config.quality_reference_presidio_url.clone().unwrap_or_else(|| config.presidio_url.clone()),
+            ..config.clone()
+        };
+        let reference = RedactorService::from_config(&reference_config)
+            .map_err(|e| anyhow!("Failed to set up the quality reference backend: {}", e))?;
+        Ok(Some(Self::new(reference, config.quality_sample_size, Duration::from_secs(config.quality_sample_seconds))))
+    }
+
+    pub fn new(reference: RedactorService, sample_size: usize, interval: Duration) -> Self {
+        Self { reference, sample_size, interval, previous: Mutex::new(None) }
+    }
+
+    // The retained originals of the most recently stored files that have one. Originals
+    // that fail to open are skipped.
+    pub fn sample(&self, storage: &dyn Storage, crypto: &CryptoService) -> Vec<SampledDocument> {
+        let mut candidates: Vec<(u64, String)> = storage.file_ids()
+            .into_iter()
+            .filter_map(|file_id| {
+                let metadata = storage.get_metadata(&file_id)?;
+                metadata.original.as_ref().map(|_| (metadata.created_at, file_id))
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.cmp(a));
+
+        candidates.into_iter()
+            .take(self.sample_size)
+            .filter_map(|(_, file_id)| {
+                let metadata = storage.get_metadata(&file_id)?;
+                match metadata.original.as_ref()?.open(crypto, &file_id) {
+                    Ok(text) => Some(SampledDocument { language: metadata.language.clone(), text, file_id }),
+                    Err(e) => {
+                        warn!("Failed to open the original of file_id {} for quality sampling: {}", file_id, e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    // Score `live` against the reference over `documents`, returning the score with that of
+    // the previous run. Documents either backend fails on are left out.
+    pub async fn run(&self, live: &RedactorService, documents: &[SampledDocument]) -> Result<(QualityScore, Option<QualityScore>)> {
+        let mut counts = MatchCounts::default();
+        let mut scored = 0;
+        for document in documents {
+            let filter = EntityFilter { language: document.language.as_deref(), ..EntityFilter::default() };
+            let segments = [Segment::Analyze(&document.text)];
+            let detected = live.detect_segments(&segments, filter, BidiMode::default(), None, None).await;
+            let expected = self.reference.detect_segments(&segments, filter, BidiMode::default(), None, None).await;
+            match (detected, expected) {
+                (Ok(detected), Ok(expected)) => {
+                    counts.add(&detected, &expected);
+                    scored += 1;
+                }
+                (Err(e), _) | (_, Err(e)) => warn!("Skipping file_id {} in quality sampling: {}", document.file_id, e),
+            }
+        }
+        if scored == 0 {
+            return Err(anyhow!("No sampled document could be analyzed by both backends"));
+        }
+
+        let score = counts.score(scored);
+        let previous = self.previous.lock().unwrap().replace(score);
+        Ok((score, previous))
+    }
+}
+
+// Detections of one backend matched against another's. A detection matches when the
+// other backend found the same entity type over an overlapping span.
+#[derive(Default)]
+struct MatchCounts {
+    detected: usize,
+    detected_matched: usize,
+    expected: usize,
+    expected_matched: usize,
+}
+
+impl MatchCounts {
+    fn add(&mut self, detected: &[Detection], expected: &[Detection]) {
+        let matches = |detection: &Detection, others: &[Detection]| {
+            others.iter().any(|other| {
+                other.entity_type == detection.entity_type && other.start < detection.end && detection.start < other.end
+            })
+        };
+        self.detected += detected.len();
+        self.detected_matched += detected.iter().filter(|detection| matches(detection, expected)).count();
+        self.expected += expected.len();
+        self.expected_matched += expected.iter().filter(|detection| matches(detection, detected)).count();
+    }
+
+    // Nothing detected is nothing wrongly detected, and nothing expected nothing missed
+    fn score(&self, documents: usize) -> QualityScore {
+        let ratio = |matched: usize, total: usize| if total == 0 { 1.0 } else { matched as f64 / total as f64 };
+        QualityScore {
+            documents,
+            precision: ratio(self.detected_matched, self.detected),
+            recall: ratio(self.expected_matched, self.expected),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sentient_redactor_core::{labels::LabelCatalog, original::RetainedOriginal, rules::RegexEngine, structured::ContentType, FileStorage};
+
+    fn detection(entity_type: &str, start: usize, end: usize) -> Detection {
+        Detection { entity_type: entity_type.to_string(), start, end, score: 1.0 }
+    }
+
+    #[test]
+    fn test_detections_are_scored_against_the_reference() {
+        let mut counts = MatchCounts::default();
+        let detected = [detection("EMAIL_ADDRESS", 0, 10), detection("PERSON", 20, 25), detection("US_SSN", 30, 41)];
+        // The PERSON span overlaps, the SSN is typed differently and the phone number is missed
+        let expected = [detection("EMAIL_ADDRESS", 0, 10), detection("PERSON", 22, 30), detection("US_ITIN", 30, 41), detection("PHONE_NUMBER", 50, 62)];
+        counts.add(&detected, &expected);
+
+        let score = counts.score(1);
+        assert!((score.precision - 2.0 / 3.0).abs() < 1e-9);
+        assert!((score.recall - 0.5).abs() < 1e-9);
+        assert_eq!(MatchCounts::default().score(1), QualityScore { documents: 1, precision: 1.0, recall: 1.0 });
+    }
+
+    #[tokio::test]
+    async fn test_recent_originals_are_sampled_and_scored() {
+        let crypto = CryptoService::new().unwrap();
+        let mut storage = FileStorage::new();
+        for (file_id, created_at, retained) in [("old", 1, true), ("plain", 3, false), ("new", 2, true)] {
+            let original = RetainedOriginal::seal(&crypto, file_id, "Mail jane@example.com about 123-45-6789", ContentType::Text).unwrap();
+            let metadata = storage.store_file(file_id, "notes.txt", "Mail [EMAIL_ADDRESS] about [US_SSN]");
+            metadata.created_at = created_at;
+            metadata.original = retained.then_some(original);
+        }
+
+        let reference = RedactorService::with_backend(Box::new(RegexEngine::new()), LabelCatalog::default());
+        let sampler = QualitySampler::new(reference, 1, Duration::from_secs(60));
+        let documents = sampler.sample(&storage, &crypto);
+        assert_eq!(documents.iter().map(|document| document.file_id.as_str()).collect::<Vec<_>>(), ["new"]);
+
+        let live = RedactorService::with_backend(Box::new(RegexEngine::new()), LabelCatalog::default());
+        let (score, previous) = sampler.run(&live, &documents).await.unwrap();
+        assert_eq!(score, QualityScore { documents: 1, precision: 1.0, recall: 1.0 });
+        assert!(previous.is_none());
+        assert_eq!(sampler.run(&live, &documents).await.unwrap().1, Some(score));
+    }
+}
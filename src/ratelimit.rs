@@ -0,0 +1,151 @@
+use anyhow::{anyhow, Result};
+use arc_swap::ArcSwapOption;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use sentient_redactor_core::{
+    caller::Caller,
+    operations::{ErrorKind, OperationError},
+    settings::RuntimeSettings,
+};
+
+// Clients tracked before those whose buckets have refilled are dropped
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+// Retry-After for requests turned away while every pipeline is busy
+const BUSY_RETRY_SECONDS: u64 = 1;
+
+// A request turned away, and when the client may try again
+#[derive(Debug)]
+pub struct Throttled {
+    pub error: OperationError,
+    pub retry_after: u64,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// Per-client request rate, as token buckets refilled at `RATE_LIMIT_PER_MINUTE` and
+// holding up to `RATE_LIMIT_BURST`, and a global bound of `MAX_CONCURRENT_REDACTIONS`
+// on redaction pipelines running at once. Both are off unless configured; the rate can
+// be changed through the admin API while the service runs.
+pub struct RateLimiter {
+    // Tokens per second, and the bucket size
+    rate: ArcSwapOption<(f64, f64)>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    pipelines: Option<Arc<Semaphore>>,
+}
+
+impl RateLimiter {
+    // The rate of `settings`, and `MAX_CONCURRENT_REDACTIONS` pipelines
+    pub fn from_settings(settings: &RuntimeSettings) -> Result<Self> {
+        let pipelines = match std::env::var("MAX_CONCURRENT_REDACTIONS") {
+            Ok(value) => Some(value.parse::<u32>().ok().filter(|value| *value > 0)
+                .ok_or_else(|| anyhow!("Invalid MAX_CONCURRENT_REDACTIONS: {}", value))?),
+            Err(_) => None,
+        };
+        Ok(Self::new(settings.rate_limit_per_minute, settings.rate_limit_burst, pipelines))
+    }
+
+    pub fn new(per_minute: Option<u32>, burst: Option<u32>, pipelines: Option<u32>) -> Self {
+        let limiter = Self {
+            rate: ArcSwapOption::empty(),
+            buckets: Mutex::new(HashMap::new()),
+            pipelines: pipelines.map(|permits| Arc::new(Semaphore::new(permits as usize))),
+        };
+        limiter.set_rate(per_minute, burst);
+        limiter
+    }
+
+    // The burst defaults to a minute's worth of requests. Buckets already filled keep
+    // their tokens, capped at the new burst as they are next used.
+    pub fn set_rate(&self, per_minute: Option<u32>, burst: Option<u32>) {
+        let rate = per_minute.map(|per_minute| Arc::new((per_minute as f64 / 60.0, burst.unwrap_or(per_minute) as f64)));
+        self.rate.store(rate);
+    }
+
+    // Take one request from the client's bucket
+    pub fn check(&self, client: &str) -> Result<(), Throttled> {
+        let Some((rate, capacity)) = self.rate.load().as_deref().copied() else { return Ok(()) };
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            buckets.retain(|_, bucket| bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < capacity);
+        }
+
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket { tokens: capacity, updated: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        let retry_after = Duration::from_secs_f64((1.0 - bucket.tokens) / rate).as_secs().max(1);
+        Err(Throttled {
+            error: OperationError::new(ErrorKind::TooManyRequests, format!("Rate limit exceeded; retry in {} s", retry_after))
+                .with_code("rate_limited"),
+            retry_after,
+        })
+    }
+
+    // A slot for one redaction pipeline, held until the permit is dropped. None when
+    // pipelines are not bounded.
+    pub fn pipeline(&self) -> Result<Option<OwnedSemaphorePermit>, Throttled> {
+        let Some(pipelines) = &self.pipelines else { return Ok(None) };
+        pipelines.clone().try_acquire_owned().map(Some).map_err(|_| Throttled {
+            error: OperationError::new(ErrorKind::TooManyRequests, "Too many redactions are in progress; retry shortly")
+                .with_code("server_busy"),
+            retry_after: BUSY_RETRY_SECONDS,
+        })
+    }
+}
+
+// Requests are counted against the caller's principal (an API key's id, for API keys),
+// or against the client address for anonymous ones
+pub fn client_key(caller: &Caller, addr: Option<IpAddr>) -> String {
+    match (&caller.principal, addr) {
+        (Some(principal), _) => format!("principal:{}", principal),
+        (None, Some(addr)) => format!("ip:{}", addr),
+        (None, None) => "anonymous".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clients_have_their_own_buckets() {
+        let limiter = RateLimiter::new(Some(60), Some(2), None);
+        let alice = client_key(&Caller { principal: Some("alice".to_string()), tenant: None, scopes: None }, None);
+        let anonymous = client_key(&Caller::default(), Some("10.0.0.7".parse().unwrap()));
+        assert_eq!(anonymous, "ip:10.0.0.7");
+
+        assert!(limiter.check(&alice).is_ok());
+        assert!(limiter.check(&alice).is_ok());
+        let throttled = limiter.check(&alice).unwrap_err();
+        assert_eq!((throttled.error.code, throttled.retry_after), (Some("rate_limited"), 1));
+        assert!(limiter.check(&anonymous).is_ok());
+
+        assert!(RateLimiter::new(None, None, None).check(&alice).is_ok());
+
+        // Lifting the limit lets the client straight back in
+        limiter.set_rate(None, None);
+        assert!(limiter.check(&alice).is_ok());
+    }
+
+    #[test]
+    fn test_pipelines_are_bounded() {
+        let limiter = RateLimiter::new(None, None, Some(1));
+        let permit = limiter.pipeline().unwrap();
+        assert!(permit.is_some());
+        assert_eq!(limiter.pipeline().unwrap_err().error.code, Some("server_busy"));
+        drop(permit);
+        assert!(limiter.pipeline().is_ok());
+    }
+}
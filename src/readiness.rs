@@ -0,0 +1,82 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+// How one dependency answered the readiness probe
+#[derive(Serialize, ToSchema)]
+pub struct DependencyStatus {
+    // `up` or `down`
+    pub status: &'static str,
+    // Which implementation was checked, e.g. `presidio,regex` or `disk`
+    pub backend: String,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl DependencyStatus {
+    pub fn new(backend: impl Into<String>, started: Instant, result: Result<()>) -> Self {
+        let latency_ms = started.elapsed().as_millis() as u64;
+        match result {
+            Ok(()) => Self { status: "up", backend: backend.into(), latency_ms, error: None },
+            Err(e) => Self { status: "down", backend: backend.into(), latency_ms, error: Some(e.to_string()) },
+        }
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.status == "up"
+    }
+}
+
+// Run `check`, giving up after `probe_timeout()`
+pub async fn check<F: Future<Output = Result<()>>>(backend: &str, check: F) -> DependencyStatus {
+    let started = Instant::now();
+    let timeout = probe_timeout();
+    let result = match tokio::time::timeout(timeout, check).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow::anyhow!("No answer within {} ms", timeout.as_millis())),
+    };
+    DependencyStatus::new(backend, started, result)
+}
+
+// Ready only while every dependency is up
+pub fn all_up(dependencies: &BTreeMap<&'static str, DependencyStatus>) -> bool {
+    dependencies.values().all(DependencyStatus::is_up)
+}
+
+// `READINESS_PROBE_TIMEOUT_MS` bounds each dependency check; defaults to 2000
+pub fn probe_timeout() -> Duration {
+    let millis = std::env::var("READINESS_PROBE_TIMEOUT_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|value| *value > 0)
+        .unwrap_or(2000);
+    Duration::from_millis(millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_slow_or_failing_dependencies_are_down() {
+        let up = check("regex", async { Ok(()) }).await;
+        assert_eq!((up.status, up.error.is_none()), ("up", true));
+
+        let failing = check("presidio", async { Err(anyhow::anyhow!("connection refused")) }).await;
+        assert_eq!((failing.status, failing.error.as_deref()), ("down", Some("connection refused")));
+
+        let hanging = check("presidio", async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(())
+        });
+        let hanging = tokio::time::timeout(Duration::from_secs(10), hanging).await.unwrap();
+        assert_eq!(hanging.error.as_deref(), Some("No answer within 2000 ms"));
+
+        let dependencies = BTreeMap::from([("storage", up), ("redaction_backend", failing)]);
+        assert!(!all_up(&dependencies));
+    }
+}
@@ -0,0 +1,118 @@
+use anyhow::{anyhow, Result};
+use axum::http::Method;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use sentient_redactor_core::config::AppConfig;
+use sentient_redactor_core::operations::{ErrorKind, OperationError};
+use sentient_redactor_core::storage::Storage;
+
+use crate::provisioning::KeyProvisioner;
+
+// GETs that still write: the self-test stores and deletes a file
+const WRITING_GETS: [&str; 1] = ["/admin/selftest/redaction"];
+// POSTs that only read stored files
+const READING_POSTS: [&str; 3] = ["/download/bulk", "/files/:file_id/unredact", "/files/:file_id/original"];
+
+// How a refresh of the shared storage last went, for readiness
+enum Refresh {
+    Pending,
+    Done(Instant),
+    Failed(String),
+}
+
+// Set with `READ_ONLY`: the instance serves downloads, reports, search and metadata from
+// disk or S3 storage an instance taking uploads writes to, and refuses everything that
+// would write. What the writer stores or removes is picked up every `refresh` interval.
+pub struct ReadOnlyMode {
+    refresh: Duration,
+    last_refresh: Mutex<Refresh>,
+}
+
+impl ReadOnlyMode {
+    pub fn from_config(config: &AppConfig) -> Option<Self> {
+        config.read_only.then(|| Self {
+            refresh: Duration::from_secs(config.read_only_refresh_seconds),
+            last_refresh: Mutex::new(Refresh::Pending),
+        })
+    }
+
+    // Whether a replica answers `method` on the matched `route`
+    pub fn serves(method: &Method, route: &str) -> bool {
+        match *method {
+            Method::GET | Method::HEAD => !WRITING_GETS.contains(&route),
+            Method::POST => READING_POSTS.contains(&route),
+            _ => false,
+        }
+    }
+
+    pub fn refused() -> OperationError {
+        OperationError::new(
+            ErrorKind::MethodNotAllowed,
+            "This instance is a read-only replica; send uploads and changes to an instance taking writes",
+        )
+        .with_code("read_only")
+    }
+
+    // Refresh the storage every interval once it has opened. Errors are logged and kept
+    // for readiness; the files already loaded are still served.
+    pub fn spawn_refresh(self: &Arc<Self>, storage: Arc<RwLock<Box<dyn Storage>>>, key_provisioner: Arc<KeyProvisioner>) {
+        let mode = self.clone();
+        tokio::spawn(async move {
+            let crypto_service = key_provisioner.wait().await;
+            let mut interval = tokio::time::interval(mode.refresh);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let result = storage.write().await.refresh(crypto_service);
+                let refresh = match result {
+                    Ok(0) => Refresh::Done(Instant::now()),
+                    Ok(changed) => {
+                        info!("Picked up {} changed file(s) from the shared storage", changed);
+                        Refresh::Done(Instant::now())
+                    }
+                    Err(e) => {
+                        warn!("Failed to refresh from the shared storage: {}", e);
+                        Refresh::Failed(e.to_string())
+                    }
+                };
+                *mode.last_refresh.lock().unwrap() = refresh;
+            }
+        });
+    }
+
+    // Down when the last refresh failed, or none succeeded for three intervals
+    pub fn status(&self) -> Result<()> {
+        match &*self.last_refresh.lock().unwrap() {
+            Refresh::Failed(e) => Err(anyhow!("Last refresh failed: {}", e)),
+            Refresh::Done(at) if at.elapsed() > self.refresh * 3 => {
+                Err(anyhow!("Not refreshed for {}s", at.elapsed().as_secs()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replicas_serve_reads_only() {
+        assert!(ReadOnlyMode::serves(&Method::GET, "/download/:file_id"));
+        assert!(ReadOnlyMode::serves(&Method::GET, "/files/search"));
+        assert!(ReadOnlyMode::serves(&Method::POST, "/download/bulk"));
+        assert!(!ReadOnlyMode::serves(&Method::POST, "/upload"));
+        assert!(!ReadOnlyMode::serves(&Method::DELETE, "/files/:file_id"));
+        assert!(!ReadOnlyMode::serves(&Method::PUT, "/admin/maintenance"));
+        assert!(!ReadOnlyMode::serves(&Method::GET, "/admin/selftest/redaction"));
+
+        let mode = ReadOnlyMode::from_config(&AppConfig { read_only: true, ..AppConfig::default() }).unwrap();
+        assert!(mode.status().is_ok());
+        *mode.last_refresh.lock().unwrap() = Refresh::Failed("index unreadable".to_string());
+        assert!(mode.status().is_err());
+        assert_eq!(ReadOnlyMode::refused().kind.status(), 405);
+    }
+}
@@ -0,0 +1,392 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, RwLock};
+use tracing::{error, info, warn};
+
+use sentient_redactor_core::{
+    config::AppConfig,
+    report::{RedactionReport, ReportQuery},
+    s3::{S3Config, S3Storage},
+    storage::StorageLimits,
+    usage::UsageTotals,
+    CryptoService, DiskStorage, FileMetadata, FileStorage, Storage,
+};
+
+use crate::provisioning::KeyProvisioner;
+
+// How often files whose copy failed are tried again, when nothing else changes
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+// Durable storage files can be kept in
+#[derive(Clone, Debug)]
+pub enum StorageTarget {
+    Disk(String),
+    S3(S3Config),
+}
+
+impl StorageTarget {
+    // Where `storage_backend` keeps files; None for memory storage
+    pub fn primary(config: &AppConfig) -> Result<Option<Self>> {
+        Ok(match (config.storage_backend(), &config.storage_dir) {
+            ("disk", Some(dir)) => Some(Self::Disk(dir.clone())),
+            ("s3", _) => Some(Self::S3(S3Config::from_config(config)?)),
+            _ => None,
+        })
+    }
+
+    // Where files are replicated to: `replica_storage_dir` or `replica_s3_bucket`
+    pub fn replica(config: &AppConfig) -> Option<Self> {
+        Self::secondary(config, &config.replica_storage_dir, &config.replica_s3_bucket, &config.replica_s3_endpoint, &config.replica_s3_region)
+    }
+
+    // Where old files move to: `cold_storage_dir` or `cold_s3_bucket`
+    pub fn cold(config: &AppConfig) -> Option<Self> {
+        Self::secondary(config, &config.cold_storage_dir, &config.cold_s3_bucket, &config.cold_s3_endpoint, &config.cold_s3_region)
+    }
+
+    // A directory, or a bucket with the primary bucket's prefix and, unless overridden,
+    // its endpoint and region
+    fn secondary(
+        config: &AppConfig,
+        dir: &Option<String>,
+        bucket: &Option<String>,
+        endpoint: &Option<String>,
+        region: &Option<String>,
+    ) -> Option<Self> {
+        if let Some(dir) = dir {
+            return Some(Self::Disk(dir.clone()));
+        }
+        Some(Self::S3(S3Config {
+            bucket: bucket.clone()?,
+            prefix: config.s3_prefix.clone(),
+            endpoint: endpoint.clone().or_else(|| config.s3_endpoint.clone()),
+            region: region.clone().unwrap_or_else(|| config.s3_region.clone()),
+            force_path_style: config.s3_force_path_style,
+        }))
+    }
+
+    pub async fn open(&self, crypto: &CryptoService) -> Result<Box<dyn Storage>> {
+        Ok(match self {
+            Self::Disk(dir) => Box::new(DiskStorage::open(dir, crypto)?),
+            Self::S3(config) => Box::new(S3Storage::open(config.clone(), crypto).await?),
+        })
+    }
+}
+
+impl fmt::Display for StorageTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Disk(dir) => write!(f, "disk storage at {}", dir),
+            Self::S3(config) => write!(f, "S3 bucket {} in {}", config.bucket, config.region),
+        }
+    }
+}
+
+// Files changed on the primary storage and not copied yet
+#[derive(Default)]
+struct ChangeLog {
+    // File id -> when it first changed since its last copy, and a counter bumped on each
+    // change, so a copy that raced a newer change leaves the file pending
+    changed: Mutex<HashMap<String, (Instant, u64)>>,
+    next: Mutex<u64>,
+    notify: Notify,
+}
+
+impl ChangeLog {
+    fn mark(&self, file_id: &str) {
+        let change = {
+            let mut next = self.next.lock().unwrap();
+            *next += 1;
+            *next
+        };
+        self.changed.lock().unwrap()
+            .entry(file_id.to_string())
+            .and_modify(|(_, latest)| *latest = change)
+            .or_insert((Instant::now(), change));
+        self.notify.notify_one();
+    }
+
+    fn pending(&self) -> Vec<(String, u64)> {
+        self.changed.lock().unwrap().iter().map(|(file_id, (_, change))| (file_id.clone(), *change)).collect()
+    }
+
+    // Done with `file_id` as of `change`, unless it changed again since
+    fn copied(&self, file_id: &str, change: u64) {
+        let mut changed = self.changed.lock().unwrap();
+        if changed.get(file_id).is_some_and(|(_, latest)| *latest == change) {
+            changed.remove(file_id);
+        }
+    }
+}
+
+// The primary storage, noting which files change so they are copied to the replica.
+// Callers persist every change, so persists, deletes and expiries are what is noted.
+pub struct ReplicatedStorage {
+    primary: Box<dyn Storage>,
+    changes: Arc<ChangeLog>,
+}
+
+impl Storage for ReplicatedStorage {
+    fn store_file(&mut self, file_id: &str, file_name: &str, content: &str) -> &mut FileMetadata {
+        self.primary.store_file(file_id, file_name, content)
+    }
+
+    fn get_metadata(&self, file_id: &str) -> Option<&FileMetadata> {
+        self.primary.get_metadata(file_id)
+    }
+
+    fn get_metadata_mut(&mut self, file_id: &str) -> Option<&mut FileMetadata> {
+        self.primary.get_metadata_mut(file_id)
+    }
+
+    fn delete_file(&mut self, file_id: &str) -> bool {
+        self.changes.mark(file_id);
+        self.primary.delete_file(file_id)
+    }
+
+    fn file_ids(&self) -> Vec<String> {
+        self.primary.file_ids()
+    }
+
+    fn set_report(&mut self, file_id: &str, report: RedactionReport) {
+        self.primary.set_report(file_id, report);
+    }
+
+    fn search_reports(&self, query: &ReportQuery) -> Vec<String> {
+        self.primary.search_reports(query)
+    }
+
+    fn find_by_external_id(&self, tenant: Option<&str>, external_id: &str) -> Option<&str> {
+        self.primary.find_by_external_id(tenant, external_id)
+    }
+
+    fn find_by_content(&self, tenant: Option<&str>, content_key: &str) -> Vec<&str> {
+        self.primary.find_by_content(tenant, content_key)
+    }
+
+    fn expired_file_ids(&self, now: u64) -> Vec<String> {
+        self.primary.expired_file_ids(now)
+    }
+
+    fn expire_file(&mut self, file_id: &str) -> bool {
+        self.changes.mark(file_id);
+        self.primary.expire_file(file_id)
+    }
+
+    fn was_expired(&self, file_id: &str) -> bool {
+        self.primary.was_expired(file_id)
+    }
+
+    fn persist(&mut self, file_id: &str) -> Result<()> {
+        self.changes.mark(file_id);
+        self.primary.persist(file_id)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        self.primary.backend_name()
+    }
+
+    fn usage(&self) -> &UsageTotals {
+        self.primary.usage()
+    }
+
+    fn usage_mut(&mut self) -> &mut UsageTotals {
+        self.primary.usage_mut()
+    }
+
+    fn persist_usage(&mut self) -> Result<()> {
+        self.primary.persist_usage()
+    }
+
+    fn collect_orphans(&mut self, min_age: Duration) -> Result<(usize, u64)> {
+        self.primary.collect_orphans(min_age)
+    }
+
+    fn rewrap(&mut self, crypto: &CryptoService) -> Result<usize> {
+        self.primary.rewrap(crypto)
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        self.primary.check_writable()
+    }
+
+    fn refresh(&mut self, crypto: &CryptoService) -> Result<usize> {
+        self.primary.refresh(crypto)
+    }
+
+    fn move_to_cold(&mut self, created_before: u64) -> Vec<String> {
+        self.primary.move_to_cold(created_before)
+    }
+
+    fn limits(&self) -> &StorageLimits {
+        self.primary.limits()
+    }
+
+    fn touch(&self, file_id: &str) {
+        self.primary.touch(file_id);
+    }
+
+    fn eviction_candidates(&self, keep: &str) -> Vec<String> {
+        self.primary.eviction_candidates(keep)
+    }
+}
+
+// Copies stored files, their metadata and the usage totals to a second backend in the
+// background, such as a bucket in another region, so `storage_failover` can serve from
+// it. File ids are random UUIDs and only the primary is written to, so copies never
+// conflict.
+pub struct Replicator {
+    target: StorageTarget,
+    changes: Arc<ChangeLog>,
+}
+
+impl Replicator {
+    pub fn new(target: StorageTarget) -> Self {
+        Self { target, changes: Arc::new(ChangeLog::default()) }
+    }
+
+    // Files waiting to be copied, and how long the oldest of them has waited
+    pub fn lag(&self) -> (usize, Duration) {
+        let changed = self.changes.changed.lock().unwrap();
+        let oldest = changed.values().map(|(since, _)| since.elapsed()).max().unwrap_or_default();
+        (changed.len(), oldest)
+    }
+
+    // Once the service key is provisioned, open the replica, bring it in line with the
+    // primary and copy every change from then on
+    pub fn spawn(self: &Arc<Self>, storage: Arc<RwLock<Box<dyn Storage>>>, key_provisioner: Arc<KeyProvisioner>) {
+        let replicator = self.clone();
+        tokio::spawn(async move {
+            let crypto_service = key_provisioner.wait().await;
+            let mut replica = match replicator.target.open(crypto_service).await {
+                Ok(replica) => replica,
+                Err(e) => {
+                    error!("Failed to open the replica {}: {}", replicator.target, e);
+                    std::process::exit(1);
+                }
+            };
+            // Taken after the primary storage has opened, which holds the lock until then
+            replicator.attach(&mut *storage.write().await, replica.as_ref());
+            info!("Replicating stored files to {}", replicator.target);
+
+            loop {
+                replicator.copy(&storage, replica.as_mut()).await;
+                let _ = tokio::time::timeout(RETRY_INTERVAL, replicator.changes.notify.notified()).await;
+            }
+        });
+    }
+
+    // Wrap the primary so its changes are noted, noting the files it differs from the
+    // replica in as changed, such as those stored while replication was down
+    fn attach(&self, storage: &mut Box<dyn Storage>, replica: &dyn Storage) {
+        let mut file_ids = storage.file_ids();
+        file_ids.extend(replica.file_ids());
+        file_ids.sort();
+        file_ids.dedup();
+        for file_id in file_ids {
+            if !same_file(storage.get_metadata(&file_id), replica.get_metadata(&file_id)) {
+                self.changes.mark(&file_id);
+            }
+        }
+
+        let primary = std::mem::replace(storage, Box::new(FileStorage::new()));
+        *storage = Box::new(ReplicatedStorage { primary, changes: self.changes.clone() });
+    }
+
+    // One pass over the pending files. A file whose copy fails stays pending.
+    async fn copy(&self, storage: &RwLock<Box<dyn Storage>>, replica: &mut dyn Storage) {
+        let pending = self.changes.pending();
+        if pending.is_empty() {
+            return;
+        }
+        for (file_id, change) in pending {
+            let (metadata, expired) = {
+                let primary = storage.read().await;
+                (primary.get_metadata(&file_id).cloned(), primary.was_expired(&file_id))
+            };
+            let copied = match metadata {
+                Some(metadata) => copy_file(replica, &file_id, metadata),
+                None => {
+                    match expired {
+                        true => replica.expire_file(&file_id),
+                        false => replica.delete_file(&file_id),
+                    };
+                    Ok(())
+                }
+            };
+            match copied {
+                Ok(()) => self.changes.copied(&file_id, change),
+                Err(e) => warn!("Failed to replicate file_id {} to {}: {}", file_id, self.target, e),
+            }
+        }
+
+        *replica.usage_mut() = storage.read().await.usage().clone();
+        if let Err(e) = replica.persist_usage() {
+            warn!("Failed to replicate usage totals to {}: {}", self.target, e);
+        }
+    }
+}
+
+pub(crate) fn copy_file(replica: &mut dyn Storage, file_id: &str, metadata: FileMetadata) -> Result<()> {
+    let report = metadata.report.clone();
+    let stored = replica.store_file(file_id, &metadata.file_name, &metadata.content);
+    *stored = metadata;
+    if let Some(report) = report {
+        replica.set_report(file_id, report);
+    }
+    replica.persist(file_id)
+}
+
+fn same_file(primary: Option<&FileMetadata>, replica: Option<&FileMetadata>) -> bool {
+    match (primary, replica) {
+        (Some(primary), Some(replica)) => {
+            primary.content == replica.content && serde_json::to_value(primary).ok() == serde_json::to_value(replica).ok()
+        }
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_changes_reach_the_replica() {
+        let mut primary: Box<dyn Storage> = Box::new(FileStorage::new());
+        primary.store_file("kept", "kept.txt", "Call [PERSON]");
+        primary.store_file("stale", "stale.txt", "old");
+        let mut replica: Box<dyn Storage> = Box::new(FileStorage::new());
+        replica.store_file("stale", "stale.txt", "older");
+        replica.store_file("gone", "gone.txt", "removed while replication was down");
+
+        // Files that differ when replication starts are brought in line
+        let replicator = Replicator::new(StorageTarget::Disk("unused".to_string()));
+        replicator.attach(&mut primary, replica.as_ref());
+        assert_eq!(replicator.lag().0, 3);
+        let storage = RwLock::new(primary);
+        replicator.copy(&storage, replica.as_mut()).await;
+        assert_eq!(replicator.lag().0, 0);
+        assert_eq!(replica.get_metadata("stale").unwrap().content, "old");
+        assert_eq!(replica.get_metadata("kept").unwrap().file_name, "kept.txt");
+        assert!(replica.get_metadata("gone").is_none());
+
+        // Then whatever the primary persists, deletes or expires
+        {
+            let mut primary = storage.write().await;
+            primary.store_file("new", "new.txt", "Mail [EMAIL_ADDRESS]").tenant = Some("acme".to_string());
+            primary.persist("new").unwrap();
+            primary.delete_file("kept");
+            primary.usage_mut().all.files = 2;
+        }
+        assert_eq!(replicator.lag().0, 2);
+        replicator.copy(&storage, replica.as_mut()).await;
+        assert_eq!(replica.get_metadata("new").unwrap().tenant.as_deref(), Some("acme"));
+        assert!(replica.get_metadata("kept").is_none());
+        assert_eq!(replica.usage().all.files, 2);
+        assert_eq!(replicator.lag(), (0, Duration::ZERO));
+    }
+}
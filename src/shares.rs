@@ -0,0 +1,128 @@
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+
+const MAX_PASSWORD_ATTEMPTS: u32 = 5;
+
+struct ShareLink {
+    file_id: String,
+    expires_at: u64,
+    // (salt, SHA-256(salt || password))
+    password: Option<([u8; 16], [u8; 32])>,
+    failed_attempts: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ShareError {
+    NotFound,
+    Expired,
+    PasswordRequired,
+    WrongPassword,
+}
+
+// One-time download links for unauthenticated recipients
+pub struct ShareStore {
+    links: HashMap<String, ShareLink>,
+}
+
+impl ShareStore {
+    pub fn new() -> Self {
+        Self {
+            links: HashMap::new(),
+        }
+    }
+
+    // Create a link valid for `ttl_seconds`, returning its token and expiry
+    pub fn create(&mut self, file_id: &str, ttl_seconds: u64, password: Option<&str>) -> (String, u64) {
+        let now = now();
+        self.links.retain(|_, link| link.expires_at > now);
+
+        let mut token_bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut token_bytes);
+        let token = token_bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+
+        let password = password.map(|password| {
+            let mut salt = [0u8; 16];
+            OsRng.fill_bytes(&mut salt);
+            (salt, hash_password(&salt, password))
+        });
+
+        let expires_at = now + ttl_seconds;
+        self.links.insert(token.clone(), ShareLink {
+            file_id: file_id.to_string(),
+            expires_at,
+            password,
+            failed_attempts: 0,
+        });
+
+        (token, expires_at)
+    }
+
+    // Consume a link, returning the shared file_id. Links are single-use and are
+    // revoked after repeated wrong passwords.
+    pub fn redeem(&mut self, token: &str, password: Option<&str>) -> Result<String, ShareError> {
+        let link = self.links.get_mut(token).ok_or(ShareError::NotFound)?;
+
+        if link.expires_at <= now() {
+            self.links.remove(token);
+            return Err(ShareError::Expired);
+        }
+
+        if let Some((salt, expected)) = &link.password {
+            let Some(password) = password else {
+                return Err(ShareError::PasswordRequired);
+            };
+            if !bool::from(hash_password(salt, password).ct_eq(expected)) {
+                link.failed_attempts += 1;
+                if link.failed_attempts >= MAX_PASSWORD_ATTEMPTS {
+                    self.links.remove(token);
+                }
+                return Err(ShareError::WrongPassword);
+            }
+        }
+
+        Ok(self.links.remove(token).map(|link| link.file_id).unwrap_or_default())
+    }
+}
+
+fn hash_password(salt: &[u8; 16], password: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(password.as_bytes());
+    hasher.finalize().into()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_share_links_are_single_use() {
+        let mut store = ShareStore::new();
+        let (token, _) = store.create("file-1", 60, None);
+
+        assert_eq!(store.redeem(&token, None), Ok("file-1".to_string()));
+        assert_eq!(store.redeem(&token, None), Err(ShareError::NotFound));
+    }
+
+    #[test]
+    fn test_password_and_expiry() {
+        let mut store = ShareStore::new();
+        let (token, _) = store.create("file-1", 60, Some("hunter2"));
+        assert_eq!(store.redeem(&token, None), Err(ShareError::PasswordRequired));
+        assert_eq!(store.redeem(&token, Some("wrong")), Err(ShareError::WrongPassword));
+        assert_eq!(store.redeem(&token, Some("hunter2")), Ok("file-1".to_string()));
+
+        let (expired, _) = store.create("file-2", 0, None);
+        assert_eq!(store.redeem(&expired, None), Err(ShareError::Expired));
+    }
+}
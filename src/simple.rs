@@ -0,0 +1,115 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use sentient_redactor_core::{crypto::{self, CryptoService}, envelope, operations::UploadRequest};
+
+// Developer shortcuts on `POST /upload`, for manual testing with curl or Postman
+#[derive(Default, Deserialize, ToSchema)]
+pub struct SimpleFields {
+    // Unencrypted file content
+    pub content: Option<String>,
+    // Raw ChaCha20-Poly1305 session key as 64 hex characters, instead of an RSA-wrapped one
+    pub session_key_hex: Option<String>,
+}
+
+impl SimpleFields {
+    pub fn is_used(&self) -> bool {
+        self.content.is_some() || self.session_key_hex.is_some()
+    }
+}
+
+// Simple mode is a development aid: it is off unless `DEV_SIMPLE_MODE` is set, and
+// release builds refuse to start with it enabled
+pub struct SimpleMode {
+    enabled: bool,
+}
+
+impl SimpleMode {
+    pub fn from_env() -> Result<Self> {
+        let enabled = std::env::var("DEV_SIMPLE_MODE")
+            .is_ok_and(|value| value == "1" || value.eq_ignore_ascii_case("true"));
+
+        if enabled && !cfg!(debug_assertions) {
+            return Err(anyhow!("DEV_SIMPLE_MODE is only available in debug builds"));
+        }
+        if enabled {
+            warn!("Simple mode is enabled: uploads may send plaintext or raw session keys");
+        }
+
+        Ok(Self { enabled })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // Rewrite a simple upload into a regular envelope under the service key, so it
+    // runs through exactly the same pipeline as an encrypted one
+    pub fn apply(&self, fields: SimpleFields, upload: &mut UploadRequest, crypto: &CryptoService) -> Result<()> {
+        if !self.enabled {
+            return Err(anyhow!("Simple mode is disabled on this service"));
+        }
+        if upload.encrypted_session_key.is_some() || upload.psk_id.is_some() {
+            return Err(anyhow!("Simple mode fields cannot be combined with encrypted_session_key or psk_id"));
+        }
+
+        let public_key = crypto.get_public_key()?;
+        match (fields.content, fields.session_key_hex) {
+            (Some(content), None) => {
+                if !upload.encrypted_data.is_empty() {
+                    return Err(anyhow!("Provide either content or encrypted_data, not both"));
+                }
+                let sealed = envelope::seal(&public_key, content.as_bytes())?;
+                upload.encrypted_data = sealed.encrypted_data;
+                upload.encrypted_session_key = Some(sealed.encrypted_session_key);
+                upload.nonce = Some(sealed.nonce);
+                upload.ciphertext_sha256.get_or_insert(sealed.ciphertext_sha256);
+                upload.plaintext_sha256.get_or_insert(sealed.plaintext_sha256);
+            }
+            (None, Some(session_key_hex)) => {
+                let session_key = crypto::parse_sha256_hex(&session_key_hex)
+                    .map_err(|_| anyhow!("session_key_hex must be 64 hex characters"))?;
+                upload.encrypted_session_key = Some(envelope::wrap_session_key(&public_key, &session_key)?);
+            }
+            _ => return Err(anyhow!("Provide either content or session_key_hex, not both")),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upload() -> UploadRequest {
+        serde_json::from_value(serde_json::json!({})).unwrap()
+    }
+
+    #[test]
+    fn test_plaintext_upload_is_sealed_for_the_service() {
+        let crypto = CryptoService::new().unwrap();
+        let mode = SimpleMode { enabled: true };
+
+        let mut request = upload();
+        let fields = SimpleFields { content: Some("Patient: Jane Roe".to_string()), session_key_hex: None };
+        mode.apply(fields, &mut request, &crypto).unwrap();
+
+        let session_key = crypto.decrypt_session_key(request.encrypted_session_key.as_deref().unwrap()).unwrap();
+        let aad = crypto::parse_sha256_hex(request.plaintext_sha256.as_deref().unwrap()).unwrap();
+        let plaintext = crypto.decrypt_file_with_session_key(&request.encrypted_data, &session_key, request.nonce.as_deref(), &aad).unwrap();
+        assert_eq!(plaintext, "Patient: Jane Roe");
+
+        let fields = SimpleFields { content: None, session_key_hex: Some("ab".repeat(32)) };
+        let mut request = upload();
+        mode.apply(fields, &mut request, &crypto).unwrap();
+        let session_key = crypto.decrypt_session_key(request.encrypted_session_key.as_deref().unwrap()).unwrap();
+        assert_eq!(*session_key, [0xab; 32]);
+
+        let disabled = SimpleMode { enabled: false };
+        let fields = SimpleFields { content: Some("x".to_string()), session_key_hex: None };
+        assert!(disabled.apply(fields, &mut upload(), &crypto).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use anyhow::{anyhow, Result};
+
+use sentient_redactor_core::{
+    bidi,
+    redactor::{RedactionOptions, RedactionReport, RedactorService},
+    report::Detection,
+    spans::Segment,
+};
+
+const DEFAULT_LOOKAHEAD_BYTES: usize = 256;
+// Buffered text is released whole once it grows this large without a safe cut
+const MAX_BUFFER_BYTES: usize = 64 * 1024;
+
+// Redacts text that arrives in pieces. The last `lookahead` bytes are held back, and
+// text is only released up to a whitespace boundary that no entity detected in the
+// buffered text crosses, so an entity split across chunks is still seen whole.
+pub struct StreamRedactor {
+    buffer: String,
+    // Start of a UTF-8 character cut off at the end of the last chunk
+    pending: Vec<u8>,
+    lookahead: usize,
+    // Offset of `buffer` into the whole stream
+    released: usize,
+    // What was redacted from the released text, with offsets into the whole stream
+    report: RedactionReport,
+}
+
+impl StreamRedactor {
+    pub fn new(lookahead: usize) -> Self {
+        Self { buffer: String::new(), pending: Vec::new(), lookahead, released: 0, report: RedactionReport::default() }
+    }
+
+    pub fn from_env() -> Self {
+        let lookahead = std::env::var("STREAM_LOOKAHEAD_BYTES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_LOOKAHEAD_BYTES);
+        Self::new(lookahead)
+    }
+
+    // Append a chunk, returning the redacted text that is now safe to release
+    pub async fn push(&mut self, redactor: &RedactorService, options: &RedactionOptions<'_>, chunk: &[u8]) -> Result<String> {
+        self.pending.extend_from_slice(chunk);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => return Err(anyhow!("Stream is not valid UTF-8")),
+        };
+        let text: Vec<u8> = self.pending.drain(..valid).collect();
+        self.buffer.push_str(std::str::from_utf8(&text)?);
+
+        if self.buffer.len() <= self.lookahead {
+            return Ok(String::new());
+        }
+        let limit = self.buffer.len() - self.lookahead;
+        let mut cuts = self.buffer.char_indices()
+            .take_while(|(i, _)| *i < limit)
+            .filter(|(_, c)| c.is_whitespace())
+            .map(|(i, c)| i + c.len_utf8())
+            .collect::<Vec<_>>();
+        if cuts.is_empty() {
+            return self.release_if_full(redactor, options).await;
+        }
+
+        // Analyze everything buffered, lookahead included, to see where entities lie
+        let (_, report) = redactor.redact_segments_with_report(&[Segment::Analyze(&self.buffer)], options).await?;
+        cuts.retain(|cut| !report.detections.iter().any(|detection| detection.start < *cut && detection.end > *cut));
+        let Some(&cut) = cuts.last() else {
+            return self.release_if_full(redactor, options).await;
+        };
+
+        // Redaction only changes detected entities, so text without any only has its bidi
+        // controls handled
+        let released: String = self.buffer.drain(..cut).collect();
+        self.record(report.detections.iter().filter(|detection| detection.end <= cut), cut);
+        if report.detections.iter().all(|detection| detection.start >= cut) {
+            return Ok(bidi::sanitize(&released, options.bidi).text.into_owned());
+        }
+        redactor.redact_segments(&[Segment::Analyze(&released)], options).await
+    }
+
+    // Redact whatever is still buffered at the end of the stream, returning it with the
+    // report for the whole stream
+    pub async fn finish(mut self, redactor: &RedactorService, options: &RedactionOptions<'_>) -> Result<(String, RedactionReport)> {
+        if !self.pending.is_empty() {
+            return Err(anyhow!("Stream ends inside a UTF-8 character"));
+        }
+        let text = self.release(redactor, options).await?;
+        Ok((text, self.report))
+    }
+
+    async fn release_if_full(&mut self, redactor: &RedactorService, options: &RedactionOptions<'_>) -> Result<String> {
+        if self.buffer.len() < MAX_BUFFER_BYTES {
+            return Ok(String::new());
+        }
+        self.release(redactor, options).await
+    }
+
+    async fn release(&mut self, redactor: &RedactorService, options: &RedactionOptions<'_>) -> Result<String> {
+        let text = std::mem::take(&mut self.buffer);
+        if text.is_empty() {
+            return Ok(text);
+        }
+        let (redacted, report) = redactor.redact_segments_with_report(&[Segment::Analyze(&text)], options).await?;
+        self.record(report.detections.iter(), text.len());
+        Ok(redacted)
+    }
+
+    // Add detections in the next `len` released bytes to the report
+    fn record<'a>(&mut self, detections: impl Iterator<Item = &'a Detection>, len: usize) {
+        for detection in detections {
+            *self.report.entities.entry(detection.entity_type.clone()).or_default() += 1;
+            self.report.detections.push(Detection {
+                start: detection.start + self.released,
+                end: detection.end + self.released,
+                ..detection.clone()
+            });
+        }
+        self.released += len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sentient_redactor_core::{bidi::BidiMode, labels::LabelCatalog, rules::RegexEngine, EntityFilter};
+
+    #[tokio::test]
+    async fn test_entities_split_across_chunks_are_redacted() {
+        let redactor = RedactorService::with_backend(Box::new(RegexEngine::new()), LabelCatalog::default());
+        let options = RedactionOptions { strategy: "replace", tenant: None, language: "en", pipelines: None, filter: EntityFilter::default(), bidi: BidiMode::default(), custom: None, boundaries: None, hashing: None };
+        let mut stream = StreamRedactor::new(16);
+
+        let mut output = String::new();
+        for chunk in ["Hello, please mail jane.d", "oe@exam", "ple.com about the café ", "order\u{e9}"] {
+            output += &stream.push(&redactor, &options, chunk.as_bytes()).await.unwrap();
+        }
+        // Nothing past the lookahead window is held back
+        assert!(output.starts_with("Hello, please mail "), "{}", output);
+        let (tail, report) = stream.finish(&redactor, &options).await.unwrap();
+        output += &tail;
+        assert_eq!(output, "Hello, please mail <EMAIL_ADDRESS> about the café order\u{e9}");
+        let detection = &report.detections[0];
+        assert_eq!((detection.start, detection.end, report.entities["EMAIL_ADDRESS"]), (19, 39, 1));
+
+        let mut split = StreamRedactor::new(16);
+        assert_eq!(split.push(&redactor, &options, &"é".as_bytes()[..1]).await.unwrap(), "");
+        assert!(split.finish(&redactor, &options).await.is_err());
+    }
+}
@@ -0,0 +1,261 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+use tracing::{error, info, warn};
+
+use sentient_redactor_core::{
+    config::AppConfig,
+    report::{RedactionReport, ReportQuery},
+    storage::{ColdTier, StorageLimits},
+    usage::UsageTotals,
+    CryptoService, FileMetadata, FileStorage, Storage,
+};
+
+use crate::provisioning::KeyProvisioner;
+use crate::replication::{copy_file, StorageTarget};
+
+// The primary storage in front of a cold backend that old files move to. Each file lives
+// in one of the two, and reads find it in either, so callers never see where it is.
+pub struct TieredStorage {
+    hot: Box<dyn Storage>,
+    cold: Box<dyn Storage>,
+}
+
+impl TieredStorage {
+    pub fn new(hot: Box<dyn Storage>, cold: Box<dyn Storage>) -> Self {
+        Self { hot, cold }
+    }
+
+    // The tier holding `file_id`, the hot one when neither does
+    fn tier(&self, file_id: &str) -> &dyn Storage {
+        match self.cold.get_metadata(file_id) {
+            Some(_) => self.cold.as_ref(),
+            None => self.hot.as_ref(),
+        }
+    }
+
+    fn tier_mut(&mut self, file_id: &str) -> &mut dyn Storage {
+        match self.cold.get_metadata(file_id) {
+            Some(_) => self.cold.as_mut(),
+            None => self.hot.as_mut(),
+        }
+    }
+}
+
+impl Storage for TieredStorage {
+    // Files are stored hot, replacing any cold file of the same id
+    fn store_file(&mut self, file_id: &str, file_name: &str, content: &str) -> &mut FileMetadata {
+        if self.cold.get_metadata(file_id).is_some() {
+            self.cold.delete_file(file_id);
+        }
+        self.hot.store_file(file_id, file_name, content)
+    }
+
+    fn get_metadata(&self, file_id: &str) -> Option<&FileMetadata> {
+        self.tier(file_id).get_metadata(file_id)
+    }
+
+    fn get_metadata_mut(&mut self, file_id: &str) -> Option<&mut FileMetadata> {
+        self.tier_mut(file_id).get_metadata_mut(file_id)
+    }
+
+    fn delete_file(&mut self, file_id: &str) -> bool {
+        self.tier_mut(file_id).delete_file(file_id)
+    }
+
+    fn file_ids(&self) -> Vec<String> {
+        let mut file_ids = self.hot.file_ids();
+        file_ids.extend(self.cold.file_ids());
+        file_ids
+    }
+
+    fn set_report(&mut self, file_id: &str, report: RedactionReport) {
+        self.tier_mut(file_id).set_report(file_id, report);
+    }
+
+    fn search_reports(&self, query: &ReportQuery) -> Vec<String> {
+        let mut file_ids = self.hot.search_reports(query);
+        file_ids.extend(self.cold.search_reports(query));
+        file_ids
+    }
+
+    fn find_by_external_id(&self, tenant: Option<&str>, external_id: &str) -> Option<&str> {
+        self.hot.find_by_external_id(tenant, external_id).or_else(|| self.cold.find_by_external_id(tenant, external_id))
+    }
+
+    fn find_by_content(&self, tenant: Option<&str>, content_key: &str) -> Vec<&str> {
+        let mut file_ids = self.hot.find_by_content(tenant, content_key);
+        file_ids.extend(self.cold.find_by_content(tenant, content_key));
+        file_ids
+    }
+
+    fn expired_file_ids(&self, now: u64) -> Vec<String> {
+        let mut file_ids = self.hot.expired_file_ids(now);
+        file_ids.extend(self.cold.expired_file_ids(now));
+        file_ids
+    }
+
+    fn expire_file(&mut self, file_id: &str) -> bool {
+        self.tier_mut(file_id).expire_file(file_id)
+    }
+
+    fn was_expired(&self, file_id: &str) -> bool {
+        self.hot.was_expired(file_id) || self.cold.was_expired(file_id)
+    }
+
+    fn persist(&mut self, file_id: &str) -> Result<()> {
+        self.tier_mut(file_id).persist(file_id)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        self.hot.backend_name()
+    }
+
+    fn usage(&self) -> &UsageTotals {
+        self.hot.usage()
+    }
+
+    fn usage_mut(&mut self) -> &mut UsageTotals {
+        self.hot.usage_mut()
+    }
+
+    fn persist_usage(&mut self) -> Result<()> {
+        self.hot.persist_usage()
+    }
+
+    fn collect_orphans(&mut self, min_age: Duration) -> Result<(usize, u64)> {
+        let (hot_files, hot_bytes) = self.hot.collect_orphans(min_age)?;
+        let (cold_files, cold_bytes) = self.cold.collect_orphans(min_age)?;
+        Ok((hot_files + cold_files, hot_bytes + cold_bytes))
+    }
+
+    fn rewrap(&mut self, crypto: &CryptoService) -> Result<usize> {
+        Ok(self.hot.rewrap(crypto)? + self.cold.rewrap(crypto)?)
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        self.hot.check_writable()?;
+        self.cold.check_writable()
+    }
+
+    fn refresh(&mut self, crypto: &CryptoService) -> Result<usize> {
+        self.hot.refresh(crypto)
+    }
+
+    // The round trip of a probe written to the cold backend is recorded on each file
+    // moved. A file is dropped from the hot tier only once its cold copy is persisted.
+    fn move_to_cold(&mut self, created_before: u64) -> Vec<String> {
+        let started = Instant::now();
+        if let Err(e) = self.cold.check_writable() {
+            warn!("Cold storage is not writable, leaving old files in place: {}", e);
+            return Vec::new();
+        }
+        let cold_tier = ColdTier {
+            moved_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            latency_ms: started.elapsed().as_millis() as u64,
+        };
+
+        let mut moved = Vec::new();
+        for file_id in self.hot.file_ids() {
+            let Some(metadata) = self.hot.get_metadata(&file_id).filter(|metadata| metadata.created_at < created_before) else {
+                continue;
+            };
+            let metadata = FileMetadata { cold_tier: Some(cold_tier), ..metadata.clone() };
+            match copy_file(self.cold.as_mut(), &file_id, metadata) {
+                Ok(()) => {
+                    self.hot.delete_file(&file_id);
+                    moved.push(file_id);
+                }
+                Err(e) => warn!("Failed to move file_id {} to cold storage: {}", file_id, e),
+            }
+        }
+        moved
+    }
+
+    fn limits(&self) -> &StorageLimits {
+        self.hot.limits()
+    }
+
+    fn touch(&self, file_id: &str) {
+        self.tier(file_id).touch(file_id);
+    }
+
+    fn eviction_candidates(&self, keep: &str) -> Vec<String> {
+        self.hot.eviction_candidates(keep)
+    }
+}
+
+// Moves files older than `cold_tier_after_days` from the primary storage to
+// `cold_storage_dir` or `cold_s3_bucket` every `cold_tier_sweep_seconds`
+pub struct ColdTiering {
+    target: StorageTarget,
+    after: Duration,
+    pub interval: Duration,
+}
+
+impl ColdTiering {
+    // None unless a cold backend is set
+    pub fn from_config(config: &AppConfig) -> Option<Self> {
+        Some(Self {
+            target: StorageTarget::cold(config)?,
+            after: Duration::from_secs(config.cold_tier_after_days * 24 * 3600),
+            interval: Duration::from_secs(config.cold_tier_sweep_seconds),
+        })
+    }
+
+    // Once the service key is provisioned, open the cold backend and put it behind the
+    // primary storage
+    pub async fn attach(&self, storage: &RwLock<Box<dyn Storage>>, key_provisioner: Arc<KeyProvisioner>) {
+        let crypto_service = key_provisioner.wait().await;
+        let cold = match self.target.open(crypto_service).await {
+            Ok(cold) => cold,
+            Err(e) => {
+                error!("Failed to open cold storage {}: {}", self.target, e);
+                std::process::exit(1);
+            }
+        };
+        // Taken after the primary storage has opened, which holds the lock until then
+        let mut storage = storage.write().await;
+        let hot = std::mem::replace(&mut *storage, Box::new(FileStorage::new()));
+        *storage = Box::new(TieredStorage::new(hot, cold));
+        info!("Moving files older than {} day(s) to {}", self.after.as_secs() / (24 * 3600), self.target);
+    }
+
+    // Files created before this are due to move
+    pub fn cutoff(&self, now: u64) -> u64 {
+        now.saturating_sub(self.after.as_secs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_old_files_move_to_cold_storage() {
+        let mut storage = TieredStorage::new(Box::new(FileStorage::new()), Box::new(FileStorage::new()));
+        for (file_id, created_at) in [("old", 100), ("new", 300)] {
+            let metadata = storage.store_file(file_id, "notes.txt", "Call [PERSON]");
+            metadata.created_at = created_at;
+            metadata.external_id = Some(format!("{}-ref", file_id));
+        }
+
+        assert_eq!(storage.move_to_cold(200), ["old"]);
+        assert!(storage.hot.get_metadata("old").is_none());
+        assert!(storage.cold.get_metadata("new").is_none());
+        // Reads find the file wherever it is
+        let old = storage.get_metadata("old").unwrap();
+        assert_eq!((old.content.as_str(), old.cold_tier.map(|tier| tier.moved_at > 0)), ("Call [PERSON]", Some(true)));
+        assert_eq!(storage.find_by_external_id(None, "old-ref"), Some("old"));
+        let mut file_ids = storage.file_ids();
+        file_ids.sort();
+        assert_eq!(file_ids, ["new", "old"]);
+        assert!(storage.move_to_cold(200).is_empty());
+
+        storage.get_metadata_mut("old").unwrap().owner = Some("alice".to_string());
+        assert_eq!(storage.cold.get_metadata("old").unwrap().owner.as_deref(), Some("alice"));
+        assert!(storage.delete_file("old"));
+        assert!(storage.get_metadata("old").is_none());
+    }
+}
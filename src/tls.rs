@@ -0,0 +1,152 @@
+use axum::{
+    extract::{connect_info::ConnectInfo, Request},
+    Router,
+};
+use futures_util::{stream, Stream};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{conn::auto::Builder, graceful::GracefulShutdown},
+    service::TowerToHyperService,
+};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::{rustls::ServerConfig, server::TlsStream, TlsAcceptor};
+use tonic::transport::server::{Connected, TcpConnectInfo};
+use tower::ServiceExt;
+use tracing::{debug, warn};
+
+// Clients that have not finished the handshake by then are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// Pause after a failed accept, which is usually the process running out of descriptors
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_secs(1);
+// Handshaken gRPC connections waiting for tonic to pick them up
+const GRPC_BACKLOG: usize = 64;
+
+// Serve `app` over TLS until `shutdown` resolves, then wait for open connections to
+// finish, as `axum::serve` does over plain HTTP
+pub async fn serve(listener: TcpListener, config: Arc<ServerConfig>, app: Router, shutdown: impl Future<Output = ()>) {
+    let acceptor = TlsAcceptor::from(config);
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        let (stream, remote) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Failed to accept a connection: {}", e);
+                    tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        let (acceptor, app, watcher) = (acceptor.clone(), app.clone(), graceful.watcher());
+        tokio::spawn(async move {
+            let Some(stream) = handshake(&acceptor, stream, remote).await else { return };
+            // Handlers see the client address as they do over plain HTTP
+            let service = tower::service_fn(move |mut request: Request<hyper::body::Incoming>| {
+                request.extensions_mut().insert(ConnectInfo(remote));
+                app.clone().oneshot(request)
+            });
+            let builder = Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), TowerToHyperService::new(service));
+            if let Err(e) = watcher.watch(connection.into_owned()).await {
+                debug!("Connection from {} ended: {}", remote, e);
+            }
+        });
+    }
+    graceful.shutdown().await;
+}
+
+// TLS connections for the gRPC interface, handshaken concurrently so a slow client
+// does not hold up the others
+pub fn incoming(listener: TcpListener, config: Arc<ServerConfig>) -> impl Stream<Item = io::Result<TlsConnection>> {
+    let acceptor = TlsAcceptor::from(config);
+    let (sender, mut receiver) = mpsc::channel(GRPC_BACKLOG);
+    tokio::spawn(async move {
+        loop {
+            let (stream, remote) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Failed to accept a gRPC connection: {}", e);
+                        tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                        continue;
+                    }
+                },
+                // The gRPC server has stopped
+                _ = sender.closed() => return,
+            };
+            let (acceptor, sender) = (acceptor.clone(), sender.clone());
+            tokio::spawn(async move {
+                if let Some(stream) = handshake(&acceptor, stream, remote).await {
+                    let _ = sender.send(TlsConnection(stream)).await;
+                }
+            });
+        }
+    });
+    stream::poll_fn(move |cx| receiver.poll_recv(cx).map(|connection| connection.map(Ok)))
+}
+
+async fn handshake(acceptor: &TlsAcceptor, stream: TcpStream, remote: SocketAddr) -> Option<TlsStream<TcpStream>> {
+    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+        Ok(Ok(stream)) => Some(stream),
+        Ok(Err(e)) => {
+            debug!("TLS handshake with {} failed: {}", remote, e);
+            None
+        }
+        Err(_) => {
+            debug!("TLS handshake with {} timed out", remote);
+            None
+        }
+    }
+}
+
+// A TLS stream that tells tonic the client address, for rate limiting by address
+pub struct TlsConnection(TlsStream<TcpStream>);
+
+impl Connected for TlsConnection {
+    type ConnectInfo = TcpConnectInfo;
+
+    fn connect_info(&self) -> TcpConnectInfo {
+        self.0.get_ref().0.connect_info()
+    }
+}
+
+impl AsyncRead for TlsConnection {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsConnection {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[io::IoSlice<'_>]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.0.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
@@ -172,17 +172,17 @@ def encrypt_session_key(session_key, server_public_key_pem):
     return base64.b64encode(encrypted_key).decode('utf-8')
 
 def encrypt_file_with_session_key(file_content, session_key):
-    """Encrypt file content with ChaCha20-Poly1305 using session key"""
+    """Encrypt file content with ChaCha20-Poly1305 using session key, returning (data, nonce)"""
     # Create ChaCha20-Poly1305 cipher
     cipher = ChaCha20Poly1305(session_key)
     
-    # Use a fixed nonce for this prototype (in production, use random nonce)
-    nonce = b'\x00' * 12
+    # Fresh random 96-bit nonce, sent alongside the ciphertext
+    nonce = os.urandom(12)
     
     # Encrypt the file content
     encrypted_data = cipher.encrypt(nonce, file_content.encode('utf-8'), None)
     
-    return base64.b64encode(encrypted_data).decode('utf-8')
+    return base64.b64encode(encrypted_data).decode('utf-8'), base64.b64encode(nonce).decode('utf-8')
 
 def test_secure_upload(base_url, server_public_key, filename, strategy="replace"):
     """Test secure file upload with proper key exchange"""
@@ -207,7 +207,7 @@ def test_secure_upload(base_url, server_public_key, filename, strategy="replace"
         print_colored(f"✅ Encrypted session key with RSA", Colors.GREEN)
         
         # Step 3: Encrypt file content with session key
-        encrypted_file_data = encrypt_file_with_session_key(test_content, session_key)
+        encrypted_file_data, nonce = encrypt_file_with_session_key(test_content, session_key)
         print_colored(f"✅ Encrypted file content with ChaCha20-Poly1305", Colors.GREEN)
         
         # Step 4: Upload encrypted data
@@ -219,6 +219,7 @@ def test_secure_upload(base_url, server_public_key, filename, strategy="replace"
         payload = {
             "encrypted_data": encrypted_file_data,
             "encrypted_session_key": encrypted_session_key,
+            "nonce": nonce,
             "file_name": name_without_ext,
             "redaction_strategy": strategy
         }